@@ -1,3 +0,0 @@
-export function getBaseURL() {
-    return window.location.origin;
-}
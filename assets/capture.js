@@ -0,0 +1,24 @@
+// Copy part of the app's canvas to the clipboard as a PNG. Must be called after egui has
+// painted the frame and before the browser presents it, while the WebGL drawing buffer still
+// holds the frame.
+
+export function copyCanvasRegion(canvasId, x, y, width, height) {
+    const source = document.getElementById(canvasId);
+    if (!source) {
+        return Promise.reject(new Error('No canvas'));
+    }
+    if (!navigator.clipboard || typeof ClipboardItem === 'undefined') {
+        return Promise.reject(new Error('This browser does not support copying images'));
+    }
+
+    const canvas = document.createElement('canvas');
+    canvas.width = width;
+    canvas.height = height;
+    canvas.getContext('2d').drawImage(source, x, y, width, height, 0, 0, width, height);
+
+    // Safari requires the ClipboardItem to be created straight away, with a promise of its data.
+    const png = new Promise((resolve, reject) => {
+        canvas.toBlob((blob) => blob ? resolve(blob) : reject(new Error('Could not encode the PNG')), 'image/png');
+    });
+    return navigator.clipboard.write([new ClipboardItem({ 'image/png': png })]);
+}
@@ -0,0 +1,35 @@
+// Runs one call on an analysis plugin for the app, so a plugin that hangs or crashes only
+// takes this worker with it. The message holds the compiled plugin module and the call to
+// make: {module, call: "info"} or {module, call: "analyze", input}. The reply holds the bytes
+// of the buffer the call returned as {buffer}, with {version} for "info", or {error}.
+
+function readBuffer(memory, ptr) {
+    // Both throw a RangeError if the buffer is out of bounds.
+    const len = new DataView(memory.buffer).getUint32(ptr, true);
+    return new Uint8Array(memory.buffer, ptr + 4, len).slice();
+}
+
+self.onmessage = async event => {
+    const { module, call, input } = event.data;
+    try {
+        const instance = await WebAssembly.instantiate(module, {});
+        const exports = instance.exports;
+        if (call === "info") {
+            const version = exports.ffweb_abi_version();
+            const buffer = readBuffer(exports.memory, exports.ffweb_info() >>> 0);
+            self.postMessage({ version, buffer });
+        }
+        else if (call === "analyze") {
+            const ptr = exports.ffweb_alloc(input.length) >>> 0;
+            new Uint8Array(exports.memory.buffer, ptr, input.length).set(input);
+            const buffer = readBuffer(exports.memory, exports.ffweb_analyze(ptr, input.length) >>> 0);
+            self.postMessage({ buffer });
+        }
+        else {
+            self.postMessage({ error: "Unknown call " + call });
+        }
+    }
+    catch (e) {
+        self.postMessage({ error: String(e) });
+    }
+};
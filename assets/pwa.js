@@ -0,0 +1,63 @@
+// Progressive web app support: registering the service worker, and the browser's install
+// prompt, which is held until the user asks to install. This module is also loaded by the
+// workers, so uses `self` rather than `window` at the top level.
+
+let installPrompt = null;
+let offlineReady = false;
+
+self.addEventListener('beforeinstallprompt', (e) => {
+    e.preventDefault();
+    installPrompt = e;
+});
+
+self.addEventListener('appinstalled', () => {
+    installPrompt = null;
+});
+
+export function registerServiceWorker(url, precache) {
+    // Caching is skipped during development so the latest build is always loaded.
+    if (!('serviceWorker' in navigator) || window.location.hash === '#dev') {
+        return;
+    }
+
+    navigator.serviceWorker.addEventListener('message', (e) => {
+        if (e.data && e.data.type === 'precached') {
+            offlineReady = true;
+        }
+    });
+
+    // Cache everything the page loaded too, such as the wasm-bindgen snippets, which are at
+    // paths the app doesn't know.
+    const loaded = performance.getEntriesByType('resource')
+        .map((entry) => entry.name)
+        .filter((name) => new URL(name).origin === window.location.origin);
+    const resolved = precache.map((path) => new URL(path, document.baseURI).href);
+    const urls = Array.from(new Set([...resolved, ...loaded]));
+
+    navigator.serviceWorker.register(new URL(url, document.baseURI).href)
+        .then(() => navigator.serviceWorker.ready)
+        .then((registration) => registration.active.postMessage({ type: 'precache', urls: urls }))
+        .catch((err) => console.error('Service worker registration failed:', err));
+}
+
+export function installAvailable() {
+    return installPrompt !== null;
+}
+
+export function promptInstall() {
+    if (installPrompt === null) {
+        return;
+    }
+    installPrompt.prompt();
+    installPrompt.userChoice.finally(() => {
+        installPrompt = null;
+    });
+}
+
+export function isOfflineReady() {
+    return offlineReady;
+}
+
+export function isOnline() {
+    return navigator.onLine;
+}
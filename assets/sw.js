@@ -1,27 +1,70 @@
-var cacheName = 'egui-template-pwa';
-// var filesToCache = [
-//   './',
-//   './index.html',
-//   './ffweb.js',
-//   './ffweb_bg.wasm',
-// ];
+// Service worker for offline use. The app sends the list of files to cache once it has
+// started; anything else fetched from the same origin is cached as it's loaded.
 
-/*var filesToCache = [];
+// Bump this when the set of cached files changes, so the old cache is dropped on activation.
+var cacheName = 'ffweb-v1';
 
-/!* Start the service worker and cache all the app's content *!/
 self.addEventListener('install', function (e) {
+  self.skipWaiting();
+});
+
+self.addEventListener('activate', function (e) {
   e.waitUntil(
-    caches.open(cacheName).then(function (cache) {
-      return cache.addAll(filesToCache);
-    })
+    caches.keys()
+      .then(function (keys) {
+        return Promise.all(keys.filter(function (key) {
+          return key !== cacheName;
+        }).map(function (key) {
+          return caches.delete(key);
+        }));
+      })
+      .then(function () {
+        return self.clients.claim();
+      })
   );
 });
 
-/!* Serve cached content when offline *!/
+/* Cache the files the app needs to run offline, then tell it they're cached. */
+self.addEventListener('message', function (e) {
+  if (!e.data || e.data.type !== 'precache') {
+    return;
+  }
+  e.waitUntil(
+    caches.open(cacheName)
+      .then(function (cache) {
+        return cache.addAll(e.data.urls);
+      })
+      .then(function () {
+        if (e.source) {
+          e.source.postMessage({ type: 'precached', count: e.data.urls.length });
+        }
+      })
+      .catch(function (err) {
+        console.error('Failed to cache files for offline use:', err);
+      })
+  );
+});
+
+/* Fetch from the network so updates are picked up, falling back to the cache offline. */
 self.addEventListener('fetch', function (e) {
+  if (e.request.method !== 'GET' || new URL(e.request.url).origin !== self.location.origin) {
+    return;
+  }
   e.respondWith(
-    caches.match(e.request).then(function (response) {
-      return response || fetch(e.request);
-    })
+    fetch(e.request)
+      .then(function (response) {
+        if (response.ok) {
+          var copy = response.clone();
+          caches.open(cacheName).then(function (cache) {
+            cache.put(e.request, copy);
+          });
+        }
+        return response;
+      })
+      .catch(function () {
+        return caches.match(e.request, { ignoreSearch: true }).then(function (response) {
+          return response || Response.error();
+        });
+      })
   );
-});*/
+});
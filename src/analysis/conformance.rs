@@ -0,0 +1,268 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Checking an image's layout against the ideal layout of a standard format, as DOS FORMAT
+//! would write it: every track present, sectors 1 to N of the right size with matching IDs,
+//! good CRCs, and gaps of the standard lengths. Useful for verifying a freshly written disk
+//! read back correctly.
+
+use std::collections::{BTreeMap, HashMap};
+
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::analysis::gaps::{GapStats, GAP_DEVIATION_MAX};
+use crate::analysis::geometry::{known_geometries, LayoutSummary, StandardGeometry};
+use crate::analysis::read_all_sectors;
+
+/// Choose the standard geometry an image was most likely meant to have: an exact match if there
+/// is one, otherwise one with the same heads and sectors per track and the closest number of
+/// cylinders.
+pub fn detect_template(layout: &LayoutSummary) -> Option<StandardGeometry> {
+    if let Some(geometry) = layout.standard_geometry() {
+        return Some(*geometry);
+    }
+    known_geometries()
+        .filter(|geometry| geometry.heads == layout.heads && geometry.sectors as usize == layout.max_sectors)
+        .min_by_key(|geometry| geometry.cylinders.abs_diff(layout.formatted_cylinders))
+        .copied()
+}
+
+/// A way the image departs from the template.
+#[derive(Clone, Debug)]
+pub struct Deviation {
+    /// The track it was found on, or None for the disk as a whole.
+    pub ch: Option<DiskCh>,
+    pub description: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConformanceReport {
+    pub template: StandardGeometry,
+    pub tracks_checked: usize,
+    pub deviations: Vec<Deviation>,
+}
+
+impl ConformanceReport {
+    pub fn new(disk: &mut DiskImage, gaps: Option<&GapStats>, template: StandardGeometry) -> Self {
+        let mut deviations = Vec::new();
+        let mut deviate = |ch: Option<DiskCh>, description: String| deviations.push(Deviation { ch, description });
+
+        let crc_errors: HashMap<(u16, u8, u8), bool> = read_all_sectors(disk)
+            .into_iter()
+            .map(|read| ((read.key.c, read.key.h, read.key.s), read.data_crc_error))
+            .collect();
+        let track_gaps: HashMap<DiskCh, _> = gaps
+            .map(|gaps| gaps.tracks.iter().map(|track| (track.ch, track)).collect())
+            .unwrap_or_default();
+        let expected_gap2 = template.encoding.gap2_range();
+        let expected_gap3 = template.measured_gap3();
+
+        let sector_map = disk.get_sector_map();
+        if sector_map.len() != template.heads as usize {
+            deviate(None, format!("{} heads, expected {}", sector_map.len(), template.heads));
+        }
+
+        let mut tracks_checked = 0;
+        for (head, cylinders) in sector_map.iter().enumerate() {
+            for (cylinder, entries) in cylinders.iter().enumerate() {
+                let ch = DiskCh::new(cylinder as u16, head as u8);
+                let in_template = cylinder < template.cylinders as usize && head < template.heads as usize;
+                if !in_template {
+                    if !entries.is_empty() {
+                        deviate(Some(ch), format!("Extra track with {} sectors", entries.len()));
+                    }
+                    continue;
+                }
+                tracks_checked += 1;
+                if entries.is_empty() {
+                    deviate(Some(ch), "Track unformatted".to_string());
+                    continue;
+                }
+                if entries.len() != template.sectors as usize {
+                    deviate(Some(ch), format!("{} sectors, expected {}", entries.len(), template.sectors));
+                }
+
+                let mut ids: BTreeMap<u8, usize> = BTreeMap::new();
+                for entry in entries {
+                    let chsn = entry.chsn;
+                    *ids.entry(chsn.s()).or_default() += 1;
+                    if chsn.c() != ch.c() || chsn.h() != ch.h() {
+                        deviate(
+                            Some(ch),
+                            format!("Sector {} has ID c:{} h:{}", chsn.s(), chsn.c(), chsn.h()),
+                        );
+                    }
+                    if chsn.n_size() != template.sector_size {
+                        let size = chsn.n_size();
+                        deviate(
+                            Some(ch),
+                            format!("Sector {} is {} bytes, expected {}", chsn.s(), size, template.sector_size),
+                        );
+                    }
+                    if crc_errors.get(&(ch.c(), ch.h(), chsn.s())).copied().unwrap_or(false) {
+                        deviate(Some(ch), format!("Sector {} has a bad data CRC", chsn.s()));
+                    }
+                }
+                let missing: Vec<String> = (1..=template.sectors)
+                    .filter(|id| !ids.contains_key(id))
+                    .map(|id| id.to_string())
+                    .collect();
+                if !missing.is_empty() {
+                    deviate(Some(ch), format!("Missing sector IDs {}", missing.join(", ")));
+                }
+                for (id, count) in ids.iter() {
+                    if *id == 0 || *id > template.sectors {
+                        deviate(Some(ch), format!("Unexpected sector ID {}", id));
+                    }
+                    if *count > 1 {
+                        deviate(Some(ch), format!("Sector ID {} appears {} times", id, count));
+                    }
+                }
+
+                if let Some(track) = track_gaps.get(&ch) {
+                    if let Some(range) = track.gap2_range() {
+                        if !expected_gap2.contains(&range.min) || !expected_gap2.contains(&range.max) {
+                            deviate(
+                                Some(ch),
+                                format!(
+                                    "GAP 2 of {} bytes outside the IBM {} range of {} to {}",
+                                    range,
+                                    template.encoding.label(),
+                                    expected_gap2.start(),
+                                    expected_gap2.end()
+                                ),
+                            );
+                        }
+                    }
+                    if let Some(range) = track.gap3_range() {
+                        if range.min.abs_diff(expected_gap3) > GAP_DEVIATION_MAX
+                            || range.max.abs_diff(expected_gap3) > GAP_DEVIATION_MAX
+                        {
+                            deviate(Some(ch), format!("GAP 3 of {} bytes, expected {}", range, expected_gap3));
+                        }
+                    }
+                }
+            }
+        }
+
+        let present: usize = sector_map.iter().map(|cylinders| cylinders.len().min(template.cylinders as usize)).sum();
+        if present < template.cylinders as usize * template.heads as usize {
+            deviate(
+                None,
+                format!("{} of {} tracks present", present, template.cylinders as usize * template.heads as usize),
+            );
+        }
+
+        Self {
+            template,
+            tracks_checked,
+            deviations,
+        }
+    }
+
+    pub fn conforms(&self) -> bool {
+        self.deviations.is_empty()
+    }
+}
+
+#[derive(Default)]
+pub struct Conformance {
+    template: Option<StandardGeometry>,
+    report: Option<ConformanceReport>,
+}
+
+impl Conformance {
+    pub fn clear(&mut self) {
+        self.template = None;
+        self.report = None;
+    }
+
+    /// Show the check for `disk`. Returns the track of a deviation the user clicked on.
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &mut DiskImage, gaps: Option<&GapStats>) -> Option<DiskCh> {
+        let mut selected = None;
+        let title = match &self.report {
+            Some(report) if report.conforms() => format!("Conformance: matches {}", report.template.name),
+            Some(report) => {
+                format!("Conformance: {} deviations from {}", report.deviations.len(), report.template.name)
+            }
+            None => "Conformance".to_string(),
+        };
+        egui::CollapsingHeader::new(title).id_salt("conformance").show(ui, |ui| {
+            if self.template.is_none() {
+                self.template = detect_template(&LayoutSummary::from_disk(disk));
+            }
+            ui.horizontal(|ui| {
+                ui.label("Template:");
+                egui::ComboBox::from_id_salt("conformance_template")
+                    .selected_text(self.template.map_or("None", |template| template.name))
+                    .show_ui(ui, |ui| {
+                        for geometry in known_geometries() {
+                            ui.selectable_value(&mut self.template, Some(*geometry), geometry.name);
+                        }
+                    });
+                if let Some(template) = self.template {
+                    if ui.button("Check").clicked() {
+                        self.report = Some(ConformanceReport::new(disk, gaps, template));
+                    }
+                }
+            });
+
+            let Some(report) = &self.report
+            else {
+                return;
+            };
+            if report.conforms() {
+                ui.colored_label(
+                    egui::Color32::GREEN,
+                    format!("All {} tracks match the {} layout.", report.tracks_checked, report.template.name),
+                );
+                return;
+            }
+            egui::ScrollArea::vertical()
+                .id_salt("conformance_deviations")
+                .max_height(240.0)
+                .show(ui, |ui| {
+                    for deviation in report.deviations.iter() {
+                        ui.horizontal(|ui| {
+                            match deviation.ch {
+                                Some(ch) => {
+                                    if ui.link(ch.to_string()).clicked() {
+                                        selected = Some(ch);
+                                    }
+                                }
+                                None => {
+                                    ui.label("Disk");
+                                }
+                            }
+                            ui.label(&deviation.description);
+                        });
+                    }
+                });
+        });
+        selected
+    }
+}
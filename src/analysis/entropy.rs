@@ -0,0 +1,151 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use std::collections::HashMap;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::{read_all_sectors, SectorKey};
+
+/// Sectors with entropy (in bits per byte) below these thresholds fall into the given class.
+pub const ENTROPY_EMPTY_MAX: f32 = 0.1;
+pub const ENTROPY_LOW_MAX: f32 = 3.0;
+pub const ENTROPY_MEDIUM_MAX: f32 = 6.0;
+pub const ENTROPY_HIGH_MAX: f32 = 7.2;
+
+/// A rough classification of sector contents by entropy.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum EntropyClass {
+    /// A single repeated byte, such as the fill byte of a freshly formatted sector.
+    Empty,
+    /// Highly structured data such as tables, bitmaps or sparse records.
+    Low,
+    /// Typical code and text.
+    Medium,
+    /// Dense data.
+    High,
+    /// Compressed, encrypted or random data.
+    Random,
+}
+
+impl EntropyClass {
+    pub const ALL: [EntropyClass; 5] = [
+        EntropyClass::Empty,
+        EntropyClass::Low,
+        EntropyClass::Medium,
+        EntropyClass::High,
+        EntropyClass::Random,
+    ];
+
+    pub fn from_entropy(entropy: f32) -> Self {
+        match entropy {
+            e if e < ENTROPY_EMPTY_MAX => EntropyClass::Empty,
+            e if e < ENTROPY_LOW_MAX => EntropyClass::Low,
+            e if e < ENTROPY_MEDIUM_MAX => EntropyClass::Medium,
+            e if e < ENTROPY_HIGH_MAX => EntropyClass::High,
+            _ => EntropyClass::Random,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EntropyClass::Empty => "Empty",
+            EntropyClass::Low => "Structured",
+            EntropyClass::Medium => "Code/Text",
+            EntropyClass::High => "Dense",
+            EntropyClass::Random => "Compressed/Random",
+        }
+    }
+
+    /// RGBA color used to represent this class in overlays.
+    pub fn rgba(&self) -> [u8; 4] {
+        match self {
+            EntropyClass::Empty => [0x40, 0x40, 0x40, 0xff],
+            EntropyClass::Low => [0x29, 0x36, 0x6f, 0xff],
+            EntropyClass::Medium => [0x38, 0xb7, 0x64, 0xff],
+            EntropyClass::High => [0xef, 0x7d, 0x57, 0xff],
+            EntropyClass::Random => [0xb1, 0x3e, 0x53, 0xff],
+        }
+    }
+}
+
+/// Calculate the Shannon entropy of `data` in bits per byte (0.0 - 8.0).
+pub fn shannon_entropy(data: &[u8]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    let len = data.len() as f32;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f32 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[derive(Default)]
+pub struct EntropyMap {
+    pub sectors: HashMap<SectorKey, f32>,
+    /// The number of sectors read with a bad data CRC, counted while reading them for entropy.
+    pub crc_errors: usize,
+}
+
+impl EntropyMap {
+    pub fn from_disk(disk: &mut DiskImage) -> Self {
+        let reads = read_all_sectors(disk);
+        let sectors = reads
+            .iter()
+            .map(|sector| (sector.key, shannon_entropy(&sector.data)))
+            .collect();
+        let crc_errors = reads.iter().filter(|sector| sector.data_crc_error).count();
+        Self { sectors, crc_errors }
+    }
+
+    pub fn get(&self, key: &SectorKey) -> Option<f32> {
+        self.sectors.get(key).copied()
+    }
+
+    pub fn class(&self, key: &SectorKey) -> Option<EntropyClass> {
+        self.get(key).map(EntropyClass::from_entropy)
+    }
+
+    /// Return the number of sectors in each entropy class.
+    pub fn class_counts(&self) -> HashMap<EntropyClass, usize> {
+        let mut counts = HashMap::new();
+        for entropy in self.sectors.values() {
+            *counts.entry(EntropyClass::from_entropy(*entropy)).or_insert(0) += 1;
+        }
+        counts
+    }
+}
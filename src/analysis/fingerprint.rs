@@ -0,0 +1,234 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A heuristic guess at the software or duplicator that formatted a disk, from its sector
+//! interleave, track skew, gap lengths and geometry. The guess is only as good as the evidence,
+//! so every guess is shown with the observations that support it.
+
+use std::collections::HashMap;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::gaps::{GapStats, GAP_DEVIATION_MAX};
+use crate::analysis::geometry::{LayoutSummary, PC_GEOMETRIES};
+
+/// Sync bytes written before each address mark, which our gap measurements include.
+pub const SYNC_BYTES: usize = 12;
+
+/// Post-index distance to the first mark when an FDC formats a track: GAP 4a (80) plus sync.
+const FDC_POST_INDEX: std::ops::RangeInclusive<usize> = 85..=100;
+
+fn mode(values: impl Iterator<Item = usize>) -> Option<usize> {
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    counts.into_iter().max_by_key(|(value, count)| (*count, usize::MAX - *value)).map(|(value, _)| value)
+}
+
+/// Sector interleave and track skew, measured from the physical order of sector IDs.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SectorOrder {
+    /// The most common physical distance between consecutive sector IDs.
+    pub interleave: Option<usize>,
+    /// The most common shift in the position of the first sector between adjacent tracks.
+    pub skew: Option<usize>,
+}
+
+impl SectorOrder {
+    pub fn from_disk(disk: &DiskImage) -> Self {
+        let mut steps = Vec::new();
+        let mut skews = Vec::new();
+
+        for cylinders in disk.get_sector_map().iter() {
+            let mut last_first: Option<usize> = None;
+            for entries in cylinders.iter() {
+                let n = entries.len();
+                if n < 2 {
+                    last_first = None;
+                    continue;
+                }
+                let position: HashMap<u8, usize> =
+                    entries.iter().enumerate().map(|(i, entry)| (entry.chsn.s(), i)).collect();
+                let first_id = entries.iter().map(|entry| entry.chsn.s()).min().unwrap_or(1);
+
+                for (id, pos) in position.iter() {
+                    if let Some(next) = id.checked_add(1).and_then(|next| position.get(&next)) {
+                        steps.push((next + n - pos) % n);
+                    }
+                }
+                if let Some(first) = position.get(&first_id) {
+                    if let Some(last) = last_first {
+                        skews.push((first + n - last) % n);
+                    }
+                    last_first = Some(*first);
+                }
+            }
+        }
+
+        Self {
+            interleave: mode(steps.into_iter()),
+            skew: mode(skews.into_iter()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FormatterGuess {
+    pub name: &'static str,
+    pub score: i32,
+    pub evidence: Vec<String>,
+}
+
+impl FormatterGuess {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            score: 0,
+            evidence: Vec::new(),
+        }
+    }
+
+    fn weigh(&mut self, weight: i32, evidence: String) {
+        self.score += weight;
+        let sign = if weight >= 0 { "+" } else { "-" };
+        self.evidence.push(format!("{}{}: {}", sign, weight.abs(), evidence));
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Fingerprint {
+    pub order: SectorOrder,
+    pub guesses: Vec<FormatterGuess>,
+}
+
+impl Fingerprint {
+    pub fn new(disk: &DiskImage, gaps: &GapStats) -> Self {
+        let layout = LayoutSummary::from_disk(disk);
+        let order = SectorOrder::from_disk(disk);
+        let spt = layout.max_sectors;
+        let uniform = layout.min_sectors == layout.max_sectors;
+        let gap3 = gaps.median_gap3;
+        let post_index = gaps.median_post_index;
+        let dos_gap3 = PC_GEOMETRIES
+            .iter()
+            .find(|geometry| geometry.sectors as usize == spt)
+            .map(|geometry| geometry.measured_gap3());
+
+        let mut dos = FormatterGuess::new("DOS / Windows FORMAT");
+        if let Some(geometry) = PC_GEOMETRIES.iter().find(|geometry| layout.matches(geometry)) {
+            dos.weigh(1, format!("standard {} geometry", geometry.name));
+        }
+        match order.interleave {
+            Some(1) => dos.weigh(2, "sectors are not interleaved".to_string()),
+            Some(n) => dos.weigh(-2, format!("interleave {}", n)),
+            None => {}
+        }
+        match order.skew {
+            Some(0) => dos.weigh(2, "no track skew".to_string()),
+            Some(n) => dos.weigh(-1, format!("track skew of {} sectors", n)),
+            None => {}
+        }
+        if let (Some(gap3), Some(expected)) = (gap3, dos_gap3) {
+            if gap3.abs_diff(expected) <= GAP_DEVIATION_MAX {
+                dos.weigh(2, format!("GAP 3 of {} matches FORMAT's {}", gap3, expected));
+            }
+            else {
+                dos.weigh(-1, format!("GAP 3 of {} differs from FORMAT's {}", gap3, expected));
+            }
+        }
+        if let Some(post_index) = post_index {
+            if FDC_POST_INDEX.contains(&post_index) {
+                dos.weigh(1, format!("post-index gap of {} is typical of an FDC", post_index));
+            }
+        }
+
+        let mut dmf = FormatterGuess::new("Microsoft DMF");
+        if spt == 21 && layout.sector_sizes.contains(&512) {
+            dmf.weigh(3, "21 sectors of 512 bytes per track".to_string());
+            if order.interleave == Some(2) {
+                dmf.weigh(2, "2:1 interleave".to_string());
+            }
+            if gap3.is_some_and(|gap3| gap3 < 20 + SYNC_BYTES) {
+                dmf.weigh(1, "very short GAP 3".to_string());
+            }
+        }
+
+        let mut interleaved = FormatterGuess::new("Early DOS / XT-era interleaved format");
+        if let Some(n) = order.interleave.filter(|n| *n > 1) {
+            if spt <= 9 {
+                interleaved.weigh(2, format!("interleave {} on a {}-sector track", n, spt));
+            }
+        }
+
+        let mut duplicator = FormatterGuess::new("Commercial duplicator (e.g. Trace, Formaster)");
+        if let Some(n) = order.skew.filter(|n| *n > 0) {
+            duplicator.weigh(2, format!("track skew of {} sectors", n));
+        }
+        if let Some(post_index) = post_index.filter(|p| !FDC_POST_INDEX.contains(p)) {
+            duplicator.weigh(1, format!("post-index gap of {} isn't what an FDC writes", post_index));
+        }
+        if let (Some(gap3), Some(expected)) = (gap3, dos_gap3) {
+            if gap3.abs_diff(expected) > GAP_DEVIATION_MAX && gaps.flagged() == 0 {
+                duplicator.weigh(1, format!("consistent but non-standard GAP 3 of {}", gap3));
+            }
+        }
+        if !uniform || !layout.sequential_ids {
+            duplicator.weigh(1, "irregular sector layout, possibly protection".to_string());
+        }
+
+        let mut guesses: Vec<FormatterGuess> = [dos, dmf, interleaved, duplicator]
+            .into_iter()
+            .filter(|guess| guess.score > 0)
+            .collect();
+        guesses.sort_by_key(|guess| -guess.score);
+
+        Self { order, guesses }
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        let title = match self.guesses.first() {
+            Some(guess) => format!("Formatter: probably {}", guess.name),
+            None => "Formatter: unknown".to_string(),
+        };
+        egui::CollapsingHeader::new(title).id_salt("formatter_fingerprint").show(ui, |ui| {
+            let show = |value: Option<usize>| value.map_or("-".to_string(), |v| v.to_string());
+            ui.label(format!(
+                "Interleave {}, track skew {}",
+                show(self.order.interleave),
+                show(self.order.skew)
+            ));
+            for guess in self.guesses.iter() {
+                ui.strong(format!("{} (score {})", guess.name, guess.score));
+                for evidence in guess.evidence.iter() {
+                    ui.label(format!("    {}", evidence));
+                }
+            }
+            ui.weak("This is a heuristic guess from formatting characteristics, not a certainty.");
+        });
+    }
+}
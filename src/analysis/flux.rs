@@ -0,0 +1,337 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Analysis of bitstream-level tracks, such as those decoded from flux images: write splice
+//! and dropout detection, from clock violations in the MFM or FM bitstream.
+//!
+//! In a correctly encoded MFM stream a 1 cell is never followed by another 1, and there are
+//! never more than three 0 cells in a row. FM writes a clock 1 before every data cell, so it
+//! never has two 0 cells in a row. Where a write starts and stops, the old and new data overlap
+//! out of phase, producing a burst of violations in the final gap. Elsewhere, a long run of 0
+//! cells means no flux transitions were seen at all: a dropout, usually from damaged media or a
+//! dirty head.
+//!
+//! A track's encoding is taken from whichever of MFM and FM finds more address marks on it.
+//! Tracks where neither finds any, such as GCR ones, follow other clock rules and aren't
+//! searched for a splice.
+//!
+//! Dropouts are found in the flux itself where the image was loaded from an SCP file, as a
+//! long interval between transitions means the same on any encoding. Other tracks fall back to
+//! runs of 0 cells in the MFM or FM bitstream.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use fluxfox::visualization::{collect_metadata, collect_streams};
+use fluxfox::{DiskCh, DiskDataResolution, DiskImage};
+
+use crate::frame_budget::Incremental;
+use crate::redecode::{find_mark, Encoding};
+use crate::scp::{ScpInfo, ScpTrack};
+
+/// Window over which violations are counted to find the densest burst.
+pub const SPLICE_WINDOW_BITS: usize = 64;
+/// The minimum number of violations in a window for it to be considered a splice.
+pub const SPLICE_MIN_VIOLATIONS: usize = 3;
+/// The longest run of 0 cells MFM's clock allows between two 1 cells.
+pub const MFM_MAX_ZEROS: usize = 3;
+/// The longest run of 0 cells FM allows, a data 0 between two clock 1s.
+pub const FM_MAX_ZEROS: usize = 1;
+/// Runs of 0 cells at least this many times as long as the longest run the encoding allows
+/// are reported as dropouts.
+pub const DROPOUT_MIN_RUNS: usize = 4;
+/// Flux intervals spanning at least this many bit cells are reported as dropouts. No encoding
+/// goes more than four cells without a transition.
+pub const FLUX_DROPOUT_MIN_CELLS: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct TrackFlux {
+    pub ch: DiskCh,
+    pub bit_len: usize,
+    /// Bit offset of the detected write splice.
+    pub splice: Option<usize>,
+    /// Start offset and length in bit cells of each dropout.
+    pub dropouts: Vec<(usize, usize)>,
+}
+
+impl TrackFlux {
+    pub fn splice_fraction(&self) -> Option<f32> {
+        self.splice.map(|bit| bit as f32 / self.bit_len.max(1) as f32)
+    }
+}
+
+/// The encoding of a track's bitstream, if MFM or FM finds any address marks in it.
+fn track_encoding(bits: &[bool]) -> Option<Encoding> {
+    let count_marks = |encoding| {
+        let mut count = 0;
+        let mut pos = 0;
+        while let Some((_, after)) = find_mark(bits, encoding, pos, bits.len()) {
+            count += 1;
+            pos = after;
+        }
+        count
+    };
+    let (mfm, fm) = (count_marks(Encoding::Mfm), count_marks(Encoding::Fm));
+    match mfm.max(fm) {
+        0 => None,
+        _ if fm > mfm => Some(Encoding::Fm),
+        _ => Some(Encoding::Mfm),
+    }
+}
+
+/// Find the bit offsets of clock violations in `bits`, a bitstream in `encoding`, which must
+/// be MFM or FM.
+fn violations(bits: impl Iterator<Item = (usize, bool)>, encoding: Encoding) -> Vec<usize> {
+    // Two 1 cells in a row are only a violation in MFM, where every 1 is followed by a 0.
+    let (max_zeros, ones_allowed) = match encoding {
+        Encoding::Fm => (FM_MAX_ZEROS, true),
+        _ => (MFM_MAX_ZEROS, false),
+    };
+    let mut found = Vec::new();
+    let mut zeros = 0;
+    let mut last_one = false;
+    for (offset, bit) in bits {
+        if bit {
+            if (last_one && !ones_allowed) || zeros > max_zeros {
+                found.push(offset);
+            }
+            zeros = 0;
+        }
+        else {
+            zeros += 1;
+        }
+        last_one = bit;
+    }
+    found
+}
+
+/// Find runs of 0 cells in `bits`, a bitstream in `encoding`, long enough to be dropouts.
+fn dropouts(bits: impl Iterator<Item = (usize, bool)>, encoding: Encoding) -> Vec<(usize, usize)> {
+    let max_zeros = match encoding {
+        Encoding::Fm => FM_MAX_ZEROS,
+        _ => MFM_MAX_ZEROS,
+    };
+    let min_zeros = (max_zeros + 1) * DROPOUT_MIN_RUNS;
+    let mut found = Vec::new();
+    let mut run: Option<(usize, usize)> = None;
+    for (offset, bit) in bits {
+        if bit {
+            if let Some((start, len)) = run.take() {
+                if len >= min_zeros {
+                    found.push((start, len));
+                }
+            }
+        }
+        else {
+            let (start, len) = run.unwrap_or((offset, 0));
+            run = Some((start, len + 1));
+        }
+    }
+    if let Some((start, len)) = run.filter(|(_, len)| *len >= min_zeros) {
+        found.push((start, len));
+    }
+    found
+}
+
+/// Find the long flux intervals of `track` within `data`, a range of bit offsets on a
+/// bitstream of `bit_len` cells decoded from it.
+fn flux_dropouts(track: &ScpTrack, bit_len: usize, data: &Range<usize>) -> Vec<(usize, usize)> {
+    let Some(revolution) = track.revolutions.first()
+    else {
+        return Vec::new();
+    };
+    let cell_ns = revolution.duration_ns / bit_len.max(1) as f64;
+    track
+        .long_intervals
+        .iter()
+        .map(|interval| ((interval.start_ns / cell_ns) as usize, (interval.duration_ns / cell_ns) as usize))
+        .filter(|(start, len)| *len >= FLUX_DROPOUT_MIN_CELLS && data.contains(start))
+        .collect()
+}
+
+/// The start of the window of `SPLICE_WINDOW_BITS` containing the most violations.
+fn densest_window(violations: &[usize]) -> Option<usize> {
+    let mut best: Option<(usize, usize)> = None;
+    for (i, start) in violations.iter().enumerate() {
+        let count = violations[i..].iter().take_while(|v| **v < start + SPLICE_WINDOW_BITS).count();
+        if count >= SPLICE_MIN_VIOLATIONS && best.map_or(true, |(_, c)| count > c) {
+            best = Some((*start, count));
+        }
+    }
+    best.map(|(start, _)| start)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FluxAnalysis {
+    pub tracks: Vec<TrackFlux>,
+}
+
+impl FluxAnalysis {
+    /// Start analyzing the tracks of a bitstream-resolution image, one track per step, as
+    /// scanning every bit cell of a disk takes too long for a single frame. `scp` is the SCP
+    /// file the image was loaded from, if it was. Returns None for sector images, which have no
+    /// bitstream to analyze.
+    pub fn start(disk: &DiskImage, scp: Option<&ScpInfo>) -> Option<Incremental<TrackFlux>> {
+        if !matches!(disk.resolution(), DiskDataResolution::BitStream) {
+            return None;
+        }
+
+        let mut tracks = Vec::new();
+        for head in 0..disk.get_sector_map().len() as u8 {
+            let streams = collect_streams(head, disk);
+            let metadata = collect_metadata(head, disk);
+            for (ti, (stream, track_meta)) in streams.into_iter().zip(metadata).enumerate() {
+                tracks.push((DiskCh::new(ti as u16, head), stream, track_meta));
+            }
+        }
+
+        let scp_tracks: HashMap<DiskCh, ScpTrack> =
+            scp.iter().flat_map(|scp| scp.tracks.iter()).map(|track| (track.ch, track.clone())).collect();
+
+        let total = tracks.len();
+        let steps = tracks.into_iter().map(move |(ch, stream, track_meta)| {
+            let bit_len = stream.len();
+            let bits: Vec<bool> = (0..bit_len).map(|i| stream[i]).collect();
+            let encoding = track_encoding(&bits);
+
+            // Only the gap between the end of the last element and the start of the first
+            // (wrapping around the index) is searched, so damaged data isn't mistaken for
+            // the splice. Offsets past the index are unwrapped by a revolution so a window
+            // can span it, then folded back.
+            let last_end = track_meta.items.iter().map(|item| item.end).max();
+            let first_start = track_meta.items.iter().map(|item| item.start).min();
+            let (splice, data) = match (last_end, first_start, encoding) {
+                (Some(last_end), Some(first_start), Some(encoding)) => {
+                    let gap = last_end..bit_len + first_start;
+                    let found = violations(gap.map(|offset| (offset, bits[offset % bit_len])), encoding);
+                    (densest_window(&found).map(|start| start % bit_len), first_start..last_end)
+                }
+                (Some(last_end), Some(first_start), None) => (None, first_start..last_end),
+                _ => (None, 0..bit_len),
+            };
+            let dropouts = match (scp_tracks.get(&ch), encoding) {
+                (Some(scp_track), _) => flux_dropouts(scp_track, bit_len, &data),
+                (None, Some(encoding)) => dropouts(data.map(|offset| (offset, bits[offset])), encoding),
+                (None, None) => Vec::new(),
+            };
+
+            TrackFlux {
+                ch,
+                bit_len,
+                splice,
+                dropouts,
+            }
+        });
+        Some(Incremental::new(steps, total))
+    }
+
+    pub fn from_tracks(tracks: Vec<TrackFlux>) -> Self {
+        Self { tracks }
+    }
+
+    /// The splice position of each track on `head`, as a fraction of a revolution.
+    pub fn splice_markers(&self, head: u8) -> Vec<(DiskCh, f32)> {
+        self.tracks
+            .iter()
+            .filter(|track| track.ch.h() == head)
+            .filter_map(|track| Some((track.ch, track.splice_fraction()?)))
+            .collect()
+    }
+
+    /// The position of each dropout on `head`, as a fraction of a revolution.
+    pub fn dropout_markers(&self, head: u8) -> Vec<(DiskCh, f32)> {
+        self.tracks
+            .iter()
+            .filter(|track| track.ch.h() == head)
+            .flat_map(|track| {
+                track
+                    .dropouts
+                    .iter()
+                    .map(|(start, _)| (track.ch, *start as f32 / track.bit_len.max(1) as f32))
+            })
+            .collect()
+    }
+
+    pub fn dropout_count(&self) -> usize {
+        self.tracks.iter().map(|t| t.dropouts.len()).sum()
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        let found = self.tracks.iter().filter(|t| t.splice.is_some()).count();
+        let title = format!(
+            "Flux analysis ({} of {} splices found, {} dropouts)",
+            found,
+            self.tracks.len(),
+            self.dropout_count()
+        );
+        egui::CollapsingHeader::new(title).id_salt("flux_splices").show(ui, |ui| {
+            if self.dropout_count() > 0 {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    "Dropouts suggest damaged media or a dirty head. Consider cleaning the drive and re-dumping.",
+                );
+            }
+            let row_height = ui.text_style_height(&egui::TextStyle::Body);
+            egui::ScrollArea::vertical()
+                .id_salt("flux_splices_table")
+                .max_height(320.0)
+                .show_rows(ui, row_height, self.tracks.len(), |ui, rows| {
+                    egui::Grid::new("flux_splices_grid").striped(true).num_columns(4).show(ui, |ui| {
+                        ui.strong("Track");
+                        ui.strong("Splice bit");
+                        ui.strong("Angle");
+                        ui.strong("Dropouts");
+                        ui.end_row();
+                        for track in self.tracks[rows].iter() {
+                            ui.label(track.ch.to_string());
+                            match (track.splice, track.splice_fraction()) {
+                                (Some(bit), Some(fraction)) => {
+                                    ui.label(bit.to_string());
+                                    ui.label(format!("{:.1}°", fraction * 360.0));
+                                }
+                                _ => {
+                                    ui.label("-");
+                                    ui.label("-");
+                                }
+                            }
+                            if track.dropouts.is_empty() {
+                                ui.label("0");
+                            }
+                            else {
+                                let longest = track.dropouts.iter().map(|(_, len)| *len).max().unwrap_or(0);
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    format!("{} (longest {} cells)", track.dropouts.len(), longest),
+                                );
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+    }
+}
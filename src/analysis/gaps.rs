@@ -0,0 +1,380 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Per-track gap statistics, measured between the structure elements found on each track.
+//!
+//! Lengths are in bytes (16 bit cells per byte in both FM and MFM). Each gap is measured up to
+//! the start of the following address mark, so it includes the sync bytes preceding the mark.
+//!
+//! The structure metadata doesn't say how a track is encoded or where its sync runs start, so
+//! each track's bitstream is also scanned for address marks as FM and as MFM, and whichever finds
+//! more sets the encoding GAP 2 and the sync runs are checked against.
+
+use fluxfox::structure_parsers::DiskStructureGenericElement;
+use fluxfox::visualization::{collect_metadata, collect_streams};
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::analysis::geometry::Encoding;
+use crate::redecode::{self, find_mark, read_bytes, INDEX_MARK};
+
+pub const BITCELLS_PER_BYTE: usize = 16;
+/// How far a track's gap may stray from the disk-wide median before it is flagged.
+pub const GAP_DEVIATION_MAX: usize = 8;
+/// The A1 or C2 bytes written between the sync run and an MFM address mark.
+const MFM_MARK_PREFIX: usize = 3;
+/// How many sync bytes a run may be short by before it is flagged; the first byte after a write
+/// splice often reads back damaged.
+const SYNC_SHORT_MAX: usize = 1;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Element {
+    Marker,
+    Header,
+    Data,
+}
+
+/// An address mark found by scanning a track's bitstream.
+struct ScannedMark {
+    mark: u8,
+    /// The bitcell the mark starts at, including the A1 or C2 bytes ahead of an MFM mark.
+    start: usize,
+    /// The bitcell after the mark byte.
+    end: usize,
+    /// The run of 0x00 sync bytes just before the mark.
+    sync: usize,
+}
+
+fn scan_marks(bits: &[bool], encoding: redecode::Encoding) -> Vec<ScannedMark> {
+    let prefix = match encoding {
+        redecode::Encoding::Fm => 0,
+        _ => MFM_MARK_PREFIX,
+    };
+    let mut marks = Vec::new();
+    let mut pos = 0;
+    while let Some((mark, end)) = find_mark(bits, encoding, pos, bits.len()) {
+        let start = end.saturating_sub((prefix + 1) * BITCELLS_PER_BYTE);
+        let sync = (1..)
+            .map_while(|n| {
+                let byte = read_bytes(bits, start.checked_sub(n * BITCELLS_PER_BYTE)?, 1)?;
+                (byte[0] == 0x00).then_some(())
+            })
+            .count();
+        marks.push(ScannedMark {
+            mark,
+            start,
+            end,
+            sync,
+        });
+        pos = end;
+    }
+    marks
+}
+
+fn classify(elem: DiskStructureGenericElement) -> Option<Element> {
+    match elem {
+        DiskStructureGenericElement::Marker => Some(Element::Marker),
+        DiskStructureGenericElement::SectorHeader | DiskStructureGenericElement::SectorBadHeader => {
+            Some(Element::Header)
+        }
+        DiskStructureGenericElement::SectorData
+        | DiskStructureGenericElement::SectorBadData
+        | DiskStructureGenericElement::SectorDeletedData
+        | DiskStructureGenericElement::SectorBadDeletedData => Some(Element::Data),
+        _ => None,
+    }
+}
+
+/// A range of gap lengths, in bytes.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GapRange {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl GapRange {
+    fn from_values(values: &[usize]) -> Option<Self> {
+        Some(Self {
+            min: *values.iter().min()?,
+            max: *values.iter().max()?,
+        })
+    }
+}
+
+impl std::fmt::Display for GapRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        }
+        else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackGaps {
+    pub ch: DiskCh,
+    /// The encoding the track's address marks were found in.
+    pub encoding: Encoding,
+    /// From the index to the first address mark (GAP 4a, plus the IAM and GAP 1 if present).
+    pub post_index: Option<usize>,
+    /// From the index address mark to the first sector header, if the track has an IAM.
+    pub gap1: Option<usize>,
+    /// The sync run before each address mark, in bytes.
+    pub sync: Vec<usize>,
+    /// Between each sector header and its data mark.
+    pub gap2: Vec<usize>,
+    /// Between each sector's data and the next sector header.
+    pub gap3: Vec<usize>,
+    /// From the end of the last sector's data to the index.
+    pub gap4b: Option<usize>,
+    pub flags: Vec<String>,
+}
+
+impl TrackGaps {
+    fn from_elements(ch: DiskCh, bit_len: usize, mut elements: Vec<(Element, usize, usize)>) -> Self {
+        elements.sort_by_key(|(_, start, _)| *start);
+        let bytes = |bits: usize| bits / BITCELLS_PER_BYTE;
+
+        // The first element starting at or after `end`, skipping anything nested inside the
+        // element that ends there.
+        let next_after = |end: usize| elements.iter().find(|(_, start, _)| *start >= end);
+
+        let mut gaps = Self {
+            ch,
+            encoding: Encoding::Mfm,
+            post_index: elements.first().map(|(_, start, _)| bytes(*start)),
+            gap1: None,
+            sync: Vec::new(),
+            gap2: Vec::new(),
+            gap3: Vec::new(),
+            gap4b: None,
+            flags: Vec::new(),
+        };
+
+        for (element, _, end) in elements.iter() {
+            match element {
+                Element::Header => {
+                    if let Some((_, start, _)) = next_after(*end) {
+                        gaps.gap2.push(bytes(start - end));
+                    }
+                }
+                Element::Data => match next_after(*end) {
+                    Some((_, start, _)) => gaps.gap3.push(bytes(start - end)),
+                    None => gaps.gap4b = Some(bytes(bit_len.saturating_sub(*end))),
+                },
+                Element::Marker => {}
+            }
+        }
+        gaps
+    }
+
+    /// Take the encoding, sync runs and GAP 1 from the address marks scanned from the bitstream.
+    fn with_marks(mut self, encoding: Encoding, marks: &[ScannedMark]) -> Self {
+        self.encoding = encoding;
+        self.sync = marks.iter().map(|m| m.sync).collect();
+        if let [iam, next, ..] = marks {
+            if iam.mark == INDEX_MARK {
+                // Up to the next mark itself, so the sync bytes before it count, as for the
+                // other gaps.
+                self.gap1 = Some(next.start.saturating_sub(iam.end) / BITCELLS_PER_BYTE);
+            }
+        }
+        self
+    }
+
+    pub fn gap2_range(&self) -> Option<GapRange> {
+        GapRange::from_values(&self.gap2)
+    }
+
+    pub fn gap3_range(&self) -> Option<GapRange> {
+        GapRange::from_values(&self.gap3)
+    }
+
+    pub fn sync_range(&self) -> Option<GapRange> {
+        GapRange::from_values(&self.sync)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GapStats {
+    pub tracks: Vec<TrackGaps>,
+    pub median_post_index: Option<usize>,
+    pub median_gap1: Option<usize>,
+    pub median_gap2: Option<usize>,
+    pub median_gap3: Option<usize>,
+}
+
+fn median(mut values: Vec<usize>) -> Option<usize> {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+impl GapStats {
+    pub fn from_disk(disk: &DiskImage) -> Self {
+        let mut stats = GapStats::default();
+
+        for head in 0..disk.get_sector_map().len() as u8 {
+            let streams = collect_streams(head, disk);
+            let metadata = collect_metadata(head, disk);
+            for (ti, (stream, track_meta)) in streams.iter().zip(metadata.iter()).enumerate() {
+                let elements: Vec<_> = track_meta
+                    .items
+                    .iter()
+                    .filter_map(|item| {
+                        classify(DiskStructureGenericElement::from(item.elem_type)).map(|e| (e, item.start, item.end))
+                    })
+                    .collect();
+                if elements.is_empty() {
+                    continue;
+                }
+                let ch = DiskCh::new(ti as u16, head);
+                let bits: Vec<bool> = (0..stream.len()).map(|i| stream[i]).collect();
+                let mfm = scan_marks(&bits, redecode::Encoding::Mfm);
+                let fm = scan_marks(&bits, redecode::Encoding::Fm);
+                let (encoding, marks) = if fm.len() > mfm.len() {
+                    (Encoding::Fm, fm)
+                }
+                else {
+                    (Encoding::Mfm, mfm)
+                };
+                let track = TrackGaps::from_elements(ch, stream.len(), elements).with_marks(encoding, &marks);
+                stats.tracks.push(track);
+            }
+        }
+
+        stats.median_post_index = median(stats.tracks.iter().filter_map(|t| t.post_index).collect());
+        stats.median_gap1 = median(stats.tracks.iter().filter_map(|t| t.gap1).collect());
+        stats.median_gap2 = median(stats.tracks.iter().flat_map(|t| t.gap2.iter().copied()).collect());
+        stats.median_gap3 = median(stats.tracks.iter().flat_map(|t| t.gap3.iter().copied()).collect());
+        stats.flag_tracks();
+        stats
+    }
+
+    fn flag_tracks(&mut self) {
+        let deviates = |value: usize, median: Option<usize>| median.is_some_and(|m| value.abs_diff(m) > GAP_DEVIATION_MAX);
+
+        for track in self.tracks.iter_mut() {
+            let encoding = track.encoding;
+            if let Some(range) = track.gap2_range() {
+                let typical = encoding.gap2_range();
+                if !typical.contains(&range.min) || !typical.contains(&range.max) {
+                    track.flags.push(format!(
+                        "GAP 2 {} outside IBM {} range {}-{}",
+                        range,
+                        encoding.label(),
+                        typical.start(),
+                        typical.end()
+                    ));
+                }
+            }
+            if let Some(range) = track.sync_range() {
+                if range.min + SYNC_SHORT_MAX < encoding.sync_bytes() {
+                    track.flags.push(format!(
+                        "sync {} short of {} {}",
+                        range,
+                        encoding.sync_bytes(),
+                        encoding.label()
+                    ));
+                }
+            }
+            if let Some(gap1) = track.gap1 {
+                if deviates(gap1, self.median_gap1) {
+                    track.flags.push(format!("GAP 1 {} differs from disk", gap1));
+                }
+            }
+            if let Some(range) = track.gap3_range() {
+                if deviates(range.min, self.median_gap3) || deviates(range.max, self.median_gap3) {
+                    track.flags.push(format!("GAP 3 {} differs from disk", range));
+                }
+            }
+            if let Some(post_index) = track.post_index {
+                if deviates(post_index, self.median_post_index) {
+                    track.flags.push(format!("post-index gap {} differs from disk", post_index));
+                }
+            }
+        }
+    }
+
+    pub fn flagged(&self) -> usize {
+        self.tracks.iter().filter(|t| !t.flags.is_empty()).count()
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        let title = format!("Track gaps ({} of {} tracks flagged)", self.flagged(), self.tracks.len());
+        egui::CollapsingHeader::new(title).id_salt("track_gaps").show(ui, |ui| {
+            let show = |value: Option<usize>| value.map_or("-".to_string(), |v| v.to_string());
+            ui.label(format!(
+                "Disk medians: post-index {}, GAP 1 {}, GAP 2 {}, GAP 3 {} bytes",
+                show(self.median_post_index),
+                show(self.median_gap1),
+                show(self.median_gap2),
+                show(self.median_gap3)
+            ));
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Body);
+            egui::ScrollArea::vertical()
+                .id_salt("track_gaps_table")
+                .max_height(320.0)
+                .show_rows(ui, row_height, self.tracks.len(), |ui, rows| {
+                    egui::Grid::new("track_gaps_grid").striped(true).num_columns(9).show(ui, |ui| {
+                        ui.strong("Track");
+                        ui.strong("Encoding");
+                        ui.strong("Post-index");
+                        ui.strong("GAP 1");
+                        ui.strong("Sync");
+                        ui.strong("GAP 2");
+                        ui.strong("GAP 3");
+                        ui.strong("GAP 4b");
+                        ui.strong("Flags");
+                        ui.end_row();
+
+                        // Only the visible rows are laid out, so a full 160 track table costs
+                        // no more per frame than a screenful.
+                        let range = |range: Option<GapRange>| range.map_or("-".to_string(), |r| r.to_string());
+                        for track in self.tracks[rows].iter() {
+                            ui.label(track.ch.to_string());
+                            ui.label(track.encoding.label());
+                            ui.label(show(track.post_index));
+                            ui.label(show(track.gap1));
+                            ui.label(range(track.sync_range()));
+                            ui.label(range(track.gap2_range()));
+                            ui.label(range(track.gap3_range()));
+                            ui.label(show(track.gap4b));
+                            if track.flags.is_empty() {
+                                ui.label("");
+                            }
+                            else {
+                                ui.colored_label(ui.visuals().warn_fg_color, track.flags.join("; "));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+    }
+}
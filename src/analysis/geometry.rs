@@ -0,0 +1,270 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use std::collections::BTreeSet;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::SectorKey;
+
+/// How a format's tracks are encoded, which sets the sync and gap lengths its formatter writes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Fm,
+    Mfm,
+}
+
+impl Encoding {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Encoding::Fm => "FM",
+            Encoding::Mfm => "MFM",
+        }
+    }
+
+    /// Sync bytes written before each address mark.
+    pub fn sync_bytes(&self) -> usize {
+        match self {
+            Encoding::Fm => 6,
+            Encoding::Mfm => 12,
+        }
+    }
+
+    /// GAP 2 as measured up to the data mark, so including its sync bytes: IBM formats write
+    /// 11 gap bytes in FM and 22 in MFM, and drives stretch it a little.
+    pub fn gap2_range(&self) -> std::ops::RangeInclusive<usize> {
+        match self {
+            Encoding::Fm => 14..=20,
+            Encoding::Mfm => 30..=40,
+        }
+    }
+}
+
+/// A standard sector-based floppy geometry.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StandardGeometry {
+    pub name: &'static str,
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors: u8,
+    pub sector_size: usize,
+    pub encoding: Encoding,
+    /// The GAP 3 the format's formatter writes between sectors, not counting sync bytes.
+    pub gap3: usize,
+}
+
+impl StandardGeometry {
+    pub const fn new(
+        name: &'static str,
+        cylinders: u16,
+        heads: u8,
+        sectors: u8,
+        sector_size: usize,
+        encoding: Encoding,
+        gap3: usize,
+    ) -> Self {
+        Self {
+            name,
+            cylinders,
+            heads,
+            sectors,
+            sector_size,
+            encoding,
+            gap3,
+        }
+    }
+
+    /// GAP 3 as measured up to the next address mark, so including its sync bytes.
+    pub fn measured_gap3(&self) -> usize {
+        self.gap3 + self.encoding.sync_bytes()
+    }
+
+    pub fn size(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors as usize * self.sector_size
+    }
+
+    pub fn total_sectors(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors as usize
+    }
+
+    /// The logical sector number of `key`, counting sectors of each track, then heads, then
+    /// cylinders. None if the key is outside the geometry.
+    pub fn lba(&self, key: SectorKey) -> Option<usize> {
+        if key.c >= self.cylinders || key.h >= self.heads || key.s == 0 || key.s > self.sectors {
+            return None;
+        }
+        Some((key.c as usize * self.heads as usize + key.h as usize) * self.sectors as usize + key.s as usize - 1)
+    }
+
+    /// The sector at logical sector number `lba`, if it's within the geometry.
+    pub fn key(&self, lba: usize) -> Option<SectorKey> {
+        if lba >= self.total_sectors() {
+            return None;
+        }
+        let track = lba / self.sectors as usize;
+        Some(SectorKey {
+            c: (track / self.heads as usize) as u16,
+            h: (track % self.heads as usize) as u8,
+            s: (lba % self.sectors as usize) as u8 + 1,
+        })
+    }
+}
+
+/// The PC geometries, with the GAP 3 DOS FORMAT writes for each.
+pub const PC_GEOMETRIES: [StandardGeometry; 8] = [
+    StandardGeometry::new("PC 160K", 40, 1, 8, 512, Encoding::Mfm, 80),
+    StandardGeometry::new("PC 180K", 40, 1, 9, 512, Encoding::Mfm, 80),
+    StandardGeometry::new("PC 320K", 40, 2, 8, 512, Encoding::Mfm, 80),
+    StandardGeometry::new("PC 360K", 40, 2, 9, 512, Encoding::Mfm, 80),
+    StandardGeometry::new("PC 720K", 80, 2, 9, 512, Encoding::Mfm, 80),
+    StandardGeometry::new("PC 1.2M", 80, 2, 15, 512, Encoding::Mfm, 84),
+    StandardGeometry::new("PC 1.44M", 80, 2, 18, 512, Encoding::Mfm, 108),
+    StandardGeometry::new("PC 2.88M", 80, 2, 36, 512, Encoding::Mfm, 83),
+];
+
+/// Microsoft's Distribution Media Format, which fits 21 sectors on a 1.44M track by shrinking
+/// GAP 3. These aren't offered as copy targets, but images in them are recognized like the
+/// standard PC geometries.
+pub const DMF_GEOMETRIES: [StandardGeometry; 2] = [
+    StandardGeometry::new("DMF 1.68M", 80, 2, 21, 512, Encoding::Mfm, 8),
+    StandardGeometry::new("DMF 1.72M", 82, 2, 21, 512, Encoding::Mfm, 8),
+];
+
+/// 8" formats, all of 77 cylinders: IBM 3740 single density FM and the double density MFM
+/// formats that followed it, with 26 sectors of 256 bytes or 8 of 1024. The GAP 3 lengths are
+/// the format gaps from the uPD765 data sheet. fluxfox has no standard 8" layout to format a
+/// new image with, so these are recognized but can't be copied to.
+pub const EIGHT_INCH_GEOMETRIES: [StandardGeometry; 4] = [
+    StandardGeometry::new("8\" SSSD 250K FM", 77, 1, 26, 128, Encoding::Fm, 27),
+    StandardGeometry::new("8\" SSDD 500K MFM", 77, 1, 26, 256, Encoding::Mfm, 54),
+    StandardGeometry::new("8\" DSDD 1M MFM", 77, 2, 26, 256, Encoding::Mfm, 54),
+    StandardGeometry::new("8\" DSDD 1.2M MFM", 77, 2, 8, 1024, Encoding::Mfm, 116),
+];
+
+/// Every geometry images are recognized by: the PC ones, DMF's and the 8" ones.
+pub fn known_geometries() -> impl Iterator<Item = &'static StandardGeometry> {
+    PC_GEOMETRIES.iter().chain(DMF_GEOMETRIES.iter()).chain(EIGHT_INCH_GEOMETRIES.iter())
+}
+
+/// The sector sizes IBM's XDF mixes on each track of a 3.5" high density disk, after the first.
+const XDF_SECTOR_SIZES: [usize; 4] = [512, 1024, 2048, 8192];
+
+/// A summary of the sector layout of a disk image, built from its sector map.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutSummary {
+    pub heads: u8,
+    pub cylinders: u16,
+    /// Number of cylinders on each head that contain at least one sector.
+    pub formatted_cylinders: u16,
+    pub min_sectors: usize,
+    pub max_sectors: usize,
+    pub sector_sizes: BTreeSet<usize>,
+    /// True if every formatted track has sector IDs 1 through N with no duplicates.
+    pub sequential_ids: bool,
+}
+
+impl LayoutSummary {
+    pub fn from_disk(disk: &DiskImage) -> Self {
+        let sector_map = disk.get_sector_map();
+        let mut summary = LayoutSummary {
+            heads: sector_map.len() as u8,
+            min_sectors: usize::MAX,
+            sequential_ids: true,
+            ..Default::default()
+        };
+
+        for cylinders in &sector_map {
+            summary.cylinders = summary.cylinders.max(cylinders.len() as u16);
+            let formatted = cylinders.iter().rposition(|entries| !entries.is_empty()).map(|c| c + 1).unwrap_or(0);
+            summary.formatted_cylinders = summary.formatted_cylinders.max(formatted as u16);
+
+            for entries in cylinders.iter().take(formatted) {
+                summary.min_sectors = summary.min_sectors.min(entries.len());
+                summary.max_sectors = summary.max_sectors.max(entries.len());
+
+                let mut ids: Vec<u8> = entries.iter().map(|entry| entry.chsn.s()).collect();
+                ids.sort_unstable();
+                if ids.iter().enumerate().any(|(i, id)| *id as usize != i + 1) {
+                    summary.sequential_ids = false;
+                }
+                for entry in entries {
+                    summary.sector_sizes.insert(entry.chsn.n_size());
+                }
+            }
+        }
+
+        if summary.min_sectors == usize::MAX {
+            summary.min_sectors = 0;
+        }
+        summary
+    }
+
+    /// Return the standard geometry this layout matches exactly, if any.
+    pub fn standard_geometry(&self) -> Option<&'static StandardGeometry> {
+        known_geometries().find(|geometry| self.matches(geometry))
+    }
+
+    /// True if the layout looks like XDF, which packs each track into a few sectors of mixed
+    /// sizes up to 8K and so matches no standard geometry.
+    pub fn is_xdf(&self) -> bool {
+        self.heads == 2
+            && self.formatted_cylinders == 80
+            && XDF_SECTOR_SIZES.iter().all(|size| self.sector_sizes.contains(size))
+    }
+
+    /// The name of the format the layout matches, if it's a known one.
+    pub fn format_name(&self) -> Option<&'static str> {
+        match self.standard_geometry() {
+            Some(geometry) => Some(geometry.name),
+            None if self.is_xdf() => Some("XDF 1.84M"),
+            None => None,
+        }
+    }
+
+    pub fn matches(&self, geometry: &StandardGeometry) -> bool {
+        self.heads == geometry.heads
+            && self.formatted_cylinders == geometry.cylinders
+            && self.min_sectors == geometry.sectors as usize
+            && self.max_sectors == geometry.sectors as usize
+            && self.sequential_ids
+            && self.sector_sizes.len() == 1
+            && self.sector_sizes.contains(&geometry.sector_size)
+    }
+}
+
+impl std::fmt::Display for LayoutSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} cylinders, {} heads, ", self.formatted_cylinders, self.heads)?;
+        if self.min_sectors == self.max_sectors {
+            write!(f, "{} sectors", self.max_sectors)?;
+        }
+        else {
+            write!(f, "{}-{} sectors", self.min_sectors, self.max_sectors)?;
+        }
+        let sizes: Vec<String> = self.sector_sizes.iter().map(|size| size.to_string()).collect();
+        write!(f, " of {} bytes", sizes.join("/"))
+    }
+}
@@ -0,0 +1,123 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Installer disk formats. Software was often shipped on disks that fit more on each track than
+//! DOS's FORMAT does, such as Microsoft's DMF with 21 sectors or IBM's XDF with a few large ones,
+//! so fewer disks were needed and ordinary copying tools couldn't duplicate them. Their unusual
+//! layouts get them mistaken for damaged or copy-protected images, so they're recognized here
+//! and explained.
+
+use crate::analysis::geometry::{LayoutSummary, DMF_GEOMETRIES};
+use crate::fat::BiosParameterBlock;
+
+/// The sectors per track of DMF and the disks formatted like it.
+const INSTALLER_SECTORS: usize = 21;
+/// The sectors per track XDF's boot sector declares for its logical layout.
+const XDF_LOGICAL_SECTORS: u16 = 23;
+
+#[derive(Clone, Debug)]
+pub struct InstallerFormat {
+    pub name: String,
+    pub explanation: &'static str,
+    /// What the format was recognized by.
+    pub evidence: Vec<String>,
+}
+
+impl InstallerFormat {
+    /// Recognize an installer format from the image's layout and boot sector, if it has one. The
+    /// layout itself is matched, and named, by [`LayoutSummary::format_name`]; this adds the boot
+    /// sector's evidence and the explanation.
+    pub fn detect(layout: &LayoutSummary, bpb: Option<&BiosParameterBlock>) -> Option<Self> {
+        let format = layout.format_name();
+        if layout.is_xdf() {
+            let mut evidence = vec![format!("tracks mix sectors of {} bytes", sizes(layout))];
+            if let Some(bpb) = bpb.filter(|bpb| bpb.sectors_per_track == XDF_LOGICAL_SECTORS) {
+                evidence.push(format!("the boot sector declares XDF's {} sectors per track", bpb.sectors_per_track));
+            }
+            return Some(Self {
+                name: format!("IBM {}", format.unwrap_or("XDF")),
+                explanation: "IBM's eXtended Density Format, used for OS/2 and PC DOS installation disks. \
+                    Each track holds a few large sectors of mixed sizes, which needs XDF-aware tools to copy, \
+                    but isn't damage.",
+                evidence,
+            });
+        }
+
+        let mut evidence = Vec::new();
+        let sectors = layout.max_sectors == INSTALLER_SECTORS && layout.sector_sizes.len() == 1;
+        if sectors {
+            evidence.push(format!("{} sectors of {} bytes per track", INSTALLER_SECTORS, sizes(layout)));
+        }
+        let dmf_oem = bpb.is_some_and(|bpb| bpb.oem_name.starts_with("MSDMF"));
+        if let Some(bpb) = bpb {
+            if dmf_oem {
+                evidence.push(format!("the boot sector's OEM name is {}", bpb.oem_name));
+            }
+            if bpb.sectors_per_track as usize == INSTALLER_SECTORS {
+                evidence.push(format!("the boot sector declares {} sectors per track", INSTALLER_SECTORS));
+            }
+            if bpb.sectors_per_cluster == 4 && bpb.root_entries == 16 {
+                evidence.push("DMF's 2K clusters and 16-entry root directory".to_string());
+            }
+        }
+        if !sectors && !dmf_oem {
+            return None;
+        }
+
+        let dmf = format.filter(|_| layout.standard_geometry().is_some_and(|g| DMF_GEOMETRIES.contains(g)));
+        Some(match dmf {
+            Some(name) => Self {
+                name: format!("Microsoft {}", name),
+                explanation: "Microsoft's Distribution Media Format, used for Windows and Office installation \
+                    disks. It fits 21 sectors on a track instead of 18, so DISKCOPY can't duplicate it, but it \
+                    isn't damaged or copy protected.",
+                evidence,
+            },
+            None => Self {
+                name: format!("{}-sector installer format", INSTALLER_SECTORS),
+                explanation: "A 21-sector format like Microsoft's DMF, as used by installation disks. The extra \
+                    sectors are deliberate, not damage, though the disk doesn't match DMF's geometry exactly.",
+                evidence,
+            },
+        })
+    }
+
+    /// Show the format and why it isn't damage, with the evidence on hover.
+    pub fn show(&self, ui: &mut egui::Ui) {
+        ui.strong(format!("ℹ Installer disk: {}", self.name)).on_hover_ui(|ui| {
+            for evidence in &self.evidence {
+                ui.label(format!("• {}", evidence));
+            }
+        });
+        ui.weak(self.explanation);
+    }
+}
+
+fn sizes(layout: &LayoutSummary) -> String {
+    let sizes: Vec<String> = layout.sector_sizes.iter().map(|size| size.to_string()).collect();
+    sizes.join("/")
+}
@@ -0,0 +1,143 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+pub mod conformance;
+pub mod entropy;
+pub mod fingerprint;
+pub mod flux;
+pub mod gaps;
+pub mod geometry;
+pub mod installer;
+pub mod registry;
+pub mod stepping;
+pub mod trim;
+
+use fluxfox::{DiskCh, DiskChs, DiskChsn, DiskImage, RwSectorScope};
+
+/// Identifies a sector by its physical track and sector ID. The cylinder and head are physical,
+/// so sectors with mismatched IDs (common on copy-protected disks) are still keyed to the track
+/// they were found on.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd, serde::Deserialize, serde::Serialize)]
+pub struct SectorKey {
+    pub c: u16,
+    pub h: u8,
+    pub s: u8,
+}
+
+impl SectorKey {
+    pub fn new(ch: DiskCh, s: u8) -> Self {
+        Self {
+            c: ch.c(),
+            h: ch.h(),
+            s,
+        }
+    }
+
+    pub fn ch(&self) -> DiskCh {
+        DiskCh::new(self.c, self.h)
+    }
+}
+
+impl std::fmt::Display for SectorKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "c:{} h:{} s:{}", self.c, self.h, self.s)
+    }
+}
+
+/// The value that occurs most often, or None if there are no values.
+pub fn most_common<T: Copy + Ord>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts = std::collections::BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0usize) += 1;
+    }
+    counts.into_iter().max_by_key(|(_, count)| *count).map(|(value, _)| value)
+}
+
+/// The data of a single sector read from a disk image.
+pub struct SectorRead {
+    pub key: SectorKey,
+    pub chsn: DiskChsn,
+    pub data: Vec<u8>,
+    pub data_crc_error: bool,
+    pub deleted: bool,
+}
+
+/// Read the data of every sector in the disk image's sector map. Sectors that cannot be read at
+/// all are skipped; sectors with bad CRCs are returned with `data_crc_error` set.
+pub fn read_all_sectors(disk: &mut DiskImage) -> Vec<SectorRead> {
+    let mut sectors = Vec::new();
+
+    for (head, cylinders) in disk.get_sector_map().iter().enumerate() {
+        for (cylinder, entries) in cylinders.iter().enumerate() {
+            let ch = DiskCh::new(cylinder as u16, head as u8);
+            for entry in entries {
+                if let Some(sector) = read_sector(disk, ch, entry.chsn) {
+                    sectors.push(sector);
+                }
+            }
+        }
+    }
+    sectors
+}
+
+/// Hash the data of every sector in key order, so images of the same disk hash the same even
+/// when their containers differ or store tracks in a different order. Sector keys are hashed
+/// too, so the same data at different positions doesn't match.
+pub fn sector_data_hash(disk: &mut DiskImage) -> String {
+    use sha1::Digest;
+
+    let mut reads = read_all_sectors(disk);
+    reads.sort_by_key(|read| read.key);
+    let mut hasher = sha1::Sha1::new();
+    for read in reads.iter() {
+        hasher.update(read.key.c.to_le_bytes());
+        hasher.update([read.key.h, read.key.s]);
+        hasher.update(&read.data);
+    }
+    crate::util::hex_string(&hasher.finalize())
+}
+
+/// Read a single sector's data from the specified physical track.
+pub fn read_sector(disk: &mut DiskImage, ch: DiskCh, chsn: DiskChsn) -> Option<SectorRead> {
+    let id_chs = DiskChs::new(chsn.c(), chsn.h(), chsn.s());
+    match disk.read_sector(ch, id_chs, Some(chsn.n()), RwSectorScope::DataOnly, false) {
+        Ok(result) => {
+            let data_end = result.data_idx + result.data_len;
+            Some(SectorRead {
+                key: SectorKey::new(ch, chsn.s()),
+                chsn,
+                data: result.read_buf[result.data_idx..data_end].to_vec(),
+                data_crc_error: result.data_crc_error,
+                deleted: result.deleted_mark,
+            })
+        }
+        Err(e) => {
+            log::warn!("read_sector(): Failed to read sector {} on {}: {:?}", chsn, ch, e);
+            None
+        }
+    }
+}
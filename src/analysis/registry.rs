@@ -0,0 +1,608 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A registry of the image checks, so they can be run together as a pipeline and switched on
+//! and off individually. Each check has a cost estimate, and the quick profile runs only the
+//! cheap ones; the deep profile runs every enabled check, including those that read every
+//! sector or every file.
+//!
+//! The panels that show each analysis in detail are unaffected; this is the single place to
+//! get a summary of everything, grouped by check, for the report. Checks loaded from plugins
+//! (see `plugin`) are added to the panel alongside the built-in ones.
+
+use std::collections::BTreeSet;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::conformance::ConformanceReport;
+use crate::analysis::entropy::{EntropyClass, EntropyMap};
+use crate::analysis::fingerprint::Fingerprint;
+use crate::analysis::gaps::GapStats;
+use crate::analysis::geometry::LayoutSummary;
+use crate::analysis::installer::InstallerFormat;
+use crate::analysis::stepping::{CylinderMap, Stepping};
+use crate::analysis::trim::TrimAnalysis;
+use crate::boot_repair::BootRepair;
+use crate::fat::check::check;
+use crate::fat::copies::FatComparison;
+use crate::fat::{FatVolume, FileNode};
+use crate::virus;
+
+/// Findings beyond this many per check are counted but not listed.
+pub const MAX_FINDINGS: usize = 20;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cost {
+    /// Uses the sector map and track metadata only.
+    Low,
+    /// Reads every sector once.
+    Medium,
+    /// Reads every sector and file, or several passes of them.
+    High,
+}
+
+impl Cost {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Cost::Low => "low",
+            Cost::Medium => "medium",
+            Cost::High => "high",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Profile {
+    #[default]
+    Quick,
+    Deep,
+}
+
+impl Profile {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Profile::Quick => "Quick",
+            Profile::Deep => "Deep",
+        }
+    }
+
+    pub fn includes(&self, cost: Cost) -> bool {
+        match self {
+            Profile::Quick => cost == Cost::Low,
+            Profile::Deep => true,
+        }
+    }
+}
+
+/// What a check can look at.
+pub struct CheckContext<'a> {
+    pub disk: &'a mut DiskImage,
+    pub fat: Option<(&'a FatVolume, &'a [FileNode])>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, serde::Deserialize, serde::Serialize)]
+pub enum Status {
+    Passed,
+    Warning,
+    /// The check doesn't apply to this image, e.g. a filesystem check without a filesystem.
+    Skipped,
+    /// The check is still running in the background.
+    #[serde(skip_deserializing)]
+    Running,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct CheckOutcome {
+    pub status: Status,
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub findings: Vec<String>,
+}
+
+impl CheckOutcome {
+    pub fn passed(summary: impl Into<String>) -> Self {
+        Self {
+            status: Status::Passed,
+            summary: summary.into(),
+            findings: Vec::new(),
+        }
+    }
+
+    pub fn skipped(summary: impl Into<String>) -> Self {
+        Self {
+            status: Status::Skipped,
+            summary: summary.into(),
+            findings: Vec::new(),
+        }
+    }
+
+    pub fn running(summary: impl Into<String>) -> Self {
+        Self {
+            status: Status::Running,
+            summary: summary.into(),
+            findings: Vec::new(),
+        }
+    }
+
+    /// A warning, or a pass if there are no findings.
+    pub fn from_findings(summary: impl Into<String>, findings: Vec<String>) -> Self {
+        Self {
+            status: if findings.is_empty() { Status::Passed } else { Status::Warning },
+            summary: summary.into(),
+            findings,
+        }
+    }
+}
+
+pub trait Check {
+    /// A stable identifier, which the enabled checks are persisted by.
+    fn id(&self) -> &str;
+    fn name(&self) -> &str;
+    fn cost(&self) -> Cost;
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome;
+    /// For a check whose run finishes in the background: its outcome, once, when it finishes.
+    fn poll(&self) -> Option<CheckOutcome> {
+        None
+    }
+}
+
+struct GapCheck;
+
+impl Check for GapCheck {
+    fn id(&self) -> &str {
+        "gaps"
+    }
+    fn name(&self) -> &str {
+        "Track gaps"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let gaps = GapStats::from_disk(ctx.disk);
+        let findings = gaps
+            .tracks
+            .iter()
+            .filter(|track| !track.flags.is_empty())
+            .map(|track| format!("{}: {}", track.ch, track.flags.join(", ")))
+            .collect();
+        CheckOutcome::from_findings(format!("{} of {} tracks flagged", gaps.flagged(), gaps.tracks.len()), findings)
+    }
+}
+
+struct SteppingCheck;
+
+impl Check for SteppingCheck {
+    fn id(&self) -> &str {
+        "stepping"
+    }
+    fn name(&self) -> &str {
+        "Track stepping"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let map = CylinderMap::from_disk(ctx.disk);
+        let summary = format!("Stepping is {}", map.stepping.label());
+        match map.stepping {
+            Stepping::Straight => CheckOutcome::passed(summary),
+            _ => CheckOutcome::from_findings(summary, vec![map.stepping.label().to_string()]),
+        }
+    }
+}
+
+struct TrimCheck;
+
+impl Check for TrimCheck {
+    fn id(&self) -> &str {
+        "trim"
+    }
+    fn name(&self) -> &str {
+        "Trailing cylinders"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let bpb = ctx.fat.map(|(volume, _)| &volume.bpb);
+        match TrimAnalysis::from_disk(ctx.disk, bpb) {
+            Some(trim) => CheckOutcome::from_findings(trim.summary(), vec![trim.summary()]),
+            None => CheckOutcome::passed("No trailing cylinders"),
+        }
+    }
+}
+
+struct FingerprintCheck;
+
+impl Check for FingerprintCheck {
+    fn id(&self) -> &str {
+        "fingerprint"
+    }
+    fn name(&self) -> &str {
+        "Formatter fingerprint"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let fingerprint = Fingerprint::new(ctx.disk, &GapStats::from_disk(ctx.disk));
+        match fingerprint.guesses.iter().max_by_key(|guess| guess.score) {
+            Some(guess) => CheckOutcome {
+                status: Status::Passed,
+                summary: format!("Most likely formatted by {}", guess.name),
+                findings: guess.evidence.clone(),
+            },
+            None => CheckOutcome::skipped("No formatter guesses"),
+        }
+    }
+}
+
+struct InstallerCheck;
+
+impl Check for InstallerCheck {
+    fn id(&self) -> &str {
+        "installer"
+    }
+    fn name(&self) -> &str {
+        "Installer format"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let layout = LayoutSummary::from_disk(ctx.disk);
+        match InstallerFormat::detect(&layout, ctx.fat.map(|(volume, _)| &volume.bpb)) {
+            Some(format) => CheckOutcome {
+                status: Status::Passed,
+                summary: format!("Recognized {}", format.name),
+                findings: format.evidence,
+            },
+            None => CheckOutcome::passed("Not an installer format"),
+        }
+    }
+}
+
+struct BootSectorCheck;
+
+impl Check for BootSectorCheck {
+    fn id(&self) -> &str {
+        "boot_sector"
+    }
+    fn name(&self) -> &str {
+        "Boot sector"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        match BootRepair::from_disk(ctx.disk) {
+            Some(repair) => CheckOutcome::from_findings("The boot sector is damaged", repair.problems),
+            None => CheckOutcome::passed("No problems found"),
+        }
+    }
+}
+
+struct ConformanceCheck;
+
+impl Check for ConformanceCheck {
+    fn id(&self) -> &str {
+        "conformance"
+    }
+    fn name(&self) -> &str {
+        "Format conformance"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Medium
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let Some(template) = LayoutSummary::from_disk(ctx.disk).standard_geometry().copied()
+        else {
+            return CheckOutcome::skipped("Not a standard geometry");
+        };
+        let gaps = GapStats::from_disk(ctx.disk);
+        let report = ConformanceReport::new(ctx.disk, Some(&gaps), template);
+        let findings = report.deviations.iter().map(|deviation| deviation.description.clone()).collect();
+        let summary = format!("Checked {} tracks against {}", report.tracks_checked, template.name);
+        CheckOutcome::from_findings(summary, findings)
+    }
+}
+
+struct EntropyCheck;
+
+impl Check for EntropyCheck {
+    fn id(&self) -> &str {
+        "entropy"
+    }
+    fn name(&self) -> &str {
+        "Sector entropy"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Medium
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let counts = EntropyMap::from_disk(ctx.disk).class_counts();
+        let findings = EntropyClass::ALL
+            .iter()
+            .filter_map(|class| counts.get(class).map(|count| format!("{}: {} sectors", class.label(), count)))
+            .collect();
+        CheckOutcome {
+            status: Status::Passed,
+            summary: format!("{} sectors classified", counts.values().sum::<usize>()),
+            findings,
+        }
+    }
+}
+
+struct FilesystemCheck;
+
+impl Check for FilesystemCheck {
+    fn id(&self) -> &str {
+        "filesystem"
+    }
+    fn name(&self) -> &str {
+        "Filesystem consistency"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Medium
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let Some((volume, _)) = ctx.fat
+        else {
+            return CheckOutcome::skipped("No FAT filesystem");
+        };
+        let report = check(volume);
+        let mut findings: Vec<String> = report.issues.iter().map(|issue| issue.to_string()).collect();
+        if !report.lost.is_empty() {
+            findings.push(format!("{} lost cluster chains", report.lost.len()));
+        }
+        let (used, free, bad) = (report.used_clusters, report.free_clusters, report.bad_clusters);
+        let summary = format!("{} clusters used, {} free, {} bad", used, free, bad);
+        CheckOutcome::from_findings(summary, findings)
+    }
+}
+
+struct FatCopiesCheck;
+
+impl Check for FatCopiesCheck {
+    fn id(&self) -> &str {
+        "fat_copies"
+    }
+    fn name(&self) -> &str {
+        "FAT copies"
+    }
+    fn cost(&self) -> Cost {
+        Cost::Low
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let Some((volume, tree)) = ctx.fat
+        else {
+            return CheckOutcome::skipped("No FAT filesystem");
+        };
+        let comparison = FatComparison::new(volume, tree);
+        let findings = comparison
+            .differences
+            .iter()
+            .map(|difference| format!("Cluster {}: {:03X?}", difference.cluster, difference.entries))
+            .collect();
+        CheckOutcome::from_findings(format!("{} FAT copies compared", comparison.copies.len()), findings)
+    }
+}
+
+struct VirusCheck;
+
+impl Check for VirusCheck {
+    fn id(&self) -> &str {
+        "virus"
+    }
+    fn name(&self) -> &str {
+        "Virus signatures"
+    }
+    fn cost(&self) -> Cost {
+        Cost::High
+    }
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        let detections = virus::scan(ctx.disk, ctx.fat);
+        let findings = detections
+            .iter()
+            .map(|detection| match &detection.location {
+                virus::Location::Sector(key) => format!("{} in sector {}", detection.name, key),
+                virus::Location::File(path) => format!("{} in {}", detection.name, path),
+            })
+            .collect();
+        CheckOutcome::from_findings(format!("{} signatures found", detections.len()), findings)
+    }
+}
+
+/// Every check, in the order they're run and listed.
+pub fn registry() -> Vec<Box<dyn Check>> {
+    vec![
+        Box::new(GapCheck),
+        Box::new(SteppingCheck),
+        Box::new(TrimCheck),
+        Box::new(FingerprintCheck),
+        Box::new(InstallerCheck),
+        Box::new(BootSectorCheck),
+        Box::new(FatCopiesCheck),
+        Box::new(ConformanceCheck),
+        Box::new(EntropyCheck),
+        Box::new(FilesystemCheck),
+        Box::new(VirusCheck),
+    ]
+}
+
+/// Which checks are switched off, and the profile last used, persisted between sessions.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct CheckSettings {
+    pub disabled: BTreeSet<String>,
+    pub profile: Profile,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct CheckResult {
+    pub id: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub outcome: CheckOutcome,
+}
+
+/// Run the checks the profile includes and the settings haven't disabled.
+pub fn run_checks<'a>(
+    checks: impl Iterator<Item = &'a dyn Check>,
+    ctx: &mut CheckContext,
+    settings: &CheckSettings,
+) -> Vec<CheckResult> {
+    checks
+        .filter(|check| settings.profile.includes(check.cost()) && !settings.disabled.contains(check.id()))
+        .map(|check| {
+            let outcome = check.run(ctx);
+            log::debug!("Check {}: {}", check.id(), outcome.summary);
+            CheckResult {
+                id: check.id().to_string(),
+                name: check.name().to_string(),
+                outcome,
+            }
+        })
+        .collect()
+}
+
+pub struct AnalysisPanel {
+    checks: Vec<Box<dyn Check>>,
+    pub results: Vec<CheckResult>,
+    copy_requested: bool,
+}
+
+impl Default for AnalysisPanel {
+    fn default() -> Self {
+        Self {
+            checks: registry(),
+            results: Vec::new(),
+            copy_requested: false,
+        }
+    }
+}
+
+impl AnalysisPanel {
+    pub fn clear(&mut self) {
+        self.results.clear();
+    }
+
+    /// Add a check from outside the registry, such as a plugin. Replaces any check with the same
+    /// id, so a plugin can be reloaded; plugin ids are namespaced so they can't match a built-in.
+    pub fn add_check(&mut self, check: Box<dyn Check>) {
+        self.checks.retain(|existing| existing.id() != check.id());
+        self.checks.push(check);
+    }
+
+    /// Show a checkbox for each check, to switch it on or off.
+    pub fn show_check_list(&self, ui: &mut egui::Ui, settings: &mut CheckSettings) {
+        egui::Grid::new("analysis_registry_check_grid").num_columns(2).show(ui, |ui| {
+            for check in &self.checks {
+                let mut enabled = !settings.disabled.contains(check.id());
+                if ui.checkbox(&mut enabled, check.name()).changed() {
+                    match enabled {
+                        true => settings.disabled.remove(check.id()),
+                        false => settings.disabled.insert(check.id().to_string()),
+                    };
+                }
+                ui.weak(format!("{} cost", check.cost().label()));
+                ui.end_row();
+            }
+        });
+    }
+
+    /// Show the panel. Returns where it was drawn if the user asked to copy it as an image.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        settings: &mut CheckSettings,
+        ctx: &mut CheckContext,
+    ) -> Option<egui::Rect> {
+        for check in &self.checks {
+            let Some(outcome) = check.poll()
+            else {
+                continue;
+            };
+            if let Some(result) = self.results.iter_mut().find(|result| result.id == check.id()) {
+                result.outcome = outcome;
+            }
+        }
+        let warnings = self.results.iter().filter(|result| result.outcome.status == Status::Warning).count();
+        let title = match self.results.is_empty() {
+            true => "Analysis".to_string(),
+            false => format!("Analysis ({} checks, {} with warnings)", self.results.len(), warnings),
+        };
+        let response = egui::CollapsingHeader::new(title).id_salt("analysis_registry").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                for profile in [Profile::Quick, Profile::Deep] {
+                    ui.radio_value(&mut settings.profile, profile, profile.label());
+                }
+                if ui.button("Run").clicked() {
+                    self.results = run_checks(self.checks.iter().map(|check| check.as_ref()), ctx, settings);
+                }
+                if ui
+                    .add_enabled(!self.results.is_empty(), egui::Button::new("Copy as image"))
+                    .on_hover_text("Copy the results to the clipboard as a PNG.")
+                    .clicked()
+                {
+                    self.copy_requested = true;
+                }
+            });
+
+            egui::CollapsingHeader::new("Checks").id_salt("analysis_registry_checks").show(ui, |ui| {
+                self.show_check_list(ui, settings);
+            });
+
+            for result in &self.results {
+                let color = match result.outcome.status {
+                    Status::Passed => ui.visuals().text_color(),
+                    Status::Warning => ui.visuals().warn_fg_color,
+                    Status::Skipped | Status::Running => ui.visuals().weak_text_color(),
+                };
+                let header = egui::RichText::new(format!("{}: {}", result.name, result.outcome.summary)).color(color);
+                egui::CollapsingHeader::new(header).id_salt(("analysis_result", &result.id)).show(ui, |ui| {
+                    for finding in result.outcome.findings.iter().take(MAX_FINDINGS) {
+                        ui.label(finding);
+                    }
+                    if result.outcome.findings.len() > MAX_FINDINGS {
+                        ui.weak(format!("and {} more", result.outcome.findings.len() - MAX_FINDINGS));
+                    }
+                    if result.outcome.findings.is_empty() {
+                        ui.weak("Nothing to report.");
+                    }
+                });
+            }
+        });
+        if !std::mem::take(&mut self.copy_requested) {
+            return None;
+        }
+        let body = response.body_response.map(|body| body.rect);
+        Some(body.map_or(response.header_response.rect, |rect| rect.union(response.header_response.rect)))
+    }
+}
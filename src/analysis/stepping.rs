@@ -0,0 +1,207 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Physical to logical cylinder mapping. A 40-track disk read in an 80-track drive without
+//! double stepping puts each logical cylinder on every other physical track, with the tracks
+//! between empty or holding a faint copy of a neighbour. An 80-track disk read in a 40-track
+//! drive has the opposite problem: only every other cylinder was read at all.
+//!
+//! The logical cylinder of each track comes from the cylinder field of its sector IDs.
+
+use std::collections::{BTreeMap, HashMap};
+
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::analysis::most_common;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stepping {
+    /// Every track's IDs match its physical cylinder.
+    Straight,
+    /// Logical cylinder N was read from physical cylinder 2N + `offset`.
+    DoubleStepped { offset: u16 },
+    /// Physical cylinder N holds logical cylinder 2N; the odd cylinders weren't read.
+    HalfStepped,
+    /// The IDs follow no consistent mapping, as on many copy-protected disks.
+    Irregular,
+}
+
+impl Stepping {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Stepping::Straight => "straight",
+            Stepping::DoubleStepped { .. } => "40-track disk read in an 80-track drive",
+            Stepping::HalfStepped => "80-track disk read in a 40-track drive",
+            Stepping::Irregular => "irregular",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackMapping {
+    pub ch: DiskCh,
+    /// The most common cylinder in the track's sector IDs, or None for an unformatted track.
+    pub logical: Option<u16>,
+    pub sectors: usize,
+    /// The most common sector size on the track.
+    pub sector_size: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct CylinderMap {
+    pub tracks: Vec<TrackMapping>,
+    pub stepping: Stepping,
+}
+
+impl CylinderMap {
+    pub fn from_disk(disk: &DiskImage) -> Self {
+        let mut tracks = Vec::new();
+        for (head, cylinders) in disk.get_sector_map().iter().enumerate() {
+            for (cylinder, entries) in cylinders.iter().enumerate() {
+                tracks.push(TrackMapping {
+                    ch: DiskCh::new(cylinder as u16, head as u8),
+                    logical: most_common(entries.iter().map(|entry| entry.chsn.c())),
+                    sectors: entries.len(),
+                    sector_size: most_common(entries.iter().map(|entry| entry.chsn.n_size())).unwrap_or(0),
+                });
+            }
+        }
+        let stepping = Self::classify(&tracks);
+        Self { tracks, stepping }
+    }
+
+    fn classify(tracks: &[TrackMapping]) -> Stepping {
+        let mapped: Vec<(u16, u16)> = tracks
+            .iter()
+            .filter_map(|track| track.logical.map(|logical| (track.ch.c(), logical)))
+            .collect();
+        // A disk with only cylinder 0 formatted could be any of them.
+        if mapped.iter().all(|(_, logical)| *logical == 0) {
+            return Stepping::Straight;
+        }
+        if mapped.iter().all(|(physical, logical)| physical == logical) {
+            return Stepping::Straight;
+        }
+        if mapped.iter().all(|(physical, logical)| *logical as u32 == *physical as u32 * 2) {
+            return Stepping::HalfStepped;
+        }
+        // Tracks between the stepped ones may hold a copy of either neighbour, so the offset
+        // comes from the tracks with the most sectors.
+        let max_sectors = tracks.iter().map(|track| track.sectors).max().unwrap_or(0);
+        let offset = most_common(
+            tracks
+                .iter()
+                .filter(|track| track.sectors == max_sectors)
+                .filter_map(|track| track.ch.c().checked_sub(track.logical? * 2)),
+        );
+        match offset {
+            Some(offset @ (0 | 1))
+                if mapped.iter().all(|(physical, logical)| {
+                    let stepped = *logical * 2 + offset;
+                    (stepped.saturating_sub(1)..=stepped + 1).contains(physical)
+                }) =>
+            {
+                Stepping::DoubleStepped { offset }
+            }
+            _ => Stepping::Irregular,
+        }
+    }
+
+    /// Show the mapping, if it isn't straight. Returns the track of a row the user clicked on,
+    /// and sets `de_double_step` if they asked for the in-between tracks to be dropped.
+    pub fn show(&self, ui: &mut egui::Ui, de_double_step: &mut bool) -> Option<DiskCh> {
+        if self.stepping == Stepping::Straight {
+            return None;
+        }
+        let mut selected = None;
+        let title = format!("Cylinder mapping: {}", self.stepping.label());
+        egui::CollapsingHeader::new(title).id_salt("cylinder_map").show(ui, |ui| {
+            if let Stepping::DoubleStepped { offset } = self.stepping {
+                let cylinders = self.tracks.iter().map(|track| track.ch.c() + 1).max().unwrap_or(0);
+                let kept = cylinders.saturating_sub(offset).div_ceil(2);
+                if ui
+                    .button(format!("De-double-step to {} cylinders", kept))
+                    .on_hover_text("Rebuild the image from the stepped tracks, dropping the ones between.")
+                    .clicked()
+                {
+                    *de_double_step = true;
+                }
+            }
+            if self.stepping == Stepping::HalfStepped {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    "Half of the disk's cylinders are missing. Re-dump it in an 80-track drive.",
+                );
+            }
+
+            // Group the heads of each cylinder into a row.
+            let mut rows: BTreeMap<u16, HashMap<u8, &TrackMapping>> = BTreeMap::new();
+            for track in self.tracks.iter() {
+                rows.entry(track.ch.c()).or_default().insert(track.ch.h(), track);
+            }
+            let heads = self.tracks.iter().map(|track| track.ch.h() + 1).max().unwrap_or(0);
+            let warn_color = ui.visuals().warn_fg_color;
+            egui::ScrollArea::vertical().id_salt("cylinder_map_table").max_height(240.0).show(ui, |ui| {
+                egui::Grid::new("cylinder_map_grid").striped(true).num_columns(heads as usize + 1).show(ui, |ui| {
+                    ui.strong("Physical");
+                    for h in 0..heads {
+                        ui.strong(format!("Head {}", h));
+                    }
+                    ui.end_row();
+                    for (c, row) in rows.iter() {
+                        ui.label(c.to_string());
+                        for h in 0..heads {
+                            match row.get(&h) {
+                                Some(track) => {
+                                    let text = match track.logical {
+                                        Some(logical) => format!("→ c:{} ({} sectors)", logical, track.sectors),
+                                        None => "unformatted".to_string(),
+                                    };
+                                    let response = match track.logical {
+                                        Some(logical) if logical != *c => ui.add(
+                                            egui::Label::new(egui::RichText::new(text).color(warn_color))
+                                                .sense(egui::Sense::click()),
+                                        ),
+                                        _ => ui.add(egui::Label::new(text).sense(egui::Sense::click())),
+                                    };
+                                    if response.clicked() {
+                                        selected = Some(track.ch);
+                                    }
+                                }
+                                None => {
+                                    ui.label("");
+                                }
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+        selected
+    }
+}
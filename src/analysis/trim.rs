@@ -0,0 +1,171 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Trailing cylinders. Dumping software often reads a few cylinders past the end of the disk to
+//! be safe, leaving an 82-cylinder image of an 80-cylinder disk that no emulator expects. The
+//! cylinders past the filesystem's extent, or past the last formatted one if there's no
+//! filesystem, can be trimmed off to get back to a standard geometry.
+
+use fluxfox::DiskImage;
+
+use crate::analysis::geometry::{known_geometries, LayoutSummary, StandardGeometry};
+use crate::analysis::most_common;
+use crate::fat::BiosParameterBlock;
+
+#[derive(Clone, Debug)]
+pub struct TrailingCylinder {
+    pub c: u16,
+    /// The number of sectors found on the cylinder, over all heads.
+    pub sectors: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrimAnalysis {
+    /// The number of cylinders in the image.
+    pub cylinders: u16,
+    /// The cylinders the filesystem covers, if there is one.
+    pub fs_cylinders: Option<u16>,
+    /// The standard geometry the image would be trimmed to.
+    pub geometry: StandardGeometry,
+    /// The cylinders that would be removed.
+    pub trailing: Vec<TrailingCylinder>,
+    /// Set when the user chooses to keep the cylinders.
+    pub declined: bool,
+}
+
+impl TrimAnalysis {
+    /// Look for cylinders past the end of the disk's contents. Returns None if there are none,
+    /// or the rest of the disk doesn't fit a standard geometry.
+    pub fn from_disk(disk: &DiskImage, bpb: Option<&BiosParameterBlock>) -> Option<Self> {
+        let layout = LayoutSummary::from_disk(disk);
+        let sector_map = disk.get_sector_map();
+
+        let fs_cylinders = bpb.and_then(|bpb| {
+            let per_cylinder = bpb.sectors_per_track as u32 * bpb.heads as u32;
+            (per_cylinder > 0).then(|| bpb.total_sectors.div_ceil(per_cylinder) as u16)
+        });
+        let keep = fs_cylinders.unwrap_or(layout.formatted_cylinders);
+        if keep == 0 || keep >= layout.cylinders {
+            return None;
+        }
+
+        let kept_tracks = || sector_map.iter().flat_map(|cylinders| cylinders.iter().take(keep as usize));
+        let sectors = most_common(kept_tracks().map(|entries| entries.len()))?;
+        let sector_size = most_common(kept_tracks().flatten().map(|entry| entry.chsn.n_size()))?;
+        let geometry = known_geometries()
+            .filter(|geometry| {
+                geometry.heads == layout.heads
+                    && geometry.sectors as usize == sectors
+                    && geometry.sector_size == sector_size
+                    && geometry.cylinders >= keep
+            })
+            .min_by_key(|geometry| geometry.cylinders)
+            .copied()?;
+        if geometry.cylinders >= layout.cylinders {
+            return None;
+        }
+
+        let trailing = (geometry.cylinders..layout.cylinders)
+            .map(|c| TrailingCylinder {
+                c,
+                sectors: sector_map
+                    .iter()
+                    .filter_map(|cylinders| cylinders.get(c as usize))
+                    .map(|entries| entries.len())
+                    .sum(),
+            })
+            .collect();
+        Some(Self {
+            cylinders: layout.cylinders,
+            fs_cylinders,
+            geometry,
+            trailing,
+            declined: false,
+        })
+    }
+
+    /// Whether any of the trimmed cylinders were formatted, so may hold something worth keeping.
+    pub fn trims_formatted(&self) -> bool {
+        self.trailing.iter().any(|cylinder| cylinder.sectors > 0)
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} trailing cylinders past {} ({} in the image)",
+            self.trailing.len(),
+            self.geometry.name,
+            self.cylinders
+        )
+    }
+
+    /// Show the analysis, unless the user declined the trim. Returns true if they asked for it.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        if self.declined {
+            return false;
+        }
+        let mut trim = false;
+        egui::CollapsingHeader::new(format!("Trim: {}", self.summary()))
+            .id_salt("trim_analysis")
+            .show(ui, |ui| {
+                let extent = match self.fs_cylinders {
+                    Some(cylinders) => format!("The filesystem ends on cylinder {}.", cylinders.saturating_sub(1)),
+                    None => "There's no filesystem, so the last formatted cylinder marks the end of the disk.".to_string(),
+                };
+                ui.label(extent);
+                egui::Grid::new("trim_grid").striped(true).num_columns(2).show(ui, |ui| {
+                    ui.strong("Cylinder");
+                    ui.strong("Sectors");
+                    ui.end_row();
+                    for cylinder in self.trailing.iter() {
+                        ui.label(cylinder.c.to_string());
+                        if cylinder.sectors > 0 {
+                            ui.colored_label(ui.visuals().warn_fg_color, cylinder.sectors.to_string());
+                        }
+                        else {
+                            ui.weak("unformatted");
+                        }
+                        ui.end_row();
+                    }
+                });
+                if self.trims_formatted() {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        "Some of these cylinders are formatted. Check them for protection tracks before trimming.",
+                    );
+                }
+                ui.horizontal(|ui| {
+                    if ui.button(format!("Trim to {}", self.geometry.name)).clicked() {
+                        trim = true;
+                    }
+                    if ui.button("Keep them").clicked() {
+                        self.declined = true;
+                    }
+                });
+            });
+        trim
+    }
+}
@@ -0,0 +1,258 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use anyhow::{anyhow, Error};
+use fluxfox::DiskCh;
+use fluxfox::tiny_skia::Color;
+
+use crate::analysis::SectorKey;
+
+/// What an annotation is attached to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, serde::Deserialize, serde::Serialize)]
+pub enum AnnotationTarget {
+    Track { c: u16, h: u8 },
+    Sector(SectorKey),
+}
+
+impl AnnotationTarget {
+    pub fn track(ch: DiskCh) -> Self {
+        AnnotationTarget::Track { c: ch.c(), h: ch.h() }
+    }
+
+    pub fn head(&self) -> u8 {
+        match self {
+            AnnotationTarget::Track { h, .. } => *h,
+            AnnotationTarget::Sector(key) => key.h,
+        }
+    }
+}
+
+impl std::fmt::Display for AnnotationTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnnotationTarget::Track { c, h } => write!(f, "track c:{} h:{}", c, h),
+            AnnotationTarget::Sector(key) => write!(f, "sector {}", key),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum AnnotationTag {
+    #[default]
+    Note,
+    Important,
+    Protection,
+    Damaged,
+    Question,
+}
+
+impl AnnotationTag {
+    pub const ALL: [AnnotationTag; 5] = [
+        AnnotationTag::Note,
+        AnnotationTag::Important,
+        AnnotationTag::Protection,
+        AnnotationTag::Damaged,
+        AnnotationTag::Question,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AnnotationTag::Note => "Note",
+            AnnotationTag::Important => "Important",
+            AnnotationTag::Protection => "Protection",
+            AnnotationTag::Damaged => "Damaged",
+            AnnotationTag::Question => "Question",
+        }
+    }
+
+    pub fn rgba(&self) -> [u8; 4] {
+        match self {
+            AnnotationTag::Note => [0x41, 0xa6, 0xf6, 0xff],
+            AnnotationTag::Important => [0xff, 0xcd, 0x75, 0xff],
+            AnnotationTag::Protection => [0xb1, 0x3e, 0xd3, 0xff],
+            AnnotationTag::Damaged => [0xe0, 0x30, 0x30, 0xff],
+            AnnotationTag::Question => [0xf0, 0xf0, 0xf0, 0xff],
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        let [r, g, b, a] = self.rgba();
+        Color::from_rgba8(r, g, b, a)
+    }
+
+    pub fn color32(&self) -> egui::Color32 {
+        let [r, g, b, a] = self.rgba();
+        egui::Color32::from_rgba_unmultiplied(r, g, b, a)
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Annotation {
+    pub target: AnnotationTarget,
+    pub tag: AnnotationTag,
+    pub note: String,
+}
+
+pub const ANNOTATION_FILE_VERSION: u32 = 1;
+pub const ANNOTATION_FILE_SUFFIX: &str = ".annotations.json";
+
+/// A shareable set of annotations. Annotations are keyed to the SHA-1 hash of the image file
+/// they were made on, so they can be exchanged without exchanging the image itself.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct AnnotationFile {
+    pub version: u32,
+    pub image_hash: String,
+    pub image_name: String,
+    pub annotations: Vec<Annotation>,
+}
+
+impl AnnotationFile {
+    pub fn is_annotation_file(name: &str) -> bool {
+        name.to_ascii_lowercase().ends_with(ANNOTATION_FILE_SUFFIX)
+    }
+
+    pub fn filename(image_name: &str) -> String {
+        format!("{}{}", image_name, ANNOTATION_FILE_SUFFIX)
+    }
+}
+
+/// Events produced by the annotation panel that the app needs to act on.
+pub enum AnnotationEvent {
+    Select(AnnotationTarget),
+    Changed,
+}
+
+/// The set of annotations attached to the loaded image.
+#[derive(Default)]
+pub struct Annotations {
+    pub items: Vec<Annotation>,
+    new_tag: AnnotationTag,
+    new_note: String,
+}
+
+impl Annotations {
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn add(&mut self, target: AnnotationTarget, tag: AnnotationTag, note: String) {
+        self.items.push(Annotation { target, tag, note });
+    }
+
+    pub fn to_file(&self, image_hash: &str, image_name: &str) -> Result<String, Error> {
+        let file = AnnotationFile {
+            version: ANNOTATION_FILE_VERSION,
+            image_hash: image_hash.to_string(),
+            image_name: image_name.to_string(),
+            annotations: self.items.clone(),
+        };
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+
+    /// Merge the annotations in an annotation file into this set, skipping exact duplicates.
+    /// Fails if the file was made for a different image. Returns the number of annotations added.
+    pub fn import(&mut self, json: &[u8], image_hash: &str) -> Result<usize, Error> {
+        let file: AnnotationFile = serde_json::from_slice(json)?;
+        if file.version > ANNOTATION_FILE_VERSION {
+            return Err(anyhow!("Unsupported annotation file version {}", file.version));
+        }
+        if !file.image_hash.eq_ignore_ascii_case(image_hash) {
+            return Err(anyhow!(
+                "Annotations are for a different image ({}, hash {})",
+                file.image_name,
+                file.image_hash
+            ));
+        }
+
+        let mut added = 0;
+        for annotation in file.annotations {
+            let duplicate = self.items.iter().any(|existing| {
+                existing.target == annotation.target && existing.tag == annotation.tag && existing.note == annotation.note
+            });
+            if !duplicate {
+                self.items.push(annotation);
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Return the tag of the first annotation on `target`, if any.
+    pub fn tag_for(&self, target: &AnnotationTarget) -> Option<AnnotationTag> {
+        self.items
+            .iter()
+            .find(|annotation| annotation.target == *target)
+            .map(|annotation| annotation.tag)
+    }
+
+    /// Show the annotation list, and an editor for adding an annotation to `selected`.
+    pub fn show(&mut self, ui: &mut egui::Ui, selected: Option<AnnotationTarget>) -> Option<AnnotationEvent> {
+        let mut event = None;
+
+        if let Some(target) = selected {
+            ui.horizontal(|ui| {
+                ui.label(format!("Annotate {}:", target));
+                egui::ComboBox::from_id_salt("annotation_new_tag")
+                    .selected_text(self.new_tag.label())
+                    .show_ui(ui, |ui| {
+                        for tag in AnnotationTag::ALL {
+                            ui.selectable_value(&mut self.new_tag, tag, tag.label());
+                        }
+                    });
+                ui.text_edit_singleline(&mut self.new_note);
+                if ui.button("Add").clicked() {
+                    let note = std::mem::take(&mut self.new_note);
+                    self.add(target, self.new_tag, note);
+                    event = Some(AnnotationEvent::Changed);
+                }
+            });
+        }
+
+        egui::CollapsingHeader::new(format!("Annotations ({})", self.items.len()))
+            .id_salt("annotations_list")
+            .show(ui, |ui| {
+                let mut remove = None;
+                for (i, annotation) in self.items.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(annotation.tag.color32(), "⏺");
+                        if ui.link(annotation.target.to_string()).clicked() {
+                            event = Some(AnnotationEvent::Select(annotation.target));
+                        }
+                        ui.label(&annotation.note);
+                        if ui.small_button("🗑").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    self.items.remove(i);
+                    event = Some(AnnotationEvent::Changed);
+                }
+            });
+
+        event
+    }
+}
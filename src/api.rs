@@ -0,0 +1,132 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Functions for using the analysis without the app, built with the `api` feature. Build the
+//! library with `--no-default-features --features api` to leave out the interactive frontend,
+//! then load, analyze and convert images from JavaScript:
+//!
+//! ```js
+//! const image = Image.load("disk.imd", bytes);
+//! const report = JSON.parse(image.analyze('{"profile": "Deep"}'));
+//! const img = image.convert("img");
+//! ```
+//!
+//! Everything runs on the calling thread, so large images are best handled in a worker.
+
+use std::io::Cursor;
+
+use fluxfox::DiskImage;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::analysis::entropy::EntropyMap;
+use crate::analysis::registry::{registry, run_checks, CheckContext, CheckSettings};
+use crate::export::{export_formats, export_to_memory};
+use crate::fat::FatVolume;
+use crate::report::ImageReport;
+
+fn api_error(e: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// A loaded disk image.
+#[wasm_bindgen]
+pub struct Image {
+    disk: DiskImage,
+    name: String,
+    source_size: usize,
+}
+
+#[wasm_bindgen]
+impl Image {
+    /// Load a disk image from the contents of its file, in any format fluxfox reads.
+    pub fn load(name: &str, bytes: &[u8]) -> Result<Image, JsValue> {
+        let mut cursor = Cursor::new(bytes);
+        let disk = DiskImage::load(&mut cursor, None, None, None).map_err(|e| api_error(format!("{:?}", e)))?;
+        log::debug!("Image::load(): loaded {} ({} bytes)", name, bytes.len());
+        Ok(Self {
+            disk,
+            name: name.to_string(),
+            source_size: bytes.len(),
+        })
+    }
+
+    /// A JSON summary of the image, without running any checks.
+    pub fn info(&self) -> Result<String, JsValue> {
+        self.report().to_json().map_err(api_error)
+    }
+
+    /// Run the analysis checks and return the report as JSON. `settings` is optional JSON of the
+    /// form `{"profile": "Quick" | "Deep", "disabled": ["check_id", ..]}`.
+    pub fn analyze(&mut self, settings: Option<String>) -> Result<String, JsValue> {
+        let settings: CheckSettings = match settings {
+            Some(json) => serde_json::from_str(&json).map_err(api_error)?,
+            None => CheckSettings::default(),
+        };
+        let entropy = EntropyMap::from_disk(&mut self.disk);
+        let volume = FatVolume::from_disk(&mut self.disk).ok();
+        let tree = volume.as_ref().map(|volume| volume.tree()).unwrap_or_default();
+        let checks = registry();
+        let mut ctx = CheckContext {
+            disk: &mut self.disk,
+            fat: volume.as_ref().map(|volume| (volume, tree.as_slice())),
+        };
+        let results = run_checks(checks.iter().map(|check| check.as_ref()), &mut ctx, &settings);
+        self.report()
+            .with_entropy(Some(&entropy))
+            .with_checks(&results)
+            .to_json()
+            .map_err(api_error)
+    }
+
+    /// The formats the image can be converted to without losing data, by extension.
+    pub fn formats(&self) -> Vec<String> {
+        export_formats(&self.disk)
+            .iter()
+            .filter_map(|format| format.extensions.first().cloned())
+            .collect()
+    }
+
+    /// Convert the image to another format, named by extension (`img`) or by fluxfox name.
+    pub fn convert(&mut self, format: &str) -> Result<Vec<u8>, JsValue> {
+        let formats = export_formats(&self.disk);
+        let Some(export_format) = formats.iter().find(|candidate| {
+            candidate.format_name().eq_ignore_ascii_case(format)
+                || candidate.extensions.iter().any(|ext| ext.eq_ignore_ascii_case(format))
+        })
+        else {
+            return Err(api_error(format!("{} can't be written as {} without losing data", self.name, format)));
+        };
+        export_to_memory(&mut self.disk, export_format.format).map_err(api_error)
+    }
+}
+
+impl Image {
+    fn report(&self) -> ImageReport {
+        ImageReport::new(&self.name, self.source_size, &self.disk)
+    }
+}
@@ -25,22 +25,86 @@
     --------------------------------------------------------------------------
 */
 
+use std::collections::{BTreeSet, HashMap};
 use std::default::Default;
-use std::sync::{Arc};
-use std::sync::mpsc;
-use fluxfox::{DiskImage, DiskImageError, LoadingStatus};
-
+use std::sync::{Arc, Mutex};
+use fluxfox::{DiskCh, DiskImage, DiskImageError, LoadingStatus};
+
+use fluxfox::tiny_skia::Color;
+
+use crate::analysis::entropy::{EntropyClass, EntropyMap};
+use crate::analysis::flux::{FluxAnalysis, TrackFlux};
+use crate::analysis::gaps::GapStats;
+use crate::analysis::geometry::LayoutSummary;
+use crate::analysis::installer::InstallerFormat;
+use crate::analysis::registry::{AnalysisPanel, Check, CheckContext, CheckSettings, Profile};
+use crate::analysis::trim::TrimAnalysis;
+use crate::analysis::{self, SectorKey};
+use crate::annotations::{AnnotationEvent, AnnotationFile, AnnotationTarget, Annotations};
+use crate::apple2::AppleBrowser;
+use crate::archive;
+use crate::assets::Assets;
+use crate::autosave::{self, AutosaveSettings, PendingRestore, SavedPosition, Workspace};
+use crate::bookmarks::{Bookmark, BookmarkAction, Bookmarks};
+use crate::boot_test;
+use crate::capture::PanelCapture;
+use crate::cbm::{CbmBrowser, CbmVolume};
+use crate::checksum::{ChecksumManifest, ChecksumResult, ChecksumVerifier, Digests};
+use crate::compare::Comparison;
+use crate::copy::{self, CopyDialog};
+use crate::cpm::CpmBrowser;
+use crate::crash::{CrashDialog, CrashReport};
+use crate::disk_set::{DiskSet, DiskSetAction};
+use crate::drag_out::DragOut;
+use crate::export::{
+    self, EmulatorExport, EmulatorPreset, ExportFormat, ExportNaming, ExportPreset, NameFields, PresetEditor,
+    WatchMode, BOOT_TEST_PRESET, EMULATOR_PRESETS,
+};
+use crate::fat::browser::{BrowserEvent, FatBrowser};
+use crate::fat::ident::FileIdent;
+use crate::frame_budget::{FrameBudget, Incremental, PerformanceSettings};
+use crate::gl_context::{ContextState, ContextWatcher};
+use crate::hard_sector::HardSectoring;
+use crate::hires::{self, HiresRender, HiresRequest};
+use crate::history::History;
+use crate::journal::EditJournal;
+use crate::kryoflux::{self, StreamMap};
+use crate::merge::{self, MergeAction, MergeTool};
+use crate::onboarding::{Tutorial, TutorialProgress, TutorialState};
+use crate::panels::{PanelContext, PanelEvent, PanelRegistry};
+use crate::persist;
+use crate::plugin::PluginLoader;
+use crate::pwa::Pwa;
+use crate::report::ImageReport;
+use crate::samples::SampleLoader;
+use crate::scp::ScpInfo;
+use crate::sector_clip::{ClipAction, SectorClip, SectorClipboard};
+use crate::selection::{Selection, SelectionBus, SelectionSource};
+use crate::session::{Session, SessionEntry};
+use crate::settings::{Section, SettingsWindow};
+use crate::sidecar::{ImageMetadata, ImageSidecar, LabelImage};
+use crate::storage::{self, StoredFile};
+use crate::templates::{self, StructTemplate};
+use crate::tasks::{TaskKind, TaskManager, TaskMessage, TaskOutput};
+use crate::toasts::{ToastLevel, Toasts};
+use crate::transform::{self, Transform, TransformDialog, UndoChange, UndoEntry, UndoStack};
 use crate::worker;
+use crate::unsupported::{FileProbe, FormatReports, UnsupportedDialog};
 use crate::util;
-use crate::viz::VisualizationState;
-
-#[derive (Default)]
-pub enum ThreadLoadStatus {
-    #[default]
-    Inactive,
-    Loading(f64),
-    Success(DiskImage),
-    Error(DiskImageError),
+use crate::viz::{VisualizationState, VizOverlayMode, VizSettings};
+use crate::watchdog::{self, ImageSnapshot};
+use crate::widgets::hex_view::HexViewer;
+
+
+/// What a change did to the image, which decides how much of the state kept for it survives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ChangeScope {
+    /// Sectors or tracks were changed in place. It's still the image the user's annotations,
+    /// bookmarks and metadata were made on, and the file it was loaded from, so those and the
+    /// file's hash are kept; only the analyses are redone.
+    InPlace,
+    /// The image was replaced by a new one derived from it.
+    Replace,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -49,24 +113,141 @@ enum RunMode {
     Continuous,
 }
 
+/// The storage key [PersistentState] is saved under. State from before versioning was saved
+/// under [eframe::APP_KEY], and is read from there if this key is empty.
+const PERSISTENT_STATE_KEY: &str = "ffweb_settings";
+
+/// Steps bringing saved [PersistentState] up to date; entry `n` upgrades version `n` to `n + 1`.
+/// When a change to the state needs saved values adjusted rather than just defaulted, such as
+/// renaming or moving a field, add a step here.
+const PERSISTENT_STATE_MIGRATIONS: &[persist::Migration] = &[migrate_state_v0];
+const PERSISTENT_STATE_VERSION: u32 = PERSISTENT_STATE_MIGRATIONS.len() as u32;
+
+/// Version 0 is state from before versioning. Its being saved at all means the app has been used
+/// here already, so don't greet the user with the tutorial unless they'd started it.
+fn migrate_state_v0(state: &mut serde_json::Map<String, serde_json::Value>) {
+    let tutorial = state.entry("tutorial").or_insert_with(|| serde_json::json!({}));
+    if let Some(tutorial) = tutorial.as_object_mut() {
+        let started = tutorial.get("step").and_then(|step| step.as_u64()).unwrap_or(0) > 0;
+        if !started {
+            tutorial.insert("closed".to_string(), serde_json::Value::Bool(true));
+        }
+    }
+}
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
-#[derive(Default)]
 pub struct PersistentState {
+    /// Set from PERSISTENT_STATE_VERSION when saved. State saved before versioning has none, so
+    /// reads as 0.
+    #[serde(default)]
+    version: u32,
     label: String,
+    viz_settings: VizSettings,
+    export_presets: Vec<ExportPreset>,
+    watch_mode: WatchMode,
+    export_naming: ExportNaming,
+    format_reports: FormatReports,
+    analysis_checks: CheckSettings,
+    hidden_panels: BTreeSet<String>,
+    tutorial: TutorialProgress,
+    performance: PerformanceSettings,
+    autosave: AutosaveSettings,
+}
+
+impl Default for PersistentState {
+    fn default() -> Self {
+        Self {
+            version: PERSISTENT_STATE_VERSION,
+            label: String::new(),
+            viz_settings: VizSettings::default(),
+            export_presets: Vec::new(),
+            watch_mode: WatchMode::default(),
+            export_naming: ExportNaming::default(),
+            format_reports: FormatReports::default(),
+            analysis_checks: CheckSettings::default(),
+            hidden_panels: BTreeSet::new(),
+            tutorial: TutorialProgress::default(),
+            performance: PerformanceSettings::default(),
+            autosave: AutosaveSettings::default(),
+        }
+    }
 }
 
 pub struct App {
     p_state: PersistentState,
+    /// Saved state set aside while loading, to be written out on the next save.
+    state_backups: Vec<(String, String)>,
     run_mode: RunMode,
     ctx_init: bool,
     dropped_files: Vec<egui::DroppedFile>,
-    load_status: ThreadLoadStatus,
-    load_sender: Option<mpsc::SyncSender<ThreadLoadStatus>>,
-    load_receiver: Option<mpsc::Receiver<ThreadLoadStatus>>,
+    tasks: TaskManager,
+    load_failed: bool,
+    stream_map: Option<StreamMap>,
+    export_save_target: Option<storage::SaveTarget>,
+    /// Whether to download the metadata sidecar with the export in progress.
+    export_sidecar: bool,
+    preset_editor: PresetEditor,
+    copy_dialog: CopyDialog,
+    transform_dialog: TransformDialog,
+    undo: UndoStack,
+    journal: EditJournal,
+    sector_clipboard: SectorClipboard,
+    comparison: Comparison,
+    merge: MergeTool,
+    export_error: Option<String>,
     disk_image_name: Option<String>,
+    disk_image_len: usize,
+    disk_image_hash: Option<String>,
+    disk_image_digests: Option<Digests>,
     pub(crate) disk_image: Option<DiskImage>,
+    entropy: Option<EntropyMap>,
+    gap_stats: Option<GapStats>,
+    trim: Option<TrimAnalysis>,
+    layout: Option<LayoutSummary>,
+    installer: Option<InstallerFormat>,
+    analysis: AnalysisPanel,
+    plugins: PluginLoader,
+    tutorial: Tutorial,
+    samples: SampleLoader,
+    settings: SettingsWindow,
+    capture: PanelCapture,
+    assets: Assets,
+    pwa: Pwa,
+    flux_analysis: Option<FluxAnalysis>,
+    flux_job: Option<Incremental<TrackFlux>>,
+    frame_budget: FrameBudget,
+    metadata: ImageMetadata,
+    metadata_open: bool,
+    annotations: Annotations,
+    annotation_error: Option<String>,
+    bookmarks: Bookmarks,
+    checksums: ChecksumVerifier,
+    history: History,
+    fat_browser: FatBrowser,
+    panels: PanelRegistry,
+    selection: SelectionBus,
+    hires: HiresRender,
+    /// A workspace saved before the page was reloaded, waiting for its image to be dropped.
+    pending_restore: Option<PendingRestore>,
+    crashes: CrashDialog,
+    disk_set: Option<DiskSet>,
+    /// The capture details of the current image, if it's an SCP image.
+    scp_info: Option<ScpInfo>,
+    /// What's known about the file being loaded, to explain it if no parser recognizes it.
+    load_probe: Option<FileProbe>,
+    unsupported: UnsupportedDialog,
+    cpm_browser: CpmBrowser,
+    cbm_browser: CbmBrowser,
+    apple_browser: AppleBrowser,
+    hex_viewer: HexViewer,
+    context_watcher: Option<ContextWatcher>,
+    toasts: Toasts,
+    session: Session,
+    /// The SHA-1 of the session entry still waiting for its thumbnail to be rendered.
+    pending_thumbnail: Option<String>,
+    drag_out: Option<DragOut>,
 
     pub(crate) viz_state: VisualizationState,
 }
@@ -74,22 +255,86 @@ pub struct App {
 impl Default for App {
     fn default() -> Self {
 
-        let (load_sender, load_receiver) = mpsc::sync_channel(128);
         Self {
             // Example stuff:
             p_state: PersistentState {
                 label: "Hello World!".to_owned(),
+                viz_settings: VizSettings::default(),
+                export_presets: Vec::new(),
+                watch_mode: WatchMode::default(),
+                export_naming: ExportNaming::default(),
+                format_reports: FormatReports::default(),
+                analysis_checks: CheckSettings::default(),
+                hidden_panels: BTreeSet::new(),
+                ..Default::default()
             },
             run_mode: RunMode::Reactive,
             ctx_init: false,
             dropped_files: Vec::new(),
 
-            load_status: ThreadLoadStatus::Inactive,
-            load_sender: Some(load_sender),
-            load_receiver: Some(load_receiver),
+            tasks: TaskManager::default(),
+            load_failed: false,
+            stream_map: None,
+
+            export_save_target: None,
+            export_sidecar: true,
+            preset_editor: PresetEditor::default(),
+            copy_dialog: CopyDialog::default(),
+            transform_dialog: TransformDialog::default(),
+            undo: UndoStack::default(),
+            journal: EditJournal::default(),
+            sector_clipboard: SectorClipboard::default(),
+            comparison: Comparison::default(),
+            merge: MergeTool::default(),
+            export_error: None,
 
             disk_image_name: None,
+            disk_image_len: 0,
+            disk_image_hash: None,
+            disk_image_digests: None,
             disk_image: None,
+            entropy: None,
+            gap_stats: None,
+            trim: None,
+            layout: None,
+            installer: None,
+            analysis: AnalysisPanel::default(),
+            plugins: PluginLoader::default(),
+            tutorial: Tutorial::default(),
+            samples: SampleLoader::default(),
+            settings: SettingsWindow::default(),
+            capture: PanelCapture::default(),
+            assets: Assets::default(),
+            pwa: Pwa::default(),
+            flux_analysis: None,
+            flux_job: None,
+            frame_budget: FrameBudget::default(),
+            metadata: ImageMetadata::default(),
+            metadata_open: false,
+            annotations: Annotations::default(),
+            annotation_error: None,
+            bookmarks: Bookmarks::default(),
+            checksums: ChecksumVerifier::default(),
+            history: History::default(),
+            fat_browser: FatBrowser::default(),
+            panels: PanelRegistry::default(),
+            selection: SelectionBus::default(),
+            hires: HiresRender::default(),
+            pending_restore: None,
+            crashes: CrashDialog::default(),
+            disk_set: None,
+            scp_info: None,
+            load_probe: None,
+            unsupported: UnsupportedDialog::default(),
+            cpm_browser: CpmBrowser::default(),
+            cbm_browser: CbmBrowser::default(),
+            apple_browser: AppleBrowser::default(),
+            hex_viewer: HexViewer::default(),
+            context_watcher: None,
+            toasts: Toasts::default(),
+            session: Session::default(),
+            pending_thumbnail: None,
+            drag_out: None,
 
             viz_state: VisualizationState::default(),
         }
@@ -107,12 +352,36 @@ impl App {
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
         if let Some(storage) = cc.storage {
-            app_state.p_state = eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+            if let Some(loaded) =
+                persist::load(storage, PERSISTENT_STATE_KEY, eframe::APP_KEY, PERSISTENT_STATE_MIGRATIONS)
+            {
+                app_state.p_state = loaded.state;
+                app_state.state_backups = loaded.backups;
+                if !loaded.warnings.is_empty() {
+                    app_state.toasts.error("Some settings couldn't be restored", loaded.warnings.join("\n"));
+                }
+            }
+            app_state.pending_restore = PendingRestore::load(storage, &mut app_state.state_backups);
+            app_state.journal = EditJournal::load(storage);
         }
 
         app_state.viz_state = VisualizationState::new(cc.egui_ctx.clone(), 512);
+        app_state.viz_state.geometry = app_state.p_state.viz_settings.geometry();
+        app_state.viz_state.renderer = app_state.p_state.viz_settings.renderer;
+        app_state.frame_budget.set_budget_ms(app_state.p_state.performance.frame_budget_ms);
+
+        app_state.drag_out = match DragOut::install() {
+            Ok(drag_out) => Some(drag_out),
+            Err(e) => {
+                log::warn!("Failed to install drag out handler: {:?}", e);
+                None
+            }
+        };
 
         egui_extras::install_image_loaders(&cc.egui_ctx);
+        app_state.pwa.register();
+        app_state.assets.start(&cc.egui_ctx);
+        app_state.tutorial.start(&app_state.p_state.tutorial);
         // Set dark mode. This doesn't seem to work for some reason.
         // So we'll use a flag in state and do it on the first update().
         //cc.egui_ctx.set_visuals(egui::Visuals::dark());
@@ -130,10 +399,33 @@ impl eframe::App for App {
         if !self.ctx_init {
             self.ctx_init(ctx);
         }
+        self.frame_budget.begin_frame();
+        if let Some(drag_out) = &mut self.drag_out {
+            drag_out.begin_frame();
+        }
 
         if matches!(self.run_mode, RunMode::Continuous) {
             ctx.request_repaint();
         }
+        self.poll_plugins();
+        self.poll_samples();
+        for (name, result) in self.capture.take_finished() {
+            match result {
+                Ok(()) => self.toasts.success(format!("Copied the {} to the clipboard", name)),
+                Err(e) => self.toasts.error(format!("Couldn't copy the {}", name), e),
+            }
+        }
+        if self.pwa.became_offline_ready() {
+            self.toasts.success("Ready to use offline");
+        }
+
+        if let Some(watcher) = &self.context_watcher {
+            match watcher.poll() {
+                // Nothing we draw will be visible; the watcher shows a notice instead.
+                ContextState::Lost => return,
+                ContextState::Ok => {}
+            }
+        }
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
@@ -154,7 +446,107 @@ impl eframe::App for App {
                         if ui.button("Upload...").clicked() {
                             println!("TODO: upload image");
                         }
+                        ui.menu_button("Load example", |ui| {
+                            if crate::samples::show_buttons(ui, &self.samples) {
+                                ui.close_menu();
+                            }
+                        });
+                        self.handle_export_menu(ui);
+                        if ui
+                            .add_enabled(self.disk_image.is_some(), egui::Button::new("Copy to new image..."))
+                            .on_hover_text("Copy the sectors of all or some tracks into a freshly formatted standard image.")
+                            .clicked()
+                        {
+                            if let Some(disk) = &self.disk_image {
+                                self.copy_dialog.open(disk);
+                            }
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(self.disk_image.is_some(), egui::Button::new("Transform..."))
+                            .on_hover_text("Swap heads, shift cylinders or reverse the track order.")
+                            .clicked()
+                        {
+                            if let Some(disk) = &self.disk_image {
+                                self.transform_dialog.open(disk);
+                            }
+                            ui.close_menu();
+                        }
+                        let undo_label = match self.undo.next_undo() {
+                            Some(description) => format!("Undo: {}", description),
+                            None => "Undo".to_string(),
+                        };
+                        // Not while an export holds the current image.
+                        if ui
+                            .add_enabled(
+                                self.undo.next_undo().is_some() && self.disk_image.is_some(),
+                                egui::Button::new(undo_label),
+                            )
+                            .clicked()
+                        {
+                            self.undo_image();
+                            ui.close_menu();
+                        }
+                        ui.menu_button("Watch mode", |ui| {
+                            self.p_state.watch_mode.show(ui, &self.p_state.export_presets);
+                        });
+                        ui.menu_button("Panels", |ui| {
+                            self.panels.show_menu(ui, &mut self.p_state.hidden_panels);
+                        });
+                        ui.separator();
+                        if ui
+                            .add_enabled(self.disk_image.is_some(), egui::Button::new("Edit metadata..."))
+                            .clicked()
+                        {
+                            self.metadata_open = true;
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(self.disk_image.is_some(), egui::Button::new("Download report"))
+                            .clicked()
+                        {
+                            self.download_report();
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(self.viz_state.have_render, egui::Button::new("Export viz layers (PNG)"))
+                            .on_hover_text("Download each visualization layer as a separate transparent PNG.")
+                            .clicked()
+                        {
+                            self.download_viz_layers();
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(self.viz_state.have_render, egui::Button::new("Render high resolution..."))
+                            .on_hover_text("Render the visualization at up to 4096 pixels and save it as a PNG.")
+                            .clicked()
+                        {
+                            self.hires.open = true;
+                            ui.close_menu();
+                        }
+                        if ui
+                            .add_enabled(
+                                !self.annotations.items.is_empty(),
+                                egui::Button::new("Export annotations"),
+                            )
+                            .on_hover_text("Drop an annotation file on the window to import it.")
+                            .clicked()
+                        {
+                            self.download_annotations();
+                            ui.close_menu();
+                        }
+                    });
+                    if ui.button("Settings").clicked() {
+                        self.settings.open = !self.settings.open;
+                    }
+                    ui.menu_button("Help", |ui| {
+                        if ui.button("Getting started").clicked() {
+                            self.tutorial.resume(&mut self.p_state.tutorial);
+                            ui.close_menu();
+                        }
                     });
+                    self.pwa.show(ui);
+                    self.assets.show_status(ui);
                 }
             });
         });
@@ -162,10 +554,9 @@ impl eframe::App for App {
         egui::CentralPanel::default().show(ctx, |ui| {
             // The central panel the region left after adding TopPanel's and SidePanel's
 
-            let url = util::construct_full_url("./assets/fluxfox_logo.png");
-            ui.add(
-                egui::Image::new(url).fit_to_original_size(1.0)
-            );
+            if let Some(logo) = self.assets.image("logo") {
+                ui.add(egui::Image::new(logo).fit_to_original_size(1.0));
+            }
 
 
 
@@ -179,22 +570,133 @@ impl eframe::App for App {
 
             // Show dropped files (if any):
             self.handle_dropped_files(ctx, None);
+            self.handle_task_messages(ctx);
+            self.poll_thumbnail();
+            self.handle_pending_restore(ui);
+            self.handle_recovery(ui);
+            self.handle_disk_set(ui);
+            self.tasks.show(ui);
             self.handle_loading_progress(ui);
             self.handle_image_info(ui);
-            self.handle_load_messages(ctx);
+            self.handle_export_status(ui);
 
-            self.viz_state.show(ui);
+            if self.viz_state.show(ui, &mut self.p_state.viz_settings) {
+                self.rerender_visualization();
+            }
+            if let Some(rect) = self.viz_state.take_copy_request() {
+                self.capture.request("visualization", rect, ui.clip_rect());
+            }
+            self.handle_overlay_legend(ui);
+            self.handle_analysis(ui);
+            self.handle_trim(ui);
+            self.handle_flux_job(ctx, ui);
+            if let Some(flux_analysis) = &self.flux_analysis {
+                flux_analysis.show(ui);
+            }
+            self.handle_scp_tracks(ui);
+            self.handle_annotations(ui);
+            self.handle_fat_browser(ui);
+            self.cbm_browser.show(ui);
+            self.apple_browser.show(ui);
+            self.handle_cpm_browser(ui);
+            self.handle_panels(ui);
+            self.handle_comparison(ui);
+            self.handle_merge(ui);
+            self.handle_hex_viewer(ui);
+            self.handle_bookmarks(ctx, ui);
+            self.checksums.show(ui);
+            self.session.show(ui);
+            if self.disk_image.is_some() {
+                self.history.show(ui);
+            }
 
             ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
                 egui::warn_if_debug_build(ui);
             });
         });
+
+        egui::Window::new("Image Metadata")
+            .open(&mut self.metadata_open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                self.metadata.show(ui);
+            });
+
+        self.handle_preset_editor(ctx);
+        self.handle_copy_dialog(ctx);
+        self.handle_transform_dialog(ctx);
+        self.handle_hires_render(ctx);
+        self.crashes.show(ctx);
+        self.plugins.show(ctx);
+        self.unsupported.show(ctx, &mut self.p_state.format_reports);
+        self.handle_settings(ctx);
+        self.toasts.show(ctx);
+        self.handle_drag_out();
+        self.dispatch_selection(ctx);
+        self.handle_tutorial(ctx);
+        self.capture.end_frame(ctx);
     }
 
     /// Called by the framework to save persistent state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        eframe::set_value(storage, eframe::APP_KEY, &self.p_state);
+        self.p_state.version = PERSISTENT_STATE_VERSION;
+        persist::save(storage, PERSISTENT_STATE_KEY, &self.p_state);
+        persist::save_backups(storage, &mut self.state_backups);
+        match self.p_state.autosave.enabled {
+            true => self.journal.save(storage),
+            false => self.journal.forget(storage),
+        }
+        let workspace = self.p_state.autosave.enabled.then(|| self.workspace()).flatten();
+        workspace.unwrap_or_else(Workspace::empty).save(storage);
     }
+
+    fn auto_save_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(autosave::AUTOSAVE_INTERVAL_SECS)
+    }
+}
+
+/// Notify the user of the result of verifying the image against a checksum manifest.
+fn checksum_toast(toasts: &mut Toasts, result: &ChecksumResult) {
+    if result.passed() {
+        toasts.success(result.to_string());
+    }
+    else {
+        toasts.error(format!("{} checksum mismatch", result.kind.label()), result.to_string());
+    }
+}
+
+/// The color of a file's data in the file overlay, `t` of the way from its start to its end.
+fn file_overlay_rgba(t: f32) -> [u8; 4] {
+    let lerp = |a: f32, b: f32| (a + (b - a) * t.clamp(0.0, 1.0)) as u8;
+    [lerp(40.0, 255.0), lerp(120.0, 220.0), lerp(255.0, 40.0), 255]
+}
+
+fn file_overlay_color(t: f32) -> Color {
+    let [r, g, b, a] = file_overlay_rgba(t);
+    Color::from_rgba8(r, g, b, a)
+}
+
+fn file_overlay_color32(t: f32) -> egui::Color32 {
+    let [r, g, b, a] = file_overlay_rgba(t);
+    egui::Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+/// The sectors before and after `key` on its track, in the order they're laid out.
+fn sector_neighbours(disk: &DiskImage, key: SectorKey) -> [Option<SectorKey>; 2] {
+    let sector_map = disk.get_sector_map();
+    let Some(entries) = sector_map.get(key.h as usize).and_then(|cylinders| cylinders.get(key.c as usize))
+    else {
+        return [None; 2];
+    };
+    let keys = entries
+        .iter()
+        .map(|entry| SectorKey::new(key.ch(), entry.chsn.s()))
+        .collect::<Vec<_>>();
+    let Some(i) = keys.iter().position(|other| *other == key)
+    else {
+        return [None; 2];
+    };
+    [i.checked_sub(1).map(|i| keys[i]), keys.get(i + 1).copied()]
 }
 
 impl App {
@@ -207,9 +709,32 @@ impl App {
         self.ctx_init = true;
     }
 
+    pub(crate) fn set_context_watcher(&mut self, watcher: Option<ContextWatcher>) {
+        self.context_watcher = watcher;
+    }
+
+    /// Pick up the new egui context of the runner eframe was restarted with after the graphics
+    /// context was restored. The textures uploaded to the old context went with it: the
+    /// visualization is uploaded again from its retained pixels, and the rest are uploaded
+    /// again the next time they're shown.
+    pub(crate) fn resume(&mut self, cc: &eframe::CreationContext<'_>) {
+        self.ctx_init = false;
+        egui_extras::install_image_loaders(&cc.egui_ctx);
+        self.viz_state.set_context(cc.egui_ctx.clone());
+        self.hires.forget_textures();
+        self.session.forget_textures();
+        self.fat_browser.forget_textures();
+        self.metadata.forget_textures();
+        self.panels.context_restored();
+        cc.egui_ctx.request_repaint();
+    }
+
     // Optional: clear dropped files when done
-    fn clear_dropped_files(&mut self) {
-        self.dropped_files.clear();
+    /// Done with the first of the queued dropped files.
+    fn finish_dropped_file(&mut self) {
+        if !self.dropped_files.is_empty() {
+            self.dropped_files.remove(0);
+        }
     }
 
     fn handle_image_info(&mut self, ui: &mut egui::Ui) {
@@ -218,182 +743,2302 @@ impl App {
                 ui.label(format!("Disk image loaded: {}", self.disk_image_name.clone().unwrap_or("unknown".to_string())));
                 ui.label(format!("Image resolution: {:?}", disk.resolution()));
                 ui.label(format!("Disk geometry: {:?}", disk.geometry()));
+                if let Some(layout) = &self.layout {
+                    ui.label(format!("Sector layout: {}", layout));
+                    // Installer formats are named, with why they aren't damage, by their notice.
+                    match &self.installer {
+                        Some(installer) => installer.show(ui),
+                        None => {
+                            if let Some(name) = layout.format_name() {
+                                ui.label(format!("Format: {}", name));
+                            }
+                        }
+                    }
+                }
+                if let Some(scp) = &self.scp_info {
+                    scp.show_header(ui);
+                }
+                if let Some(hard_sectoring) = &self.viz_state.hard_sectoring {
+                    ui.label(hard_sectoring.summary());
+                }
+                self.show_duplicate_badge(ui);
             });
         }
     }
 
-    fn handle_load_messages(&mut self, ctx: &egui::Context) {
-        // Read messages from the load thread
-        if let Some(receiver) = &mut self.load_receiver {
-
-            // We should keep draining the receiver until it's empty, otherwise messages arriving
-            // faster than once per update() will clog the channel.
-            let mut keep_polling = true;
-            while keep_polling {
-                match receiver.try_recv() {
-                    Ok(status) => {
-                        match status {
-                            ThreadLoadStatus::Loading(progress) => {
-                                log::debug!("Loading progress: {:.1}%", progress * 100.0);
-                                self.load_status = ThreadLoadStatus::Loading(progress);
-                                ctx.request_repaint();
-                            }
-                            ThreadLoadStatus::Success(disk) => {
-                                log::info!("Disk image loaded successfully!");
-                                self.disk_image = Some(disk);
-                                self.load_status = ThreadLoadStatus::Inactive;
-                                ctx.request_repaint();
-                                // Return to reactive mode
-                                self.run_mode = RunMode::Reactive;
-
-                                match self.viz_state.render_visualization(self.disk_image.as_mut(), 0) {
-                                    Ok(_) => {
-                                        log::info!("Visualization rendered successfully!");
-                                    }
-                                    Err(e) => {
-                                        log::error!("Error rendering visualization: {:?}", e);
-                                    }
-                                }
-                            }
-                            ThreadLoadStatus::Error(e) => {
-                                log::error!("Error loading disk image: {:?}", e);
-                                self.load_status = ThreadLoadStatus::Error(e);
-                                ctx.request_repaint();
-                                // Return to reactive mode
-                                self.run_mode = RunMode::Reactive;
-                            }
-                            _ => {}
-                        }
+    fn show_duplicate_badge(&self, ui: &mut egui::Ui) {
+        let (Some(name), Some(digests)) = (&self.disk_image_name, &self.disk_image_digests)
+        else {
+            return;
+        };
+        let Some(entry) = self.session.entry(name, &digests.sha1)
+        else {
+            return;
+        };
+        let duplicates = self.session.duplicates(entry);
+        if !duplicates.same_file.is_empty() {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("⚠ Duplicate file of {}", duplicates.same_file.join(", ")),
+            );
+        }
+        if !duplicates.same_data.is_empty() {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("⚠ Same sector data as {}", duplicates.same_data.join(", ")),
+            );
+        }
+    }
 
-                    }
-                    _ => {
-                        keep_polling = false;
-                    }
+    fn handle_overlay_legend(&mut self, ui: &mut egui::Ui) {
+        if self.viz_state.overlay_mode == VizOverlayMode::FileClusters {
+            if let Some(node) = self.fat_browser.selected_node() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", node.path));
+                    ui.colored_label(file_overlay_color32(0.0), "⏺");
+                    ui.label("start");
+                    ui.colored_label(file_overlay_color32(1.0), "⏺");
+                    ui.label("end");
+                });
+            }
+            return;
+        }
+        if self.viz_state.overlay_mode != VizOverlayMode::Entropy {
+            return;
+        }
+        if let Some(entropy) = &self.entropy {
+            let counts = entropy.class_counts();
+            ui.horizontal_wrapped(|ui| {
+                for class in EntropyClass::ALL {
+                    let [r, g, b, a] = class.rgba();
+                    ui.colored_label(egui::Color32::from_rgba_unmultiplied(r, g, b, a), "⏺");
+                    ui.label(format!("{}: {}", class.label(), counts.get(&class).unwrap_or(&0)));
                 }
+            });
+        }
+    }
+
+    fn handle_annotations(&mut self, ui: &mut egui::Ui) {
+        if self.disk_image.is_none() {
+            return;
+        }
+
+        let selected = self.viz_state.selection.as_ref().map(|hit| match &hit.sector {
+            Some(span) => AnnotationTarget::Sector(span.key),
+            None => AnnotationTarget::track(hit.ch),
+        });
+
+        if let Some(error) = &self.annotation_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        match self.annotations.show(ui, selected) {
+            Some(AnnotationEvent::Select(AnnotationTarget::Sector(key))) => {
+                self.selection.publish(SelectionSource::Other, Selection::Sector(key));
+            }
+            Some(AnnotationEvent::Select(AnnotationTarget::Track { c, h })) => {
+                self.selection.publish(SelectionSource::Other, Selection::Track(DiskCh::new(c, h)));
             }
+            Some(AnnotationEvent::Changed) => {
+                self.update_annotation_overlay();
+            }
+            None => {}
         }
     }
 
-    fn handle_loading_progress(&mut self, ui: &mut egui::Ui) {
-        if let ThreadLoadStatus::Loading(progress) = &self.load_status {
-            ui.add(
-                egui::ProgressBar::new(*progress as f32)
-                    .text(format!("{:.1}%", *progress * 100.0)),
-            );
+    fn handle_fat_browser(&mut self, ui: &mut egui::Ui) {
+        if self.disk_image.is_none() {
+            return;
+        }
+
+        match self.fat_browser.show(ui) {
+            Some(BrowserEvent::SelectSector(key)) => {
+                self.selection.publish(SelectionSource::FatBrowser, Selection::Sector(key));
+            }
+            Some(BrowserEvent::SelectFile(path)) => {
+                self.selection.publish(SelectionSource::FatBrowser, Selection::File(path));
+            }
+            Some(BrowserEvent::WriteSectors { description, sectors }) => {
+                self.write_sectors(description, &sectors);
+            }
+            None => {}
         }
     }
 
-    fn handle_dropped_files(&mut self, ctx: &egui::Context, ui: Option<&mut egui::Ui>) {
-        if let Some(ui) = ui {
-            ui.group(|ui| {
-                ui.label("Dropped files:");
+    fn handle_cpm_browser(&mut self, ui: &mut egui::Ui) {
+        if self.fat_browser.volume.is_some() || self.cbm_browser.is_mounted() || self.apple_browser.is_mounted() {
+            return;
+        }
+        if let Some(disk) = &mut self.disk_image {
+            self.cpm_browser.show(ui, disk);
+        }
+    }
 
-                if let Some(file) = self.dropped_files.get(0) {
-                    let mut info = if let Some(path) = &file.path {
-                        path.display().to_string()
-                    } else if !file.name.is_empty() {
-                        file.name.clone()
-                    } else {
-                        "???".to_owned()
-                    };
+    fn handle_analysis(&mut self, ui: &mut egui::Ui) {
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+        let volume = self.fat_browser.volume.clone();
+        let mut ctx = CheckContext {
+            disk,
+            fat: volume.as_deref().map(|volume| (volume, self.fat_browser.tree.as_slice())),
+        };
+        if let Some(rect) = self.analysis.show(ui, &mut self.p_state.analysis_checks, &mut ctx) {
+            self.capture.request("analysis results", rect, ui.clip_rect());
+        }
+    }
 
-                    let mut additional_info = vec![];
-                    if !file.mime.is_empty() {
-                        additional_info.push(format!("type: {}", file.mime));
-                    }
-                    if let Some(bytes) = &file.bytes {
-                        additional_info.push(format!("{} bytes", bytes.len()));
-                    } else {
-                        additional_info.push("loading...".to_string());
+    fn handle_settings(&mut self, ctx: &egui::Context) {
+        if !self.settings.open {
+            return;
+        }
+        let mut viz_changed = false;
+        let mut budget_changed = false;
+        let fields = self.name_fields();
+        let formats = self.disk_image.as_ref().map(export::export_formats).unwrap_or_default();
+        let p_state = &mut self.p_state;
+        let (analysis, panels) = (&self.analysis, &self.panels);
+        let reset = self.settings.show(ctx, |ui, section| match section {
+            Section::VizLayout => viz_changed |= p_state.viz_settings.show(ui),
+            Section::ExportNaming => p_state.export_naming.show(ui, formats.first().zip(fields.as_ref())),
+            Section::ExportPresets => {
+                if p_state.export_presets.is_empty() {
+                    ui.weak("No presets yet. Save one from Image > Export with preset.");
+                }
+                let mut delete = None;
+                for (i, preset) in p_state.export_presets.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(&preset.name);
+                        ui.weak(format!("{}, {}", preset.format, preset.naming));
+                        if ui.small_button("🗑").on_hover_text("Delete preset").clicked() {
+                            delete = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = delete {
+                    p_state.export_presets.remove(i);
+                }
+            }
+            Section::WatchMode => p_state.watch_mode.show(ui, &p_state.export_presets),
+            Section::AnalysisChecks => {
+                ui.horizontal(|ui| {
+                    ui.label("Profile:");
+                    for profile in [Profile::Quick, Profile::Deep] {
+                        ui.radio_value(&mut p_state.analysis_checks.profile, profile, profile.label());
                     }
+                });
+                analysis.show_check_list(ui, &mut p_state.analysis_checks);
+            }
+            Section::Panels => panels.show_menu(ui, &mut p_state.hidden_panels),
+            Section::Performance => budget_changed |= p_state.performance.show(ui),
+            Section::Autosave => p_state.autosave.show(ui),
+            Section::FormatReports => p_state.format_reports.show(ui),
+        });
 
-                    if !additional_info.is_empty() {
-                        info += &format!(" ({})", additional_info.join(", "));
-                    }
+        for section in reset {
+            match section {
+                Section::VizLayout => {
+                    viz_changed |= p_state.viz_settings != VizSettings::default();
+                    p_state.viz_settings = VizSettings::default();
+                }
+                Section::ExportNaming => p_state.export_naming = ExportNaming::default(),
+                Section::ExportPresets => {}
+                Section::WatchMode => p_state.watch_mode = WatchMode::default(),
+                Section::AnalysisChecks => p_state.analysis_checks = CheckSettings::default(),
+                Section::Panels => p_state.hidden_panels.clear(),
+                Section::Performance => {
+                    p_state.performance = PerformanceSettings::default();
+                    budget_changed = true;
+                }
+                Section::Autosave => p_state.autosave = AutosaveSettings::default(),
+                Section::FormatReports => p_state.format_reports = FormatReports::default(),
+            }
+        }
 
-                    ui.label(info);
-                } else {
-                    ui.label("No file currently dropped.");
+        if budget_changed {
+            self.frame_budget.set_budget_ms(self.p_state.performance.frame_budget_ms);
+        }
+        if viz_changed {
+            self.viz_state.apply_settings(&self.p_state.viz_settings);
+            self.rerender_visualization();
+        }
+    }
+
+    fn handle_tutorial(&mut self, ctx: &egui::Context) {
+        let state = TutorialState {
+            image_loaded: self.disk_image.is_some() || self.tasks.is_active(TaskKind::Load),
+            selected: self.viz_state.selection.is_some(),
+            ..Default::default()
+        };
+        self.tutorial.show(ctx, &mut self.p_state.tutorial, state, &self.samples);
+    }
+
+    /// Queue fetched samples to be loaded as if they'd been dropped.
+    fn poll_samples(&mut self) {
+        for (name, result) in self.samples.take_loaded() {
+            match result {
+                Ok(file) => {
+                    log::info!("Fetched sample {}", name);
+                    self.dropped_files.push(file);
                 }
-            });
+                Err(e) => {
+                    log::error!("Error fetching sample {}: {:?}", name, e);
+                    self.toasts.error(format!("Couldn't fetch the {} sample", name), format!("{:#}", e));
+                }
+            }
         }
+    }
 
-        // Check for new dropped files or file completion status
-        ctx.input(|i| {
-            if !i.raw.dropped_files.is_empty() {
-                let new_dropped_file = &i.raw.dropped_files[0]; // Only take the first file
+    /// Add plugins which have finished loading to the analysis checks.
+    fn poll_plugins(&mut self) {
+        for (name, result) in self.plugins.take_loaded() {
+            match result {
+                Ok(plugin) => {
+                    log::info!("Loaded analysis plugin {}", name);
+                    self.toasts.success(format!("Loaded plugin {}", plugin.name()));
+                    self.analysis.add_check(Box::new(plugin));
+                }
+                Err(e) => {
+                    log::error!("Error loading plugin {}: {:?}", name, e);
+                    self.toasts.error(format!("Couldn't load plugin {}", name), format!("{:#}", e));
+                }
+            }
+        }
+    }
 
-                // Only process a new file if there's no file already in `self.dropped_files`
-                if self.dropped_files.is_empty() {
-                    // Add the new file to `self.dropped_files` to track it
-                    self.dropped_files = vec![new_dropped_file.clone()];
+    fn handle_trim(&mut self, ui: &mut egui::Ui) {
+        let Some(trim) = &mut self.trim
+        else {
+            return;
+        };
+        if trim.show(ui) {
+            self.trim_image();
+        }
+    }
+
+    fn trim_image(&mut self) {
+        let Some(cylinders) = self.trim.as_ref().map(|trim| trim.geometry.cylinders)
+        else {
+            return;
+        };
+        self.apply_transform(Transform::Trim(cylinders));
+    }
+
+    fn handle_comparison(&mut self, ui: &mut egui::Ui) {
+        let Some(disk) = &self.disk_image
+        else {
+            return;
+        };
+        let selected = self.viz_state.selection.as_ref().map(|hit| hit.ch);
+        self.comparison.show(ui, disk, selected);
+    }
+
+    fn handle_merge(&mut self, ui: &mut egui::Ui) {
+        let (Some(_), Some(name)) = (&self.disk_image, &self.disk_image_name)
+        else {
+            return;
+        };
+        match self.merge.show(ui, name) {
+            Some(MergeAction::Merge) => self.merge_dumps(),
+            Some(MergeAction::DownloadReport) => {
+                let Some(report) = &self.merge.report
+                else {
+                    return;
+                };
+                let filename = format!("{}.merge.txt", report.sources[0]);
+                if let Err(e) = storage::download_bytes(report.to_text().as_bytes(), &filename) {
+                    log::error!("Error downloading merge report: {:?}", e);
                 }
             }
-        });
+            None => {}
+        }
+    }
 
-        // Wait for bytes to be available, then process
-        if let Some(file) = self.dropped_files.get(0) {
-            if let Some(bytes) = &file.bytes {
+    /// Write good copies of the loaded image's bad sectors from the merge tool's other dumps.
+    fn merge_dumps(&mut self) {
+        let name = self.disk_image_name.clone().unwrap_or("disk image".to_string());
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+        let (report, patches) = merge::merge(disk, &name, &mut self.merge.dumps);
+        log::info!("Merge {} dumps: {}", report.sources.len(), report.summary());
+        if patches.is_empty() {
+            let message = format!("Nothing to merge: {}", report.summary());
+            self.history.record(message.clone());
+            self.toasts.push(Toasts::toast(ToastLevel::Info, message));
+        }
+        else {
+            // Records the merge in the history along with the write.
+            self.write_sectors(format!("Merged {}", report.summary()), &patches);
+        }
+        self.merge.report = Some(report);
+    }
 
-                // Only process if bytes are now available
-                log::info!("Processing file: {} ({} bytes)", file.name, bytes.len());
+    /// Decode a re-dumped stream file of the loaded Kryoflux set in a worker, for merging.
+    fn spawn_redecode(&mut self, name: String, ch: DiskCh, stream: Vec<u8>) {
+        log::info!("Re-decoding {} for track {}", name, ch);
+        self.tasks.submit(name.clone(), TaskKind::Redecode, true, move |handle| {
+            worker::spawn_closure_worker(move || {
+                let check = kryoflux::check_stream(&stream);
+                let result = kryoflux::single_stream_set(&name, &stream).and_then(|set| {
+                    DiskImage::load(&mut std::io::Cursor::new(set), None, None, None)
+                        .map_err(|e| anyhow::anyhow!("{:?}", e))
+                });
+                handle.finish(TaskOutput::Redecoded { name, ch, check, result });
+            })
+        });
+    }
 
-                let bytes = bytes.clone();
-                let bytes_vec = bytes.to_vec();
-                let mut cursor = std::io::Cursor::new(bytes_vec);
+    /// Merge the good sectors of a re-dumped track into the bad ones of the loaded image.
+    fn handle_redecoded(
+        &mut self,
+        name: String,
+        ch: DiskCh,
+        check: Result<usize, String>,
+        result: Result<DiskImage, anyhow::Error>,
+    ) {
+        let redump = match result {
+            Ok(redump) => redump,
+            Err(e) => {
+                log::error!("Error decoding re-dumped stream {}: {}", name, e);
+                self.toasts.error(format!("Couldn't decode {}", name), e.to_string());
+                return;
+            }
+        };
+        if let Err(problem) = &check {
+            self.toasts.warning(format!("{} may be a bad capture too", name), problem.clone());
+        }
+        let primary_name = self.disk_image_name.clone().unwrap_or("disk image".to_string());
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+        let (_, patches) = merge::merge(disk, &primary_name, &mut [(name.clone(), redump)]);
+        // Only the track the stream file is named for is taken, whatever else it decoded as.
+        let patches: Vec<(SectorKey, Vec<u8>)> =
+            patches.into_iter().filter(|(key, _)| key.c == ch.c() && key.h == ch.h()).collect();
+        self.history.record(format!("Re-decoded {}: {} bad sectors recovered", name, patches.len()));
+        if patches.is_empty() {
+            self.toasts.push(Toasts::toast(ToastLevel::Info, format!("{} recovered no bad sectors", name)));
+            return;
+        }
+        let recovered = patches.len();
+        self.write_sectors(format!("Merge re-dumped {}", name), &patches);
+        if let Some(stream_map) = &mut self.stream_map {
+            stream_map.set_replaced(ch, format!("replaced by a re-dump, {} sectors recovered", recovered));
+        }
+    }
 
-                let sender1 = self.load_sender.as_mut().unwrap().clone();
-                let sender2 = self.load_sender.as_mut().unwrap().clone();
+    fn handle_scp_tracks(&mut self, ui: &mut egui::Ui) {
+        let Some(scp) = &self.scp_info
+        else {
+            return;
+        };
+        let selected = self.viz_state.selection.as_ref().map(|hit| hit.ch);
+        if let Some(ch) = scp.show(ui, selected) {
+            self.selection.publish(SelectionSource::Other, Selection::Track(ch));
+        }
+    }
 
-                // Remove the old disk image
-                self.disk_image = None;
-                // Set the name of the new disk image
-                self.disk_image_name = Some(file.name.clone());
+    fn handle_panels(&mut self, ui: &mut egui::Ui) {
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+        let volume = self.fat_browser.volume.clone();
+        let mut ctx = PanelContext {
+            disk,
+            fat: volume.as_deref().map(|volume| (volume, self.fat_browser.tree.as_slice())),
+            gap_stats: self.gap_stats.as_ref(),
+        };
+        let events = self.panels.show(ui, &mut ctx, &self.p_state.hidden_panels);
+        for event in events {
+            self.handle_panel_event(event);
+        }
+    }
 
-                log::debug!("Spawning thread to load disk image");
-                match worker::spawn_closure_worker(move || {
-                    log::debug!("Hello from worker thread!");
+    fn handle_panel_event(&mut self, event: PanelEvent) {
+        match event {
+            PanelEvent::SelectTrack(ch) => {
+                self.selection.publish(SelectionSource::Panel, Selection::Track(ch));
+            }
+            PanelEvent::SelectSector(key) => {
+                self.selection.publish(SelectionSource::Panel, Selection::Sector(key));
+            }
+            PanelEvent::SelectFile(path) => {
+                self.selection.publish(SelectionSource::Panel, Selection::File(path));
+            }
+            PanelEvent::WriteSectors { description, sectors } => {
+                self.write_sectors(description, &sectors);
+            }
+            PanelEvent::Transform(transform) => self.apply_transform(transform),
+        }
+    }
 
-                    // callback is of type Arc<dyn Fn(LoadingStatus) + Send + Sync>
-                    let callback = Arc::new(move |status: LoadingStatus| {
-                        match status {
-                            LoadingStatus::Progress(progress) => {
-                                log::debug!("Sending Loading progress: {:.1}%", progress * 100.0);
-                                sender2.send(ThreadLoadStatus::Loading(progress)).unwrap();
-                            }
-                            _ => {}
-                        }
-                    });
+    /// Offer the file under the pointer in the FAT browser for dragging out of the page.
+    fn handle_drag_out(&mut self) {
+        let Some(drag_out) = &mut self.drag_out
+        else {
+            return;
+        };
+        if let (Some(volume), Some(node)) = (&self.fat_browser.volume, self.fat_browser.hovered_node()) {
+            // The same path may name a different file in the next image loaded.
+            let key = format!("{}/{}", self.disk_image_hash.as_deref().unwrap_or(""), node.path);
+            drag_out.arm(&key, &node.entry.name, || volume.read_file(&node.entry));
+        }
+        drag_out.end_frame();
+    }
 
-                    DiskImage::load(&mut cursor, None, None, Some(callback)).map(|disk| {
-                        log::debug!("Disk image loaded successfully!");
-                        sender1.send(ThreadLoadStatus::Success(disk)).unwrap();
-                    }).unwrap_or_else(|e| {
-                        log::error!("Error loading disk image: {:?}", e);
-                        sender1.send(ThreadLoadStatus::Error(e)).unwrap();
-                    });
-                }) {
-                    Ok(_) => {
-                        log::debug!("Worker thread spawned successfully");
-                        // Enter continuous mode.
-                        self.run_mode = RunMode::Continuous;
-                        ctx.request_repaint();
-                    }
-                    Err(e) => {
-                        log::error!("Error spawning worker thread: {:?}", e);
+    /// Highlight the sectors of the file selected in the FAT browser, shaded from the start of
+    /// the file to its end so fragmentation is visible.
+    fn update_file_overlay(&mut self) {
+        let sectors = match (&self.fat_browser.volume, self.fat_browser.selected_node()) {
+            (Some(volume), Some(node)) => volume.entry_sectors(&node.entry),
+            _ => Vec::new(),
+        };
+        let order: HashMap<SectorKey, f32> = sectors
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (*key, i as f32 / sectors.len().max(2).saturating_sub(1) as f32))
+            .collect();
+
+        if !sectors.is_empty() {
+            self.viz_state.overlay_mode = VizOverlayMode::FileClusters;
+        }
+        self.viz_state.render_sector_overlay(self.viz_state.side, VizOverlayMode::FileClusters, |key| {
+            order.get(&key).map(|t| file_overlay_color(*t))
+        });
+    }
+
+    /// Show the selected sector in the hex viewer, reading it when the selection changes.
+    fn handle_hex_viewer(&mut self, ui: &mut egui::Ui) {
+        let selected = self.viz_state.selection.as_ref().and_then(|hit| hit.sector.as_ref());
+        match selected {
+            Some(span) if self.hex_viewer.key != Some(span.key) => {
+                let (key, chsn) = (span.key, span.chsn);
+                let Some(disk) = &mut self.disk_image
+                else {
+                    return;
+                };
+                match analysis::read_sector(disk, key.ch(), chsn) {
+                    Some(read) => {
+                        let suggested = self.fat_browser.volume.as_ref().and_then(|volume| {
+                            let lba = volume.volume.lba(key)?;
+                            let template = templates::template_for_role(volume, volume.sector_role(lba))?;
+                            Some((template, templates::template_offset(volume, lba)))
+                        });
+                        let neighbours = sector_neighbours(disk, key);
+                        self.hex_viewer.set_sector(key, read.data, suggested, neighbours);
                     }
+                    None => self.hex_viewer.clear(),
                 }
+            }
+            None if self.hex_viewer.key.is_some() => self.hex_viewer.clear(),
+            _ => {}
+        }
+        if let Some(key) = self.hex_viewer.show(ui) {
+            self.selection.publish(SelectionSource::HexViewer, Selection::Sector(key));
+        }
+        if let Some(key) = self.hex_viewer.key {
+            let comparison = self.comparison.disk.as_ref().and(self.comparison.name.as_deref());
+            if let Some(action) = self.sector_clipboard.show(ui, comparison) {
+                self.handle_clip_action(key, action);
+            }
+        }
+    }
 
-                // Clear the dropped file after processing
-                self.clear_dropped_files();
-            } else {
-                // Request a repaint until the file's bytes are loaded
-                ctx.request_repaint();
+    fn handle_clip_action(&mut self, key: SectorKey, action: ClipAction) {
+        let name = self.disk_image_name.clone().unwrap_or("disk image".to_string());
+        let copied = match action {
+            ClipAction::Copy(count) => self.disk_image.as_mut().map(|disk| SectorClip::copy(disk, &name, key, count)),
+            ClipAction::CopyFromComparison(count) => {
+                let name = self.comparison.name.clone().unwrap_or("comparison image".to_string());
+                self.comparison.disk.as_mut().map(|disk| SectorClip::copy(disk, &name, key, count))
+            }
+            ClipAction::Paste => {
+                let (Some(clip), Some(disk)) = (&self.sector_clipboard.clip, &self.disk_image)
+                else {
+                    return;
+                };
+                let description = format!("Paste {} at {}", clip.describe(), key);
+                match clip.paste(disk, key) {
+                    Ok(writes) => self.write_sectors(description, &writes),
+                    Err(e) => self.toasts.error("Couldn't paste sectors", e.to_string()),
+                }
+                return;
             }
+        };
+        match copied {
+            Some(Ok(clip)) => {
+                log::info!("Copied {}", clip.describe());
+                self.toasts.success(format!("Copied {}", clip.describe()));
+                self.sector_clipboard.clip = Some(clip);
+            }
+            Some(Err(e)) => self.toasts.error("Couldn't copy sectors", e.to_string()),
+            None => {}
+        }
+    }
+
+    /// Deliver the selections made this frame to everything that follows the selection: the
+    /// visualization, which the hex viewer shows the selected sector of, the FAT browser and
+    /// the registered panels.
+    fn dispatch_selection(&mut self, ctx: &egui::Context) {
+        if let Some(hit) = self.viz_state.take_clicked() {
+            let selection = match &hit.sector {
+                Some(span) => Selection::Sector(span.key),
+                None => Selection::Position {
+                    ch: hit.ch,
+                    bit_offset: hit.bit_offset,
+                },
+            };
+            self.selection.publish(SelectionSource::Viz, selection);
         }
 
+        let events = self.selection.drain();
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            if event.is_for(SelectionSource::Viz) {
+                match &event.selection {
+                    Selection::Track(ch) => self.viz_state.select_track(*ch),
+                    Selection::Position { ch, bit_offset } => self.viz_state.select_position(*ch, *bit_offset),
+                    Selection::Sector(key) => self.viz_state.select_sector(*key),
+                    Selection::File(_) => {}
+                }
+                if event.focus() {
+                    self.viz_state.focus_selection();
+                }
+            }
+
+            match &event.selection {
+                Selection::File(path) => {
+                    if event.is_for(SelectionSource::FatBrowser) {
+                        self.fat_browser.select_file(path.clone());
+                    }
+                    self.update_file_overlay();
+                }
+                Selection::Sector(key) if event.is_for(SelectionSource::FatBrowser) => {
+                    // Don't switch overlays for a file the user didn't pick directly.
+                    let following_files = self.viz_state.overlay_mode == VizOverlayMode::FileClusters;
+                    if self.fat_browser.select_sector(*key) && following_files {
+                        self.update_file_overlay();
+                    }
+                }
+                _ => {}
+            }
+
+            self.panels.selection_changed(&event.selection);
+        }
+        ctx.request_repaint();
     }
-}
 
+    fn handle_bookmarks(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        if self.disk_image.is_none() {
+            return;
+        }
 
+        let shortcut_action = self.bookmarks.handle_shortcuts(ctx);
+        let panel_action = self.bookmarks.show(ui);
 
+        match shortcut_action.or(panel_action) {
+            Some(BookmarkAction::Toggle) => {
+                if let Some(hit) = &self.viz_state.selection {
+                    self.bookmarks.toggle(Bookmark {
+                        ch: hit.ch,
+                        bit_offset: hit.bit_offset,
+                        sector: hit.sector.as_ref().map(|span| span.key),
+                    });
+                }
+            }
+            Some(BookmarkAction::Jump(i)) => {
+                if let Some(bookmark) = self.bookmarks.items.get(i).cloned() {
+                    let selection = match bookmark.sector {
+                        Some(key) => Selection::Sector(key),
+                        None => Selection::Position {
+                            ch: bookmark.ch,
+                            bit_offset: bookmark.bit_offset,
+                        },
+                    };
+                    self.selection.publish(SelectionSource::Other, selection);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Render annotation markers as a visualization overlay.
+    fn update_annotation_overlay(&mut self) {
+        let annotations = &self.annotations;
+        self.viz_state.render_overlay(
+            self.viz_state.side,
+            VizOverlayMode::Annotations,
+            |ch| annotations.tag_for(&AnnotationTarget::track(ch)).map(|tag| tag.color()),
+            |span| annotations.tag_for(&AnnotationTarget::Sector(span.key)).map(|tag| tag.color()),
+        );
+    }
+
+    /// Advance the flux analysis by as many tracks as fit in this frame's budget.
+    fn handle_flux_job(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let Some(job) = &mut self.flux_job
+        else {
+            return;
+        };
+
+        if job.run(&self.frame_budget) {
+            if let Some(job) = self.flux_job.take() {
+                let analysis = FluxAnalysis::from_tracks(job.into_results());
+                if analysis.dropout_count() > 0 {
+                    self.toasts.warning(
+                        format!("{} dropouts found", analysis.dropout_count()),
+                        "Dropouts suggest damaged media or a dirty head. See the flux analysis panel.",
+                    );
+                }
+                self.flux_analysis = Some(analysis);
+                self.update_flux_overlays();
+            }
+        }
+        else {
+            ui.horizontal(|ui| {
+                ui.label("Analyzing flux...");
+                ui.add(egui::ProgressBar::new(job.progress()).desired_width(200.0).show_percentage());
+            });
+            ctx.request_repaint();
+        }
+    }
+
+    fn update_flux_overlays(&mut self) {
+        let side = self.viz_state.side;
+        let (splices, dropouts) = match &self.flux_analysis {
+            Some(analysis) => (analysis.splice_markers(side as u8), analysis.dropout_markers(side as u8)),
+            None => (Vec::new(), Vec::new()),
+        };
+        self.viz_state.render_marker_overlay(
+            side,
+            VizOverlayMode::Splices,
+            &splices,
+            Color::from_rgba8(255, 255, 0, 255),
+        );
+        self.viz_state.render_marker_overlay(
+            side,
+            VizOverlayMode::Dropouts,
+            &dropouts,
+            Color::from_rgba8(255, 40, 40, 255),
+        );
+        // The holes are at the same angles on every track, with the index hole at the start.
+        let holes: Vec<(DiskCh, f32)> = match &self.viz_state.hard_sectoring {
+            Some(hard_sectoring) => self.viz_state.layout[side]
+                .iter()
+                .flat_map(|track| {
+                    std::iter::once(0.0).chain(hard_sectoring.holes.iter().copied()).map(|hole| (track.ch, hole))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        self.viz_state.render_marker_overlay(
+            side,
+            VizOverlayMode::SectorHoles,
+            &holes,
+            Color::from_rgba8(80, 200, 255, 255),
+        );
+    }
+
+    /// Calculate per-sector entropy for the loaded image and render it as a visualization overlay.
+    fn update_entropy_overlay(&mut self) {
+        if let Some(disk) = &mut self.disk_image {
+            self.entropy = Some(EntropyMap::from_disk(disk));
+        }
+        self.render_entropy_overlay();
+    }
+
+    fn render_entropy_overlay(&mut self) {
+        let Some(entropy) = &self.entropy
+        else {
+            return;
+        };
+        self.viz_state.render_sector_overlay(self.viz_state.side, VizOverlayMode::Entropy, |key| {
+            entropy.class(&key).map(|class| {
+                let [r, g, b, a] = class.rgba();
+                Color::from_rgba8(r, g, b, a)
+            })
+        });
+    }
+
+    /// Render the visualization and all of its overlays again, after the layout settings changed.
+    fn rerender_visualization(&mut self) {
+        if self.disk_image.is_none() {
+            return;
+        }
+        if let Err(e) = self.viz_state.render_visualization(self.disk_image.as_mut(), self.viz_state.side) {
+            log::error!("Error rendering visualization: {:?}", e);
+            return;
+        }
+        // Updating the file overlay switches to it; keep whichever overlay was being viewed.
+        let overlay_mode = self.viz_state.overlay_mode;
+        self.render_entropy_overlay();
+        self.update_annotation_overlay();
+        self.update_file_overlay();
+        self.update_flux_overlays();
+        self.viz_state.overlay_mode = overlay_mode;
+    }
+
+    fn image_report(&self) -> Option<ImageReport> {
+        let disk = self.disk_image.as_ref()?;
+        let name = self.disk_image_name.clone().unwrap_or("unknown".to_string());
+        Some(
+            ImageReport::new(&name, self.disk_image_len, disk)
+                .with_entropy(self.entropy.as_ref())
+                .with_annotations(&self.annotations)
+                .with_metadata(&self.metadata)
+                .with_history(&self.history)
+                .with_checks(&self.analysis.results),
+        )
+    }
+
+    fn download_report(&mut self) {
+        let Some(report) = self.image_report()
+        else {
+            return;
+        };
+
+        match report.to_json() {
+            Ok(json) => {
+                if let Err(e) = storage::download_bytes(json.as_bytes(), &format!("{}.report.json", report.name)) {
+                    log::error!("Error downloading report: {:?}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("Error serializing report: {:?}", e);
+            }
+        }
+    }
+
+    fn download_viz_layers(&mut self) {
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+        let name = self.disk_image_name.clone().unwrap_or("image".to_string());
+        let stem = name.rsplit_once('.').map_or(name.as_str(), |(stem, _)| stem);
+
+        match self.viz_state.export_layers(disk) {
+            Ok(layers) => {
+                for (layer, png) in layers {
+                    let filename = format!("{}_side{}_{}.png", stem, self.viz_state.side, layer);
+                    if let Err(e) = storage::download_bytes(&png, &filename) {
+                        log::error!("Error downloading {}: {:?}", filename, e);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Error exporting visualization layers: {:?}", e);
+            }
+        }
+    }
+
+    fn download_annotations(&mut self) {
+        let (Some(name), Some(hash)) = (&self.disk_image_name, &self.disk_image_hash)
+        else {
+            return;
+        };
+        match self.annotations.to_file(hash, name) {
+            Ok(json) => {
+                if let Err(e) = storage::download_bytes(json.as_bytes(), &AnnotationFile::filename(name)) {
+                    log::error!("Error downloading annotations: {:?}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("Error serializing annotations: {:?}", e);
+            }
+        }
+    }
+
+    fn import_annotations(&mut self, name: &str, bytes: &[u8]) {
+        let Some(hash) = &self.disk_image_hash
+        else {
+            return;
+        };
+        match self.annotations.import(bytes, hash) {
+            Ok(added) => {
+                log::info!("Imported {} annotations from {}", added, name);
+                self.history.record(format!("Imported {} annotations from {}", added, name));
+                self.annotation_error = None;
+                self.update_annotation_overlay();
+            }
+            Err(e) => {
+                log::error!("Error importing annotations from {}: {}", name, e);
+                self.annotation_error = Some(format!("Couldn't import {}: {}", name, e));
+            }
+        }
+    }
+
+    /// Save the metadata and history sidecar next to an exported image.
+    fn download_sidecar(&self, image_filename: &str) {
+        if self.metadata.is_empty() && self.history.is_empty() {
+            return;
+        }
+        let sidecar = ImageSidecar {
+            metadata: &self.metadata,
+            history: &self.history,
+        };
+        match sidecar.to_json() {
+            Ok(json) => {
+                if let Err(e) = storage::download_bytes(json.as_bytes(), &format!("{}.json", image_filename)) {
+                    log::error!("Error downloading metadata sidecar: {:?}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("Error serializing metadata sidecar: {:?}", e);
+            }
+        }
+    }
+
+    fn handle_export_menu(&mut self, ui: &mut egui::Ui) {
+        let formats = match &self.disk_image {
+            Some(disk) if !self.tasks.is_active(TaskKind::Convert) => export::export_formats(disk),
+            _ => Vec::new(),
+        };
+
+        ui.add_enabled_ui(!formats.is_empty(), |ui| {
+            ui.menu_button("Export as", |ui| {
+                // Offer the trim before the image is exported with its trailing cylinders.
+                if let Some(trim) = self.trim.as_ref().filter(|trim| !trim.declined) {
+                    ui.colored_label(ui.visuals().warn_fg_color, trim.summary());
+                    let label = format!("Trim to {} first", trim.geometry.name);
+                    if ui.button(label).clicked() {
+                        self.trim_image();
+                    }
+                    ui.separator();
+                }
+                let fields = self.name_fields();
+                let example = formats.first().zip(fields.as_ref());
+                self.p_state.export_naming.show(ui, example);
+                ui.separator();
+                for format in formats.iter() {
+                    if ui.button(format.label()).clicked() {
+                        self.start_export(ui.ctx(), format.clone());
+                        ui.close_menu();
+                    }
+                }
+            });
+
+            ui.menu_button("Export for emulator", |ui| {
+                self.handle_emulator_menu(ui, &formats);
+            });
+
+            ui.menu_button("Export with preset", |ui| {
+                self.handle_preset_menu(ui, &formats);
+            });
+        });
+    }
+
+    fn handle_preset_menu(&mut self, ui: &mut egui::Ui, formats: &[ExportFormat]) {
+        let fields = self.name_fields().unwrap_or_default();
+        let presets: Vec<_> = self
+            .p_state
+            .export_presets
+            .iter()
+            .map(|preset| {
+                let resolved = preset
+                    .resolve(formats)
+                    .map(|format| (preset.filename(&format, &fields), format));
+                (preset.name.clone(), preset.sidecar, resolved)
+            })
+            .collect();
+
+        let mut delete = None;
+        for (i, (name, sidecar, resolved)) in presets.into_iter().enumerate() {
+            ui.horizontal(|ui| {
+                match resolved {
+                    Ok((filename, format)) => {
+                        if ui.button(&name).on_hover_text(&filename).clicked() {
+                            let target = storage::SaveTarget::request(&filename);
+                            self.start_export_as(ui.ctx(), format, filename, sidecar, target);
+                            ui.close_menu();
+                        }
+                    }
+                    Err(reason) => {
+                        ui.add_enabled(false, egui::Button::new(&name))
+                            .on_disabled_hover_text(reason);
+                    }
+                }
+                if ui.small_button("🗑").on_hover_text("Delete preset").clicked() {
+                    delete = Some(i);
+                }
+            });
+        }
+        if let Some(i) = delete {
+            self.p_state.export_presets.remove(i);
+        }
+
+        if !self.p_state.export_presets.is_empty() {
+            ui.separator();
+        }
+        if ui.button("Save new preset...").clicked() {
+            self.preset_editor.open(formats);
+            ui.close_menu();
+        }
+    }
+
+    fn handle_copy_dialog(&mut self, ctx: &egui::Context) {
+        if !self.copy_dialog.open {
+            return;
+        }
+        let Some(settings) = self.copy_dialog.show(ctx)
+        else {
+            return;
+        };
+        let Some(source) = &mut self.disk_image
+        else {
+            return;
+        };
+
+        match copy::copy_to_new_image(source, &settings) {
+            Ok((disk, report)) => {
+                log::info!("Copied to new image: {}", report);
+                let name = self.disk_image_name.as_deref().unwrap_or("disk image");
+                let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+                let copy_name = format!("{}_copy", stem);
+                let description = format!("Copied to new {} image", settings.describe());
+                self.replace_image(disk, copy_name, description, settings.geometry.cylinders);
+                self.history.record(report.to_string());
+                self.toasts.success(format!("Copied to new image: {}", report));
+            }
+            Err(e) => {
+                log::error!("Error copying to new image: {:?}", e);
+                self.toasts.error("Couldn't copy to a new image", e.to_string());
+            }
+        }
+    }
+
+    fn handle_transform_dialog(&mut self, ctx: &egui::Context) {
+        if !self.transform_dialog.open {
+            return;
+        }
+        let Some(transform) = self.transform_dialog.show(ctx)
+        else {
+            return;
+        };
+        self.apply_transform(transform);
+    }
+
+    /// Overwrite sectors of the current image in place, keeping their previous contents so the
+    /// change can be undone.
+    fn write_sectors(&mut self, description: String, sectors: &[(SectorKey, Vec<u8>)]) {
+        let Some(mut disk) = self.disk_image.take()
+        else {
+            return;
+        };
+        let before = ImageSnapshot::of(&mut disk);
+        match copy::write_sectors_in_place(&mut disk, sectors) {
+            Ok(originals) => {
+                log::info!("{}: wrote {} sectors", description, sectors.len());
+                self.journal.record_sectors(&description, sectors);
+                let problems = watchdog::validate(&before, &ImageSnapshot::of(&mut disk), before.cylinders());
+                let name = self.disk_image_name.clone().unwrap_or("disk image".to_string());
+                let undo = Some(UndoChange::Sectors(originals));
+                self.commit_change(disk, name, description.clone(), undo, problems, ChangeScope::InPlace);
+                self.toasts.success(format!("{}: wrote {} sectors", description, sectors.len()));
+            }
+            Err(e) => {
+                log::error!("Error writing sectors: {:?}", e);
+                self.disk_image = Some(disk);
+                self.toasts.error(format!("{} failed", description), e.to_string());
+            }
+        }
+    }
+
+    fn apply_transform(&mut self, transform: Transform) {
+        let Some(source) = &mut self.disk_image
+        else {
+            return;
+        };
+        let cylinders = transform.cylinders_after(transform::dimensions(source).0);
+        match transform.apply(source) {
+            Ok((disk, report)) => {
+                log::info!("Applied transform {}: {}", transform.describe(), report);
+                let name = self.disk_image_name.clone().unwrap_or("disk image".to_string());
+                self.replace_image(disk, name, transform.describe(), cylinders);
+                self.history.record(report.to_string());
+                self.toasts.success(format!("{}: {}", transform.describe(), report));
+            }
+            Err(e) => {
+                log::error!("Error applying transform: {:?}", e);
+                self.toasts.error(format!("Couldn't apply {}", transform.label()), e.to_string());
+            }
+        }
+    }
+
+    /// Replace the current image with one derived from it, which should have `cylinders`
+    /// cylinders, keeping the current one for undo.
+    fn replace_image(&mut self, mut disk: DiskImage, name: String, description: String, cylinders: u16) {
+        self.journal.record_other();
+        let mut previous = self.disk_image.take();
+        let problems = match &mut previous {
+            Some(previous) => {
+                watchdog::validate(&ImageSnapshot::of(previous), &ImageSnapshot::of(&mut disk), cylinders)
+            }
+            None => Vec::new(),
+        };
+        self.commit_change(disk, name, description, previous.map(UndoChange::Image), problems, ChangeScope::Replace);
+    }
+
+    /// Install `disk`, the result of a change described by `description`, with `undo` to
+    /// reverse the change.
+    fn commit_change(
+        &mut self,
+        disk: DiskImage,
+        name: String,
+        description: String,
+        undo: Option<UndoChange>,
+        problems: Vec<String>,
+        scope: ChangeScope,
+    ) {
+        self.report_integrity(&description, problems);
+        if let Some(change) = undo {
+            self.undo.push(UndoEntry {
+                change,
+                name: self.disk_image_name.clone(),
+                len: self.disk_image_len,
+                digests: self.disk_image_digests.clone(),
+                hash: self.disk_image_hash.clone(),
+                description: description.clone(),
+            });
+        }
+        // The changed image has no file of its own until it's exported, but one changed in
+        // place is still keyed by the file it was loaded from.
+        match scope {
+            ChangeScope::InPlace => self.reset_derived_state(),
+            ChangeScope::Replace => {
+                self.reset_image_state();
+                self.disk_image_len = 0;
+                self.disk_image_hash = None;
+            }
+        }
+        self.disk_image_name = Some(name);
+        self.disk_image_digests = None;
+        self.history.record(description);
+        self.install_image(disk);
+    }
+
+    /// Warn of the invariants a change broke, such as leaving an image that can't be exported.
+    fn report_integrity(&mut self, description: &str, problems: Vec<String>) {
+        if problems.is_empty() {
+            return;
+        }
+        log::warn!("Integrity check after {}: {:?}", description, problems);
+        self.history.record(format!("Integrity check after {}: {}", description, problems.join(" ")));
+        self.toasts.warning(format!("{} may have damaged the image", description), problems.join("\n"));
+    }
+
+    fn undo_image(&mut self) {
+        let Some(entry) = self.undo.pop()
+        else {
+            return;
+        };
+        let scope = match entry.change {
+            UndoChange::Image(_) => ChangeScope::Replace,
+            UndoChange::Sectors(_) => ChangeScope::InPlace,
+        };
+        let disk = match entry.change {
+            UndoChange::Image(disk) => disk,
+            UndoChange::Sectors(originals) => {
+                let Some(mut disk) = self.disk_image.take()
+                else {
+                    return;
+                };
+                let lost_errors = copy::restore_sectors(&mut disk, &originals);
+                if lost_errors > 0 {
+                    self.toasts.warning(
+                        format!("Undid: {}", entry.description),
+                        format!(
+                            "{} of the restored sectors had a bad data CRC, which couldn't be restored with them.",
+                            lost_errors
+                        ),
+                    );
+                }
+                disk
+            }
+        };
+        self.journal.undo();
+        match scope {
+            ChangeScope::InPlace => self.reset_derived_state(),
+            ChangeScope::Replace => self.reset_image_state(),
+        }
+        self.disk_image_name = entry.name;
+        self.disk_image_len = entry.len;
+        self.disk_image_hash = entry.hash;
+        self.disk_image_digests = entry.digests;
+        self.history.record(format!("Undid: {}", entry.description));
+        self.install_image(disk);
+        self.toasts.push(Toasts::toast(ToastLevel::Info, format!("Undid: {}", entry.description)));
+    }
+
+    fn handle_preset_editor(&mut self, ctx: &egui::Context) {
+        if !self.preset_editor.open {
+            return;
+        }
+        let formats = match &self.disk_image {
+            Some(disk) => export::export_formats(disk),
+            None => Vec::new(),
+        };
+        if let Some(preset) = self.preset_editor.show(ctx, &formats) {
+            // Saving under an existing name replaces that preset.
+            self.p_state.export_presets.retain(|existing| existing.name != preset.name);
+            self.p_state.export_presets.push(preset);
+        }
+    }
+
+    fn handle_emulator_menu(&mut self, ui: &mut egui::Ui, formats: &[ExportFormat]) {
+        let Some(disk) = &self.disk_image
+        else {
+            return;
+        };
+        let presets: Vec<_> = EMULATOR_PRESETS
+            .iter()
+            .map(|preset| (preset, preset.resolve(disk, formats)))
+            .collect();
+        let boot_test = BOOT_TEST_PRESET.resolve(disk, formats);
+
+        for (preset, resolved) in presets {
+            let label = format!("{} (.{})", preset.name, preset.extension);
+            match resolved {
+                Ok(export) => {
+                    let hover = match &export.trimmed {
+                        Some(geometry) => format!("Trailing cylinders are trimmed to {}.", geometry.name),
+                        None => format!("Written as {}.", export.format.label()),
+                    };
+                    if ui.button(label).on_hover_text(hover).clicked() {
+                        self.start_emulator_export(ui.ctx(), preset, export);
+                        ui.close_menu();
+                    }
+                }
+                Err(reason) => {
+                    ui.add_enabled(false, egui::Button::new(label))
+                        .on_disabled_hover_text(reason);
+                }
+            }
+        }
+
+        ui.separator();
+        match boot_test {
+            Ok(export) => {
+                if ui
+                    .button("Boot test in v86...")
+                    .on_hover_text("Boot the image from drive A: in an embedded PC emulator.")
+                    .clicked()
+                {
+                    self.start_boot_test(export);
+                    ui.close_menu();
+                }
+            }
+            Err(reason) => {
+                ui.add_enabled(false, egui::Button::new("Boot test in v86..."))
+                    .on_disabled_hover_text(reason);
+            }
+        }
+    }
+
+    fn load_manifest(&mut self, name: &str, bytes: &[u8]) {
+        match ChecksumManifest::parse(name, &String::from_utf8_lossy(bytes)) {
+            Ok(manifest) => {
+                self.checksums.set_manifest(manifest);
+                if let (Some(image_name), Some(digests)) = (&self.disk_image_name, &self.disk_image_digests) {
+                    if let Some(result) = self.checksums.verify(image_name, digests) {
+                        self.history.record(result.to_string());
+                        checksum_toast(&mut self.toasts, result);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Error reading checksum manifest: {:?}", e);
+                self.checksums.error = Some(format!("Couldn't read {}: {}", name, e));
+            }
+        }
+    }
+
+    fn start_boot_test(&mut self, export: EmulatorExport) {
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+
+        let name = self.disk_image_name.clone().unwrap_or("image".to_string());
+        let format = export.format;
+        let image = match &export.trimmed {
+            Some(geometry) => export::export_trimmed(disk, format.format, geometry),
+            None => export::export_to_memory(disk, format.format),
+        };
+        match image {
+            Ok(image) => {
+                self.history.record(format!("Boot tested in v86 as {}", format.label()));
+                if let Err(e) = boot_test::open(&name, image) {
+                    log::error!("Error opening boot test: {:?}", e);
+                    self.export_error = Some(format!("Couldn't open boot test: {:?}", e));
+                }
+            }
+            Err(e) => {
+                log::error!("Error preparing boot test image: {:?}", e);
+                self.export_error = Some(format!("Couldn't prepare boot test image: {}", e));
+            }
+        }
+    }
+
+    /// The values export filename patterns are expanded with for the current image.
+    fn name_fields(&self) -> Option<NameFields> {
+        let disk = self.disk_image.as_ref()?;
+        let name = self.disk_image_name.as_deref().unwrap_or("image");
+        Some(NameFields::new(name, disk, self.disk_image_hash.as_deref()))
+    }
+
+    /// Move the disk image into an export task. The image is returned to us with the result
+    /// when the task finishes.
+
+    fn start_export(&mut self, ctx: &egui::Context, format: ExportFormat) {
+        let fields = self.name_fields().unwrap_or_default();
+        let filename = self.p_state.export_naming.filename(&format, &fields);
+        // Ask where to save now, while we still have user activation from the menu click.
+        let target = storage::SaveTarget::request(&filename);
+        self.start_export_as(ctx, format, filename, true, target);
+    }
+
+    /// Export for an emulator preset, named by the preset's pattern. Images trimmed to a
+    /// standard geometry are raw sector images, small enough to write on this thread.
+    fn start_emulator_export(&mut self, ctx: &egui::Context, preset: &EmulatorPreset, export: EmulatorExport) {
+        let mut fields = self.name_fields().unwrap_or_default();
+        if let Some(geometry) = &export.trimmed {
+            fields.geometry = export::geometry_label(geometry);
+        }
+        let filename = export::expand_naming(preset.naming, &export.format, &fields);
+        let target = storage::SaveTarget::request(&filename);
+        let Some(geometry) = export.trimmed
+        else {
+            self.start_export_as(ctx, export.format, filename, true, target);
+            return;
+        };
+        let Some(disk) = &mut self.disk_image
+        else {
+            return;
+        };
+
+        self.export_sidecar = true;
+        self.export_save_target = Some(target);
+        self.export_error = None;
+        let result = export::export_trimmed(disk, export.format.format, &geometry).map(StoredFile::Memory);
+        self.finish_export(filename, result);
+    }
+
+    fn start_export_as(
+        &mut self,
+        ctx: &egui::Context,
+        format: ExportFormat,
+        filename: String,
+        sidecar: bool,
+        target: storage::SaveTarget,
+    ) {
+        let Some(disk) = self.disk_image.take()
+        else {
+            return;
+        };
+
+        self.export_sidecar = sidecar;
+        self.export_save_target = Some(target);
+        self.export_error = None;
+
+        let size_hint = export::estimated_size(&disk, format.format);
+        let task_filename = filename.clone();
+        // The worker holds the disk, so the task can't be abandoned.
+        self.tasks.submit(filename, TaskKind::Convert, false, move |handle| {
+            let shared_disk = Arc::new(Mutex::new(Some(disk)));
+            let result = export::spawn_export(shared_disk.clone(), format, task_filename.clone(), size_hint, handle.clone());
+            if result.is_err() {
+                // Hand the disk back, so it isn't lost with the worker.
+                if let Some(disk) = shared_disk.lock().unwrap().take() {
+                    handle.finish(TaskOutput::Exported {
+                        disk,
+                        filename: task_filename,
+                        result: Err(anyhow::anyhow!("Couldn't start export worker")),
+                    });
+                }
+            }
+            result
+        });
+        ctx.request_repaint();
+    }
+
+    fn handle_export_status(&mut self, ui: &mut egui::Ui) {
+        if let Some(error) = &self.export_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+
+    fn handle_exported(&mut self, disk: DiskImage, filename: String, result: Result<StoredFile, anyhow::Error>) {
+        // A new image may have been dropped while the export was running.
+        if self.disk_image.is_none() && !self.tasks.is_active(TaskKind::Load) {
+            self.disk_image = Some(disk);
+        }
+        self.finish_export(filename, result);
+    }
+
+    /// Save, or report the failure of, an export written as `filename`.
+    fn finish_export(&mut self, filename: String, result: Result<StoredFile, anyhow::Error>) {
+        match result {
+            Ok(file) => {
+                log::info!("Exported {} ({} bytes)", filename, file.len());
+                self.tutorial.note_exported();
+                self.journal.clear();
+                self.history.record(format!("Exported as {} ({} bytes)", filename, file.len()));
+                self.toasts.success(format!("Exported {}", filename));
+                if self.export_sidecar {
+                    self.download_sidecar(&filename);
+                }
+                let target = self
+                    .export_save_target
+                    .take()
+                    .unwrap_or(storage::SaveTarget::Download);
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(e) = storage::save(file, &filename, target).await {
+                        log::error!("Error downloading {}: {:?}", filename, e);
+                    }
+                });
+            }
+            Err(e) => {
+                self.export_save_target = None;
+                log::error!("Error exporting disk image: {:?}", e);
+                self.history.record(format!("Export as {} failed: {}", filename, e));
+                self.toasts.error(format!("Export as {} failed", filename), e.to_string());
+                self.export_error = Some(format!("Export failed: {}", e));
+            }
+        }
+    }
+
+    fn handle_hires_render(&mut self, ctx: &egui::Context) {
+        let heads = self.disk_image.as_ref().map_or(0, |disk| disk.get_sector_map().len());
+        if let Some(request) = self.hires.show(ctx, heads) {
+            self.start_hires_render(ctx, request);
+        }
+    }
+
+    /// Move the disk image into a render task, as an export does.
+    fn start_hires_render(&mut self, ctx: &egui::Context, request: HiresRequest) {
+        let Some(disk) = self.disk_image.take()
+        else {
+            return;
+        };
+        let params = self.viz_state.metadata_params(&disk, request.side, self.viz_state.meta_palette.clone());
+        let name = self.disk_image_name.as_deref().unwrap_or("image");
+        let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+        let filename = format!("{}_side{}_{}px.png", stem, request.side, request.resolution);
+        self.hires.begin(ctx, request, filename.clone());
+
+        // The worker holds the disk, so the task can't be abandoned.
+        self.tasks.submit(filename, TaskKind::Render, false, move |handle| {
+            let shared_disk = Arc::new(Mutex::new(Some(disk)));
+            let result = hires::spawn_render(shared_disk.clone(), params, request, handle.clone());
+            if result.is_err() {
+                if let Some(disk) = shared_disk.lock().unwrap().take() {
+                    handle.finish(TaskOutput::Rendered {
+                        disk,
+                        request,
+                        png: Err(anyhow::anyhow!("Couldn't start render worker")),
+                    });
+                }
+            }
+            result
+        });
+        ctx.request_repaint();
+    }
+
+    /// Start the pending session thumbnail once the disk image is free, as watch mode may have
+    /// handed it straight to an export.
+    fn poll_thumbnail(&mut self) {
+        if self.disk_image.is_none() {
+            return;
+        }
+        if let Some(sha1) = self.pending_thumbnail.take() {
+            self.start_thumbnail_render(sha1);
+        }
+    }
+
+    /// Move the disk image into a render task for the session thumbnail of the image with
+    /// SHA-1 `sha1`, as a high resolution render does.
+    fn start_thumbnail_render(&mut self, sha1: String) {
+        let Some(disk) = self.disk_image.take()
+        else {
+            return;
+        };
+        let params = self.viz_state.metadata_params(&disk, 0, self.viz_state.meta_palette.clone());
+        let name = format!("{} thumbnail", self.disk_image_name.as_deref().unwrap_or("image"));
+
+        // The worker holds the disk, so the task can't be abandoned.
+        self.tasks.submit(name, TaskKind::Render, false, move |handle| {
+            let shared_disk = Arc::new(Mutex::new(Some(disk)));
+            let result = hires::spawn_thumbnail(shared_disk.clone(), params, sha1.clone(), handle.clone());
+            if result.is_err() {
+                if let Some(disk) = shared_disk.lock().unwrap().take() {
+                    handle.finish(TaskOutput::Thumbnail {
+                        disk,
+                        sha1,
+                        image: Err(anyhow::anyhow!("Couldn't start render worker")),
+                    });
+                }
+            }
+            result
+        });
+    }
+
+    fn handle_thumbnail(&mut self, disk: DiskImage, sha1: String, image: Result<egui::ColorImage, anyhow::Error>) {
+        // As with a high resolution render, a new image may have been dropped meanwhile.
+        if self.disk_image.is_none() && !self.tasks.is_active(TaskKind::Load) {
+            self.disk_image = Some(disk);
+        }
+        match image {
+            Ok(image) => self.session.set_thumbnail(&sha1, image),
+            Err(e) => log::warn!("Couldn't render thumbnail for {}: {:?}", sha1, e),
+        }
+    }
+
+    fn handle_rendered(&mut self, disk: DiskImage, request: HiresRequest, png: Result<Vec<u8>, anyhow::Error>) {
+        // A new image may have been dropped while the render was running.
+        if self.disk_image.is_none() && !self.tasks.is_active(TaskKind::Load) {
+            self.disk_image = Some(disk);
+        }
+
+        match self.hires.finish(png) {
+            Ok(()) => {
+                self.history
+                    .record(format!("Rendered side {} at {}px", request.side, request.resolution));
+                self.toasts.success(format!("Rendered {}px image", request.resolution));
+            }
+            Err(e) => {
+                log::error!("Error rendering visualization: {:?}", e);
+                self.toasts.error("High resolution render failed", e.to_string());
+            }
+        }
+    }
+
+    fn handle_task_messages(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|i| i.time);
+        let events = self.tasks.poll(now);
+        // One repaint covers everything that arrived since the last frame.
+        if !events.is_empty() {
+            ctx.request_repaint();
+        }
+        for event in events {
+            match event.message {
+                TaskMessage::Progress(progress) => {
+                    log::debug!("Task {} progress: {:.1}%", event.id, progress * 100.0);
+                }
+                TaskMessage::StreamChecked(index, result) => {
+                    if let Some(stream_map) = &mut self.stream_map {
+                        stream_map.set_checked(index, result);
+                    }
+                }
+                TaskMessage::Unwrapped(probe) => {
+                    log::info!("Unwrapped {} from {}", probe.name, event.name);
+                    if event.kind == TaskKind::Load {
+                        self.history.record(format!("Unwrapped {} ({} bytes) from the zip", probe.name, probe.size));
+                        // It's the image inside that failed to load, if it does.
+                        self.load_probe = Some(probe);
+                    }
+                }
+                TaskMessage::Finished(TaskOutput::Loaded(Ok(disk))) => {
+                    log::info!("Disk image loaded successfully!");
+                    self.load_probe = None;
+                    self.handle_loaded(ctx, disk);
+                }
+                TaskMessage::Finished(TaskOutput::Loaded(Err(e))) => {
+                    log::error!("Error loading disk image: {:?}", e);
+                    self.history.record(format!("Load failed: {:?}", e));
+                    match (&e, self.load_probe.take()) {
+                        (DiskImageError::UnknownFormat | DiskImageError::UnsupportedFormat, Some(probe)) => {
+                            self.unsupported.open(probe, format!("{:?}", e));
+                        }
+                        _ => self.toasts.error("Couldn't load disk image", format!("{:?}", e)),
+                    }
+                    self.record_session_entry();
+                    self.load_failed = true;
+                }
+                TaskMessage::Finished(TaskOutput::LoadedComparison(Ok(disk))) => {
+                    let name = self.comparison.name.clone().unwrap_or("image".to_string());
+                    log::info!("Comparison image {} loaded", name);
+                    self.history.record(format!("Loaded {} for comparison", name));
+                    self.comparison.set_disk(disk);
+                }
+                TaskMessage::Finished(TaskOutput::LoadedComparison(Err(e))) => {
+                    log::error!("Error loading comparison image: {:?}", e);
+                    self.toasts.error("Couldn't load comparison image", format!("{:?}", e));
+                    self.comparison.clear();
+                }
+                TaskMessage::Finished(TaskOutput::LoadedMergeDump(Ok(disk))) => {
+                    let name = self.merge.loading.clone().unwrap_or("image".to_string());
+                    log::info!("Merge dump {} loaded", name);
+                    self.history.record(format!("Loaded {} for merging", name));
+                    self.merge.add_dump(disk);
+                }
+                TaskMessage::Finished(TaskOutput::LoadedMergeDump(Err(e))) => {
+                    log::error!("Error loading dump for merging: {:?}", e);
+                    self.toasts.error("Couldn't load the dump to merge", format!("{:?}", e));
+                    self.merge.loading = None;
+                }
+                TaskMessage::Finished(TaskOutput::Redecoded { name, ch, check, result }) => {
+                    self.handle_redecoded(name, ch, check, result);
+                }
+                TaskMessage::Finished(TaskOutput::Exported { disk, filename, result }) => {
+                    self.handle_exported(disk, filename, result);
+                }
+                TaskMessage::Tile { origin, image } => {
+                    self.hires.apply_tile(origin, image);
+                }
+                TaskMessage::Finished(TaskOutput::Rendered { disk, request, png }) => {
+                    self.handle_rendered(disk, request, png);
+                }
+                TaskMessage::Finished(TaskOutput::Thumbnail { disk, sha1, image }) => {
+                    self.handle_thumbnail(disk, sha1, image);
+                }
+                TaskMessage::Crashed(panic) => {
+                    self.handle_crashed(event.kind, event.name, panic);
+                }
+            }
+        }
+
+        // Keep repainting while workers are running, so their messages are picked up.
+        self.run_mode = if self.tasks.busy() { RunMode::Continuous } else { RunMode::Reactive };
+    }
+
+    fn handle_crashed(&mut self, kind: TaskKind, task: String, panic: String) {
+        let lost_image = match kind {
+            TaskKind::Load => {
+                self.load_failed = true;
+                self.record_session_entry();
+                false
+            }
+            TaskKind::Compare => {
+                self.comparison.clear();
+                false
+            }
+            TaskKind::Merge => {
+                self.merge.loading = None;
+                false
+            }
+            TaskKind::Redecode => false,
+            // Exports and renders take the disk image with them.
+            TaskKind::Convert => {
+                self.export_save_target = None;
+                self.disk_image.is_none()
+            }
+            TaskKind::Render => {
+                self.hires.cancel();
+                self.disk_image.is_none()
+            }
+        };
+        self.history.record(format!("{} task {} crashed: {}", kind.label(), task, panic));
+        self.toasts.error(format!("{} task crashed", kind.label()), panic.clone());
+        self.crashes.push(CrashReport {
+            kind,
+            task,
+            image: self.disk_image_name.clone(),
+            panic,
+            lost_image,
+        });
+    }
+
+    fn handle_loaded(&mut self, ctx: &egui::Context, disk: DiskImage) {
+        self.load_failed = false;
+        self.history.record(format!("Decoded as {:?} image, geometry {:?}", disk.resolution(), disk.geometry()));
+
+        self.install_image(disk);
+        self.restore_workspace();
+        self.restore_set_member();
+        self.record_session_entry();
+        let name = self.disk_image_name.clone().unwrap_or("disk image".to_string());
+        match self.entropy.as_ref().map_or(0, |entropy| entropy.crc_errors) {
+            0 => self.toasts.success(format!("Loaded {}", name)),
+            errors => self.toasts.warning(
+                format!("Loaded {}: {} CRC errors found", name, errors),
+                "Some sectors were read with bad data CRCs. Their contents may be corrupt.",
+            ),
+        }
+        self.run_watch_mode(ctx);
+    }
+
+    /// The workspace to autosave: the current image's, or one still waiting to be restored.
+    fn workspace(&self) -> Option<Workspace> {
+        let (Some(name), Some(hash), Some(_)) = (&self.disk_image_name, &self.disk_image_hash, &self.disk_image)
+        else {
+            return self.pending_restore.as_ref().map(|pending| pending.workspace.clone());
+        };
+        let mut workspace = Workspace::new(name, hash, self.disk_image_len);
+        workspace.side = self.viz_state.side;
+        workspace.selection = self.viz_state.selection.as_ref().map(|hit| SavedPosition {
+            c: hit.ch.c(),
+            h: hit.ch.h(),
+            bit_offset: hit.bit_offset,
+            sector: hit.sector.as_ref().map(|span| span.key),
+        });
+        workspace.annotations = self.annotations.items.clone();
+        workspace.bookmarks = self.bookmarks.items.iter().map(SavedPosition::from).collect();
+        workspace.history = self.history.clone();
+        Some(workspace)
+    }
+
+    fn handle_pending_restore(&mut self, ui: &mut egui::Ui) {
+        let Some(pending) = &self.pending_restore
+        else {
+            return;
+        };
+        if pending.show(ui) {
+            self.pending_restore = None;
+        }
+    }
+
+    /// Offer to write back edits journaled for the loaded image before the tab was closed.
+    fn handle_recovery(&mut self, ui: &mut egui::Ui) {
+        if self.disk_image.is_none() {
+            return;
+        }
+        if let Some(sectors) = self.journal.show_offer(ui) {
+            self.write_sectors("Recovered edits".to_string(), &sectors);
+        }
+    }
+
+    /// Put back the saved workspace if the image just loaded is the one it was saved for.
+    fn restore_workspace(&mut self) {
+        let (Some(hash), Some(pending)) = (&self.disk_image_hash, &self.pending_restore)
+        else {
+            return;
+        };
+        if !pending.workspace.matches(hash) {
+            return;
+        }
+        let Some(PendingRestore { workspace }) = self.pending_restore.take()
+        else {
+            return;
+        };
+
+        let heads = self.disk_image.as_ref().map_or(0, |disk| disk.get_sector_map().len());
+        if workspace.side != self.viz_state.side && workspace.side < heads.min(2) {
+            self.viz_state.side = workspace.side;
+            self.rerender_visualization();
+        }
+        self.annotations.items = workspace.annotations;
+        self.update_annotation_overlay();
+        self.bookmarks.items = workspace.bookmarks.iter().map(Bookmark::from).collect();
+        if let Some(selection) = &workspace.selection {
+            let selection = match selection.sector {
+                Some(key) => Selection::Sector(key),
+                None => Selection::Position {
+                    ch: selection.ch(),
+                    bit_offset: selection.bit_offset,
+                },
+            };
+            self.selection.publish(SelectionSource::Other, selection);
+        }
+        // The saved history is from before the reload, so it goes ahead of this load's entries.
+        let mut entries = workspace.history.entries;
+        entries.append(&mut self.history.entries);
+        self.history.entries = entries;
+        self.history.record(format!("Restored the saved workspace for {}", workspace.image_name));
+        self.toasts.push(Toasts::toast(
+            ToastLevel::Info,
+            format!("Restored workspace for {}", workspace.image_name),
+        ));
+    }
+
+    /// Make `disk` the current image, rendering it and running the analyses. Used both for
+    /// loaded images and for those derived from them by copies and transforms.
+    fn install_image(&mut self, disk: DiskImage) {
+        self.disk_image = Some(disk);
+        self.viz_state.hard_sectoring = self.scp_info.as_ref().and_then(HardSectoring::from_scp);
+        match self.viz_state.render_visualization(self.disk_image.as_mut(), 0) {
+            Ok(_) => {
+                log::info!("Visualization rendered successfully!");
+            }
+            Err(e) => {
+                log::error!("Error rendering visualization: {:?}", e);
+            }
+        }
+        self.update_entropy_overlay();
+        self.update_annotation_overlay();
+        if let Some(disk) = &mut self.disk_image {
+            self.fat_browser.load(disk);
+            // Only look for 8-bit filesystems where there's no DOS filesystem.
+            if self.fat_browser.volume.is_none() {
+                self.cbm_browser.load(disk);
+                if !self.cbm_browser.is_mounted() {
+                    self.apple_browser.load(disk);
+                }
+                if !self.cbm_browser.is_mounted() && !self.apple_browser.is_mounted() {
+                    self.cpm_browser.load(disk);
+                }
+            }
+        }
+        self.update_file_overlay();
+        if let Some(disk) = &self.disk_image {
+            self.gap_stats = Some(GapStats::from_disk(disk));
+            let bpb = self.fat_browser.volume.as_ref().map(|volume| &volume.bpb);
+            let layout = LayoutSummary::from_disk(disk);
+            self.installer = InstallerFormat::detect(&layout, bpb);
+            self.layout = Some(layout);
+            self.trim = TrimAnalysis::from_disk(disk, bpb);
+            self.flux_job = FluxAnalysis::start(disk, self.scp_info.as_ref());
+        }
+        // Clears the previous image's markers until the analysis completes.
+        self.update_flux_overlays();
+        if let Some(disk) = &mut self.disk_image {
+            let volume = self.fat_browser.volume.clone();
+            let mut ctx = PanelContext {
+                disk,
+                fat: volume.as_deref().map(|volume| (volume, self.fat_browser.tree.as_slice())),
+                gap_stats: self.gap_stats.as_ref(),
+            };
+            self.panels.image_loaded(&mut ctx);
+        }
+    }
+
+    /// Add the current image, or the failed attempt to load it, to the session dashboard.
+    fn record_session_entry(&mut self) {
+        let (Some(name), Some(digests)) = (&self.disk_image_name, &self.disk_image_digests)
+        else {
+            return;
+        };
+        let mut entry = SessionEntry::new(name, self.disk_image_len, &digests.sha1);
+        if let Some(disk) = &self.disk_image {
+            entry.resolution = Some(format!("{:?}, {:?}", disk.resolution(), disk.geometry()));
+        }
+        if let Some(entropy) = &self.entropy {
+            entry.sectors = entropy.sectors.len();
+            entry.crc_errors = entropy.crc_errors;
+        }
+        if let Some(disk) = &mut self.disk_image {
+            entry.sector_hash = Some(analysis::sector_data_hash(disk));
+        }
+        if self.disk_image.is_some() {
+            self.pending_thumbnail = Some(entry.sha1.clone());
+        }
+        self.session.record(entry);
+
+        if let Some(entry) = self.session.entries.last() {
+            let duplicates = self.session.duplicates(entry);
+            if duplicates.count() > 0 {
+                let names: Vec<&str> = duplicates.same_file.iter().chain(duplicates.same_data.iter()).copied().collect();
+                self.toasts.push(
+                    Toasts::toast(ToastLevel::Info, format!("{} duplicates an image already loaded", entry.name))
+                        .with_details(names.join("\n")),
+                );
+            }
+        }
+    }
+
+    /// Track the disk set the dropped image `name` belongs to, starting a new one if it doesn't
+    /// belong to the current set. Returns whether the image should be loaded: disks dropped
+    /// together with the one being shown are only added to the set.
+    fn join_disk_set(&mut self, name: &str, bytes: Arc<[u8]>, now: f64) -> bool {
+        if let Some(set) = &mut self.disk_set {
+            if let Some(index) = set.position(name) {
+                set.current = index;
+                return true;
+            }
+            if set.accepts(name) {
+                let (index, same_drop) = set.add(name, bytes, now);
+                if same_drop {
+                    log::info!("Added {} to disk set {}", name, set.title);
+                    self.history.record(format!("Added {} to the disk set", name));
+                    return false;
+                }
+                set.current = index;
+                return true;
+            }
+        }
+        self.disk_set = DiskSet::new(name, bytes, now);
+        true
+    }
+
+    fn handle_disk_set(&mut self, ui: &mut egui::Ui) {
+        let Some(set) = &mut self.disk_set
+        else {
+            return;
+        };
+        match set.show(ui) {
+            Some(DiskSetAction::Switch(index)) => {
+                let report = self.image_report();
+                if let Some(set) = &mut self.disk_set {
+                    set.stash(self.annotations.items.clone(), self.bookmarks.items.clone(), report);
+                    if let Some(file) = set.file(index) {
+                        self.dropped_files.insert(0, file);
+                    }
+                }
+            }
+            Some(DiskSetAction::Report) => self.download_set_report(),
+            None => {}
+        }
+    }
+
+    /// Put back the annotations and bookmarks made on a disk of the set before switching away.
+    fn restore_set_member(&mut self) {
+        let Some(set) = &mut self.disk_set
+        else {
+            return;
+        };
+        let (annotations, bookmarks) = set.unstash();
+        if !annotations.is_empty() {
+            self.annotations.items = annotations;
+            self.update_annotation_overlay();
+        }
+        if !bookmarks.is_empty() {
+            self.bookmarks.items = bookmarks;
+        }
+    }
+
+    fn download_set_report(&mut self) {
+        let Some(set) = &self.disk_set
+        else {
+            return;
+        };
+        match set.report_json(self.image_report().as_ref()) {
+            Ok(json) => {
+                if let Err(e) = storage::download_bytes(json.as_bytes(), &set.report_filename()) {
+                    log::error!("Error downloading set report: {:?}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("Error serializing set report: {:?}", e);
+            }
+        }
+    }
+
+    /// Convert and download the newly loaded image as configured in watch mode.
+    fn run_watch_mode(&mut self, ctx: &egui::Context) {
+        let watch_mode = self.p_state.watch_mode.clone();
+        if !watch_mode.enabled {
+            return;
+        }
+        if watch_mode.report {
+            self.download_report();
+        }
+
+        let Some(preset_name) = &watch_mode.preset
+        else {
+            return;
+        };
+        let Some(preset) = self.p_state.export_presets.iter().find(|preset| &preset.name == preset_name).cloned()
+        else {
+            self.toasts.error("Watch mode couldn't convert", format!("The preset \"{}\" no longer exists.", preset_name));
+            return;
+        };
+        if self.tasks.is_active(TaskKind::Convert) {
+            // The running export would hand its disk back in place of this one.
+            self.toasts.warning("Watch mode skipped conversion", "Another export was still in progress.");
+            return;
+        }
+        let formats = match &self.disk_image {
+            Some(disk) => export::export_formats(disk),
+            None => return,
+        };
+
+        match preset.resolve(&formats) {
+            Ok(format) => {
+                let fields = self.name_fields().unwrap_or_default();
+                let filename = preset.filename(&format, &fields);
+                self.history.record(format!("Watch mode: converting with preset {}", preset.name));
+                // There's no user activation to show a save picker with, so always download.
+                self.start_export_as(ctx, format, filename, preset.sidecar, storage::SaveTarget::Download);
+            }
+            Err(reason) => {
+                self.toasts.warning(format!("Watch mode couldn't convert with {}", preset.name), reason);
+            }
+        }
+    }
+
+    fn handle_loading_progress(&mut self, ui: &mut egui::Ui) {
+        let Some(stream_map) = &self.stream_map
+        else {
+            return;
+        };
+        let loading = self.tasks.is_active(TaskKind::Load);
+        // Once loaded, the grid stays up while there are tracks to re-dump.
+        let loaded = !loading && !self.load_failed && self.disk_image.is_some();
+        if loading || self.load_failed || (loaded && stream_map.failed().next().is_some()) {
+            stream_map.show(ui, loaded);
+        }
+    }
+
+    /// Drop the loaded image and everything derived from it, along with what the user attached
+    /// to it.
+    fn reset_image_state(&mut self) {
+        self.reset_derived_state();
+        self.metadata = ImageMetadata::default();
+        self.annotations.clear();
+        self.annotation_error = None;
+        self.bookmarks.clear();
+        self.scp_info = None;
+    }
+
+    /// Drop the loaded image and the analyses and views derived from its contents, keeping what
+    /// the user attached to it, for a change that edits it in place.
+    fn reset_derived_state(&mut self) {
+        self.disk_image = None;
+        self.entropy = None;
+        self.gap_stats = None;
+        self.trim = None;
+        self.layout = None;
+        self.installer = None;
+        self.analysis.clear();
+        self.flux_analysis = None;
+        self.flux_job = None;
+        self.fat_browser.clear();
+        self.panels.image_changed();
+        self.hires.clear();
+        self.cpm_browser.clear();
+        self.cbm_browser.clear();
+        self.apple_browser.clear();
+        self.comparison.clear_diff();
+        self.hex_viewer.clear();
+        self.pending_thumbnail = None;
+        self.viz_state.hard_sectoring = None;
+        self.viz_state.selection = None;
+        self.selection.clear();
+    }
+
+    /// Load an image in a worker. `streams` are the paths of the Kryoflux stream files in the
+    /// file, if it is a stream set, which are checked one by one before the set is loaded.
+    fn spawn_load(&mut self, name: String, kind: TaskKind, bytes: Vec<u8>, streams: Vec<String>) {
+        log::debug!("Spawning thread to load disk image");
+        self.tasks.submit(name, kind, true, move |handle| {
+            worker::spawn_closure_worker(move || {
+                log::debug!("Hello from worker thread!");
+
+                // Zipped single images are unwrapped here rather than on the main thread, as
+                // inflating them can take a while.
+                let bytes = match archive::unwrap_single_image(&bytes) {
+                    Some((member, contents)) if streams.is_empty() => {
+                        handle.send(TaskMessage::Unwrapped(FileProbe::new(&member, &contents)));
+                        contents
+                    }
+                    _ => bytes,
+                };
+                kryoflux::check_streams(&bytes, &streams, |index, result| {
+                    handle.send(TaskMessage::StreamChecked(index, result));
+                    !handle.is_cancelled()
+                });
+                let mut cursor = std::io::Cursor::new(bytes);
+
+                // callback is of type Arc<dyn Fn(LoadingStatus) + Send + Sync>
+                let progress_handle = handle.clone();
+                let callback = Arc::new(move |status: LoadingStatus| {
+                    match status {
+                        LoadingStatus::Progress(progress) if !progress_handle.is_cancelled() => {
+                            progress_handle.progress(progress);
+                        }
+                        _ => {}
+                    }
+                });
+
+                let result = DiskImage::load(&mut cursor, None, None, Some(callback));
+                match kind {
+                    TaskKind::Compare => handle.finish(TaskOutput::LoadedComparison(result)),
+                    TaskKind::Merge => handle.finish(TaskOutput::LoadedMergeDump(result)),
+                    _ => handle.finish(TaskOutput::Loaded(result)),
+                }
+            })
+        });
+    }
+
+    fn handle_dropped_files(&mut self, ctx: &egui::Context, ui: Option<&mut egui::Ui>) {
+        if let Some(ui) = ui {
+            ui.group(|ui| {
+                ui.label("Dropped files:");
+
+                if let Some(file) = self.dropped_files.get(0) {
+                    let mut info = if let Some(path) = &file.path {
+                        path.display().to_string()
+                    } else if !file.name.is_empty() {
+                        file.name.clone()
+                    } else {
+                        "???".to_owned()
+                    };
+
+                    let mut additional_info = vec![];
+                    if !file.mime.is_empty() {
+                        additional_info.push(format!("type: {}", file.mime));
+                    }
+                    if let Some(bytes) = &file.bytes {
+                        additional_info.push(format!("{} bytes", bytes.len()));
+                    } else {
+                        additional_info.push("loading...".to_string());
+                    }
+
+                    if !additional_info.is_empty() {
+                        info += &format!(" ({})", additional_info.join(", "));
+                    }
+
+                    ui.label(info);
+                } else {
+                    ui.label("No file currently dropped.");
+                }
+            });
+        }
+
+        // Files dropped together, such as the disks of a set, are queued and processed one per frame.
+        ctx.input(|i| {
+            self.dropped_files.extend(i.raw.dropped_files.iter().cloned());
+        });
+        if self.dropped_files.len() > 1 {
+            ctx.request_repaint();
+        }
+
+        // Wait for bytes to be available, then process
+        if let Some(file) = self.dropped_files.first().cloned() {
+            if let Some(bytes) = &file.bytes {
+
+                // Hash lists look like checksum manifests, so are checked for first.
+                if FileIdent::is_hash_list(&file.name) {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    self.fat_browser.ident.load_list(&name, &bytes);
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Wasm modules are analysis plugins, not disk images.
+                if PluginLoader::is_plugin_file(&file.name) {
+                    let (name, bytes) = (file.name.clone(), bytes.to_vec());
+                    self.plugins.offer(&name, bytes);
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Checksum manifests may be dropped at any time; verify the current image against it.
+                if ChecksumManifest::is_manifest_file(&file.name) {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    self.load_manifest(&name, &bytes);
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Structure templates are added to the hex viewer.
+                if StructTemplate::is_template_file(&file.name) {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    self.hex_viewer.load_templates(&name, &bytes);
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Annotation files dropped while an image is loaded are merged into its annotations.
+                if self.disk_image.is_some() && AnnotationFile::is_annotation_file(&file.name) {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    self.import_annotations(&name, &bytes);
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Photos dropped while an image is loaded are attached as the label image.
+                if self.disk_image.is_some() && ImageMetadata::is_label_image(&file.name, &file.mime) {
+                    log::info!("Attaching label photo: {} ({} bytes)", file.name, bytes.len());
+                    self.history.record(format!("Attached label photo {}", file.name));
+                    self.metadata.label_image = Some(LabelImage::from_bytes(&file.name, &file.mime, bytes));
+                    self.metadata_open = true;
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Stream files dropped once a Kryoflux set has loaded are re-dumps of its tracks.
+                if let (Some(_), Some(_), Some(ch)) =
+                    (&self.disk_image, &self.stream_map, kryoflux::stream_track(&file.name))
+                {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    self.spawn_redecode(name, ch, bytes.to_vec());
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // While comparing, the next image dropped is loaded alongside the current one.
+                if self.comparison.awaiting && self.disk_image.is_some() {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    log::info!("Loading {} for comparison", name);
+                    self.comparison.awaiting = false;
+                    self.comparison.loading = true;
+                    self.comparison.name = Some(name.clone());
+                    self.tasks.cancel_kind(TaskKind::Compare);
+                    self.spawn_load(name, TaskKind::Compare, bytes.to_vec(), Vec::new());
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Likewise for the merge tool, which takes the next image as another dump.
+                if self.merge.awaiting && self.disk_image.is_some() {
+                    let (name, bytes) = (file.name.clone(), bytes.clone());
+                    log::info!("Loading {} for merging", name);
+                    self.merge.awaiting = false;
+                    self.merge.loading = Some(name.clone());
+                    self.spawn_load(name, TaskKind::Merge, bytes.to_vec(), Vec::new());
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                // Only process if bytes are now available
+                log::info!("Processing file: {} ({} bytes)", file.name, bytes.len());
+
+                // Disks dropped along with the one being shown only join its set.
+                if !self.join_disk_set(&file.name, bytes.clone(), ctx.input(|i| i.time)) {
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                let bytes = bytes.clone();
+
+                // Remove the old disk image
+                self.reset_image_state();
+                self.undo.clear();
+                // Set the name of the new disk image
+                self.disk_image_name = Some(file.name.clone());
+                self.disk_image_len = bytes.len();
+                let digests = Digests::new(&bytes);
+                self.disk_image_hash = Some(digests.sha1.clone());
+                self.journal.begin(&file.name, &digests.sha1, bytes.len());
+                self.history.clear();
+                self.history.record(format!(
+                    "Loaded {} ({} bytes, SHA-1 {})",
+                    file.name,
+                    bytes.len(),
+                    digests.sha1
+                ));
+                if let Some(result) = self.checksums.verify(&file.name, &digests) {
+                    self.history.record(result.to_string());
+                    checksum_toast(&mut self.toasts, result);
+                }
+                self.disk_image_digests = Some(digests);
+                self.scp_info = ScpInfo::parse(&bytes);
+
+                // D64 files are plain sector dumps with no track layout for fluxfox to decode,
+                // so they're opened straight into the CBM DOS browser.
+                if CbmVolume::is_d64_file(&file.name) {
+                    self.tasks.cancel_kind(TaskKind::Load);
+                    // There's no track data to visualize.
+                    self.viz_state.have_render = false;
+                    match CbmVolume::from_d64(&bytes) {
+                        Ok(volume) => {
+                            self.history.record("Opened as a D64 sector dump".to_string());
+                            self.toasts.success(format!("Opened {}", file.name));
+                            self.cbm_browser.mount(volume);
+                        }
+                        Err(e) => {
+                            log::error!("Error opening D64 image {}: {}", file.name, e);
+                            self.history.record(format!("Load failed: {}", e));
+                            self.toasts.error(format!("Couldn't open {}", file.name), e.to_string());
+                        }
+                    }
+                    self.finish_dropped_file();
+                    return;
+                }
+
+                self.stream_map = StreamMap::from_zip(&bytes);
+                let streams = self.stream_map.as_ref().map_or(Vec::new(), |m| m.paths());
+
+                self.load_failed = false;
+                // Only the most recently dropped image is wanted.
+                self.tasks.cancel_kind(TaskKind::Load);
+                let name = file.name.clone();
+                self.load_probe = Some(FileProbe::new(&name, &bytes));
+                self.spawn_load(name, TaskKind::Load, bytes.to_vec(), streams);
+                ctx.request_repaint();
+
+                // Clear the dropped file after processing
+                self.finish_dropped_file();
+            } else {
+                // Request a repaint until the file's bytes are loaded
+                ctx.request_repaint();
+            }
+        }
+
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotations::AnnotationTag;
+
+    fn migrated(json: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        let serde_json::Value::Object(mut state) = json
+        else {
+            panic!("not an object");
+        };
+        migrate_state_v0(&mut state);
+        state
+    }
+
+    #[test]
+    fn v0_without_tutorial_closes_it() {
+        let state = migrated(serde_json::json!({ "label": "disk" }));
+        assert_eq!(state["tutorial"]["closed"], serde_json::Value::Bool(true));
+        assert_eq!(state["label"], "disk");
+    }
+
+    #[test]
+    fn v0_tutorial_in_progress_is_kept() {
+        let state = migrated(serde_json::json!({ "tutorial": { "step": 2, "closed": false } }));
+        assert_eq!(state["tutorial"]["step"], 2);
+        assert_eq!(state["tutorial"]["closed"], serde_json::Value::Bool(false));
+    }
+
+    #[test]
+    fn v0_state_loads_as_current() {
+        let state = migrated(serde_json::json!({ "label": "disk", "hidden_panels": ["hex"] }));
+        let state: PersistentState = serde_json::from_value(serde_json::Value::Object(state)).unwrap();
+        assert_eq!(state.label, "disk");
+        assert!(state.hidden_panels.contains("hex"));
+        assert!(state.tutorial.closed);
+    }
+
+    /// An app with a freshly formatted 360K image loaded from a file with hash `sha1`.
+    fn app_with_image(sha1: &str) -> App {
+        let disk = fluxfox::ImageBuilder::new()
+            .with_resolution(fluxfox::DiskDataResolution::BitStream)
+            .with_standard_format(fluxfox::StandardFormat::PcFloppy360)
+            .with_formatted(true)
+            .build()
+            .expect("image");
+        let mut app = App::default();
+        app.disk_image_name = Some("test.img".to_string());
+        app.disk_image_len = 368_640;
+        app.disk_image_hash = Some(sha1.to_string());
+        app.install_image(disk);
+        app
+    }
+
+    #[test]
+    fn sector_writes_keep_annotations_and_bookmarks() {
+        let mut app = app_with_image("abc123");
+        let ch = DiskCh::new(0, 0);
+        let key = SectorKey::new(ch, 1);
+        app.annotations.add(AnnotationTarget::track(ch), AnnotationTag::Note, "first track".to_string());
+        app.bookmarks.toggle(Bookmark {
+            ch,
+            bit_offset: 0,
+            sector: Some(key),
+        });
+
+        app.write_sectors("Test write".to_string(), &[(key, vec![0xAA; 512])]);
+
+        assert!(app.disk_image.is_some());
+        assert_eq!(app.annotations.items.len(), 1);
+        assert_eq!(app.bookmarks.items.len(), 1);
+        assert_eq!(app.disk_image_hash.as_deref(), Some("abc123"));
+        assert!(app.workspace().is_some());
+    }
+
+    #[test]
+    fn replacing_the_image_drops_annotations() {
+        let mut app = app_with_image("abc123");
+        let ch = DiskCh::new(0, 0);
+        app.annotations.add(AnnotationTarget::track(ch), AnnotationTag::Note, "first track".to_string());
+        let disk = app.disk_image.take().expect("image");
+
+        app.replace_image(disk, "copy.img".to_string(), "Copy".to_string(), 40);
+
+        assert!(app.annotations.items.is_empty());
+        assert_eq!(app.disk_image_hash, None);
+    }
+}
@@ -0,0 +1,490 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A read-only reader for Apple II DOS 3.3 and ProDOS filesystems on 5.25" GCR disks decoded
+//! by fluxfox.
+//!
+//! Both systems interleave their logical sectors differently across the 16 physical sectors of
+//! a track, so we keep the sectors by their physical number and translate on each read.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use fluxfox::DiskImage;
+
+use crate::analysis::read_all_sectors;
+use crate::storage;
+
+pub const APPLE_SECTOR_SIZE: usize = 256;
+pub const APPLE_SECTORS_PER_TRACK: usize = 16;
+pub const PRODOS_BLOCK_SIZE: usize = 512;
+
+pub const DOS33_VTOC_TRACK: u8 = 17;
+pub const DOS33_CATALOG_ENTRY_SIZE: usize = 35;
+pub const DOS33_CATALOG_ENTRIES: usize = 7;
+pub const DOS33_TS_PAIRS: usize = 122;
+
+pub const PRODOS_VOLUME_DIR_BLOCK: usize = 2;
+pub const PRODOS_ENTRY_SIZE: usize = 0x27;
+pub const PRODOS_ENTRIES_PER_BLOCK: usize = 13;
+
+/// Stop following a chain of sectors or blocks after this many links, as a corrupt chain may
+/// loop.
+pub const APPLE_MAX_CHAIN: usize = 560;
+
+/// The physical sector holding each DOS 3.3 logical sector.
+const DOS33_PHYSICAL_SECTORS: [u8; 16] = [0, 13, 11, 9, 7, 5, 3, 1, 14, 12, 10, 8, 6, 4, 2, 15];
+/// The physical sector holding each ProDOS logical sector. Blocks are pairs of these.
+const PRODOS_PHYSICAL_SECTORS: [u8; 16] = [0, 2, 4, 6, 8, 10, 12, 14, 1, 3, 5, 7, 9, 11, 13, 15];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AppleFilesystem {
+    Dos33,
+    ProDos,
+}
+
+impl AppleFilesystem {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AppleFilesystem::Dos33 => "DOS 3.3",
+            AppleFilesystem::ProDos => "ProDOS",
+        }
+    }
+}
+
+/// Where a file's contents are found.
+#[derive(Copy, Clone, Debug)]
+enum FileLocation {
+    Dos33 { ts_list: (u8, u8) },
+    ProDos { storage_type: u8, key_block: usize, eof: usize },
+}
+
+#[derive(Clone, Debug)]
+pub struct AppleFile {
+    pub path: String,
+    /// The file type as shown in a catalog: A, I, B or T for the common types, otherwise the
+    /// DOS 3.3 letter or ProDOS type mnemonic.
+    pub file_type: String,
+    pub locked: bool,
+    /// The size in sectors (DOS 3.3) or blocks (ProDOS).
+    pub size: usize,
+    location: FileLocation,
+}
+
+impl AppleFile {
+    pub fn extension(&self) -> &'static str {
+        match self.file_type.as_str() {
+            "T" | "TXT" => "txt",
+            "B" | "BIN" => "bin",
+            "A" | "BAS" => "bas",
+            "I" | "INT" => "int",
+            "SYS" => "sys",
+            _ => "dat",
+        }
+    }
+}
+
+pub fn dos33_type_label(type_byte: u8) -> &'static str {
+    match type_byte & 0x7F {
+        0x00 => "T",
+        0x01 => "I",
+        0x02 => "A",
+        0x04 => "B",
+        0x08 => "S",
+        0x10 => "R",
+        0x20 => "a",
+        0x40 => "b",
+        _ => "?",
+    }
+}
+
+pub fn prodos_type_label(file_type: u8) -> String {
+    match file_type {
+        0x04 => "T".to_string(),
+        0x06 => "B".to_string(),
+        0xFA => "I".to_string(),
+        0xFC => "A".to_string(),
+        0x0F => "DIR".to_string(),
+        0xFF => "SYS".to_string(),
+        _ => format!("${:02X}", file_type),
+    }
+}
+
+/// Decode Apple II text, which is ASCII with the high bit usually set.
+fn apple_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b & 0x7F {
+            c @ 0x20..=0x7E => c as char,
+            _ => '?',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// The sectors of an Apple II disk, by track and physical sector number.
+pub struct AppleVolume {
+    sectors: HashMap<(u8, u8), Vec<u8>>,
+    pub filesystem: AppleFilesystem,
+    pub volume_name: String,
+}
+
+impl AppleVolume {
+    pub fn from_disk(disk: &mut DiskImage) -> Result<Self, Error> {
+        let sectors: HashMap<(u8, u8), Vec<u8>> = read_all_sectors(disk)
+            .into_iter()
+            .filter(|read| read.key.h == 0 && read.data.len() == APPLE_SECTOR_SIZE)
+            .filter(|read| (read.key.s as usize) < APPLE_SECTORS_PER_TRACK)
+            .filter_map(|read| Some(((u8::try_from(read.key.c).ok()?, read.key.s), read.data)))
+            .collect();
+
+        let mut volume = Self {
+            sectors,
+            filesystem: AppleFilesystem::Dos33,
+            volume_name: String::new(),
+        };
+        if let Some(vtoc) = volume.dos33_sector(DOS33_VTOC_TRACK, 0) {
+            // Sanity check the geometry fields of the VTOC.
+            if vtoc[0x35] as usize == APPLE_SECTORS_PER_TRACK && vtoc[0x36] == 0 && vtoc[0x37] == 1 {
+                volume.volume_name = format!("Volume {:03}", vtoc[6]);
+                return Ok(volume);
+            }
+        }
+        if let Some(key) = volume.block(PRODOS_VOLUME_DIR_BLOCK) {
+            // The volume directory has no previous block, and its header has storage type $F.
+            if key[0] == 0 && key[1] == 0 && key[4] >> 4 == 0xF {
+                let name_len = (key[4] & 0x0F) as usize;
+                volume.volume_name = format!("/{}", apple_string(&key[5..5 + name_len]));
+                volume.filesystem = AppleFilesystem::ProDos;
+                return Ok(volume);
+            }
+        }
+        Err(anyhow!("No DOS 3.3 VTOC or ProDOS volume directory found"))
+    }
+
+    fn dos33_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        let physical = *DOS33_PHYSICAL_SECTORS.get(sector as usize)?;
+        self.sectors.get(&(track, physical)).map(|data| data.as_slice())
+    }
+
+    fn block(&self, block: usize) -> Option<Vec<u8>> {
+        let track = u8::try_from(block / 8).ok()?;
+        let first = (block % 8) * 2;
+        let mut data = Vec::with_capacity(PRODOS_BLOCK_SIZE);
+        for logical in first..first + 2 {
+            data.extend_from_slice(self.sectors.get(&(track, PRODOS_PHYSICAL_SECTORS[logical]))?);
+        }
+        Some(data)
+    }
+
+    pub fn catalog(&self) -> Result<Vec<AppleFile>, Error> {
+        match self.filesystem {
+            AppleFilesystem::Dos33 => self.dos33_catalog(),
+            AppleFilesystem::ProDos => {
+                let mut files = Vec::new();
+                self.prodos_directory(PRODOS_VOLUME_DIR_BLOCK, "", &mut files, 0)?;
+                Ok(files)
+            }
+        }
+    }
+
+    fn dos33_catalog(&self) -> Result<Vec<AppleFile>, Error> {
+        let vtoc = self
+            .dos33_sector(DOS33_VTOC_TRACK, 0)
+            .ok_or_else(|| anyhow!("VTOC sector not found"))?;
+        let mut files = Vec::new();
+        let (mut track, mut sector) = (vtoc[1], vtoc[2]);
+        let mut links = 0;
+        while track != 0 {
+            links += 1;
+            if links > APPLE_MAX_CHAIN {
+                return Err(anyhow!("Catalog chain loops"));
+            }
+            let data = self
+                .dos33_sector(track, sector)
+                .ok_or_else(|| anyhow!("Missing catalog sector {}/{}", track, sector))?;
+            for entry in data[0x0B..].chunks_exact(DOS33_CATALOG_ENTRY_SIZE).take(DOS33_CATALOG_ENTRIES) {
+                // Unused entries have a T/S list track of zero, deleted ones $FF.
+                if entry[0] == 0 || entry[0] == 0xFF {
+                    continue;
+                }
+                files.push(AppleFile {
+                    path: apple_string(&entry[3..0x21]),
+                    file_type: dos33_type_label(entry[2]).to_string(),
+                    locked: entry[2] & 0x80 != 0,
+                    size: u16::from_le_bytes([entry[0x21], entry[0x22]]) as usize,
+                    location: FileLocation::Dos33 { ts_list: (entry[0], entry[1]) },
+                });
+            }
+            (track, sector) = (data[1], data[2]);
+        }
+        Ok(files)
+    }
+
+    fn prodos_directory(
+        &self,
+        key_block: usize,
+        prefix: &str,
+        files: &mut Vec<AppleFile>,
+        depth: usize,
+    ) -> Result<(), Error> {
+        if depth > 16 {
+            return Err(anyhow!("Directory nesting too deep"));
+        }
+        let mut block = key_block;
+        let mut links = 0;
+        // The first entry of the key block is the directory header.
+        let mut skip_header = true;
+        while block != 0 {
+            links += 1;
+            if links > APPLE_MAX_CHAIN {
+                return Err(anyhow!("Directory chain loops"));
+            }
+            let data = self.block(block).ok_or_else(|| anyhow!("Missing directory block {}", block))?;
+            for entry in data[4..].chunks_exact(PRODOS_ENTRY_SIZE).take(PRODOS_ENTRIES_PER_BLOCK) {
+                if std::mem::take(&mut skip_header) {
+                    continue;
+                }
+                let storage_type = entry[0] >> 4;
+                if storage_type == 0 {
+                    continue;
+                }
+                let name_len = (entry[0] & 0x0F) as usize;
+                let path = format!("{}/{}", prefix, apple_string(&entry[1..1 + name_len]));
+                let key_pointer = u16::from_le_bytes([entry[0x11], entry[0x12]]) as usize;
+                if storage_type == 0xD {
+                    self.prodos_directory(key_pointer, &path, files, depth + 1)?;
+                    continue;
+                }
+                files.push(AppleFile {
+                    path,
+                    file_type: prodos_type_label(entry[0x10]),
+                    // The destroy, rename and write access bits are all clear on a locked file.
+                    locked: entry[0x1E] & 0xC2 == 0,
+                    size: u16::from_le_bytes([entry[0x13], entry[0x14]]) as usize,
+                    location: FileLocation::ProDos {
+                        storage_type,
+                        key_block: key_pointer,
+                        eof: u32::from_le_bytes([entry[0x15], entry[0x16], entry[0x17], 0]) as usize,
+                    },
+                });
+            }
+            block = u16::from_le_bytes([data[2], data[3]]) as usize;
+        }
+        Ok(())
+    }
+
+    pub fn read_file(&self, file: &AppleFile) -> Result<Vec<u8>, Error> {
+        match file.location {
+            FileLocation::Dos33 { ts_list } => self.read_dos33(file, ts_list),
+            FileLocation::ProDos {
+                storage_type,
+                key_block,
+                eof,
+            } => {
+                let mut data = Vec::with_capacity(eof);
+                self.read_prodos_tree(storage_type, key_block, &mut data)?;
+                data.resize(eof, 0);
+                Ok(data)
+            }
+        }
+    }
+
+    fn read_dos33(&self, file: &AppleFile, ts_list: (u8, u8)) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        let (mut track, mut sector) = ts_list;
+        let mut links = 0;
+        while track != 0 {
+            links += 1;
+            if links > APPLE_MAX_CHAIN {
+                return Err(anyhow!("T/S list chain loops"));
+            }
+            let list = self
+                .dos33_sector(track, sector)
+                .ok_or_else(|| anyhow!("Missing T/S list sector {}/{}", track, sector))?;
+            for pair in list[0x0C..].chunks_exact(2).take(DOS33_TS_PAIRS) {
+                match (pair[0], pair[1]) {
+                    // Random access text files may have holes, read as zeros.
+                    (0, 0) => data.extend_from_slice(&[0; APPLE_SECTOR_SIZE]),
+                    (t, s) => data.extend_from_slice(
+                        self.dos33_sector(t, s)
+                            .ok_or_else(|| anyhow!("Missing data sector {}/{}", t, s))?,
+                    ),
+                }
+            }
+            (track, sector) = (list[1], list[2]);
+        }
+
+        // Trim trailing holes left by the last T/S list.
+        let used = data
+            .chunks(APPLE_SECTOR_SIZE)
+            .rposition(|chunk| chunk.iter().any(|b| *b != 0))
+            .map_or(0, |last| (last + 1) * APPLE_SECTOR_SIZE);
+        data.truncate(used);
+
+        // Binary and BASIC files record their length at the start, which we strip. Text files
+        // end at the first zero byte.
+        let header = |data: &[u8], offset: usize| {
+            data.get(offset..offset + 2).map(|len| u16::from_le_bytes([len[0], len[1]]) as usize)
+        };
+        let (start, len) = match file.file_type.as_str() {
+            "B" => (4, header(&data, 2)),
+            "A" | "I" => (2, header(&data, 0)),
+            "T" => (0, data.iter().position(|b| *b == 0)),
+            _ => (0, None),
+        };
+        let end = len.map_or(data.len(), |len| (start + len).min(data.len()));
+        Ok(data.get(start..end).unwrap_or(&[]).to_vec())
+    }
+
+    /// Append the data blocks of a seedling (1), sapling (2) or tree (3) file.
+    fn read_prodos_tree(&self, storage_type: u8, block: usize, data: &mut Vec<u8>) -> Result<(), Error> {
+        // Sparse files have zero pointers for blocks never written.
+        if block == 0 {
+            let blocks = match storage_type {
+                1 => 1,
+                2 => 256,
+                _ => 256 * 256,
+            };
+            data.resize(data.len() + blocks * PRODOS_BLOCK_SIZE, 0);
+            return Ok(());
+        }
+        let contents = self.block(block).ok_or_else(|| anyhow!("Missing block {}", block))?;
+        match storage_type {
+            1 => data.extend_from_slice(&contents),
+            2 | 3 => {
+                // Index blocks hold the low bytes of each pointer, then the high bytes. Master
+                // index blocks of tree files hold at most 128 pointers.
+                let pointers = if storage_type == 3 { 128 } else { 256 };
+                for i in 0..pointers {
+                    let pointer = u16::from_le_bytes([contents[i], contents[256 + i]]) as usize;
+                    self.read_prodos_tree(storage_type - 1, pointer, data)?;
+                }
+            }
+            _ => return Err(anyhow!("Unsupported storage type {}", storage_type)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct AppleBrowser {
+    volume: Option<AppleVolume>,
+    files: Vec<AppleFile>,
+    error: Option<String>,
+}
+
+impl AppleBrowser {
+    pub fn clear(&mut self) {
+        self.volume = None;
+        self.files.clear();
+        self.error = None;
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.volume.is_some()
+    }
+
+    pub fn load(&mut self, disk: &mut DiskImage) {
+        self.clear();
+        let volume = match AppleVolume::from_disk(disk) {
+            Ok(volume) => volume,
+            Err(e) => {
+                log::info!("No Apple II filesystem found: {}", e);
+                return;
+            }
+        };
+        match volume.catalog() {
+            Ok(files) => {
+                log::info!(
+                    "Mounted {} volume {} with {} files",
+                    volume.filesystem.label(),
+                    volume.volume_name,
+                    files.len()
+                );
+                self.files = files;
+            }
+            Err(e) => {
+                log::warn!("Error reading Apple II catalog: {}", e);
+                self.error = Some(e.to_string());
+            }
+        }
+        self.volume = Some(volume);
+    }
+
+    fn download(volume: &AppleVolume, file: &AppleFile) {
+        let name = file.path.trim_start_matches('/').replace('/', "_");
+        let filename = format!("{}.{}", name, file.extension());
+        match volume.read_file(file) {
+            Ok(data) => {
+                if let Err(e) = storage::download_bytes(&data, &filename) {
+                    log::error!("Error downloading {}: {:?}", filename, e);
+                }
+            }
+            Err(e) => {
+                log::error!("Error reading {}: {}", filename, e);
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let Some(volume) = &self.volume
+        else {
+            return;
+        };
+
+        egui::CollapsingHeader::new(format!("{} filesystem: {}", volume.filesystem.label(), volume.volume_name))
+            .id_salt("apple_browser")
+            .show(ui, |ui| {
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().warn_fg_color, error);
+                }
+
+                let size_label = match volume.filesystem {
+                    AppleFilesystem::Dos33 => "Sectors",
+                    AppleFilesystem::ProDos => "Blocks",
+                };
+                egui::Grid::new("apple_files_grid").striped(true).num_columns(3).show(ui, |ui| {
+                    ui.strong("Type");
+                    ui.strong(size_label);
+                    ui.strong("Name");
+                    ui.end_row();
+                    for file in self.files.iter() {
+                        // Locked files are starred, as in a DOS 3.3 catalog.
+                        let locked = if file.locked { "*" } else { " " };
+                        ui.monospace(format!("{}{}", locked, file.file_type));
+                        ui.label(file.size.to_string());
+                        if ui.link(&file.path).on_hover_text("Click to save").clicked() {
+                            Self::download(volume, file);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+}
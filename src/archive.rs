@@ -0,0 +1,74 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Zipped single images. `.imz` files are zipped `.img` images, and images are commonly passed
+//! around zipped on their own; neither is a Kryoflux set, so the image is taken out of the zip
+//! before loading so the loader sees the image itself.
+
+use std::io::{Cursor, Read};
+
+/// The signature of a zip archive's first local file header.
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// The largest image that will be unwrapped, to stay clear of zip bombs. Flux images of a
+/// whole disk are well under this.
+pub const MAX_UNWRAPPED_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Archive entries that aren't part of the contents: directory entries and the metadata
+/// macOS adds to the zips it makes.
+fn is_ignored(name: &str) -> bool {
+    name.ends_with('/') || name.starts_with("__MACOSX/") || name.rsplit('/').next() == Some(".DS_Store")
+}
+
+/// If `data` is a zip holding a single file, return the file's name, without any directory,
+/// and its contents. Returns None for anything else, including Kryoflux sets and zips of
+/// several images, which are left to the loader.
+pub fn unwrap_single_image(data: &[u8]) -> Option<(String, Vec<u8>)> {
+    if !data.starts_with(ZIP_MAGIC) {
+        return None;
+    }
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).ok()?;
+
+    let mut members = (0..archive.len()).filter(|&i| archive.name_for_index(i).is_some_and(|name| !is_ignored(name)));
+    let index = members.next()?;
+    if members.next().is_some() {
+        return None;
+    }
+
+    let mut file = archive.by_index(index).ok()?;
+    if file.size() > MAX_UNWRAPPED_SIZE {
+        log::warn!("Not unwrapping {}: {} bytes is larger than the limit", file.name(), file.size());
+        return None;
+    }
+    let name = file.name().rsplit('/').next().unwrap_or_default().to_string();
+    let mut contents = Vec::with_capacity(file.size() as usize);
+    if let Err(e) = file.by_ref().take(MAX_UNWRAPPED_SIZE).read_to_end(&mut contents) {
+        log::error!("Error unwrapping {} from zip: {}", name, e);
+        return None;
+    }
+    Some((name, contents))
+}
@@ -0,0 +1,238 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Assets fetched at startup rather than built into the wasm, for deployments that need to keep
+//! it small. The asset manifest lists each asset's URL and SHA-256:
+//!
+//! ```json
+//! { "assets": [{ "name": "logo", "url": "https://cdn.example.com/logo.png", "sha256": "9f86d0..." }] }
+//! ```
+//!
+//! The manifest is read from `assets/asset_manifest.json`, or from the URL in a
+//! `<meta name="ffweb-asset-manifest" content="...">` element in index.html. Relative asset
+//! URLs are resolved against the manifest's. Assets that fail to download or don't match
+//! their hash are not used. With the `embedded-assets` feature, whatever uses them falls back
+//! to a copy built into the app; without it, the app goes without them, and builds that need
+//! to stay small can rely on the default manifest, which lists the app's own assets.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use sha2::Digest;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Response;
+
+use crate::util;
+
+/// The copy of the image asset `name` built into the app, if there is one.
+fn embedded_image(name: &str) -> Option<egui::ImageSource<'static>> {
+    match name {
+        #[cfg(feature = "embedded-assets")]
+        "logo" => Some(egui::include_image!("../assets/fluxfox_logo.png")),
+        _ => None,
+    }
+}
+
+pub const DEFAULT_MANIFEST_URL: &str = "./assets/asset_manifest.json";
+const MANIFEST_META: &str = "ffweb-asset-manifest";
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AssetEntry {
+    pub name: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct AssetManifest {
+    #[serde(default)]
+    pub assets: Vec<AssetEntry>,
+}
+
+pub enum AssetState {
+    Loading,
+    Ready(Arc<[u8]>),
+    Failed(String),
+}
+
+#[derive(Default)]
+pub struct Assets {
+    states: Rc<RefCell<BTreeMap<String, AssetState>>>,
+    started: bool,
+}
+
+impl Assets {
+    /// Fetch the manifest and every asset in it, in the background.
+    pub fn start(&mut self, ctx: &egui::Context) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let (states, ctx) = (self.states.clone(), ctx.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let manifest_url = match manifest_url() {
+                Ok(url) => url,
+                Err(e) => {
+                    log::warn!("Couldn't resolve the asset manifest URL: {:?}", e);
+                    return;
+                }
+            };
+            let manifest = match fetch_manifest(&manifest_url).await {
+                Ok(Some(manifest)) => manifest,
+                Ok(None) => {
+                    log::debug!("No asset manifest at {}; using built-in assets", manifest_url);
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Couldn't load the asset manifest from {}: {:?}", manifest_url, e);
+                    return;
+                }
+            };
+
+            for entry in &manifest.assets {
+                states.borrow_mut().insert(entry.name.clone(), AssetState::Loading);
+            }
+            for entry in manifest.assets {
+                let state = match fetch_asset(&entry, &manifest_url).await {
+                    Ok(bytes) => {
+                        log::debug!("Loaded asset {} ({} bytes)", entry.name, bytes.len());
+                        AssetState::Ready(bytes.into())
+                    }
+                    Err(e) => {
+                        log::warn!("Couldn't load asset {} from {}: {:?}", entry.name, entry.url, e);
+                        AssetState::Failed(e.to_string())
+                    }
+                };
+                states.borrow_mut().insert(entry.name, state);
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// The asset's data, if it was downloaded and verified.
+    pub fn get(&self, name: &str) -> Option<Arc<[u8]>> {
+        match self.states.borrow().get(name) {
+            Some(AssetState::Ready(bytes)) => Some(bytes.clone()),
+            _ => None,
+        }
+    }
+
+    /// The image asset `name`, or the copy built into the app if it isn't available and there
+    /// is one.
+    pub fn image(&self, name: &str) -> Option<egui::ImageSource<'static>> {
+        match self.get(name) {
+            Some(bytes) => Some(egui::ImageSource::Bytes {
+                uri: format!("bytes://asset/{}", name).into(),
+                bytes: egui::load::Bytes::Shared(bytes),
+            }),
+            None => embedded_image(name),
+        }
+    }
+
+    /// A warning listing the assets that couldn't be loaded, if any.
+    pub fn show_status(&self, ui: &mut egui::Ui) {
+        let states = self.states.borrow();
+        let failed = states
+            .iter()
+            .filter_map(|(name, state)| match state {
+                AssetState::Failed(reason) => Some(format!("{}: {}", name, reason)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if failed.is_empty() {
+            return;
+        }
+        ui.colored_label(ui.visuals().warn_fg_color, format!("{} assets unavailable", failed.len()))
+            .on_hover_text(format!("Using the built-in copies instead.\n\n{}", failed.join("\n")));
+    }
+}
+
+/// The manifest URL, made absolute against the document.
+fn manifest_url() -> Result<String, JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| JsValue::from_str("No document"))?;
+    let configured = document
+        .query_selector(&format!("meta[name=\"{}\"]", MANIFEST_META))?
+        .and_then(|meta| meta.get_attribute("content"))
+        .filter(|content| !content.trim().is_empty())
+        .unwrap_or(DEFAULT_MANIFEST_URL.to_string());
+    let base = document.base_uri()?.unwrap_or_default();
+    Ok(web_sys::Url::new_with_base(configured.trim(), &base)?.href())
+}
+
+async fn fetch(url: &str) -> anyhow::Result<Response> {
+    let window = web_sys::window().ok_or_else(|| anyhow!("No window"))?;
+    let response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    response.dyn_into::<Response>().map_err(|_| anyhow!("Not a response"))
+}
+
+async fn response_bytes(response: &Response) -> anyhow::Result<Vec<u8>> {
+    let buffer = JsFuture::from(response.array_buffer().map_err(|e| anyhow!("{:?}", e))?)
+        .await
+        .map_err(|e| anyhow!("{:?}", e))?;
+    Ok(web_sys::js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Fetch the manifest. A missing manifest isn't an error; it just means there are no assets.
+async fn fetch_manifest(url: &str) -> anyhow::Result<Option<AssetManifest>> {
+    let response = fetch(url).await?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    if !response.ok() {
+        bail!("HTTP {}", response.status());
+    }
+    Ok(Some(serde_json::from_slice(&response_bytes(&response).await?)?))
+}
+
+/// Fetch the contents of `url`, failing on any status other than success.
+pub(crate) async fn fetch_bytes(url: &str) -> anyhow::Result<Vec<u8>> {
+    let response = fetch(url).await?;
+    if !response.ok() {
+        bail!("HTTP {}", response.status());
+    }
+    response_bytes(&response).await
+}
+
+async fn fetch_asset(entry: &AssetEntry, manifest_url: &str) -> anyhow::Result<Vec<u8>> {
+    let url = web_sys::Url::new_with_base(&entry.url, manifest_url)
+        .map_err(|e| anyhow!("Invalid URL: {:?}", e))?
+        .href();
+    let bytes = fetch_bytes(&url).await?;
+    let hash = util::hex_string(&sha2::Sha256::digest(&bytes));
+    if !hash.eq_ignore_ascii_case(entry.sha256.trim()) {
+        bail!("SHA-256 mismatch: expected {}, got {}", entry.sha256, hash);
+    }
+    Ok(bytes)
+}
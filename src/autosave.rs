@@ -0,0 +1,210 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Autosave of the workspace to browser storage, so an accidental reload doesn't lose it.
+//!
+//! Only lightweight state is saved: which image was open, where the user was on it, their
+//! annotations and bookmarks, and the image's processing history. The image itself isn't kept,
+//! so after a reload the user is asked to drop it again; once a file with the same hash is
+//! loaded, the rest of the workspace is put back.
+//!
+//! The workspace is versioned and migrated through the persist module like the settings.
+
+use fluxfox::DiskCh;
+
+use crate::analysis::SectorKey;
+use crate::annotations::Annotation;
+use crate::bookmarks::Bookmark;
+use crate::history::History;
+use crate::persist;
+
+/// The storage key the workspace is saved under. Workspaces from before it was versioned were
+/// saved as RON under [LEGACY_WORKSPACE_KEY], and are read from there if this key is empty.
+pub const WORKSPACE_KEY: &str = "ffweb_workspace_state";
+const LEGACY_WORKSPACE_KEY: &str = "ffweb_workspace";
+/// Steps bringing a saved [Workspace] up to date; entry `n` upgrades version `n` to `n + 1`.
+const WORKSPACE_MIGRATIONS: &[persist::Migration] = &[migrate_workspace_v0, migrate_workspace_v1];
+pub const WORKSPACE_VERSION: u32 = WORKSPACE_MIGRATIONS.len() as u32;
+/// How often eframe saves the app state, in seconds.
+pub const AUTOSAVE_INTERVAL_SECS: u64 = 10;
+
+/// Version 0 is never written; the first workspaces were already saved as version 1.
+fn migrate_workspace_v0(_workspace: &mut serde_json::Map<String, serde_json::Value>) {}
+
+/// Version 2 added the processing history, which older workspaces start without.
+fn migrate_workspace_v1(workspace: &mut serde_json::Map<String, serde_json::Value>) {
+    workspace.entry("history").or_insert_with(|| serde_json::json!([]));
+}
+
+/// Whether the workspace is saved at all, persisted between sessions.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct AutosaveSettings {
+    pub enabled: bool,
+}
+
+impl Default for AutosaveSettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl AutosaveSettings {
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Remember the open image between visits")
+            .on_hover_text("Saves the image's name and hash, your place on it, annotations, bookmarks and processing \
+                            history to browser storage, along with unsaved sector edits so they can be recovered \
+                            after a crash. The image itself is never saved.");
+    }
+}
+
+/// A position on the disk, as saved.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SavedPosition {
+    pub c: u16,
+    pub h: u8,
+    pub bit_offset: usize,
+    pub sector: Option<SectorKey>,
+}
+
+impl SavedPosition {
+    pub fn ch(&self) -> DiskCh {
+        DiskCh::new(self.c, self.h)
+    }
+}
+
+impl From<&Bookmark> for SavedPosition {
+    fn from(bookmark: &Bookmark) -> Self {
+        Self {
+            c: bookmark.ch.c(),
+            h: bookmark.ch.h(),
+            bit_offset: bookmark.bit_offset,
+            sector: bookmark.sector,
+        }
+    }
+}
+
+impl From<&SavedPosition> for Bookmark {
+    fn from(position: &SavedPosition) -> Self {
+        Self {
+            ch: position.ch(),
+            bit_offset: position.bit_offset,
+            sector: position.sector,
+        }
+    }
+}
+
+/// The saved workspace for one image. One with no image hash stands for no workspace, as
+/// storage can't remove a key once it's written.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Workspace {
+    pub version: u32,
+    pub image_name: String,
+    pub image_sha1: String,
+    pub image_len: usize,
+    pub side: usize,
+    pub selection: Option<SavedPosition>,
+    pub annotations: Vec<Annotation>,
+    pub bookmarks: Vec<SavedPosition>,
+    pub history: History,
+}
+
+impl Workspace {
+    pub fn new(image_name: &str, image_sha1: &str, image_len: usize) -> Self {
+        Self {
+            version: WORKSPACE_VERSION,
+            image_name: image_name.to_string(),
+            image_sha1: image_sha1.to_string(),
+            image_len,
+            side: 0,
+            selection: None,
+            annotations: Vec::new(),
+            bookmarks: Vec::new(),
+            history: History::default(),
+        }
+    }
+
+    /// The workspace saved when there's nothing to restore.
+    pub fn empty() -> Self {
+        Self::new("", "", 0)
+    }
+
+    /// Save the workspace, migrated forward on load by [PendingRestore::load].
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        persist::save(storage, WORKSPACE_KEY, self);
+    }
+
+    /// Whether this workspace was saved for the image with the given hash.
+    pub fn matches(&self, sha1: &str) -> bool {
+        self.image_sha1.eq_ignore_ascii_case(sha1)
+    }
+}
+
+/// A workspace restored from storage, waiting for its image to be dropped again.
+pub struct PendingRestore {
+    pub workspace: Workspace,
+}
+
+impl PendingRestore {
+    /// Take the saved workspace out of storage, if there is one worth restoring. Saved state
+    /// that couldn't be carried over is added to `backups`, to be written by
+    /// [persist::save_backups].
+    pub fn load(storage: &dyn eframe::Storage, backups: &mut Vec<(String, String)>) -> Option<Self> {
+        let loaded = persist::load::<Workspace>(storage, WORKSPACE_KEY, LEGACY_WORKSPACE_KEY, WORKSPACE_MIGRATIONS)?;
+        for warning in &loaded.warnings {
+            log::warn!("Saved workspace: {}", warning);
+        }
+        backups.extend(loaded.backups);
+        let workspace = loaded.state;
+        (!workspace.image_sha1.is_empty()).then_some(Self { workspace })
+    }
+
+    /// Show the prompt to drop the image again. Returns true if the user dismissed it.
+    pub fn show(&self, ui: &mut egui::Ui) -> bool {
+        let mut dismissed = false;
+        let workspace = &self.workspace;
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Your last session had {} ({} bytes) open. Drop it again to restore its workspace.",
+                    workspace.image_name, workspace.image_len
+                ))
+                .on_hover_text(format!(
+                    "SHA-1 {}\n{} annotations, {} bookmarks",
+                    workspace.image_sha1,
+                    workspace.annotations.len(),
+                    workspace.bookmarks.len()
+                ));
+                if ui.small_button("Dismiss").clicked() {
+                    dismissed = true;
+                }
+            });
+        });
+        dismissed
+    }
+}
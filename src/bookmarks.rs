@@ -0,0 +1,170 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use egui::{Key, KeyboardShortcut, Modifiers};
+use fluxfox::DiskCh;
+
+use crate::analysis::SectorKey;
+
+pub const BOOKMARK_TOGGLE: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::B);
+pub const BOOKMARK_NEXT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::F2);
+pub const BOOKMARK_PREV: KeyboardShortcut = KeyboardShortcut::new(Modifiers::SHIFT, Key::F2);
+
+/// A position on the disk the user wants to return to: a sector, or a bit offset on a track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    pub ch: DiskCh,
+    pub bit_offset: usize,
+    pub sector: Option<SectorKey>,
+}
+
+impl std::fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.sector {
+            Some(key) => write!(f, "Sector {}", key),
+            None => write!(f, "Track {} bit {}", self.ch, self.bit_offset),
+        }
+    }
+}
+
+pub enum BookmarkAction {
+    /// Add or remove a bookmark at the current selection.
+    Toggle,
+    /// Jump to the bookmark with the specified index.
+    Jump(usize),
+}
+
+#[derive(Default)]
+pub struct Bookmarks {
+    pub items: Vec<Bookmark>,
+    cursor: Option<usize>,
+}
+
+impl Bookmarks {
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.cursor = None;
+    }
+
+    /// Add `bookmark`, or remove it if it already exists.
+    pub fn toggle(&mut self, bookmark: Bookmark) {
+        let existing = self.items.iter().position(|item| {
+            item.ch == bookmark.ch
+                && match (&item.sector, &bookmark.sector) {
+                    (Some(a), Some(b)) => a == b,
+                    (None, None) => item.bit_offset == bookmark.bit_offset,
+                    _ => false,
+                }
+        });
+        match existing {
+            Some(i) => {
+                self.items.remove(i);
+                self.cursor = None;
+            }
+            None => {
+                self.items.push(bookmark);
+                self.cursor = Some(self.items.len() - 1);
+            }
+        }
+    }
+
+    fn step(&mut self, forward: bool) -> Option<usize> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let len = self.items.len();
+        let next = match (self.cursor, forward) {
+            (Some(i), true) => (i + 1) % len,
+            (Some(i), false) => (i + len - 1) % len,
+            (None, true) => 0,
+            (None, false) => len - 1,
+        };
+        self.cursor = Some(next);
+        Some(next)
+    }
+
+    /// Check for bookmark keyboard shortcuts. Shortcuts are ignored while a text field has focus.
+    pub fn handle_shortcuts(&mut self, ctx: &egui::Context) -> Option<BookmarkAction> {
+        if ctx.wants_keyboard_input() {
+            return None;
+        }
+        let (toggle, next, prev) = ctx.input_mut(|i| {
+            // Check Shift+F2 first, as consuming F2 would otherwise also match it.
+            let prev = i.consume_shortcut(&BOOKMARK_PREV);
+            (
+                i.consume_shortcut(&BOOKMARK_TOGGLE),
+                i.consume_shortcut(&BOOKMARK_NEXT),
+                prev,
+            )
+        });
+
+        if toggle {
+            Some(BookmarkAction::Toggle)
+        }
+        else if next {
+            self.step(true).map(BookmarkAction::Jump)
+        }
+        else if prev {
+            self.step(false).map(BookmarkAction::Jump)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<BookmarkAction> {
+        let mut action = None;
+
+        egui::CollapsingHeader::new(format!("Bookmarks ({})", self.items.len()))
+            .id_salt("bookmarks_list")
+            .show(ui, |ui| {
+                ui.label("B: toggle bookmark at selection, F2 / Shift+F2: next / previous bookmark");
+                let mut remove = None;
+                for (i, bookmark) in self.items.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .selectable_label(self.cursor == Some(i), bookmark.to_string())
+                            .clicked()
+                        {
+                            action = Some(BookmarkAction::Jump(i));
+                        }
+                        if ui.small_button("🗑").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    self.items.remove(i);
+                    self.cursor = None;
+                }
+            });
+
+        if let Some(BookmarkAction::Jump(i)) = action {
+            self.cursor = Some(i);
+        }
+        action
+    }
+}
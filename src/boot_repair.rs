@@ -0,0 +1,229 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Replacing a damaged or infected boot sector with a clean one.
+//!
+//! The new boot sector keeps the disk's BPB if it has a valid one, or gets the standard BPB
+//! for its geometry with the media descriptor from the FAT. Its code doesn't try to load DOS:
+//! it prints the usual non-system disk message and reboots on a key press, as the boot sector
+//! of a disk formatted without /S does. It's written over the old one in place, which the undo
+//! stack keeps.
+
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::analysis::geometry::{LayoutSummary, StandardGeometry};
+use crate::analysis::{read_sector, SectorKey};
+use crate::fat::BiosParameterBlock;
+use crate::virus;
+
+pub const BOOT_SECTOR_LEN: usize = 512;
+const BPB_START: usize = 0x0B;
+/// The end of the DOS 2.0 BPB, up to and including the head count.
+const BPB_END: usize = 0x1C;
+/// The end of the extended BPB, with the volume ID, label and filesystem type.
+const EXTENDED_BPB_END: usize = 0x3E;
+const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+const OEM_NAME: &[u8; 8] = b"FFWEB1.0";
+const MESSAGE_OFFSET: usize = 0x60;
+const MESSAGE: &[u8] = b"\r\nNon-system disk or disk error\r\nReplace and press any key when ready\r\n\0";
+
+/// Real mode code at 0x3E, after an extended BPB. It prints the message at MESSAGE_OFFSET
+/// through the BIOS, waits for a key and reboots.
+const BOOT_CODE: [u8; 0x22] = [
+    0xFA, //             cli
+    0x31, 0xC0, //       xor ax, ax
+    0x8E, 0xD8, //       mov ds, ax
+    0x8E, 0xD0, //       mov ss, ax
+    0xBC, 0x00, 0x7C, // mov sp, 7C00h
+    0xFB, //             sti
+    0xBE, 0x60, 0x7C, // mov si, 7C60h
+    0xAC, //             print: lodsb
+    0x08, 0xC0, //       or al, al
+    0x74, 0x09, //       jz wait
+    0xB4, 0x0E, //       mov ah, 0Eh
+    0xBB, 0x07, 0x00, // mov bx, 0007h
+    0xCD, 0x10, //       int 10h
+    0xEB, 0xF2, //       jmp print
+    0x31, 0xC0, //       wait: xor ax, ax
+    0xCD, 0x16, //       int 16h
+    0xCD, 0x19, //       int 19h
+];
+
+/// The BPB fields DOS gives each standard floppy format: sectors per cluster, root directory
+/// entries, media descriptor and sectors per FAT.
+fn standard_bpb(geometry: &StandardGeometry) -> Option<(u8, u16, u8, u16)> {
+    match (geometry.cylinders, geometry.heads, geometry.sectors) {
+        (40, 1, 8) => Some((1, 64, 0xFE, 1)),
+        (40, 1, 9) => Some((1, 64, 0xFC, 2)),
+        (40, 2, 8) => Some((2, 112, 0xFF, 1)),
+        (40, 2, 9) => Some((2, 112, 0xFD, 2)),
+        (80, 2, 9) => Some((2, 112, 0xF9, 3)),
+        (80, 2, 15) => Some((1, 224, 0xF9, 7)),
+        (80, 2, 18) => Some((1, 224, 0xF0, 9)),
+        (80, 2, 36) => Some((2, 240, 0xF0, 9)),
+        _ => None,
+    }
+}
+
+/// Where the BPB of the new boot sector comes from.
+#[derive(Clone, Debug)]
+pub enum BpbSource {
+    Kept,
+    /// The standard BPB for the geometry, with the media descriptor from the FAT if it had one.
+    Standard { media_from_fat: bool },
+}
+
+pub struct BootRepair {
+    pub geometry: StandardGeometry,
+    pub problems: Vec<String>,
+    pub source: BpbSource,
+    /// The replacement boot sector.
+    pub boot: Vec<u8>,
+    confirmed: bool,
+}
+
+impl BootRepair {
+    /// Check the boot sector of a DOS disk. Returns None if it's sound, or the disk isn't a DOS
+    /// disk of a standard geometry.
+    pub fn from_disk(disk: &mut DiskImage) -> Option<Self> {
+        let geometry = *LayoutSummary::from_disk(disk).standard_geometry()?;
+        let boot = read_logical(disk, 1);
+        let fat = read_logical(disk, 2);
+        // The FAT starts with the media descriptor and two bytes of 0xFF.
+        let fat_media = fat.as_ref().and_then(|fat| {
+            (fat.len() >= 3 && fat[0] >= 0xF0 && fat[1] == 0xFF && fat[2] == 0xFF).then_some(fat[0])
+        });
+
+        let mut problems = Vec::new();
+        let bpb = match &boot {
+            Some((data, crc_error)) => {
+                if *crc_error {
+                    problems.push("The boot sector has a data CRC error.".to_string());
+                }
+                if data.len() < BOOT_SECTOR_LEN || data[510..512] != [0x55, 0xAA] {
+                    problems.push("The boot sector has no 55AA signature.".to_string());
+                }
+                for detection in virus::scan_sector(SectorKey::new(DiskCh::new(0, 0), 1), data) {
+                    problems.push(format!("The boot sector is infected with {}.", detection.name));
+                }
+                match BiosParameterBlock::parse(data) {
+                    Ok(bpb) => Some(bpb),
+                    Err(e) => {
+                        problems.push(format!("The BPB is invalid: {}.", e));
+                        None
+                    }
+                }
+            }
+            None => {
+                problems.push("The boot sector can't be read.".to_string());
+                None
+            }
+        };
+        // Without a BPB or a FAT there's nothing to show this was ever a DOS disk.
+        if problems.is_empty() || (bpb.is_none() && fat_media.is_none()) {
+            return None;
+        }
+
+        let mut sector = vec![0; BOOT_SECTOR_LEN];
+        sector[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        sector[3..BPB_START].copy_from_slice(OEM_NAME);
+        let source = match (&boot, bpb) {
+            (Some((data, _)), Some(_)) => {
+                let end = match data[0x26] {
+                    EXTENDED_BOOT_SIGNATURE => EXTENDED_BPB_END,
+                    _ => BPB_END,
+                };
+                sector[BPB_START..end].copy_from_slice(&data[BPB_START..end]);
+                BpbSource::Kept
+            }
+            _ => {
+                let (cluster, root_entries, media, fat_sectors) = standard_bpb(&geometry)?;
+                let total = geometry.cylinders * geometry.heads as u16 * geometry.sectors as u16;
+                sector[0x0B..0x0D].copy_from_slice(&(geometry.sector_size as u16).to_le_bytes());
+                sector[0x0D] = cluster;
+                sector[0x0E..0x10].copy_from_slice(&1u16.to_le_bytes());
+                sector[0x10] = 2;
+                sector[0x11..0x13].copy_from_slice(&root_entries.to_le_bytes());
+                sector[0x13..0x15].copy_from_slice(&total.to_le_bytes());
+                sector[0x15] = fat_media.unwrap_or(media);
+                sector[0x16..0x18].copy_from_slice(&fat_sectors.to_le_bytes());
+                sector[0x18..0x1A].copy_from_slice(&(geometry.sectors as u16).to_le_bytes());
+                sector[0x1A..0x1C].copy_from_slice(&(geometry.heads as u16).to_le_bytes());
+                BpbSource::Standard {
+                    media_from_fat: fat_media.is_some(),
+                }
+            }
+        };
+        sector[EXTENDED_BPB_END..EXTENDED_BPB_END + BOOT_CODE.len()].copy_from_slice(&BOOT_CODE);
+        sector[MESSAGE_OFFSET..MESSAGE_OFFSET + MESSAGE.len()].copy_from_slice(MESSAGE);
+        sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        Some(Self {
+            geometry,
+            problems,
+            source,
+            boot: sector,
+            confirmed: false,
+        })
+    }
+
+    /// The sector write that replaces the boot sector.
+    pub fn write(&self) -> (SectorKey, Vec<u8>) {
+        (SectorKey::new(DiskCh::new(0, 0), 1), self.boot.clone())
+    }
+
+    /// Show the offer. Returns true if the user confirmed the replacement.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut replace = false;
+        egui::CollapsingHeader::new("Boot sector: damaged").id_salt("boot_repair").default_open(true).show(ui, |ui| {
+            for problem in &self.problems {
+                ui.colored_label(ui.visuals().warn_fg_color, problem);
+            }
+            ui.label(match self.source {
+                BpbSource::Kept => "A clean boot sector can replace it, keeping its BPB.".to_string(),
+                BpbSource::Standard { media_from_fat } => format!(
+                    "A clean boot sector can replace it, with the standard BPB for {}{}.",
+                    self.geometry.name,
+                    if media_from_fat { " and the media descriptor from the FAT" } else { "" }
+                ),
+            });
+            ui.weak("The new boot sector doesn't load DOS. Only sector 1 of track 0 is written.");
+            ui.checkbox(&mut self.confirmed, "Replace the boot code on this image");
+            if ui.add_enabled(self.confirmed, egui::Button::new("Replace boot sector")).clicked() {
+                replace = true;
+            }
+        });
+        replace
+    }
+}
+
+/// Read the data of a logical sector on the first track, and whether its CRC was bad.
+fn read_logical(disk: &mut DiskImage, s: u8) -> Option<(Vec<u8>, bool)> {
+    let ch = DiskCh::new(0, 0);
+    let chsn = disk.get_sector_map().first()?.first()?.iter().find(|entry| entry.chsn.s() == s)?.chsn;
+    read_sector(disk, ch, chsn).map(|read| (read.data, read.data_crc_error))
+}
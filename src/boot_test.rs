@@ -0,0 +1,159 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! The in-browser boot test: an overlay running the v86 emulator on the loaded image.
+//!
+//! v86 is served from the app's own origin, from the files vendor_v86.sh fetches into
+//! assets/v86. The emulator page runs in a frame sandboxed with only allow-scripts, so neither
+//! it nor v86 has the app's origin or storage. The page can't fetch anything from the app's
+//! origin either, so the app fetches the v86 files and posts them to it with the image.
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::js_sys::{Object, Reflect, Uint8Array};
+use web_sys::{HtmlElement, HtmlIFrameElement};
+
+use crate::assets;
+
+/// Page hosting the v86 emulator, relative to the document, so it's found wherever the app is
+/// deployed. It waits for a message containing a floppy image and the v86 files, and boots it.
+pub const BOOT_TEST_PAGE: &str = "./boot_test.html";
+pub const BOOT_TEST_MESSAGE: &str = "ffweb-boot-floppy";
+/// The emulator's files, by their names in the message.
+const V86_FILES: [(&str, &str); 4] = [
+    ("libv86", "./v86/libv86.js"),
+    ("wasm", "./v86/v86.wasm"),
+    ("bios", "./v86/seabios.bin"),
+    ("vga_bios", "./v86/vgabios.bin"),
+];
+/// Scripts only: no same-origin access, forms, popups or top-level navigation.
+const IFRAME_SANDBOX: &str = "allow-scripts";
+const OVERLAY_ID: &str = "ffweb_boot_test";
+
+const OVERLAY_STYLE: &str = "position: fixed; inset: 0; z-index: 1000; background: rgba(0, 0, 0, 0.8); \
+                             display: flex; flex-direction: column; align-items: center; justify-content: center;";
+const IFRAME_STYLE: &str = "width: 80vw; height: 80vh; border: 1px solid #808080; background: black;";
+const BUTTON_STYLE: &str = "margin: 8px; padding: 4px 16px;";
+
+fn set(object: &Object, key: &str, value: &JsValue) -> Result<(), JsValue> {
+    Reflect::set(object, &JsValue::from_str(key), value).map(|_| ())
+}
+
+/// Fetch the v86 files into an object keyed by their names in the message.
+async fn fetch_v86() -> anyhow::Result<Object> {
+    let files = Object::new();
+    for (key, url) in V86_FILES {
+        let bytes = assets::fetch_bytes(url).await.map_err(|e| anyhow::anyhow!("{}: {}", url, e))?;
+        set(&files, key, &Uint8Array::from(bytes.as_slice())).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    }
+    Ok(files)
+}
+
+/// Build the message for the emulator page: the image and v86, or why v86 isn't available.
+async fn boot_message(name: String, image: Vec<u8>) -> Result<Object, JsValue> {
+    let message = Object::new();
+    set(&message, "type", &JsValue::from_str(BOOT_TEST_MESSAGE))?;
+    set(&message, "name", &JsValue::from_str(&name))?;
+    match fetch_v86().await {
+        Ok(files) => {
+            set(&message, "v86", &files)?;
+            set(&message, "data", &Uint8Array::from(image.as_slice()))?;
+        }
+        Err(e) => {
+            log::error!("boot_test: Couldn't load v86: {:?}", e);
+            let error = format!("v86 isn't available on this server ({}). See assets/v86/README.md.", e);
+            set(&message, "error", &JsValue::from_str(&error))?;
+        }
+    }
+    Ok(message)
+}
+
+/// Open a modal overlay with the emulator page in a sandboxed iframe, and hand it `image` (a
+/// raw sector image) to boot from drive A: once it has loaded.
+pub fn open(name: &str, image: Vec<u8>) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window"))?;
+    let document = window.document().ok_or_else(|| JsValue::from_str("No document"))?;
+
+    // Only allow one emulator at a time.
+    if let Some(existing) = document.get_element_by_id(OVERLAY_ID) {
+        existing.remove();
+    }
+
+    let overlay: HtmlElement = document.create_element("div")?.unchecked_into();
+    overlay.set_id(OVERLAY_ID);
+    overlay.style().set_css_text(OVERLAY_STYLE);
+
+    let iframe: HtmlIFrameElement = document.create_element("iframe")?.unchecked_into();
+    iframe.style().set_css_text(IFRAME_STYLE);
+    iframe.set_attribute("sandbox", IFRAME_SANDBOX)?;
+    iframe.set_src(BOOT_TEST_PAGE);
+
+    let close: HtmlElement = document.create_element("button")?.unchecked_into();
+    close.set_inner_text("Close boot test");
+    close.style().set_css_text(BUTTON_STYLE);
+    let close_overlay = overlay.clone();
+    let on_close = Closure::once_into_js(move || {
+        close_overlay.remove();
+    });
+    close.set_onclick(Some(on_close.unchecked_ref()));
+
+    // Post the image to the emulator page once it has loaded. The sandboxed page has an opaque
+    // origin, which can't be named as the target, so the message is posted to its window with
+    // any origin.
+    let target = iframe.clone();
+    let name = name.to_string();
+    let on_load = Closure::once_into_js(move || {
+        wasm_bindgen_futures::spawn_local(async move {
+            let message = match boot_message(name, image).await {
+                Ok(message) => message,
+                Err(e) => {
+                    log::error!("boot_test::open(): Failed to build the boot message: {:?}", e);
+                    return;
+                }
+            };
+            match target.content_window() {
+                Some(content) => {
+                    if let Err(e) = content.post_message(&message, "*") {
+                        log::error!("boot_test::open(): Failed to post image to emulator: {:?}", e);
+                    }
+                }
+                None => {
+                    log::error!("boot_test::open(): Emulator frame has no window");
+                }
+            }
+        });
+    });
+    iframe.set_onload(Some(on_load.unchecked_ref()));
+
+    overlay.append_child(&iframe)?;
+    overlay.append_child(&close)?;
+    document
+        .body()
+        .ok_or_else(|| JsValue::from_str("No document body"))?
+        .append_child(&overlay)?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! "Copy as image" for panels. The panel's area of the canvas is copied once the frame it was
+//! requested in has been painted: futures spawned during update() first run in a microtask
+//! after eframe's animation frame callback returns, by which point the frame has been drawn but
+//! not yet presented, so the drawing buffer still holds it.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::Promise;
+
+use crate::gl_context::CANVAS_ID;
+
+#[wasm_bindgen(module = "/assets/capture.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = copyCanvasRegion)]
+    fn copy_canvas_region(canvas_id: &str, x: u32, y: u32, width: u32, height: u32) -> Promise;
+}
+
+/// Copies of panels to the clipboard, requested while drawing and started at the end of the frame.
+#[derive(Default)]
+pub struct PanelCapture {
+    pending: Option<(String, egui::Rect, egui::Rect)>,
+    finished: Rc<RefCell<Vec<(String, Result<(), String>)>>>,
+}
+
+impl PanelCapture {
+    /// Copy `rect` of the screen, where the panel `name` was drawn this frame, as far as it was
+    /// visible within `clip`, the clip rect of the ui it was drawn in. Parts of a panel
+    /// scrolled out of view weren't drawn, and the canvas there shows whatever is around it.
+    pub fn request(&mut self, name: &str, rect: egui::Rect, clip: egui::Rect) {
+        self.pending = Some((name.to_string(), rect, clip));
+    }
+
+    /// Start the copy requested this frame, if any. Call after everything has been drawn.
+    pub fn end_frame(&mut self, ctx: &egui::Context) {
+        let Some((name, rect, clip)) = self.pending.take()
+        else {
+            return;
+        };
+        let scale = ctx.pixels_per_point();
+        let screen = ctx.screen_rect();
+        let rect = rect.intersect(clip).intersect(screen);
+        if rect.width() < 1.0 || rect.height() < 1.0 {
+            return;
+        }
+        let (x, y) = ((rect.min.x * scale).round() as u32, (rect.min.y * scale).round() as u32);
+        let (width, height) = ((rect.width() * scale).round() as u32, (rect.height() * scale).round() as u32);
+
+        let (finished, ctx) = (self.finished.clone(), ctx.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = JsFuture::from(copy_canvas_region(CANVAS_ID, x, y, width, height))
+                .await
+                .map(|_| ())
+                .map_err(|e: JsValue| {
+                    e.as_string()
+                        .or_else(|| web_sys::js_sys::Error::from(e).message().as_string())
+                        .unwrap_or_else(|| "unknown error".to_string())
+                });
+            log::debug!("Copied {} ({}x{} pixels) to the clipboard: {:?}", name, width, height, result);
+            finished.borrow_mut().push((name, result));
+            ctx.request_repaint();
+        });
+    }
+
+    /// Copies which finished since the last call, by panel name.
+    pub fn take_finished(&self) -> Vec<(String, Result<(), String>)> {
+        std::mem::take(&mut *self.finished.borrow_mut())
+    }
+}
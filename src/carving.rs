@@ -0,0 +1,301 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Carving files out of raw sector data by their signatures, for disks whose filesystem is
+//! damaged or isn't FAT.
+//!
+//! Sector data is laid end to end in cylinder, head and sector order, the order a DOS disk's
+//! logical sectors are stored in, and searched for known file headers. Where the format records
+//! its own length, or has a recognizable trailer, the file's length is estimated from that;
+//! otherwise it runs to the next hit.
+
+use fluxfox::DiskImage;
+
+use crate::analysis::{read_all_sectors, SectorKey};
+use crate::storage;
+
+/// Length assumed for a hit whose end can't be found.
+pub const CARVE_DEFAULT_LEN: usize = 64 * 1024;
+pub const CARVE_MAX_HEIGHT: f32 = 240.0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileKind {
+    Exe,
+    Zip,
+    Gif,
+    Png,
+    Jpeg,
+    Bmp,
+    Arj,
+    Lzh,
+}
+
+impl FileKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileKind::Exe => "DOS executable",
+            FileKind::Zip => "ZIP archive",
+            FileKind::Gif => "GIF image",
+            FileKind::Png => "PNG image",
+            FileKind::Jpeg => "JPEG image",
+            FileKind::Bmp => "BMP image",
+            FileKind::Arj => "ARJ archive",
+            FileKind::Lzh => "LZH archive",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileKind::Exe => "exe",
+            FileKind::Zip => "zip",
+            FileKind::Gif => "gif",
+            FileKind::Png => "png",
+            FileKind::Jpeg => "jpg",
+            FileKind::Bmp => "bmp",
+            FileKind::Arj => "arj",
+            FileKind::Lzh => "lzh",
+        }
+    }
+
+    /// Whether a file of this kind starts at `data[0]`.
+    fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            // Check the header fields too, as "MZ" alone is common in text.
+            FileKind::Exe => data.starts_with(b"MZ") && data.len() >= 28 && u16_at(data, 4).is_some_and(|pages| pages > 0),
+            FileKind::Zip => data.starts_with(b"PK\x03\x04"),
+            FileKind::Gif => data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a"),
+            FileKind::Png => data.starts_with(b"\x89PNG\r\n\x1a\n"),
+            FileKind::Jpeg => data.starts_with(&[0xFF, 0xD8, 0xFF]),
+            FileKind::Bmp => data.starts_with(b"BM") && u32_at(data, 6) == Some(0),
+            FileKind::Arj => data.starts_with(&[0x60, 0xEA]),
+            FileKind::Lzh => data.len() >= 7 && &data[2..5] == b"-lh" && data[6] == b'-',
+        }
+    }
+
+    /// Estimate the length of a file of this kind starting at `data[0]`.
+    fn length(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            FileKind::Exe => {
+                let last_page = u16_at(data, 2)? as usize;
+                let pages = u16_at(data, 4)? as usize;
+                Some(match last_page {
+                    0 => pages * 512,
+                    _ => (pages - 1) * 512 + last_page,
+                })
+            }
+            FileKind::Bmp => Some(u32_at(data, 2)? as usize),
+            FileKind::Zip => {
+                // The end of central directory record, plus its 22 bytes and any comment.
+                let end = find(data, b"PK\x05\x06")?;
+                let comment = u16_at(data, end + 20)? as usize;
+                Some(end + 22 + comment)
+            }
+            FileKind::Gif => find(data, &[0x00, 0x3B]).map(|end| end + 2),
+            FileKind::Png => find(data, b"IEND").map(|end| end + 8),
+            FileKind::Jpeg => find(data, &[0xFF, 0xD9]).map(|end| end + 2),
+            FileKind::Arj | FileKind::Lzh => None,
+        }
+    }
+}
+
+pub const FILE_KINDS: [FileKind; 8] = [
+    FileKind::Exe,
+    FileKind::Zip,
+    FileKind::Gif,
+    FileKind::Png,
+    FileKind::Jpeg,
+    FileKind::Bmp,
+    FileKind::Arj,
+    FileKind::Lzh,
+];
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|window| window == needle)
+}
+
+#[derive(Clone, Debug)]
+pub struct CarveHit {
+    pub kind: FileKind,
+    pub offset: usize,
+    pub len: usize,
+    /// Whether `len` was found from the file itself rather than assumed.
+    pub len_known: bool,
+    pub sector: Option<SectorKey>,
+}
+
+/// The disk's sector data laid end to end, with the sector each byte came from.
+#[derive(Default)]
+pub struct LinearData {
+    pub data: Vec<u8>,
+    /// The start offset of each sector in `data`.
+    sectors: Vec<(usize, SectorKey)>,
+}
+
+impl LinearData {
+    pub fn from_disk(disk: &mut DiskImage) -> Self {
+        let mut reads = read_all_sectors(disk);
+        reads.sort_by_key(|read| read.key);
+
+        let mut linear = LinearData::default();
+        for read in reads {
+            linear.sectors.push((linear.data.len(), read.key));
+            linear.data.extend_from_slice(&read.data);
+        }
+        linear
+    }
+
+    pub fn sector_at(&self, offset: usize) -> Option<SectorKey> {
+        let i = self.sectors.partition_point(|(start, _)| *start <= offset);
+        self.sectors.get(i.checked_sub(1)?).map(|(_, key)| *key)
+    }
+}
+
+/// Scan `linear` for file signatures. Hits are only looked for at the start of a sector, or
+/// at 16 byte alignments for formats often embedded in other files, to cut down on false
+/// positives in compressed data.
+pub fn carve(linear: &LinearData) -> Vec<CarveHit> {
+    let data = &linear.data;
+    let mut hits: Vec<CarveHit> = Vec::new();
+    for offset in (0..data.len()).step_by(16) {
+        let at_sector = linear.sectors.binary_search_by_key(&offset, |(start, _)| *start).is_ok();
+        for kind in FILE_KINDS {
+            let aligned = at_sector || matches!(kind, FileKind::Gif | FileKind::Png | FileKind::Jpeg);
+            if !aligned || !kind.matches(&data[offset..]) {
+                continue;
+            }
+            let found = kind.length(&data[offset..]).filter(|len| offset + len <= data.len());
+            hits.push(CarveHit {
+                kind,
+                offset,
+                len: found.unwrap_or(CARVE_DEFAULT_LEN),
+                len_known: found.is_some(),
+                sector: linear.sector_at(offset),
+            });
+        }
+    }
+
+    // Without a known length, run to the next hit.
+    for i in 0..hits.len() {
+        let next = hits.get(i + 1).map_or(data.len(), |hit| hit.offset);
+        let hit = &mut hits[i];
+        if !hit.len_known {
+            hit.len = hit.len.min(next - hit.offset);
+        }
+        hit.len = hit.len.min(data.len() - hit.offset);
+    }
+    hits
+}
+
+/// A panel to scan an image for files and extract them.
+#[derive(Default)]
+pub struct Carver {
+    linear: Option<LinearData>,
+    pub hits: Vec<CarveHit>,
+}
+
+impl Carver {
+    pub fn clear(&mut self) {
+        self.linear = None;
+        self.hits.clear();
+    }
+
+    pub fn scan(&mut self, disk: &mut DiskImage) {
+        let linear = LinearData::from_disk(disk);
+        self.hits = carve(&linear);
+        log::info!("Carving found {} candidate files in {} bytes", self.hits.len(), linear.data.len());
+        self.linear = Some(linear);
+    }
+
+    /// Show the panel. Returns a sector to select if the user clicked one.
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &mut DiskImage) -> Option<SectorKey> {
+        let mut select = None;
+        egui::CollapsingHeader::new("Carve files").id_salt("carver").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Scan sector data").clicked() {
+                    self.scan(disk);
+                }
+                if let Some(linear) = &self.linear {
+                    ui.label(format!("{} candidates in {} bytes", self.hits.len(), linear.data.len()));
+                }
+            });
+            let Some(linear) = &self.linear
+            else {
+                return;
+            };
+
+            egui::ScrollArea::vertical().id_salt("carver_hits").max_height(CARVE_MAX_HEIGHT).show(ui, |ui| {
+                egui::Grid::new("carver_grid").striped(true).num_columns(5).show(ui, |ui| {
+                    ui.strong("Offset");
+                    ui.strong("Sector");
+                    ui.strong("Type");
+                    ui.strong("Length");
+                    ui.label("");
+                    ui.end_row();
+
+                    for hit in self.hits.iter_mut() {
+                        ui.monospace(format!("{:06X}", hit.offset));
+                        match hit.sector {
+                            Some(key) => {
+                                if ui.link(key.to_string()).clicked() {
+                                    select = Some(key);
+                                }
+                            }
+                            None => {
+                                ui.label("-");
+                            }
+                        }
+                        ui.label(hit.kind.label());
+                        let max_len = linear.data.len() - hit.offset;
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut hit.len).range(1..=max_len));
+                            if !hit.len_known {
+                                ui.weak("(guessed)");
+                            }
+                        });
+                        if ui.button("Extract").clicked() {
+                            let name = format!("carved_{:06X}.{}", hit.offset, hit.kind.extension());
+                            let bytes = &linear.data[hit.offset..hit.offset + hit.len];
+                            if let Err(e) = storage::download_bytes(bytes, &name) {
+                                log::error!("Error downloading {}: {:?}", name, e);
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+        select
+    }
+}
@@ -0,0 +1,370 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A read-only reader for the Commodore 1541 DOS filesystem, from D64 sector dumps or from GCR
+//! disks decoded by fluxfox.
+//!
+//! The 1541 varies the sector count by zone so the outer tracks hold more data. The directory
+//! and block availability map (BAM) live on track 18, and files are chains of 256-byte sectors
+//! whose first two bytes link to the next.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use fluxfox::DiskImage;
+
+use crate::analysis::read_all_sectors;
+use crate::storage;
+
+pub const CBM_SECTOR_SIZE: usize = 256;
+pub const CBM_DIR_TRACK: u8 = 18;
+pub const CBM_DIR_ENTRY_SIZE: usize = 32;
+/// PETSCII shifted space, used to pad names.
+pub const CBM_PAD: u8 = 0xA0;
+/// Stop following a sector chain after this many links, as a corrupt chain may loop.
+pub const CBM_MAX_CHAIN: usize = 802;
+
+/// D64 sizes for 35 and 40 track images, without and with the trailing error info bytes.
+const D64_SIZES: [(usize, u8); 4] = [(174848, 35), (175531, 35), (196608, 40), (197376, 40)];
+
+/// The number of sectors on a 1-based track number.
+pub fn sectors_per_track(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CbmFileType {
+    Del,
+    Seq,
+    Prg,
+    Usr,
+    Rel,
+}
+
+impl CbmFileType {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte & 0x07 {
+            0 => Some(CbmFileType::Del),
+            1 => Some(CbmFileType::Seq),
+            2 => Some(CbmFileType::Prg),
+            3 => Some(CbmFileType::Usr),
+            4 => Some(CbmFileType::Rel),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CbmFileType::Del => "DEL",
+            CbmFileType::Seq => "SEQ",
+            CbmFileType::Prg => "PRG",
+            CbmFileType::Usr => "USR",
+            CbmFileType::Rel => "REL",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CbmFileType::Del => "del",
+            CbmFileType::Seq => "seq",
+            CbmFileType::Prg => "prg",
+            CbmFileType::Usr => "usr",
+            CbmFileType::Rel => "rel",
+        }
+    }
+}
+
+/// Convert a PETSCII name to displayable text. Unshifted PETSCII letters share ASCII's upper case
+/// codes; anything unprintable is shown as '?'.
+pub fn petscii_to_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != CBM_PAD)
+        .map(|b| match b {
+            0x20..=0x5F => *b as char,
+            0xC1..=0xDA => (b - 0x80) as char,
+            _ => '?',
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct CbmFile {
+    pub name: String,
+    pub file_type: CbmFileType,
+    pub closed: bool,
+    pub locked: bool,
+    pub start: (u8, u8),
+    /// The size in blocks as recorded in the directory.
+    pub blocks: u16,
+}
+
+/// The sectors of a 1541 disk, by 1-based track and sector number.
+pub struct CbmVolume {
+    sectors: HashMap<(u8, u8), Vec<u8>>,
+    pub disk_name: String,
+    pub disk_id: String,
+    pub free_blocks: u32,
+}
+
+impl CbmVolume {
+    pub fn is_d64_file(filename: &str) -> bool {
+        filename.to_ascii_lowercase().ends_with(".d64")
+    }
+
+    /// Read a D64 image, which is every sector in track order with no headers.
+    pub fn from_d64(bytes: &[u8]) -> Result<Self, Error> {
+        let (_, tracks) = D64_SIZES
+            .iter()
+            .find(|(size, _)| *size == bytes.len())
+            .ok_or_else(|| anyhow!("Unexpected D64 size of {} bytes", bytes.len()))?;
+
+        let mut sectors = HashMap::new();
+        let mut chunks = bytes.chunks_exact(CBM_SECTOR_SIZE);
+        for track in 1..=*tracks {
+            for sector in 0..sectors_per_track(track) {
+                if let Some(data) = chunks.next() {
+                    sectors.insert((track, sector), data.to_vec());
+                }
+            }
+        }
+        Self::from_sectors(sectors)
+    }
+
+    /// Read the sectors of a GCR disk decoded by fluxfox. Physical cylinder 0 is track 1.
+    pub fn from_disk(disk: &mut DiskImage) -> Result<Self, Error> {
+        let sectors = read_all_sectors(disk)
+            .into_iter()
+            .filter(|read| read.key.h == 0 && read.data.len() == CBM_SECTOR_SIZE)
+            .filter_map(|read| {
+                let track = u8::try_from(read.key.c + 1).ok()?;
+                Some(((track, read.key.s), read.data))
+            })
+            .collect();
+        Self::from_sectors(sectors)
+    }
+
+    fn from_sectors(sectors: HashMap<(u8, u8), Vec<u8>>) -> Result<Self, Error> {
+        let bam = sectors
+            .get(&(CBM_DIR_TRACK, 0))
+            .ok_or_else(|| anyhow!("BAM sector not found"))?;
+        // The DOS version byte is 'A' for 1541 format disks.
+        if bam[2] != 0x41 {
+            return Err(anyhow!("Unexpected DOS version byte 0x{:02X}", bam[2]));
+        }
+
+        let free_blocks = (1..=35u8)
+            .filter(|track| *track != CBM_DIR_TRACK)
+            .map(|track| bam[track as usize * 4] as u32)
+            .sum();
+        let disk_name = petscii_to_string(&bam[0x90..0xA0]);
+        let disk_id = petscii_to_string(&bam[0xA2..0xA4]);
+
+        Ok(Self {
+            sectors,
+            disk_name,
+            disk_id,
+            free_blocks,
+        })
+    }
+
+    fn sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        self.sectors.get(&(track, sector)).map(|data| data.as_slice())
+    }
+
+    /// Follow a sector chain, returning each sector's data and how many of its bytes are used.
+    fn chain(&self, start: (u8, u8)) -> Result<Vec<(&[u8], usize)>, Error> {
+        let mut links = Vec::new();
+        let (mut track, mut sector) = start;
+        while track != 0 {
+            if links.len() >= CBM_MAX_CHAIN {
+                return Err(anyhow!("Sector chain loops"));
+            }
+            let data = self
+                .sector(track, sector)
+                .ok_or_else(|| anyhow!("Missing sector {}/{} in chain", track, sector))?;
+            (track, sector) = (data[0], data[1]);
+            // The last sector stores the index of its final used byte in place of a link.
+            let used = if track == 0 { (sector as usize + 1).clamp(2, CBM_SECTOR_SIZE) } else { CBM_SECTOR_SIZE };
+            links.push((data, used));
+        }
+        Ok(links)
+    }
+
+    pub fn directory(&self) -> Result<Vec<CbmFile>, Error> {
+        let mut files = Vec::new();
+        for (data, _) in self.chain((CBM_DIR_TRACK, 1))? {
+            for entry in data.chunks_exact(CBM_DIR_ENTRY_SIZE) {
+                let type_byte = entry[2];
+                // Scratched entries have a type byte of zero.
+                if type_byte == 0 {
+                    continue;
+                }
+                let Some(file_type) = CbmFileType::from_byte(type_byte)
+                else {
+                    continue;
+                };
+                files.push(CbmFile {
+                    name: petscii_to_string(&entry[5..0x15]),
+                    file_type,
+                    closed: type_byte & 0x80 != 0,
+                    locked: type_byte & 0x40 != 0,
+                    start: (entry[3], entry[4]),
+                    blocks: u16::from_le_bytes([entry[0x1E], entry[0x1F]]),
+                });
+            }
+        }
+        Ok(files)
+    }
+
+    /// PRG files begin with the address they load to.
+    pub fn load_address(&self, file: &CbmFile) -> Option<u16> {
+        if file.file_type != CbmFileType::Prg {
+            return None;
+        }
+        let data = self.sector(file.start.0, file.start.1)?;
+        Some(u16::from_le_bytes([data[2], data[3]]))
+    }
+
+    pub fn read_file(&self, file: &CbmFile) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        for (sector, used) in self.chain(file.start)? {
+            data.extend_from_slice(&sector[2..used]);
+        }
+        Ok(data)
+    }
+}
+
+#[derive(Default)]
+pub struct CbmBrowser {
+    volume: Option<CbmVolume>,
+    files: Vec<CbmFile>,
+    error: Option<String>,
+}
+
+impl CbmBrowser {
+    pub fn clear(&mut self) {
+        self.volume = None;
+        self.files.clear();
+        self.error = None;
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.volume.is_some()
+    }
+
+    /// Mount a volume, keeping it even if the directory is damaged so the error can be shown.
+    pub fn mount(&mut self, volume: CbmVolume) {
+        match volume.directory() {
+            Ok(files) => {
+                log::info!("Mounted CBM DOS volume \"{}\" with {} files", volume.disk_name, files.len());
+                self.files = files;
+                self.error = None;
+            }
+            Err(e) => {
+                log::warn!("Error reading CBM DOS directory: {}", e);
+                self.files.clear();
+                self.error = Some(e.to_string());
+            }
+        }
+        self.volume = Some(volume);
+    }
+
+    pub fn load(&mut self, disk: &mut DiskImage) {
+        self.clear();
+        match CbmVolume::from_disk(disk) {
+            Ok(volume) => self.mount(volume),
+            Err(e) => log::info!("No CBM DOS filesystem found: {}", e),
+        }
+    }
+
+    fn download(volume: &CbmVolume, file: &CbmFile) {
+        let filename = format!("{}.{}", file.name.trim(), file.file_type.extension());
+        match volume.read_file(file) {
+            Ok(data) => {
+                if let Err(e) = storage::download_bytes(&data, &filename) {
+                    log::error!("Error downloading {}: {:?}", filename, e);
+                }
+            }
+            Err(e) => {
+                log::error!("Error reading {}: {}", filename, e);
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let Some(volume) = &self.volume
+        else {
+            return;
+        };
+
+        egui::CollapsingHeader::new(format!("CBM DOS filesystem: \"{}\" {}", volume.disk_name, volume.disk_id))
+            .id_salt("cbm_browser")
+            .show(ui, |ui| {
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().warn_fg_color, error);
+                }
+
+                egui::Grid::new("cbm_files_grid").striped(true).num_columns(4).show(ui, |ui| {
+                    ui.strong("Blocks");
+                    ui.strong("Name");
+                    ui.strong("Type");
+                    ui.strong("Load address");
+                    ui.end_row();
+                    for file in self.files.iter() {
+                        ui.label(file.blocks.to_string());
+                        if ui.link(format!("\"{}\"", file.name)).on_hover_text("Click to save").clicked() {
+                            Self::download(volume, file);
+                        }
+
+                        // Unclosed files are shown with a '*', and locked files with a '<', as in
+                        // a 1541 directory listing.
+                        let mut file_type = file.file_type.label().to_string();
+                        if !file.closed {
+                            file_type.insert(0, '*');
+                        }
+                        if file.locked {
+                            file_type.push('<');
+                        }
+                        ui.monospace(file_type);
+
+                        match volume.load_address(file) {
+                            Some(address) => ui.monospace(format!("${:04X}", address)),
+                            None => ui.label(""),
+                        };
+                        ui.end_row();
+                    }
+                });
+                ui.label(format!("{} blocks free.", volume.free_blocks));
+            });
+    }
+}
@@ -0,0 +1,295 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Verification of loaded images against user-supplied checksum manifests.
+//!
+//! Supported manifests are `.sfv` (CRC32), and `.md5`/`.sha1` files in either the GNU
+//! coreutils (`<digest>  <name>`) or BSD (`MD5 (<name>) = <digest>`) style.
+
+use anyhow::{anyhow, Error};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChecksumKind {
+    Crc32,
+    Md5,
+    Sha1,
+}
+
+impl ChecksumKind {
+    pub fn from_filename(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".sfv") {
+            Some(ChecksumKind::Crc32)
+        }
+        else if name.ends_with(".md5") {
+            Some(ChecksumKind::Md5)
+        }
+        else if name.ends_with(".sha1") {
+            Some(ChecksumKind::Sha1)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChecksumKind::Crc32 => "CRC32",
+            ChecksumKind::Md5 => "MD5",
+            ChecksumKind::Sha1 => "SHA-1",
+        }
+    }
+
+    /// Length of the digest as a hex string.
+    fn hex_len(&self) -> usize {
+        match self {
+            ChecksumKind::Crc32 => 8,
+            ChecksumKind::Md5 => 32,
+            ChecksumKind::Sha1 => 40,
+        }
+    }
+}
+
+/// Digests of a source file, calculated once when it is loaded.
+#[derive(Clone, Debug, Default)]
+pub struct Digests {
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+}
+
+impl Digests {
+    pub fn new(data: &[u8]) -> Self {
+        use md5::Digest;
+        Self {
+            crc32: format!("{:08x}", crc32fast::hash(data)),
+            md5: crate::util::hex_string(&md5::Md5::digest(data)),
+            sha1: crate::util::sha1_hex(data),
+        }
+    }
+
+    pub fn get(&self, kind: ChecksumKind) -> &str {
+        match kind {
+            ChecksumKind::Crc32 => &self.crc32,
+            ChecksumKind::Md5 => &self.md5,
+            ChecksumKind::Sha1 => &self.sha1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub digest: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChecksumManifest {
+    pub name: String,
+    pub kind: ChecksumKind,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl ChecksumManifest {
+    pub fn is_manifest_file(name: &str) -> bool {
+        ChecksumKind::from_filename(name).is_some()
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Self, Error> {
+        let kind = ChecksumKind::from_filename(name).ok_or(anyhow!("Unrecognized manifest type: {}", name))?;
+
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            match Self::parse_line(kind, line) {
+                Some(entry) => entries.push(entry),
+                None => log::warn!("Skipping malformed manifest line: {}", line),
+            }
+        }
+
+        if entries.is_empty() {
+            return Err(anyhow!("No {} entries found in {}", kind.label(), name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            entries,
+        })
+    }
+
+    fn parse_line(kind: ChecksumKind, line: &str) -> Option<ManifestEntry> {
+        let (name, digest) = if kind == ChecksumKind::Crc32 {
+            // SFV: `<name> <crc32>`, where the name may itself contain spaces.
+            let (name, digest) = line.rsplit_once(char::is_whitespace)?;
+            (name.trim(), digest)
+        }
+        else if let Some((name, digest)) = line
+            .split_once(" (")
+            .and_then(|(_, rest)| rest.rsplit_once(") = "))
+        {
+            // BSD: `MD5 (<name>) = <digest>`
+            (name, digest)
+        }
+        else {
+            // GNU: `<digest>  <name>` or `<digest> *<name>` for binary mode.
+            let (digest, name) = line.split_once(char::is_whitespace)?;
+            (name.trim_start().trim_start_matches('*'), digest)
+        };
+
+        let digest = digest.trim().to_ascii_lowercase();
+        if name.is_empty() || digest.len() != kind.hex_len() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(ManifestEntry {
+            name: name.to_string(),
+            digest,
+        })
+    }
+
+    /// Find the entry for `filename`, ignoring any directory components and case.
+    pub fn find(&self, filename: &str) -> Option<&ManifestEntry> {
+        let base = |name: &str| name.rsplit(['/', '\\']).next().unwrap_or(name).to_ascii_lowercase();
+        let filename = base(filename);
+        self.entries.iter().find(|entry| base(&entry.name) == filename)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChecksumResult {
+    pub name: String,
+    pub kind: ChecksumKind,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl ChecksumResult {
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+impl std::fmt::Display for ChecksumResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = if self.passed() { "pass" } else { "FAIL" };
+        write!(f, "{} check of {}: {}", self.kind.label(), self.name, result)
+    }
+}
+
+/// The active manifest and the results of verifying files against it.
+#[derive(Default)]
+pub struct ChecksumVerifier {
+    pub manifest: Option<ChecksumManifest>,
+    pub results: Vec<ChecksumResult>,
+    pub error: Option<String>,
+}
+
+impl ChecksumVerifier {
+    pub fn set_manifest(&mut self, manifest: ChecksumManifest) {
+        log::info!("Loaded {} manifest {} with {} entries", manifest.kind.label(), manifest.name, manifest.entries.len());
+        self.manifest = Some(manifest);
+        self.results.clear();
+        self.error = None;
+    }
+
+    /// Verify a file against the manifest, replacing any previous result for it.
+    /// Returns None if there is no manifest, or the file isn't listed in it.
+    pub fn verify(&mut self, filename: &str, digests: &Digests) -> Option<&ChecksumResult> {
+        let manifest = self.manifest.as_ref()?;
+        let entry = manifest.find(filename)?;
+        let result = ChecksumResult {
+            name: entry.name.clone(),
+            kind: manifest.kind,
+            expected: entry.digest.clone(),
+            actual: digests.get(manifest.kind).to_string(),
+        };
+        log::info!("{}: {}", manifest.name, result);
+
+        self.results.retain(|r| r.name != result.name);
+        self.results.push(result);
+        self.results.last()
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        let Some(manifest) = &self.manifest
+        else {
+            return;
+        };
+
+        let mut clear = false;
+        egui::CollapsingHeader::new(format!("Checksum verification: {}", manifest.name))
+            .id_salt("checksum_results")
+            .default_open(true)
+            .show(ui, |ui| {
+                egui::Grid::new("checksum_results_grid")
+                    .striped(true)
+                    .num_columns(4)
+                    .show(ui, |ui| {
+                        ui.strong("File");
+                        ui.strong(format!("Expected {}", manifest.kind.label()));
+                        ui.strong("Actual");
+                        ui.strong("Result");
+                        ui.end_row();
+
+                        for entry in manifest.entries.iter() {
+                            ui.label(&entry.name);
+                            ui.monospace(&entry.digest);
+                            match self.results.iter().find(|r| r.name == entry.name) {
+                                Some(result) => {
+                                    ui.monospace(&result.actual);
+                                    if result.passed() {
+                                        ui.colored_label(egui::Color32::GREEN, "Pass");
+                                    }
+                                    else {
+                                        ui.colored_label(ui.visuals().error_fg_color, "Fail");
+                                    }
+                                }
+                                None => {
+                                    ui.label("-");
+                                    ui.weak("Not loaded");
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                if ui.button("Clear manifest").clicked() {
+                    clear = true;
+                }
+            });
+
+        if clear {
+            self.manifest = None;
+            self.results.clear();
+        }
+    }
+}
@@ -0,0 +1,353 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Comparing the loaded image against a second image of the same disk, down to the bitstream
+//! of a single track.
+//!
+//! Two reads of a disk rarely start at the same point of the track, so the bitstreams are
+//! aligned on the first element found in each before the bits are compared. Reads also run at
+//! slightly different speeds and may slip or gain bits, so the alignment is found again at each
+//! address mark of the first track that has a match nearby in the second; a slip then only
+//! throws off the comparison up to the next mark instead of to the end of the track.
+
+use anyhow::{anyhow, Error};
+use egui::{Color32, Rect, Sense, Stroke};
+use fluxfox::structure_parsers::{DiskStructureGenericElement, DiskStructureMetadata};
+use fluxfox::visualization::{collect_metadata, collect_streams};
+use fluxfox::{DiskCh, DiskDataResolution, DiskImage};
+
+/// The number of bins the diff strip divides a track into.
+pub const DIFF_STRIP_BINS: usize = 256;
+pub const DIFF_STRIP_HEIGHT: f32 = 24.0;
+/// How far from where the alignment so far puts it an address mark of the second track may be
+/// to be paired with one of the first.
+const RESYNC_WINDOW_BITS: usize = 2048;
+
+fn is_sector_data(elem: DiskStructureGenericElement) -> bool {
+    matches!(
+        elem,
+        DiskStructureGenericElement::SectorData
+            | DiskStructureGenericElement::SectorBadData
+            | DiskStructureGenericElement::SectorDeletedData
+            | DiskStructureGenericElement::SectorBadDeletedData
+    )
+}
+
+/// How much of a sector's data differs between the two images.
+#[derive(Clone, Debug)]
+pub struct SectorDivergence {
+    pub index: usize,
+    pub start: usize,
+    pub end: usize,
+    pub differing: usize,
+}
+
+impl SectorDivergence {
+    pub fn density(&self) -> f32 {
+        self.differing as f32 / (self.end - self.start).max(1) as f32
+    }
+}
+
+/// The bit offsets of the address marks of a track, in order.
+fn mark_offsets(meta: &DiskStructureMetadata) -> Vec<usize> {
+    let mut marks: Vec<usize> = meta
+        .items
+        .iter()
+        .filter(|item| matches!(DiskStructureGenericElement::from(item.elem_type), DiskStructureGenericElement::Marker))
+        .map(|item| item.start)
+        .collect();
+    marks.sort_unstable();
+    marks
+}
+
+/// Pair each of the marks `a_marks` of the first track with the mark of the second found
+/// within `RESYNC_WINDOW_BITS` of where `shift`, the alignment so far, puts it. Returns the
+/// offset of each paired mark in the first track with the shift to compare from it on. A mark
+/// damaged or missing in one read keeps the shift from before it.
+fn resync_anchors(a_marks: &[usize], b_marks: &[usize], mut shift: usize, b_len: usize) -> Vec<(usize, usize)> {
+    let distance = |x: usize, y: usize| {
+        let d = x.abs_diff(y);
+        d.min(b_len - d)
+    };
+    let mut anchors = Vec::new();
+    for a in a_marks {
+        let expected = (a + shift) % b_len;
+        let nearest = b_marks.iter().filter(|b| **b < b_len).min_by_key(|b| distance(**b, expected));
+        if let Some(b) = nearest.filter(|b| distance(**b, expected) <= RESYNC_WINDOW_BITS) {
+            shift = (b + b_len - a % b_len) % b_len;
+            anchors.push((*a, shift));
+        }
+    }
+    anchors
+}
+
+/// The bitstream difference of one track between two images.
+#[derive(Clone, Debug)]
+pub struct TrackDiff {
+    pub ch: DiskCh,
+    /// The number of bits compared: the length of the shorter track.
+    pub compared: usize,
+    pub differing: usize,
+    /// How many bits the second track was rotated by to align it with the first.
+    pub shift: usize,
+    /// How many address marks the alignment was found again at.
+    pub resyncs: usize,
+    /// The fraction of differing bits in each bin of the track.
+    pub bins: Vec<f32>,
+    pub sectors: Vec<SectorDivergence>,
+}
+
+impl TrackDiff {
+    pub fn new(a: &DiskImage, b: &DiskImage, ch: DiskCh) -> Result<Self, Error> {
+        for disk in [a, b] {
+            if !matches!(disk.resolution(), DiskDataResolution::BitStream) {
+                return Err(anyhow!("Bit-level diffs need bitstream images"));
+            }
+        }
+
+        let ti = ch.c() as usize;
+        let missing = |which: &str| anyhow!("Track {} not found in the {} image", ch, which);
+        let a_stream = collect_streams(ch.h(), a).into_iter().nth(ti).ok_or_else(|| missing("loaded"))?;
+        let b_stream = collect_streams(ch.h(), b).into_iter().nth(ti).ok_or_else(|| missing("comparison"))?;
+        let a_meta = collect_metadata(ch.h(), a).into_iter().nth(ti).ok_or_else(|| missing("loaded"))?;
+        let b_meta = collect_metadata(ch.h(), b).into_iter().nth(ti).ok_or_else(|| missing("comparison"))?;
+
+        let (a_len, b_len) = (a_stream.len(), b_stream.len());
+        if a_len == 0 || b_len == 0 {
+            return Err(anyhow!("Track {} is empty", ch));
+        }
+        let a_anchor = a_meta.items.iter().map(|item| item.start).min().unwrap_or(0);
+        let b_anchor = b_meta.items.iter().map(|item| item.start).min().unwrap_or(0);
+        let shift = (b_anchor + b_len - a_anchor % b_len) % b_len;
+
+        let anchors = resync_anchors(&mark_offsets(&a_meta), &mark_offsets(&b_meta), shift, b_len);
+
+        let compared = a_len.min(b_len);
+        let mut differs = Vec::with_capacity(compared);
+        let (mut current, mut next) = (shift, anchors.iter().peekable());
+        for i in 0..compared {
+            while let Some((_, anchor_shift)) = next.next_if(|(offset, _)| *offset <= i) {
+                current = *anchor_shift;
+            }
+            differs.push(a_stream[i] != b_stream[(i + current) % b_len]);
+        }
+
+        let bin_len = compared.div_ceil(DIFF_STRIP_BINS);
+        let bins = differs
+            .chunks(bin_len)
+            .map(|bin| bin.iter().filter(|d| **d).count() as f32 / bin.len() as f32)
+            .collect();
+
+        let sectors = a_meta
+            .items
+            .iter()
+            .filter(|item| is_sector_data(DiskStructureGenericElement::from(item.elem_type)))
+            .enumerate()
+            .map(|(index, item)| {
+                let (start, end) = (item.start.min(compared), item.end.min(compared));
+                SectorDivergence {
+                    index,
+                    start,
+                    end,
+                    differing: differs[start..end].iter().filter(|d| **d).count(),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            ch,
+            compared,
+            differing: differs.iter().filter(|d| **d).count(),
+            shift,
+            resyncs: anchors.len(),
+            bins,
+            sectors,
+        })
+    }
+
+    fn density_color(density: f32) -> Color32 {
+        if density == 0.0 {
+            return Color32::from_gray(40);
+        }
+        // Any difference at all should stand out, so low densities start well above black.
+        let t = (0.3 + density * 0.7).min(1.0);
+        Color32::from_rgb((255.0 * t) as u8, (60.0 * (1.0 - t)) as u8, 0)
+    }
+
+    /// Draw the diff strip: one cell per bin, brighter where more bits differ, with ticks at
+    /// the start of each sector.
+    pub fn show_strip(&self, ui: &mut egui::Ui) {
+        let width = ui.available_width();
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(width, DIFF_STRIP_HEIGHT), Sense::hover());
+        let painter = ui.painter_at(rect);
+        let bin_width = width / self.bins.len().max(1) as f32;
+        for (i, density) in self.bins.iter().enumerate() {
+            let x = rect.left() + i as f32 * bin_width;
+            let cell = Rect::from_min_max(egui::pos2(x, rect.top()), egui::pos2(x + bin_width, rect.bottom()));
+            painter.rect_filled(cell, 0.0, Self::density_color(*density));
+        }
+        for sector in self.sectors.iter() {
+            let x = rect.left() + sector.start as f32 / self.compared as f32 * width;
+            painter.line_segment(
+                [egui::pos2(x, rect.top()), egui::pos2(x, rect.top() + DIFF_STRIP_HEIGHT / 3.0)],
+                Stroke::new(1.0, Color32::WHITE),
+            );
+        }
+
+        if let Some(pos) = response.hover_pos() {
+            let bin = (((pos.x - rect.left()) / bin_width) as usize).min(self.bins.len().saturating_sub(1));
+            let bin_len = self.compared.div_ceil(DIFF_STRIP_BINS);
+            response.on_hover_text(format!(
+                "Bits {}-{}: {:.1}% differ",
+                bin * bin_len,
+                ((bin + 1) * bin_len).min(self.compared),
+                self.bins.get(bin).copied().unwrap_or(0.0) * 100.0
+            ));
+        }
+    }
+}
+
+/// A second image loaded to compare the current one against.
+#[derive(Default)]
+pub struct Comparison {
+    pub disk: Option<DiskImage>,
+    pub name: Option<String>,
+    /// Set while waiting for the user to drop the image to compare with.
+    pub awaiting: bool,
+    pub loading: bool,
+    cylinder: u16,
+    head: u8,
+    diff: Option<Result<TrackDiff, String>>,
+}
+
+impl Comparison {
+    pub fn clear(&mut self) {
+        *self = Comparison::default();
+    }
+
+    /// Forget the diff, which no longer applies once the loaded image changes.
+    pub fn clear_diff(&mut self) {
+        self.diff = None;
+    }
+
+    pub fn set_disk(&mut self, disk: DiskImage) {
+        self.disk = Some(disk);
+        self.loading = false;
+        self.diff = None;
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, primary: &DiskImage, selected: Option<DiskCh>) {
+        egui::CollapsingHeader::new("Compare").id_salt("comparison").show(ui, |ui| {
+            let Some(other) = &self.disk
+            else {
+                if self.loading {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("Loading {}...", self.name.as_deref().unwrap_or("image")));
+                    });
+                }
+                else if self.awaiting {
+                    ui.horizontal(|ui| {
+                        ui.label("Drop the image to compare with.");
+                        if ui.button("Cancel").clicked() {
+                            self.awaiting = false;
+                        }
+                    });
+                }
+                else if ui.button("Compare with another image...").clicked() {
+                    self.awaiting = true;
+                }
+                return;
+            };
+
+            let mut stop = false;
+            ui.horizontal(|ui| {
+                ui.label(format!("Comparing with {}", self.name.as_deref().unwrap_or("image")));
+                if ui.button("Stop comparing").clicked() {
+                    stop = true;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Track:");
+                ui.add(egui::DragValue::new(&mut self.cylinder).prefix("c:"));
+                ui.add(egui::DragValue::new(&mut self.head).range(0..=1).prefix("h:"));
+                if let Some(ch) = selected {
+                    if ui.button("Use selected track").clicked() {
+                        (self.cylinder, self.head) = (ch.c(), ch.h());
+                    }
+                }
+                if ui.button("Diff bitstreams").clicked() {
+                    let ch = DiskCh::new(self.cylinder, self.head);
+                    self.diff = Some(TrackDiff::new(primary, other, ch).map_err(|e| e.to_string()));
+                }
+            });
+
+            match &self.diff {
+                Some(Ok(diff)) => {
+                    ui.label(format!(
+                        "Track {}: {} of {} bits differ ({:.2}%), aligned with a shift of {} bits and \
+                         realigned at {} address marks",
+                        diff.ch,
+                        diff.differing,
+                        diff.compared,
+                        diff.differing as f32 / diff.compared.max(1) as f32 * 100.0,
+                        diff.shift,
+                        diff.resyncs
+                    ));
+                    diff.show_strip(ui);
+                    egui::Grid::new("comparison_sectors_grid").striped(true).num_columns(3).show(ui, |ui| {
+                        ui.strong("Sector");
+                        ui.strong("Bits");
+                        ui.strong("Differing");
+                        ui.end_row();
+                        for sector in diff.sectors.iter() {
+                            ui.label(sector.index.to_string());
+                            ui.monospace(format!("{}-{}", sector.start, sector.end));
+                            let text = format!("{:.2}%", sector.density() * 100.0);
+                            if sector.differing > 0 {
+                                ui.colored_label(ui.visuals().warn_fg_color, text);
+                            }
+                            else {
+                                ui.label(text);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                None => {}
+            }
+
+            if stop {
+                self.clear();
+            }
+        });
+    }
+}
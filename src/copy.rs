@@ -0,0 +1,465 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Copying the sectors of the loaded image into a new, cleanly formatted image, and writing
+//! sectors of the loaded image in place.
+//!
+//! The target of a copy is formatted from scratch with a standard geometry, so protection
+//! tracks, odd sector IDs and over-dumped cylinders of the source are left behind. Only sectors
+//! whose IDs fit the target geometry are copied. Edits never go this way: they overwrite the
+//! data of the sectors they change on the image's own tracks.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use fluxfox::{DiskCh, DiskChs, DiskChsn, DiskDataResolution, DiskImage, ImageBuilder, RwSectorScope, StandardFormat};
+
+use crate::analysis::geometry::{LayoutSummary, StandardGeometry, PC_GEOMETRIES};
+use crate::analysis::{read_all_sectors, read_sector, SectorKey};
+
+/// The byte freshly formatted sectors are filled with, and missing sectors are left as.
+pub const FORMAT_FILL_BYTE: u8 = 0xF6;
+
+/// The fluxfox format used to lay out a new image with the given geometry. fluxfox only has PC
+/// layouts, so there's none for the 8" geometries.
+fn standard_format(geometry: &StandardGeometry) -> Option<StandardFormat> {
+    match (geometry.cylinders, geometry.heads, geometry.sectors) {
+        (40, 1, 8) => Some(StandardFormat::PcFloppy160),
+        (40, 1, 9) => Some(StandardFormat::PcFloppy180),
+        (40, 2, 8) => Some(StandardFormat::PcFloppy320),
+        (40, 2, 9) => Some(StandardFormat::PcFloppy360),
+        (80, 2, 9) => Some(StandardFormat::PcFloppy720),
+        (80, 2, 15) => Some(StandardFormat::PcFloppy1200),
+        (80, 2, 18) => Some(StandardFormat::PcFloppy1440),
+        // DMF packs 21 sectors into a 1.44M track by shrinking the gaps between them.
+        (80, 2, 21) => Some(StandardFormat::PcFloppy1440),
+        (80, 2, 36) => Some(StandardFormat::PcFloppy2880),
+        _ => None,
+    }
+}
+
+/// The sector size code written in sector IDs for sectors of `sector_size` bytes.
+fn size_code(sector_size: usize) -> u8 {
+    match sector_size {
+        128 => 0,
+        256 => 1,
+        1024 => 3,
+        2048 => 4,
+        4096 => 5,
+        8192 => 6,
+        _ => 2,
+    }
+}
+
+/// Return the sector IDs of a track in physical order. Each ID is placed `interleave` slots
+/// after the last, moving on to the next free slot on collision; the first ID is placed after
+/// `track_skew` slots, so consecutive tracks can be offset for head step time.
+pub fn sector_order(sectors: u8, interleave: u8, track_skew: usize) -> Vec<u8> {
+    let n = sectors as usize;
+    let mut order = vec![0u8; n];
+    let mut pos = track_skew % n;
+    for id in 1..=sectors {
+        while order[pos] != 0 {
+            pos = (pos + 1) % n;
+        }
+        order[pos] = id;
+        pos = (pos + interleave.max(1) as usize) % n;
+    }
+    order
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HeadSelection {
+    Both,
+    Head0,
+    Head1,
+}
+
+impl HeadSelection {
+    pub const ALL: [HeadSelection; 3] = [HeadSelection::Both, HeadSelection::Head0, HeadSelection::Head1];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HeadSelection::Both => "Both heads",
+            HeadSelection::Head0 => "Head 0 only",
+            HeadSelection::Head1 => "Head 1 only",
+        }
+    }
+}
+
+/// The source tracks to copy. Selected cylinders are renumbered from 0, and a single selected
+/// head becomes head 0, so one side of a flippy disk can be split into its own image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrackSelection {
+    pub first_cylinder: u16,
+    pub last_cylinder: u16,
+    pub heads: HeadSelection,
+}
+
+impl Default for TrackSelection {
+    fn default() -> Self {
+        Self {
+            first_cylinder: 0,
+            last_cylinder: u16::MAX,
+            heads: HeadSelection::Both,
+        }
+    }
+}
+
+impl TrackSelection {
+    /// The source track to copy into the target track `c`, `h`, if any.
+    pub fn source(&self, c: u16, h: u8) -> Option<(u16, u8)> {
+        let source_c = self.first_cylinder.checked_add(c).filter(|c| *c <= self.last_cylinder)?;
+        let source_h = match (self.heads, h) {
+            (HeadSelection::Both, h) => h,
+            (HeadSelection::Head0, 0) => 0,
+            (HeadSelection::Head1, 0) => 1,
+            _ => return None,
+        };
+        Some((source_c, source_h))
+    }
+
+    pub fn is_all(&self) -> bool {
+        *self == TrackSelection::default()
+    }
+}
+
+impl std::fmt::Display for TrackSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cylinders {}-{}, {}", self.first_cylinder, self.last_cylinder, self.heads.label().to_lowercase())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CopySettings {
+    pub geometry: StandardGeometry,
+    pub interleave: u8,
+    /// How many sectors each track's first sector is offset from the previous track's.
+    pub skew: u8,
+    pub tracks: TrackSelection,
+}
+
+impl Default for CopySettings {
+    fn default() -> Self {
+        Self {
+            geometry: PC_GEOMETRIES[3],
+            interleave: 1,
+            skew: 0,
+            tracks: TrackSelection::default(),
+        }
+    }
+}
+
+impl CopySettings {
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} (interleave {}:1, skew {})", self.geometry.name, self.interleave, self.skew);
+        if !self.tracks.is_all() {
+            description.push_str(&format!(" from {}", self.tracks));
+        }
+        description
+    }
+}
+
+/// What happened to the source's sectors during a copy.
+#[derive(Clone, Debug, Default)]
+pub struct CopyReport {
+    pub copied: usize,
+    /// Target sectors with no matching source sector, left filled.
+    pub missing: usize,
+    /// Source sectors outside the track selection or target geometry, left behind.
+    pub dropped: usize,
+    /// Source sectors whose size differed from the target's, padded or truncated.
+    pub resized: usize,
+}
+
+impl std::fmt::Display for CopyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} sectors copied", self.copied)?;
+        if self.missing > 0 {
+            write!(f, ", {} missing", self.missing)?;
+        }
+        if self.dropped > 0 {
+            write!(f, ", {} outside the selection left behind", self.dropped)?;
+        }
+        if self.resized > 0 {
+            write!(f, ", {} resized", self.resized)?;
+        }
+        Ok(())
+    }
+}
+
+/// Create a new image formatted with `settings` and copy the sectors of the selected tracks of
+/// `source` into it, matching sectors by physical track and sector ID.
+pub fn copy_to_new_image(source: &mut DiskImage, settings: &CopySettings) -> Result<(DiskImage, CopyReport), Error> {
+    rebuild_image(source, settings, |c, h| settings.tracks.source(c, h))
+}
+
+/// Create a new image formatted with `settings`, filling each target track with the sectors of
+/// the source track `track_source` maps it to.
+pub fn rebuild_image(
+    source: &mut DiskImage,
+    settings: &CopySettings,
+    track_source: impl Fn(u16, u8) -> Option<(u16, u8)>,
+) -> Result<(DiskImage, CopyReport), Error> {
+    let geometry = &settings.geometry;
+    let format = standard_format(geometry).ok_or_else(|| anyhow!("No standard format for {}", geometry.name))?;
+    let mut target = ImageBuilder::new()
+        .with_resolution(DiskDataResolution::BitStream)
+        .with_standard_format(format)
+        .with_formatted(false)
+        .build()
+        .map_err(|e| anyhow!("Error creating image: {:?}", e))?;
+
+    let mut sources: HashMap<(u16, u8, u8), Vec<u8>> =
+        read_all_sectors(source).into_iter().map(|read| ((read.key.c, read.key.h, read.key.s), read.data)).collect();
+
+    let n = size_code(geometry.sector_size);
+    let fill = vec![FORMAT_FILL_BYTE; geometry.sector_size];
+    let mut report = CopyReport::default();
+    for c in 0..geometry.cylinders {
+        for h in 0..geometry.heads {
+            let ch = DiskCh::new(c, h);
+            let track_index = c as usize * geometry.heads as usize + h as usize;
+            let order = sector_order(geometry.sectors, settings.interleave, track_index * settings.skew as usize);
+            let format_buffer: Vec<DiskChsn> = order.iter().map(|s| DiskChsn::new(c, h, *s, n)).collect();
+            target
+                .format_track(ch, format_buffer, &fill, geometry.gap3)
+                .map_err(|e| anyhow!("Error formatting track {}: {:?}", ch, e))?;
+
+            let Some((source_c, source_h)) = track_source(c, h)
+            else {
+                report.missing += geometry.sectors as usize;
+                continue;
+            };
+            for s in 1..=geometry.sectors {
+                let Some(mut data) = sources.remove(&(source_c, source_h, s))
+                else {
+                    report.missing += 1;
+                    continue;
+                };
+                if data.len() != geometry.sector_size {
+                    data.resize(geometry.sector_size, FORMAT_FILL_BYTE);
+                    report.resized += 1;
+                }
+                target
+                    .write_sector(ch, DiskChs::new(c, h, s), Some(n), &data, RwSectorScope::DataOnly, false, false)
+                    .map_err(|e| anyhow!("Error writing sector {} on {}: {:?}", s, ch, e))?;
+                report.copied += 1;
+            }
+        }
+    }
+    report.dropped = sources.len();
+    Ok((target, report))
+}
+
+/// A sector's contents from before it was overwritten in place, kept so the edit can be undone.
+#[derive(Clone, Debug)]
+pub struct SectorOriginal {
+    pub key: SectorKey,
+    /// The ID the sector was found and written under.
+    pub chsn: DiskChsn,
+    pub data: Vec<u8>,
+    pub data_crc_error: bool,
+    pub deleted: bool,
+}
+
+/// Find the sector `key` on its track and read what it holds now.
+fn read_original(disk: &mut DiskImage, key: SectorKey) -> Result<SectorOriginal, Error> {
+    let chsn = disk
+        .get_sector_map()
+        .get(key.h as usize)
+        .and_then(|cylinders| cylinders.get(key.c as usize))
+        .and_then(|entries| entries.iter().find(|entry| entry.chsn.s() == key.s))
+        .map(|entry| entry.chsn)
+        .ok_or_else(|| anyhow!("Sector {} isn't on the image", key))?;
+    let read = read_sector(disk, key.ch(), chsn).ok_or_else(|| anyhow!("Sector {} has no data to overwrite", key))?;
+    Ok(SectorOriginal {
+        key,
+        chsn,
+        data: read.data,
+        data_crc_error: read.data_crc_error,
+        deleted: read.deleted,
+    })
+}
+
+fn write_data(disk: &mut DiskImage, original: &SectorOriginal, data: &[u8]) -> Result<(), Error> {
+    let chsn = original.chsn;
+    let id = DiskChs::new(chsn.c(), chsn.h(), chsn.s());
+    disk.write_sector(original.key.ch(), id, Some(chsn.n()), data, RwSectorScope::DataOnly, original.deleted, false)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Error writing sector {}: {:?}", original.key, e))
+}
+
+/// Overwrite the data of sectors on the image's own tracks. Nothing else on the track changes:
+/// its encoding, bitstream or flux, the other sectors and their CRC errors are left as they
+/// were, and a deleted sector keeps its deleted data mark. Where a track holds a sector ID more
+/// than once, the first is written.
+///
+/// Either every sector is written or none are. Returns the sectors' previous contents for undo.
+pub fn write_sectors_in_place(
+    disk: &mut DiskImage,
+    patches: &[(SectorKey, Vec<u8>)],
+) -> Result<Vec<SectorOriginal>, Error> {
+    let mut originals = Vec::with_capacity(patches.len());
+    for (key, data) in patches {
+        let original = read_original(disk, *key)?;
+        if data.len() != original.data.len() {
+            return Err(anyhow!(
+                "Sector {} holds {} bytes, not {}",
+                key,
+                original.data.len(),
+                data.len()
+            ));
+        }
+        originals.push(original);
+    }
+    for (i, (original, (_, data))) in originals.iter().zip(patches).enumerate() {
+        if let Err(e) = write_data(disk, original, data) {
+            restore_sectors(disk, &originals[..i]);
+            return Err(e);
+        }
+    }
+    Ok(originals)
+}
+
+/// Put back sectors overwritten by write_sectors_in_place(), last written first. Returns the
+/// number of sectors whose data CRC error couldn't be restored: the original bytes are written
+/// back, but fluxfox writes data with a good CRC.
+pub fn restore_sectors(disk: &mut DiskImage, originals: &[SectorOriginal]) -> usize {
+    let mut lost_errors = 0;
+    for original in originals.iter().rev() {
+        match write_data(disk, original, &original.data) {
+            Ok(()) => lost_errors += original.data_crc_error as usize,
+            Err(e) => log::error!("restore_sectors(): {:?}", e),
+        }
+    }
+    lost_errors
+}
+
+/// A window for choosing the geometry and layout of a copy.
+#[derive(Default)]
+pub struct CopyDialog {
+    pub open: bool,
+    settings: CopySettings,
+    max_cylinder: u16,
+}
+
+impl CopyDialog {
+    /// Open the dialog for `disk`, starting from its own geometry and all of its tracks.
+    pub fn open(&mut self, disk: &DiskImage) {
+        let layout = LayoutSummary::from_disk(disk);
+        if let Some(geometry) = layout.standard_geometry() {
+            self.settings.geometry = *geometry;
+        }
+        self.settings.tracks = TrackSelection {
+            last_cylinder: layout.cylinders.saturating_sub(1),
+            ..TrackSelection::default()
+        };
+        self.max_cylinder = layout.cylinders.saturating_sub(1);
+        self.open = true;
+    }
+
+    /// Show the dialog. Returns the settings when the user starts the copy.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<CopySettings> {
+        let mut start = None;
+        let mut open = self.open;
+        egui::Window::new("Copy to new image")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("copy_dialog_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Geometry:");
+                    egui::ComboBox::from_id_salt("copy_geometry")
+                        .selected_text(self.settings.geometry.name)
+                        .show_ui(ui, |ui| {
+                            for geometry in PC_GEOMETRIES {
+                                ui.selectable_value(&mut self.settings.geometry, geometry, geometry.name);
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("");
+                    ui.weak("8\" formats can't be copied to, as there's no standard 8\" layout to format with.");
+                    ui.end_row();
+
+                    let sectors = self.settings.geometry.sectors;
+                    ui.label("Interleave:");
+                    ui.add(egui::DragValue::new(&mut self.settings.interleave).range(1..=sectors).suffix(":1"));
+                    ui.end_row();
+
+                    ui.label("Track skew:");
+                    ui.add(egui::DragValue::new(&mut self.settings.skew).range(0..=sectors - 1))
+                        .on_hover_text("Offset each track's first sector to allow for head step time.");
+                    ui.end_row();
+
+                    let max_cylinder = self.max_cylinder;
+                    let tracks = &mut self.settings.tracks;
+                    ui.label("Cylinders:");
+                    ui.horizontal(|ui| {
+                        let (first, last) = (tracks.first_cylinder, tracks.last_cylinder);
+                        ui.add(egui::DragValue::new(&mut tracks.first_cylinder).range(0..=last));
+                        ui.label("to");
+                        ui.add(egui::DragValue::new(&mut tracks.last_cylinder).range(first..=max_cylinder));
+                    });
+                    ui.end_row();
+
+                    ui.label("Heads:");
+                    egui::ComboBox::from_id_salt("copy_heads")
+                        .selected_text(tracks.heads.label())
+                        .show_ui(ui, |ui| {
+                            for heads in HeadSelection::ALL {
+                                ui.selectable_value(&mut tracks.heads, heads, heads.label());
+                            }
+                        });
+                    ui.end_row();
+                });
+
+                let tracks = &self.settings.tracks;
+                let selected_cylinders = tracks.last_cylinder - tracks.first_cylinder + 1;
+                if selected_cylinders > self.settings.geometry.cylinders {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!(
+                            "Only the first {} of the {} selected cylinders fit this geometry.",
+                            self.settings.geometry.cylinders, selected_cylinders
+                        ),
+                    );
+                }
+
+                let order = sector_order(self.settings.geometry.sectors, self.settings.interleave, 0);
+                ui.weak(format!(
+                    "Track 0 order: {}",
+                    order.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(" ")
+                ));
+
+                if ui.button("Copy").clicked() {
+                    start = Some(self.settings.clone());
+                }
+            });
+        self.open = open && start.is_none();
+        start
+    }
+}
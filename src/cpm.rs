@@ -0,0 +1,423 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A read-only CP/M 2.2 filesystem reader, for 8-bit platform disks. CP/M 3 disks read too; the
+//! label, password and date stamp entries it adds to the directory are skipped.
+//!
+//! CP/M disks don't describe their own layout; the BIOS of each machine holds a disk parameter
+//! block (DPB) for it. We carry presets for common formats, and pick the first one whose
+//! directory looks valid unless the user chooses another.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Error};
+use fluxfox::DiskImage;
+
+use crate::analysis::{read_all_sectors, SectorKey};
+use crate::storage;
+
+pub const CPM_DIR_ENTRY_SIZE: usize = 32;
+pub const CPM_RECORD_SIZE: usize = 128;
+/// Each logical extent maps 128 records (16K) of a file.
+pub const CPM_EXTENT_RECORDS: usize = 128;
+pub const CPM_EMPTY: u8 = 0xE5;
+pub const CPM_MAX_USER: u8 = 15;
+/// The highest first byte of the CP/M 3 entries that aren't files: passwords (16-31), the disc
+/// label (0x20) and date stamps (0x21).
+pub const CPM3_MAX_SPECIAL: u8 = 0x21;
+
+/// A disk parameter block, along with the physical layout the BIOS reads it from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DiskParams {
+    pub name: &'static str,
+    pub sectors_per_track: u8,
+    /// The ID of the first sector on each track.
+    pub first_sector: u8,
+    pub sector_size: usize,
+    pub heads: u8,
+    pub block_size: usize,
+    /// The highest block number (DSM).
+    pub max_block: usize,
+    /// The highest directory entry number (DRM).
+    pub max_dir_entry: usize,
+    /// Reserved system tracks before the directory (OFF).
+    pub reserved_tracks: usize,
+    /// The BIOS sector skew, as a step between logical sectors. 1 means no skew.
+    pub skew: usize,
+}
+
+pub const DPB_PRESETS: [DiskParams; 5] = [
+    DiskParams {
+        name: "IBM 8\" SSSD (CP/M 2.2 standard)",
+        sectors_per_track: 26,
+        first_sector: 1,
+        sector_size: 128,
+        heads: 1,
+        block_size: 1024,
+        max_block: 242,
+        max_dir_entry: 63,
+        reserved_tracks: 2,
+        skew: 6,
+    },
+    DiskParams {
+        name: "Kaypro II (5.25\" SSDD)",
+        sectors_per_track: 10,
+        first_sector: 0,
+        sector_size: 512,
+        heads: 1,
+        block_size: 1024,
+        max_block: 194,
+        max_dir_entry: 63,
+        reserved_tracks: 1,
+        skew: 1,
+    },
+    DiskParams {
+        name: "Amstrad CPC data",
+        sectors_per_track: 9,
+        first_sector: 0xC1,
+        sector_size: 512,
+        heads: 1,
+        block_size: 1024,
+        max_block: 179,
+        max_dir_entry: 63,
+        reserved_tracks: 0,
+        skew: 1,
+    },
+    DiskParams {
+        name: "Amstrad CPC system",
+        sectors_per_track: 9,
+        first_sector: 0x41,
+        sector_size: 512,
+        heads: 1,
+        block_size: 1024,
+        max_block: 170,
+        max_dir_entry: 63,
+        reserved_tracks: 2,
+        skew: 1,
+    },
+    DiskParams {
+        name: "Amstrad PCW / Spectrum +3",
+        sectors_per_track: 9,
+        first_sector: 1,
+        sector_size: 512,
+        heads: 1,
+        block_size: 1024,
+        max_block: 174,
+        max_dir_entry: 63,
+        reserved_tracks: 1,
+        skew: 1,
+    },
+];
+
+impl DiskParams {
+    /// The order in which a track's physical sectors hold logical sectors, as indices from
+    /// the first sector. Skews that share a factor with the track length step on by one
+    /// sector each time they wrap onto a sector already used, as the standard tables do.
+    pub fn skew_table(&self) -> Vec<usize> {
+        let n = self.sectors_per_track as usize;
+        let mut table = Vec::with_capacity(n);
+        let mut used = vec![false; n];
+        let mut pos = 0;
+        for _ in 0..n {
+            while used[pos] {
+                pos = (pos + 1) % n;
+            }
+            table.push(pos);
+            used[pos] = true;
+            pos = (pos + self.skew) % n;
+        }
+        table
+    }
+
+    fn dir_blocks(&self) -> usize {
+        ((self.max_dir_entry + 1) * CPM_DIR_ENTRY_SIZE).div_ceil(self.block_size)
+    }
+
+    /// Whether block numbers in directory entries are 16 bits wide.
+    fn wide_blocks(&self) -> bool {
+        self.max_block > 255
+    }
+}
+
+/// The data area of a CP/M disk, from the first track after the reserved tracks, in logical
+/// sector order.
+pub struct CpmVolume {
+    pub params: DiskParams,
+    pub data: Vec<u8>,
+    /// The physical sector holding each logical sector of `data`, if it was found.
+    pub sectors: Vec<Option<SectorKey>>,
+}
+
+impl CpmVolume {
+    pub fn new(disk: &mut DiskImage, params: DiskParams) -> Self {
+        let reads: HashMap<SectorKey, Vec<u8>> =
+            read_all_sectors(disk).into_iter().map(|read| (read.key, read.data)).collect();
+        Self::from_reads(&reads, params)
+    }
+
+    fn from_reads(reads: &HashMap<SectorKey, Vec<u8>>, params: DiskParams) -> Self {
+        let spt = params.sectors_per_track as usize;
+        let skew = params.skew_table();
+        let total_bytes = (params.max_block + 1) * params.block_size;
+        let total_sectors = total_bytes.div_ceil(params.sector_size);
+
+        let mut volume = Self {
+            params,
+            data: vec![CPM_EMPTY; total_sectors * params.sector_size],
+            sectors: vec![None; total_sectors],
+        };
+        for logical in 0..total_sectors {
+            let track = params.reserved_tracks + logical / spt;
+            let physical = skew[logical % spt];
+            let key = SectorKey {
+                c: (track / params.heads as usize) as u16,
+                h: (track % params.heads as usize) as u8,
+                s: params.first_sector.wrapping_add(physical as u8),
+            };
+            if let Some(data) = reads.get(&key) {
+                let len = data.len().min(params.sector_size);
+                let start = logical * params.sector_size;
+                volume.data[start..start + len].copy_from_slice(&data[..len]);
+                volume.sectors[logical] = Some(key);
+            }
+        }
+        volume
+    }
+
+    /// Try each preset in turn, returning the first whose directory parses.
+    pub fn detect(disk: &mut DiskImage) -> Result<(Self, Vec<CpmFile>), Error> {
+        let reads: HashMap<SectorKey, Vec<u8>> =
+            read_all_sectors(disk).into_iter().map(|read| (read.key, read.data)).collect();
+        for params in DPB_PRESETS {
+            let volume = Self::from_reads(&reads, params);
+            if let Ok(files) = volume.directory() {
+                if !files.is_empty() {
+                    return Ok((volume, files));
+                }
+            }
+        }
+        Err(anyhow!("No CP/M directory found with any known disk format"))
+    }
+
+    pub fn block(&self, block: usize) -> Option<&[u8]> {
+        self.data
+            .get(block * self.params.block_size..(block + 1) * self.params.block_size)
+    }
+
+    /// Parse the directory into files, merging each file's extents. Fails if any entry is
+    /// implausible, which is how a wrong DPB is usually noticed.
+    pub fn directory(&self) -> Result<Vec<CpmFile>, Error> {
+        let dir_len = (self.params.max_dir_entry + 1) * CPM_DIR_ENTRY_SIZE;
+        if self.sectors.iter().take(dir_len.div_ceil(self.params.sector_size)).all(|s| s.is_none()) {
+            return Err(anyhow!("Directory sectors not found"));
+        }
+
+        let mut files: BTreeMap<(u8, String), CpmFile> = BTreeMap::new();
+        for entry in self.data[..dir_len].chunks(CPM_DIR_ENTRY_SIZE) {
+            let user = entry[0];
+            if user == CPM_EMPTY {
+                continue;
+            }
+            // No files to list, but CP/M 3 disks are otherwise read like CP/M 2.2 ones.
+            if (CPM_MAX_USER + 1..=CPM3_MAX_SPECIAL).contains(&user) {
+                continue;
+            }
+            if user > CPM_MAX_USER {
+                return Err(anyhow!("Invalid user number {}", user));
+            }
+
+            let name_bytes: Vec<u8> = entry[1..12].iter().map(|b| b & 0x7F).collect();
+            if !name_bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+                return Err(anyhow!("Invalid filename in directory"));
+            }
+            let name = String::from_utf8_lossy(&name_bytes[..8]).trim_end().to_string();
+            let ext = String::from_utf8_lossy(&name_bytes[8..]).trim_end().to_string();
+            let filename = if ext.is_empty() { name } else { format!("{}.{}", name, ext) };
+
+            let extent = ((entry[14] as usize & 0x3F) << 5) | (entry[12] as usize & 0x1F);
+            let records = entry[15] as usize;
+            let blocks: Vec<usize> = if self.params.wide_blocks() {
+                entry[16..32]
+                    .chunks(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as usize)
+                    .collect()
+            }
+            else {
+                entry[16..32].iter().map(|b| *b as usize).collect()
+            };
+            let blocks: Vec<usize> = blocks.into_iter().filter(|block| *block != 0).collect();
+            if blocks.iter().any(|block| *block > self.params.max_block || *block < self.params.dir_blocks()) {
+                return Err(anyhow!("Block number out of range in {}", filename));
+            }
+
+            let file = files.entry((user, filename.clone())).or_insert_with(|| CpmFile {
+                user,
+                name: filename,
+                read_only: entry[9] & 0x80 != 0,
+                system: entry[10] & 0x80 != 0,
+                extents: BTreeMap::new(),
+            });
+            file.extents.insert(extent, (records, blocks));
+        }
+        Ok(files.into_values().collect())
+    }
+
+    pub fn read_file(&self, file: &CpmFile) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (_, blocks) in file.extents.values() {
+            for block in blocks {
+                data.extend_from_slice(self.block(*block).unwrap_or(&[]));
+            }
+        }
+        data.truncate(file.size());
+        data
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CpmFile {
+    pub user: u8,
+    pub name: String,
+    pub read_only: bool,
+    pub system: bool,
+    /// Record count and blocks of each extent, by extent number.
+    pub extents: BTreeMap<usize, (usize, Vec<usize>)>,
+}
+
+impl CpmFile {
+    /// The file size, rounded up to whole records as CP/M doesn't record exact sizes.
+    pub fn size(&self) -> usize {
+        match self.extents.iter().next_back() {
+            Some((extent, (records, _))) => (extent * CPM_EXTENT_RECORDS + records) * CPM_RECORD_SIZE,
+            None => 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CpmBrowser {
+    volume: Option<CpmVolume>,
+    files: Vec<CpmFile>,
+    error: Option<String>,
+}
+
+impl CpmBrowser {
+    pub fn clear(&mut self) {
+        self.volume = None;
+        self.files.clear();
+        self.error = None;
+    }
+
+    pub fn load(&mut self, disk: &mut DiskImage) {
+        self.clear();
+        match CpmVolume::detect(disk) {
+            Ok((volume, files)) => {
+                log::info!("Mounted CP/M volume as {} with {} files", volume.params.name, files.len());
+                self.volume = Some(volume);
+                self.files = files;
+            }
+            Err(e) => {
+                log::info!("No CP/M filesystem found: {}", e);
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn load_with(&mut self, disk: &mut DiskImage, params: DiskParams) {
+        let volume = CpmVolume::new(disk, params);
+        match volume.directory() {
+            Ok(files) => {
+                self.files = files;
+                self.error = None;
+            }
+            Err(e) => {
+                self.files.clear();
+                self.error = Some(e.to_string());
+            }
+        }
+        self.volume = Some(volume);
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &mut DiskImage) {
+        let current = self.volume.as_ref().map(|volume| volume.params);
+        let title = match current {
+            Some(params) => format!("CP/M filesystem: {}", params.name),
+            None => "CP/M filesystem".to_string(),
+        };
+
+        egui::CollapsingHeader::new(title).id_salt("cpm_browser").show(ui, |ui| {
+            let mut chosen = current;
+            ui.horizontal(|ui| {
+                ui.label("Disk format:");
+                egui::ComboBox::from_id_salt("cpm_dpb")
+                    .selected_text(chosen.map_or("None found", |params| params.name))
+                    .show_ui(ui, |ui| {
+                        for params in DPB_PRESETS {
+                            ui.selectable_value(&mut chosen, Some(params), params.name);
+                        }
+                    });
+            });
+            if let Some(params) = chosen.filter(|params| Some(*params) != current) {
+                self.load_with(disk, params);
+            }
+
+            if let Some(error) = &self.error {
+                ui.weak(error);
+            }
+            let Some(volume) = &self.volume
+            else {
+                return;
+            };
+
+            egui::Grid::new("cpm_files_grid").striped(true).num_columns(4).show(ui, |ui| {
+                ui.strong("User");
+                ui.strong("Name");
+                ui.strong("Size");
+                ui.strong("Attributes");
+                ui.end_row();
+                for file in self.files.iter() {
+                    ui.label(file.user.to_string());
+                    if ui.link(&file.name).on_hover_text("Click to save").clicked() {
+                        if let Err(e) = storage::download_bytes(&volume.read_file(file), &file.name) {
+                            log::error!("Error downloading {}: {:?}", file.name, e);
+                        }
+                    }
+                    ui.label(file.size().to_string());
+                    let mut attributes = Vec::new();
+                    if file.read_only {
+                        attributes.push("R/O");
+                    }
+                    if file.system {
+                        attributes.push("SYS");
+                    }
+                    ui.label(attributes.join(" "));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,108 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Reports of crashed background tasks, shown in a dialog the user can copy from when filing
+//! a bug.
+
+use crate::tasks::TaskKind;
+
+pub struct CrashReport {
+    pub kind: TaskKind,
+    pub task: String,
+    pub image: Option<String>,
+    pub panic: String,
+    /// Set if the task held the disk image, which went down with the worker.
+    pub lost_image: bool,
+}
+
+impl CrashReport {
+    /// The report as plain text, for pasting into an issue.
+    pub fn text(&self) -> String {
+        let user_agent = web_sys::window()
+            .and_then(|window| window.navigator().user_agent().ok())
+            .unwrap_or_else(|| "unknown".to_string());
+        format!(
+            "ffweb {} crash report\nTask: {} ({})\nImage: {}\nUser agent: {}\nPanic: {}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.kind.label(),
+            self.task,
+            self.image.as_deref().unwrap_or("none"),
+            user_agent,
+            self.panic
+        )
+    }
+}
+
+/// A dialog listing the crashes of this session until dismissed.
+#[derive(Default)]
+pub struct CrashDialog {
+    reports: Vec<CrashReport>,
+}
+
+impl CrashDialog {
+    pub fn push(&mut self, report: CrashReport) {
+        self.reports.push(report);
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if self.reports.is_empty() {
+            return;
+        }
+
+        let mut open = true;
+        egui::Window::new("Background task crashed")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.label("A background task stopped unexpectedly. The report below can be copied into a bug report.");
+                for (i, report) in self.reports.iter().enumerate() {
+                    ui.separator();
+                    ui.strong(format!("{}: {}", report.kind.label(), report.task));
+                    if report.lost_image {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            "The task was holding the disk image, which was lost. Drop the image again to continue.",
+                        );
+                    }
+                    let mut text = report.text();
+                    egui::ScrollArea::vertical()
+                        .id_salt(("crash_report", i))
+                        .max_height(160.0)
+                        .show(ui, |ui| {
+                            ui.add(egui::TextEdit::multiline(&mut text).code_editor().desired_width(f32::INFINITY));
+                        });
+                    if ui.button("Copy report").clicked() {
+                        ui.ctx().copy_text(report.text());
+                    }
+                }
+            });
+        if !open {
+            self.reports.clear();
+        }
+    }
+}
@@ -0,0 +1,269 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Multi-disk sets. Software that shipped on several disks is usually preserved as one image
+//! per disk, named "Disk 1", "Disk 2" and so on. Images dropped with such names are grouped
+//! into a set that can be switched between, keeping each disk's annotations while another is
+//! shown, with notes for the whole set and a combined report.
+
+use std::sync::Arc;
+
+use crate::annotations::Annotation;
+use crate::bookmarks::Bookmark;
+use crate::report::ImageReport;
+
+/// Disks of a set arriving within this many seconds of each other are taken as one drop,
+/// and are added to the set without replacing the disk being shown.
+pub const DROP_GESTURE_SECS: f64 = 2.0;
+
+/// Characters separating a set's title from its disk numbers.
+const SEPARATORS: &[char] = &[' ', '_', '-', '.', '#', '(', '[', ')', ']', ','];
+
+/// Split a file name into the title of the set it belongs to and its disk number, if it's
+/// named like a disk of a set: "Game (Disk 2).img", "game_disk2.imd" or "GAME2.IMG".
+pub fn split_disk_name(name: &str) -> Option<(String, u32)> {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let lower = stem.to_ascii_lowercase();
+
+    let marker = ["disk", "disc"].iter().filter_map(|marker| lower.rfind(marker)).max();
+    let (title, digits) = match marker {
+        Some(pos) => {
+            let rest = stem[pos + 4..].trim_start_matches(SEPARATORS);
+            let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            (&stem[..pos], &rest[..end])
+        }
+        None => {
+            let start = stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            (&stem[..start], &stem[start..])
+        }
+    };
+    let number = digits.parse().ok()?;
+    let title = title.trim_matches(SEPARATORS);
+    if title.is_empty() {
+        return None;
+    }
+    Some((title.to_string(), number))
+}
+
+pub struct SetMember {
+    pub number: u32,
+    pub name: String,
+    bytes: Arc<[u8]>,
+    /// The annotations and bookmarks made on this disk, kept while another disk is shown.
+    annotations: Vec<Annotation>,
+    bookmarks: Vec<Bookmark>,
+    /// The disk's report, as of the last time it was shown.
+    report: Option<ImageReport>,
+}
+
+pub enum DiskSetAction {
+    /// Show the member with the specified index.
+    Switch(usize),
+    Report,
+}
+
+#[derive(serde::Serialize)]
+struct MemberReport<'a> {
+    number: u32,
+    name: &'a str,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<&'a ImageReport>,
+}
+
+#[derive(serde::Serialize)]
+struct SetReport<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    notes: &'a str,
+    disks: Vec<MemberReport<'a>>,
+}
+
+pub struct DiskSet {
+    /// The set's title, from the file names of its disks.
+    pub title: String,
+    /// Members in disk number order.
+    pub members: Vec<SetMember>,
+    /// The index of the member being shown.
+    pub current: usize,
+    /// Notes on the set as a whole, kept across switching disks.
+    pub notes: String,
+    /// When the last member arrived, to tell members dropped together.
+    last_added: f64,
+}
+
+impl DiskSet {
+    /// Start a set with the disk `name`, or None if it isn't named like a disk of a set.
+    pub fn new(name: &str, bytes: Arc<[u8]>, now: f64) -> Option<Self> {
+        let (title, number) = split_disk_name(name)?;
+        Some(Self {
+            title,
+            members: vec![SetMember {
+                number,
+                name: name.to_string(),
+                bytes,
+                annotations: Vec::new(),
+                bookmarks: Vec::new(),
+                report: None,
+            }],
+            current: 0,
+            notes: String::new(),
+            last_added: now,
+        })
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.members.iter().position(|member| member.name == name)
+    }
+
+    /// Whether `name` is another disk of this set.
+    pub fn accepts(&self, name: &str) -> bool {
+        match split_disk_name(name) {
+            Some((title, number)) => {
+                title.eq_ignore_ascii_case(&self.title) && !self.members.iter().any(|member| member.number == number)
+            }
+            None => false,
+        }
+    }
+
+    /// Add the disk `name`, returning its index and whether it arrived with the set's other
+    /// disks, in which case it shouldn't replace the one being shown.
+    pub fn add(&mut self, name: &str, bytes: Arc<[u8]>, now: f64) -> (usize, bool) {
+        let number = split_disk_name(name).map_or(0, |(_, number)| number);
+        let index = self.members.partition_point(|member| member.number < number);
+        self.members.insert(
+            index,
+            SetMember {
+                number,
+                name: name.to_string(),
+                bytes,
+                annotations: Vec::new(),
+                bookmarks: Vec::new(),
+                report: None,
+            },
+        );
+        if index <= self.current {
+            self.current += 1;
+        }
+        let same_drop = now - self.last_added < DROP_GESTURE_SECS;
+        self.last_added = now;
+        (index, same_drop)
+    }
+
+    /// The member with the specified index, as a dropped file to load it through the usual path.
+    pub fn file(&self, index: usize) -> Option<egui::DroppedFile> {
+        let member = self.members.get(index)?;
+        Some(egui::DroppedFile {
+            name: member.name.clone(),
+            bytes: Some(member.bytes.clone()),
+            ..Default::default()
+        })
+    }
+
+    /// Keep the work done on the current member while another is shown.
+    pub fn stash(&mut self, annotations: Vec<Annotation>, bookmarks: Vec<Bookmark>, report: Option<ImageReport>) {
+        if let Some(member) = self.members.get_mut(self.current) {
+            member.annotations = annotations;
+            member.bookmarks = bookmarks;
+            if report.is_some() {
+                member.report = report;
+            }
+        }
+    }
+
+    /// Take back the annotations and bookmarks kept for the current member.
+    pub fn unstash(&mut self) -> (Vec<Annotation>, Vec<Bookmark>) {
+        match self.members.get_mut(self.current) {
+            Some(member) => (std::mem::take(&mut member.annotations), std::mem::take(&mut member.bookmarks)),
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// A JSON report of the whole set. `current` is the report of the disk being shown, which
+    /// is more recent than any kept for it.
+    pub fn report_json(&self, current: Option<&ImageReport>) -> Result<String, serde_json::Error> {
+        let disks = self
+            .members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| MemberReport {
+                number: member.number,
+                name: &member.name,
+                size: member.bytes.len(),
+                report: if i == self.current { current.or(member.report.as_ref()) } else { member.report.as_ref() },
+            })
+            .collect();
+        serde_json::to_string_pretty(&SetReport {
+            title: &self.title,
+            notes: &self.notes,
+            disks,
+        })
+    }
+
+    pub fn report_filename(&self) -> String {
+        format!("{}.set.report.json", self.title.replace(' ', "_"))
+    }
+
+    /// Show the disk changer, once the set has more than one disk.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<DiskSetAction> {
+        if self.members.len() < 2 {
+            return None;
+        }
+
+        let mut action = None;
+        ui.group(|ui| {
+            ui.horizontal_wrapped(|ui| {
+                ui.strong(format!("Disk set: {}", self.title));
+                for (i, member) in self.members.iter().enumerate() {
+                    if ui
+                        .selectable_label(i == self.current, format!("Disk {}", member.number))
+                        .on_hover_text(&member.name)
+                        .clicked()
+                        && i != self.current
+                    {
+                        action = Some(DiskSetAction::Switch(i));
+                    }
+                }
+                ui.separator();
+                if ui
+                    .button("Download set report")
+                    .on_hover_text("Disks that haven't been shown yet are listed without analysis.")
+                    .clicked()
+                {
+                    action = Some(DiskSetAction::Report);
+                }
+            });
+            ui.label("Set notes:");
+            ui.add(
+                egui::TextEdit::multiline(&mut self.notes)
+                    .desired_rows(2)
+                    .hint_text("Notes shared by every disk in the set"),
+            );
+        });
+        action
+    }
+}
@@ -0,0 +1,136 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Dragging files out of the page to the desktop.
+//!
+//! egui draws everything to one canvas, so there are no DOM elements to drag. Instead, the
+//! canvas itself is made draggable while the pointer is over an item that can be dragged out,
+//! and its `dragstart` handler hands the browser that item's contents. The data is offered as
+//! a `DownloadURL`, which Chromium-based browsers save as a file where it is dropped. Other
+//! browsers ignore it, and the drag does nothing.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{DragEvent, HtmlCanvasElement};
+
+use crate::gl_context::CANVAS_ID;
+use crate::storage;
+
+struct DragPayload {
+    /// Identifies the item the payload was made for, so it's only rebuilt when that changes.
+    key: String,
+    filename: String,
+    url: String,
+}
+
+impl Drop for DragPayload {
+    fn drop(&mut self) {
+        if let Err(e) = web_sys::Url::revoke_object_url(&self.url) {
+            log::warn!("DragPayload: Failed to revoke object URL: {:?}", e);
+        }
+    }
+}
+
+pub struct DragOut {
+    canvas: HtmlCanvasElement,
+    payload: Rc<RefCell<Option<DragPayload>>>,
+    armed: bool,
+}
+
+impl DragOut {
+    pub fn install() -> Result<Self, JsValue> {
+        let canvas: HtmlCanvasElement = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(CANVAS_ID))
+            .ok_or_else(|| JsValue::from_str("No canvas"))?
+            .dyn_into()?;
+
+        let payload: Rc<RefCell<Option<DragPayload>>> = Rc::new(RefCell::new(None));
+        let start_payload = payload.clone();
+        let on_drag_start = Closure::<dyn FnMut(DragEvent)>::new(move |event: DragEvent| {
+            let payload = start_payload.borrow();
+            match (payload.as_ref(), event.data_transfer()) {
+                (Some(payload), Some(transfer)) => {
+                    let download = format!("application/octet-stream:{}:{}", payload.filename, payload.url);
+                    if let Err(e) = transfer.set_data("DownloadURL", &download) {
+                        log::warn!("DragOut: Failed to set drag data: {:?}", e);
+                    }
+                    transfer.set_effect_allowed("copy");
+                }
+                _ => event.prevent_default(),
+            }
+        });
+        canvas.add_event_listener_with_callback("dragstart", on_drag_start.as_ref().unchecked_ref())?;
+        // The handler lives as long as the canvas, which is the lifetime of the app.
+        on_drag_start.forget();
+
+        Ok(Self {
+            canvas,
+            payload,
+            armed: false,
+        })
+    }
+
+    /// Call at the start of each frame. Items arm the drag again each frame they're hovered.
+    pub fn begin_frame(&mut self) {
+        self.armed = false;
+    }
+
+    /// Offer the item identified by `key` for dragging out as `filename`. `data` is only called
+    /// when the hovered item changes, as the contents must be ready before a drag starts.
+    pub fn arm(&mut self, key: &str, filename: &str, data: impl FnOnce() -> Vec<u8>) {
+        self.armed = true;
+        let mut payload = self.payload.borrow_mut();
+        if payload.as_ref().is_some_and(|payload| payload.key == key) {
+            return;
+        }
+
+        let url = storage::bytes_to_blob(&data()).and_then(|blob| web_sys::Url::create_object_url_with_blob(&blob));
+        *payload = match url {
+            Ok(url) => Some(DragPayload {
+                key: key.to_string(),
+                filename: filename.to_string(),
+                url,
+            }),
+            Err(e) => {
+                log::error!("DragOut: Failed to create drag payload for {}: {:?}", filename, e);
+                None
+            }
+        };
+    }
+
+    /// Call at the end of each frame to make the canvas draggable only while an item is armed,
+    /// so other drags keep going to egui.
+    pub fn end_frame(&mut self) {
+        if self.canvas.draggable() != self.armed {
+            self.canvas.set_draggable(self.armed);
+        }
+    }
+}
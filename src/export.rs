@@ -0,0 +1,506 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use wasm_bindgen::JsValue;
+use fluxfox::file_parsers::ImageParser;
+use fluxfox::visualization::collect_streams;
+use fluxfox::{DiskDataResolution, DiskImage, DiskImageFileFormat};
+
+use crate::analysis::geometry::{LayoutSummary, StandardGeometry};
+use crate::analysis::trim::TrimAnalysis;
+use crate::storage::{ScratchFile, StoredFile};
+use crate::tasks::{TaskHandle, TaskOutput};
+use crate::worker;
+
+/// A format the current disk image can be written as.
+#[derive(Clone, Debug)]
+pub struct ExportFormat {
+    pub format: DiskImageFileFormat,
+    pub extensions: Vec<String>,
+}
+
+impl ExportFormat {
+    pub fn label(&self) -> String {
+        match self.extensions.first() {
+            Some(ext) => format!("{:?} (.{})", self.format, ext),
+            None => format!("{:?}", self.format),
+        }
+    }
+
+    /// The fluxfox name of the format, as stored in presets.
+    pub fn format_name(&self) -> String {
+        format!("{:?}", self.format)
+    }
+
+    fn extension(&self) -> &str {
+        self.extensions.first().map(|ext| ext.as_str()).unwrap_or("")
+    }
+}
+
+fn source_stem(source_name: &str) -> &str {
+    source_name
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(source_name)
+}
+
+pub const DEFAULT_NAMING: &str = "{stem}.{ext}";
+/// The number of hex digits of the source's SHA-1 used for `{hash}`.
+pub const NAMING_HASH_LEN: usize = 8;
+
+/// Characters that aren't allowed in filenames on common platforms.
+const FILENAME_RESERVED: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// The values substituted into a filename pattern for the loaded image.
+#[derive(Clone, Debug, Default)]
+pub struct NameFields {
+    pub source_name: String,
+    /// The geometry, as the standard size if there is one (`360K`), otherwise as cylinders,
+    /// heads and sectors per track (`42x2x9`).
+    pub geometry: String,
+    /// The start of the source file's SHA-1, if the image came from a file.
+    pub hash: Option<String>,
+}
+
+impl NameFields {
+    pub fn new(source_name: &str, disk: &DiskImage, sha1: Option<&str>) -> Self {
+        let layout = LayoutSummary::from_disk(disk);
+        let geometry = match layout.standard_geometry() {
+            Some(geometry) => geometry_label(geometry),
+            None => format!("{}x{}x{}", layout.formatted_cylinders, layout.heads, layout.max_sectors),
+        };
+        Self {
+            source_name: source_name.to_string(),
+            geometry,
+            hash: sha1.map(|sha1| sha1.chars().take(NAMING_HASH_LEN).collect()),
+        }
+    }
+}
+
+/// A standard geometry as it's substituted for `{geometry}`: its size, such as `360K`.
+pub fn geometry_label(geometry: &StandardGeometry) -> String {
+    // 8" geometries are spelled out, as quotes don't belong in filenames.
+    geometry.name.trim_start_matches("PC ").replace('"', "in")
+}
+
+/// Expand a filename pattern. `{stem}` is the source's name without its extension, `{ext}`
+/// the format's extension, `{geometry}` the disk geometry and `{hash}` the start of the
+/// source's SHA-1. A `{hash}` with no value is dropped along with the separator before it.
+pub fn expand_naming(pattern: &str, format: &ExportFormat, fields: &NameFields) -> String {
+    let mut name = pattern.to_string();
+    match &fields.hash {
+        Some(hash) => name = name.replace("{hash}", hash),
+        None => {
+            for separator in ["_", "-", " ", "."] {
+                name = name.replace(&format!("{}{{hash}}", separator), "");
+            }
+            name = name.replace("{hash}", "");
+        }
+    }
+    name.replace("{stem}", source_stem(&fields.source_name))
+        .replace("{ext}", format.extension())
+        .replace("{geometry}", &fields.geometry)
+        .chars()
+        .map(|c| if FILENAME_RESERVED.contains(&c) { '_' } else { c })
+        .collect()
+}
+
+/// The filename pattern for exports made without a preset.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ExportNaming {
+    pub pattern: String,
+}
+
+impl Default for ExportNaming {
+    fn default() -> Self {
+        Self {
+            pattern: DEFAULT_NAMING.to_string(),
+        }
+    }
+}
+
+impl ExportNaming {
+    pub fn filename(&self, format: &ExportFormat, fields: &NameFields) -> String {
+        let pattern = if self.pattern.trim().is_empty() { DEFAULT_NAMING } else { &self.pattern };
+        expand_naming(pattern, format, fields)
+    }
+
+    /// Show the pattern editor, with the name it gives `example` for the loaded image.
+    pub fn show(&mut self, ui: &mut egui::Ui, example: Option<(&ExportFormat, &NameFields)>) {
+        ui.horizontal(|ui| {
+            ui.label("File name:");
+            ui.text_edit_singleline(&mut self.pattern).on_hover_text(NAMING_HELP);
+            if ui.small_button("Reset").clicked() {
+                *self = ExportNaming::default();
+            }
+        });
+        if let Some((format, fields)) = example {
+            ui.weak(self.filename(format, fields));
+        }
+    }
+}
+
+pub const NAMING_HELP: &str =
+    "{stem} is the loaded image's name, {ext} the format's extension, {geometry} the disk geometry \
+     and {hash} the start of the loaded file's SHA-1.";
+
+/// Export settings saved by the user for reuse.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ExportPreset {
+    pub name: String,
+    /// The output format, by its fluxfox name.
+    pub format: String,
+    /// The output filename pattern, expanded by [expand_naming].
+    pub naming: String,
+    /// Also download the image metadata sidecar.
+    pub sidecar: bool,
+}
+
+impl Default for ExportPreset {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            format: String::new(),
+            naming: DEFAULT_NAMING.to_string(),
+            sidecar: true,
+        }
+    }
+}
+
+impl ExportPreset {
+    /// Find the preset's format among those the loaded image can be written as.
+    pub fn resolve(&self, formats: &[ExportFormat]) -> Result<ExportFormat, String> {
+        formats
+            .iter()
+            .find(|format| format.format_name() == self.format)
+            .cloned()
+            .ok_or_else(|| format!("This image can't be written as {} without losing data.", self.format))
+    }
+
+    pub fn filename(&self, format: &ExportFormat, fields: &NameFields) -> String {
+        expand_naming(&self.naming, format, fields)
+    }
+}
+
+/// Settings for processing every image as it is loaded: once decoded and analyzed, the image is
+/// converted with an export preset and downloaded along with its report.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct WatchMode {
+    pub enabled: bool,
+    /// The name of the export preset to convert with, if any.
+    pub preset: Option<String>,
+    pub report: bool,
+}
+
+impl WatchMode {
+    pub fn show(&mut self, ui: &mut egui::Ui, presets: &[ExportPreset]) {
+        ui.checkbox(&mut self.enabled, "Process images on load")
+            .on_hover_text("Your browser may ask once whether to allow multiple downloads.");
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Convert with:");
+                egui::ComboBox::from_id_salt("watch_mode_preset")
+                    .selected_text(self.preset.as_deref().unwrap_or("Don't convert"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.preset, None, "Don't convert");
+                        for preset in presets {
+                            ui.selectable_value(&mut self.preset, Some(preset.name.clone()), &preset.name);
+                        }
+                    });
+            });
+            if presets.is_empty() {
+                ui.weak("Save an export preset to convert images on load.");
+            }
+            ui.checkbox(&mut self.report, "Download report");
+        });
+    }
+}
+
+/// A window for saving a new export preset.
+#[derive(Default)]
+pub struct PresetEditor {
+    pub open: bool,
+    draft: ExportPreset,
+}
+
+impl PresetEditor {
+    pub fn open(&mut self, formats: &[ExportFormat]) {
+        self.draft = ExportPreset {
+            format: formats.first().map(|format| format.format_name()).unwrap_or_default(),
+            ..ExportPreset::default()
+        };
+        self.open = true;
+    }
+
+    /// Show the editor. Returns the preset when the user saves it.
+    pub fn show(&mut self, ctx: &egui::Context, formats: &[ExportFormat]) -> Option<ExportPreset> {
+        let mut saved = None;
+        let mut open = self.open;
+        egui::Window::new("Save export preset")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("preset_editor_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Name:");
+                    ui.text_edit_singleline(&mut self.draft.name);
+                    ui.end_row();
+
+                    ui.label("Format:");
+                    egui::ComboBox::from_id_salt("preset_editor_format")
+                        .selected_text(&self.draft.format)
+                        .show_ui(ui, |ui| {
+                            for format in formats {
+                                ui.selectable_value(&mut self.draft.format, format.format_name(), format.label());
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("File name:");
+                    ui.text_edit_singleline(&mut self.draft.naming).on_hover_text(NAMING_HELP);
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.checkbox(&mut self.draft.sidecar, "Download metadata sidecar");
+                    ui.end_row();
+                });
+
+                let valid = !self.draft.name.trim().is_empty() && !self.draft.naming.trim().is_empty();
+                if ui.add_enabled(valid, egui::Button::new("Save")).clicked() {
+                    saved = Some(self.draft.clone());
+                }
+            });
+        self.open = open && saved.is_none();
+        saved
+    }
+}
+
+/// Allowances for the headers of each sector and track in [estimated_size].
+const SIZE_SECTOR_OVERHEAD: usize = 16;
+const SIZE_TRACK_OVERHEAD: usize = 256;
+
+/// What an emulator preset does with the image's geometry.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GeometryRule {
+    /// The image is written as it is, whatever its layout.
+    Keep,
+    /// The emulator only accepts images with a standard PC geometry, which it recognizes by the
+    /// image's size. Images with unformatted cylinders past the end of one are trimmed to it;
+    /// other layouts are rejected rather than silently truncated.
+    Standard,
+}
+
+/// One-click export settings targeting a specific emulator.
+pub struct EmulatorPreset {
+    pub name: &'static str,
+    pub format: DiskImageFileFormat,
+    pub extension: &'static str,
+    /// The output filename pattern, expanded by [expand_naming].
+    pub naming: &'static str,
+    pub geometry: GeometryRule,
+}
+
+/// The presets offered in the export menu. There's none for WinUAE, as fluxfox can't write the
+/// ADF images it expects.
+pub const EMULATOR_PRESETS: [EmulatorPreset; 2] = [
+    // 86F keeps the bitstream, so copy protection and odd layouts survive. fluxfox's 86F writer
+    // takes no settings; it fills in the encoding, data rate and RPM from the image.
+    EmulatorPreset {
+        name: "86Box",
+        format: DiskImageFileFormat::F86Image,
+        extension: "86f",
+        naming: "{stem}.86f",
+        geometry: GeometryRule::Keep,
+    },
+    // Both pick the drive type from the image size, so the geometry goes in the name to tell
+    // the same disk's dumps apart.
+    EmulatorPreset {
+        name: "PCem / DOSBox-X",
+        format: DiskImageFileFormat::RawSectorImage,
+        extension: "img",
+        naming: "{stem}_{geometry}.img",
+        geometry: GeometryRule::Standard,
+    },
+];
+
+/// Preset used to hand images to the in-browser boot test.
+pub const BOOT_TEST_PRESET: EmulatorPreset = EmulatorPreset {
+    name: "v86",
+    format: DiskImageFileFormat::RawSectorImage,
+    extension: "img",
+    naming: DEFAULT_NAMING,
+    geometry: GeometryRule::Standard,
+};
+
+/// An emulator preset resolved against the loaded image.
+pub struct EmulatorExport {
+    pub format: ExportFormat,
+    /// The standard geometry the written image is cut to, if it has trailing cylinders past it.
+    pub trimmed: Option<StandardGeometry>,
+}
+
+impl EmulatorPreset {
+    /// Resolve this preset against the loaded image, returning how to export it or the reason
+    /// the preset can't be used.
+    pub fn resolve(&self, disk: &DiskImage, formats: &[ExportFormat]) -> Result<EmulatorExport, String> {
+        let Some(export_format) = formats.iter().find(|f| f.format == self.format)
+        else {
+            return Err(format!("This image can't be written as {:?} without losing data.", self.format));
+        };
+
+        let trimmed = match self.geometry {
+            GeometryRule::Keep => None,
+            GeometryRule::Standard => {
+                let layout = LayoutSummary::from_disk(disk);
+                match layout.standard_geometry() {
+                    Some(_) => None,
+                    None => match TrimAnalysis::from_disk(disk, None) {
+                        Some(trim) if !trim.trims_formatted() => Some(trim.geometry),
+                        _ => {
+                            return Err(format!(
+                                "{} requires a standard PC geometry; this image has {}.",
+                                self.name, layout
+                            ))
+                        }
+                    },
+                }
+            }
+        };
+
+        Ok(EmulatorExport {
+            format: ExportFormat {
+                format: self.format,
+                extensions: std::iter::once(self.extension.to_string())
+                    .chain(export_format.extensions.iter().cloned())
+                    .collect(),
+            },
+            trimmed,
+        })
+    }
+}
+
+/// Return the formats the disk image can be exported to without losing data.
+pub fn export_formats(disk: &DiskImage) -> Vec<ExportFormat> {
+    disk.compatible_formats(true)
+        .into_iter()
+        .map(|(format, extensions)| ExportFormat { format, extensions })
+        .collect()
+}
+
+/// Estimate the size of `disk` written as `format`, to decide where to put the output before
+/// it's written. Sector formats hold the data of each sector, with some allowance for the
+/// headers container formats add; bitstream formats hold a revolution of every track, which
+/// for an image decoded to sectors is two bit cells, a clock and a data bit, per data bit.
+pub fn estimated_size(disk: &DiskImage, format: DiskImageFileFormat) -> usize {
+    let sector_map = disk.get_sector_map();
+    let tracks: usize = sector_map.iter().map(|cylinders| cylinders.len()).sum();
+    let sector_bytes: usize = sector_map
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.chsn.n_size() + SIZE_SECTOR_OVERHEAD)
+        .sum();
+    let data = match format.resolution() {
+        DiskDataResolution::BitStream => {
+            let bits: usize = (0..sector_map.len() as u8)
+                .flat_map(|head| collect_streams(head, disk))
+                .map(|stream| stream.len())
+                .sum();
+            match bits {
+                0 => sector_bytes * 2,
+                bits => bits.div_ceil(8),
+            }
+        }
+        _ => sector_bytes,
+    };
+    data + tracks * SIZE_TRACK_OVERHEAD
+}
+
+/// Write `disk` as `format` to a scratch file. When called from a worker, outputs larger than
+/// [crate::storage::OPFS_THRESHOLD] are streamed to OPFS instead of being held in memory.
+pub async fn export_image(
+    disk: &mut DiskImage,
+    format: DiskImageFileFormat,
+    filename: &str,
+    size_hint: usize,
+) -> Result<StoredFile, Error> {
+    let mut scratch = ScratchFile::create(&format!("scratch_{}", filename), size_hint).await;
+    format
+        .save_image(disk, &mut scratch)
+        .map_err(|e| anyhow!("Error writing image: {:?}", e))?;
+    Ok(scratch.finish()?)
+}
+
+/// Write `disk` as `format` to memory on the calling thread. Only suitable for small outputs
+/// such as raw sector images.
+pub fn export_to_memory(disk: &mut DiskImage, format: DiskImageFileFormat) -> Result<Vec<u8>, Error> {
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    format
+        .save_image(disk, &mut cursor)
+        .map_err(|e| anyhow!("Error writing image: {:?}", e))?;
+    Ok(cursor.into_inner())
+}
+
+/// Write `disk` as a raw sector image to memory, cut to the size of `geometry`. Raw images
+/// hold the cylinders in order, so this drops those past the geometry's last.
+pub fn export_trimmed(
+    disk: &mut DiskImage,
+    format: DiskImageFileFormat,
+    geometry: &StandardGeometry,
+) -> Result<Vec<u8>, Error> {
+    let mut data = export_to_memory(disk, format)?;
+    data.truncate(geometry.size());
+    Ok(data)
+}
+
+/// Export the disk image in a worker. The disk is held in `disk` while the worker runs, so the
+/// caller can recover it if the worker fails to start.
+pub fn spawn_export(
+    disk: Arc<Mutex<Option<DiskImage>>>,
+    format: ExportFormat,
+    filename: String,
+    size_hint: usize,
+    handle: TaskHandle,
+) -> Result<web_sys::Worker, JsValue> {
+    worker::spawn_closure_worker(move || {
+        wasm_bindgen_futures::spawn_local(async move {
+            let Some(mut disk) = disk.lock().unwrap().take()
+            else {
+                log::error!("spawn_export(): No disk image to export");
+                return;
+            };
+
+            log::debug!("Exporting disk image as {:?}...", format.format);
+            let result = export_image(&mut disk, format.format, &filename, size_hint).await;
+            handle.finish(TaskOutput::Exported { disk, filename, result });
+        });
+    })
+}
@@ -0,0 +1,407 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A browser for the FAT filesystem on a disk image, linking files to their physical sectors.
+
+use std::sync::Arc;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::SectorKey;
+use crate::fat::check::{check, CheckReport, LostChain};
+use crate::fat::copies::FatComparison;
+use crate::fat::exe::{is_executable, ExeInfo};
+use crate::fat::free_space::{self, FreeSpaceReport};
+use crate::fat::ident::FileIdent;
+use crate::fat::label::LabelEditor;
+use crate::fat::search::FileSearch;
+use crate::fat::slack;
+use crate::fat::timeline::Timeline;
+use crate::fat::treemap::Treemap;
+use crate::fat::undelete::{find_deleted, DeletedFiles};
+use crate::fat::{flatten, FatVolume, FileNode};
+use crate::preview::FilePreview;
+use crate::storage;
+
+pub const BROWSER_MAX_HEIGHT: f32 = 320.0;
+
+pub enum BrowserEvent {
+    SelectFile(String),
+    SelectSector(SectorKey),
+    /// Write repaired sectors to the image in place, keeping their old contents for undo.
+    WriteSectors {
+        description: String,
+        sectors: Vec<(SectorKey, Vec<u8>)>,
+    },
+}
+
+#[derive(Default)]
+pub struct FatBrowser {
+    pub volume: Option<Arc<FatVolume>>,
+    pub tree: Vec<FileNode>,
+    pub selected: Option<String>,
+    /// The path of the file under the pointer this frame, which may be dragged out of the page.
+    pub hovered: Option<String>,
+    error: Option<String>,
+    /// Set when a file is selected from outside the tree, to expand its parent directories.
+    reveal: bool,
+    search: FileSearch,
+    check: Option<CheckReport>,
+    free_space: Option<FreeSpaceReport>,
+    copies: Option<FatComparison>,
+    label: LabelEditor,
+    timeline: Timeline,
+    treemap: Treemap,
+    deleted: DeletedFiles,
+    pub ident: FileIdent,
+    preview: FilePreview,
+    /// The header summary of the selected executable, with its path.
+    exe: Option<(String, ExeInfo)>,
+}
+
+impl FatBrowser {
+    pub fn clear(&mut self) {
+        self.volume = None;
+        self.tree.clear();
+        self.selected = None;
+        self.error = None;
+        self.search.clear();
+        self.check = None;
+        self.free_space = None;
+        self.copies = None;
+        self.timeline.clear();
+        self.treemap.clear();
+        self.deleted = DeletedFiles::default();
+        self.ident.clear();
+        self.preview.clear();
+        self.exe = None;
+    }
+
+    pub fn forget_textures(&mut self) {
+        self.preview.forget_textures();
+    }
+
+    pub fn load(&mut self, disk: &mut DiskImage) {
+        self.clear();
+        match FatVolume::from_disk(disk) {
+            Ok(volume) => {
+                self.tree = volume.tree();
+                self.deleted = find_deleted(&volume);
+                self.timeline = Timeline::new(&self.tree);
+                self.label.load(&volume);
+                self.ident.hash_files(&volume, &self.tree);
+                log::info!(
+                    "Mounted {} volume with {} entries",
+                    volume.fat_type,
+                    flatten(&self.tree).len()
+                );
+                self.volume = Some(Arc::new(volume));
+            }
+            Err(e) => {
+                log::info!("No FAT filesystem found: {}", e);
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn selected_node(&self) -> Option<&FileNode> {
+        let path = self.selected.as_ref()?;
+        flatten(&self.tree).into_iter().find(|node| &node.path == path)
+    }
+
+    /// Select a file from outside the browser, expanding its parent directories.
+    pub fn select_file(&mut self, path: String) {
+        self.reveal = true;
+        self.selected = Some(path);
+    }
+
+    /// Select the file holding a sector selected elsewhere, if there is one. Returns whether the
+    /// selected file changed.
+    pub fn select_sector(&mut self, key: SectorKey) -> bool {
+        let Some(volume) = &self.volume
+        else {
+            return false;
+        };
+        let Some(lba) = volume.volume.lba(key).filter(|lba| *lba >= volume.bpb.data_lba())
+        else {
+            return false;
+        };
+        let path = flatten(&self.tree)
+            .into_iter()
+            .find(|node| volume.entry_lbas(&node.entry).contains(&lba))
+            .map(|node| node.path.clone());
+        match path {
+            Some(path) if self.selected.as_ref() != Some(&path) => {
+                self.select_file(path);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn hovered_node(&self) -> Option<&FileNode> {
+        let path = self.hovered.as_ref()?;
+        flatten(&self.tree).into_iter().find(|node| &node.path == path)
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<BrowserEvent> {
+        self.hovered = None;
+        let Some(volume) = self.volume.clone()
+        else {
+            if let Some(error) = &self.error {
+                ui.weak(format!("No FAT filesystem: {}", error));
+            }
+            return None;
+        };
+
+        let mut event = None;
+        let title = match volume.label() {
+            Some(label) => format!("Filesystem: {} \"{}\"", volume.fat_type, label),
+            None => format!("Filesystem: {}", volume.fat_type),
+        };
+        egui::CollapsingHeader::new(title).id_salt("fat_browser").show(ui, |ui| {
+            if let Some(search_event) = self.search.show(ui, &volume) {
+                event = Some(search_event);
+            }
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .id_salt("fat_browser_tree")
+                .max_height(BROWSER_MAX_HEIGHT)
+                .show(ui, |ui| {
+                    let selected = self.selected.as_deref();
+                    show_nodes(ui, &self.tree, selected, self.reveal, &mut self.hovered, &mut event);
+                });
+            self.reveal = false;
+
+            if !self.deleted.files.is_empty() || self.deleted.overwritten > 0 {
+                egui::CollapsingHeader::new(format!("Deleted files ({})", self.deleted.files.len()))
+                    .id_salt("fat_browser_deleted")
+                    .show(ui, |ui| {
+                        show_deleted(ui, &volume, &self.deleted);
+                    });
+            }
+
+            if let Some(path) = self.timeline.show(ui) {
+                event = Some(BrowserEvent::SelectFile(path));
+            }
+            if let Some(path) = self.treemap.show(ui, &self.tree, self.selected.as_deref()) {
+                event = Some(BrowserEvent::SelectFile(path));
+            }
+
+            if let Some(label_event) = self.label.show(ui, &volume) {
+                event = Some(label_event);
+            }
+
+            let volume_name = volume.label().unwrap_or("volume".to_string());
+            self.ident.show(ui, &volume_name);
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Check filesystem").clicked() {
+                    self.check = Some(check(&volume));
+                }
+                if ui
+                    .button("Analyze free space")
+                    .on_hover_text("Find out what fills the free clusters, and which hold leftover data.")
+                    .clicked()
+                {
+                    self.free_space = Some(free_space::analyze(&volume));
+                }
+                if ui.button("Compare FAT copies").clicked() {
+                    self.copies = Some(FatComparison::new(&volume, &self.tree));
+                }
+            });
+            ui.horizontal(|ui| {
+                let stem = volume_name.replace(' ', "_");
+                if ui
+                    .button("Extract file slack")
+                    .on_hover_text("Download the bytes past the end of each file in its last cluster, with an index.")
+                    .clicked()
+                {
+                    let extract = slack::file_slack(&volume, &self.tree);
+                    log::info!("Extracted slack of {} files ({} bytes)", extract.pieces, extract.data.len());
+                    extract.download(&format!("{}_slack", stem));
+                }
+                if ui
+                    .button("Extract unallocated clusters")
+                    .on_hover_text("Download the contents of every free cluster, with an index.")
+                    .clicked()
+                {
+                    let extract = slack::unallocated(&volume);
+                    log::info!("Extracted {} unallocated clusters ({} bytes)", extract.pieces, extract.data.len());
+                    extract.download(&format!("{}_unallocated", stem));
+                }
+            });
+            if let Some(report) = &self.check {
+                show_check_report(ui, &volume, report);
+            }
+            if let Some(report) = &self.free_space {
+                if let Some(key) = report.show(ui) {
+                    event = Some(BrowserEvent::SelectSector(key));
+                }
+            }
+            if let Some(copies) = &self.copies {
+                if let Some(repair) = copies.show(ui) {
+                    let fat = copies.repaired(&volume, &self.tree, repair);
+                    event = Some(BrowserEvent::WriteSectors {
+                        description: repair.describe(),
+                        sectors: FatComparison::patches(&volume, &fat),
+                    });
+                }
+            }
+
+            if let Some(node) = self.selected_node().cloned() {
+                ui.separator();
+                let sectors = volume.entry_sectors(&node.entry);
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{}: {} bytes, {} clusters in {} fragments",
+                        node.path,
+                        node.entry.size,
+                        volume.cluster_chain(node.entry.cluster).len(),
+                        volume.fragments(&node.entry)
+                    ));
+                    if let Some(key) = sectors.first() {
+                        if ui.link(format!("first sector {}", key)).clicked() {
+                            event = Some(BrowserEvent::SelectSector(*key));
+                        }
+                    }
+                });
+                if is_executable(&node.entry) {
+                    if self.exe.as_ref().map(|(path, _)| path) != Some(&node.path) {
+                        self.exe = Some((node.path.clone(), ExeInfo::parse(&volume.read_file(&node.entry))));
+                    }
+                    if let Some((_, info)) = &self.exe {
+                        info.show(ui);
+                    }
+                }
+                else if !node.entry.is_dir() {
+                    self.preview.show(ui, &node.path, || volume.read_file(&node.entry));
+                }
+            }
+        });
+
+        if let Some(BrowserEvent::SelectFile(path)) = &event {
+            self.reveal = self.selected.as_ref() != Some(path);
+            self.selected = Some(path.clone());
+        }
+        event
+    }
+}
+
+fn show_check_report(ui: &mut egui::Ui, volume: &FatVolume, report: &CheckReport) {
+    ui.label(format!(
+        "{} clusters used, {} free, {} bad",
+        report.used_clusters, report.free_clusters, report.bad_clusters
+    ));
+    if report.is_clean() {
+        ui.label("No errors found.");
+        return;
+    }
+
+    for issue in report.issues.iter() {
+        ui.colored_label(ui.visuals().warn_fg_color, issue.to_string());
+    }
+    for (i, chain) in report.lost.iter().enumerate() {
+        ui.horizontal(|ui| {
+            let filename = LostChain::filename(i);
+            ui.label(format!("Lost chain of {} clusters starting at {}", chain.clusters.len(), chain.clusters[0]));
+            if ui.button(format!("Save as {}", filename)).clicked() {
+                if let Err(e) = storage::download_bytes(&chain.read(volume), &filename) {
+                    log::error!("Error downloading {}: {:?}", filename, e);
+                }
+            }
+        });
+    }
+}
+
+fn show_deleted(ui: &mut egui::Ui, volume: &FatVolume, deleted: &DeletedFiles) {
+    if deleted.overwritten > 0 {
+        ui.weak(format!(
+            "{} more deleted files aren't listed: their clusters have since been reused.",
+            deleted.overwritten
+        ));
+    }
+    egui::Grid::new("fat_browser_deleted_grid").striped(true).num_columns(4).show(ui, |ui| {
+        for file in &deleted.files {
+            ui.label(&file.path);
+            ui.weak(format!("{} bytes", file.entry.size));
+            ui.label(file.recovery.to_string());
+            if ui
+                .add_enabled(file.recovery.is_recoverable(), egui::Button::new("Recover"))
+                .clicked()
+            {
+                let filename = file.recovered_name();
+                if let Err(e) = storage::download_bytes(&file.recover(volume), &filename) {
+                    log::error!("Error downloading {}: {:?}", filename, e);
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+fn show_nodes(
+    ui: &mut egui::Ui,
+    nodes: &[FileNode],
+    selected: Option<&str>,
+    reveal: bool,
+    hovered: &mut Option<String>,
+    event: &mut Option<BrowserEvent>,
+) {
+    for node in nodes {
+        if node.entry.is_dir() {
+            let contains_selection = selected.is_some_and(|path| path.starts_with(&format!("{}/", node.path)));
+            egui::CollapsingHeader::new(format!("🗀 {}", node.entry.name))
+                .id_salt(&node.path)
+                .open((reveal && contains_selection).then_some(true))
+                .show(ui, |ui| {
+                    show_nodes(ui, &node.children, selected, reveal, hovered, event);
+                });
+        }
+        else {
+            ui.horizontal(|ui| {
+                let is_selected = selected == Some(node.path.as_str());
+                let response = ui.selectable_label(is_selected, &node.entry.name);
+                if reveal && is_selected {
+                    response.scroll_to_me(Some(egui::Align::Center));
+                }
+                if response.clicked() {
+                    *event = Some(BrowserEvent::SelectFile(node.path.clone()));
+                }
+                if response.hovered() {
+                    *hovered = Some(node.path.clone());
+                }
+                response.on_hover_text("Click to select. In Chromium-based browsers, drag to your desktop to save.");
+                ui.weak(format!("{} bytes", node.entry.size));
+                ui.weak(node.entry.modified.to_string());
+                ui.monospace(node.entry.attribute_string());
+            });
+        }
+    }
+}
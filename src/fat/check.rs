@@ -0,0 +1,310 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A CHKDSK-style consistency check of a FAT volume: cross-linked files, broken or mis-sized
+//! cluster chains, lost chains that are allocated but not owned by any directory entry, and
+//! directory errors: missing or misdirected `.` and `..` entries, invalid or duplicate names,
+//! and directories with a size. A boot sector claiming more sectors than the image holds is
+//! reported too.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::fat::{flatten, ChainEnd, FatVolume, FileNode, DIR_ENTRY_SIZE};
+
+/// Characters DOS doesn't allow in a short name, besides control characters.
+const INVALID_NAME_CHARS: &[u8] = b"\"*+,./:;<=>?[\\]|";
+/// The `.` and `..` entries at the start of every subdirectory, as stored.
+const DOT_ENTRIES: [(&str, &[u8; 11]); 2] = [(".", b".          "), ("..", b"..         ")];
+
+#[derive(Clone, Debug)]
+pub enum CheckIssue {
+    CrossLinked { paths: Vec<String>, clusters: usize },
+    InvalidStart { path: String, cluster: u32 },
+    BrokenChain { path: String, end: ChainEnd },
+    SizeMismatch { path: String, size: u32, clusters: usize },
+    BadSectors { path: String, sectors: usize },
+    MissingDotEntry { path: String, name: &'static str },
+    WrongDotEntry { path: String, name: &'static str, cluster: u32, expected: u32 },
+    InvalidName { path: String },
+    DuplicateName { path: String },
+    DirectorySize { path: String, size: u32 },
+    VolumeTruncated { claimed: usize, held: usize },
+}
+
+impl std::fmt::Display for CheckIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckIssue::CrossLinked { paths, clusters } => {
+                write!(f, "{} are cross-linked on {} clusters", paths.join(" and "), clusters)
+            }
+            CheckIssue::InvalidStart { path, cluster } => {
+                write!(f, "{} has an invalid first cluster {}", path, cluster)
+            }
+            CheckIssue::BrokenChain { path, end } => {
+                write!(f, "{} has a cluster chain ending in {}", path, end)
+            }
+            CheckIssue::SizeMismatch { path, size, clusters } => {
+                write!(f, "{} is {} bytes but has {} clusters allocated", path, size, clusters)
+            }
+            CheckIssue::BadSectors { path, sectors } => {
+                write!(f, "{} has {} sectors with bad data CRCs", path, sectors)
+            }
+            CheckIssue::MissingDotEntry { path, name } => {
+                write!(f, "{} has no \"{}\" entry", path, name)
+            }
+            CheckIssue::WrongDotEntry { path, name, cluster, expected } => {
+                write!(f, "{}'s \"{}\" entry points to cluster {} instead of {}", path, name, cluster, expected)
+            }
+            CheckIssue::InvalidName { path } => {
+                write!(f, "{} has characters DOS doesn't allow in its name", path)
+            }
+            CheckIssue::DuplicateName { path } => {
+                write!(f, "{} appears more than once in its directory", path)
+            }
+            CheckIssue::DirectorySize { path, size } => {
+                write!(f, "{} is a directory but lists a size of {} bytes", path, size)
+            }
+            CheckIssue::VolumeTruncated { claimed, held } => {
+                write!(f, "The boot sector gives the volume {} sectors, but the image only holds {}", claimed, held)
+            }
+        }
+    }
+}
+
+/// An allocated cluster chain that no directory entry points to.
+#[derive(Clone, Debug)]
+pub struct LostChain {
+    pub clusters: Vec<u32>,
+}
+
+impl LostChain {
+    /// The name CHKDSK would give the recovered chain.
+    pub fn filename(index: usize) -> String {
+        format!("FILE{:04}.CHK", index)
+    }
+
+    pub fn read(&self, volume: &FatVolume) -> Vec<u8> {
+        volume
+            .volume
+            .read(self.clusters.iter().flat_map(|cluster| volume.cluster_lbas(*cluster)))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    pub issues: Vec<CheckIssue>,
+    pub lost: Vec<LostChain>,
+    pub used_clusters: usize,
+    pub free_clusters: usize,
+    pub bad_clusters: usize,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty() && self.lost.is_empty()
+    }
+}
+
+/// Check the entries of one directory, then its subdirectories. `cluster` is the directory's
+/// first cluster, 0 for the root.
+fn check_dir(volume: &FatVolume, nodes: &[FileNode], cluster: u32, issues: &mut Vec<CheckIssue>) {
+    let mut names = HashSet::new();
+    for node in nodes {
+        let entry = &node.entry;
+        let raw = volume
+            .volume
+            .sector(entry.entry_lba)
+            .and_then(|sector| sector.get(entry.entry_offset..entry.entry_offset + DIR_ENTRY_SIZE));
+        // A leading 0x05 stands in for 0xE5 and is allowed.
+        let invalid = raw.is_some_and(|raw| {
+            raw[..11].iter().enumerate().any(|(i, &b)| {
+                (b < 0x20 && !(i == 0 && b == 0x05)) || INVALID_NAME_CHARS.contains(&b)
+            })
+        });
+        if invalid {
+            issues.push(CheckIssue::InvalidName { path: node.path.clone() });
+        }
+        if !names.insert(entry.name.as_str()) {
+            issues.push(CheckIssue::DuplicateName { path: node.path.clone() });
+        }
+
+        if !entry.is_dir() {
+            continue;
+        }
+        if entry.size != 0 {
+            issues.push(CheckIssue::DirectorySize { path: node.path.clone(), size: entry.size });
+        }
+        if !volume.is_valid_cluster(entry.cluster) {
+            // Reported as an invalid start along with the files.
+            continue;
+        }
+        check_dot_entries(volume, node, cluster, issues);
+        check_dir(volume, &node.children, entry.cluster, issues);
+    }
+}
+
+/// Check that subdirectory `node` starts with `.` pointing to itself and `..` pointing to
+/// `parent`, its parent's first cluster or 0 for the root.
+fn check_dot_entries(volume: &FatVolume, node: &FileNode, parent: u32, issues: &mut Vec<CheckIssue>) {
+    let first = volume.volume.sector(volume.cluster_lbas(node.entry.cluster).start);
+    for (i, (&(name, stored), expected)) in DOT_ENTRIES.iter().zip([node.entry.cluster, parent]).enumerate() {
+        let raw = first.and_then(|sector| sector.get(i * DIR_ENTRY_SIZE..(i + 1) * DIR_ENTRY_SIZE));
+        match raw {
+            Some(raw) if raw[..11] == stored[..] => {
+                let cluster = u16::from_le_bytes([raw[26], raw[27]]) as u32;
+                if cluster != expected {
+                    issues.push(CheckIssue::WrongDotEntry {
+                        path: node.path.clone(),
+                        name,
+                        cluster,
+                        expected,
+                    });
+                }
+            }
+            _ => issues.push(CheckIssue::MissingDotEntry { path: node.path.clone(), name }),
+        }
+    }
+}
+
+pub fn check(volume: &FatVolume) -> CheckReport {
+    let mut report = CheckReport::default();
+    let held = volume.volume.sectors.len();
+    if volume.volume.claimed_sectors > held {
+        report.issues.push(CheckIssue::VolumeTruncated { claimed: volume.volume.claimed_sectors, held });
+    }
+    let tree = volume.tree();
+    check_dir(volume, &tree, 0, &mut report.issues);
+    let mut owners: HashMap<u32, Vec<&str>> = HashMap::new();
+
+    for node in flatten(&tree) {
+        let entry = &node.entry;
+        if entry.cluster == 0 {
+            if entry.size > 0 {
+                report.issues.push(CheckIssue::InvalidStart { path: node.path.clone(), cluster: 0 });
+            }
+            continue;
+        }
+        if !volume.is_valid_cluster(entry.cluster) {
+            report.issues.push(CheckIssue::InvalidStart { path: node.path.clone(), cluster: entry.cluster });
+            continue;
+        }
+
+        let (chain, end) = volume.follow_chain(entry.cluster);
+        if end != ChainEnd::EndOfChain {
+            report.issues.push(CheckIssue::BrokenChain { path: node.path.clone(), end });
+        }
+        if !entry.is_dir() {
+            let expected = (entry.size as usize).div_ceil(volume.cluster_size());
+            if expected != chain.len() {
+                report.issues.push(CheckIssue::SizeMismatch {
+                    path: node.path.clone(),
+                    size: entry.size,
+                    clusters: chain.len(),
+                });
+            }
+        }
+
+        let bad = volume
+            .entry_lbas(entry)
+            .into_iter()
+            .filter(|lba| volume.volume.bad.get(*lba).copied().unwrap_or(false))
+            .count();
+        if bad > 0 {
+            report.issues.push(CheckIssue::BadSectors { path: node.path.clone(), sectors: bad });
+        }
+
+        for cluster in chain {
+            owners.entry(cluster).or_default().push(&node.path);
+        }
+    }
+
+    // Group cross-linked clusters by the set of files sharing them.
+    let mut cross_links: BTreeMap<Vec<String>, usize> = BTreeMap::new();
+    for paths in owners.values().filter(|paths| paths.len() > 1) {
+        let mut paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        paths.sort();
+        *cross_links.entry(paths).or_default() += 1;
+    }
+    for (paths, clusters) in cross_links {
+        report.issues.push(CheckIssue::CrossLinked { paths, clusters });
+    }
+
+    // Find allocated clusters nobody owns, then split them into chains starting at clusters
+    // that no other lost cluster links to.
+    let max_cluster = volume.bpb.cluster_count() + 2;
+    let mut lost = HashSet::new();
+    for cluster in 2..max_cluster {
+        let entry = volume.fat_entry(cluster);
+        if entry == 0 {
+            report.free_clusters += 1;
+        }
+        else if volume.is_bad_cluster(entry) {
+            report.bad_clusters += 1;
+        }
+        else {
+            report.used_clusters += 1;
+            if !owners.contains_key(&cluster) {
+                lost.insert(cluster);
+            }
+        }
+    }
+
+    let linked: HashSet<u32> = lost.iter().map(|cluster| volume.fat_entry(*cluster)).collect();
+    let mut heads: Vec<u32> = lost.iter().copied().filter(|cluster| !linked.contains(cluster)).collect();
+    heads.sort_unstable();
+
+    let mut claimed = HashSet::new();
+    for head in heads {
+        let clusters: Vec<u32> = volume
+            .cluster_chain(head)
+            .into_iter()
+            .take_while(|cluster| lost.contains(cluster) && claimed.insert(*cluster))
+            .collect();
+        if !clusters.is_empty() {
+            report.lost.push(LostChain { clusters });
+        }
+    }
+
+    // Lost clusters that only form loops have no head; recover each loop once.
+    let mut remaining: Vec<u32> = lost.difference(&claimed).copied().collect();
+    remaining.sort_unstable();
+    for start in remaining {
+        if claimed.contains(&start) {
+            continue;
+        }
+        let clusters: Vec<u32> = volume
+            .cluster_chain(start)
+            .into_iter()
+            .take_while(|cluster| lost.contains(cluster) && claimed.insert(*cluster))
+            .collect();
+        if !clusters.is_empty() {
+            report.lost.push(LostChain { clusters });
+        }
+    }
+
+    report
+}
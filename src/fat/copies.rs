@@ -0,0 +1,270 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Comparing the copies of the FAT, and repairing them. DOS keeps two identical copies but only
+//! ever reads the first, so a disk whose first copy was damaged often has a good second one.
+//! Where neither can be trusted, the FAT can be rebuilt from the directory: each file's chain
+//! is taken from whichever copy agrees with its size, or assumed contiguous, as files written
+//! to a fresh floppy almost always are.
+
+use crate::analysis::SectorKey;
+use crate::fat::browser::BROWSER_MAX_HEIGHT;
+use crate::fat::{flatten, ChainEnd, FatType, FatVolume, FileNode};
+
+/// The number of differing entries listed.
+pub const MAX_DIFFERENCE_ROWS: usize = 256;
+
+/// A cluster whose entry differs between the copies.
+#[derive(Clone, Debug)]
+pub struct FatDifference {
+    pub cluster: u32,
+    /// The entry in each copy.
+    pub entries: Vec<u32>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FatRepair {
+    /// Overwrite every other copy with this one.
+    CopyFrom(usize),
+    Reconstruct,
+}
+
+impl FatRepair {
+    pub fn describe(&self) -> String {
+        match self {
+            FatRepair::CopyFrom(index) => format!("Copy FAT {} over the other copies", index + 1),
+            FatRepair::Reconstruct => "Rebuild the FAT from the directory".to_string(),
+        }
+    }
+}
+
+pub struct FatComparison {
+    pub copies: Vec<Vec<u8>>,
+    /// The sectors of each copy that were missing or read with a bad CRC.
+    pub bad_sectors: Vec<usize>,
+    /// The files and directories whose chain in each copy is broken or doesn't fit their size.
+    pub broken_chains: Vec<usize>,
+    pub differences: Vec<FatDifference>,
+}
+
+impl FatComparison {
+    pub fn new(volume: &FatVolume, tree: &[FileNode]) -> Self {
+        let spf = volume.bpb.sectors_per_fat as usize;
+        let lbas = |copy: usize| volume.bpb.fat_lba() + copy * spf..volume.bpb.fat_lba() + (copy + 1) * spf;
+        let copy_count = volume.bpb.fat_count as usize;
+        let copies: Vec<Vec<u8>> = (0..copy_count).map(|copy| volume.volume.read(lbas(copy))).collect();
+        let bad_sectors = (0..copy_count)
+            .map(|copy| {
+                lbas(copy)
+                    .filter(|lba| volume.volume.sector_key(*lba).is_none() || volume.volume.bad[*lba])
+                    .count()
+            })
+            .collect();
+        let broken_chains = copies.iter().map(|fat| broken_chains(volume, fat, tree)).collect();
+
+        let mut differences = Vec::new();
+        for cluster in 2..volume.bpb.cluster_count() + 2 {
+            let entries: Vec<u32> = copies.iter().map(|fat| volume.entry_in(fat, cluster)).collect();
+            if entries.iter().any(|entry| *entry != entries[0]) {
+                differences.push(FatDifference { cluster, entries });
+            }
+        }
+        Self {
+            copies,
+            bad_sectors,
+            broken_chains,
+            differences,
+        }
+    }
+
+    /// The new contents of the FAT for a repair, to be written to every copy.
+    pub fn repaired(&self, volume: &FatVolume, tree: &[FileNode], repair: FatRepair) -> Vec<u8> {
+        match repair {
+            FatRepair::CopyFrom(index) => self.copies[index].clone(),
+            FatRepair::Reconstruct => self.reconstruct(volume, tree),
+        }
+    }
+
+    fn reconstruct(&self, volume: &FatVolume, tree: &[FileNode]) -> Vec<u8> {
+        let mut fat = vec![0; self.copies[0].len()];
+        // The first two entries hold the media descriptor and an end of chain marker.
+        let reserved = match volume.fat_type {
+            FatType::Fat12 => 3,
+            FatType::Fat16 => 4,
+        };
+        fat[0] = volume.bpb.media_descriptor;
+        for byte in fat.iter_mut().take(reserved).skip(1) {
+            *byte = 0xFF;
+        }
+
+        let last_cluster = volume.bpb.cluster_count() + 2;
+        // Bad clusters are kept from any copy that marks them.
+        for cluster in 2..last_cluster {
+            if self.copies.iter().any(|copy| volume.is_bad_cluster(volume.entry_in(copy, cluster))) {
+                volume.set_entry_in(&mut fat, cluster, volume.bad_cluster_marker());
+            }
+        }
+
+        let views: Vec<FatVolume> = self.copies.iter().map(|copy| view(volume, copy)).collect();
+        let mut owned = vec![false; last_cluster as usize];
+        for node in flatten(tree) {
+            if !volume.is_valid_cluster(node.entry.cluster) {
+                continue;
+            }
+            let expected = expected_clusters(volume, node);
+            let chain = views
+                .iter()
+                .find_map(|view| {
+                    let (chain, end) = view.follow_chain(node.entry.cluster);
+                    let fits = expected.map_or(true, |expected| chain.len() == expected);
+                    (end == ChainEnd::EndOfChain && fits).then_some(chain)
+                })
+                .unwrap_or_else(|| {
+                    let start = node.entry.cluster;
+                    let len = expected.unwrap_or(1) as u32;
+                    (start..(start + len).min(last_cluster)).collect()
+                });
+            if chain.iter().any(|cluster| owned[*cluster as usize]) {
+                log::warn!("Not rebuilding the chain of {}, which overlaps another", node.path);
+                continue;
+            }
+            for (i, cluster) in chain.iter().enumerate() {
+                owned[*cluster as usize] = true;
+                let next = chain.get(i + 1).copied().unwrap_or(volume.end_of_chain_marker());
+                volume.set_entry_in(&mut fat, *cluster, next);
+            }
+        }
+        fat
+    }
+
+    /// The sectors to write to put `fat` in every copy of the FAT. Copies whose sectors weren't
+    /// found on the disk are left out.
+    pub fn patches(volume: &FatVolume, fat: &[u8]) -> Vec<(SectorKey, Vec<u8>)> {
+        let sector_size = volume.volume.sector_size;
+        let spf = volume.bpb.sectors_per_fat as usize;
+        let mut patches = Vec::new();
+        for copy in 0..volume.bpb.fat_count as usize {
+            for (i, data) in fat.chunks(sector_size).enumerate().take(spf) {
+                let lba = volume.bpb.fat_lba() + copy * spf + i;
+                if let Some(key) = volume.volume.sector_key(lba) {
+                    patches.push((key, data.to_vec()));
+                }
+            }
+        }
+        patches
+    }
+
+    /// Show the comparison. Returns a repair if the user chose one.
+    pub fn show(&self, ui: &mut egui::Ui) -> Option<FatRepair> {
+        let mut repair = None;
+        ui.separator();
+        if self.copies.len() < 2 {
+            ui.label("This volume has a single FAT.");
+        }
+        egui::Grid::new("fat_copies_grid").striped(true).num_columns(3).show(ui, |ui| {
+            ui.strong("Copy");
+            ui.strong("Bad sectors");
+            ui.strong("Broken chains");
+            ui.end_row();
+            for (index, (bad, broken)) in self.bad_sectors.iter().zip(&self.broken_chains).enumerate() {
+                ui.label(format!("FAT {}", index + 1));
+                ui.label(bad.to_string());
+                ui.label(broken.to_string());
+                ui.end_row();
+            }
+        });
+
+        if self.differences.is_empty() {
+            if self.copies.len() > 1 {
+                ui.label("The copies are identical.");
+            }
+        }
+        else {
+            ui.label(format!("{} clusters have different entries.", self.differences.len()));
+            egui::ScrollArea::vertical().id_salt("fat_copies_differences").max_height(BROWSER_MAX_HEIGHT).show(
+                ui,
+                |ui| {
+                    egui::Grid::new("fat_copies_difference_grid").striped(true).show(ui, |ui| {
+                        ui.strong("Cluster");
+                        for index in 0..self.copies.len() {
+                            ui.strong(format!("FAT {}", index + 1));
+                        }
+                        ui.end_row();
+                        for difference in self.differences.iter().take(MAX_DIFFERENCE_ROWS) {
+                            ui.monospace(difference.cluster.to_string());
+                            for entry in &difference.entries {
+                                ui.monospace(format!("{:03X}", entry));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                },
+            );
+            ui.horizontal(|ui| {
+                for index in 0..self.copies.len() {
+                    if ui.button(format!("Use FAT {}", index + 1)).clicked() {
+                        repair = Some(FatRepair::CopyFrom(index));
+                    }
+                }
+            });
+        }
+        if ui
+            .button("Rebuild from directory")
+            .on_hover_text("Rebuild the FAT from the file sizes and start clusters in the directory.")
+            .clicked()
+        {
+            repair = Some(FatRepair::Reconstruct);
+        }
+        repair
+    }
+}
+
+/// The clusters a file of its size needs, or None for directories, which have no size.
+fn expected_clusters(volume: &FatVolume, node: &FileNode) -> Option<usize> {
+    (!node.entry.is_dir()).then(|| (node.entry.size as usize).div_ceil(volume.cluster_size()))
+}
+
+/// The volume as it would be mounted with a different copy of the FAT.
+fn view(volume: &FatVolume, fat: &[u8]) -> FatVolume {
+    FatVolume {
+        fat: fat.to_vec(),
+        ..volume.clone()
+    }
+}
+
+fn broken_chains(volume: &FatVolume, fat: &[u8], tree: &[FileNode]) -> usize {
+    let view = view(volume, fat);
+    flatten(tree)
+        .into_iter()
+        .filter(|node| volume.is_valid_cluster(node.entry.cluster))
+        .filter(|node| {
+            let (chain, end) = view.follow_chain(node.entry.cluster);
+            let fits = expected_clusters(volume, node).map_or(true, |expected| chain.len() == expected);
+            end != ChainEnd::EndOfChain || !fits
+        })
+        .count()
+}
@@ -0,0 +1,248 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A summary of DOS executables from their headers: the MZ header fields, whether data is
+//! appended after the load image as an overlay, which packer compressed the file, and any
+//! copyright or version strings, which are often enough to tell releases of a program apart.
+
+use crate::fat::DirEntry;
+
+pub const EXECUTABLE_EXTENSIONS: [&str; 4] = ["EXE", "COM", "OVL", "OVR"];
+
+const MZ_HEADER_LEN: usize = 0x1C;
+/// Packer signatures are searched for within this many bytes of the start of the file.
+const SIGNATURE_WINDOW: usize = 2048;
+const MAX_STRINGS: usize = 8;
+const MIN_STRING_LEN: usize = 8;
+
+/// Packer signatures, with the name they identify. LZEXE writes its version at 0x1C, so it's
+/// matched there rather than anywhere in the window.
+const PACKER_SIGNATURES: [(&[u8], &str); 4] = [
+    (b"PKLITE", "PKLITE"),
+    (b"UPX!", "UPX"),
+    (b"Packed file is corrupt", "EXEPACK"),
+    (b"LHa's SFX", "LHA self-extractor"),
+];
+
+pub fn is_executable(entry: &DirEntry) -> bool {
+    !entry.is_dir()
+        && entry
+            .name
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| EXECUTABLE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+#[derive(Clone, Debug)]
+pub struct MzHeader {
+    pub last_page_bytes: u16,
+    pub pages: u16,
+    pub relocations: u16,
+    pub header_paragraphs: u16,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub relocation_offset: u16,
+    pub overlay_number: u16,
+}
+
+impl MzHeader {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < MZ_HEADER_LEN || !(data.starts_with(b"MZ") || data.starts_with(b"ZM")) {
+            return None;
+        }
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        Some(Self {
+            last_page_bytes: word(0x02),
+            pages: word(0x04),
+            relocations: word(0x06),
+            header_paragraphs: word(0x08),
+            min_alloc: word(0x0A),
+            max_alloc: word(0x0C),
+            ss: word(0x0E),
+            sp: word(0x10),
+            checksum: word(0x12),
+            ip: word(0x14),
+            cs: word(0x16),
+            relocation_offset: word(0x18),
+            overlay_number: word(0x1A),
+        })
+    }
+
+    /// The size of the file as the header describes it, header included.
+    pub fn image_end(&self) -> usize {
+        let pages = self.pages as usize * 512;
+        match self.last_page_bytes {
+            0 => pages,
+            last => pages.saturating_sub(512) + last as usize,
+        }
+    }
+
+    pub fn header_len(&self) -> usize {
+        self.header_paragraphs as usize * 16
+    }
+
+    /// The file offset of the entry point.
+    pub fn entry_offset(&self) -> usize {
+        self.header_len() + ((self.cs as usize * 16 + self.ip as usize) & 0xFFFFF)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExeInfo {
+    pub size: usize,
+    /// None for COM files, which have no header.
+    pub mz: Option<MzHeader>,
+    /// The signature of a Windows or OS/2 executable pointed to from the MZ header.
+    pub new_header: Option<String>,
+    pub overlay_len: usize,
+    pub packers: Vec<String>,
+    pub strings: Vec<String>,
+}
+
+impl ExeInfo {
+    pub fn parse(data: &[u8]) -> Self {
+        let mz = MzHeader::parse(data);
+        let new_header = mz.as_ref().and_then(|mz| new_header(data, mz));
+        // Windows and OS/2 executables put their own image after the MZ stub, so it isn't an overlay.
+        let overlay_len = match &mz {
+            Some(mz) if new_header.is_none() => data.len().saturating_sub(mz.image_end()),
+            _ => 0,
+        };
+
+        let window = &data[..data.len().min(SIGNATURE_WINDOW)];
+        let mut packers = Vec::new();
+        if let Some(version) = data.get(0x1C..0x20).filter(|_| mz.is_some()) {
+            match version {
+                b"LZ09" => packers.push("LZEXE 0.90".to_string()),
+                b"LZ91" => packers.push("LZEXE 0.91".to_string()),
+                _ => {}
+            }
+        }
+        for (signature, name) in PACKER_SIGNATURES {
+            if window.windows(signature.len()).any(|w| w == signature) {
+                packers.push(name.to_string());
+            }
+        }
+
+        Self {
+            size: data.len(),
+            mz,
+            new_header,
+            overlay_len,
+            packers,
+            strings: find_strings(data),
+        }
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Executable").default_open(true).show(ui, |ui| {
+            let kind = match (&self.mz, &self.new_header) {
+                (Some(_), Some(signature)) => format!("{} executable with a DOS stub", signature),
+                (Some(_), None) => "DOS MZ executable".to_string(),
+                (None, _) => "COM program (no header)".to_string(),
+            };
+            ui.label(format!("{}, {} bytes", kind, self.size));
+
+            if let Some(mz) = &self.mz {
+                egui::Grid::new("exe_header_grid").striped(true).num_columns(2).show(ui, |ui| {
+                    let rows = [
+                        ("Load image", format!("{} bytes", mz.image_end())),
+                        ("Header", format!("{} bytes", mz.header_len())),
+                        ("Relocations", format!("{} at {:#06X}", mz.relocations, mz.relocation_offset)),
+                        ("Entry point", format!("{:04X}:{:04X} (file offset {:#X})", mz.cs, mz.ip, mz.entry_offset())),
+                        ("Stack", format!("{:04X}:{:04X}", mz.ss, mz.sp)),
+                        ("Extra memory", format!("{} to {} paragraphs", mz.min_alloc, mz.max_alloc)),
+                        ("Checksum", format!("{:#06X}", mz.checksum)),
+                        ("Overlay number", mz.overlay_number.to_string()),
+                    ];
+                    for (name, value) in rows {
+                        ui.label(name);
+                        ui.monospace(value);
+                        ui.end_row();
+                    }
+                });
+            }
+
+            if self.overlay_len > 0 {
+                ui.label(format!("{} bytes of overlay data after the load image.", self.overlay_len));
+            }
+            if self.packers.is_empty() {
+                ui.label("No packer signature found.");
+            }
+            else {
+                ui.label(format!("Packed with {}.", self.packers.join(", ")));
+            }
+            if !self.strings.is_empty() {
+                ui.label("Copyright and version strings:");
+                for string in &self.strings {
+                    ui.monospace(string);
+                }
+            }
+        });
+    }
+}
+
+/// The signature of the new-style header at the offset in the word at 0x3C, which is only
+/// meaningful when the relocation table starts at 0x40 or later.
+fn new_header(data: &[u8], mz: &MzHeader) -> Option<String> {
+    if mz.relocation_offset < 0x40 {
+        return None;
+    }
+    let offset = u32::from_le_bytes(data.get(0x3C..0x40)?.try_into().ok()?) as usize;
+    match data.get(offset..offset + 2)? {
+        b"NE" => Some("NE (16-bit Windows or OS/2)".to_string()),
+        b"LE" => Some("LE (VxD or DOS extender)".to_string()),
+        b"LX" => Some("LX (32-bit OS/2)".to_string()),
+        b"PE" => Some("PE (32-bit Windows)".to_string()),
+        _ => None,
+    }
+}
+
+/// Printable ASCII runs mentioning a copyright or version.
+fn find_strings(data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    for run in data.split(|byte| !(0x20..0x7F).contains(byte)) {
+        if run.len() < MIN_STRING_LEN {
+            continue;
+        }
+        let text = String::from_utf8_lossy(run).trim().to_string();
+        let lower = text.to_ascii_lowercase();
+        if ["copyright", "copr.", "(c)", "version", "ver. ", "release"].iter().any(|word| lower.contains(word))
+            && !strings.contains(&text)
+        {
+            strings.push(text);
+            if strings.len() == MAX_STRINGS {
+                break;
+            }
+        }
+    }
+    strings
+}
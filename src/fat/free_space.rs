@@ -0,0 +1,188 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Fill bytes of a FAT volume's free space. DOS FORMAT fills sectors with 0xF6 and never
+//! touches free clusters again, so what's in them tells how a disk was used: all 0xF6 is a disk
+//! used only since its last full format, zeroes point to a wipe or another formatter, and
+//! anything else is data left behind by deleted files or a quick format, and worth carving.
+
+use std::collections::BTreeMap;
+
+use crate::analysis::SectorKey;
+use crate::fat::FatVolume;
+
+/// The fill byte DOS FORMAT writes to every sector.
+pub const FORMAT_FILL: u8 = 0xF6;
+/// The number of residual clusters listed.
+pub const MAX_RESIDUAL_ROWS: usize = 64;
+
+/// A free cluster holding something other than a single repeated byte.
+#[derive(Clone, Debug)]
+pub struct ResidualCluster {
+    pub cluster: u32,
+    /// The first of the cluster's sectors holding data.
+    pub sector: SectorKey,
+    /// The number of bytes in the cluster that aren't the format fill or zero.
+    pub data_bytes: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FreeSpaceReport {
+    pub free_clusters: usize,
+    /// The free sectors found on the disk; sectors that weren't found aren't counted.
+    pub free_sectors: usize,
+    /// The number of free sectors filled with each byte.
+    pub fills: BTreeMap<u8, usize>,
+    /// Free sectors holding mixed data.
+    pub mixed_sectors: usize,
+    pub residual: Vec<ResidualCluster>,
+}
+
+/// The byte a sector is filled with, if it's a single repeated byte.
+fn fill_byte(sector: &[u8]) -> Option<u8> {
+    let first = *sector.first()?;
+    sector.iter().all(|byte| *byte == first).then_some(first)
+}
+
+pub fn analyze(volume: &FatVolume) -> FreeSpaceReport {
+    let mut report = FreeSpaceReport::default();
+    let clusters = volume.bpb.cluster_count();
+    for cluster in 2..clusters + 2 {
+        if volume.fat_entry(cluster) != 0 {
+            continue;
+        }
+        report.free_clusters += 1;
+
+        let mut residual: Option<ResidualCluster> = None;
+        for lba in volume.cluster_lbas(cluster) {
+            let (Some(key), Some(sector)) = (volume.volume.sector_key(lba), volume.volume.sector(lba))
+            else {
+                continue;
+            };
+            report.free_sectors += 1;
+            match fill_byte(sector) {
+                Some(byte) => *report.fills.entry(byte).or_insert(0) += 1,
+                None => {
+                    report.mixed_sectors += 1;
+                    let data_bytes = sector.iter().filter(|byte| **byte != FORMAT_FILL && **byte != 0).count();
+                    let residual = residual.get_or_insert(ResidualCluster {
+                        cluster,
+                        sector: key,
+                        data_bytes: 0,
+                    });
+                    residual.data_bytes += data_bytes;
+                }
+            }
+        }
+        report.residual.extend(residual);
+    }
+    report
+}
+
+impl FreeSpaceReport {
+    fn fill_count(&self, byte: u8) -> usize {
+        self.fills.get(&byte).copied().unwrap_or(0)
+    }
+
+    /// What the free space says about the disk's history.
+    pub fn verdict(&self) -> &'static str {
+        if self.free_sectors == 0 {
+            return "There's no free space to analyze.";
+        }
+        if !self.residual.is_empty() {
+            return "Free clusters hold leftover data: the disk was used before, or only quick formatted. \
+                The data may be worth carving.";
+        }
+        let formatted = self.fill_count(FORMAT_FILL);
+        let zeroed = self.fill_count(0);
+        if formatted == self.free_sectors {
+            "All free space holds the DOS format fill: nothing has been deleted since the last full format."
+        }
+        else if zeroed == self.free_sectors {
+            "All free space is zeroed: the disk was wiped, or formatted by something other than DOS."
+        }
+        else if formatted + zeroed == self.free_sectors {
+            "Free space mixes the DOS format fill and zeroes: some clusters were wiped or written with zeroes."
+        }
+        else {
+            "Free space is filled with unusual bytes, possibly by a non-DOS formatter or a duplicator."
+        }
+    }
+
+    /// Show the report. Returns a sector if the user clicked a residual cluster.
+    pub fn show(&self, ui: &mut egui::Ui) -> Option<SectorKey> {
+        let mut selected = None;
+        ui.label(format!(
+            "{} free clusters, {} sectors: {} mixed data",
+            self.free_clusters, self.free_sectors, self.mixed_sectors
+        ));
+        egui::Grid::new("fat_free_space_fills").striped(true).num_columns(3).show(ui, |ui| {
+            ui.strong("Fill");
+            ui.strong("Sectors");
+            ui.strong("Share");
+            ui.end_row();
+            let mut fills: Vec<(&u8, &usize)> = self.fills.iter().collect();
+            fills.sort_by(|a, b| b.1.cmp(a.1));
+            for (byte, count) in fills {
+                let note = match *byte {
+                    FORMAT_FILL => " (DOS format)",
+                    0x00 => " (zero)",
+                    0xE5 => " (CP/M format)",
+                    _ => "",
+                };
+                ui.monospace(format!("{:02X}{}", byte, note));
+                ui.label(count.to_string());
+                ui.label(format!("{:.1}%", *count as f64 * 100.0 / self.free_sectors.max(1) as f64));
+                ui.end_row();
+            }
+            ui.monospace("mixed");
+            ui.label(self.mixed_sectors.to_string());
+            ui.label(format!("{:.1}%", self.mixed_sectors as f64 * 100.0 / self.free_sectors.max(1) as f64));
+            ui.end_row();
+        });
+        ui.label(self.verdict());
+
+        if !self.residual.is_empty() {
+            egui::CollapsingHeader::new(format!("Free clusters with data ({})", self.residual.len()))
+                .id_salt("fat_free_space_residual")
+                .show(ui, |ui| {
+                    for residual in self.residual.iter().take(MAX_RESIDUAL_ROWS) {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Cluster {}: {} data bytes", residual.cluster, residual.data_bytes));
+                            if ui.link(format!("sector {}", residual.sector)).clicked() {
+                                selected = Some(residual.sector);
+                            }
+                        });
+                    }
+                    if self.residual.len() > MAX_RESIDUAL_ROWS {
+                        ui.weak(format!("and {} more", self.residual.len() - MAX_RESIDUAL_ROWS));
+                    }
+                });
+        }
+        selected
+    }
+}
@@ -0,0 +1,192 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Identifying the programs on a FAT volume by hashing each file and matching the digests
+//! against user-supplied hash lists.
+//!
+//! Hash lists use the same formats as checksum manifests, with `.ident` before the extension
+//! (e.g. `sierra.ident.md5`). Each entry names a program version rather than a file, and is
+//! matched by digest alone, so files are identified whatever they were renamed to.
+
+use crate::checksum::{ChecksumKind, ChecksumManifest, Digests};
+use crate::fat::browser::BROWSER_MAX_HEIGHT;
+use crate::fat::{flatten, FatVolume, FileNode};
+use crate::storage;
+
+pub const HASH_LIST_MARKER: &str = ".ident.";
+
+#[derive(Clone, Debug)]
+pub struct FileHash {
+    pub path: String,
+    pub size: u32,
+    pub digests: Digests,
+}
+
+/// A file whose digest matched a hash list entry.
+#[derive(Clone, Debug)]
+pub struct FileMatch {
+    pub path: String,
+    pub list: String,
+    pub kind: ChecksumKind,
+    /// The name of the matching entry, which identifies the program.
+    pub identity: String,
+}
+
+#[derive(Default)]
+pub struct FileIdent {
+    /// Hash lists are kept across images, as they're likely to be used on many disks.
+    lists: Vec<ChecksumManifest>,
+    hashes: Vec<FileHash>,
+    matches: Vec<FileMatch>,
+    error: Option<String>,
+}
+
+impl FileIdent {
+    pub fn is_hash_list(filename: &str) -> bool {
+        filename.to_ascii_lowercase().contains(HASH_LIST_MARKER) && ChecksumManifest::is_manifest_file(filename)
+    }
+
+    /// Forget the hashes of the previous volume, keeping the hash lists.
+    pub fn clear(&mut self) {
+        self.hashes.clear();
+        self.matches.clear();
+    }
+
+    pub fn load_list(&mut self, name: &str, bytes: &[u8]) {
+        match ChecksumManifest::parse(name, &String::from_utf8_lossy(bytes)) {
+            Ok(list) => {
+                log::info!("Loaded hash list {} with {} entries", list.name, list.entries.len());
+                self.lists.retain(|existing| existing.name != list.name);
+                self.lists.push(list);
+                self.error = None;
+                self.match_files();
+            }
+            Err(e) => {
+                log::error!("Error loading hash list {}: {}", name, e);
+                self.error = Some(format!("Couldn't load {}: {}", name, e));
+            }
+        }
+    }
+
+    /// Hash every file on the volume.
+    pub fn hash_files(&mut self, volume: &FatVolume, tree: &[FileNode]) {
+        self.hashes = flatten(tree)
+            .into_iter()
+            .filter(|node| !node.entry.is_dir() && !node.entry.is_volume_label())
+            .map(|node| FileHash {
+                path: node.path.clone(),
+                size: node.entry.size,
+                digests: Digests::new(&volume.read_file(&node.entry)),
+            })
+            .collect();
+        self.match_files();
+    }
+
+    fn match_files(&mut self) {
+        self.matches.clear();
+        for hash in self.hashes.iter() {
+            for list in self.lists.iter() {
+                let digest = hash.digests.get(list.kind);
+                for entry in list.entries.iter().filter(|entry| entry.digest == digest) {
+                    self.matches.push(FileMatch {
+                        path: hash.path.clone(),
+                        list: list.name.clone(),
+                        kind: list.kind,
+                        identity: entry.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Write the file hashes as a GNU style SHA-1 list, which can be edited into a hash list.
+    fn sha1_list(&self) -> String {
+        self.hashes
+            .iter()
+            .map(|hash| format!("{}  {}\n", hash.digests.sha1, hash.path.trim_start_matches('/')))
+            .collect()
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, volume_name: &str) {
+        egui::CollapsingHeader::new(format!("File hashes ({} identified)", self.matches.len()))
+            .id_salt("fat_file_ident")
+            .show(ui, |ui| {
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                if self.lists.is_empty() {
+                    ui.weak(format!(
+                        "Drop a hash list (e.g. programs{}sha1) to identify the files on this disk.",
+                        HASH_LIST_MARKER
+                    ));
+                }
+                else {
+                    let names: Vec<&str> = self.lists.iter().map(|list| list.name.as_str()).collect();
+                    ui.label(format!("Hash lists: {}", names.join(", ")));
+                }
+
+                for file_match in self.matches.iter() {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(egui::Color32::GREEN, &file_match.identity);
+                        ui.label(format!(
+                            "matches {} by {} in {}",
+                            file_match.path,
+                            file_match.kind.label(),
+                            file_match.list
+                        ));
+                    });
+                }
+
+                egui::ScrollArea::vertical()
+                    .id_salt("fat_file_ident_hashes")
+                    .max_height(BROWSER_MAX_HEIGHT)
+                    .show(ui, |ui| {
+                        egui::Grid::new("fat_file_ident_grid").striped(true).num_columns(4).show(ui, |ui| {
+                            ui.strong("File");
+                            ui.strong("Size");
+                            ui.strong("CRC32");
+                            ui.strong("SHA-1");
+                            ui.end_row();
+                            for hash in self.hashes.iter() {
+                                ui.label(&hash.path);
+                                ui.label(hash.size.to_string());
+                                ui.monospace(&hash.digests.crc32);
+                                ui.monospace(&hash.digests.sha1).on_hover_text(format!("MD5 {}", hash.digests.md5));
+                                ui.end_row();
+                            }
+                        });
+                    });
+
+                if ui.button("Download SHA-1 list").clicked() {
+                    let filename = format!("{}.sha1", volume_name);
+                    if let Err(e) = storage::download_bytes(self.sha1_list().as_bytes(), &filename) {
+                        log::error!("Error downloading {}: {:?}", filename, e);
+                    }
+                }
+            });
+    }
+}
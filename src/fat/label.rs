@@ -0,0 +1,178 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Editing the volume label, serial number and OEM name of a FAT volume. The label is kept in
+//! two places: the extended BPB of the boot sector, and a volume label entry in the root
+//! directory, which is the one DOS shows. Both are written.
+
+use crate::analysis::SectorKey;
+use crate::fat::browser::BrowserEvent;
+use crate::fat::{FatVolume, ATTR_VOLUME_ID, DIR_ENTRY_SIZE};
+
+const OEM_RANGE: std::ops::Range<usize> = 0x03..0x0B;
+const SERIAL_OFFSET: usize = 0x27;
+const LABEL_RANGE: std::ops::Range<usize> = 0x2B..0x36;
+const LABEL_LEN: usize = 11;
+/// What FORMAT writes to the boot sector of a volume without a label.
+const NO_NAME: &str = "NO NAME";
+/// Characters DOS doesn't allow in volume labels.
+const INVALID_LABEL_CHARS: &str = "*?/\\|.,;:+=<>[]\"";
+
+/// Pad `text` with spaces to `len` bytes.
+fn padded(text: &str, len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = text.bytes().take(len).collect();
+    bytes.resize(len, b' ');
+    bytes
+}
+
+#[derive(Default)]
+pub struct LabelEditor {
+    oem: String,
+    label: String,
+    serial: String,
+    error: Option<String>,
+}
+
+impl LabelEditor {
+    pub fn load(&mut self, volume: &FatVolume) {
+        self.oem = volume.bpb.oem_name.clone();
+        self.label = volume.label().unwrap_or_default();
+        self.serial = match volume.bpb.volume_id {
+            Some(id) => format!("{:04X}-{:04X}", id >> 16, id & 0xFFFF),
+            None => String::new(),
+        };
+        self.error = None;
+    }
+
+    fn parse_serial(&self) -> Result<u32, String> {
+        u32::from_str_radix(&self.serial.replace('-', ""), 16)
+            .map_err(|_| format!("{} isn't a serial number in the form 1234-ABCD.", self.serial))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.oem.len() > 8 || !self.oem.is_ascii() {
+            return Err("The OEM name must be up to 8 ASCII characters.".to_string());
+        }
+        if self.label.len() > LABEL_LEN || !self.label.is_ascii() {
+            return Err("The label must be up to 11 ASCII characters.".to_string());
+        }
+        if let Some(c) = self.label.chars().find(|c| INVALID_LABEL_CHARS.contains(*c) || c.is_ascii_control()) {
+            return Err(format!("Labels can't contain '{}'.", c));
+        }
+        Ok(())
+    }
+
+    /// The boot sector and root directory sectors to write for the edited values.
+    fn patches(&self, volume: &FatVolume) -> Result<Vec<(SectorKey, Vec<u8>)>, String> {
+        self.validate()?;
+        let label = self.label.to_ascii_uppercase();
+        let mut patches = Vec::new();
+
+        let mut boot = volume.volume.sector(0).ok_or("The boot sector wasn't found")?.to_vec();
+        boot[OEM_RANGE].copy_from_slice(&padded(&self.oem, OEM_RANGE.len()));
+        if volume.bpb.volume_id.is_some() {
+            boot[SERIAL_OFFSET..SERIAL_OFFSET + 4].copy_from_slice(&self.parse_serial()?.to_le_bytes());
+            let boot_label = if label.is_empty() { NO_NAME } else { &label };
+            boot[LABEL_RANGE].copy_from_slice(&padded(boot_label, LABEL_LEN));
+        }
+        patches.push((volume.volume.sector_key(0).ok_or("The boot sector wasn't found")?, boot));
+
+        // Rewrite the existing label entry, or take the first free slot for a new one.
+        let existing = volume.read_dir(None).into_iter().find(|entry| entry.is_volume_label() && !entry.deleted);
+        let slot = match existing {
+            Some(entry) => Some((entry.entry_lba, entry.entry_offset)),
+            None if !label.is_empty() => Some(self.free_root_slot(volume).ok_or("The root directory is full")?),
+            None => None,
+        };
+        if let Some((lba, offset)) = slot {
+            let mut sector = volume.volume.sector(lba).ok_or("The root directory sector wasn't found")?.to_vec();
+            let raw = &mut sector[offset..offset + DIR_ENTRY_SIZE];
+            if label.is_empty() {
+                raw[0] = 0xE5;
+            }
+            else {
+                raw.fill(0);
+                raw[..LABEL_LEN].copy_from_slice(&padded(&label, LABEL_LEN));
+                raw[11] = ATTR_VOLUME_ID;
+            }
+            let key = volume.volume.sector_key(lba).ok_or("The root directory sector wasn't found")?;
+            patches.push((key, sector));
+        }
+        Ok(patches)
+    }
+
+    fn free_root_slot(&self, volume: &FatVolume) -> Option<(usize, usize)> {
+        (volume.bpb.root_lba()..volume.bpb.data_lba()).find_map(|lba| {
+            let sector = volume.volume.sector(lba)?;
+            sector
+                .chunks_exact(DIR_ENTRY_SIZE)
+                .position(|raw| raw[0] == 0 || raw[0] == 0xE5)
+                .map(|i| (lba, i * DIR_ENTRY_SIZE))
+        })
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, volume: &FatVolume) -> Option<BrowserEvent> {
+        let mut event = None;
+        egui::CollapsingHeader::new("Volume label").id_salt("fat_label_editor").show(ui, |ui| {
+            egui::Grid::new("fat_label_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Label");
+                ui.add(egui::TextEdit::singleline(&mut self.label).char_limit(LABEL_LEN));
+                ui.end_row();
+                ui.label("Serial number");
+                ui.add_enabled_ui(volume.bpb.volume_id.is_some(), |ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.serial).char_limit(9))
+                        .on_disabled_hover_text("Volumes formatted before DOS 4 have no serial number.");
+                });
+                ui.end_row();
+                ui.label("OEM name");
+                ui.add(egui::TextEdit::singleline(&mut self.oem).char_limit(8));
+                ui.end_row();
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Write").clicked() {
+                    match self.patches(volume) {
+                        Ok(patches) => {
+                            self.error = None;
+                            event = Some(BrowserEvent::WriteSectors {
+                                description: "Edit volume label".to_string(),
+                                sectors: patches,
+                            });
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+                if ui.button("Revert").clicked() {
+                    self.load(volume);
+                }
+            });
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+        event
+    }
+}
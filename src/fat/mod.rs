@@ -0,0 +1,751 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A read-only FAT12/FAT16 filesystem reader over the logical sectors of a disk image.
+
+pub mod browser;
+pub mod check;
+pub mod copies;
+pub mod exe;
+pub mod free_space;
+pub mod ident;
+pub mod label;
+pub mod search;
+pub mod slack;
+pub mod timeline;
+pub mod treemap;
+pub mod undelete;
+
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
+use anyhow::{anyhow, Error};
+use fluxfox::DiskImage;
+
+use crate::analysis::{read_all_sectors, SectorKey, SectorRead};
+
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Long file name entries set all of the low four attribute bits.
+pub const ATTR_LONG_NAME: u8 = 0x0F;
+
+const DELETED_MARKER: u8 = 0xE5;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+}
+
+impl std::fmt::Display for FatType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FatType::Fat12 => write!(f, "FAT12"),
+            FatType::Fat16 => write!(f, "FAT16"),
+        }
+    }
+}
+
+/// The BIOS Parameter Block from the boot sector, with the extended fields if present.
+#[derive(Clone, Debug)]
+pub struct BiosParameterBlock {
+    pub oem_name: String,
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub root_entries: u16,
+    pub total_sectors: u32,
+    pub media_descriptor: u8,
+    pub sectors_per_fat: u16,
+    pub sectors_per_track: u16,
+    pub heads: u16,
+    pub volume_id: Option<u32>,
+    pub volume_label: Option<String>,
+}
+
+impl BiosParameterBlock {
+    pub fn parse(boot: &[u8]) -> Result<Self, Error> {
+        if boot.len() < 64 {
+            return Err(anyhow!("Boot sector too short"));
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes([boot[offset], boot[offset + 1], boot[offset + 2], boot[offset + 3]]);
+
+        let total_sectors = match u16_at(0x13) {
+            0 => u32_at(0x20),
+            n => n as u32,
+        };
+        let extended = boot[0x26] == 0x29;
+
+        let bpb = Self {
+            oem_name: decode_name(&boot[0x03..0x0B]),
+            bytes_per_sector: u16_at(0x0B),
+            sectors_per_cluster: boot[0x0D],
+            reserved_sectors: u16_at(0x0E),
+            fat_count: boot[0x10],
+            root_entries: u16_at(0x11),
+            total_sectors,
+            media_descriptor: boot[0x15],
+            sectors_per_fat: u16_at(0x16),
+            sectors_per_track: u16_at(0x18),
+            heads: u16_at(0x1A),
+            volume_id: extended.then(|| u32_at(0x27)),
+            volume_label: extended.then(|| decode_name(&boot[0x2B..0x36])),
+        };
+
+        if !matches!(bpb.bytes_per_sector, 128 | 256 | 512 | 1024 | 2048 | 4096) {
+            return Err(anyhow!("Invalid bytes per sector: {}", bpb.bytes_per_sector));
+        }
+        if !bpb.sectors_per_cluster.is_power_of_two() {
+            return Err(anyhow!("Invalid sectors per cluster: {}", bpb.sectors_per_cluster));
+        }
+        if bpb.fat_count == 0 || bpb.sectors_per_fat == 0 || bpb.reserved_sectors == 0 {
+            return Err(anyhow!("Invalid FAT layout"));
+        }
+        if bpb.sectors_per_track == 0 || bpb.heads == 0 || bpb.total_sectors == 0 {
+            return Err(anyhow!("Invalid disk geometry"));
+        }
+        Ok(bpb)
+    }
+
+    pub fn fat_lba(&self) -> usize {
+        self.reserved_sectors as usize
+    }
+
+    pub fn root_lba(&self) -> usize {
+        self.fat_lba() + self.fat_count as usize * self.sectors_per_fat as usize
+    }
+
+    pub fn root_sectors(&self) -> usize {
+        (self.root_entries as usize * DIR_ENTRY_SIZE).div_ceil(self.bytes_per_sector as usize)
+    }
+
+    pub fn data_lba(&self) -> usize {
+        self.root_lba() + self.root_sectors()
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors = (self.total_sectors as usize).saturating_sub(self.data_lba());
+        (data_sectors / self.sectors_per_cluster as usize) as u32
+    }
+
+    pub fn fat_type(&self) -> Result<FatType, Error> {
+        match self.cluster_count() {
+            0 => Err(anyhow!("No data clusters")),
+            1..=4084 => Ok(FatType::Fat12),
+            4085..=65524 => Ok(FatType::Fat16),
+            n => Err(anyhow!("Unsupported cluster count for a floppy filesystem: {}", n)),
+        }
+    }
+}
+
+/// The sectors of a disk image in logical (LBA) order, assembled using the geometry in the
+/// boot sector's BPB.
+#[derive(Clone, Debug)]
+pub struct LogicalVolume {
+    pub sector_size: usize,
+    pub data: Vec<u8>,
+    /// The physical location of each logical sector, or None if it wasn't found on the disk.
+    pub sectors: Vec<Option<SectorKey>>,
+    /// Logical sectors that were read with a data CRC error.
+    pub bad: Vec<bool>,
+    /// The number of sectors the BPB gives the volume, which may be more than the image's
+    /// tracks can hold.
+    pub claimed_sectors: usize,
+}
+
+impl LogicalVolume {
+    pub fn from_disk(disk: &mut DiskImage) -> Result<(Self, BiosParameterBlock), Error> {
+        let reads = read_all_sectors(disk);
+        let boot = reads
+            .iter()
+            .find(|read| read.key == SectorKey { c: 0, h: 0, s: 1 })
+            .ok_or(anyhow!("No boot sector found"))?;
+        let bpb = BiosParameterBlock::parse(&boot.data)?;
+
+        let sector_size = bpb.bytes_per_sector as usize;
+        let spt = bpb.sectors_per_track as usize;
+        let heads = bpb.heads as usize;
+        // The boot sector can claim any size, so the volume is no bigger than the image's
+        // tracks could hold with the BPB's sectors per track.
+        let tracks: usize = disk.get_sector_map().iter().map(|cylinders| cylinders.len()).sum();
+        let held = tracks * spt.min(u8::MAX as usize);
+        let claimed = bpb.total_sectors as usize;
+        let total = claimed.min(held);
+        if total < claimed {
+            log::warn!("BPB claims {} sectors, but the image's tracks hold at most {}", claimed, held);
+        }
+
+        let mut volume = Self {
+            sector_size,
+            data: vec![0; total * sector_size],
+            sectors: vec![None; total],
+            bad: vec![false; total],
+            claimed_sectors: claimed,
+        };
+
+        // Sectors that aren't the BPB's size or whose IDs are past the end of a track, by track.
+        let mut irregular: BTreeMap<(u16, u8), Vec<&SectorRead>> = BTreeMap::new();
+        for read in reads.iter() {
+            let s = read.key.s as usize;
+            if read.key.h as usize >= heads {
+                continue;
+            }
+            if s == 0 || s > spt || read.data.len() != sector_size {
+                irregular.entry((read.key.c, read.key.h)).or_default().push(read);
+                continue;
+            }
+            let lba = (read.key.c as usize * heads + read.key.h as usize) * spt + (s - 1);
+            // The first sector found with a given ID wins; duplicates are usually protection.
+            if lba >= total || volume.sectors[lba].is_some() {
+                continue;
+            }
+            volume.place_data(lba, &read.data, read);
+        }
+
+        // Formats such as XDF pack a track into a few large sectors instead. Their data is laid
+        // out in ID order over the logical sectors the track has left, a sector at a time, so
+        // the filesystem can still be read from them. Tracks without any large sectors are left
+        // alone, as their stray IDs are more likely protection than data.
+        for ((c, h), mut reads) in irregular {
+            if !reads.iter().any(|read| read.data.len() > sector_size) {
+                continue;
+            }
+            reads.sort_by_key(|read| read.key.s);
+            let first = (c as usize * heads + h as usize) * spt;
+            let mut free = (first..(first + spt).min(total)).filter(|lba| volume.sectors[*lba].is_none());
+            'reads: for read in reads {
+                for chunk in read.data.chunks(sector_size) {
+                    let Some(lba) = free.next()
+                    else {
+                        break 'reads;
+                    };
+                    volume.place_data(lba, chunk, read);
+                }
+            }
+        }
+
+        Ok((volume, bpb))
+    }
+
+    /// Put `data`, read from `read`, at logical sector `lba`.
+    fn place_data(&mut self, lba: usize, data: &[u8], read: &SectorRead) {
+        let len = data.len().min(self.sector_size);
+        self.data[lba * self.sector_size..lba * self.sector_size + len].copy_from_slice(&data[..len]);
+        self.sectors[lba] = Some(read.key);
+        self.bad[lba] = read.data_crc_error;
+    }
+
+    pub fn sector(&self, lba: usize) -> Option<&[u8]> {
+        self.data.get(lba * self.sector_size..(lba + 1) * self.sector_size)
+    }
+
+    pub fn sector_key(&self, lba: usize) -> Option<SectorKey> {
+        self.sectors.get(lba).copied().flatten()
+    }
+
+    /// The logical sector stored at the specified physical sector, if any.
+    pub fn lba(&self, key: SectorKey) -> Option<usize> {
+        self.sectors.iter().position(|sector| *sector == Some(key))
+    }
+
+    pub fn read(&self, lbas: impl IntoIterator<Item = usize>) -> Vec<u8> {
+        let mut data = Vec::new();
+        for lba in lbas {
+            match self.sector(lba) {
+                Some(sector) => data.extend_from_slice(sector),
+                None => data.resize(data.len() + self.sector_size, 0),
+            }
+        }
+        data
+    }
+}
+
+/// A packed FAT date and time.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct FatTimestamp {
+    pub date: u16,
+    pub time: u16,
+}
+
+impl FatTimestamp {
+    pub fn is_set(&self) -> bool {
+        self.date != 0
+    }
+
+    pub fn year(&self) -> u16 {
+        1980 + (self.date >> 9)
+    }
+
+    pub fn month(&self) -> u16 {
+        (self.date >> 5) & 0x0F
+    }
+
+    pub fn day(&self) -> u16 {
+        self.date & 0x1F
+    }
+
+    /// Whether every field is in range. Out of range fields are never written by DOS, so
+    /// point to a damaged directory or a tampered image.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month())
+            && (1..=31).contains(&self.day())
+            && self.time >> 11 < 24
+            && (self.time >> 5) & 0x3F < 60
+            && self.time & 0x1F < 30
+    }
+}
+
+impl std::fmt::Display for FatTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.is_set() {
+            return write!(f, "-");
+        }
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year(),
+            self.month(),
+            self.day(),
+            self.time >> 11,
+            (self.time >> 5) & 0x3F,
+            (self.time & 0x1F) * 2
+        )
+    }
+}
+
+/// A short-name directory entry.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub attributes: u8,
+    pub cluster: u32,
+    pub size: u32,
+    pub modified: FatTimestamp,
+    /// The creation time and last access date, which DOS 7 and later record. Zero before that.
+    pub created: FatTimestamp,
+    pub accessed: FatTimestamp,
+    pub deleted: bool,
+    /// The logical sector and byte offset of the entry itself within its directory.
+    pub entry_lba: usize,
+    pub entry_offset: usize,
+}
+
+impl DirEntry {
+    fn parse(raw: &[u8], entry_lba: usize, entry_offset: usize) -> Self {
+        let deleted = raw[0] == DELETED_MARKER;
+        let mut name_bytes = [0u8; 8];
+        name_bytes.copy_from_slice(&raw[0..8]);
+        match name_bytes[0] {
+            DELETED_MARKER => name_bytes[0] = b'?',
+            // 0x05 stands in for a real 0xE5 first character.
+            0x05 => name_bytes[0] = DELETED_MARKER,
+            _ => {}
+        }
+        let base = decode_name(&name_bytes);
+        let ext = decode_name(&raw[8..11]);
+        let name = if ext.is_empty() { base } else { format!("{}.{}", base, ext) };
+
+        Self {
+            name,
+            attributes: raw[11],
+            cluster: u16::from_le_bytes([raw[26], raw[27]]) as u32,
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]),
+            modified: FatTimestamp {
+                time: u16::from_le_bytes([raw[22], raw[23]]),
+                date: u16::from_le_bytes([raw[24], raw[25]]),
+            },
+            created: FatTimestamp {
+                time: u16::from_le_bytes([raw[14], raw[15]]),
+                date: u16::from_le_bytes([raw[16], raw[17]]),
+            },
+            accessed: FatTimestamp {
+                time: 0,
+                date: u16::from_le_bytes([raw[18], raw[19]]),
+            },
+            deleted,
+            entry_lba,
+            entry_offset,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes & ATTR_VOLUME_ID != 0
+    }
+
+    /// A short attribute string in the style of `ATTRIB`.
+    pub fn attribute_string(&self) -> String {
+        [(ATTR_READ_ONLY, 'R'), (ATTR_HIDDEN, 'H'), (ATTR_SYSTEM, 'S'), (ATTR_ARCHIVE, 'A'), (ATTR_DIRECTORY, 'D')]
+            .iter()
+            .map(|(bit, c)| if self.attributes & bit != 0 { *c } else { '-' })
+            .collect()
+    }
+}
+
+/// Why a cluster chain stopped.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChainEnd {
+    EndOfChain,
+    Free,
+    Bad,
+    OutOfRange,
+    Loop,
+}
+
+impl std::fmt::Display for ChainEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainEnd::EndOfChain => write!(f, "end of chain"),
+            ChainEnd::Free => write!(f, "a free cluster"),
+            ChainEnd::Bad => write!(f, "a bad cluster"),
+            ChainEnd::OutOfRange => write!(f, "an invalid cluster number"),
+            ChainEnd::Loop => write!(f, "a loop"),
+        }
+    }
+}
+
+/// What a logical sector holds.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SectorRole {
+    Boot,
+    Reserved,
+    Fat,
+    Directory,
+    Data,
+}
+
+/// A file or directory in the filesystem tree.
+#[derive(Clone, Debug)]
+pub struct FileNode {
+    pub path: String,
+    pub entry: DirEntry,
+    pub children: Vec<FileNode>,
+}
+
+/// Flatten a file tree into a list of nodes, depth-first.
+pub fn flatten(nodes: &[FileNode]) -> Vec<&FileNode> {
+    let mut flat = Vec::new();
+    for node in nodes {
+        flat.push(node);
+        flat.extend(flatten(&node.children));
+    }
+    flat
+}
+
+/// A mounted FAT12/FAT16 volume.
+#[derive(Clone, Debug)]
+pub struct FatVolume {
+    pub bpb: BiosParameterBlock,
+    pub fat_type: FatType,
+    pub volume: LogicalVolume,
+    /// The raw bytes of the first FAT.
+    pub fat: Vec<u8>,
+}
+
+impl FatVolume {
+    pub fn from_disk(disk: &mut DiskImage) -> Result<Self, Error> {
+        let (volume, bpb) = LogicalVolume::from_disk(disk)?;
+        Self::new(volume, bpb)
+    }
+
+    pub fn new(volume: LogicalVolume, bpb: BiosParameterBlock) -> Result<Self, Error> {
+        let fat_type = bpb.fat_type()?;
+        let fat_lbas = bpb.fat_lba()..bpb.fat_lba() + bpb.sectors_per_fat as usize;
+        let fat = volume.read(fat_lbas);
+        Ok(Self {
+            bpb,
+            fat_type,
+            volume,
+            fat,
+        })
+    }
+
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.bpb.cluster_count() + 2
+    }
+
+    pub fn fat_entry(&self, cluster: u32) -> u32 {
+        self.entry_in(&self.fat, cluster)
+    }
+
+    /// Read a cluster's entry from `fat`, which may be any copy of the FAT.
+    pub fn entry_in(&self, fat: &[u8], cluster: u32) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster as usize * 3 / 2;
+                let Some(bytes) = fat.get(offset..offset + 2)
+                else {
+                    return 0xFFF;
+                };
+                let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+                if cluster & 1 == 1 { (value >> 4) as u32 } else { (value & 0x0FFF) as u32 }
+            }
+            FatType::Fat16 => {
+                let offset = cluster as usize * 2;
+                let Some(bytes) = fat.get(offset..offset + 2)
+                else {
+                    return 0xFFFF;
+                };
+                u16::from_le_bytes([bytes[0], bytes[1]]) as u32
+            }
+        }
+    }
+
+    /// Write a cluster's entry into `fat`, leaving the neighbouring FAT12 entry's nibble alone.
+    pub fn set_entry_in(&self, fat: &mut [u8], cluster: u32, value: u32) {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster as usize * 3 / 2;
+                let Some(bytes) = fat.get_mut(offset..offset + 2)
+                else {
+                    return;
+                };
+                let old = u16::from_le_bytes([bytes[0], bytes[1]]);
+                let value = (value & 0x0FFF) as u16;
+                let new = if cluster & 1 == 1 { (old & 0x000F) | (value << 4) } else { (old & 0xF000) | value };
+                bytes.copy_from_slice(&new.to_le_bytes());
+            }
+            FatType::Fat16 => {
+                let offset = cluster as usize * 2;
+                if let Some(bytes) = fat.get_mut(offset..offset + 2) {
+                    bytes.copy_from_slice(&(value as u16).to_le_bytes());
+                }
+            }
+        }
+    }
+
+    pub fn end_of_chain_marker(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+        }
+    }
+
+    pub fn bad_cluster_marker(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFF7,
+            FatType::Fat16 => 0xFFF7,
+        }
+    }
+
+    pub fn is_end_of_chain(&self, entry: u32) -> bool {
+        match self.fat_type {
+            FatType::Fat12 => entry >= 0xFF8,
+            FatType::Fat16 => entry >= 0xFFF8,
+        }
+    }
+
+    pub fn is_bad_cluster(&self, entry: u32) -> bool {
+        match self.fat_type {
+            FatType::Fat12 => entry == 0xFF7,
+            FatType::Fat16 => entry == 0xFFF7,
+        }
+    }
+
+    /// Follow a cluster chain from `start`. The chain stops at the end-of-chain marker, or at
+    /// the first free, bad, out-of-range or already visited cluster.
+    pub fn cluster_chain(&self, start: u32) -> Vec<u32> {
+        self.follow_chain(start).0
+    }
+
+    /// Follow a cluster chain from `start`, returning the clusters and why the chain stopped.
+    pub fn follow_chain(&self, start: u32) -> (Vec<u32>, ChainEnd) {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut cluster = start;
+        loop {
+            if !self.is_valid_cluster(cluster) {
+                return (chain, ChainEnd::OutOfRange);
+            }
+            if !visited.insert(cluster) {
+                return (chain, ChainEnd::Loop);
+            }
+            chain.push(cluster);
+            let next = self.fat_entry(cluster);
+            if next == 0 {
+                return (chain, ChainEnd::Free);
+            }
+            if self.is_end_of_chain(next) {
+                return (chain, ChainEnd::EndOfChain);
+            }
+            if self.is_bad_cluster(next) {
+                return (chain, ChainEnd::Bad);
+            }
+            cluster = next;
+        }
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bpb.sectors_per_cluster as usize * self.volume.sector_size
+    }
+
+    pub fn cluster_lbas(&self, cluster: u32) -> Range<usize> {
+        let spc = self.bpb.sectors_per_cluster as usize;
+        let start = self.bpb.data_lba() + (cluster as usize - 2) * spc;
+        start..start + spc
+    }
+
+    /// The logical sectors occupied by an entry's data, in file order.
+    pub fn entry_lbas(&self, entry: &DirEntry) -> Vec<usize> {
+        self.cluster_chain(entry.cluster)
+            .into_iter()
+            .flat_map(|cluster| self.cluster_lbas(cluster))
+            .collect()
+    }
+
+    /// The number of contiguous runs of clusters in an entry's chain. A file with more than one
+    /// fragment is fragmented.
+    pub fn fragments(&self, entry: &DirEntry) -> usize {
+        let chain = self.cluster_chain(entry.cluster);
+        if chain.is_empty() {
+            return 0;
+        }
+        1 + chain.windows(2).filter(|pair| pair[1] != pair[0] + 1).count()
+    }
+
+    /// The physical sectors occupied by an entry's data, in file order.
+    pub fn entry_sectors(&self, entry: &DirEntry) -> Vec<SectorKey> {
+        self.entry_lbas(entry)
+            .into_iter()
+            .filter_map(|lba| self.volume.sector_key(lba))
+            .collect()
+    }
+
+    /// Read a file's data. Directories are read to the end of their cluster chain.
+    pub fn read_file(&self, entry: &DirEntry) -> Vec<u8> {
+        let mut data = self.volume.read(self.entry_lbas(entry));
+        if !entry.is_dir() {
+            data.truncate(entry.size as usize);
+        }
+        data
+    }
+
+    fn parse_dir(&self, lbas: impl IntoIterator<Item = usize>) -> Vec<DirEntry> {
+        let mut entries = Vec::new();
+        for lba in lbas {
+            let Some(sector) = self.volume.sector(lba)
+            else {
+                break;
+            };
+            for (i, raw) in sector.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                if raw[0] == 0 {
+                    // End of directory.
+                    return entries;
+                }
+                if raw[11] & ATTR_LONG_NAME == ATTR_LONG_NAME || raw[0] == b'.' {
+                    continue;
+                }
+                entries.push(DirEntry::parse(raw, lba, i * DIR_ENTRY_SIZE));
+            }
+        }
+        entries
+    }
+
+    /// Read the entries of a directory, including deleted entries. `None` reads the root
+    /// directory.
+    pub fn read_dir(&self, dir: Option<&DirEntry>) -> Vec<DirEntry> {
+        match dir {
+            Some(dir) => self.parse_dir(self.entry_lbas(dir)),
+            None => self.parse_dir(self.bpb.root_lba()..self.bpb.data_lba()),
+        }
+    }
+
+    pub fn sector_role(&self, lba: usize) -> SectorRole {
+        if lba == 0 {
+            SectorRole::Boot
+        }
+        else if lba < self.bpb.fat_lba() {
+            SectorRole::Reserved
+        }
+        else if lba < self.bpb.root_lba() {
+            SectorRole::Fat
+        }
+        else if lba < self.bpb.data_lba() {
+            SectorRole::Directory
+        }
+        else {
+            let tree = self.tree();
+            let is_dir = flatten(&tree)
+                .into_iter()
+                .filter(|node| node.entry.is_dir())
+                .any(|node| self.entry_lbas(&node.entry).contains(&lba));
+            if is_dir { SectorRole::Directory } else { SectorRole::Data }
+        }
+    }
+
+    /// The volume label, from the root directory or the extended BPB.
+    pub fn label(&self) -> Option<String> {
+        self.read_dir(None)
+            .into_iter()
+            .find(|entry| entry.is_volume_label() && !entry.deleted)
+            .map(|entry| entry.name.replace('.', ""))
+            .or(self.bpb.volume_label.clone())
+            .filter(|label| !label.is_empty() && label != "NO NAME")
+    }
+
+    /// Build the tree of live (not deleted) files and directories.
+    pub fn tree(&self) -> Vec<FileNode> {
+        let mut visited = HashSet::new();
+        self.build_tree(None, "", &mut visited)
+    }
+
+    fn build_tree(&self, dir: Option<&DirEntry>, parent: &str, visited: &mut HashSet<u32>) -> Vec<FileNode> {
+        let mut nodes = Vec::new();
+        for entry in self.read_dir(dir) {
+            if entry.deleted || entry.is_volume_label() {
+                continue;
+            }
+            let path = format!("{}/{}", parent, entry.name);
+            // Guard against directory loops on damaged filesystems.
+            let children = if entry.is_dir() && visited.insert(entry.cluster) {
+                self.build_tree(Some(&entry), &path, visited)
+            }
+            else {
+                Vec::new()
+            };
+            nodes.push(FileNode { path, entry, children });
+        }
+        nodes
+    }
+}
+
+/// Decode a space-padded name field. Bytes outside ASCII are mapped through Latin-1, which is
+/// close enough to code page 437 for display.
+fn decode_name(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect::<String>().trim_end_matches([' ', '\0']).to_string()
+}
@@ -0,0 +1,206 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Searching a FAT volume's file names and, optionally, file contents. Searches run in a
+//! worker so that content searches of large volumes don't block the UI.
+
+use std::sync::{mpsc, Arc};
+
+use crate::analysis::SectorKey;
+use crate::fat::browser::BrowserEvent;
+use crate::fat::{flatten, FatVolume};
+use crate::worker;
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub contents: bool,
+}
+
+/// A file matching a search, with the physical sector containing the match.
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub path: String,
+    /// Byte offset of the first content match within the file, if the match was in its data.
+    pub offset: Option<usize>,
+    /// The sector holding the match, or the file's first sector for name matches.
+    pub sector: Option<SectorKey>,
+}
+
+pub struct SearchResults {
+    pub generation: u64,
+    pub hits: Vec<SearchHit>,
+}
+
+/// Find the first case-insensitive occurrence of `needle` in `haystack`.
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+pub fn search(volume: &FatVolume, query: &SearchQuery) -> Vec<SearchHit> {
+    let needle = query.text.as_bytes();
+    let tree = volume.tree();
+    let mut hits = Vec::new();
+
+    for node in flatten(&tree) {
+        if find_ignore_case(node.entry.name.as_bytes(), needle).is_some() {
+            hits.push(SearchHit {
+                path: node.path.clone(),
+                offset: None,
+                sector: volume.entry_sectors(&node.entry).first().copied(),
+            });
+        }
+        else if query.contents && !node.entry.is_dir() {
+            let data = volume.read_file(&node.entry);
+            if let Some(offset) = find_ignore_case(&data, needle) {
+                let lbas = volume.entry_lbas(&node.entry);
+                let sector = lbas
+                    .get(offset / volume.volume.sector_size)
+                    .and_then(|lba| volume.volume.sector_key(*lba));
+                hits.push(SearchHit {
+                    path: node.path.clone(),
+                    offset: Some(offset),
+                    sector,
+                });
+            }
+        }
+    }
+    hits
+}
+
+/// The search panel's state. Each search is tagged with a generation so that results from a
+/// superseded search are dropped.
+pub struct FileSearch {
+    pub query: SearchQuery,
+    pub hits: Vec<SearchHit>,
+    running: bool,
+    searched: bool,
+    generation: u64,
+    sender: mpsc::SyncSender<SearchResults>,
+    receiver: mpsc::Receiver<SearchResults>,
+}
+
+impl Default for FileSearch {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::sync_channel(4);
+        Self {
+            query: SearchQuery::default(),
+            hits: Vec::new(),
+            running: false,
+            searched: false,
+            generation: 0,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl FileSearch {
+    pub fn clear(&mut self) {
+        self.hits.clear();
+        self.running = false;
+        self.searched = false;
+        self.generation += 1;
+    }
+
+    pub fn start(&mut self, volume: Arc<FatVolume>) {
+        self.generation += 1;
+        self.hits.clear();
+        self.searched = true;
+        if self.query.text.is_empty() {
+            self.running = false;
+            return;
+        }
+
+        let generation = self.generation;
+        let query = self.query.clone();
+        let sender = self.sender.clone();
+        match worker::spawn_closure_worker(move || {
+            let hits = search(&volume, &query);
+            log::debug!("File search for {:?} found {} hits", query.text, hits.len());
+            if sender.send(SearchResults { generation, hits }).is_err() {
+                log::warn!("File search results receiver dropped");
+            }
+        }) {
+            Ok(_) => self.running = true,
+            Err(e) => log::error!("Error spawning search worker: {:?}", e),
+        }
+    }
+
+    fn poll(&mut self) {
+        while let Ok(results) = self.receiver.try_recv() {
+            if results.generation == self.generation {
+                self.hits = results.hits;
+                self.running = false;
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, volume: &Arc<FatVolume>) -> Option<BrowserEvent> {
+        self.poll();
+        if self.running {
+            ui.ctx().request_repaint();
+        }
+
+        let mut event = None;
+        ui.horizontal(|ui| {
+            ui.label("Find:");
+            let response = ui.text_edit_singleline(&mut self.query.text);
+            ui.checkbox(&mut self.query.contents, "Search contents");
+            let enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Search").clicked() || enter {
+                self.start(volume.clone());
+            }
+            if self.running {
+                ui.spinner();
+            }
+        });
+
+        for hit in self.hits.iter() {
+            ui.horizontal(|ui| {
+                if ui.link(&hit.path).clicked() {
+                    event = Some(BrowserEvent::SelectFile(hit.path.clone()));
+                }
+                if let Some(offset) = hit.offset {
+                    ui.label(format!("at offset {}", offset));
+                }
+                if let Some(key) = hit.sector {
+                    if ui.link(key.to_string()).clicked() {
+                        event = Some(BrowserEvent::SelectSector(key));
+                    }
+                }
+            });
+        }
+        if !self.running && self.hits.is_empty() && self.searched {
+            ui.weak("No matches.");
+        }
+        event
+    }
+}
@@ -0,0 +1,109 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Extraction of the parts of a FAT volume no file owns, for forensic examination: file slack,
+//! the bytes between a file's end and the end of its last cluster, and unallocated clusters.
+//! DOS didn't clear either, so they often hold fragments of earlier files or of memory.
+//!
+//! Each extract is a blob of the bytes back to back, with a tab-separated index of where each
+//! piece came from.
+
+use crate::fat::{flatten, FatVolume, FileNode};
+use crate::storage;
+
+pub struct Extract {
+    pub data: Vec<u8>,
+    pub index: String,
+    /// The number of pieces in the blob.
+    pub pieces: usize,
+}
+
+impl Extract {
+    fn new(header: &str) -> Self {
+        Self {
+            data: Vec::new(),
+            index: format!("{}\n", header),
+            pieces: 0,
+        }
+    }
+
+    /// Download the blob and its index as `<stem>.bin` and `<stem>.txt`.
+    pub fn download(&self, stem: &str) {
+        let files = [
+            (&self.data[..], format!("{}.bin", stem)),
+            (self.index.as_bytes(), format!("{}.txt", stem)),
+        ];
+        for (data, filename) in files {
+            if let Err(e) = storage::download_bytes(data, &filename) {
+                log::error!("Error downloading {}: {:?}", filename, e);
+            }
+        }
+    }
+}
+
+/// The slack of every file in `tree`, in path order.
+pub fn file_slack(volume: &FatVolume, tree: &[FileNode]) -> Extract {
+    let mut extract = Extract::new("path\toffset\tlength\tfirst sector");
+    for node in flatten(tree) {
+        let entry = &node.entry;
+        if entry.is_dir() || entry.deleted || entry.cluster == 0 {
+            continue;
+        }
+        let lbas = volume.entry_lbas(entry);
+        let allocated = lbas.len() * volume.volume.sector_size;
+        let size = entry.size as usize;
+        if size >= allocated {
+            continue;
+        }
+        let data = volume.volume.read(lbas.iter().copied());
+        let first_sector = lbas
+            .get(size / volume.volume.sector_size)
+            .and_then(|lba| volume.volume.sector_key(*lba))
+            .map_or("-".to_string(), |key| key.to_string());
+        extract.index += &format!("{}\t{}\t{}\t{}\n", node.path, extract.data.len(), allocated - size, first_sector);
+        extract.data.extend_from_slice(&data[size..]);
+        extract.pieces += 1;
+    }
+    extract
+}
+
+/// The contents of every free cluster, in cluster order.
+pub fn unallocated(volume: &FatVolume) -> Extract {
+    let mut extract = Extract::new("cluster\toffset\tlength\tfirst sector");
+    for cluster in 2..volume.bpb.cluster_count() + 2 {
+        if volume.fat_entry(cluster) != 0 {
+            continue;
+        }
+        let lbas = volume.cluster_lbas(cluster);
+        let first_sector = volume.volume.sector_key(lbas.start).map_or("-".to_string(), |key| key.to_string());
+        let data = volume.volume.read(lbas);
+        extract.index += &format!("{}\t{}\t{}\t{}\n", cluster, extract.data.len(), data.len(), first_sector);
+        extract.data.extend_from_slice(&data);
+        extract.pieces += 1;
+    }
+    extract
+}
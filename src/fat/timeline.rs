@@ -0,0 +1,281 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A timeline of the timestamps in a FAT volume's directories: a histogram by year, a sortable
+//! table of file dates, and the anomalies that suggest an image was tampered with or its clock
+//! was wrong, such as dates in the future or files created after they were last modified.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::fat::{flatten, FatTimestamp, FileNode};
+
+pub const HISTOGRAM_HEIGHT: f32 = 60.0;
+pub const HISTOGRAM_BAR_WIDTH: f32 = 12.0;
+/// Files dated this many years after nearly all the others are flagged as outliers.
+pub const OUTLIER_YEARS: u16 = 5;
+
+#[derive(Clone, Debug)]
+pub struct FileDates {
+    pub path: String,
+    pub modified: FatTimestamp,
+    pub created: FatTimestamp,
+    pub accessed: FatTimestamp,
+}
+
+impl FileDates {
+    /// The set timestamps, for the histogram and the last use.
+    fn timestamps(&self) -> impl Iterator<Item = FatTimestamp> + '_ {
+        [self.modified, self.created, self.accessed].into_iter().filter(|timestamp| timestamp.is_set())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SortColumn {
+    Path,
+    #[default]
+    Modified,
+    Created,
+    Accessed,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    pub files: Vec<FileDates>,
+    /// Anomalies found, by path.
+    pub anomalies: Vec<(String, String)>,
+    sort: SortColumn,
+    descending: bool,
+}
+
+/// The current year, from the browser's clock.
+fn current_year() -> u16 {
+    web_sys::js_sys::Date::new_0().get_full_year() as u16
+}
+
+impl Timeline {
+    pub fn new(tree: &[FileNode]) -> Self {
+        let files: Vec<FileDates> = flatten(tree)
+            .into_iter()
+            .filter(|node| !node.entry.is_volume_label())
+            .map(|node| FileDates {
+                path: node.path.clone(),
+                modified: node.entry.modified,
+                created: node.entry.created,
+                accessed: node.entry.accessed,
+            })
+            .collect();
+        let anomalies = find_anomalies(&files, current_year());
+        Self {
+            files,
+            anomalies,
+            ..Default::default()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+        self.anomalies.clear();
+    }
+
+    /// The latest valid timestamp, the best guess of when the disk was last used.
+    pub fn last_use(&self) -> Option<FatTimestamp> {
+        self.files
+            .iter()
+            .flat_map(|file| file.timestamps())
+            .filter(|timestamp| timestamp.is_valid() && timestamp.year() <= current_year())
+            .max()
+    }
+
+    /// The number of timestamps in each year.
+    pub fn histogram(&self) -> BTreeMap<u16, usize> {
+        let mut years = BTreeMap::new();
+        for timestamp in self.files.iter().flat_map(|file| file.timestamps()).filter(|t| t.is_valid()) {
+            *years.entry(timestamp.year()).or_insert(0) += 1;
+        }
+        years
+    }
+
+    fn sorted(&self) -> Vec<&FileDates> {
+        let mut files: Vec<&FileDates> = self.files.iter().collect();
+        files.sort_by(|a, b| -> Ordering {
+            match self.sort {
+                SortColumn::Path => a.path.cmp(&b.path),
+                SortColumn::Modified => a.modified.cmp(&b.modified),
+                SortColumn::Created => a.created.cmp(&b.created),
+                SortColumn::Accessed => a.accessed.cmp(&b.accessed),
+            }
+        });
+        if self.descending {
+            files.reverse();
+        }
+        files
+    }
+
+    fn sort_header(&mut self, ui: &mut egui::Ui, column: SortColumn, label: &str) {
+        let text = match (self.sort == column, self.descending) {
+            (true, false) => format!("{} ⏶", label),
+            (true, true) => format!("{} ⏷", label),
+            _ => label.to_string(),
+        };
+        if ui.add(egui::Label::new(egui::RichText::new(text).strong()).sense(egui::Sense::click())).clicked() {
+            self.descending = self.sort == column && !self.descending;
+            self.sort = column;
+        }
+    }
+
+    fn show_histogram(&self, ui: &mut egui::Ui) {
+        let histogram = self.histogram();
+        let (Some(first), Some(last)) = (histogram.keys().next().copied(), histogram.keys().last().copied())
+        else {
+            return;
+        };
+        let max = histogram.values().copied().max().unwrap_or(1) as f32;
+        let years = (last - first + 1) as f32;
+        let size = egui::vec2(years * HISTOGRAM_BAR_WIDTH, HISTOGRAM_HEIGHT);
+
+        egui::ScrollArea::horizontal().id_salt("fat_timeline_histogram").show(ui, |ui| {
+            let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+            let painter = ui.painter_at(rect);
+            let color = ui.visuals().selection.bg_fill;
+            for (year, count) in histogram.iter() {
+                let x = rect.min.x + (year - first) as f32 * HISTOGRAM_BAR_WIDTH;
+                let height = *count as f32 / max * HISTOGRAM_HEIGHT;
+                let bar = egui::Rect::from_min_max(
+                    egui::pos2(x + 1.0, rect.max.y - height),
+                    egui::pos2(x + HISTOGRAM_BAR_WIDTH - 1.0, rect.max.y),
+                );
+                painter.rect_filled(bar, egui::Rounding::ZERO, color);
+            }
+            if let Some(pos) = response.hover_pos() {
+                let year = first + ((pos.x - rect.min.x) / HISTOGRAM_BAR_WIDTH) as u16;
+                let count = histogram.get(&year).copied().unwrap_or(0);
+                response.on_hover_text(format!("{}: {} timestamps", year, count));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.weak(first.to_string());
+            ui.add_space((size.x - 60.0).max(8.0));
+            ui.weak(last.to_string());
+        });
+    }
+
+    /// Show the timeline. Returns the path of a file the user clicked on.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let mut selected = None;
+        if self.files.is_empty() {
+            return None;
+        }
+        let title = match self.anomalies.len() {
+            0 => "Timeline".to_string(),
+            n => format!("Timeline ({} anomalies)", n),
+        };
+        egui::CollapsingHeader::new(title).id_salt("fat_timeline").show(ui, |ui| {
+            match self.last_use() {
+                Some(last_use) => ui.label(format!("Last used: {}", last_use)),
+                None => ui.label("No valid timestamps."),
+            };
+            self.show_histogram(ui);
+
+            for (path, anomaly) in self.anomalies.iter() {
+                ui.horizontal(|ui| {
+                    if ui.link(path).clicked() {
+                        selected = Some(path.clone());
+                    }
+                    ui.colored_label(ui.visuals().warn_fg_color, anomaly);
+                });
+            }
+
+            ui.separator();
+            egui::ScrollArea::vertical().id_salt("fat_timeline_table").max_height(240.0).show(ui, |ui| {
+                egui::Grid::new("fat_timeline_grid").striped(true).num_columns(4).show(ui, |ui| {
+                    self.sort_header(ui, SortColumn::Path, "Path");
+                    self.sort_header(ui, SortColumn::Modified, "Modified");
+                    self.sort_header(ui, SortColumn::Created, "Created");
+                    self.sort_header(ui, SortColumn::Accessed, "Accessed");
+                    ui.end_row();
+                    for file in self.sorted() {
+                        if ui.link(&file.path).clicked() {
+                            selected = Some(file.path.clone());
+                        }
+                        ui.monospace(file.modified.to_string());
+                        ui.monospace(file.created.to_string());
+                        // Only the date of the last access is recorded.
+                        let accessed = &file.accessed;
+                        let date = match accessed.is_set() {
+                            true => format!("{:04}-{:02}-{:02}", accessed.year(), accessed.month(), accessed.day()),
+                            false => "-".to_string(),
+                        };
+                        ui.monospace(date);
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+        selected
+    }
+}
+
+fn find_anomalies(files: &[FileDates], current_year: u16) -> Vec<(String, String)> {
+    let mut anomalies = Vec::new();
+    let mut years: Vec<u16> = files
+        .iter()
+        .map(|file| file.modified)
+        .filter(|timestamp| timestamp.is_set() && timestamp.is_valid())
+        .map(|timestamp| timestamp.year())
+        .collect();
+    years.sort_unstable();
+    // The year by which nearly all files had been written.
+    let typical = years.get(years.len().saturating_sub(1) * 9 / 10).copied();
+
+    for file in files {
+        let mut flag = |anomaly: String| anomalies.push((file.path.clone(), anomaly));
+        let timestamps = [("modified", file.modified), ("created", file.created), ("accessed", file.accessed)];
+        for (label, timestamp) in timestamps {
+            if !timestamp.is_set() {
+                continue;
+            }
+            if !timestamp.is_valid() {
+                flag(format!("invalid {} timestamp {:04X}:{:04X}", label, timestamp.date, timestamp.time));
+            }
+            else if timestamp.year() > current_year {
+                flag(format!("{} in the future, {}", label, timestamp));
+            }
+        }
+        let (modified, created) = (file.modified, file.created);
+        if created.is_set() && created.is_valid() && modified.is_valid() && created > modified {
+            flag(format!("created {} after it was last modified", created));
+        }
+        if let Some(typical) = typical {
+            let year = modified.year();
+            if modified.is_valid() && year > typical + OUTLIER_YEARS && year <= current_year {
+                flag(format!("modified {}, years after the other files", modified));
+            }
+        }
+    }
+    anomalies
+}
@@ -0,0 +1,180 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A squarified treemap of what occupies a FAT volume: each file is a rectangle with an area
+//! proportional to its size, nested inside its directory's rectangle. Files are coloured by
+//! extension, so the same kind of file is the same colour wherever it is.
+
+use egui::{Color32, Rect, Sense, Stroke};
+
+use crate::fat::{flatten, FileNode};
+
+pub const TREEMAP_HEIGHT: f32 = 240.0;
+/// The inset of a directory's contents within its rectangle, so directories stay visible.
+const DIR_PADDING: f32 = 2.0;
+/// Rectangles smaller than this in either dimension aren't subdivided further.
+const MIN_CELL: f32 = 3.0;
+
+/// A laid out rectangle of the treemap.
+pub struct Cell {
+    pub path: String,
+    pub rect: Rect,
+    pub size: u64,
+    pub is_dir: bool,
+    pub color: Color32,
+}
+
+/// The size of a node: a file's size, or the total of a directory's contents.
+fn node_size(node: &FileNode) -> u64 {
+    match node.entry.is_dir() {
+        true => node.children.iter().map(node_size).sum(),
+        false => node.entry.size as u64,
+    }
+}
+
+/// A colour for a file's extension, picked by hashing it so it's stable between volumes.
+fn extension_color(name: &str) -> Color32 {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    let hash = extension.bytes().fold(2166136261u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(16777619));
+    egui::ecolor::Hsva::new((hash % 360) as f32 / 360.0, 0.55, 0.75, 1.0).into()
+}
+
+/// The worst aspect ratio of a row of areas laid along a side of length `side`.
+fn worst_ratio(row: &[f32], side: f32) -> f32 {
+    let sum: f32 = row.iter().sum();
+    let max = row.iter().cloned().fold(f32::MIN, f32::max);
+    let min = row.iter().cloned().fold(f32::MAX, f32::min);
+    let side2 = side * side;
+    let sum2 = sum * sum;
+    (side2 * max / sum2).max(sum2 / (side2 * min))
+}
+
+/// Lay out `nodes` in `rect`, recursing into directories.
+fn layout(nodes: &[FileNode], rect: Rect, cells: &mut Vec<Cell>) {
+    let mut items: Vec<(&FileNode, u64)> =
+        nodes.iter().map(|node| (node, node_size(node))).filter(|(_, size)| *size > 0).collect();
+    items.sort_by(|a, b| b.1.cmp(&a.1));
+    let total: u64 = items.iter().map(|(_, size)| size).sum();
+    if total == 0 || rect.width() < MIN_CELL || rect.height() < MIN_CELL {
+        return;
+    }
+    let scale = rect.area() / total as f32;
+    let areas: Vec<f32> = items.iter().map(|(_, size)| *size as f32 * scale).collect();
+
+    let mut remaining = rect;
+    let mut start = 0;
+    while start < items.len() {
+        let side = remaining.width().min(remaining.height());
+        let mut end = start + 1;
+        while end < items.len() && worst_ratio(&areas[start..=end], side) <= worst_ratio(&areas[start..end], side) {
+            end += 1;
+        }
+
+        // Lay the row along the shorter side of the remaining space.
+        let row_area: f32 = areas[start..end].iter().sum();
+        let horizontal = remaining.width() >= remaining.height();
+        let thickness = row_area / side;
+        let mut offset = 0.0;
+        for ((node, size), area) in items[start..end].iter().zip(&areas[start..end]) {
+            let length = area / thickness;
+            let cell = match horizontal {
+                true => Rect::from_min_size(remaining.min + egui::vec2(0.0, offset), egui::vec2(thickness, length)),
+                false => Rect::from_min_size(remaining.min + egui::vec2(offset, 0.0), egui::vec2(length, thickness)),
+            };
+            offset += length;
+            let is_dir = node.entry.is_dir();
+            cells.push(Cell {
+                path: node.path.clone(),
+                rect: cell,
+                size: *size,
+                is_dir,
+                color: if is_dir { Color32::from_gray(40) } else { extension_color(&node.entry.name) },
+            });
+            if is_dir {
+                layout(&node.children, cell.shrink(DIR_PADDING), cells);
+            }
+        }
+        match horizontal {
+            true => remaining.min.x += thickness,
+            false => remaining.min.y += thickness,
+        }
+        start = end;
+    }
+}
+
+#[derive(Default)]
+pub struct Treemap {
+    /// The layout and the rectangle it was made for, redone when the panel is resized.
+    cells: Vec<Cell>,
+    laid_out: Option<Rect>,
+}
+
+impl Treemap {
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.laid_out = None;
+    }
+
+    /// Show the treemap. Returns the path of a file or directory if the user clicked one.
+    pub fn show(&mut self, ui: &mut egui::Ui, tree: &[FileNode], selected: Option<&str>) -> Option<String> {
+        let mut clicked = None;
+        egui::CollapsingHeader::new("Disk usage").id_salt("fat_treemap").show(ui, |ui| {
+            let files = flatten(tree).into_iter().filter(|node| !node.entry.is_dir()).count();
+            let total: u64 = tree.iter().map(node_size).sum();
+            ui.label(format!("{} bytes in {} files", total, files));
+
+            let size = egui::vec2(ui.available_width(), TREEMAP_HEIGHT);
+            let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+            if self.laid_out != Some(rect) {
+                self.cells.clear();
+                layout(tree, rect, &mut self.cells);
+                self.laid_out = Some(rect);
+            }
+
+            let painter = ui.painter_at(rect);
+            for cell in &self.cells {
+                painter.rect_filled(cell.rect, egui::Rounding::ZERO, cell.color);
+                painter.rect_stroke(cell.rect, egui::Rounding::ZERO, Stroke::new(1.0, Color32::from_gray(20)));
+                if selected == Some(cell.path.as_str()) {
+                    painter.rect_stroke(cell.rect, egui::Rounding::ZERO, Stroke::new(2.0, Color32::WHITE));
+                }
+            }
+
+            // The innermost cell under the pointer, which comes last in the layout.
+            let hovered =
+                response.hover_pos().and_then(|pos| self.cells.iter().rev().find(|cell| cell.rect.contains(pos)));
+            if let Some(cell) = hovered {
+                if response.clicked() {
+                    clicked = Some(cell.path.clone());
+                }
+                let kind = if cell.is_dir { "directory" } else { "file" };
+                response.on_hover_text(format!("{}\n{} bytes ({})", cell.path, cell.size, kind));
+            }
+        });
+        clicked
+    }
+}
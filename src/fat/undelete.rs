@@ -0,0 +1,152 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Finding and recovering deleted files. Deleting a file marks its directory entry with 0xE5
+//! and frees its clusters in the FAT, so the chain is gone; like UNDELETE, recovery assumes the
+//! file was stored contiguously from its first cluster, which holds for most floppy files.
+//!
+//! Once any of those clusters has been allocated again, what's there belongs to another file,
+//! so the entry is stale: it's counted but not listed, rather than offered for recovery.
+
+use crate::fat::{flatten, DirEntry, FatVolume};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Recovery {
+    /// All of the file's clusters are still free, so its data is probably intact.
+    Intact,
+    /// Some of the file's clusters have since been allocated to other files.
+    Overwritten { clusters: usize },
+    /// The entry has no data to recover.
+    Empty,
+    /// The entry's first cluster is not a valid cluster number.
+    InvalidStart,
+}
+
+impl Recovery {
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Recovery::Intact)
+    }
+}
+
+impl std::fmt::Display for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recovery::Intact => write!(f, "intact"),
+            Recovery::Overwritten { clusters } => write!(f, "{} clusters overwritten", clusters),
+            Recovery::Empty => write!(f, "empty"),
+            Recovery::InvalidStart => write!(f, "invalid start cluster"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeletedFile {
+    pub path: String,
+    pub entry: DirEntry,
+    pub recovery: Recovery,
+}
+
+impl DeletedFile {
+    /// The clusters the file most likely occupied.
+    pub fn clusters(&self, volume: &FatVolume) -> Vec<u32> {
+        if !volume.is_valid_cluster(self.entry.cluster) {
+            return Vec::new();
+        }
+        let count = (self.entry.size as usize).div_ceil(volume.cluster_size()) as u32;
+        (self.entry.cluster..self.entry.cluster + count)
+            .take_while(|cluster| volume.is_valid_cluster(*cluster))
+            .collect()
+    }
+
+    pub fn recover(&self, volume: &FatVolume) -> Vec<u8> {
+        let lbas = self.clusters(volume).into_iter().flat_map(|cluster| volume.cluster_lbas(cluster));
+        let mut data = volume.volume.read(lbas);
+        data.truncate(self.entry.size as usize);
+        data
+    }
+
+    /// A filename for the recovered file. The first character of a deleted name is lost, so
+    /// it is replaced with an underscore.
+    pub fn recovered_name(&self) -> String {
+        let name = self.entry.name.strip_prefix('?').unwrap_or(&self.entry.name);
+        format!("_{}", name)
+    }
+}
+
+fn assess(volume: &FatVolume, file: &DeletedFile) -> Recovery {
+    if file.entry.size == 0 {
+        return Recovery::Empty;
+    }
+    if !volume.is_valid_cluster(file.entry.cluster) {
+        return Recovery::InvalidStart;
+    }
+    match file.clusters(volume).iter().filter(|cluster| volume.fat_entry(**cluster) != 0).count() {
+        0 => Recovery::Intact,
+        clusters => Recovery::Overwritten { clusters },
+    }
+}
+
+/// The deleted files found on a volume.
+#[derive(Clone, Debug, Default)]
+pub struct DeletedFiles {
+    pub files: Vec<DeletedFile>,
+    /// Deleted files left out because their clusters have since been allocated again.
+    pub overwritten: usize,
+}
+
+/// List the deleted files in the root directory and every live subdirectory, leaving out those
+/// that have been overwritten.
+pub fn find_deleted(volume: &FatVolume) -> DeletedFiles {
+    let tree = volume.tree();
+    let mut dirs: Vec<(String, Option<&DirEntry>)> = vec![(String::new(), None)];
+    dirs.extend(
+        flatten(&tree)
+            .into_iter()
+            .filter(|node| node.entry.is_dir())
+            .map(|node| (node.path.clone(), Some(&node.entry))),
+    );
+
+    let mut deleted = DeletedFiles::default();
+    for (parent, dir) in dirs {
+        for entry in volume.read_dir(dir) {
+            if !entry.deleted || entry.is_volume_label() || entry.is_dir() {
+                continue;
+            }
+            let mut file = DeletedFile {
+                path: format!("{}/{}", parent, entry.name),
+                entry,
+                recovery: Recovery::Empty,
+            };
+            file.recovery = assess(volume, &file);
+            match file.recovery {
+                Recovery::Overwritten { .. } => deleted.overwritten += 1,
+                _ => deleted.files.push(file),
+            }
+        }
+    }
+    deleted
+}
@@ -0,0 +1,156 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A per-frame time budget, for keeping the UI responsive while expensive work is done.
+//!
+//! Work that would stall a frame is split into steps with [`Incremental`], and as many steps
+//! as fit in the budget are run each frame. The app keeps requesting repaints until the work
+//! completes, so it progresses even while the user isn't interacting.
+
+/// Time per frame that may be spent on incremental work, leaving the rest of a 60fps frame
+/// for the UI itself.
+pub const DEFAULT_FRAME_BUDGET_MS: f64 = 8.0;
+
+/// The budget as chosen by the user, persisted between sessions.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PerformanceSettings {
+    pub frame_budget_ms: f64,
+}
+
+impl Default for PerformanceSettings {
+    fn default() -> Self {
+        Self {
+            frame_budget_ms: DEFAULT_FRAME_BUDGET_MS,
+        }
+    }
+}
+
+impl PerformanceSettings {
+    /// Show the settings editor. Returns true if any setting was changed.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.horizontal(|ui| {
+            ui.label("Frame budget:");
+            ui.add(egui::Slider::new(&mut self.frame_budget_ms, 2.0..=30.0).suffix(" ms"))
+                .on_hover_text(
+                    "Time per frame spent on long computations such as analysis. Lower keeps the UI \
+                     smoother on slow machines; higher finishes sooner.",
+                )
+                .changed()
+        })
+        .inner
+    }
+}
+
+fn now_ms() -> f64 {
+    match web_sys::window().and_then(|window| window.performance()) {
+        Some(performance) => performance.now(),
+        None => web_sys::js_sys::Date::now(),
+    }
+}
+
+pub struct FrameBudget {
+    start: f64,
+    budget_ms: f64,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        Self {
+            start: now_ms(),
+            budget_ms: DEFAULT_FRAME_BUDGET_MS,
+        }
+    }
+}
+
+impl FrameBudget {
+    /// Mark the start of a frame. Call once at the top of each update.
+    pub fn begin_frame(&mut self) {
+        self.start = now_ms();
+    }
+
+    pub fn set_budget_ms(&mut self, budget_ms: f64) {
+        self.budget_ms = budget_ms;
+    }
+
+    pub fn elapsed_ms(&self) -> f64 {
+        now_ms() - self.start
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.elapsed_ms() >= self.budget_ms
+    }
+}
+
+/// A computation split into steps, each producing one result.
+pub struct Incremental<T> {
+    steps: Box<dyn Iterator<Item = T>>,
+    done: Vec<T>,
+    total: usize,
+    finished: bool,
+}
+
+impl<T> Incremental<T> {
+    /// Create a computation from `steps`, an iterator doing the work of one step in each call to
+    /// `next()`. `total` is the expected number of steps, used to report progress.
+    pub fn new(steps: impl Iterator<Item = T> + 'static, total: usize) -> Self {
+        Self {
+            steps: Box::new(steps),
+            done: Vec::with_capacity(total),
+            total,
+            finished: false,
+        }
+    }
+
+    /// Run steps until the budget for this frame is exhausted, always running at least one so
+    /// the computation can't stall. Returns true once all steps have completed.
+    pub fn run(&mut self, budget: &FrameBudget) -> bool {
+        while !self.finished {
+            match self.steps.next() {
+                Some(result) => self.done.push(result),
+                None => self.finished = true,
+            }
+            if budget.exhausted() {
+                break;
+            }
+        }
+        self.finished
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.finished {
+            1.0
+        }
+        else {
+            self.done.len() as f32 / self.total.max(1) as f32
+        }
+    }
+
+    pub fn into_results(self) -> Vec<T> {
+        self.done
+    }
+}
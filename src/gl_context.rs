@@ -0,0 +1,209 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Handling for loss of the WebGL context backing the egui canvas.
+//!
+//! Browsers may drop the graphics context when a tab is backgrounded or the GPU is reset. By
+//! default the context is then gone for good and the canvas stays blank until the page is
+//! reloaded. We cancel the default handling so the browser may restore the context, and show a
+//! DOM notice while it is unavailable (we can't draw one with egui).
+//!
+//! Everything on the GPU went with the old context, including the shaders and buffers of
+//! eframe's glow painter, which can't be rebuilt from inside the app. So once the context comes
+//! back we shut down the eframe runner and start a new one on the same canvas, handing it the
+//! same [App]. The new runner builds a new painter, and the app uploads its textures again to
+//! the new egui context.
+
+#[cfg(feature = "gui")]
+use std::cell::RefCell;
+#[cfg(feature = "gui")]
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Event, HtmlCanvasElement, HtmlElement};
+
+#[cfg(feature = "gui")]
+use crate::App;
+
+/// The id of the canvas eframe renders to, as set in index.html.
+pub const CANVAS_ID: &str = "the_canvas_id";
+const NOTICE_ID: &str = "ffweb_context_lost";
+const NOTICE_STYLE: &str = "position: fixed; top: 16px; left: 50%; transform: translateX(-50%); z-index: 1000; \
+                            padding: 8px 16px; background: #402000; color: #ffd080; border: 1px solid #ffd080; \
+                            font-family: sans-serif;";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ContextState {
+    Ok,
+    Lost,
+}
+
+#[derive(Clone)]
+pub struct ContextWatcher {
+    lost: Arc<AtomicBool>,
+}
+
+impl ContextWatcher {
+    /// Install context loss handlers on `canvas`. `on_restored` is called when the browser gives
+    /// the context back.
+    pub fn install(canvas: &HtmlCanvasElement, mut on_restored: impl FnMut() + 'static) -> Result<Self, JsValue> {
+        let lost = Arc::new(AtomicBool::new(false));
+
+        let lost_state = lost.clone();
+        let on_lost = Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+            log::warn!("WebGL context lost, waiting for the browser to restore it");
+            // Without this the browser will never try to restore the context.
+            event.prevent_default();
+            lost_state.store(true, Ordering::Release);
+            if let Err(e) = show_notice() {
+                log::error!("ContextWatcher: Failed to show context loss notice: {:?}", e);
+            }
+        });
+        canvas.add_event_listener_with_callback("webglcontextlost", on_lost.as_ref().unchecked_ref())?;
+
+        let restored_state = lost.clone();
+        let on_restored = Closure::<dyn FnMut(Event)>::new(move |_event: Event| {
+            log::info!("WebGL context restored");
+            restored_state.store(false, Ordering::Release);
+            hide_notice();
+            on_restored();
+        });
+        canvas.add_event_listener_with_callback("webglcontextrestored", on_restored.as_ref().unchecked_ref())?;
+
+        // The handlers live as long as the canvas, which is the lifetime of the app.
+        on_lost.forget();
+        on_restored.forget();
+
+        Ok(Self { lost })
+    }
+
+    pub fn poll(&self) -> ContextState {
+        match self.lost.load(Ordering::Acquire) {
+            true => ContextState::Lost,
+            false => ContextState::Ok,
+        }
+    }
+}
+
+/// The app, shared by each eframe runner started on the canvas in turn.
+#[cfg(feature = "gui")]
+struct SharedApp(Rc<RefCell<App>>);
+
+#[cfg(feature = "gui")]
+impl eframe::App for SharedApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        eframe::App::update(&mut *self.0.borrow_mut(), ctx, frame);
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::App::save(&mut *self.0.borrow_mut(), storage);
+    }
+
+    fn auto_save_interval(&self) -> std::time::Duration {
+        eframe::App::auto_save_interval(&*self.0.borrow())
+    }
+}
+
+/// Start the app on `canvas`, and start it again on a new eframe runner each time the graphics
+/// context is restored.
+#[cfg(feature = "gui")]
+pub async fn start(canvas: HtmlCanvasElement) -> Result<(), JsValue> {
+    let runner = Rc::new(RefCell::new(eframe::WebRunner::new()));
+    let shared: Rc<RefCell<Option<Rc<RefCell<App>>>>> = Rc::default();
+
+    let restart = {
+        let (canvas, runner, shared) = (canvas.clone(), runner.clone(), shared.clone());
+        move || {
+            // The context can only be lost once the first runner has created the app.
+            let Some(app) = shared.borrow().clone()
+            else {
+                return;
+            };
+            runner.borrow().destroy();
+            let next = eframe::WebRunner::new();
+            *runner.borrow_mut() = next.clone();
+            let canvas = canvas.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let creator = move |cc: &eframe::CreationContext<'_>| {
+                    app.borrow_mut().resume(cc);
+                    Ok(Box::new(SharedApp(app)) as Box<dyn eframe::App>)
+                };
+                if let Err(e) = next.start(canvas, eframe::WebOptions::default(), Box::new(creator)).await {
+                    log::error!("Failed to restart eframe after the graphics context was restored: {:?}", e);
+                }
+            });
+        }
+    };
+    let watcher = match ContextWatcher::install(&canvas, restart) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            log::warn!("Failed to install graphics context loss handlers: {:?}", e);
+            None
+        }
+    };
+
+    let first = runner.borrow().clone();
+    let creator = move |cc: &eframe::CreationContext<'_>| {
+        let mut app = App::new(cc);
+        app.set_context_watcher(watcher);
+        let app = Rc::new(RefCell::new(app));
+        *shared.borrow_mut() = Some(app.clone());
+        Ok(Box::new(SharedApp(app)) as Box<dyn eframe::App>)
+    };
+    first.start(canvas, eframe::WebOptions::default(), Box::new(creator)).await
+}
+
+fn show_notice() -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| JsValue::from_str("No document"))?;
+    if document.get_element_by_id(NOTICE_ID).is_some() {
+        return Ok(());
+    }
+
+    let notice: HtmlElement = document.create_element("div")?.unchecked_into();
+    notice.set_id(NOTICE_ID);
+    notice.style().set_css_text(NOTICE_STYLE);
+    notice.set_inner_text("The graphics context was lost. Waiting for the browser to restore it...");
+    document
+        .body()
+        .ok_or_else(|| JsValue::from_str("No document body"))?
+        .append_child(&notice)?;
+    Ok(())
+}
+
+fn hide_notice() {
+    if let Some(notice) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id(NOTICE_ID))
+    {
+        notice.remove();
+    }
+}
@@ -0,0 +1,119 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Hard-sectored media, where each sector starts at a hole punched in the disk instead of being
+//! found by its address mark, and an extra index hole halfway between two of them marks the
+//! start of the track. The drive pulses its index line at every hole, and SCP images record
+//! one revolution entry per pulse, so the holes are recovered from those entries' durations:
+//! the index hole splits one sector's interval into two short halves.
+//!
+//! Hole positions are given from the index hole, which assumes the decoded track starts there,
+//! as it does when the capture was index aligned.
+
+use crate::scp::ScpInfo;
+
+/// Intervals shorter than this fraction of the median are the halves split by the index hole.
+const INDEX_SPLIT_RATIO: f64 = 0.75;
+/// Captures with hole-to-hole intervals longer than this are soft sectored. A revolution takes
+/// 200ms at 300 RPM, and hard-sectored disks have at least eight holes.
+const MAX_HOLE_INTERVAL_NS: f64 = 50.0e6;
+
+#[derive(Clone, Debug)]
+pub struct HardSectoring {
+    /// The number of sector holes, which is the number of sectors per rotation.
+    pub sectors: usize,
+    pub rotation_ns: f64,
+    /// The position of each sector hole, as a fraction of a rotation past the index hole.
+    pub holes: Vec<f32>,
+}
+
+impl HardSectoring {
+    /// Recover the hole layout from the durations between index pulses on one track, or None if
+    /// they don't look hard sectored.
+    pub fn from_intervals(durations: &[f64]) -> Option<Self> {
+        if durations.len() < 3 {
+            return None;
+        }
+        let mut sorted = durations.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = sorted[sorted.len() / 2];
+        if median > MAX_HOLE_INTERVAL_NS {
+            return None;
+        }
+
+        // The index hole comes between two consecutive short intervals.
+        let short = |i: usize| durations[i] < median * INDEX_SPLIT_RATIO;
+        let splits: Vec<usize> = (0..durations.len() - 1).filter(|i| short(*i) && short(i + 1)).collect();
+        let (first, second) = (*splits.first()?, *splits.get(1)?);
+
+        // One rotation runs from the index hole after interval `first` to the one after `second`,
+        // and its two half intervals make up a single sector.
+        let rotation = &durations[first + 1..=second];
+        let sectors = rotation.len() - 1;
+        if sectors < 2 {
+            return None;
+        }
+        let rotation_ns: f64 = rotation.iter().sum();
+        let holes = rotation[..sectors]
+            .iter()
+            .scan(0.0, |elapsed, duration| {
+                *elapsed += duration;
+                Some((*elapsed / rotation_ns) as f32)
+            })
+            .collect();
+        Some(Self {
+            sectors,
+            rotation_ns,
+            holes,
+        })
+    }
+
+    /// Find the hole layout from the first track of an SCP image that shows one.
+    pub fn from_scp(scp: &ScpInfo) -> Option<Self> {
+        scp.tracks.iter().find_map(|track| {
+            let durations: Vec<f64> = track.revolutions.iter().map(|rev| rev.duration_ns).collect();
+            Self::from_intervals(&durations)
+        })
+    }
+
+    pub fn rpm(&self) -> f64 {
+        60.0e9 / self.rotation_ns.max(1.0)
+    }
+
+    /// The hard sector, numbered from 1, at the point `fraction` of a rotation past the index
+    /// hole. The index hole falls in the middle of the last sector.
+    pub fn sector_at(&self, fraction: f32) -> usize {
+        match self.holes.iter().rposition(|hole| *hole <= fraction) {
+            Some(i) => i + 1,
+            None => self.sectors,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!("Hard sectored: {} sectors per rotation at {:.0} RPM", self.sectors, self.rpm())
+    }
+}
@@ -0,0 +1,264 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! High resolution renders of the visualization, for saving as images.
+//!
+//! Rendering at 2048 or 4096 pixels takes long enough to notice, so the render runs as a
+//! background task and sends each quadrant back as it completes. Quadrants are uploaded into
+//! the preview texture as they arrive, so the image fills in instead of appearing all at once.
+//! The session dashboard's thumbnails are rendered the same way, off the UI thread.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use wasm_bindgen::JsValue;
+use egui::{ColorImage, TextureHandle, TextureOptions};
+use fluxfox::tiny_skia::{self, Pixmap};
+use fluxfox::visualization::{render_track_metadata_quadrant, RenderTrackMetadataParams};
+use fluxfox::DiskImage;
+
+use crate::session;
+use crate::storage;
+use crate::tasks::{TaskHandle, TaskMessage, TaskOutput};
+use crate::viz::{encode_png, quadrant_origin};
+use crate::worker;
+
+pub const HIRES_RESOLUTIONS: [u32; 3] = [1024, 2048, 4096];
+pub const HIRES_DEFAULT_RESOLUTION: u32 = 2048;
+pub const HIRES_PREVIEW_SIZE: f32 = 384.0;
+
+/// A render the user asked for.
+#[derive(Copy, Clone, Debug)]
+pub struct HiresRequest {
+    pub resolution: u32,
+    pub side: usize,
+}
+
+pub fn color_image(pixmap: &Pixmap) -> ColorImage {
+    ColorImage::from_rgba_premultiplied([pixmap.width() as usize, pixmap.height() as usize], pixmap.data())
+}
+
+/// Render the metadata at `resolution` pixels square, calling `on_quadrant` with each quadrant
+/// and its position as it completes.
+pub fn render_quadrants(
+    disk: &mut DiskImage,
+    mut params: RenderTrackMetadataParams,
+    resolution: u32,
+    mut on_quadrant: impl FnMut(usize, (u32, u32), &Pixmap),
+) -> Result<Pixmap, Error> {
+    let mut composite = Pixmap::new(resolution, resolution).ok_or_else(|| anyhow!("Invalid resolution"))?;
+    let mut quadrant_pixmap = Pixmap::new(resolution / 2, resolution / 2).ok_or_else(|| anyhow!("Invalid resolution"))?;
+
+    for quadrant in 0..4 {
+        params.quadrant = quadrant as u8;
+        quadrant_pixmap.fill(tiny_skia::Color::TRANSPARENT);
+        render_track_metadata_quadrant(disk, &mut quadrant_pixmap, &params)
+            .map_err(|e| anyhow!("Error rendering quadrant {}: {}", quadrant, e))?;
+
+        let (x, y) = quadrant_origin(quadrant, resolution);
+        on_quadrant(quadrant, (x, y), &quadrant_pixmap);
+
+        composite.draw_pixmap(
+            x as i32,
+            y as i32,
+            quadrant_pixmap.as_ref(),
+            &tiny_skia::PixmapPaint::default(),
+            tiny_skia::Transform::identity(),
+            None,
+        );
+    }
+    Ok(composite)
+}
+
+/// Render in a worker, which takes the disk image out of `disk` and hands it back with the
+/// result.
+pub fn spawn_render(
+    disk: Arc<Mutex<Option<DiskImage>>>,
+    params: RenderTrackMetadataParams,
+    request: HiresRequest,
+    handle: TaskHandle,
+) -> Result<web_sys::Worker, JsValue> {
+    worker::spawn_closure_worker(move || {
+        let Some(mut disk) = disk.lock().unwrap().take()
+        else {
+            log::error!("spawn_render(): No disk image to render");
+            return;
+        };
+
+        log::debug!("Rendering side {} at {}px...", request.side, request.resolution);
+        let png = render_quadrants(&mut disk, params, request.resolution, |quadrant, (x, y), pixmap| {
+            handle.send(TaskMessage::Tile {
+                origin: [x as usize, y as usize],
+                image: color_image(pixmap),
+            });
+            handle.progress((quadrant + 1) as f64 / 4.0);
+        })
+        .and_then(|composite| encode_png(&composite));
+        handle.finish(TaskOutput::Rendered { disk, request, png });
+    })
+}
+
+/// Render the session thumbnail of the image with SHA-1 `sha1` in a worker, handing the disk
+/// back with it.
+pub fn spawn_thumbnail(
+    disk: Arc<Mutex<Option<DiskImage>>>,
+    params: RenderTrackMetadataParams,
+    sha1: String,
+    handle: TaskHandle,
+) -> Result<web_sys::Worker, JsValue> {
+    worker::spawn_closure_worker(move || {
+        let Some(mut disk) = disk.lock().unwrap().take()
+        else {
+            log::error!("spawn_thumbnail(): No disk image to render");
+            return;
+        };
+        let image = render_quadrants(&mut disk, params, session::THUMBNAIL_SIZE, |quadrant, _, _| {
+            handle.progress((quadrant + 1) as f64 / 4.0);
+        })
+        .map(|pixmap| color_image(&pixmap));
+        handle.finish(TaskOutput::Thumbnail { disk, sha1, image });
+    })
+}
+
+/// The high resolution render window, with a preview that fills in as the render progresses.
+pub struct HiresRender {
+    pub open: bool,
+    resolution: u32,
+    side: usize,
+    rendering: bool,
+    texture: Option<TextureHandle>,
+    png: Option<Vec<u8>>,
+    filename: String,
+}
+
+impl Default for HiresRender {
+    fn default() -> Self {
+        Self {
+            open: false,
+            resolution: HIRES_DEFAULT_RESOLUTION,
+            side: 0,
+            rendering: false,
+            texture: None,
+            png: None,
+            filename: String::new(),
+        }
+    }
+}
+
+impl HiresRender {
+    pub fn clear(&mut self) {
+        self.texture = None;
+        self.png = None;
+    }
+
+    /// Drop the preview texture, which belonged to a graphics context that was lost. Any
+    /// tiles of a render in progress that were already applied aren't shown again.
+    pub fn forget_textures(&mut self) {
+        self.texture = None;
+    }
+
+    /// Prepare an empty preview for a render that is starting.
+    pub fn begin(&mut self, ctx: &egui::Context, request: HiresRequest, filename: String) {
+        let size = request.resolution as usize;
+        let blank = ColorImage::new([size, size], egui::Color32::TRANSPARENT);
+        self.texture = Some(ctx.load_texture("hires_render", blank, TextureOptions::LINEAR));
+        self.png = None;
+        self.rendering = true;
+        self.filename = filename;
+    }
+
+    /// Upload a completed part of the render to the preview.
+    pub fn apply_tile(&mut self, origin: [usize; 2], image: ColorImage) {
+        if let Some(texture) = &mut self.texture {
+            texture.set_partial(origin, image, TextureOptions::LINEAR);
+        }
+    }
+
+    /// Give up on a render that won't finish.
+    pub fn cancel(&mut self) {
+        self.rendering = false;
+    }
+
+    pub fn finish(&mut self, png: Result<Vec<u8>, Error>) -> Result<(), Error> {
+        self.rendering = false;
+        self.png = Some(png?);
+        Ok(())
+    }
+
+    /// Show the window. Returns a render to start if the user asked for one.
+    pub fn show(&mut self, ctx: &egui::Context, heads: usize) -> Option<HiresRequest> {
+        let mut request = None;
+        let mut open = self.open;
+        egui::Window::new("High resolution render")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(!self.rendering, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Size:");
+                        for resolution in HIRES_RESOLUTIONS {
+                            ui.radio_value(&mut self.resolution, resolution, format!("{}px", resolution));
+                        }
+                    });
+                    if heads > 1 {
+                        ui.horizontal(|ui| {
+                            ui.label("Side:");
+                            for side in 0..heads {
+                                ui.radio_value(&mut self.side, side, side.to_string());
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(heads > 0, egui::Button::new("Render")).clicked() {
+                            request = Some(HiresRequest {
+                                resolution: self.resolution,
+                                side: self.side.min(heads.saturating_sub(1)),
+                            });
+                        }
+                        if let Some(png) = &self.png {
+                            if ui.button("Save PNG").clicked() {
+                                if let Err(e) = storage::download_bytes(png, &self.filename) {
+                                    log::error!("Error downloading {}: {:?}", self.filename, e);
+                                }
+                            }
+                        }
+                    });
+                });
+                if self.rendering {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Rendering...");
+                    });
+                }
+                if let Some(texture) = &self.texture {
+                    ui.add(egui::Image::new(texture).fit_to_exact_size(egui::Vec2::splat(HIRES_PREVIEW_SIZE)));
+                }
+            });
+        self.open = open;
+        request
+    }
+}
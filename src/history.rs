@@ -0,0 +1,76 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A per-image processing history, so that exports and reports carry a record of where an
+//! image came from and what was done to it.
+
+/// A single step in an image's history.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct HistoryEntry {
+    /// ISO 8601 timestamp, from the browser clock.
+    pub time: String,
+    pub event: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn record(&mut self, event: impl Into<String>) {
+        let entry = HistoryEntry {
+            time: String::from(web_sys::js_sys::Date::new_0().to_iso_string()),
+            event: event.into(),
+        };
+        log::debug!("History: {} {}", entry.time, entry.event);
+        self.entries.push(entry);
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(format!("History ({})", self.entries.len()))
+            .id_salt("image_history")
+            .show(ui, |ui| {
+                egui::Grid::new("image_history_grid").striped(true).num_columns(2).show(ui, |ui| {
+                    for entry in self.entries.iter() {
+                        ui.monospace(&entry.time);
+                        ui.label(&entry.event);
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+}
@@ -0,0 +1,267 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A journal of unsaved sector edits, kept in browser storage so a crashed tab doesn't lose them.
+//!
+//! Each edit to the loaded image is recorded as a step, mirroring the undo stack. When the app
+//! saves its state, the sectors the steps wrote are coalesced into their latest contents and
+//! stored under the SHA-1 of the file the image was loaded from. If that file is loaded again
+//! while a journal for it is stored, the user is offered the edits back.
+//!
+//! Only sector writes can be replayed. An edit that replaces the image, such as a transform,
+//! ends what can be recovered: steps after it aren't saved. Exporting the image counts as saving
+//! the edits, and clears its journal.
+
+use std::collections::BTreeMap;
+
+use base64::Engine;
+
+use crate::analysis::SectorKey;
+
+pub const JOURNAL_KEY: &str = "ffweb_journal";
+/// Journals are kept for at most this many images, dropping the oldest first.
+const MAX_JOURNALS: usize = 4;
+
+/// The edits stored for one image.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SavedEdits {
+    pub image_name: String,
+    pub image_len: usize,
+    /// When the edits were last saved, in milliseconds since the epoch.
+    pub saved_at: f64,
+    /// What each edit was, oldest first.
+    pub descriptions: Vec<String>,
+    /// The latest contents of each sector written, base64 encoded.
+    pub sectors: Vec<(SectorKey, String)>,
+}
+
+impl SavedEdits {
+    /// Decode the sectors to write, in order. Sectors that don't decode are skipped.
+    pub fn decode(&self) -> Vec<(SectorKey, Vec<u8>)> {
+        self.sectors
+            .iter()
+            .filter_map(|(key, data)| {
+                let data = base64::engine::general_purpose::STANDARD.decode(data).ok()?;
+                Some((*key, data))
+            })
+            .collect()
+    }
+}
+
+enum Step {
+    Sectors {
+        description: String,
+        sectors: Vec<(SectorKey, Vec<u8>)>,
+    },
+    /// An edit that can't be replayed from sector writes.
+    Other,
+}
+
+/// The image the journal's edits apply to: the file it was loaded from.
+struct Base {
+    name: String,
+    sha1: String,
+    len: usize,
+}
+
+#[derive(Default)]
+pub struct EditJournal {
+    base: Option<Base>,
+    steps: Vec<Step>,
+    /// Journals in storage, by image SHA-1.
+    saved: BTreeMap<String, SavedEdits>,
+    /// Set when the steps changed since the last save.
+    dirty: bool,
+    /// A stored journal for the loaded image, offered until the user decides what to do with it.
+    offered: Option<SavedEdits>,
+}
+
+impl EditJournal {
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        Self {
+            saved: eframe::get_value(storage, JOURNAL_KEY).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Start journaling for a newly loaded file. Offers its stored edits, if there are any.
+    pub fn begin(&mut self, name: &str, sha1: &str, len: usize) {
+        let sha1 = sha1.to_ascii_lowercase();
+        self.steps.clear();
+        self.offered = self.saved.get(&sha1).cloned();
+        self.base = Some(Base {
+            name: name.to_string(),
+            sha1,
+            len,
+        });
+        self.dirty = false;
+    }
+
+    pub fn record_sectors(&mut self, description: &str, sectors: &[(SectorKey, Vec<u8>)]) {
+        self.steps.push(Step::Sectors {
+            description: description.to_string(),
+            sectors: sectors.to_vec(),
+        });
+        self.edited();
+    }
+
+    pub fn record_other(&mut self) {
+        self.steps.push(Step::Other);
+        self.edited();
+    }
+
+    /// Drop the last edit, which was undone.
+    pub fn undo(&mut self) {
+        self.steps.pop();
+        self.dirty = true;
+    }
+
+    /// The edits were saved to a file, so there's nothing left to recover.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.dirty = true;
+    }
+
+    fn edited(&mut self) {
+        self.dirty = true;
+        // Once the image has been edited, the stored edits would be applied on top of the new
+        // ones, so it's too late to offer them.
+        self.offered = None;
+    }
+
+    /// Write the journal to storage, if it changed.
+    pub fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        if !std::mem::take(&mut self.dirty) {
+            return;
+        }
+        let Some(base) = &self.base
+        else {
+            return;
+        };
+        match self.coalesce() {
+            Some(edits) => {
+                self.saved.insert(base.sha1.clone(), edits);
+            }
+            None => {
+                self.saved.remove(&base.sha1);
+            }
+        }
+        while self.saved.len() > MAX_JOURNALS {
+            let oldest = self
+                .saved
+                .iter()
+                .min_by(|(_, a), (_, b)| a.saved_at.total_cmp(&b.saved_at))
+                .map(|(sha1, _)| sha1.clone());
+            if let Some(oldest) = oldest {
+                self.saved.remove(&oldest);
+            }
+        }
+        eframe::set_value(storage, JOURNAL_KEY, &self.saved);
+    }
+
+    /// Remove every stored journal, when the user has asked for nothing to be kept.
+    pub fn forget(&mut self, storage: &mut dyn eframe::Storage) {
+        if !self.saved.is_empty() {
+            self.saved.clear();
+            eframe::set_value(storage, JOURNAL_KEY, &self.saved);
+        }
+    }
+
+    /// The edits to store: the latest contents of each sector written before the first edit
+    /// that can't be replayed. None if there aren't any.
+    fn coalesce(&self) -> Option<SavedEdits> {
+        let base = self.base.as_ref()?;
+        let mut descriptions = Vec::new();
+        let mut sectors = BTreeMap::new();
+        for step in &self.steps {
+            let Step::Sectors {
+                description,
+                sectors: written,
+            } = step
+            else {
+                break;
+            };
+            descriptions.push(description.clone());
+            for (key, data) in written {
+                sectors.insert(*key, data);
+            }
+        }
+        if sectors.is_empty() {
+            return None;
+        }
+        Some(SavedEdits {
+            image_name: base.name.clone(),
+            image_len: base.len,
+            saved_at: web_sys::js_sys::Date::now(),
+            descriptions,
+            sectors: sectors
+                .into_iter()
+                .map(|(key, data)| (key, base64::engine::general_purpose::STANDARD.encode(data)))
+                .collect(),
+        })
+    }
+
+    /// Show the offer to recover stored edits. Returns the sectors to write if the user accepted.
+    pub fn show_offer(&mut self, ui: &mut egui::Ui) -> Option<Vec<(SectorKey, Vec<u8>)>> {
+        let edits = self.offered.as_ref()?;
+        let mut recover = false;
+        let mut discard = false;
+        ui.group(|ui| {
+            ui.horizontal_wrapped(|ui| {
+                let minutes = (web_sys::js_sys::Date::now() - edits.saved_at) / 60_000.0;
+                let age = match minutes {
+                    m if m < 1.0 => "less than a minute".to_string(),
+                    m if m < 120.0 => format!("{:.0} minutes", m),
+                    m => format!("{:.0} hours", m / 60.0),
+                };
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "{} has {} unsaved edits to {} sectors from {} ago.",
+                        edits.image_name,
+                        edits.descriptions.len(),
+                        edits.sectors.len(),
+                        age
+                    ),
+                )
+                .on_hover_text(edits.descriptions.join("\n"));
+                recover = ui.button("Recover").clicked();
+                discard = ui.button("Discard").clicked();
+            });
+        });
+
+        if recover {
+            return self.offered.take().map(|edits| edits.decode());
+        }
+        if discard {
+            self.offered = None;
+            // Forget the stored edits on the next save.
+            self.dirty = true;
+        }
+        None
+    }
+}
@@ -0,0 +1,335 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Per-stream-file status for Kryoflux stream sets.
+//!
+//! A Kryoflux set is a zip of `trackCC.H.raw` stream files, one per track. The loader only reports
+//! progress for the set as a whole, so before handing it over the worker reads each stream file
+//! itself and checks what its out-of-band blocks say about the capture: whether the stream ended
+//! cleanly, whether the board reported a buffering problem or no index signal, and how many
+//! revolutions it holds. Each result is sent back as it's found, so a bad stream file shows up
+//! in the grid by name instead of as one opaque percentage.
+//!
+//! Once a set has loaded, a re-dump of one of its tracks can be dropped as a single stream file.
+//! It's decoded on its own, as a one-track set, and its good sectors merged into the bad ones of
+//! the loaded image, so a bad track doesn't mean reloading the whole set.
+
+use std::io::{Cursor, Read, Write};
+
+use anyhow::Error;
+use fluxfox::DiskCh;
+use zip::write::SimpleFileOptions;
+
+/// Cell size of the stream grid, in points.
+const CELL_SIZE: egui::Vec2 = egui::vec2(10.0, 14.0);
+
+/// Out-of-band block types.
+const OOB_INDEX: u8 = 0x02;
+const OOB_STREAM_END: u8 = 0x03;
+const OOB_EOF: u8 = 0x0D;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StreamStatus {
+    Pending,
+    Checking,
+    Ok,
+    Error,
+}
+
+impl StreamStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            StreamStatus::Pending => "Pending",
+            StreamStatus::Checking => "Checking",
+            StreamStatus::Ok => "Ok",
+            StreamStatus::Error => "Error",
+        }
+    }
+
+    pub fn color32(&self) -> egui::Color32 {
+        match self {
+            StreamStatus::Pending => egui::Color32::from_gray(60),
+            StreamStatus::Checking => egui::Color32::from_rgb(230, 200, 40),
+            StreamStatus::Ok => egui::Color32::from_rgb(40, 170, 70),
+            StreamStatus::Error => egui::Color32::from_rgb(220, 50, 50),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamFile {
+    /// Path of the stream file in the zip.
+    pub path: String,
+    pub name: String,
+    pub c: u16,
+    pub h: u8,
+    pub status: StreamStatus,
+    /// The revolution count once checked, or what's wrong with the stream.
+    pub detail: Option<String>,
+}
+
+impl StreamFile {
+    /// Parse a stream file name of the form `trackCC.H.raw`, ignoring any directory prefix.
+    fn parse(path: &str) -> Option<Self> {
+        let name = path.rsplit('/').next()?;
+        let lower = name.to_ascii_lowercase();
+        let stem = lower.strip_prefix("track")?.strip_suffix(".raw")?;
+        let (c, h) = stem.split_once('.')?;
+        Some(Self {
+            path: path.to_string(),
+            name: name.to_string(),
+            c: c.parse().ok()?,
+            h: h.parse().ok()?,
+            status: StreamStatus::Pending,
+            detail: None,
+        })
+    }
+}
+
+/// The track a stream file named `trackCC.H.raw` holds, if `name` is one.
+pub fn stream_track(name: &str) -> Option<DiskCh> {
+    StreamFile::parse(name).map(|stream| DiskCh::new(stream.c, stream.h))
+}
+
+/// Wrap a single stream file in a zip, making a one-track set the loader can decode.
+pub fn single_stream_set(name: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file(name, SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored))?;
+    zip.write_all(data)?;
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Check a stream file's blocks. Returns the number of complete revolutions it holds, or what's
+/// wrong with it.
+pub fn check_stream(data: &[u8]) -> Result<usize, String> {
+    let mut pos = 0;
+    // Stream positions count flux data only, not out-of-band blocks.
+    let mut stream_pos = 0usize;
+    let mut indexes = 0usize;
+    let mut end = None;
+    while pos < data.len() {
+        let len = match data[pos] {
+            0x00..=0x07 | 0x09 => 2,
+            0x0A | 0x0C => 3,
+            0x0D => {
+                let header = data.get(pos..pos + 4).ok_or("the stream ends inside a block header")?;
+                let size = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if header[1] == OOB_EOF {
+                    break;
+                }
+                let payload = data.get(pos + 4..pos + 4 + size).ok_or("the stream ends inside a block")?;
+                match header[1] {
+                    OOB_INDEX => indexes += 1,
+                    OOB_STREAM_END if size >= 8 => {
+                        let position = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                        let result = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+                        end = Some((position as usize, result));
+                    }
+                    _ => {}
+                }
+                pos += 4 + size;
+                continue;
+            }
+            // Flux1, Nop1 and Ovl16.
+            _ => 1,
+        };
+        if pos + len > data.len() {
+            return Err("the stream ends inside a flux value".to_string());
+        }
+        pos += len;
+        stream_pos += len;
+    }
+
+    let Some((position, result)) = end
+    else {
+        return Err("there's no stream end block, so the capture was cut short".to_string());
+    };
+    match result {
+        0 => {}
+        1 => return Err("the board reported a buffering problem, so flux data was lost".to_string()),
+        2 => return Err("the board detected no index signal".to_string()),
+        code => return Err(format!("the board reported error {}", code)),
+    }
+    if position != stream_pos {
+        return Err(format!(
+            "the stream end is at {} but there are {} bytes of flux data",
+            position, stream_pos
+        ));
+    }
+    if indexes < 2 {
+        return Err("there are fewer than two index pulses, so not a full revolution".to_string());
+    }
+    Ok(indexes - 1)
+}
+
+/// Check each stream file of the set in `data`, in the order of `paths`, passing each result to
+/// `report`. Stops early if `report` returns false.
+pub fn check_streams(data: &[u8], paths: &[String], mut report: impl FnMut(usize, Result<usize, String>) -> bool) {
+    let mut archive = match zip::ZipArchive::new(Cursor::new(data)) {
+        Ok(archive) => archive,
+        Err(e) => {
+            log::error!("Couldn't reopen the Kryoflux set: {}", e);
+            return;
+        }
+    };
+    for (index, path) in paths.iter().enumerate() {
+        let mut stream = Vec::new();
+        let result = match archive.by_name(path) {
+            Ok(mut file) => match file.read_to_end(&mut stream) {
+                Ok(_) => check_stream(&stream),
+                Err(e) => Err(format!("it couldn't be read from the zip: {}", e)),
+            },
+            Err(e) => Err(format!("it couldn't be found in the zip: {}", e)),
+        };
+        if !report(index, result) {
+            return;
+        }
+    }
+}
+
+/// The stream files of a Kryoflux set being loaded, in checking order.
+#[derive(Clone, Debug, Default)]
+pub struct StreamMap {
+    pub streams: Vec<StreamFile>,
+}
+
+impl StreamMap {
+    /// Read the stream file list from a zip archive. Returns None if the archive isn't a
+    /// Kryoflux set.
+    pub fn from_zip(data: &[u8]) -> Option<Self> {
+        let archive = zip::ZipArchive::new(Cursor::new(data)).ok()?;
+        let mut streams: Vec<StreamFile> = archive.file_names().filter_map(StreamFile::parse).collect();
+        if streams.is_empty() {
+            return None;
+        }
+        streams.sort_by_key(|s| (s.c, s.h));
+        if let Some(first) = streams.first_mut() {
+            first.status = StreamStatus::Checking;
+        }
+        Some(Self { streams })
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Record that the stream for `ch` was replaced by a re-dump, described by `detail`.
+    pub fn set_replaced(&mut self, ch: DiskCh, detail: String) {
+        if let Some(stream) = self.streams.iter_mut().find(|s| s.c == ch.c() && s.h == ch.h()) {
+            stream.status = StreamStatus::Ok;
+            stream.detail = Some(detail);
+        }
+    }
+
+    /// Paths of the stream files in the zip, in checking order.
+    pub fn paths(&self) -> Vec<String> {
+        self.streams.iter().map(|s| s.path.clone()).collect()
+    }
+
+    /// Record the check of the stream at `index`. The worker checks them in order, so the next
+    /// one is being checked now.
+    pub fn set_checked(&mut self, index: usize, result: Result<usize, String>) {
+        let Some(stream) = self.streams.get_mut(index)
+        else {
+            return;
+        };
+        (stream.status, stream.detail) = match result {
+            Ok(revolutions) => (StreamStatus::Ok, Some(format!("{} revolutions", revolutions))),
+            Err(problem) => (StreamStatus::Error, Some(problem)),
+        };
+        if let Some(next) = self.streams.get_mut(index + 1) {
+            next.status = StreamStatus::Checking;
+        }
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &StreamFile> {
+        self.streams.iter().filter(|s| s.status == StreamStatus::Error)
+    }
+
+    /// Draw the stream files as a grid of cylinders (columns) by heads (rows). `loaded` is set
+    /// once the set has loaded, when failed tracks can be re-dumped into it.
+    pub fn show(&self, ui: &mut egui::Ui, loaded: bool) {
+        let cylinders = self.streams.iter().map(|s| s.c as usize + 1).max().unwrap_or(0);
+        let heads = self.streams.iter().map(|s| s.h as usize + 1).max().unwrap_or(0);
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Kryoflux streams ({}):", self.streams.len()));
+            for status in [StreamStatus::Pending, StreamStatus::Checking, StreamStatus::Ok, StreamStatus::Error] {
+                ui.colored_label(status.color32(), "⏹");
+                ui.label(status.label());
+            }
+        });
+
+        egui::ScrollArea::horizontal().id_salt("kryoflux_stream_grid").show(ui, |ui| {
+            let size = egui::vec2(cylinders as f32 * CELL_SIZE.x, heads as f32 * CELL_SIZE.y);
+            let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+            let painter = ui.painter_at(rect);
+
+            let cell_rect = |stream: &StreamFile| {
+                let min = rect.min + egui::vec2(stream.c as f32 * CELL_SIZE.x, stream.h as f32 * CELL_SIZE.y);
+                egui::Rect::from_min_size(min, CELL_SIZE).shrink(1.0)
+            };
+
+            for stream in self.streams.iter() {
+                painter.rect_filled(cell_rect(stream), egui::Rounding::ZERO, stream.status.color32());
+            }
+
+            if let Some(pos) = response.hover_pos() {
+                if let Some(stream) = self.streams.iter().find(|s| cell_rect(s).expand(1.0).contains(pos)) {
+                    let mut text = format!(
+                        "{} (c:{} h:{}): {}",
+                        stream.name,
+                        stream.c,
+                        stream.h,
+                        stream.status.label()
+                    );
+                    if let Some(detail) = &stream.detail {
+                        text += &format!(", {}", detail);
+                    }
+                    response.on_hover_text(text);
+                }
+            }
+        });
+
+        let failed: Vec<String> = self
+            .failed()
+            .map(|s| format!("{}: {}", s.name, s.detail.as_deref().unwrap_or("unreadable")))
+            .collect();
+        if !failed.is_empty() {
+            let advice = if loaded {
+                "Drop re-dumped stream files for these tracks to merge their good sectors into the image."
+            }
+            else {
+                "Re-dump these tracks and reload the set."
+            };
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("Failed stream files:\n{}\n{}", failed.join("\n"), advice),
+            );
+        }
+    }
+}
@@ -26,11 +26,79 @@
 */
 
 #![warn(clippy::all, rust_2018_idioms)]
+// Without the app, much of the UI code is unused.
+#![cfg_attr(not(feature = "gui"), allow(dead_code))]
 
+#[cfg(feature = "gui")]
 mod app;
+pub(crate) mod analysis;
+pub(crate) mod annotations;
+pub(crate) mod apple2;
+#[cfg(feature = "api")]
+pub mod api;
+pub(crate) mod archive;
+pub(crate) mod assets;
+#[cfg(feature = "gui")]
+pub(crate) mod autosave;
+pub(crate) mod bookmarks;
+pub(crate) mod boot_repair;
+pub(crate) mod boot_test;
+pub(crate) mod capture;
+pub(crate) mod carving;
+pub(crate) mod cbm;
+pub(crate) mod checksum;
+pub(crate) mod compare;
+pub(crate) mod copy;
+pub(crate) mod cpm;
+pub(crate) mod crash;
+pub(crate) mod disk_set;
+pub(crate) mod drag_out;
+pub(crate) mod export;
+pub(crate) mod fat;
+pub(crate) mod frame_budget;
+pub(crate) mod gl_context;
+pub(crate) mod hard_sector;
+pub(crate) mod hires;
+pub(crate) mod history;
+#[cfg(feature = "gui")]
+pub(crate) mod journal;
+pub(crate) mod kryoflux;
+pub(crate) mod mark_browser;
+pub(crate) mod merge;
+pub(crate) mod onboarding;
+pub(crate) mod panels;
+#[cfg(feature = "gui")]
+pub(crate) mod persist;
+pub(crate) mod plugin;
+pub(crate) mod preview;
+pub(crate) mod pwa;
+pub(crate) mod redecode;
+pub(crate) mod report;
+pub(crate) mod samples;
+pub(crate) mod scp;
+pub(crate) mod sector_clip;
+pub(crate) mod selection;
+pub(crate) mod sector_list;
+pub(crate) mod session;
+pub(crate) mod settings;
+pub(crate) mod sidecar;
+pub(crate) mod storage;
+pub(crate) mod tasks;
+pub(crate) mod templates;
+pub(crate) mod toasts;
+pub(crate) mod track_list;
+pub(crate) mod transform;
+pub(crate) mod unsupported;
 pub(crate) mod worker;
 pub(crate) mod util;
+pub(crate) mod virus;
 pub(crate) mod viz;
+pub(crate) mod viz_mesh;
+pub(crate) mod watchdog;
+pub(crate) mod waterfall;
 pub(crate) mod widgets;
 
+#[cfg(feature = "gui")]
 pub use app::App;
+#[cfg(feature = "gui")]
+pub use gl_context::start as start_web;
@@ -60,8 +60,6 @@ fn main() {
     eframe::WebLogger::init(log::LevelFilter::Debug).ok();
     log::debug!("Hello, web!");
 
-    let web_options = eframe::WebOptions::default();
-
     wasm_bindgen_futures::spawn_local(async {
         let document = web_sys::window()
             .expect("No window")
@@ -74,13 +72,7 @@ fn main() {
             .dyn_into::<web_sys::HtmlCanvasElement>()
             .expect("the_canvas_id was not a HtmlCanvasElement");
 
-        let start_result = eframe::WebRunner::new()
-            .start(
-                canvas,
-                web_options,
-                Box::new(|cc| Ok(Box::new(ffweb::App::new(cc)))),
-            )
-            .await;
+        let start_result = ffweb::start_web(canvas).await;
 
         // Remove the loading text and spinner:
         if let Some(loading_text) = document.get_element_by_id("loading_text") {
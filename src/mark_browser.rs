@@ -0,0 +1,454 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A browser of the address marks on each track, for protection analysis. The bitstream is
+//! searched for every index, ID and data mark with the same scanner as track re-decoding, so
+//! marks are listed at their exact bitcell offsets, including those fluxfox's decoder skips
+//! over, such as marks hidden inside another sector's data.
+//!
+//! Each mark is checked against the rest of its track: IDs without a data mark after them and
+//! data marks without an ID before them are orphans, and marks far from where the track's other
+//! sectors put them are out of place.
+//!
+//! Scanning every track of a disk takes too long for a frame, so the disk scan runs a track per
+//! step within the panel's frame budget, with each head's bitstreams collected once up front.
+
+use std::collections::HashMap;
+
+use fluxfox::visualization::collect_streams;
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::analysis::SectorKey;
+use crate::frame_budget::{FrameBudget, Incremental};
+use crate::panels::PanelEvent;
+use crate::redecode::{
+    crc16,
+    crc_preamble,
+    find_mark,
+    read_bytes,
+    track_bits,
+    Encoding,
+    DATA_MARK,
+    DATA_MARK_WINDOW,
+    DELETED_DATA_MARK,
+    ID_MARK,
+    INDEX_MARK,
+};
+
+/// Marks more than this fraction of the track's usual spacing away from it are out of place.
+const SPACING_TOLERANCE: f32 = 0.1;
+/// Data marks more than this many bitcells from the track's usual distance after their ID are
+/// out of place: a few bytes either way.
+const DATA_OFFSET_TOLERANCE: usize = 8 * 16;
+const MAX_SIZE_CODE: u8 = 6;
+pub const MARK_LIST_MAX_HEIGHT: f32 = 320.0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MarkKind {
+    Index,
+    Id,
+    Data,
+    DeletedData,
+}
+
+impl MarkKind {
+    fn from_mark(mark: u8) -> Option<Self> {
+        match mark {
+            INDEX_MARK => Some(MarkKind::Index),
+            ID_MARK => Some(MarkKind::Id),
+            DATA_MARK => Some(MarkKind::Data),
+            DELETED_DATA_MARK => Some(MarkKind::DeletedData),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MarkKind::Index => "IAM",
+            MarkKind::Id => "IDAM",
+            MarkKind::Data => "DAM",
+            MarkKind::DeletedData => "DDAM",
+        }
+    }
+
+    fn is_data(&self) -> bool {
+        matches!(self, MarkKind::Data | MarkKind::DeletedData)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MarkEntry {
+    pub kind: MarkKind,
+    /// Where the mark byte starts, in bitcells from the index.
+    pub bit_offset: usize,
+    /// The cylinder, head, sector and size code, for ID marks.
+    pub id: Option<[u8; 4]>,
+    /// Whether the ID's CRC, or the data's CRC for a data mark that follows an ID, is good.
+    pub crc_ok: Option<bool>,
+    /// IDs without data and data without an ID.
+    pub orphan: bool,
+    /// Everything else unusual about the mark.
+    pub flags: Vec<String>,
+}
+
+impl MarkEntry {
+    pub fn is_anomaly(&self) -> bool {
+        self.orphan || !self.flags.is_empty() || self.crc_ok == Some(false)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackMarks {
+    pub ch: DiskCh,
+    pub encoding: Encoding,
+    pub bit_len: usize,
+    pub marks: Vec<MarkEntry>,
+}
+
+impl TrackMarks {
+    pub fn anomalies(&self) -> usize {
+        self.marks.iter().filter(|mark| mark.is_anomaly()).count()
+    }
+
+    /// Scan track `ch` for marks in `encoding`, or if None, in whichever of FM and MFM finds
+    /// more of them. None if the track has no bitstream.
+    pub fn scan(disk: &DiskImage, ch: DiskCh, encoding: Option<Encoding>) -> Option<Self> {
+        Self::from_bits(ch, &track_bits(disk, ch)?, encoding)
+    }
+
+    fn from_bits(ch: DiskCh, bits: &[bool], encoding: Option<Encoding>) -> Option<Self> {
+        let marks = match encoding {
+            Some(encoding) => (encoding, scan_marks(bits, ch, encoding)),
+            None => [Encoding::Mfm, Encoding::Fm]
+                .into_iter()
+                .map(|encoding| (encoding, scan_marks(bits, ch, encoding)))
+                .max_by_key(|(_, marks)| marks.len())?,
+        };
+        Some(Self {
+            ch,
+            encoding: marks.0,
+            bit_len: bits.len(),
+            marks: marks.1,
+        })
+    }
+}
+
+fn median(mut values: Vec<usize>) -> Option<usize> {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+/// Find every mark on a track and check it against the others.
+fn scan_marks(bits: &[bool], ch: DiskCh, encoding: Encoding) -> Vec<MarkEntry> {
+    let mut marks = Vec::new();
+    let mut pos = 0;
+    while let Some((mark, after)) = find_mark(bits, encoding, pos, bits.len()) {
+        pos = after;
+        let Some(kind) = MarkKind::from_mark(mark)
+        else {
+            continue;
+        };
+        let mut entry = MarkEntry {
+            kind,
+            bit_offset: after - 16,
+            id: None,
+            crc_ok: None,
+            orphan: false,
+            flags: Vec::new(),
+        };
+        if kind == MarkKind::Id {
+            if let Some(id) = read_bytes(bits, after, 6) {
+                let mut covered = crc_preamble(encoding, mark);
+                covered.extend_from_slice(&id[..4]);
+                entry.id = Some([id[0], id[1], id[2], id[3]]);
+                entry.crc_ok = Some(crc16(&covered) == u16::from_be_bytes([id[4], id[5]]));
+            }
+        }
+        marks.push((entry, mark, after));
+    }
+
+    // Pair each data mark with the ID before it, if it's close enough to belong to it.
+    let mut paired = vec![false; marks.len()];
+    let mut data_offsets = Vec::new();
+    let mut data_extents = Vec::new();
+    for i in 1..marks.len() {
+        let (previous, next) = (&marks[i - 1].0, &marks[i].0);
+        let Some(id) = previous.id.filter(|_| previous.kind == MarkKind::Id && next.kind.is_data())
+        else {
+            continue;
+        };
+        let id_end = previous.bit_offset + 7 * 16;
+        if next.bit_offset < id_end || next.bit_offset - id_end > DATA_MARK_WINDOW {
+            continue;
+        }
+        paired[i - 1] = true;
+        paired[i] = true;
+        data_offsets.push((i, next.bit_offset - id_end));
+
+        let (mark, data_start) = (marks[i].1, marks[i].2);
+        let size = 128usize << id[3].min(MAX_SIZE_CODE);
+        if let Some(data) = read_bytes(bits, data_start, size + 2) {
+            let mut covered = crc_preamble(encoding, mark);
+            covered.extend_from_slice(&data[..size]);
+            marks[i].0.crc_ok = Some(crc16(&covered) == u16::from_be_bytes([data[size], data[size + 1]]));
+        }
+        data_extents.push((id[2], data_start, data_start + size * 16));
+    }
+
+    let usual_data_offset = median(data_offsets.iter().map(|(_, offset)| *offset).collect());
+    let id_offsets: Vec<usize> = marks
+        .iter()
+        .filter(|(entry, _, _)| entry.kind == MarkKind::Id)
+        .map(|(entry, _, _)| entry.bit_offset)
+        .collect();
+    let usual_spacing = median(id_offsets.windows(2).map(|pair| pair[1] - pair[0]).collect());
+
+    let mut entries: Vec<MarkEntry> = marks.into_iter().map(|(entry, _, _)| entry).collect();
+    for (i, offset) in data_offsets {
+        if let Some(usual) = usual_data_offset.filter(|usual| offset.abs_diff(*usual) > DATA_OFFSET_TOLERANCE) {
+            entries[i].flags.push(format!("{} bitcells after its ID, usually {}", offset, usual));
+        }
+    }
+
+    let mut seen: HashMap<[u8; 3], usize> = HashMap::new();
+    let mut previous_id: Option<usize> = None;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        if entry.kind != MarkKind::Index && !paired[i] {
+            entry.orphan = true;
+        }
+        if let Some((s, _, _)) =
+            data_extents.iter().find(|(_, start, end)| entry.bit_offset >= *start && entry.bit_offset < *end)
+        {
+            entry.flags.push(format!("inside the data of sector {}", s));
+        }
+        let Some([c, h, s, n]) = entry.id
+        else {
+            continue;
+        };
+        if c as u16 != ch.c() || h != ch.h() {
+            entry.flags.push(format!("ID names cylinder {} head {}", c, h));
+        }
+        if n > MAX_SIZE_CODE {
+            entry.flags.push(format!("size code {}", n));
+        }
+        if let Some(first) = seen.insert([c, h, s], entry.bit_offset) {
+            entry.flags.push(format!("same ID as the mark at bitcell {}", first));
+        }
+        if let (Some(previous), Some(usual)) = (previous_id, usual_spacing) {
+            let spacing = entry.bit_offset - previous;
+            if spacing.abs_diff(usual) as f32 > usual as f32 * SPACING_TOLERANCE {
+                entry.flags.push(format!("{} bitcells after the previous ID, usually {}", spacing, usual));
+            }
+        }
+        previous_id = Some(entry.bit_offset);
+    }
+    entries
+}
+
+/// A panel listing the address marks of the selected track, and the tracks with anomalies.
+#[derive(Default)]
+pub struct MarkBrowser {
+    track: Option<DiskCh>,
+    /// The encoding to scan in, or None to pick between FM and MFM for each track.
+    encoding: Option<Encoding>,
+    result: Option<TrackMarks>,
+    only_anomalies: bool,
+    /// Each track with anomalies and how many, after a scan of the whole disk.
+    disk_scan: Option<Vec<(DiskCh, usize)>>,
+    /// The scan of the whole disk while it runs, with the anomaly count of each track.
+    disk_job: Option<Incremental<Option<(DiskCh, usize)>>>,
+    budget: FrameBudget,
+}
+
+impl MarkBrowser {
+    pub fn clear(&mut self) {
+        self.track = None;
+        self.result = None;
+        self.disk_scan = None;
+        self.disk_job = None;
+    }
+
+    pub fn select(&mut self, ch: DiskCh) {
+        if self.track != Some(ch) {
+            self.track = Some(ch);
+            self.result = None;
+        }
+    }
+
+    /// Start scanning every track for anomalies, one track per step.
+    fn start_disk_scan(&mut self, disk: &DiskImage) {
+        let mut streams = Vec::new();
+        for head in 0..disk.get_sector_map().len() as u8 {
+            for (c, stream) in collect_streams(head, disk).into_iter().enumerate() {
+                streams.push((DiskCh::new(c as u16, head), stream));
+            }
+        }
+        let total = streams.len();
+        let encoding = self.encoding;
+        let steps = streams.into_iter().map(move |(ch, stream)| {
+            let bits: Vec<bool> = (0..stream.len()).map(|i| stream[i]).collect();
+            let marks = TrackMarks::from_bits(ch, &bits, encoding)?;
+            (marks.anomalies() > 0).then(|| (ch, marks.anomalies()))
+        });
+        self.disk_scan = None;
+        self.disk_job = Some(Incremental::new(steps, total));
+    }
+
+    /// Run the disk scan for this frame's budget, keeping the results once it completes.
+    fn run_disk_scan(&mut self, ui: &mut egui::Ui) {
+        let Some(job) = &mut self.disk_job
+        else {
+            return;
+        };
+        if job.run(&self.budget) {
+            if let Some(job) = self.disk_job.take() {
+                let mut tracks: Vec<(DiskCh, usize)> = job.into_results().into_iter().flatten().collect();
+                tracks.sort_by_key(|(ch, _)| (ch.c(), ch.h()));
+                self.disk_scan = Some(tracks);
+            }
+        }
+        else {
+            ui.horizontal(|ui| {
+                ui.label("Scanning tracks...");
+                ui.add(egui::ProgressBar::new(job.progress()).desired_width(200.0).show_percentage());
+            });
+            ui.ctx().request_repaint();
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &DiskImage) -> Option<PanelEvent> {
+        let mut event = None;
+        self.budget.begin_frame();
+        egui::CollapsingHeader::new("Address marks").id_salt("mark_browser").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Encoding:");
+                let mut changed = ui.selectable_value(&mut self.encoding, None, "Auto").changed();
+                for encoding in [Encoding::Fm, Encoding::Mfm] {
+                    changed |= ui.selectable_value(&mut self.encoding, Some(encoding), encoding.label()).changed();
+                }
+                if changed {
+                    self.result = None;
+                    self.disk_scan = None;
+                    self.disk_job = None;
+                }
+                ui.checkbox(&mut self.only_anomalies, "Only anomalies");
+                let scan = ui.add_enabled(self.disk_job.is_none(), egui::Button::new("Scan all tracks"));
+                if scan.clicked() {
+                    self.start_disk_scan(disk);
+                }
+            });
+            self.run_disk_scan(ui);
+
+            if let Some(tracks) = &self.disk_scan {
+                match tracks.is_empty() {
+                    true => ui.weak("No track has anomalous marks."),
+                    false => ui.label(format!("{} tracks with anomalous marks:", tracks.len())),
+                };
+                ui.horizontal_wrapped(|ui| {
+                    for (ch, count) in tracks {
+                        if ui.link(format!("{} ({})", ch, count)).clicked() {
+                            event = Some(PanelEvent::SelectTrack(*ch));
+                        }
+                    }
+                });
+            }
+
+            let Some(ch) = self.track
+            else {
+                ui.weak("Select a track to list its address marks.");
+                return;
+            };
+            if self.result.is_none() {
+                self.result = TrackMarks::scan(disk, ch, self.encoding);
+            }
+            let Some(result) = &self.result
+            else {
+                ui.weak(format!("Track {} has no bitstream to search.", ch));
+                return;
+            };
+
+            let orphans = result.marks.iter().filter(|mark| mark.orphan).count();
+            ui.label(format!(
+                "Track {}: {} marks found as {} in {} bitcells, {} orphans, {} anomalies",
+                ch,
+                result.marks.len(),
+                result.encoding.label(),
+                result.bit_len,
+                orphans,
+                result.anomalies()
+            ));
+            egui::ScrollArea::vertical()
+                .id_salt("mark_browser_rows")
+                .max_height(MARK_LIST_MAX_HEIGHT)
+                .show(ui, |ui| {
+                    egui::Grid::new("mark_browser_grid").striped(true).num_columns(5).show(ui, |ui| {
+                        for header in ["Bitcell", "Mark", "ID (C H S N)", "CRC", "Flags"] {
+                            ui.strong(header);
+                        }
+                        ui.end_row();
+                        for mark in result.marks.iter().filter(|mark| !self.only_anomalies || mark.is_anomaly()) {
+                            ui.monospace(mark.bit_offset.to_string());
+                            match mark.orphan {
+                                true => ui.colored_label(ui.visuals().warn_fg_color, mark.kind.label()),
+                                false => ui.label(mark.kind.label()),
+                            };
+                            match mark.id {
+                                Some([c, h, s, n]) => {
+                                    let id = egui::RichText::new(format!("{} {} {} {}", c, h, s, n)).monospace();
+                                    if ui.link(id).clicked() {
+                                        event = Some(PanelEvent::SelectSector(SectorKey::new(ch, s)));
+                                    }
+                                }
+                                None => {
+                                    ui.label("");
+                                }
+                            }
+                            match mark.crc_ok {
+                                Some(true) => ui.label("ok"),
+                                Some(false) => ui.colored_label(ui.visuals().error_fg_color, "bad"),
+                                None => ui.weak("-"),
+                            };
+                            let mut flags = mark.flags.clone();
+                            if mark.orphan {
+                                flags.insert(0, orphan_reason(mark.kind).to_string());
+                            }
+                            ui.label(flags.join("; "));
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+        event
+    }
+}
+
+fn orphan_reason(kind: MarkKind) -> &'static str {
+    match kind {
+        MarkKind::Id => "no data mark follows",
+        _ => "no ID before it",
+    }
+}
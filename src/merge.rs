@@ -0,0 +1,305 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Reconstructing a best-of image from several dumps of the same disk.
+//!
+//! Each dump of a worn disk usually loses different sectors, so a sector that's bad in one is
+//! often good in another. The loaded image is the base: its bad sectors are written over in
+//! place with good copies from the other dumps, and everything else on it is left as it was.
+//! For each track the other dumps are ranked by how many of its sectors they read with a good
+//! CRC, and each bad sector is taken from the best-ranked dump with a good copy of the same size
+//! and data mark. A sector no dump read cleanly keeps the loaded image's copy and its CRC error,
+//! so the merged image still shows which sectors were never recovered.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::{read_all_sectors, SectorKey, SectorRead};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Provenance {
+    /// The loaded image's copy was good, so it was kept.
+    Kept,
+    /// The loaded image's copy was bad, and a good one was taken from another dump.
+    Recovered,
+    /// No dump had a good copy, so the loaded image's bad one was kept, CRC error and all.
+    BadCrc,
+    /// Other dumps have the sector, but the loaded image has no copy of it to write over.
+    Missing,
+}
+
+impl Provenance {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Provenance::Kept => "good",
+            Provenance::Recovered => "recovered",
+            Provenance::BadCrc => "bad CRC",
+            Provenance::Missing => "missing",
+        }
+    }
+}
+
+pub struct MergedSector {
+    pub key: SectorKey,
+    /// The index of the dump the sector's data is from.
+    pub source: Option<usize>,
+    pub provenance: Provenance,
+    pub deleted: bool,
+}
+
+pub struct MergeReport {
+    /// The dumps' names, starting with the loaded image.
+    pub sources: Vec<String>,
+    pub sectors: Vec<MergedSector>,
+}
+
+impl MergeReport {
+    pub fn count(&self, provenance: Provenance) -> usize {
+        self.sectors.iter().filter(|sector| sector.provenance == provenance).count()
+    }
+
+    /// The number of sectors taken from each dump.
+    pub fn per_source(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.sources.len()];
+        for source in self.sectors.iter().filter_map(|sector| sector.source) {
+            counts[source] += 1;
+        }
+        counts
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} good, {} recovered, {} still with bad CRCs, {} missing, from {} dumps",
+            self.count(Provenance::Kept),
+            self.count(Provenance::Recovered),
+            self.count(Provenance::BadCrc),
+            self.count(Provenance::Missing),
+            self.sources.len()
+        )
+    }
+
+    /// The report as text, listing where each sector came from.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "Merged {}", self.summary());
+        for (i, (name, count)) in self.sources.iter().zip(self.per_source()).enumerate() {
+            let _ = writeln!(text, "  [{}] {}: {} sectors", i, name, count);
+        }
+        let _ = writeln!(text);
+        for sector in &self.sectors {
+            let source = sector.source.map_or("-".to_string(), |source| format!("[{}]", source));
+            let deleted = if sector.deleted { " deleted" } else { "" };
+            let _ = writeln!(
+                text,
+                "{:<18} {:<9} {}{}",
+                sector.key.to_string(),
+                sector.provenance.label(),
+                source,
+                deleted
+            );
+        }
+        text
+    }
+}
+
+/// The reads of one dump by sector, keeping a good read over a bad one where a sector is read
+/// more than once.
+fn reads_by_key(disk: &mut DiskImage) -> HashMap<SectorKey, SectorRead> {
+    let mut reads: HashMap<SectorKey, SectorRead> = HashMap::new();
+    for read in read_all_sectors(disk) {
+        match reads.get(&read.key) {
+            Some(existing) if !existing.data_crc_error || read.data_crc_error => {}
+            _ => {
+                reads.insert(read.key, read);
+            }
+        }
+    }
+    reads
+}
+
+/// Merge `primary`, the loaded image, with `others`, the other dumps by name. Returns the report
+/// and the sector writes that apply the merge to `primary`.
+pub fn merge(
+    primary: &mut DiskImage,
+    primary_name: &str,
+    others: &mut [(String, DiskImage)],
+) -> (MergeReport, Vec<(SectorKey, Vec<u8>)>) {
+    let mut reads = vec![reads_by_key(primary)];
+    reads.extend(others.iter_mut().map(|(_, disk)| reads_by_key(disk)));
+    let mut names = vec![primary_name.to_string()];
+    names.extend(others.iter().map(|(name, _)| name.clone()));
+
+    let mut tracks: BTreeMap<(u16, u8), Vec<SectorKey>> = BTreeMap::new();
+    for key in reads.iter().flat_map(|dump| dump.keys()) {
+        let keys = tracks.entry((key.c, key.h)).or_default();
+        if !keys.contains(key) {
+            keys.push(*key);
+        }
+    }
+
+    let mut sectors = Vec::new();
+    let mut patches = Vec::new();
+    for keys in tracks.values_mut() {
+        keys.sort_by_key(|key| key.s);
+        // Rank the other dumps by how much of the track they recovered.
+        let mut ranked: Vec<usize> = (1..reads.len()).collect();
+        ranked.sort_by_key(|&source| {
+            let good = keys.iter().filter(|key| reads[source].get(key).is_some_and(|read| !read.data_crc_error));
+            std::cmp::Reverse(good.count())
+        });
+
+        for &key in keys.iter() {
+            let Some(base) = reads[0].get(&key)
+            else {
+                sectors.push(MergedSector {
+                    key,
+                    source: None,
+                    provenance: Provenance::Missing,
+                    deleted: false,
+                });
+                continue;
+            };
+            if !base.data_crc_error {
+                sectors.push(MergedSector {
+                    key,
+                    source: Some(0),
+                    provenance: Provenance::Kept,
+                    deleted: base.deleted,
+                });
+                continue;
+            }
+            let good = ranked.iter().find(|&&source| {
+                reads[source].get(&key).is_some_and(|read| {
+                    !read.data_crc_error && read.data.len() == base.data.len() && read.deleted == base.deleted
+                })
+            });
+            let (source, provenance) = match good {
+                Some(&source) => {
+                    patches.push((key, reads[source][&key].data.clone()));
+                    (source, Provenance::Recovered)
+                }
+                None => (0, Provenance::BadCrc),
+            };
+            sectors.push(MergedSector {
+                key,
+                source: Some(source),
+                provenance,
+                deleted: base.deleted,
+            });
+        }
+    }
+
+    (MergeReport { sources: names, sectors }, patches)
+}
+
+/// The merge tool's state: the other dumps loaded so far, and the last merge's report.
+#[derive(Default)]
+pub struct MergeTool {
+    pub dumps: Vec<(String, DiskImage)>,
+    /// Set while waiting for the user to drop another dump.
+    pub awaiting: bool,
+    /// The name of the dump being loaded, if any.
+    pub loading: Option<String>,
+    pub report: Option<MergeReport>,
+}
+
+pub enum MergeAction {
+    Merge,
+    DownloadReport,
+}
+
+impl MergeTool {
+    pub fn clear(&mut self) {
+        *self = MergeTool::default();
+    }
+
+    pub fn add_dump(&mut self, disk: DiskImage) {
+        if let Some(name) = self.loading.take() {
+            self.dumps.push((name, disk));
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, primary: &str) -> Option<MergeAction> {
+        let mut action = None;
+        egui::CollapsingHeader::new("Merge dumps").id_salt("merge_dumps").show(ui, |ui| {
+            ui.label(
+                "Build a best-of image from several dumps of this disk, replacing its bad sectors with good \
+                 copies from the other dumps.",
+            );
+            ui.label(format!("[0] {} (loaded)", primary));
+            let mut remove = None;
+            for (i, (name, _)) in self.dumps.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("[{}] {}", i + 1, name));
+                    if ui.small_button("🗑").on_hover_text("Remove dump").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                self.dumps.remove(i);
+            }
+
+            ui.horizontal(|ui| {
+                if let Some(name) = &self.loading {
+                    ui.spinner();
+                    ui.label(format!("Loading {}...", name));
+                }
+                else if self.awaiting {
+                    ui.label("Drop another dump of the disk.");
+                    if ui.button("Cancel").clicked() {
+                        self.awaiting = false;
+                    }
+                }
+                else if ui.button("Add dump...").clicked() {
+                    self.awaiting = true;
+                }
+                if ui.add_enabled(!self.dumps.is_empty(), egui::Button::new("Merge")).clicked() {
+                    action = Some(MergeAction::Merge);
+                }
+            });
+
+            if let Some(report) = &self.report {
+                ui.separator();
+                ui.label(format!("Merged: {}", report.summary()));
+                egui::Grid::new("merge_report_grid").num_columns(2).show(ui, |ui| {
+                    for (name, count) in report.sources.iter().zip(report.per_source()) {
+                        ui.label(name);
+                        ui.label(format!("{} sectors", count));
+                        ui.end_row();
+                    }
+                });
+                if ui.button("Download report").clicked() {
+                    action = Some(MergeAction::DownloadReport);
+                }
+            }
+        });
+        action
+    }
+}
@@ -0,0 +1,216 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! The getting started tutorial shown on first use. It walks through loading an image, reading
+//! the visualization, inspecting sectors and files, and exporting, moving on by itself as each
+//! step is done. Closing it keeps the step reached, so it can be resumed from the Help menu.
+
+use crate::samples::{self, SampleLoader};
+
+/// How far the user got, persisted so the tutorial isn't shown again once closed.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct TutorialProgress {
+    pub step: usize,
+    /// Set once the tutorial has been finished or closed.
+    pub closed: bool,
+}
+
+/// What the user has done, for telling when a step is complete.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TutorialState {
+    pub image_loaded: bool,
+    pub selected: bool,
+    /// Whether anything has been exported while the tutorial was open. Filled in from
+    /// [`Tutorial::note_exported`].
+    pub exported: bool,
+}
+
+struct Step {
+    title: &'static str,
+    text: &'static [&'static str],
+    /// Whether the step is done. Steps that return false until the user moves on are only
+    /// finished with Next.
+    done: fn(&TutorialState) -> bool,
+    samples: bool,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        title: "Load a disk image",
+        text: &[
+            "Drag a disk image file onto this window to load it. Most formats are supported, \
+             including IMD, IMG, PSI, HFE, 86F, SCP, MFI and zipped Kryoflux stream sets.",
+            "No image at hand? Load an example here, or later from Image > Load example:",
+        ],
+        done: |state| state.image_loaded,
+        samples: true,
+    },
+    Step {
+        title: "Read the visualization",
+        text: &[
+            "Each ring is a track, from cylinder 0 at the outside inwards. Green is sector data, blue \
+             sector headers, orange data with a bad CRC, and purple address marks.",
+            "Hover over the disk to see which track and sector is under the pointer, and click a \
+             sector to select it.",
+        ],
+        done: |state| state.selected,
+        samples: false,
+    },
+    Step {
+        title: "Inspect sectors and files",
+        text: &[
+            "The selected sector is shown in the hex viewer below the visualization, with its \
+             structure decoded where it's recognized. Use Overlay to colour the disk by entropy, \
+             file and more.",
+            "If the disk has a FAT filesystem, the filesystem browser lists its files. Select one to \
+             preview it and see where its clusters lie on the disk. The Analysis panel runs every \
+             check at once.",
+        ],
+        done: |_| false,
+        samples: false,
+    },
+    Step {
+        title: "Export",
+        text: &[
+            "Use Image > Export to write the disk in another format. Only formats that can hold \
+             everything on this disk are offered.",
+            "That's the tour. The tutorial can be shown again from Help at any time.",
+        ],
+        done: |state| state.exported,
+        samples: false,
+    },
+];
+
+#[derive(Default)]
+pub struct Tutorial {
+    open: bool,
+    exported: bool,
+    /// Whether the current step was already done when last shown. Steps only move on by
+    /// themselves when they become done, so Back to a finished step stays there.
+    was_done: Option<bool>,
+}
+
+impl Tutorial {
+    /// Show the tutorial at startup unless it has been closed before.
+    pub fn start(&mut self, progress: &TutorialProgress) {
+        self.open = !progress.closed;
+    }
+
+    /// Called when an export finishes, which completes the last step.
+    pub fn note_exported(&mut self) {
+        self.exported = self.open;
+    }
+
+    /// Show the tutorial again from the step reached, or from the start if it was finished.
+    pub fn resume(&mut self, progress: &mut TutorialProgress) {
+        if progress.step >= STEPS.len() {
+            progress.step = 0;
+        }
+        progress.closed = false;
+        self.open = true;
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        progress: &mut TutorialProgress,
+        mut state: TutorialState,
+        loader: &SampleLoader,
+    ) {
+        if !self.open {
+            return;
+        }
+        state.exported |= self.exported;
+        let Some(step) = STEPS.get(progress.step)
+        else {
+            self.close(progress);
+            return;
+        };
+        let done = (step.done)(&state);
+        if done && self.was_done == Some(false) {
+            self.advance(progress);
+            ctx.request_repaint();
+            return;
+        }
+        self.was_done = Some(done);
+
+        let mut open = true;
+        let mut next = false;
+        let mut back = false;
+        egui::Window::new(format!("Getting started ({}/{})", progress.step + 1, STEPS.len()))
+            .id(egui::Id::new("tutorial"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .default_width(320.0)
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-16.0, 48.0))
+            .show(ctx, |ui| {
+                ui.strong(step.title);
+                for paragraph in step.text {
+                    ui.label(*paragraph);
+                }
+                if step.samples {
+                    ui.horizontal_wrapped(|ui| {
+                        samples::show_buttons(ui, loader);
+                    });
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    back = ui.add_enabled(progress.step > 0, egui::Button::new("Back")).clicked();
+                    let label = if progress.step + 1 == STEPS.len() { "Finish" } else { "Next" };
+                    next = ui.button(label).clicked();
+                });
+            });
+
+        if back {
+            progress.step -= 1;
+            self.was_done = None;
+        }
+        else if next {
+            self.advance(progress);
+        }
+        if !open {
+            self.close(progress);
+        }
+    }
+
+    fn advance(&mut self, progress: &mut TutorialProgress) {
+        progress.step += 1;
+        self.was_done = None;
+        if progress.step == STEPS.len() {
+            self.close(progress);
+        }
+    }
+
+    fn close(&mut self, progress: &mut TutorialProgress) {
+        self.open = false;
+        self.exported = false;
+        self.was_done = None;
+        progress.closed = true;
+    }
+}
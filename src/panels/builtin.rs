@@ -0,0 +1,313 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! The panels that come with the app.
+
+use crate::analysis::conformance::Conformance;
+use crate::analysis::fingerprint::Fingerprint;
+use crate::analysis::stepping::{CylinderMap, Stepping};
+use crate::boot_repair::BootRepair;
+use crate::carving::Carver;
+use crate::mark_browser::MarkBrowser;
+use crate::panels::{Panel, PanelContext, PanelEvent, PanelRegistry};
+use crate::redecode::TrackRedecoder;
+use crate::sector_list::SectorList;
+use crate::selection::Selection;
+use crate::track_list::TrackList;
+use crate::transform::Transform;
+use crate::virus::VirusScan;
+use crate::waterfall::Waterfall;
+
+/// The gap statistics gathered when the image loaded.
+#[derive(Default)]
+pub struct GapSummary;
+
+/// The image's fingerprint, taken when it loaded.
+#[derive(Default)]
+pub struct FingerprintPanel(Option<Fingerprint>);
+
+/// How the image's physical cylinders map to logical ones, found when it loaded.
+#[derive(Default)]
+pub struct CylinderMapPanel(Option<CylinderMap>);
+
+/// A replacement for a damaged boot sector, if the image has one.
+#[derive(Default)]
+pub struct BootRepairPanel(Option<BootRepair>);
+
+pub fn register(registry: &mut PanelRegistry) {
+    registry.register(GapSummary);
+    registry.register(FingerprintPanel::default());
+    registry.register(Conformance::default());
+    registry.register(CylinderMapPanel::default());
+    registry.register(BootRepairPanel::default());
+    registry.register(Waterfall::default());
+    registry.register(Carver::default());
+    registry.register(VirusScan::default());
+    registry.register(SectorList::default());
+    registry.register(TrackList::default());
+    registry.register(TrackRedecoder::default());
+    registry.register(MarkBrowser::default());
+}
+
+impl Panel for Carver {
+    fn id(&self) -> &'static str {
+        "carver"
+    }
+    fn title(&self) -> &'static str {
+        "Carve files"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk).map(PanelEvent::SelectSector)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+}
+
+impl Panel for VirusScan {
+    fn id(&self) -> &'static str {
+        "virus_scan"
+    }
+    fn title(&self) -> &'static str {
+        "Virus scan"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk, ctx.fat).map(PanelEvent::from)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+}
+
+impl Panel for SectorList {
+    fn id(&self) -> &'static str {
+        "sector_list"
+    }
+    fn title(&self) -> &'static str {
+        "Sectors"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk).map(PanelEvent::SelectSector)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+    fn on_selection(&mut self, selection: &Selection) {
+        match selection {
+            Selection::Sector(key) => self.select(Some(*key)),
+            Selection::Track(_) | Selection::Position { .. } => self.select(None),
+            Selection::File(_) => {}
+        }
+    }
+}
+
+impl Panel for TrackList {
+    fn id(&self) -> &'static str {
+        "track_list"
+    }
+    fn title(&self) -> &'static str {
+        "Tracks"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk).map(PanelEvent::SelectTrack)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+    fn on_selection(&mut self, selection: &Selection) {
+        match selection {
+            Selection::Track(ch) | Selection::Position { ch, .. } => self.select(Some(*ch)),
+            Selection::Sector(key) => self.select(Some(key.ch())),
+            Selection::File(_) => {}
+        }
+    }
+}
+
+impl Panel for TrackRedecoder {
+    fn id(&self) -> &'static str {
+        "track_redecoder"
+    }
+    fn title(&self) -> &'static str {
+        "Re-decode track"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk);
+        None
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+    fn on_selection(&mut self, selection: &Selection) {
+        match selection {
+            Selection::Track(ch) | Selection::Position { ch, .. } => self.select(*ch),
+            Selection::Sector(key) => self.select(key.ch()),
+            Selection::File(_) => {}
+        }
+    }
+}
+
+impl Panel for MarkBrowser {
+    fn id(&self) -> &'static str {
+        "mark_browser"
+    }
+    fn title(&self) -> &'static str {
+        "Address marks"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+    fn on_selection(&mut self, selection: &Selection) {
+        match selection {
+            Selection::Track(ch) | Selection::Position { ch, .. } => self.select(*ch),
+            Selection::Sector(key) => self.select(key.ch()),
+            Selection::File(_) => {}
+        }
+    }
+}
+
+impl Panel for GapSummary {
+    fn id(&self) -> &'static str {
+        "gap_stats"
+    }
+    fn title(&self) -> &'static str {
+        "Gap statistics"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        if let Some(gap_stats) = ctx.gap_stats {
+            gap_stats.show(ui);
+        }
+        None
+    }
+}
+
+impl Panel for FingerprintPanel {
+    fn id(&self) -> &'static str {
+        "fingerprint"
+    }
+    fn title(&self) -> &'static str {
+        "Fingerprint"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &mut PanelContext) -> Option<PanelEvent> {
+        if let Some(fingerprint) = &self.0 {
+            fingerprint.show(ui);
+        }
+        None
+    }
+    fn on_image_changed(&mut self) {
+        self.0 = None;
+    }
+    fn on_image_loaded(&mut self, ctx: &mut PanelContext) {
+        self.0 = ctx.gap_stats.map(|gap_stats| Fingerprint::new(ctx.disk, gap_stats));
+    }
+}
+
+impl Panel for Conformance {
+    fn id(&self) -> &'static str {
+        "conformance"
+    }
+    fn title(&self) -> &'static str {
+        "Format conformance"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk, ctx.gap_stats).map(PanelEvent::SelectTrack)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+}
+
+impl Panel for CylinderMapPanel {
+    fn id(&self) -> &'static str {
+        "cylinder_map"
+    }
+    fn title(&self) -> &'static str {
+        "Cylinder map"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &mut PanelContext) -> Option<PanelEvent> {
+        let cylinder_map = self.0.as_ref()?;
+        let mut de_double_step = false;
+        let clicked = cylinder_map.show(ui, &mut de_double_step);
+        match cylinder_map.stepping {
+            Stepping::DoubleStepped { offset } if de_double_step => {
+                Some(PanelEvent::Transform(Transform::DeDoubleStep(offset)))
+            }
+            _ => clicked.map(PanelEvent::SelectTrack),
+        }
+    }
+    fn on_image_changed(&mut self) {
+        self.0 = None;
+    }
+    fn on_image_loaded(&mut self, ctx: &mut PanelContext) {
+        self.0 = Some(CylinderMap::from_disk(ctx.disk));
+    }
+}
+
+impl Panel for BootRepairPanel {
+    fn id(&self) -> &'static str {
+        "boot_repair"
+    }
+    fn title(&self) -> &'static str {
+        "Boot sector repair"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &mut PanelContext) -> Option<PanelEvent> {
+        let boot_repair = self.0.as_mut()?;
+        if !boot_repair.show(ui) {
+            return None;
+        }
+        Some(PanelEvent::WriteSectors {
+            description: "Replace boot sector".to_string(),
+            sectors: vec![boot_repair.write()],
+        })
+    }
+    fn on_image_changed(&mut self) {
+        self.0 = None;
+    }
+    fn on_image_loaded(&mut self, ctx: &mut PanelContext) {
+        self.0 = BootRepair::from_disk(ctx.disk);
+    }
+}
+
+impl Panel for Waterfall {
+    fn id(&self) -> &'static str {
+        "waterfall"
+    }
+    fn title(&self) -> &'static str {
+        "Flux waterfall"
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent> {
+        self.show(ui, ctx.disk).map(PanelEvent::SelectTrack)
+    }
+    fn on_image_changed(&mut self) {
+        self.clear();
+    }
+    fn on_context_restored(&mut self) {
+        self.forget_textures();
+    }
+}
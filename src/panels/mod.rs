@@ -0,0 +1,170 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Inspection panels for the central panel, behind a common trait so new tools can be added by
+//! registering them rather than by adding another field and handler to the app.
+//!
+//! A panel draws itself with the loaded image and filesystem it's given, and reports what the
+//! user asked for as a PanelEvent, which the app carries out: selecting a track, sector or
+//! file, writing sectors, or transforming the track layout. Panels are told when the image
+//! changes so they can drop anything they computed from the previous one, and given the new one
+//! once it's in place so they can analyse it up front. They're also told of selections made
+//! elsewhere so they can follow them.
+//!
+//! Tools whose state the rest of the app feeds or reads are still drawn by the app: the
+//! comparison and merge tools take images from load tasks, the FAT browser's volume drives the
+//! overlays, drag-out and the panels' own context, the hex viewer follows the visualization's
+//! sector selection and the clipboard, the trim analysis is also offered from the export menu,
+//! the SCP track table comes from the file rather than the decoded image, and the 8-bit
+//! filesystem browsers can be mounted without one.
+
+pub mod builtin;
+
+use std::collections::BTreeSet;
+
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::analysis::gaps::GapStats;
+use crate::analysis::SectorKey;
+use crate::fat::browser::BrowserEvent;
+use crate::fat::{FatVolume, FileNode};
+use crate::selection::Selection;
+use crate::transform::Transform;
+
+/// What a panel can look at while it's drawn.
+pub struct PanelContext<'a> {
+    pub disk: &'a mut DiskImage,
+    pub fat: Option<(&'a FatVolume, &'a [FileNode])>,
+    pub gap_stats: Option<&'a GapStats>,
+}
+
+pub enum PanelEvent {
+    SelectTrack(DiskCh),
+    SelectSector(SectorKey),
+    SelectFile(String),
+    /// Write sectors to the image in place, keeping their old contents so the change can be undone.
+    WriteSectors {
+        description: String,
+        sectors: Vec<(SectorKey, Vec<u8>)>,
+    },
+    /// Remap the image's tracks in place, undoably.
+    Transform(Transform),
+}
+
+impl From<BrowserEvent> for PanelEvent {
+    fn from(event: BrowserEvent) -> Self {
+        match event {
+            BrowserEvent::SelectFile(path) => PanelEvent::SelectFile(path),
+            BrowserEvent::SelectSector(key) => PanelEvent::SelectSector(key),
+            BrowserEvent::WriteSectors { description, sectors } => PanelEvent::WriteSectors { description, sectors },
+        }
+    }
+}
+
+pub trait Panel {
+    /// A stable identifier, which hidden panels are persisted by.
+    fn id(&self) -> &'static str;
+    fn title(&self) -> &'static str;
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext) -> Option<PanelEvent>;
+    /// Called when an image is loaded, replaced or closed.
+    fn on_image_changed(&mut self) {}
+    /// Called once a new image is in place, after on_image_changed.
+    fn on_image_loaded(&mut self, _ctx: &mut PanelContext) {}
+    /// Called when something is selected elsewhere, including by another panel.
+    fn on_selection(&mut self, _selection: &Selection) {}
+    /// Called when the graphics context was restored. Textures the panel uploaded are gone and
+    /// must be uploaded again.
+    fn on_context_restored(&mut self) {}
+}
+
+pub struct PanelRegistry {
+    panels: Vec<Box<dyn Panel>>,
+}
+
+impl Default for PanelRegistry {
+    fn default() -> Self {
+        let mut registry = Self { panels: Vec::new() };
+        builtin::register(&mut registry);
+        registry
+    }
+}
+
+impl PanelRegistry {
+    pub fn register(&mut self, panel: impl Panel + 'static) {
+        if self.panels.iter().any(|existing| existing.id() == panel.id()) {
+            log::warn!("A panel with id {} is already registered", panel.id());
+            return;
+        }
+        self.panels.push(Box::new(panel));
+    }
+
+    pub fn image_changed(&mut self) {
+        for panel in self.panels.iter_mut() {
+            panel.on_image_changed();
+        }
+    }
+
+    pub fn image_loaded(&mut self, ctx: &mut PanelContext) {
+        for panel in self.panels.iter_mut() {
+            panel.on_image_loaded(ctx);
+        }
+    }
+
+    pub fn selection_changed(&mut self, selection: &Selection) {
+        for panel in self.panels.iter_mut() {
+            panel.on_selection(selection);
+        }
+    }
+
+    pub fn context_restored(&mut self) {
+        for panel in self.panels.iter_mut() {
+            panel.on_context_restored();
+        }
+    }
+
+    /// Show every panel that isn't hidden, in the order they were registered.
+    pub fn show(&mut self, ui: &mut egui::Ui, ctx: &mut PanelContext, hidden: &BTreeSet<String>) -> Vec<PanelEvent> {
+        self.panels
+            .iter_mut()
+            .filter(|panel| !hidden.contains(panel.id()))
+            .filter_map(|panel| panel.ui(ui, ctx))
+            .collect()
+    }
+
+    /// Checkboxes to show or hide each panel.
+    pub fn show_menu(&self, ui: &mut egui::Ui, hidden: &mut BTreeSet<String>) {
+        for panel in &self.panels {
+            let mut visible = !hidden.contains(panel.id());
+            if ui.checkbox(&mut visible, panel.title()).changed() {
+                match visible {
+                    true => hidden.remove(panel.id()),
+                    false => hidden.insert(panel.id().to_string()),
+                };
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Versioned storage of the app's preferences.
+//!
+//! State is stored as JSON carrying a `version` field. On load, the saved JSON is passed through
+//! a migration for each version it's behind before it's deserialized, so a change that renames
+//! or restructures a setting can carry the user's value over instead of quietly resetting it.
+//!
+//! Loading never gives up on the whole state because part of it is bad: a setting that still
+//! can't be read after migration is dropped on its own and reported, and what was saved is kept
+//! under a backup key. State saved by a newer build is backed up the same way before this build
+//! writes over it.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Brings saved state up from one version to the next, by editing its JSON in place.
+pub type Migration = fn(&mut Map<String, Value>);
+
+/// State read from storage, with what couldn't be carried over.
+pub struct Loaded<T> {
+    pub state: T,
+    /// Descriptions of settings that were dropped or saved by a newer build, for telling the user.
+    pub warnings: Vec<String>,
+    /// Saved state to keep under other keys, as (key, JSON). Storage can only be written when the
+    /// app saves, so these are held until then and written with [save_backups].
+    pub backups: Vec<(String, String)>,
+}
+
+/// Load state saved under `key`. `migrations[n]` upgrades version `n` to `n + 1`, so there's one
+/// for each version before `migrations.len()`, the current one. Returns None if nothing was saved.
+///
+/// `legacy_key` is read instead if nothing is saved under `key`, for state eframe saved as RON
+/// before versioning.
+pub fn load<T>(
+    storage: &dyn eframe::Storage,
+    key: &str,
+    legacy_key: &str,
+    migrations: &[Migration],
+) -> Option<Loaded<T>>
+where
+    T: Default + DeserializeOwned + Serialize,
+{
+    let current = migrations.len() as u64;
+    let mut warnings = Vec::new();
+    let mut backups = Vec::new();
+    let (saved, saved_key, text) = match storage.get_string(key) {
+        Some(json) => (serde_json::from_str::<Value>(&json).ok(), key, json),
+        None => {
+            let ron = storage.get_string(legacy_key)?;
+            (read_legacy(&ron), legacy_key, ron)
+        }
+    };
+    let Some(Value::Object(saved)) = saved
+    else {
+        log::error!("Saved state under {} isn't an object; backing it up", saved_key);
+        backups.push((backup_key(key, "unreadable"), text));
+        warnings.push("The saved settings couldn't be read and have been reset.".to_string());
+        return Some(Loaded {
+            state: T::default(),
+            warnings,
+            backups,
+        });
+    };
+
+    let version = saved.get("version").and_then(Value::as_u64).unwrap_or(0);
+    let mut map = saved.clone();
+    if version > current {
+        log::warn!("Saved state under {} is version {}, newer than {}", key, version, current);
+        let backup = backup_key(key, &format!("v{}", version));
+        warnings.push(format!(
+            "The settings were saved by a newer version of the app. Settings it doesn't know have been set \
+             aside under \"{}\".",
+            backup
+        ));
+        backups.push((backup, Value::Object(saved.clone()).to_string()));
+    }
+    for (from, migration) in migrations.iter().enumerate().skip(version as usize) {
+        log::info!("Migrating saved state under {} from version {}", key, from);
+        migration(&mut map);
+    }
+    // Anything from a newer version has been backed up, and is written back as this version so
+    // the newer build migrates it again.
+    map.insert("version".to_string(), Value::from(current));
+
+    let state = match serde_json::from_value::<T>(Value::Object(map.clone())) {
+        Ok(state) => state,
+        Err(e) => {
+            log::warn!("Saved state under {} didn't deserialize ({}); recovering it setting by setting", key, e);
+            backups.push((backup_key(key, "unreadable"), Value::Object(saved).to_string()));
+            let (state, dropped) = recover(map);
+            for name in dropped {
+                warnings.push(format!("The \"{}\" setting couldn't be read and has been reset.", name));
+            }
+            state
+        }
+    };
+    Some(Loaded {
+        state,
+        warnings,
+        backups,
+    })
+}
+
+pub fn save<T: Serialize>(storage: &mut dyn eframe::Storage, key: &str, state: &T) {
+    match serde_json::to_string(state) {
+        Ok(json) => storage.set_string(key, json),
+        Err(e) => log::error!("Error serializing state for {}: {:?}", key, e),
+    }
+}
+
+/// Write the backups held by [Loaded], leaving `backups` empty.
+pub fn save_backups(storage: &mut dyn eframe::Storage, backups: &mut Vec<(String, String)>) {
+    for (key, json) in backups.drain(..) {
+        log::info!("Backing up saved state to {}", key);
+        storage.set_string(&key, json);
+    }
+}
+
+/// Read state saved as RON before versioning into JSON, so it can be migrated and recovered
+/// setting by setting like the rest. RON read without its types loses enum variant names, so
+/// those settings come through as null or a list; they fail to deserialize and are reset alone.
+fn read_legacy(ron: &str) -> Option<Value> {
+    match ron::from_str::<Value>(ron) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            log::warn!("Legacy saved state isn't readable RON: {}", e);
+            None
+        }
+    }
+}
+
+/// Build the state from defaults, taking each saved setting that can be read. Returns the state
+/// and the names of the settings that couldn't be.
+fn recover<T>(saved: Map<String, Value>) -> (T, Vec<String>)
+where
+    T: Default + DeserializeOwned + Serialize,
+{
+    let Ok(Value::Object(mut map)) = serde_json::to_value(T::default())
+    else {
+        return (T::default(), Vec::new());
+    };
+    let mut dropped = Vec::new();
+    for (name, value) in saved {
+        let previous = map.insert(name.clone(), value);
+        if serde_json::from_value::<T>(Value::Object(map.clone())).is_err() {
+            log::warn!("Dropping saved setting {}", name);
+            match previous {
+                Some(previous) => map.insert(name.clone(), previous),
+                None => map.remove(&name),
+            };
+            dropped.push(name);
+        }
+    }
+    let state = serde_json::from_value(Value::Object(map)).unwrap_or_default();
+    (state, dropped)
+}
+
+fn backup_key(key: &str, suffix: &str) -> String {
+    format!("{}_backup_{}", key, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const KEY: &str = "state";
+    const LEGACY_KEY: &str = "app";
+
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<String, String>);
+
+    impl eframe::Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_string(), value);
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    fn storage(entries: &[(&str, &str)]) -> MemoryStorage {
+        MemoryStorage(entries.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect())
+    }
+
+    #[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+    enum Mode {
+        #[default]
+        Slow,
+        Fast,
+    }
+
+    #[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+    #[serde(default)]
+    struct State {
+        version: u32,
+        name: String,
+        count: u32,
+        mode: Mode,
+    }
+
+    /// Version 0 called `count` `total`.
+    fn rename_total(state: &mut Map<String, Value>) {
+        if let Some(total) = state.remove("total") {
+            state.insert("count".to_string(), total);
+        }
+    }
+
+    const MIGRATIONS: &[Migration] = &[rename_total];
+
+    fn load_state(storage: &MemoryStorage) -> Loaded<State> {
+        load(storage, KEY, LEGACY_KEY, MIGRATIONS).expect("state was saved")
+    }
+
+    #[test]
+    fn nothing_saved() {
+        assert!(load::<State>(&storage(&[]), KEY, LEGACY_KEY, MIGRATIONS).is_none());
+    }
+
+    #[test]
+    fn migrates_v0_json() {
+        let loaded = load_state(&storage(&[(KEY, r#"{"name": "disk", "total": 3, "mode": "Fast"}"#)]));
+        assert_eq!(
+            loaded.state,
+            State {
+                version: 1,
+                name: "disk".to_string(),
+                count: 3,
+                mode: Mode::Fast,
+            }
+        );
+        assert!(loaded.warnings.is_empty());
+        assert!(loaded.backups.is_empty());
+    }
+
+    #[test]
+    fn current_version_is_not_migrated_again() {
+        let loaded = load_state(&storage(&[(KEY, r#"{"version": 1, "count": 4, "total": 9}"#)]));
+        assert_eq!(loaded.state.count, 4);
+    }
+
+    #[test]
+    fn reads_legacy_ron() {
+        let loaded = load_state(&storage(&[(LEGACY_KEY, r#"(name: "disk", total: 5)"#)]));
+        assert_eq!(loaded.state.name, "disk");
+        assert_eq!(loaded.state.count, 5);
+        assert_eq!(loaded.state.version, 1);
+        assert!(loaded.warnings.is_empty());
+    }
+
+    #[test]
+    fn legacy_ron_is_recovered_setting_by_setting() {
+        // Enum variants don't survive being read without their types, so only `mode` is reset.
+        let loaded = load_state(&storage(&[(LEGACY_KEY, r#"(name: "disk", total: 5, mode: Fast)"#)]));
+        assert_eq!(loaded.state.name, "disk");
+        assert_eq!(loaded.state.count, 5);
+        assert_eq!(loaded.state.mode, Mode::Slow);
+        assert_eq!(loaded.warnings.len(), 1);
+        assert!(loaded.warnings[0].contains("\"mode\""));
+    }
+
+    #[test]
+    fn versioned_state_wins_over_legacy() {
+        let loaded = load_state(&storage(&[(KEY, r#"{"version": 1, "count": 1}"#), (LEGACY_KEY, "(total: 2)")]));
+        assert_eq!(loaded.state.count, 1);
+    }
+
+    #[test]
+    fn unreadable_legacy_ron_is_backed_up() {
+        let loaded = load_state(&storage(&[(LEGACY_KEY, "(name: ")]));
+        assert_eq!(loaded.state, State::default());
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.backups, vec![(backup_key(KEY, "unreadable"), "(name: ".to_string())]);
+    }
+
+    #[test]
+    fn newer_version_is_backed_up() {
+        let saved = r#"{"version": 7, "name": "disk", "count": 2, "future": true}"#;
+        let loaded = load_state(&storage(&[(KEY, saved)]));
+        assert_eq!(loaded.state.name, "disk");
+        assert_eq!(loaded.state.count, 2);
+        assert_eq!(loaded.state.version, 1);
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.backups.len(), 1);
+        assert_eq!(loaded.backups[0].0, backup_key(KEY, "v7"));
+        let backup: Value = serde_json::from_str(&loaded.backups[0].1).unwrap();
+        assert_eq!(backup["future"], Value::Bool(true));
+    }
+
+    #[test]
+    fn bad_field_is_reset_alone() {
+        let loaded = load_state(&storage(&[(KEY, r#"{"version": 1, "name": 12, "count": 6, "mode": "Fast"}"#)]));
+        assert_eq!(loaded.state.name, "");
+        assert_eq!(loaded.state.count, 6);
+        assert_eq!(loaded.state.mode, Mode::Fast);
+        assert_eq!(loaded.warnings.len(), 1);
+        assert!(loaded.warnings[0].contains("\"name\""));
+        assert_eq!(loaded.backups.len(), 1);
+    }
+
+    #[test]
+    fn non_object_is_backed_up() {
+        let loaded = load_state(&storage(&[(KEY, "[1, 2]")]));
+        assert_eq!(loaded.state, State::default());
+        assert_eq!(loaded.backups, vec![(backup_key(KEY, "unreadable"), "[1, 2]".to_string())]);
+    }
+
+    #[test]
+    fn backups_are_written_once() {
+        let mut storage = storage(&[]);
+        let mut backups = vec![("a".to_string(), "1".to_string())];
+        save_backups(&mut storage, &mut backups);
+        assert!(backups.is_empty());
+        assert_eq!(eframe::Storage::get_string(&storage, "a").as_deref(), Some("1"));
+    }
+}
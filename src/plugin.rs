@@ -0,0 +1,314 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+//! Analysis checks loaded from wasm modules at runtime.
+//!
+//! A plugin is a wasm module with no imports that exports the following (ABI version 1):
+//!
+//! - `memory`: its linear memory.
+//! - `ffweb_abi_version() -> i32`: must return 1.
+//! - `ffweb_info() -> i32`: a pointer to a buffer describing the check as JSON
+//!   `{"id": .., "name": .., "cost": "low" | "medium" | "high"}`.
+//! - `ffweb_alloc(len: i32) -> i32`: a pointer to `len` bytes the host may write the input to.
+//! - `ffweb_analyze(ptr: i32, len: i32) -> i32`: a pointer to a buffer holding the outcome as
+//!   JSON `{"status": "Passed" | "Warning" | "Skipped", "summary": .., "findings": [..]}`.
+//!
+//! Returned buffers are a little-endian u32 length followed by that many bytes.
+//!
+//! The input is the magic `FFSD`, a u32 sector count, then one record per sector: u16 cylinder,
+//! u8 head, u8 sector id, u8 size code, u8 flags (bit 0: bad data CRC, bit 1: deleted mark),
+//! u16 data length, then the data. All integers are little-endian.
+//!
+//! A plugin's code never runs on the main thread. The module is only compiled there; each call
+//! instantiates it in a fresh worker (see `assets/plugin_worker.js`), which is terminated when
+//! it answers or after `PLUGIN_TIMEOUT_MS`, so a plugin that hangs or crashes can't take the
+//! app with it. Plugin check ids are prefixed with `PLUGIN_ID_PREFIX`, so a plugin can replace
+//! an earlier load of itself but never a built-in check.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail, Context};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::{Function, Object, Promise, Reflect, Uint8Array, WebAssembly};
+
+use crate::analysis::read_all_sectors;
+use crate::analysis::registry::{Check, CheckContext, CheckOutcome, Cost};
+
+pub const ABI_VERSION: i32 = 1;
+/// Prefixed to the id a plugin reports to make the id of its check.
+pub const PLUGIN_ID_PREFIX: &str = "plugin:";
+/// How long a plugin call may run before its worker is terminated.
+const PLUGIN_TIMEOUT_MS: i32 = 10_000;
+const PLUGIN_WORKER_URL: &str = "./plugin_worker.js";
+const INPUT_MAGIC: &[u8; 4] = b"FFSD";
+
+#[derive(serde::Deserialize)]
+struct PluginInfo {
+    id: String,
+    name: String,
+    cost: Cost,
+}
+
+pub struct PluginCheck {
+    /// The id of the check, made from the id the plugin reported.
+    id: String,
+    info: PluginInfo,
+    module: WebAssembly::Module,
+    ctx: egui::Context,
+    running: Rc<Cell<bool>>,
+    /// The outcome of the last run, once its worker answered.
+    finished: Rc<RefCell<Option<CheckOutcome>>>,
+}
+
+impl PluginCheck {
+    /// Compile a plugin module and read its description in a worker.
+    async fn instantiate(ctx: egui::Context, bytes: &[u8]) -> anyhow::Result<Self> {
+        let module: WebAssembly::Module = JsFuture::from(WebAssembly::compile(&Uint8Array::from(bytes)))
+            .await
+            .map_err(js_error)
+            .context("Couldn't compile the module")?
+            .unchecked_into();
+
+        let reply = call_in_worker(&module, "info", None).await?;
+        let version = get(&reply, "version")?
+            .as_f64()
+            .ok_or_else(|| anyhow!("ffweb_abi_version didn't return a number"))? as i32;
+        if version != ABI_VERSION {
+            bail!("Unsupported plugin ABI version {} (expected {})", version, ABI_VERSION);
+        }
+        let info: PluginInfo = serde_json::from_slice(&reply_buffer(&reply)?).context("Invalid plugin info")?;
+        if info.id.is_empty() {
+            bail!("The plugin has no id");
+        }
+        Ok(Self {
+            id: format!("{}{}", PLUGIN_ID_PREFIX, info.id),
+            info,
+            module,
+            ctx,
+            running: Rc::default(),
+            finished: Rc::default(),
+        })
+    }
+}
+
+/// Call `call` on a fresh instance of `module` in a new worker, passing `input` if given.
+/// Returns the worker's reply. The worker is terminated once it has answered, or if it
+/// doesn't within `PLUGIN_TIMEOUT_MS`.
+async fn call_in_worker(module: &WebAssembly::Module, call: &str, input: Option<&[u8]>) -> anyhow::Result<JsValue> {
+    let worker = web_sys::Worker::new(PLUGIN_WORKER_URL).map_err(js_error).context("Couldn't start the worker")?;
+    let message = Object::new();
+    Reflect::set(&message, &"module".into(), module).map_err(js_error)?;
+    Reflect::set(&message, &"call".into(), &call.into()).map_err(js_error)?;
+    if let Some(input) = input {
+        Reflect::set(&message, &"input".into(), &Uint8Array::from(input)).map_err(js_error)?;
+    }
+
+    let mut setup = Ok(());
+    let reply = Promise::new(&mut |resolve: Function, reject: Function| {
+        let on_message = Closure::once_into_js(move |event: JsValue| {
+            let data = Reflect::get(&event, &"data".into()).unwrap_or(JsValue::UNDEFINED);
+            let _ = resolve.call1(&JsValue::NULL, &data);
+        });
+        worker.set_onmessage(Some(on_message.unchecked_ref()));
+        let on_error = {
+            let reject = reject.clone();
+            Closure::once_into_js(move |event: JsValue| {
+                let message = Reflect::get(&event, &"message".into()).unwrap_or(JsValue::UNDEFINED);
+                let _ = reject.call1(&JsValue::NULL, &format!("The worker failed: {:?}", message).into());
+            })
+        };
+        worker.set_onerror(Some(on_error.unchecked_ref()));
+        let on_timeout = Closure::once_into_js(move || {
+            let message = format!("The plugin didn't answer within {} s", PLUGIN_TIMEOUT_MS / 1000);
+            let _ = reject.call1(&JsValue::NULL, &message.into());
+        });
+        setup = web_sys::window()
+            .ok_or_else(|| JsValue::from_str("No window"))
+            .and_then(|window| {
+                window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                    on_timeout.unchecked_ref(),
+                    PLUGIN_TIMEOUT_MS,
+                )
+            })
+            .and_then(|_| worker.post_message(&message));
+    });
+    if let Err(e) = setup {
+        worker.terminate();
+        return Err(js_error(e)).context("Couldn't start the plugin call");
+    }
+    let reply = JsFuture::from(reply).await;
+    // A settled call is done with its worker either way; a hung one only stops here.
+    worker.terminate();
+    let reply = reply.map_err(js_error)?;
+    if let Ok(error) = get(&reply, "error") {
+        bail!("{} failed: {}", call, error.as_string().unwrap_or_default());
+    }
+    Ok(reply)
+}
+
+/// The bytes of the buffer a plugin call returned, which the worker copied out of its memory.
+fn reply_buffer(reply: &JsValue) -> anyhow::Result<Vec<u8>> {
+    let buffer = get(reply, "buffer")?
+        .dyn_into::<Uint8Array>()
+        .map_err(|_| anyhow!("The worker didn't return a buffer"))?;
+    Ok(buffer.to_vec())
+}
+
+impl Check for PluginCheck {
+    fn id(&self) -> &str {
+        &self.id
+    }
+    fn name(&self) -> &str {
+        &self.info.name
+    }
+    fn cost(&self) -> Cost {
+        self.info.cost
+    }
+    /// Start the plugin on the image in a worker. Its outcome is picked up with poll().
+    fn run(&self, ctx: &mut CheckContext) -> CheckOutcome {
+        if self.running.replace(true) {
+            return CheckOutcome::running("Still running the last analysis");
+        }
+        let input = encode_sectors(ctx);
+        let (module, id) = (self.module.clone(), self.id.clone());
+        let (running, finished, egui_ctx) = (self.running.clone(), self.finished.clone(), self.ctx.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let outcome = match call_in_worker(&module, "analyze", Some(&input)).await {
+                Ok(reply) => reply_buffer(&reply)
+                    .and_then(|buffer| serde_json::from_slice::<CheckOutcome>(&buffer).context("Invalid outcome")),
+                Err(e) => Err(e),
+            };
+            let outcome = outcome.unwrap_or_else(|e| {
+                log::error!("Plugin {} failed: {:?}", id, e);
+                CheckOutcome::skipped(format!("The plugin failed: {:#}", e))
+            });
+            *finished.borrow_mut() = Some(outcome);
+            running.set(false);
+            egui_ctx.request_repaint();
+        });
+        CheckOutcome::running("Running in the background")
+    }
+    fn poll(&self) -> Option<CheckOutcome> {
+        self.finished.borrow_mut().take()
+    }
+}
+
+/// Serialize every readable sector in the plugin input format.
+fn encode_sectors(ctx: &mut CheckContext) -> Vec<u8> {
+    let sectors = read_all_sectors(ctx.disk);
+    let mut input = Vec::with_capacity(8 + sectors.iter().map(|read| 8 + read.data.len()).sum::<usize>());
+    input.extend_from_slice(INPUT_MAGIC);
+    input.extend_from_slice(&(sectors.len() as u32).to_le_bytes());
+    for read in sectors {
+        let flags = read.data_crc_error as u8 | (read.deleted as u8) << 1;
+        input.extend_from_slice(&read.key.c.to_le_bytes());
+        input.extend_from_slice(&[read.key.h, read.key.s, read.chsn.n(), flags]);
+        input.extend_from_slice(&(read.data.len() as u16).to_le_bytes());
+        input.extend_from_slice(&read.data);
+    }
+    input
+}
+
+fn get(target: &JsValue, key: &str) -> anyhow::Result<JsValue> {
+    let value = Reflect::get(target, &JsValue::from_str(key)).map_err(js_error)?;
+    if value.is_undefined() {
+        bail!("The reply has no {}", key);
+    }
+    Ok(value)
+}
+
+fn js_error(e: JsValue) -> anyhow::Error {
+    anyhow!("{:?}", e)
+}
+
+/// Instantiates plugins in the background. Finished loads are collected with take_loaded().
+#[derive(Default)]
+pub struct PluginLoader {
+    /// A dropped plugin waiting for the user to agree to load it.
+    offered: Option<(String, Vec<u8>)>,
+    loaded: Rc<RefCell<Vec<(String, anyhow::Result<PluginCheck>)>>>,
+}
+
+impl PluginLoader {
+    pub fn is_plugin_file(name: &str) -> bool {
+        name.to_ascii_lowercase().ends_with(".wasm")
+    }
+
+    /// Ask the user whether to load the plugin `name`, the next time show() is called.
+    pub fn offer(&mut self, name: &str, bytes: Vec<u8>) {
+        self.offered = Some((name.to_string(), bytes));
+    }
+
+    /// Show the prompt for an offered plugin, loading it if the user agrees.
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let Some((name, _)) = &self.offered
+        else {
+            return;
+        };
+        let (mut load, mut dismiss) = (false, false);
+        egui::Window::new("Load analysis plugin?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("{} is an analysis plugin.", name));
+                ui.label(
+                    "Loading it runs the code it contains on every image you analyze. It runs in a worker \
+                     without access to the page, but only load plugins from sources you trust.",
+                );
+                ui.horizontal(|ui| {
+                    load = ui.button("Load plugin").clicked();
+                    dismiss = ui.button("Don't load").clicked();
+                });
+            });
+        if load {
+            if let Some((name, bytes)) = self.offered.take() {
+                self.load(ctx, &name, bytes);
+            }
+        }
+        else if dismiss {
+            self.offered = None;
+        }
+    }
+
+    pub fn load(&self, ctx: &egui::Context, name: &str, bytes: Vec<u8>) {
+        let loaded = self.loaded.clone();
+        let (ctx, name) = (ctx.clone(), name.to_string());
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = PluginCheck::instantiate(ctx.clone(), &bytes).await;
+            loaded.borrow_mut().push((name, result));
+            ctx.request_repaint();
+        });
+    }
+
+    /// Plugins which finished loading since the last call, by file name.
+    pub fn take_loaded(&self) -> Vec<(String, anyhow::Result<PluginCheck>)> {
+        std::mem::take(&mut *self.loaded.borrow_mut())
+    }
+}
@@ -0,0 +1,207 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Plain text and ANSI art. DOS text is code page 437, and ANSI art colours it with SGR escape
+//! sequences and positions it with cursor movements, of which only moving forward is common
+//! enough in art files to be worth following.
+
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Color32, FontId};
+
+/// Code page 437 characters 0x80 to 0xFF.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// The CGA/EGA/VGA text mode palette, in SGR colour order: black, red, green, yellow (brown),
+/// blue, magenta, cyan and white, then their bright versions.
+const ANSI_PALETTE: [Color32; 16] = [
+    Color32::from_rgb(0x00, 0x00, 0x00),
+    Color32::from_rgb(0xAA, 0x00, 0x00),
+    Color32::from_rgb(0x00, 0xAA, 0x00),
+    Color32::from_rgb(0xAA, 0x55, 0x00),
+    Color32::from_rgb(0x00, 0x00, 0xAA),
+    Color32::from_rgb(0xAA, 0x00, 0xAA),
+    Color32::from_rgb(0x00, 0xAA, 0xAA),
+    Color32::from_rgb(0xAA, 0xAA, 0xAA),
+    Color32::from_rgb(0x55, 0x55, 0x55),
+    Color32::from_rgb(0xFF, 0x55, 0x55),
+    Color32::from_rgb(0x55, 0xFF, 0x55),
+    Color32::from_rgb(0xFF, 0xFF, 0x55),
+    Color32::from_rgb(0x55, 0x55, 0xFF),
+    Color32::from_rgb(0xFF, 0x55, 0xFF),
+    Color32::from_rgb(0x55, 0xFF, 0xFF),
+    Color32::from_rgb(0xFF, 0xFF, 0xFF),
+];
+
+const ESC: u8 = 0x1B;
+/// DOS end of file. Anything after it, such as a SAUCE record, isn't part of the text.
+const EOF: u8 = 0x1A;
+
+pub fn cp437_char(byte: u8) -> char {
+    match byte {
+        0x80..=0xFF => CP437_HIGH[byte as usize - 0x80],
+        _ => byte as char,
+    }
+}
+
+/// Decode code page 437 text, dropping carriage returns and anything after an end of file.
+pub fn decode_cp437(data: &[u8]) -> String {
+    data.iter()
+        .take_while(|byte| **byte != EOF)
+        .filter(|byte| **byte != b'\r')
+        .map(|byte| cp437_char(*byte))
+        .collect()
+}
+
+/// Whether `data` looks like text: almost all printable, with no NULs.
+pub fn is_text(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(4096)];
+    let binary = sample
+        .iter()
+        .take_while(|byte| **byte != EOF)
+        .filter(|byte| matches!(byte, 0x00..=0x08 | 0x0E..=0x19 | 0x1C..=0x1F))
+        .count();
+    !sample.is_empty() && binary * 100 <= sample.len()
+}
+
+pub fn is_ansi(data: &[u8]) -> bool {
+    data.windows(2).any(|pair| pair == [ESC, b'['])
+}
+
+struct AnsiState {
+    fg: usize,
+    bg: usize,
+    bold: bool,
+}
+
+impl AnsiState {
+    fn apply(&mut self, params: &[usize]) {
+        if params.is_empty() {
+            *self = AnsiState::default();
+        }
+        for param in params {
+            match param {
+                0 => *self = AnsiState::default(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30..=37 => self.fg = param - 30,
+                39 => self.fg = 7,
+                40..=47 => self.bg = param - 40,
+                49 => self.bg = 0,
+                _ => {}
+            }
+        }
+    }
+
+    fn format(&self, font_id: &FontId) -> TextFormat {
+        let fg = if self.bold { self.fg + 8 } else { self.fg };
+        TextFormat {
+            font_id: font_id.clone(),
+            color: ANSI_PALETTE[fg],
+            background: ANSI_PALETTE[self.bg],
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for AnsiState {
+    fn default() -> Self {
+        Self { fg: 7, bg: 0, bold: false }
+    }
+}
+
+/// Lay out ANSI art in colour, in the monospace font.
+pub fn layout_ansi(data: &[u8], font_id: FontId) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    let mut state = AnsiState::default();
+    let mut run = String::new();
+    let mut column = 0;
+    // Set after wrapping at the screen edge, where a line ending adds no further line.
+    let mut wrapped = false;
+
+    let mut i = 0;
+    while i < data.len() && data[i] != EOF {
+        let byte = data[i];
+        i += 1;
+        if byte == ESC && data.get(i) == Some(&b'[') {
+            let start = i + 1;
+            let end = data[start..]
+                .iter()
+                .position(|byte| byte.is_ascii_alphabetic())
+                .map_or(data.len(), |end| start + end);
+            let params: Vec<usize> = String::from_utf8_lossy(&data[start..end])
+                .split(';')
+                .filter_map(|param| param.parse().ok())
+                .collect();
+            match data.get(end) {
+                Some(b'm') => {
+                    job.append(&std::mem::take(&mut run), 0.0, state.format(&font_id));
+                    state.apply(&params);
+                }
+                Some(b'C') => {
+                    let count = params.first().copied().unwrap_or(1).min(80);
+                    run.extend(std::iter::repeat(' ').take(count));
+                    column += count;
+                }
+                _ => {}
+            }
+            i = end + 1;
+            continue;
+        }
+        match byte {
+            b'\r' => {}
+            b'\n' => {
+                if !wrapped {
+                    run.push('\n');
+                }
+                column = 0;
+                wrapped = false;
+            }
+            _ => {
+                run.push(cp437_char(byte));
+                column += 1;
+                wrapped = false;
+                // Art is drawn for an 80 column screen, which wraps.
+                if column == 80 {
+                    run.push('\n');
+                    column = 0;
+                    wrapped = true;
+                }
+            }
+        }
+    }
+    job.append(&run, 0.0, state.format(&font_id));
+    job
+}
@@ -0,0 +1,204 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Listings of tokenized GW-BASIC and BASICA programs, the format `SAVE` writes by default.
+//! Each line is a link to the next line, a line number, then keywords as tokens, numbers in
+//! binary and everything else as text, ending with a zero.
+
+use crate::preview::ansi::cp437_char;
+
+/// The first byte of an unprotected tokenized program.
+pub const TOKENIZED_MARKER: u8 = 0xFF;
+/// The first byte of a program saved with `,P`, which is encrypted.
+pub const PROTECTED_MARKER: u8 = 0xFE;
+
+/// Keywords 0x81 to 0xF4.
+const KEYWORDS: [&str; 116] = [
+    "END", "FOR", "NEXT", "DATA", "INPUT", "DIM", "READ", "LET", "GOTO", "RUN", "IF", "RESTORE", "GOSUB", "RETURN",
+    "REM", "STOP", "PRINT", "CLEAR", "LIST", "NEW", "ON", "WAIT", "DEF", "POKE", "CONT", "", "", "OUT", "LPRINT",
+    "LLIST", "", "WIDTH", "ELSE", "TRON", "TROFF", "SWAP", "ERASE", "EDIT", "ERROR", "RESUME", "DELETE", "AUTO",
+    "RENUM", "DEFSTR", "DEFINT", "DEFSNG", "DEFDBL", "LINE", "WHILE", "WEND", "CALL", "", "", "", "WRITE", "OPTION",
+    "RANDOMIZE", "OPEN", "CLOSE", "LOAD", "MERGE", "SAVE", "COLOR", "CLS", "MOTOR", "BSAVE", "BLOAD", "SOUND",
+    "BEEP", "PSET", "PRESET", "SCREEN", "KEY", "LOCATE", "", "TO", "THEN", "TAB(", "STEP", "USR", "FN", "SPC(", "NOT",
+    "ERL", "ERR", "STRING$", "USING", "INSTR", "'", "VARPTR", "CSRLIN", "POINT", "OFF", "INKEY$", "", "", "", "", "",
+    "", "", ">", "=", "<", "+", "-", "*", "/", "^", "AND", "OR", "XOR", "EQV", "IMP", "MOD", "\\",
+];
+
+/// Statements prefixed with 0xFE, from 0x81.
+const FE_KEYWORDS: [&str; 40] = [
+    "FILES", "FIELD", "SYSTEM", "NAME", "LSET", "RSET", "KILL", "PUT", "GET", "RESET", "COMMON", "CHAIN", "DATE$",
+    "TIME$", "PAINT", "COM", "CIRCLE", "DRAW", "PLAY", "TIMER", "ERDEV", "IOCTL", "CHDIR", "MKDIR", "RMDIR", "SHELL",
+    "ENVIRON", "VIEW", "WINDOW", "PMAP", "PALETTE", "LCOPY", "CALLS", "", "", "NOISE", "PCOPY", "TERM", "LOCK",
+    "UNLOCK",
+];
+
+/// Functions prefixed with 0xFF, from 0x81.
+const FF_KEYWORDS: [&str; 37] = [
+    "LEFT$", "RIGHT$", "MID$", "SGN", "INT", "ABS", "SQR", "RND", "SIN", "LOG", "EXP", "COS", "TAN", "ATN", "FRE",
+    "INP", "POS", "LEN", "STR$", "VAL", "ASC", "CHR$", "PEEK", "SPACE$", "OCT$", "HEX$", "LPOS", "CINT", "CSNG", "CDBL",
+    "FIX", "PEN", "STICK", "STRIG", "EOF", "LOC", "LOF",
+];
+
+/// Conversion functions prefixed with 0xFD, from 0x81.
+const FD_KEYWORDS: [&str; 11] = ["CVI", "CVS", "CVD", "MKI$", "MKS$", "MKD$", "", "", "", "", "EXTERR"];
+
+const TOKEN_REM: u8 = 0x8F;
+const TOKEN_DATA: u8 = 0x84;
+const TOKEN_ELSE: u8 = 0xA1;
+const TOKEN_WHILE: u8 = 0xB1;
+const TOKEN_APOSTROPHE: u8 = 0xD9;
+const TOKEN_PLUS: u8 = 0xE9;
+
+fn keyword(table: &[&'static str], token: u8) -> Option<&'static str> {
+    let keyword = *table.get(token.checked_sub(0x81)? as usize)?;
+    (!keyword.is_empty()).then_some(keyword)
+}
+
+/// Convert a Microsoft Binary Format float to an f64. The last byte is the exponent, and the
+/// top bit of the byte before it the sign.
+fn mbf_to_f64(bytes: &[u8]) -> f64 {
+    let (mantissa, exponent) = bytes.split_at(bytes.len() - 1);
+    if exponent[0] == 0 {
+        return 0.0;
+    }
+    let negative = mantissa[mantissa.len() - 1] & 0x80 != 0;
+    let mut value = 0.0;
+    for (i, byte) in mantissa.iter().rev().enumerate() {
+        let byte = if i == 0 { byte | 0x80 } else { *byte };
+        value += byte as f64 / 256f64.powi(i as i32 + 1);
+    }
+    let value = value * 2f64.powi(exponent[0] as i32 - 128);
+    if negative { -value } else { value }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+/// List a tokenized program. Returns None if `data` isn't one; protected programs are listed
+/// as a note that they can't be.
+pub fn detokenize(data: &[u8]) -> Option<String> {
+    match data.first() {
+        Some(&TOKENIZED_MARKER) => {}
+        Some(&PROTECTED_MARKER) => return Some("(This program was saved protected, and can't be listed.)".to_string()),
+        _ => return None,
+    }
+
+    let mut listing = String::new();
+    let mut pos = 1;
+    loop {
+        let next = u16_at(data, pos)?;
+        if next == 0 {
+            break;
+        }
+        let number = u16_at(data, pos + 2)?;
+        pos += 4;
+        listing += &format!("{} ", number);
+
+        let mut quoted = false;
+        // Set after REM or DATA, after which the rest of the line is text.
+        let mut literal = false;
+        while let Some(&byte) = data.get(pos) {
+            pos += 1;
+            if byte == 0 {
+                break;
+            }
+            if quoted || literal || (0x20..0x80).contains(&byte) && byte != b':' {
+                if byte == b'"' && !literal {
+                    quoted = !quoted;
+                }
+                listing.push(cp437_char(byte));
+                continue;
+            }
+            match byte {
+                // ELSE and ' are stored after a statement separator that isn't listed.
+                b':' => match data.get(pos) {
+                    Some(&TOKEN_ELSE) => {}
+                    Some(&TOKEN_REM) if data.get(pos + 1) == Some(&TOKEN_APOSTROPHE) => {
+                        pos += 2;
+                        listing.push('\'');
+                        literal = true;
+                    }
+                    _ => listing.push(':'),
+                },
+                // Octal and hexadecimal constants.
+                0x0B => {
+                    listing += &format!("&O{:o}", u16_at(data, pos)?);
+                    pos += 2;
+                }
+                0x0C => {
+                    listing += &format!("&H{:X}", u16_at(data, pos)?);
+                    pos += 2;
+                }
+                // Line numbers, and line numbers that a RUN has replaced with pointers.
+                0x0D | 0x0E => {
+                    listing += &u16_at(data, pos)?.to_string();
+                    pos += 2;
+                }
+                0x0F => {
+                    listing += &data.get(pos)?.to_string();
+                    pos += 1;
+                }
+                0x11..=0x1B => listing += &(byte - 0x11).to_string(),
+                0x1C => {
+                    listing += &(u16_at(data, pos)? as i16).to_string();
+                    pos += 2;
+                }
+                0x1D => {
+                    listing += &format!("{}", mbf_to_f64(data.get(pos..pos + 4)?) as f32);
+                    pos += 4;
+                }
+                0x1F => {
+                    listing += &format!("{}#", mbf_to_f64(data.get(pos..pos + 8)?));
+                    pos += 8;
+                }
+                0xFD | 0xFE | 0xFF => {
+                    let table: &[&'static str] = match byte {
+                        0xFD => &FD_KEYWORDS,
+                        0xFE => &FE_KEYWORDS,
+                        _ => &FF_KEYWORDS,
+                    };
+                    let token = *data.get(pos)?;
+                    pos += 1;
+                    listing += keyword(table, token).unwrap_or("?");
+                }
+                0x81..=0xF4 => {
+                    listing += keyword(&KEYWORDS, byte).unwrap_or("?");
+                    match byte {
+                        TOKEN_REM | TOKEN_DATA => literal = true,
+                        // WHILE is always stored followed by a + that isn't listed.
+                        TOKEN_WHILE if data.get(pos) == Some(&TOKEN_PLUS) => pos += 1,
+                        _ => {}
+                    }
+                }
+                _ => listing.push(cp437_char(byte)),
+            }
+        }
+        listing.push('\n');
+    }
+    Some(listing)
+}
@@ -0,0 +1,173 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Previews of the file types common on DOS disks: text and ANSI art, BMP, PCX and GIF images,
+//! and tokenized GW-BASIC programs. The type is chosen by extension, then by content for files
+//! with an unfamiliar one.
+
+pub mod ansi;
+pub mod basic;
+pub mod pcx;
+
+use egui::text::LayoutJob;
+use egui::{ColorImage, FontId, TextureHandle, TextureOptions};
+use image::ImageFormat;
+
+pub const PREVIEW_MAX_HEIGHT: f32 = 400.0;
+/// Larger files are previewed from their start only.
+const MAX_TEXT_BYTES: usize = 64 * 1024;
+
+enum PreviewContent {
+    Text(LayoutJob),
+    Image(ColorImage),
+    Basic(String),
+    Unsupported,
+    Error(String),
+}
+
+fn extension(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_uppercase()).unwrap_or_default()
+}
+
+fn decode_image(data: &[u8], format: ImageFormat) -> Result<ColorImage, String> {
+    let image = image::load_from_memory_with_format(data, format).map_err(|e| e.to_string())?;
+    let rgba = image.to_rgba8();
+    let size = [rgba.width() as usize, rgba.height() as usize];
+    Ok(ColorImage::from_rgba_unmultiplied(size, rgba.as_raw()))
+}
+
+fn classify(path: &str, data: &[u8]) -> PreviewContent {
+    let text = &data[..data.len().min(MAX_TEXT_BYTES)];
+    let font_id = FontId::monospace(12.0);
+    let image = |result: Result<ColorImage, String>| match result {
+        Ok(image) => PreviewContent::Image(image),
+        Err(e) => PreviewContent::Error(e),
+    };
+    match extension(path).as_str() {
+        "BMP" => return image(decode_image(data, ImageFormat::Bmp)),
+        "GIF" => return image(decode_image(data, ImageFormat::Gif)),
+        "PCX" => return image(pcx::decode(data).map_err(|e| e.to_string())),
+        "BAS" => {
+            if let Some(listing) = basic::detokenize(data) {
+                return PreviewContent::Basic(listing);
+            }
+        }
+        "ANS" | "TXT" | "DOC" | "NFO" | "DIZ" | "BAT" | "ME" | "1ST" => {
+            return PreviewContent::Text(ansi::layout_ansi(text, font_id));
+        }
+        _ => {}
+    }
+
+    if data.starts_with(b"BM") {
+        image(decode_image(data, ImageFormat::Bmp))
+    }
+    else if data.starts_with(b"GIF8") {
+        image(decode_image(data, ImageFormat::Gif))
+    }
+    else if pcx::is_pcx(data) {
+        image(pcx::decode(data).map_err(|e| e.to_string()))
+    }
+    else if ansi::is_ansi(text) || ansi::is_text(text) {
+        PreviewContent::Text(ansi::layout_ansi(text, font_id))
+    }
+    else {
+        PreviewContent::Unsupported
+    }
+}
+
+/// The preview of the selected file, decoded once when the selection changes.
+#[derive(Default)]
+pub struct FilePreview {
+    path: Option<String>,
+    content: Option<PreviewContent>,
+    texture: Option<TextureHandle>,
+}
+
+impl FilePreview {
+    pub fn clear(&mut self) {
+        self.path = None;
+        self.content = None;
+        self.texture = None;
+    }
+
+    /// Drop the image texture, which is uploaded again from the decoded image when next shown.
+    pub fn forget_textures(&mut self) {
+        self.texture = None;
+    }
+
+    /// Show the preview of the file at `path`, calling `read` for its contents if it isn't the
+    /// file previewed last.
+    pub fn show(&mut self, ui: &mut egui::Ui, path: &str, read: impl FnOnce() -> Vec<u8>) {
+        if self.path.as_deref() != Some(path) {
+            self.clear();
+            self.content = Some(classify(path, &read()));
+            self.path = Some(path.to_string());
+        }
+        let Some(content) = &self.content
+        else {
+            return;
+        };
+
+        egui::CollapsingHeader::new("Preview").default_open(true).show(ui, |ui| match content {
+            PreviewContent::Text(job) => {
+                egui::ScrollArea::both().id_salt("file_preview_text").max_height(PREVIEW_MAX_HEIGHT).show(
+                    ui,
+                    |ui| {
+                        ui.label(job.clone());
+                    },
+                );
+            }
+            PreviewContent::Basic(listing) => {
+                egui::ScrollArea::both().id_salt("file_preview_basic").max_height(PREVIEW_MAX_HEIGHT).show(
+                    ui,
+                    |ui| {
+                        ui.label(egui::RichText::new(listing).monospace());
+                    },
+                );
+            }
+            PreviewContent::Image(image) => {
+                let texture = self.texture.get_or_insert_with(|| {
+                    ui.ctx().load_texture("file_preview", image.clone(), TextureOptions::NEAREST)
+                });
+                ui.label(format!("{} × {}", image.size[0], image.size[1]));
+                egui::ScrollArea::both().id_salt("file_preview_image").max_height(PREVIEW_MAX_HEIGHT).show(
+                    ui,
+                    |ui| {
+                        ui.image((texture.id(), texture.size_vec2()));
+                    },
+                );
+            }
+            PreviewContent::Unsupported => {
+                ui.weak("No preview for this type of file.");
+            }
+            PreviewContent::Error(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Couldn't decode the image: {}", e));
+            }
+        });
+    }
+}
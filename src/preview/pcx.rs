@@ -0,0 +1,127 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A decoder for ZSoft PCX images, the common DOS paint format, which the image crate doesn't
+//! read. Handles the layouts DOS software wrote: monochrome, CGA, EGA planar, VGA 256-colour
+//! and 24-bit planar.
+
+use anyhow::{anyhow, bail, Error};
+use egui::{Color32, ColorImage};
+
+const HEADER_LEN: usize = 128;
+const MANUFACTURER: u8 = 0x0A;
+/// The marker before the 256-colour palette at the end of the file.
+const VGA_PALETTE_MARKER: u8 = 0x0C;
+const VGA_PALETTE_LEN: usize = 768;
+/// Images larger than this in either dimension are refused, as they can't be real.
+pub const MAX_DIMENSION: usize = 4096;
+
+fn u16_at(data: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) as usize
+}
+
+pub fn is_pcx(data: &[u8]) -> bool {
+    data.len() > HEADER_LEN && data[0] == MANUFACTURER && data[2] == 1
+}
+
+/// Expand the run-length encoded scanlines.
+fn decode_rle(data: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut bytes = data.iter();
+    while out.len() < len {
+        let Some(&byte) = bytes.next()
+        else {
+            break;
+        };
+        if byte & 0xC0 == 0xC0 {
+            let value = bytes.next().copied().unwrap_or(0);
+            out.extend(std::iter::repeat(value).take((byte & 0x3F) as usize));
+        }
+        else {
+            out.push(byte);
+        }
+    }
+    out.resize(len, 0);
+    out
+}
+
+pub fn decode(data: &[u8]) -> Result<ColorImage, Error> {
+    if !is_pcx(data) {
+        bail!("Not a PCX image");
+    }
+    let bits = data[3] as usize;
+    let width = u16_at(data, 8).checked_sub(u16_at(data, 4)).ok_or(anyhow!("Bad image width"))? + 1;
+    let height = u16_at(data, 10).checked_sub(u16_at(data, 6)).ok_or(anyhow!("Bad image height"))? + 1;
+    let planes = data[65] as usize;
+    let line_bytes = u16_at(data, 66);
+    if width > MAX_DIMENSION || height > MAX_DIMENSION || line_bytes * 8 < width * bits {
+        bail!("Unsupported PCX dimensions {}x{}", width, height);
+    }
+
+    let header_palette: Vec<Color32> =
+        data[16..64].chunks(3).map(|rgb| Color32::from_rgb(rgb[0], rgb[1], rgb[2])).collect();
+    let palette_start = data.len().saturating_sub(VGA_PALETTE_LEN);
+    let vga_palette = (data.len() > HEADER_LEN + VGA_PALETTE_LEN && data[palette_start - 1] == VGA_PALETTE_MARKER)
+        .then(|| &data[palette_start..]);
+    let stride = line_bytes * planes;
+    let pixels = decode_rle(&data[HEADER_LEN..], stride * height);
+
+    let mut image = ColorImage::new([width, height], Color32::BLACK);
+    for y in 0..height {
+        let line = &pixels[y * stride..(y + 1) * stride];
+        for x in 0..width {
+            image.pixels[y * width + x] = match (bits, planes) {
+                (8, 1) => match vga_palette {
+                    Some(palette) => {
+                        let rgb = &palette[line[x] as usize * 3..];
+                        Color32::from_rgb(rgb[0], rgb[1], rgb[2])
+                    }
+                    None => Color32::from_gray(line[x]),
+                },
+                (8, 3) => Color32::from_rgb(line[x], line[line_bytes + x], line[line_bytes * 2 + x]),
+                (1, 1) => match line[x / 8] >> (7 - x % 8) & 1 {
+                    0 => Color32::BLACK,
+                    _ => Color32::WHITE,
+                },
+                (1, 2..=4) => {
+                    let index = (0..planes)
+                        .map(|plane| ((line[plane * line_bytes + x / 8] >> (7 - x % 8)) & 1) << plane)
+                        .sum::<u8>();
+                    header_palette[index as usize]
+                }
+                (2 | 4, 1) => {
+                    let per_byte = 8 / bits;
+                    let shift = (per_byte - 1 - x % per_byte) * bits;
+                    let index = (line[x / per_byte] >> shift) & ((1 << bits) - 1);
+                    header_palette[index as usize]
+                }
+                _ => bail!("Unsupported PCX layout: {} bits in {} planes", bits, planes),
+            };
+        }
+    }
+    Ok(image)
+}
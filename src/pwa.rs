@@ -0,0 +1,118 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Offline use and installation as a progressive web app. The service worker in assets/sw.js
+//! caches the files listed here, along with everything the page loaded, so the app keeps
+//! working with no connection once it has been opened online.
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use web_sys::js_sys::Array;
+
+#[wasm_bindgen(module = "/assets/pwa.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = registerServiceWorker)]
+    fn register_service_worker(url: &str, precache: Array);
+    #[wasm_bindgen(js_name = installAvailable)]
+    fn install_available() -> bool;
+    #[wasm_bindgen(js_name = promptInstall)]
+    fn prompt_install();
+    #[wasm_bindgen(js_name = isOfflineReady)]
+    fn is_offline_ready() -> bool;
+    #[wasm_bindgen(js_name = isOnline)]
+    fn is_online() -> bool;
+}
+
+/// Files the app loads after startup, or only on demand, which must be cached up front to be
+/// available offline. Paths are relative to the document, and resolved against it in pwa.js.
+const PRECACHE: &[&str] = &[
+    "./",
+    "./index.html",
+    "./ffweb.js",
+    "./ffweb_bg.wasm",
+    "./worker.js",
+    "./load_worker.js",
+    "./plugin_worker.js",
+    "./boot_test.html",
+    "./favicon.ico",
+    "./assets/manifest.json",
+    "./assets/asset_manifest.json",
+    "./assets/fluxfox_logo.png",
+    "./assets/icon-256.png",
+    "./assets/icon-1024.png",
+    "./assets/icon_ios_touch_192.png",
+    "./assets/maskable_icon_x512.png",
+    "./samples/dos_360k.imd",
+    "./samples/protected_360k.imd",
+    "./samples/damaged_360k.imd",
+];
+
+#[derive(Default)]
+pub struct Pwa {
+    registered: bool,
+    offline_notified: bool,
+}
+
+impl Pwa {
+    /// Register the service worker and ask it to cache the app.
+    pub fn register(&mut self) {
+        if self.registered {
+            return;
+        }
+        let precache = PRECACHE
+            .iter()
+            .map(|path| wasm_bindgen::JsValue::from_str(path))
+            .collect::<Array>();
+        register_service_worker("./sw.js", precache);
+        self.registered = true;
+    }
+
+    /// Returns true the first time the app is found to be cached for offline use.
+    pub fn became_offline_ready(&mut self) -> bool {
+        if self.offline_notified || !is_offline_ready() {
+            return false;
+        }
+        self.offline_notified = true;
+        true
+    }
+
+    /// An "Install app" button, if the browser offers installation, and an indicator when
+    /// there's no connection.
+    pub fn show(&self, ui: &mut egui::Ui) {
+        if install_available()
+            && ui
+                .button("Install app")
+                .on_hover_text("Install fluxfox web to use it offline, like any other app.")
+                .clicked()
+        {
+            prompt_install();
+        }
+        if !is_online() {
+            ui.colored_label(ui.visuals().warn_fg_color, "Offline")
+                .on_hover_text("There's no network connection. The app works from its cache.");
+        }
+    }
+}
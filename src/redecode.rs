@@ -0,0 +1,425 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Re-decoding a track's bitstream with an encoding the user chooses. fluxfox detects the
+//! encoding of each track itself, and can pick wrong on marginal tracks or on disks that mix
+//! encodings, such as those with an FM track 0 and MFM everywhere else. The bitcells it decoded
+//! are searched again here for the chosen encoding's address marks, and the sectors found are
+//! listed.
+//!
+//! This is a read-only view. The image keeps fluxfox's decoding of the track, because fluxfox
+//! writes sectors only in the encoding it detected: writing these sectors back would re-encode
+//! an FM or GCR track as MFM.
+//!
+//! The bitcells are reused as they are, so this can't help if the cell rate was detected wrong
+//! as well as the encoding.
+
+use std::collections::BTreeMap;
+
+use fluxfox::visualization::collect_streams;
+use fluxfox::{DiskCh, DiskImage};
+
+
+/// Size codes past this are taken as damage rather than read as sectors of up to 8K.
+const MAX_SIZE_CODE: u8 = 6;
+/// How far past the end of an ID its data mark is looked for, in bitcells.
+pub(crate) const DATA_MARK_WINDOW: usize = 64 * 16;
+/// The A1 sync byte with its missing clock bit, which precedes every MFM address mark but the
+/// index mark, which follows C2 bytes with a missing clock bit instead.
+const MFM_SYNC: u32 = 0x4489;
+const MFM_SYNC_BYTE: u8 = 0xA1;
+const MFM_INDEX_SYNC: u32 = 0x5224;
+const MFM_INDEX_SYNC_BYTE: u8 = 0xC2;
+/// The clock patterns with missing bits that FM address marks are written with.
+const FM_MARK_CLOCK: u8 = 0xC7;
+const FM_INDEX_MARK_CLOCK: u8 = 0xD7;
+pub(crate) const ID_MARK: u8 = 0xFE;
+pub(crate) const DATA_MARK: u8 = 0xFB;
+pub(crate) const DELETED_DATA_MARK: u8 = 0xF8;
+pub(crate) const INDEX_MARK: u8 = 0xFC;
+/// Commodore GCR blocks follow a sync of at least this many 1 bits.
+const GCR_SYNC_LEN: usize = 10;
+const GCR_HEADER_BLOCK: u8 = 0x08;
+const GCR_DATA_BLOCK: u8 = 0x07;
+const GCR_SECTOR_SIZE: usize = 256;
+/// The 5-bit group code of each nibble.
+const GCR_CODES: [u8; 16] = [
+    0x0A, 0x0B, 0x12, 0x13, 0x0E, 0x0F, 0x16, 0x17, 0x09, 0x19, 0x1A, 0x1B, 0x0D, 0x1D, 0x1E, 0x15,
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Fm,
+    Mfm,
+    /// Commodore's 4-to-5 group code, as written by the 1541.
+    CbmGcr,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 3] = [Encoding::Fm, Encoding::Mfm, Encoding::CbmGcr];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Encoding::Fm => "FM",
+            Encoding::Mfm => "MFM",
+            Encoding::CbmGcr => "Commodore GCR",
+        }
+    }
+}
+
+/// A sector found by re-decoding.
+#[derive(Clone, Debug)]
+pub struct DecodedSector {
+    /// Where the ID's address mark ends, in bitcells from the index.
+    pub bit_offset: usize,
+    /// The cylinder, head, sector and size code from the ID. GCR headers have no head or size
+    /// code, so those are 0 and 1.
+    pub id: [u8; 4],
+    pub id_ok: bool,
+    /// The sector's data, if its data mark was found.
+    pub data: Option<Vec<u8>>,
+    pub data_ok: bool,
+    pub deleted: bool,
+}
+
+impl DecodedSector {
+    pub fn good(&self) -> bool {
+        self.id_ok && self.data.is_some() && self.data_ok
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Redecoded {
+    pub ch: DiskCh,
+    pub encoding: Encoding,
+    pub bit_len: usize,
+    pub sectors: Vec<DecodedSector>,
+}
+
+/// The CRC of FM and MFM IDs and data: CRC-16/CCITT, starting at FFFF.
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x1021,
+        })
+    })
+}
+
+/// The 16 bitcells of an FM byte written with `clock` as its clock bits.
+fn fm_cells(data: u8, clock: u8) -> u32 {
+    (0..8).rev().fold(0, |cells, bit| (cells << 2) | ((((clock >> bit) & 1) << 1) | ((data >> bit) & 1)) as u32)
+}
+
+/// Read `count` FM or MFM bytes starting at bitcell `pos`, taking the data bit of each pair of
+/// cells.
+pub(crate) fn read_bytes(bits: &[bool], pos: usize, count: usize) -> Option<Vec<u8>> {
+    if pos + count * 16 > bits.len() {
+        return None;
+    }
+    Some(
+        (0..count)
+            .map(|i| (0..8).fold(0u8, |byte, bit| (byte << 1) | bits[pos + i * 16 + bit * 2 + 1] as u8))
+            .collect(),
+    )
+}
+
+/// Find the next FM or MFM address mark from bitcell `from` up to `to`. Returns the mark and the
+/// bitcell after it.
+pub(crate) fn find_mark(bits: &[bool], encoding: Encoding, from: usize, to: usize) -> Option<(u8, usize)> {
+    let marks = [ID_MARK, DATA_MARK, DELETED_DATA_MARK, INDEX_MARK];
+    let fm_patterns = marks.map(|mark| match mark {
+        INDEX_MARK => fm_cells(mark, FM_INDEX_MARK_CLOCK),
+        _ => fm_cells(mark, FM_MARK_CLOCK),
+    });
+    let mut cells = 0u32;
+    for (i, bit) in bits.iter().enumerate().take(to).skip(from) {
+        cells = ((cells << 1) | *bit as u32) & 0xFFFF;
+        match encoding {
+            Encoding::Fm => {
+                if let Some(index) = fm_patterns.iter().position(|pattern| *pattern == cells) {
+                    return Some((marks[index], i + 1));
+                }
+            }
+            _ => {
+                if cells != MFM_SYNC && cells != MFM_INDEX_SYNC {
+                    continue;
+                }
+                // The mark follows the last of the sync bytes.
+                match read_bytes(bits, i + 1, 1).map(|bytes| bytes[0]) {
+                    Some(MFM_SYNC_BYTE | MFM_INDEX_SYNC_BYTE) => {}
+                    Some(mark) if marks.contains(&mark) => return Some((mark, i + 17)),
+                    _ => {}
+                }
+            }
+        }
+    }
+    None
+}
+
+/// The bytes the CRC covers ahead of a mark's bytes.
+pub(crate) fn crc_preamble(encoding: Encoding, mark: u8) -> Vec<u8> {
+    match encoding {
+        Encoding::Fm => vec![mark],
+        _ => vec![MFM_SYNC_BYTE, MFM_SYNC_BYTE, MFM_SYNC_BYTE, mark],
+    }
+}
+
+fn decode_fm_mfm(bits: &[bool], encoding: Encoding) -> Vec<DecodedSector> {
+    let mut sectors = Vec::new();
+    let mut pos = 0;
+    while let Some((mark, after)) = find_mark(bits, encoding, pos, bits.len()) {
+        pos = after;
+        if mark != ID_MARK {
+            continue;
+        }
+        let Some(id) = read_bytes(bits, after, 6)
+        else {
+            break;
+        };
+        let mut covered = crc_preamble(encoding, mark);
+        covered.extend_from_slice(&id[..4]);
+        let mut sector = DecodedSector {
+            bit_offset: after,
+            id: [id[0], id[1], id[2], id[3]],
+            id_ok: crc16(&covered) == u16::from_be_bytes([id[4], id[5]]),
+            data: None,
+            data_ok: false,
+            deleted: false,
+        };
+
+        let id_end = after + 6 * 16;
+        let data_mark = find_mark(bits, encoding, id_end, id_end + DATA_MARK_WINDOW);
+        if let Some((mark @ (DATA_MARK | DELETED_DATA_MARK), data_start)) = data_mark {
+            let size = 128usize << sector.id[3].min(MAX_SIZE_CODE);
+            if let Some(data) = read_bytes(bits, data_start, size + 2) {
+                let mut covered = crc_preamble(encoding, mark);
+                covered.extend_from_slice(&data[..size]);
+                sector.data_ok = crc16(&covered) == u16::from_be_bytes([data[size], data[size + 1]]);
+                sector.data = Some(data[..size].to_vec());
+                sector.deleted = mark == DELETED_DATA_MARK;
+                pos = data_start;
+            }
+        }
+        sectors.push(sector);
+    }
+    sectors
+}
+
+/// Find the start of the next GCR block, after a sync, from bitcell `from` up to `to`.
+fn find_gcr_sync(bits: &[bool], from: usize, to: usize) -> Option<usize> {
+    let mut ones = 0;
+    for (i, bit) in bits.iter().enumerate().take(to).skip(from) {
+        match *bit {
+            true => ones += 1,
+            false if ones >= GCR_SYNC_LEN => return Some(i),
+            false => ones = 0,
+        }
+    }
+    None
+}
+
+/// Read `count` GCR bytes starting at bitcell `pos`. None if any group isn't a valid code.
+fn read_gcr_bytes(bits: &[bool], pos: usize, count: usize) -> Option<Vec<u8>> {
+    if pos + count * 10 > bits.len() {
+        return None;
+    }
+    let nibble = |start: usize| {
+        let code = (0..5).fold(0u8, |code, bit| (code << 1) | bits[start + bit] as u8);
+        GCR_CODES.iter().position(|c| *c == code).map(|n| n as u8)
+    };
+    (0..count).map(|i| Some((nibble(pos + i * 10)? << 4) | nibble(pos + i * 10 + 5)?)).collect()
+}
+
+fn decode_gcr(bits: &[bool]) -> Vec<DecodedSector> {
+    let mut sectors = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = find_gcr_sync(bits, pos, bits.len()) {
+        pos = start + 1;
+        // Header: block ID, checksum, sector, track, two disk ID bytes and padding.
+        let Some(header) = read_gcr_bytes(bits, start, 6).filter(|header| header[0] == GCR_HEADER_BLOCK)
+        else {
+            continue;
+        };
+        let (sector_number, track) = (header[2], header[3]);
+        let mut sector = DecodedSector {
+            bit_offset: start,
+            id: [track, 0, sector_number, 1],
+            id_ok: header[1] == header[2] ^ header[3] ^ header[4] ^ header[5],
+            data: None,
+            data_ok: false,
+            deleted: false,
+        };
+
+        let header_end = start + 8 * 10;
+        if let Some(data_start) = find_gcr_sync(bits, header_end, header_end + DATA_MARK_WINDOW) {
+            let block = read_gcr_bytes(bits, data_start, GCR_SECTOR_SIZE + 2)
+                .filter(|block| block[0] == GCR_DATA_BLOCK);
+            if let Some(block) = block {
+                let data = &block[1..=GCR_SECTOR_SIZE];
+                sector.data_ok = data.iter().fold(0, |sum, byte| sum ^ byte) == block[GCR_SECTOR_SIZE + 1];
+                sector.data = Some(data.to_vec());
+                pos = data_start + 1;
+            }
+        }
+        sectors.push(sector);
+    }
+    sectors
+}
+
+/// The bitcells of track `ch` of `disk`. None if the track has no bitstream.
+pub(crate) fn track_bits(disk: &DiskImage, ch: DiskCh) -> Option<Vec<bool>> {
+    let streams = collect_streams(ch.h(), disk);
+    let stream = streams.get(ch.c() as usize)?;
+    let bits: Vec<bool> = (0..stream.len()).map(|i| stream[i]).collect();
+    (!bits.is_empty()).then_some(bits)
+}
+
+/// Decode track `ch` of `disk` again as `encoding`. None if the track has no bitstream.
+pub fn redecode(disk: &DiskImage, ch: DiskCh, encoding: Encoding) -> Option<Redecoded> {
+    let bits = track_bits(disk, ch)?;
+    let sectors = match encoding {
+        Encoding::CbmGcr => decode_gcr(&bits),
+        _ => decode_fm_mfm(&bits, encoding),
+    };
+    Some(Redecoded {
+        ch,
+        encoding,
+        bit_len: bits.len(),
+        sectors,
+    })
+}
+
+/// A panel for re-decoding the selected track with a chosen encoding. The encoding chosen for
+/// each track is remembered until the image changes, and used again when the track is selected.
+#[derive(Default)]
+pub struct TrackRedecoder {
+    track: Option<DiskCh>,
+    /// The encoding chosen for each track, by cylinder and head.
+    choices: BTreeMap<(u16, u8), Encoding>,
+    result: Option<Redecoded>,
+}
+
+impl TrackRedecoder {
+    pub fn clear(&mut self) {
+        self.track = None;
+        self.choices.clear();
+        self.result = None;
+    }
+
+    pub fn select(&mut self, ch: DiskCh) {
+        if self.track != Some(ch) {
+            self.track = Some(ch);
+            self.result = None;
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &DiskImage) {
+        egui::CollapsingHeader::new("Re-decode track").id_salt("track_redecoder").show(ui, |ui| {
+            let Some(ch) = self.track
+            else {
+                ui.weak("Select a track to decode it again as FM, MFM or GCR.");
+                return;
+            };
+            let key = (ch.c(), ch.h());
+            // Tracks with a chosen encoding are decoded again when they're selected.
+            if self.result.is_none() {
+                if let Some(encoding) = self.choices.get(&key) {
+                    self.result = redecode(disk, ch, *encoding);
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.label(format!("Track {}, decode as:", ch));
+                for encoding in Encoding::ALL {
+                    let selected = self.choices.get(&key) == Some(&encoding);
+                    if ui.selectable_label(selected, encoding.label()).clicked() {
+                        self.choices.insert(key, encoding);
+                        self.result = redecode(disk, ch, encoding);
+                    }
+                }
+                if ui
+                    .add_enabled(self.choices.contains_key(&key), egui::Button::new("As loaded"))
+                    .on_hover_text("Stop re-decoding the track; the image shows it as fluxfox decoded it.")
+                    .clicked()
+                {
+                    self.choices.remove(&key);
+                    self.result = None;
+                }
+            });
+            if self.choices.len() > 1 {
+                let choices: Vec<String> = self
+                    .choices
+                    .iter()
+                    .map(|((c, h), encoding)| format!("{}.{} {}", c, h, encoding.label()))
+                    .collect();
+                ui.weak(format!("Re-decoded: {}", choices.join(", ")));
+            }
+
+            let Some(result) = &self.result
+            else {
+                return;
+            };
+            let good = result.sectors.iter().filter(|sector| sector.good()).count();
+            ui.label(format!(
+                "{} sectors found as {} in {} bitcells, {} without errors",
+                result.sectors.len(),
+                result.encoding.label(),
+                result.bit_len,
+                good
+            ));
+            egui::Grid::new("track_redecoder_grid").striped(true).num_columns(5).show(ui, |ui| {
+                for header in ["Bitcell", "ID (C H S N)", "Size", "ID", "Data"] {
+                    ui.strong(header);
+                }
+                ui.end_row();
+                for sector in &result.sectors {
+                    let [c, h, s, n] = sector.id;
+                    ui.monospace(sector.bit_offset.to_string());
+                    ui.monospace(format!("{} {} {} {}", c, h, s, n));
+                    ui.label(sector.data.as_ref().map_or("-".to_string(), |data| data.len().to_string()));
+                    check_label(ui, sector.id_ok);
+                    match &sector.data {
+                        Some(_) if sector.deleted => check_label(ui, sector.data_ok).on_hover_text("Deleted data mark"),
+                        Some(_) => check_label(ui, sector.data_ok),
+                        None => ui.weak("no data mark"),
+                    };
+                    ui.end_row();
+                }
+            });
+
+            ui.weak("Read-only: the image keeps its own decoding of the track.");
+        });
+    }
+}
+
+fn check_label(ui: &mut egui::Ui, ok: bool) -> egui::Response {
+    match ok {
+        true => ui.label("ok"),
+        false => ui.colored_label(ui.visuals().error_fg_color, "CRC"),
+    }
+}
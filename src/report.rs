@@ -0,0 +1,103 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use std::collections::BTreeMap;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::entropy::{EntropyClass, EntropyMap};
+use crate::analysis::registry::CheckResult;
+use crate::annotations::{Annotation, Annotations};
+use crate::history::History;
+use crate::sidecar::ImageMetadata;
+
+/// A JSON summary of a loaded disk image, its analysis results and any user-supplied metadata.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct ImageReport {
+    pub name: String,
+    pub source_size: usize,
+    pub resolution: String,
+    pub geometry: String,
+    pub entropy: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ImageMetadata>,
+    #[serde(skip_serializing_if = "History::is_empty")]
+    pub history: History,
+    /// The results of the last analysis run, grouped by check.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckResult>,
+}
+
+impl ImageReport {
+    pub fn new(name: &str, source_size: usize, disk: &DiskImage) -> Self {
+        Self {
+            name: name.to_string(),
+            source_size,
+            resolution: format!("{:?}", disk.resolution()),
+            geometry: format!("{:?}", disk.geometry()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_entropy(mut self, entropy: Option<&EntropyMap>) -> Self {
+        if let Some(entropy) = entropy {
+            let counts = entropy.class_counts();
+            self.entropy = EntropyClass::ALL
+                .iter()
+                .map(|class| (class.label().to_string(), counts.get(class).copied().unwrap_or(0)))
+                .collect();
+        }
+        self
+    }
+
+    pub fn with_annotations(mut self, annotations: &Annotations) -> Self {
+        self.annotations = annotations.items.clone();
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: &ImageMetadata) -> Self {
+        if !metadata.is_empty() {
+            self.metadata = Some(metadata.clone());
+        }
+        self
+    }
+
+    pub fn with_history(mut self, history: &History) -> Self {
+        self.history = history.clone();
+        self
+    }
+
+    pub fn with_checks(mut self, checks: &[CheckResult]) -> Self {
+        self.checks = checks.to_vec();
+        self
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
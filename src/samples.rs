@@ -0,0 +1,123 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Sample disk images, fetched on demand from the samples directory next to the app so they
+//! don't add to the download for users who have images of their own.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::assets;
+
+pub const SAMPLE_DIR: &str = "./samples/";
+
+pub struct Sample {
+    pub name: &'static str,
+    pub file: &'static str,
+    pub description: &'static str,
+}
+
+pub const SAMPLES: &[Sample] = &[
+    Sample {
+        name: "DOS 360K",
+        file: "dos_360k.imd",
+        description: "A standard double-density DOS floppy with a few files, one of them fragmented.",
+    },
+    Sample {
+        name: "Copy-protected",
+        file: "protected_360k.imd",
+        description: "A DOS floppy with a key track on cylinder 39: a duplicate sector ID with a bad CRC, \
+                      and 1024-byte sectors with odd IDs and a deleted data mark.",
+    },
+    Sample {
+        name: "Damaged",
+        file: "damaged_360k.imd",
+        description: "A worn DOS floppy with bad CRCs in the FAT, root directory and a file, a sector \
+                      with no data and a track missing a sector.",
+    },
+];
+
+/// Fetches samples in the background. A fetched sample is handed back as a dropped file, to be
+/// loaded through the same path as any other.
+#[derive(Default)]
+pub struct SampleLoader {
+    loading: Rc<RefCell<Option<&'static str>>>,
+    loaded: Rc<RefCell<Vec<(&'static str, anyhow::Result<egui::DroppedFile>)>>>,
+}
+
+impl SampleLoader {
+    /// The name of the sample being fetched, if any.
+    pub fn loading(&self) -> Option<&'static str> {
+        *self.loading.borrow()
+    }
+
+    pub fn load(&self, ctx: &egui::Context, sample: &'static Sample) {
+        if self.loading().is_some() {
+            return;
+        }
+        *self.loading.borrow_mut() = Some(sample.name);
+        let (loading, loaded, ctx) = (self.loading.clone(), self.loaded.clone(), ctx.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let url = format!("{}{}", SAMPLE_DIR, sample.file);
+            let result = assets::fetch_bytes(&url).await.map(|bytes| egui::DroppedFile {
+                name: sample.file.to_string(),
+                bytes: Some(Arc::from(bytes)),
+                ..Default::default()
+            });
+            *loading.borrow_mut() = None;
+            loaded.borrow_mut().push((sample.name, result));
+            ctx.request_repaint();
+        });
+    }
+
+    /// Samples which finished fetching since the last call, by name.
+    pub fn take_loaded(&self) -> Vec<(&'static str, anyhow::Result<egui::DroppedFile>)> {
+        std::mem::take(&mut *self.loaded.borrow_mut())
+    }
+}
+
+/// A button for each sample, with its description on hover. Returns true if one was clicked.
+pub fn show_buttons(ui: &mut egui::Ui, loader: &SampleLoader) -> bool {
+    let mut clicked = false;
+    for sample in SAMPLES {
+        let busy = loader.loading().is_some();
+        let label = match loader.loading() {
+            Some(name) if name == sample.name => format!("Loading {}...", sample.name),
+            _ => sample.name.to_string(),
+        };
+        if ui
+            .add_enabled(!busy, egui::Button::new(label))
+            .on_hover_text(sample.description)
+            .clicked()
+        {
+            loader.load(ui.ctx(), sample);
+            clicked = true;
+        }
+    }
+    clicked
+}
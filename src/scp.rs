@@ -0,0 +1,319 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! SuperCard Pro (SCP) header and track header details. The loader decodes the flux but has
+//! no use for how the image was captured, so the headers are read from the file directly.
+
+use fluxfox::DiskCh;
+
+const SCP_MAGIC: &[u8] = b"SCP";
+const HEADER_LEN: usize = 0x10;
+/// Extended mode images have 0x70 bytes of extra header before the track table.
+const EXTENDED_HEADER_LEN: usize = 0x80;
+const TRACK_TABLE_ENTRIES: usize = 168;
+/// The index times in track headers count 25ns ticks, whatever the capture resolution.
+const INDEX_TICK_NS: f64 = 25.0;
+/// Flux intervals at least this long are kept as possible dropouts. That's 16 bit cells at high
+/// density, and fewer at lower densities, so no dropout the analysis reports is left out.
+const LONG_INTERVAL_NS: f64 = 16_000.0;
+
+const FLAG_INDEX: u8 = 0x01;
+const FLAG_96TPI: u8 = 0x02;
+const FLAG_360RPM: u8 = 0x04;
+const FLAG_NORMALIZED: u8 = 0x08;
+const FLAG_READ_WRITE: u8 = 0x10;
+const FLAG_FOOTER: u8 = 0x20;
+const FLAG_EXTENDED: u8 = 0x40;
+const FLAG_OTHER_CREATOR: u8 = 0x80;
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScpRevolution {
+    /// The time from index to index, in nanoseconds.
+    pub duration_ns: f64,
+    pub flux_count: u32,
+}
+
+impl ScpRevolution {
+    pub fn rpm(&self) -> f64 {
+        60.0e9 / self.duration_ns.max(1.0)
+    }
+}
+
+/// A flux interval of at least `LONG_INTERVAL_NS`, with no transitions in it.
+#[derive(Copy, Clone, Debug)]
+pub struct LongInterval {
+    /// The time from the index to the start of the interval, in nanoseconds.
+    pub start_ns: f64,
+    pub duration_ns: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ScpTrack {
+    pub number: u8,
+    pub ch: DiskCh,
+    pub revolutions: Vec<ScpRevolution>,
+    /// The long flux intervals of the first revolution.
+    pub long_intervals: Vec<LongInterval>,
+}
+
+/// Find the intervals of at least `LONG_INTERVAL_NS` in a revolution's flux data: `count`
+/// big-endian tick counts, where 0 adds 65536 ticks to the next.
+fn long_intervals(data: &[u8], count: usize, tick_ns: f64) -> Vec<LongInterval> {
+    let mut found = Vec::new();
+    let mut time_ns = 0.0;
+    let mut ticks = 0u64;
+    for pair in data.chunks_exact(2).take(count) {
+        let value = u16::from_be_bytes([pair[0], pair[1]]);
+        if value == 0 {
+            ticks += 0x10000;
+            continue;
+        }
+        let duration_ns = (ticks + value as u64) as f64 * tick_ns;
+        if duration_ns >= LONG_INTERVAL_NS {
+            found.push(LongInterval {
+                start_ns: time_ns,
+                duration_ns,
+            });
+        }
+        time_ns += duration_ns;
+        ticks = 0;
+    }
+    found
+}
+
+#[derive(Clone, Debug)]
+pub struct ScpInfo {
+    pub version: u8,
+    pub disk_type: u8,
+    pub revolutions: u8,
+    pub start_track: u8,
+    pub end_track: u8,
+    pub flags: u8,
+    /// The bit cell width in bits; 0 in the file means 16.
+    pub cell_width: u8,
+    /// 0 for both heads, 1 for head 0 only, 2 for head 1 only.
+    pub heads: u8,
+    /// The capture resolution in nanoseconds.
+    pub resolution_ns: u32,
+    pub tracks: Vec<ScpTrack>,
+}
+
+impl ScpInfo {
+    /// Read the headers of an SCP image, or return None if `data` isn't one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if !data.starts_with(SCP_MAGIC) || data.len() < HEADER_LEN {
+            return None;
+        }
+        let flags = data[0x08];
+        let table = if flags & FLAG_EXTENDED != 0 { EXTENDED_HEADER_LEN } else { HEADER_LEN };
+        let revolutions = data[0x05];
+        let (start_track, end_track) = (data[0x06], data[0x07]);
+        let resolution_ns = 25 * (data[0x0B] as u32 + 1);
+        let cell_width = if data[0x09] == 0 { 16 } else { data[0x09] };
+
+        let mut tracks = Vec::new();
+        for number in start_track..=end_track.min(TRACK_TABLE_ENTRIES as u8 - 1) {
+            let offset = match u32_at(data, table + number as usize * 4) {
+                Some(0) | None => continue,
+                Some(offset) => offset as usize,
+            };
+            if data.get(offset..offset + 3) != Some(b"TRK") {
+                log::warn!("SCP track {} has no track header at offset {:#X}", number, offset);
+                continue;
+            }
+            let revolutions: Vec<ScpRevolution> = (0..revolutions as usize)
+                .map_while(|rev| {
+                    let entry = offset + 4 + rev * 12;
+                    Some(ScpRevolution {
+                        duration_ns: u32_at(data, entry)? as f64 * INDEX_TICK_NS,
+                        flux_count: u32_at(data, entry + 4)?,
+                    })
+                })
+                .collect();
+            // The first revolution's flux data, at an offset from the track header. Only 16-bit
+            // cells are read, as nearly every capture uses them.
+            let long_intervals = match (revolutions.first(), u32_at(data, offset + 12)) {
+                (Some(first), Some(flux_offset)) if cell_width == 16 => {
+                    let flux = data.get(offset + flux_offset as usize..).unwrap_or_default();
+                    long_intervals(flux, first.flux_count as usize, resolution_ns as f64)
+                }
+                _ => Vec::new(),
+            };
+            tracks.push(ScpTrack {
+                number,
+                ch: DiskCh::new(number as u16 / 2, number % 2),
+                revolutions,
+                long_intervals,
+            });
+        }
+
+        Some(Self {
+            version: data[0x03],
+            disk_type: data[0x04],
+            revolutions,
+            start_track,
+            end_track,
+            flags,
+            cell_width,
+            heads: data[0x0A],
+            resolution_ns,
+            tracks,
+        })
+    }
+
+    pub fn version_label(&self) -> String {
+        if self.flags & FLAG_OTHER_CREATOR != 0 && self.version == 0 {
+            return "unspecified".to_string();
+        }
+        format!("{}.{}", self.version >> 4, self.version & 0x0F)
+    }
+
+    pub fn manufacturer(&self) -> &'static str {
+        match self.disk_type >> 4 {
+            0x0 => "Commodore",
+            0x1 => "Atari",
+            0x2 => "Apple",
+            0x3 => "PC",
+            0x4 => "Tandy",
+            0x5 => "Texas Instruments",
+            0x6 => "Roland",
+            0x8 => "Other",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn heads_label(&self) -> &'static str {
+        match self.heads {
+            0 => "both",
+            1 => "head 0 only",
+            2 => "head 1 only",
+            _ => "unknown",
+        }
+    }
+
+    /// The set flags, by name.
+    pub fn flag_labels(&self) -> Vec<&'static str> {
+        [
+            (FLAG_INDEX, "index aligned"),
+            (FLAG_NORMALIZED, "normalized"),
+            (FLAG_READ_WRITE, "read/write"),
+            (FLAG_FOOTER, "footer"),
+            (FLAG_EXTENDED, "extended"),
+            (FLAG_OTHER_CREATOR, "not made by SCP hardware"),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.flags & flag != 0)
+        .map(|(_, label)| *label)
+        .collect()
+    }
+
+    pub fn track(&self, ch: DiskCh) -> Option<&ScpTrack> {
+        self.tracks.iter().find(|track| track.ch == ch)
+    }
+
+    /// Show the header details, for the image info panel.
+    pub fn show_header(&self, ui: &mut egui::Ui) {
+        ui.label(format!(
+            "SCP v{}: {} disk type {:#04X}, {} revolutions per track, tracks {}-{}, heads: {}",
+            self.version_label(),
+            self.manufacturer(),
+            self.disk_type,
+            self.revolutions,
+            self.start_track,
+            self.end_track,
+            self.heads_label()
+        ));
+        let flags = self.flag_labels();
+        ui.label(format!(
+            "Capture resolution {}ns, {}-bit cells, {} TPI, {} RPM drive{}",
+            self.resolution_ns,
+            self.cell_width,
+            if self.flags & FLAG_96TPI != 0 { 96 } else { 48 },
+            if self.flags & FLAG_360RPM != 0 { 360 } else { 300 },
+            if flags.is_empty() { String::new() } else { format!("; {}", flags.join(", ")) }
+        ));
+    }
+
+    /// Show the revolutions captured for each track, highlighting the `selected` one. Returns
+    /// a track if one was clicked.
+    pub fn show(&self, ui: &mut egui::Ui, selected: Option<DiskCh>) -> Option<DiskCh> {
+        let mut clicked = None;
+        let title = format!("SCP tracks ({})", self.tracks.len());
+        egui::CollapsingHeader::new(title).id_salt("scp_tracks").show(ui, |ui| {
+            if let Some(track) = selected.and_then(|ch| self.track(ch)) {
+                ui.label(format!("Selected: SCP track {} ({})", track.number, track.ch));
+                egui::Grid::new("scp_selected_grid").striped(true).num_columns(4).show(ui, |ui| {
+                    ui.strong("Revolution");
+                    ui.strong("Duration");
+                    ui.strong("RPM");
+                    ui.strong("Flux transitions");
+                    ui.end_row();
+                    for (i, rev) in track.revolutions.iter().enumerate() {
+                        ui.label((i + 1).to_string());
+                        ui.label(format!("{:.3} ms", rev.duration_ns / 1.0e6));
+                        ui.label(format!("{:.2}", rev.rpm()));
+                        ui.label(rev.flux_count.to_string());
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+            }
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Body);
+            egui::ScrollArea::vertical()
+                .id_salt("scp_tracks_table")
+                .max_height(240.0)
+                .show_rows(ui, row_height, self.tracks.len(), |ui, rows| {
+                    egui::Grid::new("scp_tracks_grid").striped(true).num_columns(4).show(ui, |ui| {
+                        ui.strong("SCP track");
+                        ui.strong("Track");
+                        ui.strong("Mean RPM");
+                        ui.strong("Flux transitions");
+                        ui.end_row();
+                        for track in self.tracks[rows].iter() {
+                            ui.label(track.number.to_string());
+                            if ui.selectable_label(selected == Some(track.ch), track.ch.to_string()).clicked() {
+                                clicked = Some(track.ch);
+                            }
+                            let count = track.revolutions.len().max(1) as f64;
+                            let rpm = track.revolutions.iter().map(|rev| rev.rpm()).sum::<f64>() / count;
+                            let flux: f64 = track.revolutions.iter().map(|rev| rev.flux_count as f64).sum();
+                            ui.label(format!("{:.2}", rpm));
+                            ui.label(format!("{:.0}", flux / count));
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+        clicked
+    }
+}
@@ -0,0 +1,203 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Copy and paste of sector data, within an image or from one image to another.
+//!
+//! This is the usual repair for a bad sector when a second dump of the same disk has a good
+//! copy of it: copy the sector from the comparison image and paste it over the bad one. A range
+//! of sectors is copied in logical order, so it can cross tracks. Pasting goes through the same
+//! path as other repairs, which overwrites each sector's data in place with a freshly computed
+//! CRC.
+
+use anyhow::{anyhow, bail, Error};
+use fluxfox::DiskImage;
+
+use crate::analysis::geometry::{LayoutSummary, StandardGeometry};
+use crate::analysis::{self, SectorKey};
+
+/// The most sectors that can be copied at once: a 2.88M track's worth.
+pub const MAX_CLIP_SECTORS: usize = 36;
+
+pub struct CopiedSector {
+    pub key: SectorKey,
+    pub data: Vec<u8>,
+    /// The sector was copied with a bad CRC, so its data may not be what was written.
+    pub crc_error: bool,
+}
+
+/// Sector data copied from an image, waiting to be pasted.
+pub struct SectorClip {
+    /// The name of the image the sectors were copied from.
+    pub source: String,
+    pub sectors: Vec<CopiedSector>,
+}
+
+fn geometry(disk: &DiskImage) -> Result<&'static StandardGeometry, Error> {
+    LayoutSummary::from_disk(disk)
+        .standard_geometry()
+        .ok_or_else(|| anyhow!("The image isn't in a standard geometry."))
+}
+
+impl SectorClip {
+    /// Copy `count` sectors of `disk` in logical order, starting with `start`.
+    pub fn copy(disk: &mut DiskImage, source: &str, start: SectorKey, count: usize) -> Result<Self, Error> {
+        let keys = match count {
+            0 | 1 => vec![start],
+            _ => {
+                let geometry = geometry(disk)?;
+                let first = geometry
+                    .lba(start)
+                    .ok_or_else(|| anyhow!("Sector {} is outside the {} geometry.", start, geometry.name))?;
+                (first..first + count.min(MAX_CLIP_SECTORS)).map_while(|lba| geometry.key(lba)).collect()
+            }
+        };
+
+        let sector_map = disk.get_sector_map();
+        let mut ids = Vec::new();
+        for key in &keys {
+            let entry = sector_map
+                .get(key.h as usize)
+                .and_then(|cylinders| cylinders.get(key.c as usize))
+                .and_then(|entries| entries.iter().find(|entry| entry.chsn.s() == key.s))
+                .ok_or_else(|| anyhow!("There's no sector {} in {}.", key, source))?;
+            ids.push((*key, entry.chsn));
+        }
+
+        let mut sectors = Vec::new();
+        for (key, chsn) in ids {
+            let read = analysis::read_sector(disk, key.ch(), chsn)
+                .ok_or_else(|| anyhow!("Sector {} of {} couldn't be read.", key, source))?;
+            sectors.push(CopiedSector {
+                key,
+                data: read.data,
+                crc_error: read.data_crc_error,
+            });
+        }
+        Ok(Self {
+            source: source.to_string(),
+            sectors,
+        })
+    }
+
+    /// The sector writes that paste the clip into `disk`, with its first sector at `target`. A
+    /// single sector can be pasted anywhere; a range needs a standard geometry to be laid out in.
+    pub fn paste(&self, disk: &DiskImage, target: SectorKey) -> Result<Vec<(SectorKey, Vec<u8>)>, Error> {
+        if let [sector] = self.sectors.as_slice() {
+            return Ok(vec![(target, sector.data.clone())]);
+        }
+        let geometry = geometry(disk)?;
+        let first = geometry
+            .lba(target)
+            .ok_or_else(|| anyhow!("Sector {} is outside the {} geometry.", target, geometry.name))?;
+        let mut writes = Vec::new();
+        for (i, sector) in self.sectors.iter().enumerate() {
+            let Some(key) = geometry.key(first + i)
+            else {
+                bail!("{} sectors don't fit between {} and the end of the disk.", self.sectors.len(), target);
+            };
+            if sector.data.len() != geometry.sector_size {
+                bail!(
+                    "Sector {} holds {} bytes, but sectors of this image hold {}.",
+                    sector.key,
+                    sector.data.len(),
+                    geometry.sector_size
+                );
+            }
+            writes.push((key, sector.data.clone()));
+        }
+        Ok(writes)
+    }
+
+    pub fn describe(&self) -> String {
+        match (self.sectors.first(), self.sectors.last()) {
+            (Some(first), Some(last)) if self.sectors.len() > 1 => {
+                format!("{} sectors ({} to {}) of {}", self.sectors.len(), first.key, last.key, self.source)
+            }
+            (Some(first), _) => format!("sector {} of {}", first.key, self.source),
+            _ => format!("nothing from {}", self.source),
+        }
+    }
+
+    pub fn crc_errors(&self) -> usize {
+        self.sectors.iter().filter(|sector| sector.crc_error).count()
+    }
+}
+
+pub enum ClipAction {
+    /// Copy the given number of sectors from the loaded image.
+    Copy(usize),
+    /// Copy the same sectors from the comparison image.
+    CopyFromComparison(usize),
+    Paste,
+}
+
+/// The copy and paste controls shown with the hex viewer.
+pub struct SectorClipboard {
+    pub clip: Option<SectorClip>,
+    count: usize,
+}
+
+impl Default for SectorClipboard {
+    fn default() -> Self {
+        Self { clip: None, count: 1 }
+    }
+}
+
+impl SectorClipboard {
+    /// Show the controls for the selected sector. `comparison` is the name of the image being
+    /// compared with, if one is loaded.
+    pub fn show(&mut self, ui: &mut egui::Ui, comparison: Option<&str>) -> Option<ClipAction> {
+        let mut action = None;
+        ui.horizontal(|ui| {
+            if ui.button("Copy").on_hover_text("Copy sector data, to paste over other sectors.").clicked() {
+                action = Some(ClipAction::Copy(self.count));
+            }
+            ui.add(egui::DragValue::new(&mut self.count).range(1..=MAX_CLIP_SECTORS));
+            ui.label(if self.count == 1 { "sector" } else { "sectors" });
+            if let Some(name) = comparison {
+                if ui
+                    .button(format!("Copy from {}", name))
+                    .on_hover_text("Copy the same sectors from the comparison image, such as another dump of the disk.")
+                    .clicked()
+                {
+                    action = Some(ClipAction::CopyFromComparison(self.count));
+                }
+            }
+            if let Some(clip) = &self.clip {
+                let hover = format!("Paste {} over this sector onwards.", clip.describe());
+                if ui.button("Paste here").on_hover_text(hover).clicked() {
+                    action = Some(ClipAction::Paste);
+                }
+                if clip.crc_errors() > 0 {
+                    ui.colored_label(ui.visuals().warn_fg_color, "⚠")
+                        .on_hover_text(format!("{} of the copied sectors had bad CRCs.", clip.crc_errors()));
+                }
+            }
+        });
+        action
+    }
+}
@@ -0,0 +1,230 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A list of every sector on the disk, each drawn as a small strip of its contents so that
+//! structure (boot code, FAT tables, text, fill and compressed data) can be skimmed at a glance.
+
+use egui::{Color32, Rect, Sense};
+use fluxfox::DiskImage;
+
+use crate::analysis::entropy::{shannon_entropy, EntropyClass};
+use crate::analysis::{read_all_sectors, SectorKey};
+
+/// The number of cells each sector's data is divided into.
+pub const SECTOR_STRIP_CELLS: usize = 64;
+pub const SECTOR_STRIP_WIDTH: f32 = 256.0;
+/// The narrowest a strip is drawn when sizes are mixed, so small sectors stay clickable.
+pub const SECTOR_STRIP_MIN_WIDTH: f32 = 16.0;
+pub const SECTOR_LIST_MAX_HEIGHT: f32 = 320.0;
+
+/// What each cell of a sector's strip shows.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum StripMode {
+    /// The mean byte value of the cell, from black to white.
+    #[default]
+    Bytes,
+    /// The entropy of the cell, colored by entropy class.
+    Entropy,
+}
+
+impl StripMode {
+    pub const ALL: [StripMode; 2] = [StripMode::Bytes, StripMode::Entropy];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StripMode::Bytes => "Byte values",
+            StripMode::Entropy => "Entropy",
+        }
+    }
+}
+
+/// One sector's row in the list, with its data reduced to strip cells.
+pub struct SectorRow {
+    pub key: SectorKey,
+    pub size: usize,
+    pub entropy: f32,
+    pub data_crc_error: bool,
+    pub deleted: bool,
+    means: Vec<u8>,
+    entropies: Vec<f32>,
+}
+
+impl SectorRow {
+    fn new(key: SectorKey, data: &[u8], data_crc_error: bool, deleted: bool) -> Self {
+        let cell_len = data.len().div_ceil(SECTOR_STRIP_CELLS).max(1);
+        let cells = data.chunks(cell_len);
+        Self {
+            key,
+            size: data.len(),
+            entropy: shannon_entropy(data),
+            data_crc_error,
+            deleted,
+            means: cells
+                .clone()
+                .map(|cell| (cell.iter().map(|b| *b as usize).sum::<usize>() / cell.len()) as u8)
+                .collect(),
+            // Entropy of so few bytes is low at best, so scale it to the most a cell could have.
+            entropies: cells
+                .map(|cell| shannon_entropy(cell) * 8.0 / (cell.len() as f32).log2().clamp(1.0, 8.0))
+                .collect(),
+        }
+    }
+
+    fn cell_color(&self, mode: StripMode, cell: usize) -> Color32 {
+        match mode {
+            StripMode::Bytes => Color32::from_gray(self.means[cell]),
+            StripMode::Entropy => {
+                let [r, g, b, _] = EntropyClass::from_entropy(self.entropies[cell]).rgba();
+                Color32::from_rgb(r, g, b)
+            }
+        }
+    }
+
+    /// Draw the strip, one cell per slice of the sector's data. The strip's width is in
+    /// proportion to the sector's size against the largest on the disk, so the mixed sizes of
+    /// formats like XDF read as such rather than as equal sectors.
+    fn show_strip(&self, ui: &mut egui::Ui, mode: StripMode, max_size: usize) -> egui::Response {
+        let height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let width = (SECTOR_STRIP_WIDTH * self.size as f32 / max_size.max(1) as f32)
+            .clamp(SECTOR_STRIP_MIN_WIDTH, SECTOR_STRIP_WIDTH);
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(width, height), Sense::click());
+        let painter = ui.painter_at(rect);
+        let cell_width = width / self.means.len().max(1) as f32;
+        for cell in 0..self.means.len() {
+            let x = rect.left() + cell as f32 * cell_width;
+            let cell_rect = Rect::from_min_max(egui::pos2(x, rect.top()), egui::pos2(x + cell_width, rect.bottom()));
+            painter.rect_filled(cell_rect, 0.0, self.cell_color(mode, cell));
+        }
+        response
+    }
+}
+
+/// A panel listing every sector with a strip of its contents. Sectors are read the first time
+/// the panel is opened, since most sessions never look at it.
+#[derive(Default)]
+pub struct SectorList {
+    rows: Option<Vec<SectorRow>>,
+    mode: StripMode,
+    selected: Option<SectorKey>,
+    /// Set when the selection was made elsewhere, so the list scrolls to it once.
+    reveal: bool,
+}
+
+impl SectorList {
+    pub fn clear(&mut self) {
+        self.rows = None;
+        self.selected = None;
+    }
+
+    /// Highlight a sector selected elsewhere, scrolling to it the next time the list is shown.
+    pub fn select(&mut self, key: Option<SectorKey>) {
+        self.reveal = key.is_some() && key != self.selected;
+        self.selected = key;
+    }
+
+    fn load(&mut self, disk: &mut DiskImage) {
+        let mut reads = read_all_sectors(disk);
+        reads.sort_by_key(|read| read.key);
+        let rows = reads
+            .iter()
+            .map(|read| SectorRow::new(read.key, &read.data, read.data_crc_error, read.deleted))
+            .collect::<Vec<_>>();
+        log::debug!("SectorList::load(): read {} sectors", rows.len());
+        self.rows = Some(rows);
+    }
+
+    /// Show the panel. Returns a sector to select if the user clicked one.
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &mut DiskImage) -> Option<SectorKey> {
+        let mut select = None;
+        egui::CollapsingHeader::new("Sectors").id_salt("sector_list").show(ui, |ui| {
+            if self.rows.is_none() {
+                self.load(disk);
+            }
+            let Some(rows) = &self.rows
+            else {
+                return;
+            };
+
+            let max_size = rows.iter().map(|row| row.size).max().unwrap_or(0);
+            let mixed = rows.iter().any(|row| row.size != max_size);
+            ui.horizontal(|ui| {
+                ui.label(format!("{} sectors", rows.len()));
+                if mixed {
+                    ui.weak("of mixed sizes, drawn to scale");
+                }
+                for mode in StripMode::ALL {
+                    ui.radio_value(&mut self.mode, mode, mode.label());
+                }
+            });
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Monospace) + ui.spacing().item_spacing.y;
+            let mut scroll = egui::ScrollArea::vertical()
+                .id_salt("sector_list_rows")
+                .max_height(SECTOR_LIST_MAX_HEIGHT);
+            if std::mem::take(&mut self.reveal) {
+                if let Some(index) = rows.iter().position(|row| Some(row.key) == self.selected) {
+                    scroll = scroll.vertical_scroll_offset(index as f32 * row_height);
+                }
+            }
+            scroll.show_rows(ui, row_height, rows.len(), |ui, range| {
+                for row in &rows[range] {
+                    ui.horizontal(|ui| {
+                        let mut key = egui::RichText::new(row.key.to_string()).monospace();
+                        if Some(row.key) == self.selected {
+                            key = key.strong();
+                        }
+                        if ui.link(key).clicked() {
+                            select = Some(row.key);
+                        }
+                        if mixed {
+                            ui.monospace(format!("{:>5}", row.size));
+                        }
+                        let strip = row.show_strip(ui, self.mode, max_size).on_hover_text(format!(
+                            "{} bytes, {:.2} bits/byte ({})",
+                            row.size,
+                            row.entropy,
+                            EntropyClass::from_entropy(row.entropy).label()
+                        ));
+                        if strip.clicked() {
+                            select = Some(row.key);
+                        }
+                        if row.data_crc_error {
+                            ui.colored_label(Color32::LIGHT_RED, "CRC");
+                        }
+                        if row.deleted {
+                            ui.weak("deleted");
+                        }
+                    });
+                }
+            });
+        });
+        if select.is_some() {
+            self.selected = select;
+        }
+        select
+    }
+}
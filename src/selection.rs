@@ -0,0 +1,102 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! The selection bus. Panels publish what the user selected instead of updating each other, and
+//! the app delivers each selection to every panel that follows it once per frame, so the
+//! visualization, sector list, hex viewer and FAT browser stay in step whichever one the user
+//! clicked in.
+
+use std::collections::VecDeque;
+
+use fluxfox::DiskCh;
+
+use crate::analysis::SectorKey;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selection {
+    Track(DiskCh),
+    /// A point on a track, and the sector there, if any.
+    Position { ch: DiskCh, bit_offset: usize },
+    Sector(SectorKey),
+    /// A file in the FAT browser, by path.
+    File(String),
+}
+
+/// Where a selection was made. A selection isn't delivered back to where it came from, which
+/// already shows it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SelectionSource {
+    Viz,
+    HexViewer,
+    FatBrowser,
+    /// A registered panel. Panels are given every selection, and ignore their own.
+    Panel,
+    /// Anything that selects but doesn't follow the selection, such as bookmarks.
+    Other,
+}
+
+#[derive(Clone, Debug)]
+pub struct SelectionEvent {
+    pub selection: Selection,
+    pub source: SelectionSource,
+}
+
+impl SelectionEvent {
+    /// Whether `target` should be given this selection.
+    pub fn is_for(&self, target: SelectionSource) -> bool {
+        self.source != target
+    }
+
+    /// Whether the visualization should scroll to this selection. It doesn't when the user
+    /// clicked in it, since the selection is already under the pointer.
+    pub fn focus(&self) -> bool {
+        self.source != SelectionSource::Viz
+    }
+}
+
+#[derive(Default)]
+pub struct SelectionBus {
+    pending: VecDeque<SelectionEvent>,
+}
+
+impl SelectionBus {
+    pub fn publish(&mut self, source: SelectionSource, selection: Selection) {
+        log::trace!("SelectionBus::publish(): {:?} from {:?}", selection, source);
+        // Only the latest selection from a source matters, e.g. when dragging through a list.
+        self.pending.retain(|event| event.source != source);
+        self.pending.push_back(SelectionEvent { selection, source });
+    }
+
+    /// Take the selections published since the last call, in the order they were made.
+    pub fn drain(&mut self) -> Vec<SelectionEvent> {
+        self.pending.drain(..).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
@@ -0,0 +1,278 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A record of every image loaded during the session, summarized in a sortable dashboard so a
+//! batch of dumps can be triaged one drop after another.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use egui::{ColorImage, TextureHandle, TextureOptions};
+
+/// The size of the visualization thumbnails shown for each image.
+pub const THUMBNAIL_SIZE: u32 = 128;
+/// The size thumbnails are shown at in the dashboard; the full thumbnail shows on hover.
+pub const THUMBNAIL_ROW_SIZE: f32 = 32.0;
+
+#[derive(Clone, Debug)]
+pub struct SessionEntry {
+    pub name: String,
+    pub size: usize,
+    /// The container format, from the file extension.
+    pub format: String,
+    /// The decoded resolution and geometry, or None if the image failed to load.
+    pub resolution: Option<String>,
+    pub sectors: usize,
+    pub crc_errors: usize,
+    pub sha1: String,
+    /// A hash of the decoded sector data, independent of the container.
+    pub sector_hash: Option<String>,
+    /// A small render of the visualization, to recognize the image by.
+    pub thumbnail: Option<ColorImage>,
+}
+
+impl SessionEntry {
+    pub fn new(name: &str, size: usize, sha1: &str) -> Self {
+        let format = match name.rsplit_once('.') {
+            Some((_, ext)) => ext.to_uppercase(),
+            None => "-".to_string(),
+        };
+        Self {
+            name: name.to_string(),
+            size,
+            format,
+            resolution: None,
+            sectors: 0,
+            crc_errors: 0,
+            sha1: sha1.to_string(),
+            sector_hash: None,
+            thumbnail: None,
+        }
+    }
+
+    pub fn error_rate(&self) -> f32 {
+        self.crc_errors as f32 / self.sectors.max(1) as f32
+    }
+}
+
+/// Other images in the session that duplicate an image.
+#[derive(Clone, Debug, Default)]
+pub struct Duplicates<'a> {
+    /// Images with the same file hash.
+    pub same_file: Vec<&'a str>,
+    /// Images in a different file that decode to the same sector data.
+    pub same_data: Vec<&'a str>,
+}
+
+impl Duplicates<'_> {
+    pub fn count(&self) -> usize {
+        self.same_file.len() + self.same_data.len()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SortColumn {
+    #[default]
+    Loaded,
+    Name,
+    Format,
+    Size,
+    ErrorRate,
+    Duplicates,
+}
+
+#[derive(Default)]
+pub struct Session {
+    pub entries: Vec<SessionEntry>,
+    sort: SortColumn,
+    descending: bool,
+    /// Thumbnail textures by SHA-1, uploaded the first time each entry is shown.
+    textures: HashMap<String, TextureHandle>,
+}
+
+impl Session {
+    /// Record a loaded image. Loading the same file again replaces its earlier entry.
+    pub fn record(&mut self, entry: SessionEntry) {
+        self.entries
+            .retain(|existing| existing.name != entry.name || existing.sha1 != entry.sha1);
+        self.textures.remove(&entry.sha1);
+        self.entries.push(entry);
+    }
+
+    /// Drop the thumbnail textures, which belonged to a graphics context that was lost. They're
+    /// uploaded again from the entries' images when next shown.
+    pub fn forget_textures(&mut self) {
+        self.textures.clear();
+    }
+
+    /// Attach a thumbnail rendered after the entries for `sha1` were recorded.
+    pub fn set_thumbnail(&mut self, sha1: &str, thumbnail: ColorImage) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.sha1 == sha1) {
+            entry.thumbnail = Some(thumbnail.clone());
+        }
+        self.textures.remove(sha1);
+    }
+
+    pub fn duplicates(&self, entry: &SessionEntry) -> Duplicates<'_> {
+        let mut duplicates = Duplicates::default();
+        for other in self.entries.iter().filter(|other| !std::ptr::eq(*other, entry)) {
+            if other.sha1 == entry.sha1 {
+                duplicates.same_file.push(&other.name);
+            }
+            else if other.sector_hash.is_some() && other.sector_hash == entry.sector_hash {
+                duplicates.same_data.push(&other.name);
+            }
+        }
+        duplicates
+    }
+
+    /// Find the session's entry for a loaded image.
+    pub fn entry(&self, name: &str, sha1: &str) -> Option<&SessionEntry> {
+        self.entries.iter().find(|entry| entry.name == name && entry.sha1 == sha1)
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    fn sorted(&self) -> Vec<&SessionEntry> {
+        let mut entries: Vec<&SessionEntry> = self.entries.iter().collect();
+        let compare = |a: &&SessionEntry, b: &&SessionEntry| -> Ordering {
+            match self.sort {
+                SortColumn::Loaded => Ordering::Equal,
+                SortColumn::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                SortColumn::Format => a.format.cmp(&b.format),
+                SortColumn::Size => a.size.cmp(&b.size),
+                SortColumn::ErrorRate => a.error_rate().total_cmp(&b.error_rate()),
+                SortColumn::Duplicates => self.duplicates(a).count().cmp(&self.duplicates(b).count()),
+            }
+        };
+        // A stable sort keeps entries in load order within equal keys.
+        entries.sort_by(compare);
+        if self.descending {
+            entries.reverse();
+        }
+        entries
+    }
+
+    fn thumbnail(
+        textures: &mut HashMap<String, TextureHandle>,
+        ctx: &egui::Context,
+        entry: &SessionEntry,
+    ) -> Option<TextureHandle> {
+        let image = entry.thumbnail.as_ref()?;
+        let texture = textures.entry(entry.sha1.clone()).or_insert_with(|| {
+            ctx.load_texture(format!("thumbnail_{}", entry.sha1), image.clone(), TextureOptions::LINEAR)
+        });
+        Some(texture.clone())
+    }
+
+    fn sort_header(&mut self, ui: &mut egui::Ui, column: SortColumn, label: &str) {
+        let text = match (self.sort == column, self.descending) {
+            (true, false) => format!("{} ⏶", label),
+            (true, true) => format!("{} ⏷", label),
+            _ => label.to_string(),
+        };
+        if ui.add(egui::Label::new(egui::RichText::new(text).strong()).sense(egui::Sense::click())).clicked() {
+            self.descending = self.sort == column && !self.descending;
+            self.sort = column;
+        }
+    }
+
+    /// Show the dashboard, once more than one image has been loaded.
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        if self.entries.len() < 2 {
+            return;
+        }
+
+        let failed = self.entries.iter().filter(|entry| entry.resolution.is_none()).count();
+        let title = format!("Session ({} images, {} failed)", self.entries.len(), failed);
+        egui::CollapsingHeader::new(title).id_salt("session_dashboard").show(ui, |ui| {
+            let size_mib = self.total_size() as f64 / (1024.0 * 1024.0);
+            ui.label(format!("Total size: {:.2} MiB", size_mib));
+
+            egui::Grid::new("session_grid").striped(true).num_columns(7).show(ui, |ui| {
+                self.sort_header(ui, SortColumn::Loaded, "#");
+                ui.label("");
+                self.sort_header(ui, SortColumn::Name, "Name");
+                self.sort_header(ui, SortColumn::Format, "Format");
+                self.sort_header(ui, SortColumn::Size, "Size");
+                self.sort_header(ui, SortColumn::ErrorRate, "CRC errors");
+                self.sort_header(ui, SortColumn::Duplicates, "Duplicates");
+                ui.end_row();
+
+                let order: Vec<usize> = self
+                    .sorted()
+                    .into_iter()
+                    .filter_map(|entry| self.entries.iter().position(|e| std::ptr::eq(e, entry)))
+                    .collect();
+                for index in order {
+                    let entry = &self.entries[index];
+                    ui.label((index + 1).to_string());
+                    match Self::thumbnail(&mut self.textures, ui.ctx(), entry) {
+                        Some(texture) => {
+                            ui.add(egui::Image::new(&texture).fit_to_exact_size(egui::Vec2::splat(THUMBNAIL_ROW_SIZE)))
+                                .on_hover_ui(|ui| {
+                                    ui.image(&texture);
+                                });
+                        }
+                        None => {
+                            ui.label("");
+                        }
+                    }
+                    ui.label(&entry.name).on_hover_text(format!("SHA-1 {}", entry.sha1));
+                    match &entry.resolution {
+                        Some(resolution) => ui.label(format!("{} ({})", entry.format, resolution)),
+                        None => ui.colored_label(ui.visuals().error_fg_color, format!("{} (failed)", entry.format)),
+                    };
+                    ui.label(entry.size.to_string());
+                    if entry.crc_errors > 0 {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("{} of {} ({:.1}%)", entry.crc_errors, entry.sectors, entry.error_rate() * 100.0),
+                        );
+                    }
+                    else {
+                        ui.label(format!("0 of {}", entry.sectors));
+                    }
+                    let duplicates = self.duplicates(entry);
+                    ui.horizontal(|ui| {
+                        if !duplicates.same_file.is_empty() {
+                            ui.colored_label(ui.visuals().warn_fg_color, "Same file")
+                                .on_hover_text(duplicates.same_file.join("\n"));
+                        }
+                        if !duplicates.same_data.is_empty() {
+                            ui.colored_label(ui.visuals().warn_fg_color, "Same data")
+                                .on_hover_text(duplicates.same_data.join("\n"));
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,238 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! The settings window, which gathers the app's preferences into categories in one place.
+//!
+//! Each section edits one part of the persisted state. The state itself stays with the app, so
+//! the window only lays the sections out, filters them by the search text and reports which
+//! ones the user asked to reset; the app draws and resets each section's state.
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Category {
+    #[default]
+    Visualization,
+    Export,
+    Analysis,
+    Hardware,
+    Privacy,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Category::Visualization,
+        Category::Export,
+        Category::Analysis,
+        Category::Hardware,
+        Category::Privacy,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::Visualization => "Visualization",
+            Category::Export => "Export",
+            Category::Analysis => "Analysis",
+            Category::Hardware => "Hardware",
+            Category::Privacy => "Privacy",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Section {
+    VizLayout,
+    ExportNaming,
+    ExportPresets,
+    WatchMode,
+    AnalysisChecks,
+    Panels,
+    Performance,
+    Autosave,
+    FormatReports,
+}
+
+impl Section {
+    pub const ALL: [Section; 9] = [
+        Section::VizLayout,
+        Section::ExportNaming,
+        Section::ExportPresets,
+        Section::WatchMode,
+        Section::AnalysisChecks,
+        Section::Panels,
+        Section::Performance,
+        Section::Autosave,
+        Section::FormatReports,
+    ];
+
+    pub fn category(&self) -> Category {
+        match self {
+            Section::VizLayout => Category::Visualization,
+            Section::ExportNaming | Section::ExportPresets | Section::WatchMode => Category::Export,
+            Section::AnalysisChecks | Section::Panels => Category::Analysis,
+            Section::Performance => Category::Hardware,
+            Section::Autosave | Section::FormatReports => Category::Privacy,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Section::VizLayout => "Layout",
+            Section::ExportNaming => "File names",
+            Section::ExportPresets => "Presets",
+            Section::WatchMode => "Watch mode",
+            Section::AnalysisChecks => "Checks",
+            Section::Panels => "Panels",
+            Section::Performance => "Performance",
+            Section::Autosave => "Workspace autosave",
+            Section::FormatReports => "Unsupported format reports",
+        }
+    }
+
+    /// Words besides the title that a search should find the section by.
+    fn keywords(&self) -> &'static str {
+        match self {
+            Section::VizLayout => "radius track spacing gap rotation clockwise index renderer gpu cpu",
+            Section::ExportNaming => "pattern stem extension hash filename",
+            Section::ExportPresets => "format sidecar delete",
+            Section::WatchMode => "process on load convert report download",
+            Section::AnalysisChecks => "profile quick deep enable disable",
+            Section::Panels => "hide show sector list carver",
+            Section::Performance => "frame budget responsive speed",
+            Section::Autosave => "storage workspace reload restore annotations bookmarks",
+            Section::FormatReports => "endpoint url telemetry send",
+        }
+    }
+
+    /// Whether the section has defaults to go back to. Presets are the user's own, so they're
+    /// only ever deleted one at a time.
+    pub fn resettable(&self) -> bool {
+        !matches!(self, Section::ExportPresets)
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        query.is_empty()
+            || [self.title(), self.keywords(), self.category().label()]
+                .iter()
+                .any(|text| text.to_lowercase().contains(&query))
+    }
+}
+
+#[derive(Default)]
+pub struct SettingsWindow {
+    pub open: bool,
+    category: Category,
+    search: String,
+}
+
+impl SettingsWindow {
+    /// Show the window, calling `show_section` to draw each section that's visible. Returns the
+    /// sections the user asked to reset to their defaults.
+    pub fn show(&mut self, ctx: &egui::Context, mut show_section: impl FnMut(&mut egui::Ui, Section)) -> Vec<Section> {
+        let mut reset = Vec::new();
+        if !self.open {
+            return reset;
+        }
+        let mut open = self.open;
+        egui::Window::new("Settings")
+            .id(egui::Id::new("settings_window"))
+            .open(&mut open)
+            .default_size([560.0, 400.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.search).hint_text("Search settings"));
+                    if !self.search.is_empty() && ui.small_button("✖").on_hover_text("Clear search").clicked() {
+                        self.search.clear();
+                    }
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui
+                            .button("Reset all")
+                            .on_hover_text("Reset every setting, in every category, to its default.")
+                            .clicked()
+                        {
+                            reset.extend(Section::ALL.into_iter().filter(Section::resettable));
+                        }
+                    });
+                });
+                ui.separator();
+
+                let searching = !self.search.trim().is_empty();
+                let visible: Vec<Section> = Section::ALL
+                    .into_iter()
+                    .filter(|section| match searching {
+                        true => section.matches(&self.search),
+                        false => section.category() == self.category,
+                    })
+                    .collect();
+
+                ui.horizontal_top(|ui| {
+                    ui.vertical(|ui| {
+                        ui.set_width(110.0);
+                        for category in Category::ALL {
+                            let found = visible.iter().any(|section| section.category() == category);
+                            let selected = match searching {
+                                true => found,
+                                false => self.category == category,
+                            };
+                            let response = ui.add_enabled(
+                                !searching || found,
+                                egui::SelectableLabel::new(selected, category.label()),
+                            );
+                            if response.clicked() {
+                                self.category = category;
+                                self.search.clear();
+                            }
+                        }
+                    });
+                    ui.separator();
+                    egui::ScrollArea::vertical().auto_shrink([false, true]).show(ui, |ui| {
+                        if visible.is_empty() {
+                            ui.weak("No settings match the search.");
+                        }
+                        for section in visible {
+                            ui.horizontal(|ui| {
+                                match searching {
+                                    true => ui.strong(format!("{} > {}", section.category().label(), section.title())),
+                                    false => ui.strong(section.title()),
+                                };
+                                if section.resettable() {
+                                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                        if ui.small_button("Reset").on_hover_text("Reset to defaults").clicked() {
+                                            reset.push(section);
+                                        }
+                                    });
+                                }
+                            });
+                            ui.push_id(("settings_section", section as u8), |ui| show_section(ui, section));
+                            ui.add_space(8.0);
+                        }
+                    });
+                });
+            });
+        self.open = open;
+        reset
+    }
+}
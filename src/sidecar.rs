@@ -0,0 +1,233 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use base64::Engine;
+use egui::{ColorImage, TextureHandle, TextureOptions};
+
+use crate::history::History;
+
+pub const MEDIA_TYPES: [&str; 8] = [
+    "5.25\" DD",
+    "5.25\" HD",
+    "3.5\" DD",
+    "3.5\" HD",
+    "3.5\" ED",
+    "8\" SD",
+    "8\" DD",
+    "Other",
+];
+
+/// The label photo as uploaded for display, so it's decoded once rather than every frame.
+#[derive(Clone, Default)]
+enum LabelTexture {
+    #[default]
+    Pending,
+    Loaded(TextureHandle),
+    Failed,
+}
+
+impl std::fmt::Debug for LabelTexture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelTexture::Pending => write!(f, "Pending"),
+            LabelTexture::Loaded(texture) => write!(f, "Loaded({:?})", texture.id()),
+            LabelTexture::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+/// A photo of the disk label, embedded in the sidecar as base64.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct LabelImage {
+    pub name: String,
+    pub mime: String,
+    pub data: String,
+    #[serde(skip)]
+    texture: LabelTexture,
+}
+
+impl LabelImage {
+    pub fn from_bytes(name: &str, mime: &str, bytes: &[u8]) -> Self {
+        Self {
+            name: name.to_string(),
+            mime: mime.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+            texture: LabelTexture::Pending,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        base64::engine::general_purpose::STANDARD.decode(&self.data).ok()
+    }
+
+    /// The photo's texture, decoded and uploaded the first time it's asked for.
+    fn texture(&mut self, ctx: &egui::Context) -> Option<&TextureHandle> {
+        if let LabelTexture::Pending = self.texture {
+            self.texture = match self.decode() {
+                Ok(image) => LabelTexture::Loaded(ctx.load_texture("label_image", image, TextureOptions::LINEAR)),
+                Err(e) => {
+                    log::warn!("Couldn't decode label photo {}: {}", self.name, e);
+                    LabelTexture::Failed
+                }
+            };
+        }
+        match &self.texture {
+            LabelTexture::Loaded(texture) => Some(texture),
+            _ => None,
+        }
+    }
+
+    fn decode(&self) -> Result<ColorImage, String> {
+        let bytes = self.bytes().ok_or("invalid base64")?;
+        let rgba = image::load_from_memory(&bytes).map_err(|e| e.to_string())?.to_rgba8();
+        let size = [rgba.width() as usize, rgba.height() as usize];
+        Ok(ColorImage::from_rgba_unmultiplied(size, rgba.as_raw()))
+    }
+}
+
+/// The JSON sidecar saved next to exported images: the cataloging metadata and the image's
+/// processing history.
+#[derive(Debug, serde::Serialize)]
+pub struct ImageSidecar<'a> {
+    #[serde(flatten)]
+    pub metadata: &'a ImageMetadata,
+    #[serde(skip_serializing_if = "no_history")]
+    pub history: &'a History,
+}
+
+fn no_history(history: &&History) -> bool {
+    history.is_empty()
+}
+
+impl ImageSidecar<'_> {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Cataloging metadata attached to a disk image. Saved as a JSON sidecar next to exports and
+/// included in reports.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ImageMetadata {
+    pub title: String,
+    pub publisher: String,
+    pub year: String,
+    pub media_type: String,
+    pub notes: String,
+    pub label_image: Option<LabelImage>,
+}
+
+impl ImageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_empty()
+            && self.publisher.is_empty()
+            && self.year.is_empty()
+            && self.media_type.is_empty()
+            && self.notes.is_empty()
+            && self.label_image.is_none()
+    }
+
+    /// Drop the label photo's texture after the graphics context was restored; it's uploaded
+    /// again the next time it's shown.
+    pub fn forget_textures(&mut self) {
+        if let Some(image) = &mut self.label_image {
+            if let LabelTexture::Loaded(_) = image.texture {
+                image.texture = LabelTexture::Pending;
+            }
+        }
+    }
+
+    /// Return true if a dropped file looks like a label photo rather than a disk image.
+    pub fn is_label_image(name: &str, mime: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        mime.starts_with("image/") || name.ends_with(".jpg") || name.ends_with(".jpeg") || name.ends_with(".png")
+    }
+
+    /// Show the metadata editor. Returns true if any field was changed.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+
+        egui::Grid::new("image_metadata_grid")
+            .num_columns(2)
+            .spacing([8.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("Title:");
+                changed |= ui.text_edit_singleline(&mut self.title).changed();
+                ui.end_row();
+
+                ui.label("Publisher:");
+                changed |= ui.text_edit_singleline(&mut self.publisher).changed();
+                ui.end_row();
+
+                ui.label("Year:");
+                changed |= ui.text_edit_singleline(&mut self.year).changed();
+                ui.end_row();
+
+                ui.label("Media type:");
+                egui::ComboBox::from_id_salt("image_metadata_media_type")
+                    .selected_text(self.media_type.as_str())
+                    .show_ui(ui, |ui| {
+                        for media_type in MEDIA_TYPES {
+                            changed |= ui
+                                .selectable_value(&mut self.media_type, media_type.to_string(), media_type)
+                                .changed();
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Notes:");
+                changed |= ui.text_edit_multiline(&mut self.notes).changed();
+                ui.end_row();
+            });
+
+        ui.separator();
+        match &mut self.label_image {
+            Some(image) => {
+                match image.texture(ui.ctx()) {
+                    Some(texture) => {
+                        ui.add(
+                            egui::Image::new((texture.id(), texture.size_vec2()))
+                                .max_width(256.0)
+                                .max_height(256.0),
+                        );
+                    }
+                    None => {
+                        ui.colored_label(ui.visuals().error_fg_color, "Couldn't decode the label photo.");
+                    }
+                }
+                if ui.button("Remove label photo").clicked() {
+                    self.label_image = None;
+                    changed = true;
+                }
+            }
+            None => {
+                ui.label("Drop a photo of the disk label here to attach it.");
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,408 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::{Function, Object, Promise, Reflect};
+use web_sys::{
+    Blob,
+    BlobPropertyBag,
+    FileSystemDirectoryHandle,
+    FileSystemFileHandle,
+    FileSystemGetFileOptions,
+    FileSystemReadWriteOptions,
+    FileSystemSyncAccessHandle,
+    FileSystemWritableFileStream,
+    HtmlAnchorElement,
+};
+
+/// Output expected to be larger than this is written to the Origin Private File System instead
+/// of memory, when OPFS is available.
+pub const OPFS_THRESHOLD: usize = 32 * 1024 * 1024;
+/// Size of each write when streaming a file to a location chosen with the save picker.
+pub const SAVE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Number of times a failed chunk write is retried before the save is abandoned.
+pub const SAVE_CHUNK_RETRIES: usize = 3;
+
+/// A finished scratch file. Unlike [ScratchFile] this holds no JS handles, so it can be sent
+/// back to the main thread from a worker.
+pub enum StoredFile {
+    Memory(Vec<u8>),
+    Opfs { name: String, len: u64 },
+}
+
+impl StoredFile {
+    pub fn len(&self) -> u64 {
+        match self {
+            StoredFile::Memory(data) => data.len() as u64,
+            StoredFile::Opfs { len, .. } => *len,
+        }
+    }
+}
+
+/// A temporary file used as the output of conversions and exports.
+pub enum ScratchFile {
+    Memory(Cursor<Vec<u8>>),
+    Opfs(OpfsFile),
+}
+
+impl ScratchFile {
+    pub fn memory() -> Self {
+        ScratchFile::Memory(Cursor::new(Vec::new()))
+    }
+
+    /// Create a scratch file for roughly `size_hint` bytes of output. Large files are placed in
+    /// OPFS when called from a worker; synchronous access handles are not available on the main
+    /// thread, so we fall back to memory there.
+    pub async fn create(name: &str, size_hint: usize) -> Self {
+        if size_hint >= OPFS_THRESHOLD {
+            match OpfsFile::create(name).await {
+                Ok(file) => {
+                    log::debug!("ScratchFile::create(): Using OPFS scratch file '{}'", name);
+                    return ScratchFile::Opfs(file);
+                }
+                Err(e) => {
+                    log::warn!("ScratchFile::create(): OPFS unavailable, using memory: {:?}", e);
+                }
+            }
+        }
+        ScratchFile::memory()
+    }
+
+    /// Finish writing and return a [StoredFile] that can be handed to [download].
+    pub fn finish(self) -> io::Result<StoredFile> {
+        match self {
+            ScratchFile::Memory(cursor) => Ok(StoredFile::Memory(cursor.into_inner())),
+            ScratchFile::Opfs(file) => file.finish(),
+        }
+    }
+}
+
+impl Read for ScratchFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ScratchFile::Memory(cursor) => cursor.read(buf),
+            ScratchFile::Opfs(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for ScratchFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ScratchFile::Memory(cursor) => cursor.write(buf),
+            ScratchFile::Opfs(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ScratchFile::Memory(cursor) => cursor.flush(),
+            ScratchFile::Opfs(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for ScratchFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ScratchFile::Memory(cursor) => cursor.seek(pos),
+            ScratchFile::Opfs(file) => file.seek(pos),
+        }
+    }
+}
+
+/// A file in the Origin Private File System, accessed through a synchronous access handle.
+pub struct OpfsFile {
+    name: String,
+    handle: FileSystemSyncAccessHandle,
+    pos: u64,
+}
+
+impl OpfsFile {
+    pub async fn create(name: &str) -> Result<Self, JsValue> {
+        if web_sys::js_sys::global()
+            .dyn_ref::<web_sys::WorkerGlobalScope>()
+            .is_none()
+        {
+            return Err(JsValue::from_str("OPFS access handles require a worker"));
+        }
+
+        let root = opfs_root().await?;
+        let options = FileSystemGetFileOptions::new();
+        options.set_create(true);
+        let file_handle: FileSystemFileHandle = JsFuture::from(root.get_file_handle_with_options(name, &options))
+            .await?
+            .unchecked_into();
+        let handle: FileSystemSyncAccessHandle = JsFuture::from(file_handle.create_sync_access_handle())
+            .await?
+            .unchecked_into();
+
+        // Scratch files are reused between jobs, so discard any previous contents.
+        handle.truncate_with_u32(0)?;
+
+        Ok(Self {
+            name: name.to_string(),
+            handle,
+            pos: 0,
+        })
+    }
+
+    fn finish(self) -> io::Result<StoredFile> {
+        self.handle.flush().map_err(js_io_error)?;
+        let len = self.handle.get_size().map_err(js_io_error)? as u64;
+        self.handle.close();
+        Ok(StoredFile::Opfs { name: self.name, len })
+    }
+
+    fn rw_options(&self) -> FileSystemReadWriteOptions {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(self.pos as f64);
+        options
+    }
+}
+
+impl Read for OpfsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let options = self.rw_options();
+        let read = self
+            .handle
+            .read_with_u8_array_and_options(buf, &options)
+            .map_err(js_io_error)? as u64;
+        self.pos += read;
+        Ok(read as usize)
+    }
+}
+
+impl Write for OpfsFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let options = self.rw_options();
+        let written = self
+            .handle
+            .write_with_u8_array_and_options(buf, &options)
+            .map_err(js_io_error)? as u64;
+        self.pos += written;
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.handle.flush().map_err(js_io_error)
+    }
+}
+
+impl Seek for OpfsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.handle.get_size().map_err(js_io_error)? as i64;
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => len + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if new_pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of file"));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
+
+fn js_io_error(e: JsValue) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+/// Return the root directory of the Origin Private File System, from either a window or worker.
+async fn opfs_root() -> Result<FileSystemDirectoryHandle, JsValue> {
+    let global = web_sys::js_sys::global();
+    let storage = if let Some(worker) = global.dyn_ref::<web_sys::WorkerGlobalScope>() {
+        worker.navigator().storage()
+    }
+    else if let Some(window) = global.dyn_ref::<web_sys::Window>() {
+        window.navigator().storage()
+    }
+    else {
+        return Err(JsValue::from_str("No storage manager available"));
+    };
+
+    Ok(JsFuture::from(storage.get_directory()).await?.unchecked_into())
+}
+
+pub fn bytes_to_blob(data: &[u8]) -> Result<Blob, JsValue> {
+    let array = web_sys::js_sys::Uint8Array::from(data);
+    let parts = web_sys::js_sys::Array::of1(&array);
+    let options = BlobPropertyBag::new();
+    options.set_type("application/octet-stream");
+    Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+}
+
+/// Open an existing OPFS file as a [Blob] without reading it into memory.
+async fn opfs_blob(name: &str) -> Result<Blob, JsValue> {
+    let root = opfs_root().await?;
+    let handle: FileSystemFileHandle = JsFuture::from(root.get_file_handle(name)).await?.unchecked_into();
+    Ok(JsFuture::from(handle.get_file()).await?.unchecked_into())
+}
+
+/// Offer a stored file to the user as a browser download. OPFS files are handed to the browser
+/// as a file handle without being read back into memory.
+pub async fn download(file: StoredFile, filename: &str) -> Result<(), JsValue> {
+    let blob = match file {
+        StoredFile::Memory(data) => bytes_to_blob(&data)?,
+        StoredFile::Opfs { name, .. } => opfs_blob(&name).await?,
+    };
+    download_blob(&blob, filename)
+}
+
+/// Where a finished export should be saved.
+pub enum SaveTarget {
+    /// A location chosen with the File System Access API save picker. The promise resolves to a
+    /// [FileSystemFileHandle] once the user has picked a file.
+    Picker(Promise),
+    /// A regular blob URL download.
+    Download,
+}
+
+impl SaveTarget {
+    /// Ask the user where to save `filename`, if the browser supports `showSaveFilePicker`.
+    /// This must be called while handling user input, as the picker requires user activation.
+    pub fn request(filename: &str) -> Self {
+        match show_save_file_picker(filename) {
+            Ok(Some(promise)) => SaveTarget::Picker(promise),
+            Ok(None) => SaveTarget::Download,
+            Err(e) => {
+                log::warn!("SaveTarget::request(): Save picker failed, using download: {:?}", e);
+                SaveTarget::Download
+            }
+        }
+    }
+}
+
+fn show_save_file_picker(filename: &str) -> Result<Option<Promise>, JsValue> {
+    let Some(window) = web_sys::window()
+    else {
+        return Ok(None);
+    };
+
+    let picker = Reflect::get(&window, &JsValue::from_str("showSaveFilePicker"))?;
+    let Some(picker) = picker.dyn_ref::<Function>()
+    else {
+        return Ok(None);
+    };
+
+    let options = Object::new();
+    Reflect::set(&options, &JsValue::from_str("suggestedName"), &JsValue::from_str(filename))?;
+    Ok(Some(picker.call1(&window, &options)?.unchecked_into()))
+}
+
+/// Save a stored file to `target`.
+pub async fn save(file: StoredFile, filename: &str, target: SaveTarget) -> Result<(), JsValue> {
+    match target {
+        SaveTarget::Download => download(file, filename).await,
+        SaveTarget::Picker(promise) => {
+            let handle: FileSystemFileHandle = match JsFuture::from(promise).await {
+                Ok(handle) => handle.unchecked_into(),
+                Err(e) => {
+                    log::debug!("save(): Save picker was dismissed: {:?}", e);
+                    return Ok(());
+                }
+            };
+            write_chunked(file, &handle).await
+        }
+    }
+}
+
+/// Stream a stored file to `handle` in chunks of [SAVE_CHUNK_SIZE], seeking back and retrying
+/// any chunk that fails to write.
+async fn write_chunked(file: StoredFile, handle: &FileSystemFileHandle) -> Result<(), JsValue> {
+    let len = file.len() as usize;
+    let source = match file {
+        StoredFile::Memory(data) => ChunkSource::Memory(data),
+        StoredFile::Opfs { name, .. } => ChunkSource::Blob(opfs_blob(&name).await?),
+    };
+
+    let stream: FileSystemWritableFileStream = JsFuture::from(handle.create_writable()).await?.unchecked_into();
+
+    let mut pos = 0;
+    while pos < len {
+        let end = (pos + SAVE_CHUNK_SIZE).min(len);
+        let mut attempts = 0;
+        loop {
+            match source.write_chunk(&stream, pos, end).await {
+                Ok(()) => break,
+                Err(e) if attempts < SAVE_CHUNK_RETRIES => {
+                    attempts += 1;
+                    log::warn!("write_chunked(): Write at offset {} failed, retrying: {:?}", pos, e);
+                    JsFuture::from(stream.seek_with_f64(pos as f64)?).await?;
+                }
+                Err(e) => {
+                    log::error!("write_chunked(): Giving up at offset {}: {:?}", pos, e);
+                    _ = JsFuture::from(stream.abort()).await;
+                    return Err(e);
+                }
+            }
+        }
+        pos = end;
+    }
+
+    JsFuture::from(stream.close()).await?;
+    Ok(())
+}
+
+enum ChunkSource {
+    Memory(Vec<u8>),
+    Blob(Blob),
+}
+
+impl ChunkSource {
+    async fn write_chunk(&self, stream: &FileSystemWritableFileStream, start: usize, end: usize) -> Result<(), JsValue> {
+        let promise = match self {
+            ChunkSource::Memory(data) => stream.write_with_u8_array(&data[start..end])?,
+            ChunkSource::Blob(blob) => stream.write_with_blob(&blob.slice_with_f64_and_f64(start as f64, end as f64)?)?,
+        };
+        JsFuture::from(promise).await?;
+        Ok(())
+    }
+}
+
+/// Trigger a browser download of an in-memory buffer.
+pub fn download_bytes(data: &[u8], filename: &str) -> Result<(), JsValue> {
+    download_blob(&bytes_to_blob(data)?, filename)
+}
+
+/// Trigger a browser download of `blob` via a temporary anchor element.
+pub fn download_blob(blob: &Blob, filename: &str) -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| JsValue::from_str("No document"))?;
+
+    let url = web_sys::Url::create_object_url_with_blob(blob)?;
+    let anchor: HtmlAnchorElement = document.create_element("a")?.unchecked_into();
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+    web_sys::Url::revoke_object_url(&url)?;
+    Ok(())
+}
@@ -0,0 +1,470 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A queue of named background jobs, each run in its own worker.
+//!
+//! Jobs report back over a shared channel through a [TaskHandle]. The manager tracks each
+//! task's progress and state for display, and hands the messages the app acts on (results,
+//! and load-specific progress details) back from [TaskManager::poll]. Cancelling a task
+//! terminates its worker.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+
+use anyhow::Error;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use fluxfox::{DiskCh, DiskImage, DiskImageError};
+
+use crate::hires::HiresRequest;
+use crate::storage::StoredFile;
+use crate::unsupported::FileProbe;
+use crate::worker;
+
+/// How many workers may run at once. Further tasks wait in the queue.
+pub const MAX_RUNNING_TASKS: usize = 2;
+/// How long a finished task stays listed, in seconds.
+pub const TASK_LINGER_SECS: f64 = 5.0;
+/// The shortest interval between progress updates sent by a task, in milliseconds. Fast
+/// decodes report progress far more often than the UI can show it.
+pub const PROGRESS_INTERVAL_MS: f64 = 1000.0 / 30.0;
+
+pub type TaskId = u64;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TaskKind {
+    Load,
+    /// Loading a second image to compare the current one against.
+    Compare,
+    /// Loading another dump of the disk for the merge tool.
+    Merge,
+    /// Decoding a re-dumped stream file of a loaded Kryoflux set, to merge into it.
+    Redecode,
+    Convert,
+    Render,
+}
+
+impl TaskKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TaskKind::Load => "Load",
+            TaskKind::Compare => "Load for comparison",
+            TaskKind::Merge => "Load for merging",
+            TaskKind::Redecode => "Re-decode track",
+            TaskKind::Convert => "Convert",
+            TaskKind::Render => "Render",
+        }
+    }
+}
+
+/// The result of a task, handed back to the app.
+pub enum TaskOutput {
+    Loaded(Result<DiskImage, DiskImageError>),
+    LoadedComparison(Result<DiskImage, DiskImageError>),
+    LoadedMergeDump(Result<DiskImage, DiskImageError>),
+    /// A re-dumped stream file was decoded, for the track `ch`. `check` is what the stream
+    /// file's own blocks say about it, as for the streams of a set.
+    Redecoded {
+        name: String,
+        ch: DiskCh,
+        check: Result<usize, String>,
+        result: Result<DiskImage, Error>,
+    },
+    /// The export finished. The disk image is handed back along with the output or error.
+    Exported {
+        disk: DiskImage,
+        filename: String,
+        result: Result<StoredFile, Error>,
+    },
+    /// A high resolution render finished. The disk image is handed back as with an export.
+    Rendered {
+        disk: DiskImage,
+        request: HiresRequest,
+        png: Result<Vec<u8>, Error>,
+    },
+    /// A session thumbnail for the image with SHA-1 `sha1` was rendered, handing the disk image
+    /// back as a high resolution render does.
+    Thumbnail {
+        disk: DiskImage,
+        sha1: String,
+        image: Result<egui::ColorImage, Error>,
+    },
+}
+
+impl TaskOutput {
+    fn error(&self) -> Option<String> {
+        match self {
+            TaskOutput::Loaded(Err(e))
+            | TaskOutput::LoadedComparison(Err(e))
+            | TaskOutput::LoadedMergeDump(Err(e)) => Some(format!("{:?}", e)),
+            TaskOutput::Exported { result: Err(e), .. } => Some(e.to_string()),
+            TaskOutput::Rendered { png: Err(e), .. } => Some(e.to_string()),
+            TaskOutput::Redecoded { result: Err(e), .. } => Some(e.to_string()),
+            TaskOutput::Thumbnail { image: Err(e), .. } => Some(e.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Messages sent from a task's worker.
+pub enum TaskMessage {
+    Progress(f64),
+    /// A Kryoflux stream set load checked the stream at this index, finding its revolution
+    /// count or what's wrong with it.
+    StreamChecked(usize, Result<usize, String>),
+    /// The file was a zip holding a single image, which is what's being loaded.
+    Unwrapped(FileProbe),
+    /// A render finished the part of the image at `origin`.
+    Tile { origin: [usize; 2], image: egui::ColorImage },
+    /// The worker died, usually from a panic. Carries the panic message and location, or the
+    /// browser's error message if no panic was recorded. No other message will follow.
+    Crashed(String),
+    Finished(TaskOutput),
+}
+
+/// The messages from a task the app needs to act on.
+pub struct TaskEvent {
+    pub id: TaskId,
+    pub kind: TaskKind,
+    pub name: String,
+    pub message: TaskMessage,
+}
+
+/// The worker's side of a task.
+#[derive(Clone)]
+pub struct TaskHandle {
+    id: TaskId,
+    sender: mpsc::Sender<(TaskId, TaskMessage)>,
+    cancelled: Arc<AtomicBool>,
+    /// When progress was last sent, as the bits of a millisecond timestamp. Shared by clones
+    /// of the handle, so the limit holds however many callbacks report progress.
+    last_progress: Arc<AtomicU64>,
+}
+
+impl TaskHandle {
+    pub fn send(&self, message: TaskMessage) {
+        if self.sender.send((self.id, message)).is_err() {
+            log::warn!("Task {} receiver dropped", self.id);
+        }
+    }
+
+    /// Report progress, dropping updates that come sooner than PROGRESS_INTERVAL_MS after the
+    /// last one sent. Completion is always sent.
+    pub fn progress(&self, progress: f64) {
+        let now = web_sys::js_sys::Date::now();
+        let last = f64::from_bits(self.last_progress.load(Ordering::Relaxed));
+        if progress < 1.0 && now - last < PROGRESS_INTERVAL_MS {
+            return;
+        }
+        self.last_progress.store(now.to_bits(), Ordering::Relaxed);
+        self.send(TaskMessage::Progress(progress));
+    }
+
+    pub fn finish(&self, output: TaskOutput) {
+        self.send(TaskMessage::Finished(output));
+    }
+
+    /// Whether the user cancelled the task. The worker is terminated on cancel, but callbacks
+    /// that run before then can check this to stop reporting; the results of a cancelled task
+    /// are discarded either way.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Report the task as crashed if its worker raises an error. A panic in a worker aborts it
+/// without unwinding, so this is the only way the task's end is seen.
+fn watch_for_crash(worker: &web_sys::Worker, id: TaskId, sender: mpsc::Sender<(TaskId, TaskMessage)>) {
+    let on_error = Closure::<dyn FnMut(web_sys::ErrorEvent)>::new(move |event: web_sys::ErrorEvent| {
+        let report = worker::take_panic_report().unwrap_or_else(|| event.message());
+        log::error!("Worker for task {} crashed: {}", id, report);
+        if sender.send((id, TaskMessage::Crashed(report))).is_err() {
+            log::warn!("Task {} receiver dropped", id);
+        }
+    });
+    worker.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    // The worker may outlive the task's listing, so the handler is never freed.
+    on_error.forget();
+}
+
+/// Starts a job's worker, given its handle.
+type Job = Box<dyn FnOnce(TaskHandle) -> Result<web_sys::Worker, JsValue>>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TaskState {
+    Queued,
+    Running,
+    Complete,
+    Failed(String),
+    Cancelled,
+}
+
+impl TaskState {
+    pub fn is_active(&self) -> bool {
+        matches!(self, TaskState::Queued | TaskState::Running)
+    }
+}
+
+struct Task {
+    id: TaskId,
+    name: String,
+    kind: TaskKind,
+    state: TaskState,
+    progress: Option<f64>,
+    cancellable: bool,
+    cancelled: Arc<AtomicBool>,
+    job: Option<Job>,
+    /// The task's worker while it runs, for terminating it on cancel.
+    worker: Option<web_sys::Worker>,
+    /// The time at which the task finished, in egui input time.
+    finished_at: Option<f64>,
+}
+
+pub struct TaskManager {
+    tasks: Vec<Task>,
+    next_id: TaskId,
+    sender: mpsc::Sender<(TaskId, TaskMessage)>,
+    receiver: mpsc::Receiver<(TaskId, TaskMessage)>,
+}
+
+impl Default for TaskManager {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            tasks: Vec::new(),
+            next_id: 0,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl TaskManager {
+    /// Queue a job. `job` is called on the main thread to spawn the task's worker once there is
+    /// a free slot. Tasks that can't be safely abandoned, such as those holding the disk image,
+    /// should not be `cancellable`.
+    pub fn submit(
+        &mut self,
+        name: impl Into<String>,
+        kind: TaskKind,
+        cancellable: bool,
+        job: impl FnOnce(TaskHandle) -> Result<web_sys::Worker, JsValue> + 'static,
+    ) -> TaskId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.push(Task {
+            id,
+            name: name.into(),
+            kind,
+            state: TaskState::Queued,
+            progress: None,
+            cancellable,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            job: Some(Box::new(job)),
+            worker: None,
+            finished_at: None,
+        });
+        self.start_queued();
+        id
+    }
+
+    pub fn cancel(&mut self, id: TaskId) {
+        self.stop(id);
+        self.start_queued();
+    }
+
+    fn stop(&mut self, id: TaskId) {
+        if let Some(task) = self.tasks.iter_mut().find(|task| task.id == id) {
+            if task.cancellable && task.state.is_active() {
+                log::debug!("Cancelling task {} ({})", task.id, task.name);
+                task.cancelled.store(true, Ordering::Relaxed);
+                task.state = TaskState::Cancelled;
+                task.job = None;
+                // Stopping the worker frees its slot for the next task straight away, rather than
+                // once the job notices. Whatever the job held in the shared memory is leaked.
+                if let Some(worker) = task.worker.take() {
+                    worker.terminate();
+                }
+            }
+        }
+    }
+
+    /// Cancel all active tasks of `kind`, e.g. a load superseded by another.
+    pub fn cancel_kind(&mut self, kind: TaskKind) {
+        let ids: Vec<TaskId> = self.tasks.iter().filter(|task| task.kind == kind).map(|task| task.id).collect();
+        for id in ids {
+            self.stop(id);
+        }
+        self.start_queued();
+    }
+
+    pub fn is_active(&self, kind: TaskKind) -> bool {
+        self.tasks.iter().any(|task| task.kind == kind && task.state.is_active())
+    }
+
+    pub fn busy(&self) -> bool {
+        self.tasks.iter().any(|task| task.state.is_active())
+    }
+
+    fn start_queued(&mut self) {
+        let mut running = self.tasks.iter().filter(|task| task.state == TaskState::Running).count();
+        for task in self.tasks.iter_mut().filter(|task| task.state == TaskState::Queued) {
+            if running >= MAX_RUNNING_TASKS {
+                break;
+            }
+            let Some(job) = task.job.take()
+            else {
+                continue;
+            };
+
+            let handle = TaskHandle {
+                id: task.id,
+                sender: self.sender.clone(),
+                cancelled: task.cancelled.clone(),
+                last_progress: Arc::new(AtomicU64::new(0f64.to_bits())),
+            };
+            match job(handle) {
+                Ok(worker) => {
+                    watch_for_crash(&worker, task.id, self.sender.clone());
+                    log::debug!("Started task {} ({})", task.id, task.name);
+                    task.worker = Some(worker);
+                    task.state = TaskState::Running;
+                    running += 1;
+                }
+                Err(e) => {
+                    log::error!("Error spawning worker for task {}: {:?}", task.name, e);
+                    task.state = TaskState::Failed(format!("Couldn't start worker: {:?}", e));
+                }
+            }
+        }
+    }
+
+    /// Drain messages from the workers, start queued tasks as slots free up, and return the
+    /// messages the app needs to handle. Messages from cancelled tasks are dropped, and a
+    /// task's progress updates are coalesced into the latest.
+    pub fn poll(&mut self, now: f64) -> Vec<TaskEvent> {
+        let mut events = Vec::new();
+        while let Ok((id, message)) = self.receiver.try_recv() {
+            let Some(task) = self.tasks.iter_mut().find(|task| task.id == id)
+            else {
+                continue;
+            };
+            if task.state == TaskState::Cancelled {
+                continue;
+            }
+
+            match &message {
+                TaskMessage::Progress(progress) => {
+                    task.progress = Some(*progress);
+                    if let Some(event) = events.iter_mut().rev().find(|event| event.id == id) {
+                        if let TaskMessage::Progress(latest) = &mut event.message {
+                            *latest = *progress;
+                            continue;
+                        }
+                    }
+                }
+                TaskMessage::StreamChecked(..) | TaskMessage::Tile { .. } => {}
+                TaskMessage::Crashed(_) if !task.state.is_active() => continue,
+                TaskMessage::Crashed(report) => {
+                    let summary = report.lines().next().unwrap_or("unknown error");
+                    task.state = TaskState::Failed(format!("Crashed: {}", summary));
+                    task.worker = None;
+                    task.finished_at = Some(now);
+                }
+                TaskMessage::Finished(output) => {
+                    task.state = match output.error() {
+                        Some(error) => TaskState::Failed(error),
+                        None => TaskState::Complete,
+                    };
+                    task.worker = None;
+                    task.finished_at = Some(now);
+                }
+            }
+            events.push(TaskEvent {
+                id,
+                kind: task.kind,
+                name: task.name.clone(),
+                message,
+            });
+        }
+
+        for task in self.tasks.iter_mut().filter(|task| !task.state.is_active()) {
+            task.finished_at.get_or_insert(now);
+        }
+        self.tasks
+            .retain(|task| task.finished_at.map_or(true, |finished| now - finished < TASK_LINGER_SECS));
+        self.start_queued();
+        events
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        if self.tasks.is_empty() {
+            return;
+        }
+
+        let mut cancel = None;
+        ui.group(|ui| {
+            for task in self.tasks.iter() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}: {}", task.kind.label(), task.name));
+                    match &task.state {
+                        TaskState::Queued => {
+                            ui.weak("Queued");
+                        }
+                        TaskState::Running => match task.progress {
+                            Some(progress) => {
+                                ui.add(
+                                    egui::ProgressBar::new(progress as f32)
+                                        .desired_width(200.0)
+                                        .show_percentage(),
+                                );
+                            }
+                            None => {
+                                ui.spinner();
+                            }
+                        },
+                        TaskState::Complete => {
+                            ui.label("✔ Done");
+                        }
+                        TaskState::Failed(error) => {
+                            ui.colored_label(ui.visuals().error_fg_color, format!("✖ {}", error));
+                        }
+                        TaskState::Cancelled => {
+                            ui.weak("Cancelled");
+                        }
+                    }
+                    if task.cancellable && task.state.is_active() && ui.small_button("Cancel").clicked() {
+                        cancel = Some(task.id);
+                    }
+                });
+            }
+        });
+        if let Some(id) = cancel {
+            self.cancel(id);
+        }
+    }
+}
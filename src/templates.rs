@@ -0,0 +1,330 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Structure templates for the hex viewer. A template is a list of named fields at fixed
+//! offsets, applied once or repeated every `size` bytes (for tables such as directories).
+//!
+//! Custom templates are JSON files named `*.template.json`, holding one template or an array
+//! of them, for example:
+//!
+//! ```json
+//! {
+//!     "name": "My header",
+//!     "fields": [
+//!         { "name": "magic", "offset": 0, "type": "ascii", "len": 4 },
+//!         { "name": "length", "offset": 4, "type": "u32", "endian": "big" }
+//!     ]
+//! }
+//! ```
+
+use anyhow::{anyhow, Error};
+
+use crate::fat::{FatType, FatVolume, SectorRole};
+
+pub const TEMPLATE_FILE_SUFFIX: &str = ".template.json";
+
+pub const BPB_TEMPLATE: &str = "Boot sector (BPB)";
+pub const DIR_ENTRY_TEMPLATE: &str = "FAT directory entries";
+pub const FAT12_TEMPLATE: &str = "FAT12 table";
+pub const FAT16_TEMPLATE: &str = "FAT16 table";
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    /// `len` bytes of text.
+    Ascii,
+    /// `len` bytes shown as hex.
+    Bytes,
+    /// Two packed 12-bit FAT entries in three bytes.
+    U12Pair,
+    FatDate,
+    FatTime,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct TemplateField {
+    pub name: String,
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    /// Length in bytes of `ascii` and `bytes` fields.
+    #[serde(default)]
+    pub len: usize,
+    #[serde(default)]
+    pub endian: Endian,
+}
+
+impl TemplateField {
+    fn new(name: &str, offset: usize, ty: FieldType) -> Self {
+        Self {
+            name: name.to_string(),
+            offset,
+            ty,
+            len: 0,
+            endian: Endian::Little,
+        }
+    }
+
+    fn with_len(mut self, len: usize) -> Self {
+        self.len = len;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        match self.ty {
+            FieldType::U8 => 1,
+            FieldType::U16 | FieldType::FatDate | FieldType::FatTime => 2,
+            FieldType::U12Pair => 3,
+            FieldType::U32 => 4,
+            FieldType::Ascii | FieldType::Bytes => self.len,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        let uint = |bytes: &[u8]| {
+            let fold = |acc: u32, b: &u8| (acc << 8) | *b as u32;
+            match self.endian {
+                Endian::Little => bytes.iter().rev().fold(0, fold),
+                Endian::Big => bytes.iter().fold(0, fold),
+            }
+        };
+        match self.ty {
+            FieldType::U8 | FieldType::U16 | FieldType::U32 => {
+                let value = uint(bytes);
+                format!("{} (0x{:0width$X})", value, value, width = bytes.len() * 2)
+            }
+            FieldType::Ascii => bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect(),
+            FieldType::Bytes => crate::util::hex_string(bytes),
+            FieldType::U12Pair => {
+                let value = uint(bytes);
+                format!("{:03X} {:03X}", value & 0xFFF, value >> 12)
+            }
+            FieldType::FatDate => {
+                let date = uint(bytes);
+                format!("{:04}-{:02}-{:02}", 1980 + (date >> 9), (date >> 5) & 0x0F, date & 0x1F)
+            }
+            FieldType::FatTime => {
+                let time = uint(bytes);
+                format!("{:02}:{:02}:{:02}", time >> 11, (time >> 5) & 0x3F, (time & 0x1F) * 2)
+            }
+        }
+    }
+}
+
+/// A decoded field, positioned relative to the start of the data it was applied to.
+#[derive(Clone, Debug)]
+pub struct FieldValue {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct StructTemplate {
+    pub name: String,
+    /// Size of one instance of the structure. Only needed for repeating templates.
+    #[serde(default)]
+    pub size: usize,
+    /// Apply the template every `size` bytes to the end of the data.
+    #[serde(default)]
+    pub repeat: bool,
+    pub fields: Vec<TemplateField>,
+}
+
+impl StructTemplate {
+    /// Decode the template's fields from `data`, starting at `offset`. Fields that extend past
+    /// the end of the data are skipped.
+    pub fn apply(&self, data: &[u8], offset: usize) -> Vec<FieldValue> {
+        let mut values = Vec::new();
+        let instances = if self.repeat && self.size > 0 {
+            data.len().saturating_sub(offset) / self.size
+        }
+        else {
+            1
+        };
+
+        for i in 0..instances {
+            let base = offset + i * self.size;
+            for field in self.fields.iter() {
+                let start = base + field.offset;
+                let Some(bytes) = data.get(start..start + field.size())
+                else {
+                    continue;
+                };
+                let name = if self.repeat { format!("[{}].{}", i, field.name) } else { field.name.clone() };
+                values.push(FieldValue {
+                    name,
+                    offset: start,
+                    len: field.size(),
+                    value: field.decode(bytes),
+                });
+            }
+        }
+        values
+    }
+}
+
+impl StructTemplate {
+    pub fn is_template_file(name: &str) -> bool {
+        name.to_ascii_lowercase().ends_with(TEMPLATE_FILE_SUFFIX)
+    }
+
+    /// Parse a template file containing a single template or an array of templates.
+    pub fn parse(json: &[u8]) -> Result<Vec<StructTemplate>, Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum TemplateFile {
+            One(StructTemplate),
+            Many(Vec<StructTemplate>),
+        }
+
+        let templates = match serde_json::from_slice(json)? {
+            TemplateFile::One(template) => vec![template],
+            TemplateFile::Many(templates) => templates,
+        };
+        for template in templates.iter() {
+            template.validate()?;
+        }
+        Ok(templates)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.repeat && self.size == 0 {
+            return Err(anyhow!("Template \"{}\" repeats but has no size", self.name));
+        }
+        if let Some(field) = self.fields.iter().find(|field| field.size() == 0) {
+            return Err(anyhow!("Field \"{}\" of template \"{}\" needs a len", field.name, self.name));
+        }
+        if self.repeat {
+            if let Some(field) = self.fields.iter().find(|field| field.offset + field.size() > self.size) {
+                return Err(anyhow!("Field \"{}\" extends past the size of template \"{}\"", field.name, self.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn builtin_templates() -> Vec<StructTemplate> {
+    use FieldType::*;
+    vec![
+        StructTemplate {
+            name: BPB_TEMPLATE.to_string(),
+            size: 0,
+            repeat: false,
+            fields: vec![
+                TemplateField::new("jump", 0x00, Bytes).with_len(3),
+                TemplateField::new("oem_name", 0x03, Ascii).with_len(8),
+                TemplateField::new("bytes_per_sector", 0x0B, U16),
+                TemplateField::new("sectors_per_cluster", 0x0D, U8),
+                TemplateField::new("reserved_sectors", 0x0E, U16),
+                TemplateField::new("fat_count", 0x10, U8),
+                TemplateField::new("root_entries", 0x11, U16),
+                TemplateField::new("total_sectors", 0x13, U16),
+                TemplateField::new("media_descriptor", 0x15, U8),
+                TemplateField::new("sectors_per_fat", 0x16, U16),
+                TemplateField::new("sectors_per_track", 0x18, U16),
+                TemplateField::new("heads", 0x1A, U16),
+                TemplateField::new("hidden_sectors", 0x1C, U32),
+                TemplateField::new("total_sectors_32", 0x20, U32),
+                TemplateField::new("drive_number", 0x24, U8),
+                TemplateField::new("boot_signature", 0x26, U8),
+                TemplateField::new("volume_id", 0x27, U32),
+                TemplateField::new("volume_label", 0x2B, Ascii).with_len(11),
+                TemplateField::new("fs_type", 0x36, Ascii).with_len(8),
+                TemplateField::new("signature", 0x1FE, U16),
+            ],
+        },
+        StructTemplate {
+            name: DIR_ENTRY_TEMPLATE.to_string(),
+            size: 32,
+            repeat: true,
+            fields: vec![
+                TemplateField::new("name", 0, Ascii).with_len(8),
+                TemplateField::new("ext", 8, Ascii).with_len(3),
+                TemplateField::new("attributes", 11, U8),
+                TemplateField::new("time", 22, FatTime),
+                TemplateField::new("date", 24, FatDate),
+                TemplateField::new("cluster", 26, U16),
+                TemplateField::new("size", 28, U32),
+            ],
+        },
+        StructTemplate {
+            name: FAT12_TEMPLATE.to_string(),
+            size: 3,
+            repeat: true,
+            fields: vec![TemplateField::new("entries", 0, U12Pair)],
+        },
+        StructTemplate {
+            name: FAT16_TEMPLATE.to_string(),
+            size: 2,
+            repeat: true,
+            fields: vec![TemplateField::new("entry", 0, U16)],
+        },
+    ]
+}
+
+/// The built-in template for a sector, based on its role in the FAT volume.
+pub fn template_for_role(volume: &FatVolume, role: SectorRole) -> Option<&'static str> {
+    match role {
+        SectorRole::Boot => Some(BPB_TEMPLATE),
+        SectorRole::Fat => match volume.fat_type {
+            FatType::Fat12 => Some(FAT12_TEMPLATE),
+            FatType::Fat16 => Some(FAT16_TEMPLATE),
+        },
+        SectorRole::Directory => Some(DIR_ENTRY_TEMPLATE),
+        SectorRole::Reserved | SectorRole::Data => None,
+    }
+}
+
+/// Where in the sector at `lba` the template suggested for its role should be applied. FAT12
+/// packs two entries into each three bytes, so a FAT sector that doesn't start on a pair opens
+/// with the end of one split from the sector before; its first whole pair follows.
+pub fn template_offset(volume: &FatVolume, lba: usize) -> usize {
+    let bpb = &volume.bpb;
+    if volume.fat_type != FatType::Fat12 || volume.sector_role(lba) != SectorRole::Fat {
+        return 0;
+    }
+    // Each copy of the FAT starts on a sector, so the phase runs from the start of the copy.
+    let sector_in_copy = (lba - bpb.fat_lba()) % bpb.sectors_per_fat as usize;
+    let phase = sector_in_copy * bpb.bytes_per_sector as usize % 3;
+    (3 - phase) % 3
+}
@@ -0,0 +1,171 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Non-blocking notifications for background completions, stacked in the bottom right corner
+//! of the window so results are seen whichever panels are open.
+
+use egui::{Align2, Color32};
+
+/// How long a toast is shown before it dismisses itself, in seconds.
+pub const TOAST_DURATION_SECS: f64 = 6.0;
+pub const TOAST_WIDTH: f32 = 280.0;
+const MAX_TOASTS: usize = 5;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ToastLevel {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl ToastLevel {
+    fn icon(&self) -> &'static str {
+        match self {
+            ToastLevel::Info => "ℹ",
+            ToastLevel::Success => "✔",
+            ToastLevel::Warning => "⚠",
+            ToastLevel::Error => "✖",
+        }
+    }
+
+    fn color(&self, visuals: &egui::Visuals) -> Color32 {
+        match self {
+            ToastLevel::Info => visuals.text_color(),
+            ToastLevel::Success => Color32::from_rgb(0x38, 0xb7, 0x64),
+            ToastLevel::Warning => visuals.warn_fg_color,
+            ToastLevel::Error => visuals.error_fg_color,
+        }
+    }
+}
+
+pub struct Toast {
+    pub level: ToastLevel,
+    pub title: String,
+    pub details: Option<String>,
+    /// When the toast was first shown, in egui input time.
+    shown_at: Option<f64>,
+    expanded: bool,
+}
+
+impl Toast {
+    /// Attach details, shown when the toast is clicked. Expanded toasts stay until closed.
+    pub fn with_details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct Toasts {
+    items: Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn push(&mut self, toast: Toast) {
+        self.items.push(toast);
+        if self.items.len() > MAX_TOASTS {
+            self.items.remove(0);
+        }
+    }
+
+    pub fn toast(level: ToastLevel, title: impl Into<String>) -> Toast {
+        Toast {
+            level,
+            title: title.into(),
+            details: None,
+            shown_at: None,
+            expanded: false,
+        }
+    }
+
+    pub fn success(&mut self, title: impl Into<String>) {
+        self.push(Toasts::toast(ToastLevel::Success, title));
+    }
+
+    pub fn warning(&mut self, title: impl Into<String>, details: impl Into<String>) {
+        self.push(Toasts::toast(ToastLevel::Warning, title).with_details(details));
+    }
+
+    pub fn error(&mut self, title: impl Into<String>, details: impl Into<String>) {
+        self.push(Toasts::toast(ToastLevel::Error, title).with_details(details));
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if self.items.is_empty() {
+            return;
+        }
+
+        let now = ctx.input(|i| i.time);
+        let mut close = None;
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                ui.set_width(TOAST_WIDTH);
+                for (i, toast) in self.items.iter_mut().enumerate() {
+                    toast.shown_at.get_or_insert(now);
+                    let frame = egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(toast.level.color(ui.visuals()), toast.level.icon());
+                            let title = match (&toast.details, toast.expanded) {
+                                (Some(_), false) => format!("{} ⏵", toast.title),
+                                _ => toast.title.clone(),
+                            };
+                            let label = ui.add(egui::Label::new(title).sense(egui::Sense::click()));
+                            if label.clicked() && toast.details.is_some() {
+                                toast.expanded = !toast.expanded;
+                            }
+                            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                if ui.small_button("×").clicked() {
+                                    close = Some(i);
+                                }
+                            });
+                        });
+                        if let (true, Some(details)) = (toast.expanded, &toast.details) {
+                            ui.separator();
+                            ui.label(details);
+                        }
+                    });
+                    // Hovering restarts the countdown, so a toast doesn't vanish while read.
+                    if frame.response.hovered() {
+                        toast.shown_at = Some(now);
+                    }
+                }
+            });
+
+        if let Some(i) = close {
+            self.items.remove(i);
+        }
+        self.items.retain(|toast| {
+            toast.expanded || toast.shown_at.map_or(true, |shown| now - shown < TOAST_DURATION_SECS)
+        });
+        if !self.items.is_empty() {
+            ctx.request_repaint_after(std::time::Duration::from_millis(250));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A list of every track on the disk with what was found on it. Tracks with no sectors, which
+//! the visualization hatches, are flagged with the reason, so a blank-looking ring can be told
+//! apart from one that failed to render.
+
+use fluxfox::{DiskCh, DiskImage};
+
+use crate::viz::{build_track_layout, TrackLayout};
+
+pub const TRACK_LIST_MAX_HEIGHT: f32 = 320.0;
+
+/// A panel listing every track. The layouts are built the first time the panel is opened.
+#[derive(Default)]
+pub struct TrackList {
+    tracks: Option<Vec<TrackLayout>>,
+    only_empty: bool,
+    selected: Option<DiskCh>,
+}
+
+impl TrackList {
+    pub fn clear(&mut self) {
+        self.tracks = None;
+        self.selected = None;
+    }
+
+    pub fn select(&mut self, ch: Option<DiskCh>) {
+        self.selected = ch;
+    }
+
+    fn load(&mut self, disk: &DiskImage) {
+        let heads = disk.get_sector_map().len() as u8;
+        let mut tracks: Vec<TrackLayout> = (0..heads).flat_map(|head| build_track_layout(disk, head)).collect();
+        tracks.sort_by_key(|track| (track.ch.c(), track.ch.h()));
+        self.tracks = Some(tracks);
+    }
+
+    /// Show the panel. Returns a track to select if the user clicked one.
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &DiskImage) -> Option<DiskCh> {
+        let mut select = None;
+        egui::CollapsingHeader::new("Tracks").id_salt("track_list").show(ui, |ui| {
+            if self.tracks.is_none() {
+                self.load(disk);
+            }
+            let Some(tracks) = &self.tracks
+            else {
+                return;
+            };
+
+            let empty = tracks.iter().filter(|track| track.is_empty()).count();
+            ui.horizontal(|ui| {
+                ui.label(format!("{} tracks, {} without sectors", tracks.len(), empty));
+                ui.checkbox(&mut self.only_empty, "Only tracks without sectors");
+            });
+            if empty > 0 {
+                ui.weak("Tracks without sectors are hatched on the visualization.");
+            }
+
+            let shown: Vec<&TrackLayout> = tracks.iter().filter(|track| !self.only_empty || track.is_empty()).collect();
+            egui::ScrollArea::vertical()
+                .id_salt("track_list_rows")
+                .max_height(TRACK_LIST_MAX_HEIGHT)
+                .show(ui, |ui| {
+                    egui::Grid::new("track_list_grid").striped(true).num_columns(3).show(ui, |ui| {
+                        for track in shown {
+                            let mut label = egui::RichText::new(track.ch.to_string()).monospace();
+                            if Some(track.ch) == self.selected {
+                                label = label.strong();
+                            }
+                            if ui.link(label).clicked() {
+                                select = Some(track.ch);
+                            }
+                            match track.is_empty() {
+                                true => ui.colored_label(ui.visuals().warn_fg_color, track.empty_reason()),
+                                false => ui.label(format!("{} sectors", track.sectors.len())),
+                            };
+                            ui.weak(format!("{} bitcells, {} elements", track.bit_len, track.elements));
+                            ui.end_row();
+                        }
+                    });
+                });
+        });
+        if select.is_some() {
+            self.selected = select;
+        }
+        select
+    }
+}
@@ -0,0 +1,329 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Transformations that correct common dumping mistakes by remapping the tracks of the loaded
+//! image, with a preview of the mapping and an undo stack.
+//!
+//! Tracks are rebuilt sector by sector into a fresh image of the standard geometry the moved
+//! tracks are laid out in, with as many cylinders as the transform leaves. Only sector data
+//! survives the rebuild: the tracks' flux, weak bits and gaps are those of a freshly formatted
+//! disk, and tracks moved in from outside the image are left blank.
+
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{anyhow, bail, Error};
+use fluxfox::DiskImage;
+
+use crate::analysis::geometry::{known_geometries, StandardGeometry};
+use crate::analysis::most_common;
+use crate::checksum::Digests;
+use crate::copy::{self, CopyReport, CopySettings, SectorOriginal};
+
+/// An undo level can hold a whole disk image, so only a few are kept.
+pub const MAX_UNDO_DEPTH: usize = 4;
+/// The number of track mappings listed in the preview.
+pub const PREVIEW_ROWS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transform {
+    SwapHeads,
+    /// Move every track this many cylinders outward, or inward if negative.
+    ShiftCylinders(i16),
+    ReverseTracks,
+    /// Take every other track, starting at cylinder `offset`, from an image of a 40-track disk
+    /// read in an 80-track drive.
+    DeDoubleStep(u16),
+    /// Drop the cylinders past this many.
+    Trim(u16),
+}
+
+impl Transform {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Transform::SwapHeads => "Swap heads",
+            Transform::ShiftCylinders(_) => "Shift cylinders",
+            Transform::ReverseTracks => "Reverse track order",
+            Transform::DeDoubleStep(_) => "De-double-step",
+            Transform::Trim(_) => "Trim trailing cylinders",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Transform::ShiftCylinders(shift) => format!("Shift cylinders by {:+}", shift),
+            Transform::Trim(cylinders) => format!("Trim to {} cylinders", cylinders),
+            _ => self.label().to_string(),
+        }
+    }
+
+    /// The number of cylinders an image of `cylinders` has after the transform.
+    pub fn cylinders_after(&self, cylinders: u16) -> u16 {
+        match self {
+            Transform::ShiftCylinders(shift) if *shift < 0 => cylinders.saturating_sub(shift.unsigned_abs()),
+            Transform::DeDoubleStep(offset) => cylinders.saturating_sub(*offset).div_ceil(2),
+            Transform::Trim(keep) => cylinders.min(*keep),
+            _ => cylinders,
+        }
+    }
+
+    /// The track that ends up on track `c`, `h` of an image of `cylinders` and `heads`, or None
+    /// if the position is left without one.
+    pub fn source(&self, cylinders: u16, heads: u8, c: u16, h: u8) -> Option<(u16, u8)> {
+        match self {
+            Transform::SwapHeads => Some((c, heads - 1 - h)),
+            Transform::ShiftCylinders(shift) => {
+                let source = c as i32 - *shift as i32;
+                (0..cylinders as i32).contains(&source).then_some((source as u16, h))
+            }
+            Transform::ReverseTracks => Some((cylinders - 1 - c, h)),
+            Transform::DeDoubleStep(offset) => Some((c * 2 + offset, h)).filter(|(source, _)| *source < cylinders),
+            Transform::Trim(_) => Some((c, h)),
+        }
+    }
+
+    /// The standard geometry of `disk` after the transform: the one the tracks it keeps are laid
+    /// out in, with as many cylinders as it leaves.
+    pub fn geometry_after(&self, disk: &DiskImage) -> Result<StandardGeometry, Error> {
+        let (cylinders, heads) = dimensions(disk);
+        let after = self.cylinders_after(cylinders);
+        let sector_map = &disk.get_sector_map();
+        let transform = *self;
+        let kept_tracks = move || {
+            (0..after)
+                .flat_map(move |c| (0..heads).map(move |h| (c, h)))
+                .filter_map(move |(c, h)| transform.source(cylinders, heads, c, h))
+                .filter_map(move |(c, h)| sector_map.get(h as usize).and_then(|cylinders| cylinders.get(c as usize)))
+        };
+        let (Some(sectors), Some(sector_size)) = (
+            most_common(kept_tracks().map(|entries| entries.len()).filter(|sectors| *sectors > 0)),
+            most_common(kept_tracks().flatten().map(|entry| entry.chsn.n_size())),
+        )
+        else {
+            bail!("{} would leave no sectors on the image.", self.describe());
+        };
+        known_geometries()
+            .find(|geometry| {
+                geometry.cylinders == after
+                    && geometry.heads == heads
+                    && geometry.sectors as usize == sectors
+                    && geometry.sector_size == sector_size
+            })
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} would leave {} cylinders of {} {}-byte sectors per track, which isn't a standard geometry.",
+                    self.describe(),
+                    after,
+                    sectors,
+                    sector_size
+                )
+            })
+    }
+
+    /// Rebuild `disk` with its tracks moved, in the geometry the transform leaves it with.
+    pub fn apply(&self, disk: &mut DiskImage) -> Result<(DiskImage, CopyReport), Error> {
+        let (cylinders, heads) = dimensions(disk);
+        let settings = CopySettings {
+            geometry: self.geometry_after(disk)?,
+            ..CopySettings::default()
+        };
+        copy::rebuild_image(disk, &settings, |c, h| self.source(cylinders, heads, c, h))
+    }
+}
+
+/// The number of cylinders and heads `disk` has tracks on.
+pub fn dimensions(disk: &DiskImage) -> (u16, u8) {
+    let sector_map = disk.get_sector_map();
+    let heads = sector_map.iter().take_while(|cylinders| !cylinders.is_empty()).count() as u8;
+    let cylinders = sector_map.first().map_or(0, |cylinders| cylinders.len() as u16);
+    (cylinders, heads)
+}
+
+/// What's needed to reverse a change to the image.
+pub enum UndoChange {
+    /// The whole previous image, for a change that replaced it.
+    Image(DiskImage),
+    /// The previous contents of sectors overwritten in place.
+    Sectors(Vec<SectorOriginal>),
+}
+
+/// A previous state of the image, kept so a change to it can be undone.
+pub struct UndoEntry {
+    pub change: UndoChange,
+    pub name: Option<String>,
+    pub len: usize,
+    pub digests: Option<Digests>,
+    /// The SHA-1 of the file the image was loaded from, which in-place changes keep.
+    pub hash: Option<String>,
+    /// What was done to the image after this entry was saved.
+    pub description: String,
+}
+
+#[derive(Default)]
+pub struct UndoStack {
+    entries: VecDeque<UndoEntry>,
+}
+
+impl UndoStack {
+    pub fn push(&mut self, entry: UndoEntry) {
+        if self.entries.len() == MAX_UNDO_DEPTH {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        self.entries.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// What undoing will reverse, if anything.
+    pub fn next_undo(&self) -> Option<&str> {
+        self.entries.back().map(|entry| entry.description.as_str())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TransformKind {
+    SwapHeads,
+    ShiftCylinders,
+    ReverseTracks,
+}
+
+/// A window for choosing a transform and previewing how it moves tracks.
+pub struct TransformDialog {
+    pub open: bool,
+    kind: TransformKind,
+    shift: i16,
+    cylinders: u16,
+    heads: u8,
+    /// The number of sectors on each track of the image, by cylinder and head.
+    track_sectors: HashMap<(u16, u8), usize>,
+}
+
+impl Default for TransformDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            kind: TransformKind::SwapHeads,
+            shift: -1,
+            cylinders: 0,
+            heads: 0,
+            track_sectors: HashMap::new(),
+        }
+    }
+}
+
+impl TransformDialog {
+    pub fn open(&mut self, disk: &DiskImage) {
+        (self.cylinders, self.heads) = dimensions(disk);
+        self.track_sectors.clear();
+        for (head, cylinders) in disk.get_sector_map().iter().enumerate() {
+            for (cylinder, entries) in cylinders.iter().enumerate() {
+                self.track_sectors.insert((cylinder as u16, head as u8), entries.len());
+            }
+        }
+        self.open = true;
+    }
+
+    fn transform(&self) -> Transform {
+        match self.kind {
+            TransformKind::SwapHeads => Transform::SwapHeads,
+            TransformKind::ShiftCylinders => Transform::ShiftCylinders(self.shift),
+            TransformKind::ReverseTracks => Transform::ReverseTracks,
+        }
+    }
+
+    /// Show the dialog. Returns the transform when the user applies it.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<Transform> {
+        let mut apply = None;
+        let mut open = self.open;
+        egui::Window::new("Transform image")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if self.cylinders == 0 {
+                    ui.colored_label(ui.visuals().warn_fg_color, "The image has no tracks to move.");
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.kind, TransformKind::SwapHeads, "Swap heads");
+                    ui.selectable_value(&mut self.kind, TransformKind::ShiftCylinders, "Shift cylinders");
+                    ui.selectable_value(&mut self.kind, TransformKind::ReverseTracks, "Reverse track order");
+                });
+                if self.kind == TransformKind::ShiftCylinders {
+                    let max = self.cylinders as i16 - 1;
+                    ui.horizontal(|ui| {
+                        ui.label("Shift by:");
+                        ui.add(egui::DragValue::new(&mut self.shift).range(-max..=max));
+                    });
+                }
+
+                let transform = self.transform();
+                let (cylinders, heads) = (self.cylinders, self.heads);
+                let after = transform.cylinders_after(cylinders);
+                ui.separator();
+                ui.label("Preview:");
+                egui::Grid::new("transform_preview_grid").striped(true).num_columns(3).show(ui, |ui| {
+                    ui.strong("Track");
+                    ui.strong("Moved from");
+                    ui.strong("Sectors");
+                    ui.end_row();
+                    let tracks = (0..after).flat_map(|c| (0..heads).map(move |h| (c, h)));
+                    for (c, h) in tracks.take(PREVIEW_ROWS) {
+                        ui.monospace(format!("c:{} h:{}", c, h));
+                        match transform.source(cylinders, heads, c, h) {
+                            Some((source_c, source_h)) => {
+                                ui.monospace(format!("c:{} h:{}", source_c, source_h));
+                                let sectors = self.track_sectors.get(&(source_c, source_h)).copied().unwrap_or(0);
+                                ui.label(sectors.to_string());
+                            }
+                            None => {
+                                ui.weak("(blank)");
+                                ui.label("0");
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                if transform == Transform::SwapHeads && heads == 1 {
+                    ui.weak("This image is single sided, so swapping heads does nothing.");
+                }
+                if after < cylinders {
+                    ui.weak(format!("{} of the {} cylinders are dropped.", cylinders - after, cylinders));
+                }
+                if ui.button("Apply").clicked() {
+                    apply = Some(transform);
+                }
+            });
+        self.open = open && apply.is_none();
+        apply
+    }
+}
@@ -0,0 +1,225 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Explaining files that no image parser recognized, and optionally reporting them.
+//!
+//! When a dropped file fails format detection, the user is shown what could be told about it:
+//! its size, its first bytes, and whether either looks like something known. If they opt in,
+//! an anonymous report (extension, size and first bytes only, never the name or contents) can
+//! be sent to an endpoint they configure, to help decide which formats to support next.
+
+use crate::analysis::geometry::known_geometries;
+use crate::util;
+
+/// The number of leading bytes shown and reported.
+pub const PROBE_MAGIC_LEN: usize = 16;
+
+/// Signatures of files people commonly drop that aren't disk images, or are images of a kind
+/// fluxfox doesn't read.
+const KNOWN_SIGNATURES: [(&[u8], &str); 8] = [
+    (b"PK\x03\x04", "a ZIP archive with no recognized disk image inside"),
+    (b"Rar!", "a RAR archive"),
+    (b"7z\xBC\xAF", "a 7-Zip archive"),
+    (b"\x1F\x8B", "a gzip-compressed file"),
+    (b"MZ", "a DOS or Windows executable"),
+    (b"WOZ", "an Apple II WOZ flux image"),
+    (b"CAPS", "an IPF (SPS) flux image"),
+    (b"HXCPICFE", "an HxC HFE image"),
+];
+
+/// What could be learned about a file from its bytes alone.
+#[derive(Clone, Debug)]
+pub struct FileProbe {
+    pub name: String,
+    pub size: usize,
+    pub magic: Vec<u8>,
+}
+
+impl FileProbe {
+    pub fn new(name: &str, bytes: &[u8]) -> Self {
+        Self {
+            name: name.to_string(),
+            size: bytes.len(),
+            magic: bytes[..bytes.len().min(PROBE_MAGIC_LEN)].to_vec(),
+        }
+    }
+
+    pub fn extension(&self) -> String {
+        match self.name.rsplit_once('.') {
+            Some((_, ext)) => ext.to_ascii_lowercase(),
+            None => String::new(),
+        }
+    }
+
+    /// The leading bytes as printable ASCII, with dots for everything else.
+    pub fn magic_ascii(&self) -> String {
+        self.magic
+            .iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect()
+    }
+
+    /// Guesses at what the file is, from its signature and size.
+    pub fn observations(&self) -> Vec<String> {
+        let mut observations = Vec::new();
+        if let Some((_, description)) = KNOWN_SIGNATURES.iter().find(|(signature, _)| self.magic.starts_with(signature)) {
+            observations.push(format!("Starts like {}.", description));
+        }
+        if let Some(geometry) = known_geometries().find(|geometry| geometry.size() == self.size) {
+            observations.push(format!("Its size matches a raw {} sector image.", geometry.name));
+        }
+        if self.size % 512 == 0 && observations.is_empty() {
+            observations.push(format!("Its size is a whole number of 512-byte sectors ({}).", self.size / 512));
+        }
+        if self.magic.iter().all(|b| *b == 0) {
+            observations.push("It starts with zeros, so it has no header.".to_string());
+        }
+        observations
+    }
+
+    /// The anonymous report: nothing that identifies the file or its owner.
+    pub fn report_json(&self) -> String {
+        serde_json::json!({
+            "app_version": env!("CARGO_PKG_VERSION"),
+            "extension": self.extension(),
+            "size": self.size,
+            "magic": util::hex_string(&self.magic),
+        })
+        .to_string()
+    }
+}
+
+/// Opt-in settings for unsupported format reports, persisted between sessions.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct FormatReports {
+    /// The URL reports are posted to. Nothing is sent while it's empty.
+    pub endpoint: String,
+}
+
+impl FormatReports {
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.label("Reports are only sent when you choose to, from the dialog shown for a file that isn't recognized.");
+        ui.horizontal(|ui| {
+            ui.label("Endpoint:");
+            ui.add(egui::TextEdit::singleline(&mut self.endpoint).hint_text("https://..."));
+        });
+    }
+}
+
+/// Send a report with the browser's beacon API, which doesn't wait for or expose a reply.
+fn send_report(endpoint: &str, json: &str) -> Result<(), String> {
+    let window = web_sys::window().ok_or("No window")?;
+    match window.navigator().send_beacon_with_opt_str(endpoint, Some(json)) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("The browser refused to queue the report".to_string()),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+/// The dialog shown when a dropped file wasn't recognized.
+#[derive(Default)]
+pub struct UnsupportedDialog {
+    probe: Option<FileProbe>,
+    error: String,
+    consent: bool,
+    sent: Option<Result<(), String>>,
+}
+
+impl UnsupportedDialog {
+    pub fn open(&mut self, probe: FileProbe, error: String) {
+        *self = UnsupportedDialog {
+            probe: Some(probe),
+            error,
+            ..Default::default()
+        };
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, reports: &mut FormatReports) {
+        let Some(probe) = &self.probe
+        else {
+            return;
+        };
+
+        let mut open = true;
+        egui::Window::new("Unrecognized file")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} isn't a disk image format that can be read.", probe.name));
+                ui.weak(&self.error);
+                ui.separator();
+                egui::Grid::new("unsupported_probe").num_columns(2).show(ui, |ui| {
+                    ui.label("Size:");
+                    ui.label(format!("{} bytes", probe.size));
+                    ui.end_row();
+                    ui.label("First bytes:");
+                    ui.monospace(util::hex_string(&probe.magic));
+                    ui.end_row();
+                    ui.label("");
+                    ui.monospace(probe.magic_ascii());
+                    ui.end_row();
+                });
+                for observation in probe.observations() {
+                    ui.label(observation);
+                }
+
+                ui.separator();
+                ui.collapsing("Report this format", |ui| {
+                    ui.label(
+                        "An anonymous report helps decide which formats to support. It contains only the file's \
+                         extension, size and first 16 bytes; never its name or contents.",
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("Endpoint:");
+                        ui.text_edit_singleline(&mut reports.endpoint);
+                    });
+                    ui.checkbox(&mut self.consent, "I agree to send this report");
+                    ui.collapsing("Report contents", |ui| {
+                        ui.monospace(probe.report_json());
+                    });
+                    let can_send = self.consent && !reports.endpoint.trim().is_empty() && self.sent.is_none();
+                    if ui.add_enabled(can_send, egui::Button::new("Send report")).clicked() {
+                        self.sent = Some(send_report(reports.endpoint.trim(), &probe.report_json()));
+                    }
+                    match &self.sent {
+                        Some(Ok(())) => {
+                            ui.label("Report sent. Thank you!");
+                        }
+                        Some(Err(e)) => {
+                            ui.colored_label(ui.visuals().error_fg_color, format!("Couldn't send report: {}", e));
+                        }
+                        None => {}
+                    }
+                });
+            });
+        if !open {
+            self.probe = None;
+        }
+    }
+}
@@ -25,17 +25,13 @@
     --------------------------------------------------------------------------
 */
 
-use eframe::wasm_bindgen::prelude::wasm_bindgen;
-
-#[wasm_bindgen(module = "/assets/base_url.js")]
-extern "C" {
-    fn getBaseURL() -> String;
+/// Format a byte slice as a lowercase hex string.
+pub(crate) fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-pub(crate) fn construct_full_url(relative_path: &str) -> String {
-    //unsafe {
-        let base_url = getBaseURL(); // Get the base URL from JS
-        format!("{}/{}", base_url.trim_end_matches('/'), relative_path.trim_start_matches('/'))
-    //}
+/// Calculate the SHA-1 hash of `data` as a hex string.
+pub(crate) fn sha1_hex(data: &[u8]) -> String {
+    use sha1::Digest;
+    hex_string(&sha1::Sha1::digest(data))
 }
-
@@ -0,0 +1,256 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! An on-demand scan for the boot sector and file viruses common on period DOS disks.
+//!
+//! The signature set is small: the text most of these viruses carry, which is unambiguous, and
+//! for boot viruses without any (Michelangelo and the many Stoned variants) a heuristic for
+//! boot code that takes memory from the top of conventional memory to stay resident, which
+//! legitimate boot sectors never do.
+
+use fluxfox::DiskImage;
+
+use crate::analysis::{read_all_sectors, SectorKey};
+use crate::fat::browser::BrowserEvent;
+use crate::fat::exe::is_executable;
+use crate::fat::{flatten, FatVolume, FileNode};
+
+pub const SCAN_MAX_HEIGHT: f32 = 240.0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VirusKind {
+    Boot,
+    File,
+}
+
+pub struct Signature {
+    pub name: &'static str,
+    pub kind: VirusKind,
+    pub pattern: &'static [u8],
+    /// Whether the pattern only counts at the start of a sector, as for loader code.
+    pub at_start: bool,
+}
+
+impl Signature {
+    const fn new(name: &'static str, kind: VirusKind, pattern: &'static [u8]) -> Self {
+        Self { name, kind, pattern, at_start: false }
+    }
+
+    const fn at_start(name: &'static str, kind: VirusKind, pattern: &'static [u8]) -> Self {
+        Self { name, kind, pattern, at_start: true }
+    }
+
+    fn find(&self, data: &[u8]) -> Option<usize> {
+        match self.at_start {
+            true => data.starts_with(self.pattern).then_some(0),
+            false => find(data, self.pattern),
+        }
+    }
+}
+
+pub const SIGNATURES: [Signature; 10] = [
+    Signature::new("Stoned", VirusKind::Boot, b"Your PC is now Stoned"),
+    Signature::new("Stoned", VirusKind::Boot, b"LEGALISE MARIJUANA"),
+    Signature::new("Brain", VirusKind::Boot, b"Welcome to the Dungeon"),
+    Signature::new("Form", VirusKind::Boot, b"The FORM-Virus sends greetings"),
+    Signature::new("Joshi", VirusKind::Boot, b"Happy Birthday Joshi"),
+    Signature::new("Disk Killer", VirusKind::Boot, b"Disk Killer"),
+    // A far jump to 07C0:0005, which Stoned uses to normalize its code segment.
+    Signature::at_start("Stoned (loader)", VirusKind::Boot, &[0xEA, 0x05, 0x00, 0xC0, 0x07]),
+    Signature::new("Jerusalem", VirusKind::File, b"sUMsDos"),
+    Signature::new("Tequila", VirusKind::File, b"T.TEQUILA"),
+    Signature::new("Vacsina", VirusKind::File, b"VACSINA"),
+];
+
+/// Instructions that read or write the BIOS memory size at 0040:0013, as seen from segment 0.
+const MEMORY_SIZE_ACCESS: [&[u8]; 4] = [
+    &[0xA1, 0x13, 0x04],       // mov ax, [0413]
+    &[0xA3, 0x13, 0x04],       // mov [0413], ax
+    &[0xFF, 0x0E, 0x13, 0x04], // dec word [0413]
+    &[0x83, 0x2E, 0x13, 0x04], // sub word [0413], imm8
+];
+
+/// The end of the BIOS Parameter Block, whose bytes are data rather than code.
+const BPB_END: usize = 0x3E;
+
+#[derive(Clone, Debug)]
+pub enum Location {
+    Sector(SectorKey),
+    File(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub name: String,
+    pub location: Location,
+    /// Where the signature was found within the sector or file.
+    pub offset: usize,
+    pub heuristic: bool,
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|window| window == pattern)
+}
+
+/// Whether a boot sector's code touches the BIOS memory size, skipping the BPB if it has one.
+fn takes_memory(boot: &[u8]) -> Option<usize> {
+    let code_start = match boot.first() {
+        Some(0xEB) | Some(0xE9) if boot.len() > BPB_END => BPB_END,
+        _ => 0,
+    };
+    MEMORY_SIZE_ACCESS.iter().find_map(|pattern| find(&boot[code_start..], pattern).map(|offset| code_start + offset))
+}
+
+/// Scan one sector for boot viruses, with the heuristic too if it's the boot sector.
+pub fn scan_sector(key: SectorKey, data: &[u8]) -> Vec<Detection> {
+    let mut detections: Vec<Detection> = SIGNATURES
+        .iter()
+        .filter(|signature| signature.kind == VirusKind::Boot)
+        .filter_map(|signature| {
+            signature.find(data).map(|offset| Detection {
+                name: signature.name.to_string(),
+                location: Location::Sector(key),
+                offset,
+                heuristic: false,
+            })
+        })
+        .collect();
+    let is_boot_sector = key.c == 0 && key.h == 0 && key.s == 1;
+    if is_boot_sector && detections.is_empty() {
+        if let Some(offset) = takes_memory(data) {
+            detections.push(Detection {
+                name: "Unknown memory-resident boot virus".to_string(),
+                location: Location::Sector(key),
+                offset,
+                heuristic: true,
+            });
+        }
+    }
+    detections
+}
+
+pub fn scan(disk: &mut DiskImage, fat: Option<(&FatVolume, &[FileNode])>) -> Vec<Detection> {
+    let mut detections = Vec::new();
+    for read in read_all_sectors(disk) {
+        detections.extend(scan_sector(read.key, &read.data));
+    }
+
+    if let Some((volume, tree)) = fat {
+        for node in flatten(tree).into_iter().filter(|node| is_executable(&node.entry)) {
+            let data = volume.read_file(&node.entry);
+            for signature in SIGNATURES.iter().filter(|signature| signature.kind == VirusKind::File) {
+                if let Some(offset) = signature.find(&data) {
+                    detections.push(Detection {
+                        name: signature.name.to_string(),
+                        location: Location::File(node.path.clone()),
+                        offset,
+                        heuristic: false,
+                    });
+                }
+            }
+        }
+    }
+    detections
+}
+
+#[derive(Default)]
+pub struct VirusScan {
+    detections: Option<Vec<Detection>>,
+}
+
+impl VirusScan {
+    pub fn clear(&mut self) {
+        self.detections = None;
+    }
+
+    /// Show the panel. Returns a sector or file to select if the user clicked one.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        disk: &mut DiskImage,
+        fat: Option<(&FatVolume, &[FileNode])>,
+    ) -> Option<BrowserEvent> {
+        let mut event = None;
+        let title = match &self.detections {
+            Some(detections) if !detections.is_empty() => format!("Virus scan: {} found", detections.len()),
+            _ => "Virus scan".to_string(),
+        };
+        egui::CollapsingHeader::new(title).id_salt("virus_scan").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Scan").clicked() {
+                    let detections = scan(disk, fat);
+                    log::info!("Virus scan found {} signatures", detections.len());
+                    self.detections = Some(detections);
+                }
+                if fat.is_none() {
+                    ui.weak("Only sectors are scanned, as there's no FAT filesystem.");
+                }
+            });
+            let Some(detections) = &self.detections
+            else {
+                return;
+            };
+            if detections.is_empty() {
+                ui.label("No known viruses found.");
+                return;
+            }
+
+            egui::ScrollArea::vertical().id_salt("virus_scan_hits").max_height(SCAN_MAX_HEIGHT).show(ui, |ui| {
+                egui::Grid::new("virus_scan_grid").striped(true).num_columns(3).show(ui, |ui| {
+                    ui.strong("Virus");
+                    ui.strong("Found in");
+                    ui.strong("Offset");
+                    ui.end_row();
+
+                    for detection in detections {
+                        if detection.heuristic {
+                            ui.label(format!("{} (heuristic)", detection.name));
+                        }
+                        else {
+                            ui.colored_label(ui.visuals().error_fg_color, &detection.name);
+                        }
+                        match &detection.location {
+                            Location::Sector(key) => {
+                                if ui.link(key.to_string()).clicked() {
+                                    event = Some(BrowserEvent::SelectSector(*key));
+                                }
+                            }
+                            Location::File(path) => {
+                                if ui.link(path).clicked() {
+                                    event = Some(BrowserEvent::SelectFile(path.clone()));
+                                }
+                            }
+                        }
+                        ui.monospace(format!("{:#06X}", detection.offset));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+        event
+    }
+}
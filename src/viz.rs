@@ -26,24 +26,531 @@
 */
 use std::default::Default;
 use std::collections::HashMap;
+use std::f32::consts::TAU;
 use std::sync::{Arc, Mutex};
 use anyhow::{anyhow, Error};
-use fluxfox::{tiny_skia, DiskImage};
+use egui::Pos2;
+use fluxfox::{tiny_skia, DiskCh, DiskChsn, DiskImage};
 use fluxfox::structure_parsers::DiskStructureGenericElement;
-use fluxfox::tiny_skia::{Color, Pixmap};
-use fluxfox::visualization::RenderTrackMetadataParams;
+use fluxfox::tiny_skia::{Color, FillRule, Paint, PathBuilder, Pixmap};
+use fluxfox::visualization::{collect_metadata, collect_streams, RenderTrackMetadataParams, RotationDirection};
 use fluxfox::visualization::render_track_metadata_quadrant;
+use crate::analysis::SectorKey;
+use crate::hard_sector::HardSectoring;
 use crate::App;
-use crate::widgets::texture::{PixelCanvas, PixelCanvasDepth};
+use crate::viz_mesh::{color32, ArcMesh, MeshView, VizRenderer};
+use crate::widgets::texture::{PixelCanvas, PixelCanvasDepth, ZOOM_LUT};
 
 pub const VIZ_RESOLUTION: u32 = 512;
+/// Zoom levels offered for the visualization, as indices into ZOOM_LUT.
+pub const VIZ_ZOOM_LEVELS: std::ops::Range<usize> = 0..4;
+pub const VIZ_MINIMAP_SIZE: f32 = 128.0;
+pub const VIZ_OVERLAY_OPACITY: f32 = 0.85;
+/// Maximum angle in radians covered by a single line segment when approximating an arc.
+pub const ARC_SEGMENT_ANGLE: f32 = 0.02;
+/// Width of point markers, such as write splices, as a fraction of a revolution.
+pub const VIZ_MARKER_WIDTH: f32 = 0.005;
+/// The number of hatch strokes drawn around a track with no sectors, and their width as a
+/// fraction of a revolution.
+pub const EMPTY_TRACK_HATCHES: usize = 72;
+pub const EMPTY_TRACK_HATCH_WIDTH: f32 = 0.004;
+
+/// Parameters controlling how tracks are laid out on the visualization.
+#[derive(Copy, Clone, Debug)]
+pub struct VizGeometry {
+    pub min_radius_fraction: f32,
+    pub track_gap: f32,
+    pub index_angle: f32,
+    pub direction: RotationDirection,
+}
+
+impl Default for VizGeometry {
+    fn default() -> Self {
+        Self {
+            min_radius_fraction: 0.333,
+            track_gap: 0.10,
+            index_angle: 0.0,
+            direction: RotationDirection::CounterClockwise,
+        }
+    }
+}
+
+/// Where the index is drawn on the visualization.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum IndexPosition {
+    #[default]
+    Right,
+    Top,
+    Left,
+    Bottom,
+}
+
+impl IndexPosition {
+    pub const ALL: [IndexPosition; 4] = [IndexPosition::Right, IndexPosition::Top, IndexPosition::Left, IndexPosition::Bottom];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            IndexPosition::Right => "Right",
+            IndexPosition::Top => "Top",
+            IndexPosition::Left => "Left",
+            IndexPosition::Bottom => "Bottom",
+        }
+    }
+
+    /// The angle of the index in screen coordinates, where y increases downwards.
+    pub fn angle(&self) -> f32 {
+        match self {
+            IndexPosition::Right => 0.0,
+            IndexPosition::Top => -TAU / 4.0,
+            IndexPosition::Left => TAU / 2.0,
+            IndexPosition::Bottom => TAU / 4.0,
+        }
+    }
+}
+
+/// User-adjustable visualization layout, persisted between sessions.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct VizSettings {
+    pub min_radius_fraction: f32,
+    pub track_gap: f32,
+    pub clockwise: bool,
+    pub index_position: IndexPosition,
+    pub renderer: VizRenderer,
+}
+
+impl Default for VizSettings {
+    fn default() -> Self {
+        let geometry = VizGeometry::default();
+        Self {
+            min_radius_fraction: geometry.min_radius_fraction,
+            track_gap: geometry.track_gap,
+            clockwise: matches!(geometry.direction, RotationDirection::Clockwise),
+            index_position: IndexPosition::Right,
+            renderer: VizRenderer::default(),
+        }
+    }
+}
+
+impl VizSettings {
+    pub fn geometry(&self) -> VizGeometry {
+        VizGeometry {
+            min_radius_fraction: self.min_radius_fraction,
+            track_gap: self.track_gap,
+            index_angle: self.index_position.angle(),
+            direction: if self.clockwise { RotationDirection::Clockwise } else { RotationDirection::CounterClockwise },
+        }
+    }
+
+    /// Show the settings editor. Returns true if any setting was changed.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        // Re-rendering is too slow to follow a slider drag, so only apply slider changes once
+        // the drag is released.
+        let slider_changed = |response: egui::Response| response.drag_stopped() || (response.changed() && !response.dragged());
+
+        egui::Grid::new("viz_settings_grid").num_columns(2).show(ui, |ui| {
+            ui.label("Inner radius:");
+            changed |= slider_changed(ui.add(egui::Slider::new(&mut self.min_radius_fraction, 0.0..=0.8)));
+            ui.end_row();
+
+            ui.label("Track spacing:");
+            changed |= slider_changed(ui.add(egui::Slider::new(&mut self.track_gap, 0.0..=0.9)));
+            ui.end_row();
+
+            ui.label("Rotation:");
+            ui.horizontal(|ui| {
+                changed |= ui.radio_value(&mut self.clockwise, false, "Counter-clockwise").changed();
+                changed |= ui.radio_value(&mut self.clockwise, true, "Clockwise").changed();
+            });
+            ui.end_row();
+
+            ui.label("Index at:");
+            egui::ComboBox::from_id_salt("viz_settings_index")
+                .selected_text(self.index_position.label())
+                .show_ui(ui, |ui| {
+                    for position in IndexPosition::ALL {
+                        changed |= ui.selectable_value(&mut self.index_position, position, position.label()).changed();
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Renderer:");
+            ui.horizontal(|ui| {
+                for renderer in VizRenderer::ALL {
+                    changed |= ui.radio_value(&mut self.renderer, renderer, renderer.label()).changed();
+                }
+            });
+            ui.end_row();
+        });
+        if ui.button("Reset to defaults").clicked() && *self != VizSettings::default() {
+            *self = VizSettings::default();
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl VizGeometry {
+    /// Return the outer and inner radius of track `ti` when `track_ct` tracks are drawn within
+    /// `total_radius`.
+    pub fn track_radii(&self, ti: usize, track_ct: usize, total_radius: f32) -> (f32, f32) {
+        let min_radius = self.min_radius_fraction * total_radius;
+        let track_width = (total_radius - min_radius) / track_ct.max(1) as f32;
+        let outer = total_radius - ti as f32 * track_width;
+        let inner = outer - track_width * (1.0 - self.track_gap);
+        (outer, inner)
+    }
+
+    /// Return the angle of a point `fraction` of a revolution past the index.
+    pub fn fraction_angle(&self, fraction: f32) -> f32 {
+        let angle = fraction * TAU;
+        match self.direction {
+            RotationDirection::Clockwise => self.index_angle + angle,
+            RotationDirection::CounterClockwise => self.index_angle - angle,
+        }
+    }
+
+    /// Return how far past the index, as a fraction of a revolution, the point at `angle` is.
+    /// This is the inverse of fraction_angle().
+    pub fn angle_fraction(&self, angle: f32) -> f32 {
+        let relative = match self.direction {
+            RotationDirection::Clockwise => angle - self.index_angle,
+            RotationDirection::CounterClockwise => self.index_angle - angle,
+        };
+        relative.rem_euclid(TAU) / TAU
+    }
+}
+
+/// The extent of a sector's data element on a track, as fractions of a revolution from the index.
+/// The extent comes from where the data was found in the track's bitstream, not from its size
+/// code, so sectors of different sizes on one track each get their true share of it. `end` is
+/// past 1.0 when the data runs over the index, as a sector larger than the rest of the track
+/// does.
+#[derive(Clone, Debug)]
+pub struct SectorSpan {
+    pub key: SectorKey,
+    pub chsn: DiskChsn,
+    pub start: f32,
+    pub end: f32,
+    /// The length of the data element in bitcells.
+    pub bits: usize,
+}
+
+impl SectorSpan {
+    /// Whether the point `fraction` of a revolution past the index is within the sector.
+    pub fn contains(&self, fraction: f32) -> bool {
+        (fraction >= self.start && fraction <= self.end) || fraction + 1.0 <= self.end
+    }
+
+    /// Whether the data runs over the index into the start of the track.
+    pub fn wraps(&self) -> bool {
+        self.end > 1.0
+    }
+}
+
+/// The position of each sector on a track, captured when the visualization is rendered so that
+/// overlays and hit testing don't need access to the disk image.
+#[derive(Clone, Debug)]
+pub struct TrackLayout {
+    pub ch: DiskCh,
+    pub bit_len: usize,
+    /// The number of structure elements, such as address marks, found on the track.
+    pub elements: usize,
+    pub sectors: Vec<SectorSpan>,
+}
+
+impl TrackLayout {
+    /// True if no sectors were found on the track, so it looks blank on the visualization.
+    pub fn is_empty(&self) -> bool {
+        self.sectors.is_empty()
+    }
+
+    /// Why the track is empty: either nothing was found in it at all, or marks were found
+    /// without any sector data.
+    pub fn empty_reason(&self) -> &'static str {
+        match (self.bit_len, self.elements) {
+            (0, _) => "no bitstream",
+            (_, 0) => "unformatted",
+            _ => "marks but no sectors",
+        }
+    }
+
+    /// The sector at the point `fraction` of a revolution past the index. Where sectors overlap,
+    /// as when a protection track's oversized sector runs over those after it, this is the last
+    /// on the track, which is the one drawn on top.
+    pub fn sector_at(&self, fraction: f32) -> Option<&SectorSpan> {
+        self.sectors.iter().rev().find(|span| span.contains(fraction))
+    }
+}
+
+/// The result of hit testing a point on the visualization.
+#[derive(Clone, Debug)]
+pub struct VizHit {
+    pub ch: DiskCh,
+    pub bit_offset: usize,
+    pub sector: Option<SectorSpan>,
+}
+
+fn is_sector_data(elem: DiskStructureGenericElement) -> bool {
+    matches!(
+        elem,
+        DiskStructureGenericElement::SectorData
+            | DiskStructureGenericElement::SectorBadData
+            | DiskStructureGenericElement::SectorDeletedData
+            | DiskStructureGenericElement::SectorBadDeletedData
+    )
+}
+
+pub(crate) fn build_track_layout(disk: &DiskImage, head: u8) -> Vec<TrackLayout> {
+    let streams = collect_streams(head, disk);
+    let metadata = collect_metadata(head, disk);
+
+    streams
+        .iter()
+        .zip(metadata.iter())
+        .enumerate()
+        .map(|(ti, (stream, track_meta))| {
+            let ch = DiskCh::new(ti as u16, head);
+            let bit_len = stream.len();
+            let sectors = track_meta
+                .items
+                .iter()
+                .filter(|item| is_sector_data(DiskStructureGenericElement::from(item.elem_type)))
+                .filter_map(|item| {
+                    let chsn = item.chsn?;
+                    // Data can't cover more than one revolution, however large its size code.
+                    let bits = item.end.saturating_sub(item.start).min(bit_len);
+                    Some(SectorSpan {
+                        key: SectorKey::new(ch, chsn.s()),
+                        chsn,
+                        start: item.start as f32 / bit_len.max(1) as f32,
+                        end: (item.start + bits) as f32 / bit_len.max(1) as f32,
+                        bits,
+                    })
+                })
+                .collect();
+            TrackLayout {
+                ch,
+                bit_len,
+                elements: track_meta.items.len(),
+                sectors,
+            }
+        })
+        .collect()
+}
+
+/// Tessellate the elements of one side of the disk that have a color in `palette`, laid out
+/// as the CPU renderer lays them out.
+fn build_metadata_mesh(
+    disk: &DiskImage,
+    head: u8,
+    geometry: &VizGeometry,
+    palette: &HashMap<DiskStructureGenericElement, Color>,
+) -> ArcMesh {
+    let streams = collect_streams(head, disk);
+    let metadata = collect_metadata(head, disk);
+    let track_ct = streams.len();
+
+    let mut mesh = ArcMesh::default();
+    for (ti, (stream, track_meta)) in streams.iter().zip(metadata.iter()).enumerate() {
+        let bit_len = stream.len().max(1) as f32;
+        let radii = geometry.track_radii(ti, track_ct, 1.0);
+        for item in track_meta.items.iter() {
+            if let Some(color) = palette.get(&DiskStructureGenericElement::from(item.elem_type)) {
+                // Elements that run over the index are drawn on past it, up to one revolution.
+                let end = (item.end as f32 / bit_len).min(item.start as f32 / bit_len + 1.0);
+                let angles = (
+                    geometry.fraction_angle(item.start as f32 / bit_len),
+                    geometry.fraction_angle(end),
+                );
+                mesh.add_arc(radii, angles, color32(*color, 1.0));
+            }
+        }
+    }
+    mesh
+}
+
+fn empty_track_hatch_color() -> Color {
+    Color::from_rgba8(128, 128, 128, 96)
+}
+
+/// The arcs of the hatch drawn on each empty track of a side, as track index and angles, so
+/// they aren't mistaken for a failure to render them.
+fn empty_track_hatches<'a>(
+    layout: &'a [TrackLayout],
+    geometry: &'a VizGeometry,
+) -> impl Iterator<Item = (usize, (f32, f32))> + 'a {
+    layout.iter().enumerate().filter(|(_, track)| track.is_empty()).flat_map(move |(ti, _)| {
+        (0..EMPTY_TRACK_HATCHES).map(move |i| {
+            let fraction = i as f32 / EMPTY_TRACK_HATCHES as f32;
+            (ti, (geometry.fraction_angle(fraction), geometry.fraction_angle(fraction + EMPTY_TRACK_HATCH_WIDTH)))
+        })
+    })
+}
+
+/// Additional layers that can be drawn over the metadata visualization.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum VizOverlayMode {
+    #[default]
+    None,
+    Entropy,
+    Annotations,
+    FileClusters,
+    Splices,
+    Dropouts,
+    SectorHoles,
+}
+
+impl VizOverlayMode {
+    pub const ALL: [VizOverlayMode; 7] = [
+        VizOverlayMode::None,
+        VizOverlayMode::Entropy,
+        VizOverlayMode::Annotations,
+        VizOverlayMode::FileClusters,
+        VizOverlayMode::Splices,
+        VizOverlayMode::Dropouts,
+        VizOverlayMode::SectorHoles,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            VizOverlayMode::None => "None",
+            VizOverlayMode::Entropy => "Data entropy",
+            VizOverlayMode::Annotations => "Annotations",
+            VizOverlayMode::FileClusters => "Selected file",
+            VizOverlayMode::Splices => "Write splices",
+            VizOverlayMode::Dropouts => "Dropouts",
+            VizOverlayMode::SectorHoles => "Sector holes",
+        }
+    }
+
+    /// A name for the overlay in exported filenames.
+    pub fn file_stem(&self) -> &'static str {
+        match self {
+            VizOverlayMode::None => "none",
+            VizOverlayMode::Entropy => "entropy",
+            VizOverlayMode::Annotations => "annotations",
+            VizOverlayMode::FileClusters => "file",
+            VizOverlayMode::Splices => "splices",
+            VizOverlayMode::Dropouts => "dropouts",
+            VizOverlayMode::SectorHoles => "holes",
+        }
+    }
+}
+
+fn is_error_element(elem: DiskStructureGenericElement) -> bool {
+    matches!(
+        elem,
+        DiskStructureGenericElement::SectorBadData
+            | DiskStructureGenericElement::SectorBadDeletedData
+            | DiskStructureGenericElement::SectorBadHeader
+    )
+}
+
+/// Return the position of a quadrant's pixmap in a render `resolution` pixels square.
+pub(crate) fn quadrant_origin(quadrant: usize, resolution: u32) -> (u32, u32) {
+    match quadrant {
+        0 => (0, 0),
+        1 => (resolution / 2, 0),
+        2 => (0, resolution / 2),
+        3 => (resolution / 2, resolution / 2),
+        _ => panic!("Invalid quadrant"),
+    }
+}
+
+/// Encode a pixmap as a PNG with straight (not premultiplied) alpha.
+pub(crate) fn encode_png(pixmap: &Pixmap) -> Result<Vec<u8>, Error> {
+    use image::ImageEncoder;
+
+    let data: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png).write_image(
+        &data,
+        pixmap.width(),
+        pixmap.height(),
+        image::ExtendedColorType::Rgba8,
+    )?;
+    Ok(png)
+}
+
+/// Fill the ring segment between `inner` and `outer` radius from angle `start` to `end`.
+pub(crate) fn fill_arc(
+    pixmap: &mut Pixmap,
+    center: (f32, f32),
+    radii: (f32, f32),
+    angles: (f32, f32),
+    color: Color,
+) {
+    let (outer, inner) = radii;
+    let (start, end) = if angles.1 < angles.0 {
+        (angles.1, angles.0)
+    }
+    else {
+        angles
+    };
+    let segments = (((end - start) / ARC_SEGMENT_ANGLE).ceil() as usize).max(1);
+    let point = |radius: f32, i: usize| {
+        let angle = start + (end - start) * (i as f32 / segments as f32);
+        (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+    };
+
+    let mut pb = PathBuilder::new();
+    let (x, y) = point(outer, 0);
+    pb.move_to(x, y);
+    for i in 1..=segments {
+        let (x, y) = point(outer, i);
+        pb.line_to(x, y);
+    }
+    for i in (0..=segments).rev() {
+        let (x, y) = point(inner, i);
+        pb.line_to(x, y);
+    }
+    pb.close();
+
+    if let Some(path) = pb.finish() {
+        let mut paint = Paint::default();
+        paint.set_color(color);
+        paint.anti_alias = true;
+        pixmap.fill_path(&path, &paint, FillRule::Winding, tiny_skia::Transform::identity(), None);
+    }
+}
 
 pub struct VisualizationState {
     pub meta_pixmap_pool: Vec<Arc<Mutex<Pixmap>>>,
     pub metadata_img: [Pixmap; 2],
     pub meta_palette: HashMap<DiskStructureGenericElement, Color>,
+    pub overlays: HashMap<VizOverlayMode, [Pixmap; 2]>,
+    pub overlay_mode: VizOverlayMode,
+    pub geometry: VizGeometry,
+    pub layout: [Vec<TrackLayout>; 2],
+    pub side: usize,
+    pub selection: Option<VizHit>,
+    pub hover: Option<VizHit>,
+    /// The hole layout of a hard-sectored disk, for numbering positions by hard sector.
+    pub hard_sectoring: Option<HardSectoring>,
+    /// Set when the user changes the selection by clicking, until take_clicked() is called.
+    clicked: bool,
+    /// Where the visualization was last drawn, and whether the user asked to copy it.
+    view_rect: Option<egui::Rect>,
+    copy_requested: bool,
     pub have_render: bool,
     pub canvas: Option<PixelCanvas>,
+    pub renderer: VizRenderer,
+    meta_mesh: [ArcMesh; 2],
+    overlay_meshes: HashMap<VizOverlayMode, [ArcMesh; 2]>,
+    mesh_view: MeshView,
+    /// Set for a side when the GPU renderer skipped rasterizing its metadata, which is then
+    /// only rendered on the CPU if it's exported.
+    metadata_stale: [bool; 2],
 }
 
 impl Default for VisualizationState {
@@ -52,8 +559,24 @@ impl Default for VisualizationState {
             meta_pixmap_pool: Vec::new(),
             metadata_img: [Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap(), Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap()],
             meta_palette: HashMap::new(),
+            overlays: HashMap::new(),
+            overlay_mode: VizOverlayMode::None,
+            geometry: VizGeometry::default(),
+            layout: [Vec::new(), Vec::new()],
+            side: 0,
+            selection: None,
+            hover: None,
+            hard_sectoring: None,
+            clicked: false,
+            view_rect: None,
+            copy_requested: false,
             have_render: false,
             canvas: None,
+            renderer: VizRenderer::default(),
+            meta_mesh: Default::default(),
+            overlay_meshes: HashMap::new(),
+            mesh_view: MeshView::new(VIZ_RESOLUTION as f32),
+            metadata_stale: [false; 2],
         }
     }
 }
@@ -104,95 +627,567 @@ impl VisualizationState {
         }
     }
 
+    /// Parameters for rendering the metadata of one side of the disk with the current layout,
+    /// starting at the first quadrant.
+    pub(crate) fn metadata_params(
+        &self,
+        disk: &DiskImage,
+        side: usize,
+        palette: HashMap<DiskStructureGenericElement, Color>,
+    ) -> RenderTrackMetadataParams {
+        RenderTrackMetadataParams {
+            quadrant: 0,
+            head: side as u8,
+            min_radius_fraction: self.geometry.min_radius_fraction,
+            index_angle: self.geometry.index_angle,
+            track_limit: disk.get_track_ct(side.into()),
+            track_gap: self.geometry.track_gap,
+            direction: self.geometry.direction,
+            palette,
+            draw_empty_tracks: true,
+            pin_last_standard_track: true,
+        }
+    }
+
+    /// Render the metadata of one side of the disk, drawing the elements in `palette`, into
+    /// `target`.
+    fn render_metadata(
+        &self,
+        disk: &mut DiskImage,
+        side: usize,
+        palette: HashMap<DiskStructureGenericElement, Color>,
+        target: &mut Pixmap,
+    ) -> Result<(), Error> {
+        let mut render_params = self.metadata_params(disk, side, palette);
+
+        for quadrant in 0..4 {
+
+            render_params.quadrant = quadrant as u8;
+            let mut pixmap = self.meta_pixmap_pool[quadrant].lock().unwrap();
+
+            match render_track_metadata_quadrant(disk, &mut pixmap, &render_params) {
+                Ok(_) => {
+                    log::debug!("...Rendered quadrant {}", quadrant);
+                }
+                Err(e) => {
+                    log::error!("Error rendering quadrant: {}", e);
+                    return Err(anyhow!("Error rendering metadata"));
+                }
+            }
+        }
+
+        for quadrant in 0..4 {
+            log::debug!("Received quadrant {}, compositing...", quadrant);
+            let (x, y) = quadrant_origin(quadrant, VIZ_RESOLUTION);
+
+            let paint = tiny_skia::PixmapPaint::default();
+            //let mut pixmap = self.meta_pixmap_pool[quadrant].lock()?;
+
+            target.draw_pixmap(
+                x as i32,
+                y as i32,
+                self.meta_pixmap_pool[quadrant].lock().unwrap().as_ref(),
+                &paint,
+                tiny_skia::Transform::identity(),
+                None,
+            );
+
+            // Clear pixmap after compositing
+            self.meta_pixmap_pool[quadrant].lock().unwrap().as_mut().fill(Color::TRANSPARENT);
+        }
+        Ok(())
+    }
+
+    /// Draw the hatch over the empty tracks of one side's metadata image.
+    fn hatch_empty_tracks(&self, side: usize, target: &mut Pixmap) {
+        let layout = &self.layout[side];
+        let total_radius = target.width() as f32 / 2.0;
+        for (ti, angles) in empty_track_hatches(layout, &self.geometry) {
+            let radii = self.geometry.track_radii(ti, layout.len(), total_radius);
+            fill_arc(target, (total_radius, total_radius), radii, angles, empty_track_hatch_color());
+        }
+    }
+
     pub(crate) fn render_visualization(&mut self, disk_image: Option<&mut DiskImage>, side: usize) -> Result<(), Error> {
 
         if let Some(disk) = disk_image {
             let head = side as u8;
-            let quadrant = 0;
-            let angle = 0.0;
-            let min_radius_fraction = 0.333;
-            let render_track_gap = 0.10;
-            let direction = fluxfox::visualization::RotationDirection::CounterClockwise;
-
-            let track_ct = disk.get_track_ct(side.into());
-            let mut render_params = RenderTrackMetadataParams {
-                quadrant,
-                head,
-                min_radius_fraction,
-                index_angle: angle,
-                track_limit: track_ct,
-                track_gap: render_track_gap,
-                direction,
-                palette: self.meta_palette.clone(),
-                draw_empty_tracks: true,
-                pin_last_standard_track: true,
-            };
 
-            for quadrant in 0..4 {
+            self.layout[side] = build_track_layout(disk, head);
+            let layout = &self.layout[side];
 
-                render_params.quadrant = quadrant as u8;
-                let mut pixmap = self.meta_pixmap_pool[quadrant].lock().unwrap();
+            if self.renderer == VizRenderer::Gpu {
+                let mut mesh = build_metadata_mesh(disk, head, &self.geometry, &self.meta_palette);
+                let hatch = color32(empty_track_hatch_color(), 1.0);
+                for (ti, angles) in empty_track_hatches(layout, &self.geometry) {
+                    mesh.add_arc(self.geometry.track_radii(ti, layout.len(), 1.0), angles, hatch);
+                }
+                self.meta_mesh[side] = mesh;
+                self.metadata_stale[side] = true;
+            }
+            else {
+                let mut metadata_img = std::mem::replace(&mut self.metadata_img[side], Pixmap::new(1, 1).unwrap());
+                let result = self.render_metadata(disk, side, self.meta_palette.clone(), &mut metadata_img);
+                if result.is_ok() {
+                    self.hatch_empty_tracks(side, &mut metadata_img);
+                }
+                self.metadata_img[side] = metadata_img;
+                result?;
+                self.metadata_stale[side] = false;
+            }
 
-                match render_track_metadata_quadrant(disk, &mut pixmap, &render_params) {
-                    Ok(_) => {
-                        log::debug!("...Rendered quadrant {}", quadrant);
-                    }
-                    Err(e) => {
-                        log::error!("Error rendering quadrant: {}", e);
-                        return Err(anyhow!("Error rendering metadata"));
-                    }
+            self.side = side;
+            self.update_canvas();
+        }
+        Ok(())
+    }
+
+    /// Render an overlay for `mode` over the sector data elements of the specified side.
+    /// `sector_color` is called for each sector and returns the color to paint it with, if any.
+    pub(crate) fn render_sector_overlay(
+        &mut self,
+        side: usize,
+        mode: VizOverlayMode,
+        mut sector_color: impl FnMut(SectorKey) -> Option<Color>,
+    ) {
+        self.render_overlay(side, mode, |_| None, |span| sector_color(span.key));
+    }
+
+    /// Render an overlay for `mode` on the specified side. Tracks for which `track_color`
+    /// returns a color are filled entirely, then sectors are painted with `sector_color`.
+    pub(crate) fn render_overlay(
+        &mut self,
+        side: usize,
+        mode: VizOverlayMode,
+        mut track_color: impl FnMut(DiskCh) -> Option<Color>,
+        mut sector_color: impl FnMut(&SectorSpan) -> Option<Color>,
+    ) {
+        let geometry = self.geometry;
+        let layout = &self.layout[side];
+        let pixmaps = self.overlays.entry(mode).or_insert_with(|| {
+            [
+                Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap(),
+                Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap(),
+            ]
+        });
+        let pixmap = &mut pixmaps[side];
+        pixmap.fill(Color::TRANSPARENT);
+        let mesh = &mut self.overlay_meshes.entry(mode).or_default()[side];
+        mesh.clear();
+
+        let track_ct = layout.len();
+        let total_radius = pixmap.width() as f32 / 2.0;
+        let center = (total_radius, total_radius);
+
+        for (ti, track) in layout.iter().enumerate() {
+            let radii = geometry.track_radii(ti, track_ct, total_radius);
+            let unit_radii = geometry.track_radii(ti, track_ct, 1.0);
+
+            if let Some(color) = track_color(track.ch) {
+                fill_arc(pixmap, center, radii, (0.0, TAU), color);
+                mesh.add_arc(unit_radii, (0.0, TAU), color32(color, VIZ_OVERLAY_OPACITY));
+            }
+
+            for span in &track.sectors {
+                if let Some(color) = sector_color(span) {
+                    let angles = (geometry.fraction_angle(span.start), geometry.fraction_angle(span.end));
+                    fill_arc(pixmap, center, radii, angles, color);
+                    mesh.add_arc(unit_radii, angles, color32(color, VIZ_OVERLAY_OPACITY));
                 }
             }
+        }
 
-            for quadrant in 0..4 {
-                log::debug!("Received quadrant {}, compositing...", quadrant);
-                let (x, y) = match quadrant {
-                    0 => (0, 0),
-                    1 => (VIZ_RESOLUTION / 2, 0),
-                    2 => (0, VIZ_RESOLUTION / 2),
-                    3 => (VIZ_RESOLUTION / 2, VIZ_RESOLUTION / 2),
-                    _ => panic!("Invalid quadrant"),
-                };
-
-                let paint = tiny_skia::PixmapPaint::default();
-                //let mut pixmap = self.meta_pixmap_pool[quadrant].lock()?;
-
-                self.metadata_img[side].draw_pixmap(
-                    x as i32,
-                    y as i32,
-                    self.meta_pixmap_pool[quadrant].lock().unwrap().as_ref(),
-                    &paint,
-                    tiny_skia::Transform::identity(),
-                    None,
-                );
+        if self.overlay_mode == mode && self.side == side {
+            self.update_canvas();
+        }
+    }
+
+    /// Render an overlay of point markers at positions given as (track, fraction of a
+    /// revolution) on the specified side.
+    pub(crate) fn render_marker_overlay(
+        &mut self,
+        side: usize,
+        mode: VizOverlayMode,
+        markers: &[(DiskCh, f32)],
+        color: Color,
+    ) {
+        let geometry = self.geometry;
+        let layout = &self.layout[side];
+        let pixmaps = self.overlays.entry(mode).or_insert_with(|| {
+            [
+                Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap(),
+                Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap(),
+            ]
+        });
+        let pixmap = &mut pixmaps[side];
+        pixmap.fill(Color::TRANSPARENT);
+        let mesh = &mut self.overlay_meshes.entry(mode).or_default()[side];
+        mesh.clear();
 
-                // Clear pixmap after compositing
-                self.meta_pixmap_pool[quadrant].lock().unwrap().as_mut().fill(Color::TRANSPARENT);
+        let total_radius = pixmap.width() as f32 / 2.0;
+        let center = (total_radius, total_radius);
+        for (ch, fraction) in markers {
+            let Some(ti) = layout.iter().position(|track| track.ch == *ch)
+            else {
+                continue;
+            };
+            let radii = geometry.track_radii(ti, layout.len(), total_radius);
+            let angles = (
+                geometry.fraction_angle(fraction - VIZ_MARKER_WIDTH / 2.0),
+                geometry.fraction_angle(fraction + VIZ_MARKER_WIDTH / 2.0),
+            );
+            fill_arc(pixmap, center, radii, angles, color);
+            mesh.add_arc(geometry.track_radii(ti, layout.len(), 1.0), angles, color32(color, VIZ_OVERLAY_OPACITY));
+        }
+
+        if self.overlay_mode == mode && self.side == side {
+            self.update_canvas();
+        }
+    }
+
+    /// Render each layer of the current side as a separate transparent PNG: the metadata, the
+    /// error elements alone, and every overlay that has been rendered. Returns the layer names
+    /// and PNG data.
+    pub(crate) fn export_layers(&self, disk: &mut DiskImage) -> Result<Vec<(&'static str, Vec<u8>)>, Error> {
+        let side = self.side;
+        let mut layers = if self.metadata_stale[side] {
+            let mut metadata = Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap();
+            self.render_metadata(disk, side, self.meta_palette.clone(), &mut metadata)?;
+            self.hatch_empty_tracks(side, &mut metadata);
+            vec![("metadata", encode_png(&metadata)?)]
+        }
+        else {
+            vec![("metadata", encode_png(&self.metadata_img[side])?)]
+        };
+
+        let error_palette = self
+            .meta_palette
+            .iter()
+            .filter(|(elem, _)| is_error_element(**elem))
+            .map(|(elem, color)| (*elem, *color))
+            .collect();
+        let mut errors = Pixmap::new(VIZ_RESOLUTION, VIZ_RESOLUTION).unwrap();
+        self.render_metadata(disk, side, error_palette, &mut errors)?;
+        layers.push(("errors", encode_png(&errors)?));
+
+        for mode in VizOverlayMode::ALL {
+            if let Some(overlay) = self.overlays.get(&mode) {
+                layers.push((mode.file_stem(), encode_png(&overlay[side])?));
+            }
+        }
+        Ok(layers)
+    }
+
+    /// Find the track and sector under `uv`, a point in normalized texture coordinates.
+    pub fn hit_test(&self, uv: Pos2) -> Option<VizHit> {
+        let layout = &self.layout[self.side];
+        let (dx, dy) = ((uv.x - 0.5) * 2.0, (uv.y - 0.5) * 2.0);
+        let radius = (dx * dx + dy * dy).sqrt();
+
+        let ti = (0..layout.len()).find(|ti| {
+            let (outer, inner) = self.geometry.track_radii(*ti, layout.len(), 1.0);
+            radius <= outer && radius >= inner
+        })?;
+
+        let track = &layout[ti];
+        let fraction = self.geometry.angle_fraction(dy.atan2(dx));
+        Some(VizHit {
+            ch: track.ch,
+            bit_offset: (fraction * track.bit_len as f32) as usize,
+            sector: track.sector_at(fraction).cloned(),
+        })
+    }
+
+    /// Which hard sector the hit is in, for the hover text, if the disk is hard sectored.
+    fn hard_sector_label(&self, hit: &VizHit) -> String {
+        let (Some(hard_sectoring), Some(track)) =
+            (&self.hard_sectoring, self.layout[self.side].iter().find(|track| track.ch == hit.ch))
+        else {
+            return String::new();
+        };
+        let fraction = hit.bit_offset as f32 / track.bit_len.max(1) as f32;
+        format!(", hard sector {} of {}", hard_sectoring.sector_at(fraction), hard_sectoring.sectors)
+    }
+
+    /// Select the sector with the specified key, if it exists on the current side.
+    pub fn select_sector(&mut self, key: SectorKey) {
+        let layout = &self.layout[self.side];
+        if let Some(track) = layout.iter().find(|track| track.ch == key.ch()) {
+            if let Some(span) = track.sectors.iter().find(|span| span.key == key) {
+                self.selection = Some(VizHit {
+                    ch: track.ch,
+                    bit_offset: (span.start * track.bit_len as f32) as usize,
+                    sector: Some(span.clone()),
+                });
             }
+        }
+    }
 
-            if let Some(canvas) = &mut self.canvas {
-                if canvas.has_texture() {
-                    log::debug!("Updating canvas...");
-                    log::debug!("pixmap data slice: {:0X?}", &self.metadata_img[side].data()[0..16]);
-                    canvas.update_data(self.metadata_img[side].data());
-                    self.have_render = true;
+    /// The selection, if the user changed it by clicking since the last call.
+    pub fn take_clicked(&mut self) -> Option<&VizHit> {
+        match std::mem::take(&mut self.clicked) {
+            true => self.selection.as_ref(),
+            false => None,
+        }
+    }
+
+    /// Where the visualization was drawn this frame, if the user asked to copy it.
+    pub fn take_copy_request(&mut self) -> Option<egui::Rect> {
+        match std::mem::take(&mut self.copy_requested) {
+            true => self.view_rect,
+            false => None,
+        }
+    }
+
+    pub fn select_track(&mut self, ch: DiskCh) {
+        self.select_position(ch, 0);
+    }
+
+    /// Select the point `bit_offset` bits into a track, and the sector there, if any.
+    pub fn select_position(&mut self, ch: DiskCh, bit_offset: usize) {
+        let sector = self.layout[self.side]
+            .iter()
+            .find(|track| track.ch == ch)
+            .and_then(|track| {
+                let fraction = bit_offset as f32 / track.bit_len.max(1) as f32;
+                track.sector_at(fraction).cloned()
+            });
+        self.selection = Some(VizHit { ch, bit_offset, sector });
+    }
+
+    /// Move the visualization to a new egui context, uploading it again from its retained
+    /// pixels.
+    pub(crate) fn set_context(&mut self, ctx: egui::Context) {
+        if let Some(canvas) = &mut self.canvas {
+            canvas.set_context(ctx);
+        }
+    }
+
+    /// Scroll the canvas so the current selection is in view.
+    pub fn focus_selection(&mut self) {
+        let Some(selection) = &self.selection
+        else {
+            return;
+        };
+        let layout = &self.layout[self.side];
+        let Some(ti) = layout.iter().position(|track| track.ch == selection.ch)
+        else {
+            return;
+        };
+
+        let (outer, inner) = self.geometry.track_radii(ti, layout.len(), 1.0);
+        let fraction = match &selection.sector {
+            Some(span) => (span.start + span.end) / 2.0,
+            None => selection.bit_offset as f32 / layout[ti].bit_len.max(1) as f32,
+        };
+        let angle = self.geometry.fraction_angle(fraction);
+        let radius = (outer + inner) / 2.0;
+        let uv = egui::pos2(0.5 + radius * angle.cos() / 2.0, 0.5 + radius * angle.sin() / 2.0);
+
+        if let Some(canvas) = &mut self.canvas {
+            canvas.scroll_to_uv(uv);
+        }
+        self.mesh_view.scroll_to_uv(uv);
+    }
+
+    /// Outline the selected sector, or track if no sector is selected, on the canvas.
+    fn paint_selection(&self, ui: &egui::Ui, image_rect: egui::Rect, clip_rect: egui::Rect) {
+        let Some(selection) = &self.selection
+        else {
+            return;
+        };
+        let layout = &self.layout[self.side];
+        let Some(ti) = layout.iter().position(|track| track.ch == selection.ch)
+        else {
+            return;
+        };
+
+        let (outer, inner) = self.geometry.track_radii(ti, layout.len(), image_rect.width() / 2.0);
+        let (start, end) = match &selection.sector {
+            Some(span) => (span.start, span.end),
+            None => (0.0, 1.0),
+        };
+
+        let segments = ((end - start) * TAU / ARC_SEGMENT_ANGLE).ceil().max(1.0) as usize;
+        let center = image_rect.center();
+        let point = |radius: f32, i: usize| {
+            let angle = self.geometry.fraction_angle(start + (end - start) * (i as f32 / segments as f32));
+            center + radius * egui::vec2(angle.cos(), angle.sin())
+        };
+
+        let mut points: Vec<Pos2> = (0..=segments).map(|i| point(outer, i)).collect();
+        points.extend((0..=segments).rev().map(|i| point(inner, i)));
+
+        ui.painter_at(clip_rect).add(egui::Shape::closed_line(
+            points,
+            egui::Stroke::new(2.0, egui::Color32::WHITE),
+        ));
+    }
+
+    /// Composite the current side's metadata image with the active overlay and upload it to
+    /// the canvas.
+    pub(crate) fn update_canvas(&mut self) {
+        // The mesh layers are drawn straight from the meshes every frame.
+        if self.renderer == VizRenderer::Gpu {
+            self.have_render = true;
+            return;
+        }
+        let side = self.side;
+        let mut composite = self.metadata_img[side].clone();
+
+        if let Some(overlay) = self.overlays.get(&self.overlay_mode) {
+            let paint = tiny_skia::PixmapPaint {
+                opacity: VIZ_OVERLAY_OPACITY,
+                ..Default::default()
+            };
+            composite.draw_pixmap(0, 0, overlay[side].as_ref(), &paint, tiny_skia::Transform::identity(), None);
+        }
+
+        if let Some(canvas) = &mut self.canvas {
+            if canvas.has_texture() {
+                log::debug!("Updating canvas...");
+                log::debug!("pixmap data slice: {:0X?}", &composite.data()[0..16]);
+                canvas.update_data(composite.data());
+                self.have_render = true;
+            }
+            else {
+                log::debug!("Canvas not initialized, deferring update...");
+                //self.draw_deferred = true;
+            }
+        }
+    }
+
+    /// Take up changed layout settings. The visualization needs to be rendered again afterwards.
+    pub(crate) fn apply_settings(&mut self, settings: &VizSettings) {
+        self.geometry = settings.geometry();
+        self.renderer = settings.renderer;
+    }
+
+    /// Show the visualization and its controls. Returns true if the layout settings changed,
+    /// in which case the visualization and its overlays need to be rendered again.
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, settings: &mut VizSettings) -> bool {
+        let mut layout_changed = false;
+        if self.have_render {
+            let mut overlay_mode = self.overlay_mode;
+            ui.horizontal(|ui| {
+                ui.label("Overlay:");
+                egui::ComboBox::from_id_salt("viz_overlay_mode")
+                    .selected_text(overlay_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in VizOverlayMode::ALL {
+                            ui.selectable_value(&mut overlay_mode, mode, mode.label());
+                        }
+                    });
+                ui.menu_button("Layout", |ui| {
+                    layout_changed = settings.show(ui);
+                });
+                if ui
+                    .button("Copy as image")
+                    .on_hover_text("Copy the visualization to the clipboard as a PNG.")
+                    .clicked()
+                {
+                    self.copy_requested = true;
                 }
-                else {
-                    log::debug!("Canvas not initialized, deferring update...");
-                    //self.draw_deferred = true;
+            });
+            if layout_changed {
+                self.apply_settings(settings);
+            }
+            if overlay_mode != self.overlay_mode {
+                self.overlay_mode = overlay_mode;
+                self.update_canvas();
+            }
+
+            if self.renderer == VizRenderer::Gpu {
+                self.show_mesh(ui);
+            }
+            else if let Some(canvas) = &mut self.canvas {
+                ui.horizontal(|ui| {
+                    ui.label("Zoom:");
+                    for zoom in &ZOOM_LUT[VIZ_ZOOM_LEVELS] {
+                        if ui
+                            .selectable_label(canvas.zoom() == *zoom, format!("{}x", zoom))
+                            .clicked()
+                        {
+                            // Keep the current view centered on the same point while zooming.
+                            let center = canvas.view_center();
+                            canvas.set_zoom(*zoom);
+                            canvas.scroll_to_uv(center);
+                        }
+                    }
+                });
+
+                let viewport = canvas.draw(ui);
+
+                // Show an overview inset so the user doesn't get lost when zoomed in.
+                if canvas.zoom() > 1.0 {
+                    canvas.draw_minimap(ui, VIZ_MINIMAP_SIZE);
+                }
+
+                if let Some(viewport) = viewport {
+                    self.hover = viewport.hovered.and_then(|uv| self.hit_test(uv));
+                    if let Some(uv) = viewport.clicked {
+                        self.selection = self.hit_test(uv);
+                        self.clicked = true;
+                    }
+                    self.paint_selection(ui, viewport.image_rect, viewport.rect);
+                    self.view_rect = Some(viewport.rect);
                 }
             }
 
+            let hard_sector = self.hover.as_ref().map(|hit| self.hard_sector_label(hit)).unwrap_or_default();
+            match &self.hover {
+                Some(VizHit { ch, sector: Some(span), .. }) => {
+                    let wraps = if span.wraps() { ", over the index" } else { "" };
+                    ui.label(format!(
+                        "Track {} sector {}: {} bytes in {} bitcells, {:.1}% of the track{}{}",
+                        ch,
+                        span.chsn,
+                        span.chsn.n_size(),
+                        span.bits,
+                        (span.end - span.start) * 100.0,
+                        wraps,
+                        hard_sector
+                    ));
+                }
+                Some(VizHit { ch, bit_offset, .. }) => {
+                    ui.label(format!("Track {} bit {}{}", ch, bit_offset, hard_sector));
+                }
+                None => {
+                    ui.label("");
+                }
+            }
         }
-        Ok(())
+        layout_changed
     }
 
-    pub(crate) fn show(&mut self, ui: &mut egui::Ui) {
-        if self.have_render {
-            if let Some(canvas) = &mut self.canvas {
-                canvas.draw(ui);
+    /// Show the mesh layers of the current side with their zoom controls, for the GPU renderer.
+    fn show_mesh(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Zoom:");
+            for zoom in &ZOOM_LUT[VIZ_ZOOM_LEVELS] {
+                if ui.selectable_label(self.mesh_view.zoom() == *zoom, format!("{}x", zoom)).clicked() {
+                    self.mesh_view.set_zoom(*zoom);
+                }
             }
+        });
+
+        let side = self.side;
+        let mut layers = vec![&self.meta_mesh[side]];
+        if let Some(overlay) = self.overlay_meshes.get(&self.overlay_mode) {
+            layers.push(&overlay[side]);
+        }
+        let viewport = self.mesh_view.draw(ui, &layers);
+        if self.mesh_view.zoom() > 1.0 {
+            self.mesh_view.draw_minimap(ui, &layers, VIZ_MINIMAP_SIZE);
+        }
+
+        self.hover = viewport.hovered.and_then(|uv| self.hit_test(uv));
+        if let Some(uv) = viewport.clicked {
+            self.selection = self.hit_test(uv);
+            self.clicked = true;
         }
+        self.paint_selection(ui, viewport.image_rect, viewport.rect);
+        self.view_rect = Some(viewport.rect);
     }
 }
 
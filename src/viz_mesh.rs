@@ -0,0 +1,269 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A GPU render path for the disk visualization.
+//!
+//! Instead of rasterizing arcs into a pixmap on the CPU and uploading it as a texture, each
+//! track element is tessellated once into triangles in unit coordinates and handed to egui as
+//! a mesh, which the GPU draws at whatever size the view is. Zooming only rescales the
+//! vertices, so it stays sharp at any level and can be animated. The CPU path is kept for
+//! image export and as a fallback.
+
+use std::cell::RefCell;
+
+use egui::{Color32, Mesh, Pos2, Rect, ScrollArea, Vec2};
+use fluxfox::tiny_skia::Color;
+
+use crate::viz::ARC_SEGMENT_ANGLE;
+use crate::widgets::texture::{PixelCanvasViewport, MINIMAP_MARGIN};
+
+/// How long a zoom change takes to animate, in seconds.
+pub const MESH_ZOOM_ANIMATION_TIME: f32 = 0.2;
+/// How many placements of a mesh are kept: one for the view and one for its minimap.
+const PLACED_CACHE_LEN: usize = 2;
+
+/// Which path renders the visualization.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum VizRenderer {
+    /// Rasterize into a texture on the CPU.
+    #[default]
+    Cpu,
+    /// Draw tessellated meshes on the GPU.
+    Gpu,
+}
+
+impl VizRenderer {
+    pub const ALL: [VizRenderer; 2] = [VizRenderer::Cpu, VizRenderer::Gpu];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            VizRenderer::Cpu => "CPU (texture)",
+            VizRenderer::Gpu => "GPU (mesh)",
+        }
+    }
+}
+
+pub fn color32(color: Color, opacity: f32) -> Color32 {
+    let channel = |value: f32| (value * 255.0).round() as u8;
+    Color32::from_rgba_unmultiplied(
+        channel(color.red()),
+        channel(color.green()),
+        channel(color.blue()),
+        channel(color.alpha() * opacity),
+    )
+}
+
+/// Ring segments tessellated in unit coordinates: the disk is centered on the origin with a
+/// radius of 1.
+#[derive(Clone, Default)]
+pub struct ArcMesh {
+    mesh: Mesh,
+    /// The mesh as last mapped onto each rect it was drawn in, so a view that hasn't moved
+    /// isn't transformed again every frame.
+    placed: RefCell<Vec<(Rect, Mesh)>>,
+}
+
+impl ArcMesh {
+    pub fn clear(&mut self) {
+        self.mesh.clear();
+        self.placed.get_mut().clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mesh.is_empty()
+    }
+
+    /// Add the ring segment between `inner` and `outer` radius from angle `start` to `end`.
+    /// Takes the same arguments as fill_arc(), with radii relative to the disk radius.
+    pub fn add_arc(&mut self, radii: (f32, f32), angles: (f32, f32), color: Color32) {
+        let (outer, inner) = radii;
+        let (start, end) = if angles.1 < angles.0 { (angles.1, angles.0) } else { angles };
+        let segments = (((end - start) / ARC_SEGMENT_ANGLE).ceil() as u32).max(1);
+
+        self.placed.get_mut().clear();
+        let base = self.mesh.vertices.len() as u32;
+        for i in 0..=segments {
+            let angle = start + (end - start) * (i as f32 / segments as f32);
+            let direction = egui::vec2(angle.cos(), angle.sin());
+            self.mesh.colored_vertex(Pos2::ZERO + direction * outer, color);
+            self.mesh.colored_vertex(Pos2::ZERO + direction * inner, color);
+        }
+        for i in 0..segments {
+            let v = base + i * 2;
+            self.mesh.add_triangle(v, v + 1, v + 2);
+            self.mesh.add_triangle(v + 1, v + 3, v + 2);
+        }
+    }
+
+    /// Return the mesh mapped onto the square `rect`. The mapping is cached per rect; egui
+    /// takes ownership of the meshes it paints, so the cached mesh is handed over as a copy.
+    pub fn placed(&self, rect: Rect) -> Mesh {
+        let mut placed = self.placed.borrow_mut();
+        if let Some((_, mesh)) = placed.iter().find(|(placed_rect, _)| *placed_rect == rect) {
+            return mesh.clone();
+        }
+
+        let mut mesh = self.mesh.clone();
+        let (center, half) = (rect.center(), rect.width() / 2.0);
+        for vertex in mesh.vertices.iter_mut() {
+            vertex.pos = center + vertex.pos.to_vec2() * half;
+        }
+        if placed.len() == PLACED_CACHE_LEN {
+            placed.remove(0);
+        }
+        placed.push((rect, mesh.clone()));
+        mesh
+    }
+}
+
+/// A scrollable, zoomable view of a set of meshes, standing in for the visualization's
+/// PixelCanvas when the GPU renderer is selected.
+pub struct MeshView {
+    size: f32,
+    zoom: f32,
+    scroll_target: Option<Pos2>,
+    viewport: Option<PixelCanvasViewport>,
+}
+
+impl MeshView {
+    pub fn new(size: f32) -> Self {
+        Self {
+            size,
+            zoom: 1.0,
+            scroll_target: None,
+            viewport: None,
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Change the zoom, animating to it while keeping the view centered on the same point.
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.scroll_target = Some(self.view_center());
+        self.zoom = zoom;
+    }
+
+    /// Scroll so that `center`, in normalized coordinates, is in the middle of the view.
+    pub fn scroll_to_uv(&mut self, center: Pos2) {
+        self.scroll_target = Some(center);
+    }
+
+    pub fn view_center(&self) -> Pos2 {
+        self.viewport
+            .map(|v| v.uv.center())
+            .unwrap_or(egui::pos2(0.5, 0.5))
+    }
+
+    /// Draw `layers`, bottom first, inside a scroll area the size of the unzoomed view.
+    pub fn draw(&mut self, ui: &mut egui::Ui, layers: &[&ArcMesh]) -> PixelCanvasViewport {
+        let zoom = ui
+            .ctx()
+            .animate_value_with_time(ui.id().with("viz_mesh_zoom"), self.zoom, MESH_ZOOM_ANIMATION_TIME);
+        let img_size = self.size * zoom;
+
+        let mut scroll_area = ScrollArea::both()
+            .id_salt("viz_mesh_view")
+            .auto_shrink([false; 2])
+            .max_width(self.size)
+            .max_height(self.size);
+        if let Some(center) = self.scroll_target {
+            let view_size = self.viewport.map(|v| v.rect.size()).unwrap_or(Vec2::splat(self.size));
+            let offset = egui::vec2(center.x, center.y) * img_size - view_size / 2.0;
+            scroll_area = scroll_area.scroll_offset(offset.max(Vec2::ZERO));
+            // Keep the center pinned until the zoom animation settles.
+            if zoom == self.zoom {
+                self.scroll_target = None;
+            }
+        }
+
+        let output = scroll_area.show_viewport(ui, |ui, viewport| {
+            let (rect, response) = ui.allocate_exact_size(Vec2::splat(img_size), egui::Sense::click());
+            let painter = ui.painter();
+            for layer in layers {
+                painter.add(layer.placed(rect));
+            }
+            (viewport, rect, response)
+        });
+
+        let (visible, image_rect, response) = output.inner;
+        let to_uv = |pos: Pos2| {
+            let uv = (pos - image_rect.min) / image_rect.size();
+            egui::pos2(uv.x, uv.y)
+        };
+        let viewport = PixelCanvasViewport {
+            rect: output.inner_rect,
+            uv: Rect::from_min_max(
+                (visible.min.to_vec2() / img_size).to_pos2(),
+                (visible.max.to_vec2() / img_size).min(Vec2::splat(1.0)).to_pos2(),
+            ),
+            image_rect,
+            hovered: response.hover_pos().map(to_uv),
+            clicked: response
+                .clicked()
+                .then(|| response.interact_pointer_pos())
+                .flatten()
+                .map(to_uv),
+        };
+        self.viewport = Some(viewport);
+        viewport
+    }
+
+    /// Draw an overview of `layers` in the upper right corner of the last drawn view, as
+    /// PixelCanvas::draw_minimap() does.
+    pub fn draw_minimap(&mut self, ui: &mut egui::Ui, layers: &[&ArcMesh], size: f32) {
+        let Some(viewport) = self.viewport
+        else {
+            return;
+        };
+        let inset = Rect::from_min_size(
+            egui::pos2(viewport.rect.right() - size - MINIMAP_MARGIN, viewport.rect.top() + MINIMAP_MARGIN),
+            Vec2::splat(size),
+        );
+        let response = ui.interact(inset, ui.id().with("viz_mesh_minimap"), egui::Sense::click_and_drag());
+
+        let painter = ui.painter_at(viewport.rect);
+        painter.rect_filled(inset.expand(2.0), egui::Rounding::ZERO, Color32::from_black_alpha(200));
+        for layer in layers {
+            painter.add(layer.placed(inset));
+        }
+        let visible = Rect::from_min_max(
+            inset.lerp_inside(viewport.uv.min.to_vec2()),
+            inset.lerp_inside(viewport.uv.max.to_vec2()),
+        );
+        painter.rect_stroke(visible, egui::Rounding::ZERO, egui::Stroke::new(1.0, Color32::YELLOW));
+
+        if response.clicked() || response.dragged() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                let uv = (pos - inset.min) / inset.size();
+                self.scroll_to_uv(egui::pos2(uv.x.clamp(0.0, 1.0), uv.y.clamp(0.0, 1.0)));
+                ui.ctx().request_repaint();
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! Consistency checks run on the image after every change made to it in the app.
+//!
+//! Edits and merges write sectors in place, while transforms and copies rebuild the image, and
+//! either may go wrong and leave it in a state that looks fine on screen but can't be exported.
+//! The watchdog compares quick facts about the image from before and after the change, and
+//! reports invariants the change broke rather than ones the image never met, so a copy-protected
+//! original doesn't warn on every edit. Changes that resize the image on purpose say how many
+//! cylinders they mean to leave, so the sectors they drop aren't reported as damage.
+
+use std::collections::BTreeSet;
+
+use fluxfox::DiskImage;
+
+use crate::analysis::geometry::LayoutSummary;
+use crate::analysis::read_all_sectors;
+use crate::export;
+
+/// The facts about an image the watchdog compares.
+pub struct ImageSnapshot {
+    layout: LayoutSummary,
+    /// The number of tracks on each head.
+    tracks_per_head: Vec<usize>,
+    sectors: usize,
+    /// Sectors whose data fails its CRC.
+    crc_errors: usize,
+    /// Sectors with a deleted data mark.
+    deleted: usize,
+    /// Tracks holding the same sector ID more than once.
+    duplicate_id_tracks: usize,
+    /// Sectors whose ID names a different cylinder or head than the track they're on.
+    mismatched_ids: usize,
+    /// The names of the formats the image can be exported to.
+    formats: BTreeSet<String>,
+}
+
+impl ImageSnapshot {
+    pub fn of(disk: &mut DiskImage) -> Self {
+        let reads = read_all_sectors(disk);
+        let sector_map = disk.get_sector_map();
+        let mut snapshot = Self {
+            layout: LayoutSummary::from_disk(disk),
+            tracks_per_head: sector_map.iter().map(|cylinders| cylinders.len()).collect(),
+            sectors: 0,
+            crc_errors: reads.iter().filter(|read| read.data_crc_error).count(),
+            deleted: reads.iter().filter(|read| read.deleted).count(),
+            duplicate_id_tracks: 0,
+            mismatched_ids: 0,
+            formats: export::export_formats(disk).iter().map(|format| format.label()).collect(),
+        };
+        for (head, cylinders) in sector_map.iter().enumerate() {
+            for (cylinder, entries) in cylinders.iter().enumerate() {
+                let mut ids = BTreeSet::new();
+                let mut duplicate = false;
+                for entry in entries {
+                    duplicate |= !ids.insert(entry.chsn.s());
+                    if entry.chsn.c() as usize != cylinder || entry.chsn.h() as usize != head {
+                        snapshot.mismatched_ids += 1;
+                    }
+                }
+                snapshot.sectors += entries.len();
+                snapshot.duplicate_id_tracks += duplicate as usize;
+            }
+        }
+        snapshot
+    }
+
+    /// The number of cylinders the image has tracks on.
+    pub fn cylinders(&self) -> u16 {
+        self.layout.cylinders
+    }
+}
+
+/// Check `after`, the image as changed, against `before`, for a change meant to leave it with
+/// `cylinders` cylinders. Returns a description of each broken invariant.
+pub fn validate(before: &ImageSnapshot, after: &ImageSnapshot, cylinders: u16) -> Vec<String> {
+    let mut problems = Vec::new();
+    // A change that drops or adds cylinders changes the sector count with them.
+    let resized = cylinders != before.layout.cylinders;
+
+    if after.tracks_per_head.iter().all(|tracks| *tracks == 0) {
+        problems.push("The image has no tracks left.".to_string());
+    }
+    else if after.layout.cylinders != cylinders {
+        problems.push(format!(
+            "The image has {} cylinders, where it should have {}.",
+            after.layout.cylinders, cylinders
+        ));
+    }
+    if after.sectors == 0 && before.sectors > 0 {
+        problems.push("The image has no sectors left.".to_string());
+    }
+    else if after.sectors != before.sectors && !resized {
+        problems.push(format!("The image has {} sectors, where it had {}.", after.sectors, before.sectors));
+    }
+    if after.crc_errors > before.crc_errors {
+        problems.push(format!("{} more sectors fail their data CRC.", after.crc_errors - before.crc_errors));
+    }
+    if after.deleted > before.deleted || (after.deleted < before.deleted && !resized) {
+        problems.push(format!("{} sectors have deleted data marks, where {} did.", after.deleted, before.deleted));
+    }
+    let uneven = |tracks: &[usize]| tracks.windows(2).any(|pair| pair[0] != pair[1]);
+    if uneven(&after.tracks_per_head) && !uneven(&before.tracks_per_head) {
+        let counts: Vec<String> = after.tracks_per_head.iter().map(|tracks| tracks.to_string()).collect();
+        problems.push(format!("The heads have different numbers of tracks ({}).", counts.join(" and ")));
+    }
+
+    if after.duplicate_id_tracks > before.duplicate_id_tracks {
+        problems.push(format!(
+            "{} more tracks hold the same sector ID twice.",
+            after.duplicate_id_tracks - before.duplicate_id_tracks
+        ));
+    }
+    if after.mismatched_ids > before.mismatched_ids {
+        problems.push(format!(
+            "{} more sectors have IDs naming a different track than the one they're on.",
+            after.mismatched_ids - before.mismatched_ids
+        ));
+    }
+    if after.layout.sector_sizes.len() > before.layout.sector_sizes.len() {
+        problems.push(format!(
+            "The image now mixes sector sizes ({:?} bytes).",
+            after.layout.sector_sizes
+        ));
+    }
+    if before.layout.standard_geometry().is_some() && after.layout.standard_geometry().is_none() {
+        problems.push(format!(
+            "The image no longer has a standard geometry ({}), so it can't be exported as a raw sector image.",
+            after.layout
+        ));
+    }
+
+    if after.formats.is_empty() && !before.formats.is_empty() {
+        problems.push("No format can hold the image any more, so it can't be exported.".to_string());
+    }
+    else {
+        let lost: Vec<&str> = before.formats.difference(&after.formats).map(String::as_str).collect();
+        if !lost.is_empty() {
+            problems.push(format!("The image can no longer be exported as {}.", lost.join(", ")));
+        }
+    }
+    problems
+}
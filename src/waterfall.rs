@@ -0,0 +1,196 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+//! A waterfall of every track on one side of the disk: each row is a track's bitstream drawn
+//! as a strip from the index, stacked by cylinder. Changes in formatting, and the odd tracks
+//! copy protection schemes leave behind, show up as breaks in the vertical pattern.
+
+use egui::{Color32, ColorImage, Sense, TextureHandle, TextureOptions};
+use fluxfox::structure_parsers::DiskStructureGenericElement;
+use fluxfox::visualization::{collect_metadata, collect_streams};
+use fluxfox::{DiskCh, DiskImage};
+
+/// The number of columns each track is divided into.
+pub const WATERFALL_COLUMNS: usize = 512;
+/// The on-screen height of each track's row.
+pub const WATERFALL_ROW_HEIGHT: f32 = 4.0;
+const GAP_COLOR: Color32 = Color32::from_gray(24);
+
+/// What each column of a row shows.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum WaterfallMode {
+    /// The fraction of bit cells holding a flux transition, from black to white.
+    #[default]
+    Density,
+    /// The track elements found, in the visualization's colors.
+    Structure,
+}
+
+impl WaterfallMode {
+    pub const ALL: [WaterfallMode; 2] = [WaterfallMode::Density, WaterfallMode::Structure];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            WaterfallMode::Density => "Flux density",
+            WaterfallMode::Structure => "Structure",
+        }
+    }
+}
+
+fn element_color(elem: DiskStructureGenericElement) -> Option<Color32> {
+    match elem {
+        DiskStructureGenericElement::SectorData => Some(Color32::from_rgb(0x38, 0xb7, 0x64)),
+        DiskStructureGenericElement::SectorBadData => Some(Color32::from_rgb(0xef, 0x7d, 0x57)),
+        DiskStructureGenericElement::SectorDeletedData => Some(Color32::from_rgb(0x25, 0x71, 0x79)),
+        DiskStructureGenericElement::SectorBadDeletedData => Some(Color32::from_rgb(180, 0, 0)),
+        DiskStructureGenericElement::SectorHeader => Some(Color32::from_rgb(0x41, 0xa6, 0xf6)),
+        DiskStructureGenericElement::SectorBadHeader => Some(Color32::from_rgb(0x3b, 0x5d, 0xc9)),
+        DiskStructureGenericElement::Marker => Some(Color32::from_rgb(180, 0, 180)),
+        _ => None,
+    }
+}
+
+/// Build the waterfall image for one side: one row per track, `WATERFALL_COLUMNS` wide.
+pub fn build_waterfall(disk: &DiskImage, head: u8, mode: WaterfallMode) -> ColorImage {
+    let streams = collect_streams(head, disk);
+    let metadata = collect_metadata(head, disk);
+    let rows = streams.len().max(1);
+    let mut image = ColorImage::new([WATERFALL_COLUMNS, rows], GAP_COLOR);
+
+    for (ti, stream) in streams.iter().enumerate() {
+        let len = stream.len();
+        if len == 0 {
+            continue;
+        }
+        let row = &mut image.pixels[ti * WATERFALL_COLUMNS..(ti + 1) * WATERFALL_COLUMNS];
+        let column_of = |offset: usize| (offset * WATERFALL_COLUMNS / len).min(WATERFALL_COLUMNS - 1);
+
+        match mode {
+            WaterfallMode::Density => {
+                let mut ones = [0usize; WATERFALL_COLUMNS];
+                let mut cells = [0usize; WATERFALL_COLUMNS];
+                for i in 0..len {
+                    let column = column_of(i);
+                    cells[column] += 1;
+                    if stream[i] {
+                        ones[column] += 1;
+                    }
+                }
+                for (column, pixel) in row.iter_mut().enumerate() {
+                    // Encoded data rarely has more than every other cell set, so stretch the
+                    // range to make use of the whole ramp.
+                    let density = ones[column] as f32 / cells[column].max(1) as f32;
+                    *pixel = Color32::from_gray((density * 2.0 * 255.0).min(255.0) as u8);
+                }
+            }
+            WaterfallMode::Structure => {
+                let Some(track_meta) = metadata.get(ti)
+                else {
+                    continue;
+                };
+                // Later items are drawn over earlier ones, so markers show inside headers.
+                for item in track_meta.items.iter() {
+                    if let Some(color) = element_color(DiskStructureGenericElement::from(item.elem_type)) {
+                        let (start, end) = (column_of(item.start), column_of(item.end.min(len)));
+                        row[start..=end.max(start)].fill(color);
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+/// A panel showing the waterfall of one side of the disk.
+#[derive(Default)]
+pub struct Waterfall {
+    head: u8,
+    mode: WaterfallMode,
+    texture: Option<TextureHandle>,
+    rows: usize,
+}
+
+impl Waterfall {
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.texture = None;
+        self.rows = 0;
+    }
+
+    /// Drop the texture, which is built again when the panel is next shown.
+    pub fn forget_textures(&mut self) {
+        self.texture = None;
+    }
+
+    fn build(&mut self, ctx: &egui::Context, disk: &DiskImage) {
+        let image = build_waterfall(disk, self.head, self.mode);
+        self.rows = image.size[1];
+        let options = TextureOptions::NEAREST;
+        match &mut self.texture {
+            Some(texture) => texture.set(image, options),
+            None => self.texture = Some(ctx.load_texture("track_waterfall", image, options)),
+        }
+    }
+
+    /// Show the panel. The image is built the first time the panel is opened, and again when
+    /// the side or mode changes. Returns the track to select if the user clicked a row.
+    pub fn show(&mut self, ui: &mut egui::Ui, disk: &DiskImage) -> Option<DiskCh> {
+        let mut select = None;
+        egui::CollapsingHeader::new("Track waterfall").id_salt("track_waterfall").show(ui, |ui| {
+            let (head, mode) = (self.head, self.mode);
+            ui.horizontal(|ui| {
+                ui.label("Side:");
+                for h in 0..disk.get_sector_map().len().max(1) as u8 {
+                    ui.radio_value(&mut self.head, h, h.to_string());
+                }
+                ui.separator();
+                for mode in WaterfallMode::ALL {
+                    ui.radio_value(&mut self.mode, mode, mode.label());
+                }
+            });
+            if self.texture.is_none() || head != self.head || mode != self.mode {
+                self.build(ui.ctx(), disk);
+            }
+            let Some(texture) = &self.texture
+            else {
+                return;
+            };
+
+            let size = egui::vec2(ui.available_width(), self.rows as f32 * WATERFALL_ROW_HEIGHT);
+            let response = ui.add(egui::Image::new(texture).fit_to_exact_size(size).sense(Sense::click()));
+            if let Some(pos) = response.hover_pos() {
+                let row = ((pos.y - response.rect.top()) / WATERFALL_ROW_HEIGHT) as usize;
+                let ch = DiskCh::new(row.min(self.rows.saturating_sub(1)) as u16, self.head);
+                if response.clicked() {
+                    select = Some(ch);
+                }
+                response.on_hover_text(format!("Track {}", ch));
+            }
+        });
+        select
+    }
+}
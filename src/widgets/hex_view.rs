@@ -0,0 +1,276 @@
+/*
+    FluxFox
+    https://github.com/dbalsom/fluxfox
+
+    Copyright 2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+*/
+
+use egui::text::{LayoutJob, TextFormat};
+use egui::{Color32, FontId};
+
+use crate::analysis::SectorKey;
+use crate::templates::{builtin_templates, FieldValue, StructTemplate, TEMPLATE_FILE_SUFFIX};
+
+pub const HEX_ROW_BYTES: usize = 16;
+pub const HEX_VIEW_MAX_HEIGHT: f32 = 320.0;
+
+/// Colors cycled through to distinguish adjacent template fields.
+const FIELD_COLORS: [Color32; 4] = [
+    Color32::from_rgb(0x41, 0xa6, 0xf6),
+    Color32::from_rgb(0x38, 0xb7, 0x64),
+    Color32::from_rgb(0xef, 0x7d, 0x57),
+    Color32::from_rgb(0xd0, 0x80, 0xe0),
+];
+const UNMAPPED_COLOR: Color32 = Color32::GRAY;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TemplateChoice {
+    /// Use the template suggested for the sector, if any.
+    #[default]
+    Auto,
+    None,
+    Named(String),
+}
+
+/// A hex dump of a single sector, with an optional structure template decoded beside it.
+pub struct HexViewer {
+    pub key: Option<SectorKey>,
+    pub data: Vec<u8>,
+    pub choice: TemplateChoice,
+    pub templates: Vec<StructTemplate>,
+    /// Offset in the sector at which the template is applied.
+    pub offset: usize,
+    pub template_error: Option<String>,
+    suggested: Option<String>,
+    values: Vec<FieldValue>,
+    /// The sectors before and after this one on its track.
+    neighbours: [Option<SectorKey>; 2],
+}
+
+impl Default for HexViewer {
+    fn default() -> Self {
+        Self {
+            key: None,
+            data: Vec::new(),
+            choice: TemplateChoice::Auto,
+            templates: builtin_templates(),
+            offset: 0,
+            template_error: None,
+            suggested: None,
+            values: Vec::new(),
+            neighbours: [None; 2],
+        }
+    }
+}
+
+impl HexViewer {
+    pub fn clear(&mut self) {
+        self.key = None;
+        self.data.clear();
+        self.suggested = None;
+        self.values.clear();
+        self.neighbours = [None; 2];
+    }
+
+    /// Show `data` from the sector `key`, with the name of the template suggested for it and the
+    /// offset to apply it at, and the sectors either side of it, which can be stepped to.
+    pub fn set_sector(
+        &mut self,
+        key: SectorKey,
+        data: Vec<u8>,
+        suggested: Option<(&str, usize)>,
+        neighbours: [Option<SectorKey>; 2],
+    ) {
+        self.key = Some(key);
+        self.neighbours = neighbours;
+        self.data = data;
+        self.suggested = suggested.map(|(name, _)| name.to_string());
+        self.offset = suggested.map_or(0, |(_, offset)| offset);
+        self.apply_template();
+    }
+
+    /// Load templates from a template file, replacing any existing templates with the same
+    /// names. Returns the number of templates loaded.
+    pub fn load_templates(&mut self, name: &str, json: &[u8]) -> usize {
+        match StructTemplate::parse(json) {
+            Ok(templates) => {
+                let count = templates.len();
+                for template in templates {
+                    self.templates.retain(|existing| existing.name != template.name);
+                    self.templates.push(template);
+                }
+                log::info!("Loaded {} templates from {}", count, name);
+                self.template_error = None;
+                self.apply_template();
+                count
+            }
+            Err(e) => {
+                log::error!("Error loading templates from {}: {}", name, e);
+                self.template_error = Some(format!("Couldn't load {}: {}", name, e));
+                0
+            }
+        }
+    }
+
+    fn active_template(&self) -> Option<&StructTemplate> {
+        let name = match &self.choice {
+            TemplateChoice::Auto => self.suggested.as_ref()?,
+            TemplateChoice::None => return None,
+            TemplateChoice::Named(name) => name,
+        };
+        self.templates.iter().find(|template| &template.name == name)
+    }
+
+    fn apply_template(&mut self) {
+        self.values = match self.active_template() {
+            Some(template) => template.apply(&self.data, self.offset),
+            None => Vec::new(),
+        };
+    }
+
+    /// Show the sector. Returns a neighbouring sector to select if the user stepped to one.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<SectorKey> {
+        let Some(key) = self.key
+        else {
+            return None;
+        };
+        let mut step = None;
+
+        egui::CollapsingHeader::new(format!("Sector {} ({} bytes)", key, self.data.len()))
+            .id_salt("hex_viewer")
+            .default_open(true)
+            .show(ui, |ui| {
+                if let Some(error) = &self.template_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+
+                ui.horizontal(|ui| {
+                    let [previous, next] = self.neighbours;
+                    if ui.add_enabled(previous.is_some(), egui::Button::new("⏴ Previous")).clicked() {
+                        step = previous;
+                    }
+                    if ui.add_enabled(next.is_some(), egui::Button::new("Next ⏵")).clicked() {
+                        step = next;
+                    }
+                });
+
+                let (choice, offset) = self.show_template_choice(ui);
+                if choice != self.choice || offset != self.offset {
+                    self.choice = choice;
+                    self.offset = offset;
+                    self.apply_template();
+                }
+
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                let row_count = self.data.len().div_ceil(HEX_ROW_BYTES);
+                egui::ScrollArea::vertical()
+                    .id_salt("hex_viewer_rows")
+                    .max_height(HEX_VIEW_MAX_HEIGHT)
+                    .show_rows(ui, row_height, row_count, |ui, rows| {
+                        self.show_rows(ui, rows);
+                    });
+            });
+        step
+    }
+
+    fn show_template_choice(&self, ui: &mut egui::Ui) -> (TemplateChoice, usize) {
+        let mut choice = self.choice.clone();
+        let mut offset = self.offset;
+        let label = |choice: &TemplateChoice| match choice {
+            TemplateChoice::Auto => match &self.suggested {
+                Some(name) => format!("Auto ({})", name),
+                None => "Auto".to_string(),
+            },
+            TemplateChoice::None => "None".to_string(),
+            TemplateChoice::Named(name) => name.clone(),
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Template:");
+            egui::ComboBox::from_id_salt("hex_viewer_template")
+                .selected_text(label(&choice))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut choice, TemplateChoice::Auto, label(&TemplateChoice::Auto));
+                    ui.selectable_value(&mut choice, TemplateChoice::None, "None");
+                    for template in self.templates.iter() {
+                        let named = TemplateChoice::Named(template.name.clone());
+                        ui.selectable_value(&mut choice, named, &template.name);
+                    }
+                });
+            ui.label("at offset");
+            ui.add(
+                egui::DragValue::new(&mut offset)
+                    .range(0..=self.data.len().saturating_sub(1))
+                    .hexadecimal(4, false, true),
+            );
+            ui.weak(format!("Drop a {} file to add templates.", TEMPLATE_FILE_SUFFIX));
+        });
+        (choice, offset)
+    }
+
+    /// Show only the visible `rows`, so large sectors don't slow down every frame.
+    fn show_rows(&self, ui: &mut egui::Ui, rows: std::ops::Range<usize>) {
+        let start = rows.start * HEX_ROW_BYTES;
+        let end = (rows.end * HEX_ROW_BYTES).min(self.data.len());
+
+        // Map each visible byte to the index of the field covering it, for coloring.
+        let mut coverage = vec![None; end.saturating_sub(start)];
+        for (i, value) in self.values.iter().enumerate() {
+            for offset in value.offset.max(start)..(value.offset + value.len).min(end) {
+                coverage[offset - start] = Some(i);
+            }
+        }
+
+        let font = FontId::monospace(12.0);
+        for (row, bytes) in self.data[start..end].chunks(HEX_ROW_BYTES).enumerate() {
+            let row_start = start + row * HEX_ROW_BYTES;
+            let mut hex = LayoutJob::default();
+            let mut ascii = String::with_capacity(HEX_ROW_BYTES);
+
+            for (i, byte) in bytes.iter().enumerate() {
+                let color = match coverage[row_start - start + i] {
+                    Some(field) => FIELD_COLORS[field % FIELD_COLORS.len()],
+                    None => UNMAPPED_COLOR,
+                };
+                hex.append(&format!("{:02X} ", byte), 0.0, TextFormat::simple(font.clone(), color));
+                ascii.push(if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' });
+            }
+
+            let fields: Vec<String> = self
+                .values
+                .iter()
+                .filter(|value| value.offset >= row_start && value.offset < row_start + HEX_ROW_BYTES)
+                .map(|value| format!("{}={}", value.name, value.value))
+                .collect();
+
+            ui.horizontal(|ui| {
+                ui.monospace(format!("{:04X}", row_start));
+                ui.label(hex);
+                ui.monospace(ascii);
+                if !fields.is_empty() {
+                    ui.weak(fields.join(", "));
+                }
+            });
+        }
+    }
+}
@@ -25,4 +25,5 @@
     --------------------------------------------------------------------------
 */
 
+pub mod hex_view;
 pub mod texture;
\ No newline at end of file
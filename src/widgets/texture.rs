@@ -34,10 +34,12 @@ use egui::{
     ColorImage,
     Context,
     ImageData,
+    Pos2,
     Rect,
     ScrollArea,
     TextureHandle,
     TextureOptions,
+    Vec2,
 };
 
 use anyhow::Error;
@@ -67,6 +69,8 @@ pub const ZOOM_LUT: [f32; 5] = [1.0, 2.0, 4.0, 8.0, 16.0];
 pub const DEFAULT_WIDTH: u32 = 128;
 pub const DEFAULT_HEIGHT: u32 = 128;
 
+pub const MINIMAP_MARGIN: f32 = 8.0;
+
 pub const PALETTE_1BPP: [Color32; 2] = [Color32::from_rgb(0, 0, 0), Color32::from_rgb(255, 255, 255)];
 pub const PALETTE_2BPP: [Color32; 4] = [
     Color32::from_rgb(0x00u8, 0x00u8, 0x00u8),
@@ -123,6 +127,21 @@ pub struct PixelCanvasPalette {
     colors: Vec<Color32>,
 }
 
+/// The region of a [PixelCanvas] that was visible during the last call to [PixelCanvas::draw].
+#[derive(Copy, Clone, Debug)]
+pub struct PixelCanvasViewport {
+    /// Screen-space rectangle occupied by the canvas view.
+    pub rect: Rect,
+    /// Visible portion of the canvas in normalized (0.0-1.0) texture coordinates.
+    pub uv: Rect,
+    /// Screen-space rectangle of the entire canvas image, including any part scrolled out of view.
+    pub image_rect: Rect,
+    /// Position of the pointer over the canvas, in normalized texture coordinates.
+    pub hovered: Option<Pos2>,
+    /// Position of a click on the canvas this frame, in normalized texture coordinates.
+    pub clicked: Option<Pos2>,
+}
+
 pub struct PixelCanvas {
     data_buf: Vec<u8>,
     backing_buf: Vec<Color32>,
@@ -140,6 +159,8 @@ pub struct PixelCanvas {
     default_uv: Rect,
     ctx: Context,
     data_unpacked: bool,
+    scroll_target: Option<Vec2>,
+    viewport: Option<PixelCanvasViewport>,
 }
 
 impl Default for PixelCanvas {
@@ -173,6 +194,8 @@ impl Default for PixelCanvas {
             default_uv: Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
             ctx: Context::default(),
             data_unpacked: false,
+            scroll_target: None,
+            viewport: None,
         }
     }
 }
@@ -235,45 +258,115 @@ impl PixelCanvas {
         self.view_dimensions.0 as f32 * self.zoom
     }
 
-    pub fn draw(&mut self, ui: &mut egui::Ui) {
-        if let Some(texture) = &self.texture {
-            ui.vertical(|ui| {
-                // Draw background rect
-                //ui.painter().rect_filled(ui.max_rect(), egui::Rounding::default(), egui::Color32::BLACK);
-                let scroll_area = ScrollArea::vertical().auto_shrink([false; 2]);
-                let img_w = self.view_dimensions.0 as f32 * self.zoom;
-                let img_h = self.view_dimensions.1 as f32 * self.zoom;
-                ui.shrink_width_to_current();
-                ui.set_width(img_w);
-                //ui.set_height(ui.cursor().top() + img_h);
-                ui.set_height(img_h);
-
-                scroll_area.show_viewport(ui, |ui, viewport| {
-                    let start_x = viewport.min.x + ui.min_rect().left();
-                    let start_y = viewport.min.y + ui.min_rect().top();
-
-                    //log::debug!("Viewport is: {:?} StartX: {} StartY: {}", viewport, start_x, start_y);
-
-                    ui.painter().image(
-                        texture.id(),
-                        Rect::from_min_max(
-                            egui::pos2(start_x, start_y),
-                            egui::pos2(start_x + img_w, start_y + img_h),
-                        ),
-                        self.default_uv,
-                        Color32::WHITE,
-                    );
-                });
-            });
-        /*            log::debug!(
-            "Drawing PixelCanvas texture ({}x{}), id: {:?}",
-            texture.size()[0],
-            texture.size()[1],
-            texture.id()
-        );*/
+    pub fn get_height(&self) -> f32 {
+        self.view_dimensions.1 as f32 * self.zoom
+    }
+
+    /// Draw the canvas inside a scroll area the size of the unzoomed canvas. When zoomed in, the
+    /// canvas can be panned by dragging or with the scroll bars.
+    pub fn draw(&mut self, ui: &mut egui::Ui) -> Option<PixelCanvasViewport> {
+        let texture_id = match &self.texture {
+            Some(texture) => texture.id(),
+            None => {
+                log::debug!("No texture to draw.");
+                return None;
+            }
+        };
+
+        let img_w = self.get_width();
+        let img_h = self.get_height();
+
+        let mut scroll_area = ScrollArea::both()
+            .auto_shrink([false; 2])
+            .max_width(self.view_dimensions.0 as f32)
+            .max_height(self.view_dimensions.1 as f32);
+
+        if let Some(offset) = self.scroll_target.take() {
+            scroll_area = scroll_area.scroll_offset(offset);
         }
+
+        let output = scroll_area.show_viewport(ui, |ui, viewport| {
+            let (rect, response) = ui.allocate_exact_size(egui::vec2(img_w, img_h), egui::Sense::click());
+            ui.painter().image(texture_id, rect, self.default_uv, Color32::WHITE);
+            (viewport, rect, response)
+        });
+
+        let (visible, image_rect, response) = output.inner;
+        let to_uv = |pos: Pos2| {
+            let uv = (pos - image_rect.min) / image_rect.size();
+            egui::pos2(uv.x, uv.y)
+        };
+        let viewport = PixelCanvasViewport {
+            rect: output.inner_rect,
+            uv: Rect::from_min_max(
+                egui::pos2(visible.min.x / img_w, visible.min.y / img_h),
+                egui::pos2((visible.max.x / img_w).min(1.0), (visible.max.y / img_h).min(1.0)),
+            ),
+            image_rect,
+            hovered: response.hover_pos().map(to_uv),
+            clicked: response
+                .clicked()
+                .then(|| response.interact_pointer_pos())
+                .flatten()
+                .map(to_uv),
+        };
+        self.viewport = Some(viewport);
+        Some(viewport)
+    }
+
+    /// Scroll the canvas so that `center`, in normalized texture coordinates, is in the middle of
+    /// the view. Takes effect on the next call to draw().
+    pub fn scroll_to_uv(&mut self, center: Pos2) {
+        let img_size = egui::vec2(self.get_width(), self.get_height());
+        let view_size = self.viewport.map(|v| v.rect.size()).unwrap_or(img_size);
+        let offset = egui::vec2(center.x * img_size.x, center.y * img_size.y) - view_size / 2.0;
+        self.scroll_target = Some(offset.max(Vec2::ZERO));
+    }
+
+    /// Return the center of the visible region in normalized texture coordinates.
+    pub fn view_center(&self) -> Pos2 {
+        self.viewport
+            .map(|v| v.uv.center())
+            .unwrap_or(egui::pos2(0.5, 0.5))
+    }
+
+    /// Draw an overview of the entire canvas in the upper right corner of the last drawn view,
+    /// with a rectangle marking the visible region. Clicking or dragging on the overview jumps
+    /// to that point.
+    pub fn draw_minimap(&mut self, ui: &mut egui::Ui, size: f32) {
+        let (Some(texture), Some(viewport)) = (&self.texture, self.viewport)
         else {
-            log::debug!("No texture to draw.");
+            return;
+        };
+        let texture_id = texture.id();
+
+        let aspect = self.view_dimensions.1 as f32 / self.view_dimensions.0 as f32;
+        let inset = Rect::from_min_size(
+            egui::pos2(
+                viewport.rect.right() - size - MINIMAP_MARGIN,
+                viewport.rect.top() + MINIMAP_MARGIN,
+            ),
+            egui::vec2(size, size * aspect),
+        );
+
+        let response = ui.interact(inset, ui.id().with("pixel_canvas_minimap"), egui::Sense::click_and_drag());
+
+        let painter = ui.painter_at(viewport.rect);
+        painter.rect_filled(inset.expand(2.0), egui::Rounding::ZERO, Color32::from_black_alpha(200));
+        painter.image(texture_id, inset, self.default_uv, Color32::WHITE);
+
+        let visible = Rect::from_min_max(
+            inset.lerp_inside(viewport.uv.min.to_vec2()),
+            inset.lerp_inside(viewport.uv.max.to_vec2()),
+        );
+        painter.rect_stroke(visible, egui::Rounding::ZERO, egui::Stroke::new(1.0, Color32::YELLOW));
+
+        if response.clicked() || response.dragged() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                let uv = (pos - inset.min) / inset.size();
+                self.scroll_to_uv(egui::pos2(uv.x.clamp(0.0, 1.0), uv.y.clamp(0.0, 1.0)));
+                ui.ctx().request_repaint();
+            }
         }
     }
 
@@ -331,6 +424,16 @@ impl PixelCanvas {
         }
     }
 
+    /// Create a new texture from the retained image data, e.g. after the graphics context was lost.
+    pub fn recreate_texture(&mut self) {
+        self.texture = Some(self.create_texture());
+    }
+
+    pub fn set_context(&mut self, ctx: Context) {
+        self.ctx = ctx;
+        self.recreate_texture();
+    }
+
     pub fn set_bpp(&mut self, bpp: PixelCanvasDepth) {
         self.bpp = bpp;
         self.data_unpacked = false;
@@ -340,6 +443,10 @@ impl PixelCanvas {
         self.zoom = zoom;
     }
 
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
     pub fn resize(&mut self, dims: (u32, u32)) {
         self.view_dimensions = dims;
         self.data_buf = vec![0; PixelCanvas::calc_slice_size(dims, self.bpp)];
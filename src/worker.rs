@@ -28,10 +28,11 @@
 // Worker code adapted from
 // https://www.tweag.io/blog/2022-11-24-wasm-threads-and-messages/
 
-use eframe::wasm_bindgen;
-use eframe::wasm_bindgen::{JsCast, JsValue};
-use eframe::wasm_bindgen::closure::Closure;
-use eframe::wasm_bindgen::prelude::wasm_bindgen;
+use std::sync::{Mutex, Once};
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::prelude::wasm_bindgen;
 
 // Spawn a worker and communicate with it.
 #[allow (dead_code)]
@@ -109,8 +110,46 @@ pub(crate) fn spawn_loading_worker(bytes: &[u8]) {
     log::debug!("spawn_loading_worker(): finished");
 }
 
+/// The message and location of the last panic, recorded by the panic hook. A panicking
+/// worker aborts before it can report anything itself, so the main thread picks this up when
+/// the worker's error event fires.
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+/// Chain a hook onto the current panic hook that records panics for take_panic_report().
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let message = if let Some(message) = info.payload().downcast_ref::<&str>() {
+                message.to_string()
+            }
+            else if let Some(message) = info.payload().downcast_ref::<String>() {
+                message.clone()
+            }
+            else {
+                "unknown panic".to_string()
+            };
+            let location = info
+                .location()
+                .map(|location| format!("{}:{}:{}", location.file(), location.line(), location.column()))
+                .unwrap_or_else(|| "unknown location".to_string());
+            if let Ok(mut last) = LAST_PANIC.lock() {
+                *last = Some(format!("{}\n  at {}", message, location));
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// Take the report of the last panic in any thread, if there was one.
+pub(crate) fn take_panic_report() -> Option<String> {
+    LAST_PANIC.lock().ok()?.take()
+}
+
 // Spawn a worker and communicate with it.
 pub(crate) fn spawn_closure_worker(f: impl FnOnce() + Send + 'static) -> Result<web_sys::Worker, JsValue> {
+    install_panic_hook();
     let worker_opts = web_sys::WorkerOptions::new();
     worker_opts.set_type(web_sys::WorkerType::Module);
     let worker = web_sys::Worker::new_with_options("./worker.js", &worker_opts)?;
@@ -0,0 +1,23 @@
+#!/usr/bin/env bash
+# Fetch the v86 emulator files the boot test runs into assets/v86, so they're served with the
+# app rather than loaded from a third-party site. The first run records their SHA-256 sums in
+# assets/v86/SHA256SUMS; commit that file, and later runs refuse files that don't match it.
+set -eu
+
+V86_RELEASE=${V86_RELEASE:-https://github.com/copy/v86/releases/download/latest}
+V86_BIOS=${V86_BIOS:-https://raw.githubusercontent.com/copy/v86/master/bios}
+DEST="$(dirname "$0")/assets/v86"
+
+mkdir -p "$DEST"
+curl -fL -o "$DEST/libv86.js" "$V86_RELEASE/libv86.js"
+curl -fL -o "$DEST/v86.wasm" "$V86_RELEASE/v86.wasm"
+curl -fL -o "$DEST/seabios.bin" "$V86_BIOS/seabios.bin"
+curl -fL -o "$DEST/vgabios.bin" "$V86_BIOS/vgabios.bin"
+
+cd "$DEST"
+if [ -f SHA256SUMS ]; then
+    sha256sum -c SHA256SUMS
+else
+    sha256sum libv86.js v86.wasm seabios.bin vgabios.bin > SHA256SUMS
+    echo "Recorded checksums in $DEST/SHA256SUMS"
+fi